@@ -0,0 +1,14 @@
+#include <stdio.h>
+#include <unistd.h>
+#include <fcntl.h>
+
+int main(int argc, char** argv){
+
+	char *exec_args[]={"zstd","-c",NULL};
+
+	execvp("zstd",exec_args);
+
+	perror("error executing command");	
+
+	return 0;
+}
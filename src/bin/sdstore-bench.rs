@@ -0,0 +1,183 @@
+use rust_sdstore::{
+    client_api::{ClientError, SdstoreClient},
+    core::{
+        bench::{self, BenchCli, Outcomes, Report, Rng, WeightedPipeline},
+        client_task::ClientTask,
+        filter::Filter,
+        messaging::{self, MessageToClient, WireFormat},
+        paths,
+        transport::{TransportMode, TRANSPORT_MODE_VAR},
+    },
+};
+
+use std::{
+    collections::HashMap, env, fs, path::{Path, PathBuf}, process, time::{Duration, Instant},
+};
+
+use clap::Parser;
+
+/// A request submitted, yet to conclude.
+struct InFlight {
+    /// Index of its pipeline, see [`BenchCli::pipelines`].
+    pipeline: usize,
+    submitted: Instant,
+    output: PathBuf,
+}
+
+fn main() {
+    rust_sdstore::util::init_logging_infrastructure(
+        None,
+        log::LevelFilter::Warn,
+        &[],
+        rust_sdstore::util::LogFormat::Text,
+        rust_sdstore::util::Rotation::default(),
+        rust_sdstore::util::LogSink::Terminal
+    ).unwrap_or_else(|err| {
+        eprintln!("Could not init logging infrastructure! Error: {:?}", err);
+        eprintln!("Exiting");
+        process::exit(1);
+    });
+
+    let mut cli = BenchCli::parse();
+    if cli.pipelines.is_empty() {
+        cli.pipelines.push(WeightedPipeline { filters: vec![Filter::Nop], weight: 1 });
+    }
+
+    let transport_mode = TransportMode::from_env().unwrap_or_else(|err| {
+        log::error!("Invalid transport mode in {}. Error: {:?}", TRANSPORT_MODE_VAR, err);
+        process::exit(1);
+    });
+    let codec = WireFormat::from_env().unwrap_or_else(|err| {
+        log::error!("Invalid wire format in {}. Error: {:?}", messaging::WIRE_FORMAT_VAR, err);
+        process::exit(1);
+    });
+    let udsock_dir = paths::socket_dir(cli.socket_dir.as_deref());
+    let mut client = SdstoreClient::connect(&udsock_dir, transport_mode, codec).unwrap_or_else(|err| {
+        log::error!("Could not connect to the server in {:?}. Error: {:?}", udsock_dir, err);
+        process::exit(1);
+    });
+
+    // The server opens the tasks' files relative to its own working directory.
+    let dir = match &cli.dir {
+        Some(dir) => env::current_dir().map(|cwd| cwd.join(dir)).unwrap_or_else(|_| dir.clone()),
+        None => env::temp_dir().join(format!("sdstore_bench_{}", process::id())),
+    };
+    let inputs = write_inputs(&cli, &dir).unwrap_or_else(|err| {
+        log::error!("Could not write the inputs to {:?}. Error: {:?}", dir, err);
+        process::exit(1);
+    });
+
+    let report = run(&cli, &mut client, &dir, &inputs);
+    if cli.dir.is_none() {
+        let _ = fs::remove_dir_all(&dir);
+    } else {
+        inputs.iter().for_each(|input| { let _ = fs::remove_file(input); });
+    }
+    match cli.json {
+        true => println!("{}", serde_json::to_string(&report).expect("reports serialize to JSON")),
+        false => print!("{report}"),
+    }
+}
+
+/// Write the synthetic inputs to `dir`, returning their paths.
+fn write_inputs(cli: &BenchCli, dir: &Path) -> std::io::Result<Vec<PathBuf>> {
+    fs::create_dir_all(dir)?;
+    let mut rng = Rng::new(cli.seed);
+    (0..cli.inputs)
+        .map(|n| {
+            let input = dir.join(format!("input_{n}"));
+            bench::write_input(&input, cli.input_size, &mut rng)?;
+            Ok(input)
+        })
+        .collect()
+}
+
+/// Submit every request, at the times drawn, following those in flight in between, then
+/// wait for the rest, up to the drain timeout.
+fn run(cli: &BenchCli, client: &mut SdstoreClient, dir: &Path, inputs: &[PathBuf]) -> Report {
+    // Drawn apart from the inputs, so that their number and size don't change which requests are made.
+    let mut rng = Rng::new(!cli.seed);
+    let mut outcomes = vec![Outcomes::default(); cli.pipelines.len()];
+    let mut in_flight = HashMap::new();
+    let start = Instant::now();
+    let mut next_arrival = start;
+    let mut last_concluded = start;
+
+    for n in 0..cli.requests {
+        loop {
+            let now = Instant::now();
+            if now >= next_arrival {
+                break
+            }
+            follow(client, next_arrival - now, &mut in_flight, &mut outcomes, &mut last_concluded);
+        }
+        let pipeline = rng.pick(&cli.pipelines);
+        let input = inputs[n % inputs.len()].clone();
+        let output = dir.join(format!("output_{n}"));
+        let task = ClientTask::new(0, cli.priority, input, output.clone(), cli.pipelines[pipeline].filters.clone());
+        match client.submit(task) {
+            Ok(handle) => {
+                in_flight.insert(handle.request_id(), InFlight { pipeline, submitted: Instant::now(), output });
+            },
+            Err(ClientError::Io(err)) => {
+                log::error!("Could not submit request #{n} to the server. Error: {:?}", err);
+                process::exit(1);
+            },
+            Err(err) => {
+                log::warn!("Could not submit request #{n}. Error: {:?}", err);
+                outcomes[pipeline].failed += 1;
+            },
+        }
+        next_arrival += rng.interval(cli.rate, cli.poisson);
+    }
+
+    let deadline = Instant::now() + Duration::from_secs(cli.drain_timeout);
+    while !in_flight.is_empty() {
+        let now = Instant::now();
+        if now >= deadline {
+            log::warn!("{} request(s) unfinished after {}s", in_flight.len(), cli.drain_timeout);
+            break
+        }
+        follow(client, deadline - now, &mut in_flight, &mut outcomes, &mut last_concluded);
+    }
+
+    Report::new(cli.requests, last_concluded - start, &cli.pipelines, &outcomes)
+}
+
+/// Wait up to `timeout` for the server's next message, recording the outcome of the request
+/// it concludes, if any.
+fn follow(
+    client: &mut SdstoreClient,
+    timeout: Duration,
+    in_flight: &mut HashMap<uuid::Uuid, InFlight>,
+    outcomes: &mut [Outcomes],
+    last_concluded: &mut Instant,
+) {
+    let (handle, msg) = match client.next(timeout) {
+        Ok(Some(received)) => received,
+        Ok(None) => return,
+        Err(err) => {
+            log::error!("Lost the server. Error: {:?}", err);
+            process::exit(1);
+        },
+    };
+    // Suspended requests are restarted, once the server is.
+    if !msg.is_last() || matches!(msg, MessageToClient::Suspended) {
+        return
+    }
+    let Some(request) = in_flight.remove(&handle.request_id()) else { return };
+    *last_concluded = Instant::now();
+    let outcome = &mut outcomes[request.pipeline];
+    match msg {
+        MessageToClient::Concluded(summary) => {
+            outcome.latencies.push(request.submitted.elapsed());
+            outcome.queue_waits.push(summary.queue_wait);
+            outcome.bytes_in += summary.bytes_in;
+        },
+        msg => {
+            log::warn!("A request failed: {msg}");
+            outcome.failed += 1;
+        },
+    }
+    let _ = fs::remove_file(&request.output);
+}
@@ -0,0 +1,72 @@
+use rust_sdstore::{
+    client_api::SdstoreClient,
+    core::{
+        fuse::{self, FuseCli, FuseMount},
+        messaging::{self, WireFormat},
+        paths,
+        transport::{TransportMode, TRANSPORT_MODE_VAR},
+    },
+};
+
+use std::{process, thread};
+
+use clap::Parser;
+use signal_hook::{consts::{SIGINT, SIGTERM}, iterator::Signals};
+
+fn main() {
+    rust_sdstore::util::init_logging_infrastructure(
+        None,
+        log::LevelFilter::Info,
+        &[],
+        rust_sdstore::util::LogFormat::Text,
+        rust_sdstore::util::Rotation::default(),
+        rust_sdstore::util::LogSink::Terminal
+    ).unwrap_or_else(|err| {
+        eprintln!("Could not init logging infrastructure! Error: {:?}", err);
+        eprintln!("Exiting");
+        process::exit(1);
+    });
+
+    let cli = FuseCli::parse();
+
+    let transport_mode = TransportMode::from_env().unwrap_or_else(|err| {
+        log::error!("Invalid transport mode in {}. Error: {:?}", TRANSPORT_MODE_VAR, err);
+        process::exit(1);
+    });
+    let codec = WireFormat::from_env().unwrap_or_else(|err| {
+        log::error!("Invalid wire format in {}. Error: {:?}", messaging::WIRE_FORMAT_VAR, err);
+        process::exit(1);
+    });
+    let udsock_dir = paths::socket_dir(cli.socket_dir.as_deref());
+    let client = SdstoreClient::connect(&udsock_dir, transport_mode, codec).unwrap_or_else(|err| {
+        log::error!("Could not connect to the server in {:?}. Error: {:?}", udsock_dir, err);
+        process::exit(1);
+    });
+
+    let mut mount = FuseMount::mount(&cli, client).unwrap_or_else(|err| {
+        log::error!("Could not mount on {:?}. Error: {:?}", cli.mountpoint, err);
+        process::exit(1);
+    });
+    log::info!("mounted on {:?}", cli.mountpoint);
+
+    // Unmounting ends `serve`, once the files still open are closed.
+    let mut signals = Signals::new([SIGINT, SIGTERM]).unwrap_or_else(|err| {
+        log::error!("Could not install signal handlers. Error: {:?}", err);
+        process::exit(1);
+    });
+    let mountpoint = cli.mountpoint.clone();
+    thread::spawn(move || {
+        for signal in signals.forever() {
+            log::info!("received signal {signal}, unmounting");
+            if let Err(err) = fuse::unmount(&mountpoint) {
+                log::error!("Could not unmount {:?}. Error: {:?}", mountpoint, err);
+            }
+        }
+    });
+
+    if let Err(err) = mount.serve() {
+        log::error!("Lost the mount. Error: {:?}", err);
+        process::exit(1);
+    }
+    log::info!("unmounted");
+}
@@ -1,124 +1,971 @@
-use rust_sdstore::core::messaging::{self, MessageToClient};
+use rust_sdstore::core::{
+    batch,
+    cli::{ClientCli, ClientCommand, OutputFormat, WatchDirArgs},
+    client_task::{ClientTask, MODE_BITS},
+    drop_folder::DropFolder,
+    framing,
+    paths,
+    messaging::{self, Codec, MessageToClient, NotificationReceiver, RequestFailure, WireFormat},
+    progress::ProgressBar,
+    server::streaming::STREAM_SOCKET,
+    status::Dashboard,
+    transport::{
+        self, Backoff, Peer, SocketNamespace, Transport, TransportMode, CONNECTION_SOCKET, SOCKET_NAMESPACE_VAR,
+        TRANSPORT_MODE_VAR,
+    }
+};
 
-use std::{env, process, os::unix::net::UnixDatagram, fs};
+use std::{
+    collections::HashMap, ffi::c_int, process, os::unix::{fs::PermissionsExt, net::{UnixDatagram, UnixStream}}, fs, io::{self, Write},
+    path::{Path, PathBuf},
+    sync::OnceLock, thread, time::Duration,
+};
 
-/// After the cliend executes a `./sdstore status` command, this function
-/// does what is required to receive and output the reply from the server.
-fn status_msg(listener: &UnixDatagram) {
-    let mut buf = [0; 1024];
-    let n = listener.recv(&mut buf).unwrap_or_else(|err| {
-        log::error!("Could not read from UdSocket. Error: {:?}", err);
-        process::exit(1);
-    });
-    match bincode::deserialize::<String>(&buf[..n]) {
-        Err(err) => log::warn!("Error deserializing message from socket: {:?}", err),
-        Ok(status) => log::info!("Server current status is: \n{status}"),
+use signal_hook::{consts::{SIGHUP, SIGINT, SIGTERM}, iterator::Signals};
+use uuid::Uuid;
+
+/// How long the client waits for the server's first reply to its request, unless told
+/// otherwise with `--timeout`, see [`Timeouts`].
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How long the client waits for the server's later replies to its request before checking
+/// that the server is still alive, unless told otherwise with `--timeout`, see [`Timeouts`].
+const PING_AFTER: Duration = Duration::from_secs(30);
+
+/// How long the client waits for the server's messages at a time, or for files to turn up if
+/// none are expected, while it watches a directory, see [`watch_dir_msg`].
+const WATCH_DIR_POLL: Duration = Duration::from_millis(250);
+
+/// Signals on which the client exits, see [`exit_on_signal`].
+const EXIT_SIGNALS: [c_int; 3] = [SIGHUP, SIGINT, SIGTERM];
+
+/// The client's datagram socket file, if it bound one, which is removed when it exits.
+static CLIENT_UDSOCK: OnceLock<PathBuf> = OnceLock::new();
+
+/// Exit with `code`, after removing the client's socket file, see [`CLIENT_UDSOCK`].
+fn exit(code: i32) -> ! {
+    if let Some(client_udsock) = CLIENT_UDSOCK.get() {
+        if let Err(err) = fs::remove_file(client_udsock) {
+            log::error!("Error deleting client udsocket file: {:?}", err);
+            process::exit(1);
+        }
+    }
+    process::exit(code)
+}
+
+/// How long the client waits on the server's replies to its request, before giving up on it.
+///
+/// By default, only the first reply is waited on for [`DEFAULT_TIMEOUT`], as a request may be
+/// pending for long before the server sends another: later ones are waited on as long as the
+/// server is alive, which it is pinged to tell after [`PING_AFTER`] without any, and has to
+/// answer within [`DEFAULT_TIMEOUT`], see [`NotificationReceiver::recv_alive`].
+/// `./sdstore --timeout <seconds> <command>` waits as long for every reply instead, or forever
+/// with `0`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Timeouts {
+    /// How long to wait for the first reply, if at all.
+    first: Option<Duration>,
+    /// How long to wait for each later reply, if at all.
+    idle: Option<Duration>,
+    /// How long to wait for a later reply before pinging the server, if it is, when they are
+    /// waited on without a timeout.
+    ping_after: Option<Duration>,
+}
+
+impl Timeouts {
+    /// The timeouts given `--timeout <seconds>`, if it was.
+    fn new(timeout: Option<u64>) -> Self {
+        let timeout = match timeout {
+            None => return Timeouts { first: Some(DEFAULT_TIMEOUT), idle: None, ping_after: Some(PING_AFTER) },
+            Some(0) => None,
+            Some(secs) => Some(Duration::from_secs(secs)),
+        };
+        Timeouts { first: timeout, idle: timeout, ping_after: None }
+    }
+
+    /// How long to wait for later replies, pinging the server after a while if need be, see
+    /// [`NotificationReceiver::recv_alive`], rather than the transport's read timeout.
+    fn liveness(&self) -> Option<Duration> {
+        self.ping_after.filter(|_| self.idle.is_none())
+    }
+}
+
+/// Whether `err` is a [`Transport`] giving up on waiting for a message, see [`Timeouts`].
+fn timed_out(err: &io::Error) -> bool {
+    matches!(err.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut)
+}
+
+/// Exit, as the server didn't reply in time, see [`Timeouts`].
+fn no_response() -> ! {
+    log::error!("no response from server, is it running? Wait longer with --timeout <seconds>");
+    exit(1)
+}
+
+/// Exit, having failed to reach the server, as `what` says, with `err`. If its socket was
+/// unavailable, even after retrying as `backoff` allows, the server is likely down.
+fn unreachable(what: &str, err: io::Error, backoff: Backoff) -> ! {
+    match transport::server_unavailable(&err) {
+        true => log::error!(
+            "{what}: the server's socket is unavailable after {} retries, is it running? \
+            Retry for longer with --retries <n>. Error: {:?}", backoff.retries, err
+        ),
+        false => log::error!("{what}. Error: {:?}", err),
+    }
+    exit(1)
+}
+
+/// After the cliend executes a `./sdstore status`, `history` or `query` command, this
+/// function does what is required to receive and output the reply from the server, exiting
+/// with an error if the request was refused, e.g. a `report` by a user who isn't an admin.
+fn reply_msg(listener: &dyn Transport, mut notifications: NotificationReceiver<MessageToClient>, output: OutputFormat) {
+    match notifications.recv(listener) {
+        Err(err) if err.kind() == io::ErrorKind::InvalidData =>
+            log::warn!("Error deserializing message from socket: {:?}", err),
+        Err(err) if timed_out(&err) => no_response(),
+        Err(err) => {
+            log::error!("Could not read from UdSocket. Error: {:?}", err);
+            exit(1);
+        },
+        Ok(msg @ MessageToClient::Refused(_)) => {
+            output.print(log::Level::Error, &msg);
+            exit(1);
+        },
+        Ok(msg) => output.print(log::Level::Info, &msg),
     };
 }
 
+/// After the client executes a `./sdstore ping` command, this function outputs the server's
+/// reply, exiting with an error if it isn't a [`MessageToClient::Pong`].
+fn ping_msg(listener: &dyn Transport, mut notifications: NotificationReceiver<MessageToClient>, output: OutputFormat) {
+    match notifications.recv(listener) {
+        Ok(pong @ MessageToClient::Pong { .. }) => output.print(log::Level::Info, &pong),
+        Ok(msg) => {
+            output.print(log::Level::Error, &msg);
+            exit(1);
+        },
+        Err(err) if timed_out(&err) => no_response(),
+        Err(err) => {
+            log::error!("Could not read from UdSocket. Error: {:?}", err);
+            exit(1);
+        },
+    }
+}
+
+/// After the client executes a `./sdstore health` command, this function outputs the
+/// server's reply, exiting with an error unless it tells the server is ready, see
+/// [`Health::is_ready`].
+fn health_msg(listener: &dyn Transport, mut notifications: NotificationReceiver<MessageToClient>, output: OutputFormat) {
+    match notifications.recv(listener) {
+        Ok(msg) if matches!(&msg, MessageToClient::Health(health) if health.is_ready()) =>
+            output.print(log::Level::Info, &msg),
+        Ok(msg) => {
+            output.print(log::Level::Error, &msg);
+            exit(1);
+        },
+        Err(err) if timed_out(&err) => no_response(),
+        Err(err) => {
+            log::error!("Could not read from UdSocket. Error: {:?}", err);
+            exit(1);
+        },
+    }
+}
+
+/// After the client executes a `./sdstore watch` command, this function redraws the server's
+/// status, see [`Dashboard`], each time the server replies, then asks for it again every
+/// `interval`, until the client is interrupted. Every status is printed on a line of its own
+/// instead, if output as JSON.
+///
+/// Each status is asked for in a request of its own, and waited on as the first was.
+#[allow(clippy::too_many_arguments)]
+fn watch_msg(
+    listener: &dyn Transport,
+    mut notifications: NotificationReceiver<MessageToClient>,
+    codec: WireFormat,
+    client_pid: u32,
+    server: &Peer,
+    interval: Duration,
+    backoff: Backoff,
+    output: OutputFormat
+) -> ! {
+    loop {
+        match notifications.recv(listener) {
+            Ok(MessageToClient::Status(status)) if output == OutputFormat::Human => {
+                // Clears the terminal, and moves the cursor back to its top left.
+                println!("\x1b[2J\x1b[H{}", Dashboard(&status));
+                if let Err(err) = io::stdout().flush() {
+                    log::warn!("Could not write to stdout. Error: {:?}", err);
+                }
+            },
+            Ok(msg @ MessageToClient::Status(_)) => output.print(log::Level::Info, &msg),
+            Ok(msg) => {
+                output.print(log::Level::Error, &msg);
+                exit(1);
+            },
+            Err(err) if timed_out(&err) => no_response(),
+            Err(err) => {
+                log::error!("Could not read from UdSocket. Error: {:?}", err);
+                exit(1);
+            },
+        }
+
+        thread::sleep(interval);
+        let request_id = Uuid::new_v4();
+        let request = codec.encode(&messaging::ClientRequest::Status(client_pid, request_id)).unwrap_or_else(|err| {
+            log::error!("Could not serialize request. Error: {:?}", err);
+            exit(1);
+        });
+        backoff
+            .retry(|| messaging::send_message(listener, &request, server))
+            .unwrap_or_else(|err| unreachable("sdstored: Could not send to UdSocket", err, backoff));
+        notifications = NotificationReceiver::new(codec, client_pid, request_id, server.clone());
+    }
+}
+
+/// After the client executes a `./sdstore wait <request-id>` command, this function outputs
+/// the server's last message about the awaited request, once it has one, exiting with an
+/// error unless the request concluded.
+fn wait_msg(listener: &dyn Transport, mut notifications: NotificationReceiver<MessageToClient>, output: OutputFormat) {
+    match notifications.recv(listener) {
+        Ok(msg @ (MessageToClient::Concluded(_) | MessageToClient::BatchConcluded(_))) =>
+            output.print(log::Level::Info, &msg),
+        Ok(msg) => {
+            output.print(log::Level::Error, &msg);
+            exit(1);
+        },
+        Err(err) if timed_out(&err) => no_response(),
+        Err(err) => {
+            log::error!("Could not read from UdSocket. Error: {:?}", err);
+            exit(1);
+        },
+    }
+}
+
+/// After the client executes a `./sdstore reexec` command, this function outputs the
+/// server's replies, until the server that took over says so, exiting with an error if the
+/// request was refused, or the server shut down instead.
+fn reexec_msg(listener: &dyn Transport, mut notifications: NotificationReceiver<MessageToClient>, output: OutputFormat) {
+    loop {
+        match notifications.recv(listener) {
+            Ok(msg @ MessageToClient::HandingOver { .. }) => output.print(log::Level::Info, &msg),
+            Ok(msg @ MessageToClient::TookOver { .. }) => break output.print(log::Level::Info, &msg),
+            Ok(msg) => {
+                output.print(log::Level::Error, &msg);
+                exit(1);
+            },
+            Err(err) if timed_out(&err) => no_response(),
+            Err(err) => {
+                log::error!("Could not read from UdSocket. Error: {:?}", err);
+                exit(1);
+            },
+        }
+    }
+}
+
+/// After the client executes a `./sdstore suspend` or `resume` command, this function
+/// outputs the server's reply, exiting with an error unless the task was suspended, or
+/// resumed.
+fn suspend_msg(listener: &dyn Transport, mut notifications: NotificationReceiver<MessageToClient>, output: OutputFormat) {
+    match notifications.recv(listener) {
+        Ok(msg @ (MessageToClient::TaskSuspended { .. } | MessageToClient::TaskResumed { .. })) =>
+            output.print(log::Level::Info, &msg),
+        Ok(msg) => {
+            output.print(log::Level::Error, &msg);
+            exit(1);
+        },
+        Err(err) if timed_out(&err) => no_response(),
+        Err(err) => {
+            log::error!("Could not read from UdSocket. Error: {:?}", err);
+            exit(1);
+        },
+    }
+}
+
+/// After the client executes a `./sdstore subscribe` command, this function outputs every
+/// task event the server sends it, until the server tells it it was unsubscribed, see
+/// [`unsubscribe_on_signal`].
+fn subscribe_msg(listener: &dyn Transport, mut notifications: NotificationReceiver<MessageToClient>, output: OutputFormat) {
+    loop {
+        match notifications.recv(listener) {
+            Err(err) if err.kind() == io::ErrorKind::InvalidData =>
+                log::warn!("Error deserializing message from socket: {:?}", err),
+            Err(err) if timed_out(&err) => no_response(),
+            Err(err) => {
+                log::error!("Could not read from UdSocket. Error: {:?}", err);
+                exit(1);
+            },
+            Ok(MessageToClient::Unsubscribed) => break output.print(log::Level::Info, &MessageToClient::Unsubscribed),
+            Ok(msg) => output.print(log::Level::Info, &msg),
+        }
+    }
+}
+
+/// Spawn a thread which, on the first of [`EXIT_SIGNALS`] the client receives, sends the
+/// serialized `unsubscribe` request to the server, after which the server tells the client
+/// it was unsubscribed, see [`subscribe_msg`]. On a second signal, the client exits at once,
+/// as in [`exit_on_signal`].
+///
+/// The request is sent over a transport of its own, so as not to interleave with the
+/// acknowledgements the client sends over its own; the server replies over the client's.
+fn unsubscribe_on_signal(
+    transport_mode: TransportMode,
+    namespace: SocketNamespace,
+    udsock_dir: &Path,
+    unsubscribe: Vec<u8>
+) {
+    let mut signals = Signals::new(EXIT_SIGNALS).unwrap_or_else(|err| {
+        log::error!("Could not install signal handlers. Error: {:?}", err);
+        exit(1);
+    });
+    let udsock_dir = udsock_dir.to_path_buf();
+    thread::spawn(move || {
+        let mut signals = signals.forever();
+        let Some(mut signal) = signals.next() else { return };
+        let sent = match transport_mode {
+            TransportMode::Datagram => UnixDatagram::unbound().and_then(|socket| {
+                messaging::send_message(&socket, &unsubscribe, &namespace.peer(udsock_dir.join("sdstored.sock")))
+            }),
+            TransportMode::Stream => namespace.connect(&udsock_dir.join(CONNECTION_SOCKET)).and_then(|stream| {
+                messaging::send_message(&stream, &unsubscribe, &namespace.peer(udsock_dir.join(CONNECTION_SOCKET)))
+            }),
+        };
+        if let Err(err) = sent {
+            log::error!("Could not unsubscribe. Error: {:?}", err);
+        } else {
+            log::info!("unsubscribing, signal again to exit at once");
+            signal = signals.next().unwrap_or(signal);
+        }
+        exit(128 + signal);
+    });
+}
+
+/// Spawn a thread which, on any of [`EXIT_SIGNALS`] the client receives, exits with the
+/// shell's code for a process killed by it, `128` plus its number, after removing the
+/// client's socket file, which would otherwise be left behind, see [`exit`].
+fn exit_on_signal() {
+    let mut signals = Signals::new(EXIT_SIGNALS).unwrap_or_else(|err| {
+        log::error!("Could not install signal handlers. Error: {:?}", err);
+        exit(1);
+    });
+    thread::spawn(move || {
+        if let Some(signal) = signals.forever().next() {
+            log::info!("received signal {signal}, exiting");
+            exit(128 + signal);
+        }
+    });
+}
+
 /// If the client submits an `./sdstore proc-file` request, this function is used
 /// to process the server's replies.
 ///
-/// The client must loop over a blocking `UnixDatagram` read until the server notifies
-/// it that its request either finished, or failed.
+/// The client must loop over a blocking read from its [`Transport`] until the server notifies
+/// it that its request either finished, or failed. A request suspended by the server
+/// shutting down is waited on until it resumes. Every message is acknowledged, see
+/// [`NotificationReceiver`].
+///
+/// Once the server first replies, each later reply is waited on as `timeouts` say: for their
+/// idle timeout, if any, or as long as the server answers pings, see [`Timeouts`], so that
+/// the client exits, rather than wait forever, if the server dies.
 ///
-/// If neither happens, the client will deadlock.
+/// With `no_wait`, the client stops once the request is queued instead, telling how to
+/// follow it, see `./sdstore wait`.
 ///
-/// # TODO
-/// This loop only breaks if the client receives an error from the socket, or
-/// its request is concluded.
+/// The task's progress is drawn on `progress`, if given, rather than output as other messages.
 ///
-/// Otherwise, it'll hang forever. This can be fixed with a timeout thread.
-fn proc_file_msg(listener: &UnixDatagram) {
-    let mut buf = [0; 64];
+/// Returns whether the request concluded successfully, or was queued, with `no_wait`.
+fn proc_file_msg(
+    listener: &dyn Transport,
+    mut notifications: NotificationReceiver<MessageToClient>,
+    timeouts: Timeouts,
+    output: OutputFormat,
+    no_wait: bool,
+    mut progress: Option<ProgressBar>,
+    inline: Option<&ClientTask>
+) -> bool {
+    let mut replied = false;
     loop {
-        let n = listener.recv(&mut buf).unwrap_or_else(|err| {
-            log::error!("Could not read from UdSocket. Error: {:?}", err);
-            process::exit(1);
-        });
-        let msg: MessageToClient = match bincode::deserialize(&buf[..n]) {
-            Err(err) => {
+        let received = match timeouts.liveness().filter(|_| replied) {
+            None => notifications.recv(listener),
+            Some(ping_after) => notifications.recv_alive(listener, ping_after, DEFAULT_TIMEOUT),
+        };
+        replied = true;
+        let msg = match received {
+            Err(err) if err.kind() == io::ErrorKind::InvalidData => {
                 log::warn!("Error deserializing message from socket: {:?}", err);
                 log::warn!("Moving on to next message");
                 break;
             },
+            Err(err) if timed_out(&err) => no_response(),
+            Err(err) => {
+                log::error!("Could not read from UdSocket. Error: {:?}", err);
+                exit(1);
+            },
             Ok(val) => val,
         };
-        log::info!("{msg}");
+        match (&msg, progress.as_mut()) {
+            (MessageToClient::Progress { bytes_out }, Some(bar)) => bar.update(*bytes_out),
+            (_, bar) => {
+                // The bar's line is ended before anything else is output.
+                if let Some(bar) = bar {
+                    bar.finish();
+                    if matches!(msg, MessageToClient::Processing) {
+                        bar.start();
+                    }
+                }
+                match (&msg, inline) {
+                    (MessageToClient::InlineOutput(bytes), Some(task)) => write_inline_output(task, bytes),
+                    _ => output.print(log::Level::Info, &msg),
+                }
+            },
+        }
+        if let Err(err) = listener.set_read_timeout(timeouts.idle) {
+            log::warn!("Could not set timeout on UdSocket. Error: {:?}", err);
+        }
 
         match &msg {
-            MessageToClient::Pending | MessageToClient::Processing => continue,
+            // The server numbers its messages from the start once it restarts.
+            MessageToClient::Suspended => notifications.restart_sequence(notifications.request_id()),
+            MessageToClient::Queued { .. } if no_wait => {
+                let request_id = notifications.request_id();
+                log::info!("not waiting for request {request_id}, follow it with `./sdstore wait {request_id}`");
+                return true
+            },
+            MessageToClient::Duplicate { original, .. } if no_wait => {
+                log::info!("not waiting for request {original}, follow it with `./sdstore wait {original}`");
+                return true
+            },
+            MessageToClient::Queued { .. } | MessageToClient::Duplicate { .. } | MessageToClient::Processing |
+            MessageToClient::Progress { .. } | MessageToClient::Optimized(..) |
+            MessageToClient::BatchFile { .. } | MessageToClient::InlineOutput(..) => continue,
+            MessageToClient::Concluded(_) | MessageToClient::BatchConcluded(_) => return true,
             _ => break
         }
     }
+    false
+}
+
+/// The bar to draw the progress of `task` on, see [`ProgressBar::new`], unless the server's
+/// messages are output as JSON.
+fn progress_bar(task: &ClientTask, output: OutputFormat) -> Option<ProgressBar> {
+    match output {
+        OutputFormat::Human => ProgressBar::new(batch::input_size(task.input_filepath())),
+        OutputFormat::Json => None,
+    }
+}
+
+/// If the client submits an `./sdstore proc-file --out-dir <dir>` request of several files,
+/// one task each, this function processes the server's replies about every one of `tasks`,
+/// as [`proc_file_msg`] does for a single one, and then tells how many concluded.
+///
+/// Messages are output as they arrive, each prefixed with its task's input, unless output as
+/// JSON. Messages about no request in particular, with a nil ID, can't be told apart, and
+/// fail every task still waited on, see [`NotificationReceiver`].
+///
+/// Returns whether every task concluded successfully, or was queued, with `no_wait`.
+fn proc_files_msg(
+    listener: &dyn Transport,
+    mut notifications: NotificationReceiver<MessageToClient>,
+    tasks: &[ClientTask],
+    timeouts: Timeouts,
+    output: OutputFormat,
+    no_wait: bool
+) -> bool {
+    let mut waiting = tasks.iter().map(|task| (task.request_id, task)).collect::<HashMap<_, _>>();
+    let mut failed = Vec::new();
+    let mut replied = false;
+    while !waiting.is_empty() {
+        let received = match timeouts.liveness().filter(|_| replied) {
+            None => notifications.recv_any(listener),
+            Some(ping_after) => notifications.recv_any_alive(listener, ping_after, DEFAULT_TIMEOUT),
+        };
+        replied = true;
+        let (request_id, msg) = match received {
+            Err(err) if err.kind() == io::ErrorKind::InvalidData => {
+                log::warn!("Error deserializing message from socket: {:?}", err);
+                continue
+            },
+            Err(err) if timed_out(&err) => no_response(),
+            Err(err) => {
+                log::error!("Could not read from UdSocket. Error: {:?}", err);
+                exit(1);
+            },
+            Ok(val) => val,
+        };
+        if let Err(err) = listener.set_read_timeout(timeouts.idle) {
+            log::warn!("Could not set timeout on UdSocket. Error: {:?}", err);
+        }
+
+        if request_id.is_nil() {
+            output.print(log::Level::Error, &msg);
+            failed.extend(waiting.drain().map(|(_, task)| task));
+            break
+        }
+        let Some(task) = waiting.get(&request_id).copied() else {
+            log::warn!("dropping notification about request {request_id}");
+            continue
+        };
+        match output {
+            OutputFormat::Human => log::info!("{}: {msg}", task.input_filepath().display()),
+            OutputFormat::Json => output.print(log::Level::Info, &msg),
+        }
+
+        match &msg {
+            // The server numbers its messages from the start once it restarts.
+            MessageToClient::Suspended => notifications.restart_sequence(request_id),
+            MessageToClient::Queued { .. } if no_wait => {
+                log::info!("not waiting for request {request_id}, follow it with `./sdstore wait {request_id}`");
+                waiting.remove(&request_id);
+            },
+            MessageToClient::Concluded(_) | MessageToClient::BatchConcluded(_) | MessageToClient::DryRun(_) => {
+                waiting.remove(&request_id);
+            },
+            msg if msg.is_last() => {
+                waiting.remove(&request_id);
+                failed.push(task);
+            },
+            _ => {},
+        }
+    }
+
+    log::info!("{} of {} file(s) done, {} failed", tasks.len() - failed.len(), tasks.len(), failed.len());
+    for task in &failed {
+        log::error!("failed: {} (request {})", task.input_filepath().display(), task.request_id);
+    }
+    failed.is_empty()
+}
+
+/// If the client executes a `./sdstore watch-dir` command, this function submits a task for
+/// every file that turns up in `folder`, see [`DropFolder`], and processes the server's
+/// replies about each, as [`proc_files_msg`] does, until the client is interrupted.
+///
+/// A file whose task can't be submitted, see [`ClientTask::check_paths`], or fails, is
+/// reported, and left in the directory, while the others are still transformed. Only with
+/// `--remove-input` are the files transformed removed from it.
+#[allow(clippy::too_many_arguments)]
+fn watch_dir_msg(
+    listener: &dyn Transport,
+    mut notifications: NotificationReceiver<MessageToClient>,
+    folder: &DropFolder,
+    args: &WatchDirArgs,
+    codec: WireFormat,
+    client_pid: u32,
+    server: &Peer,
+    backoff: Backoff,
+    output: OutputFormat
+) -> ! {
+    log::info!("watching {:?} for files to transform into {:?}", folder.dir(), args.out_dir);
+    let mut waiting = HashMap::new();
+    loop {
+        let timeout = if waiting.is_empty() { WATCH_DIR_POLL } else { Duration::ZERO };
+        let files = folder.ready_files(timeout).unwrap_or_else(|err| {
+            log::error!("Could not watch {:?}. Error: {:?}", folder.dir(), err);
+            exit(1);
+        });
+        for input in files {
+            let mut task = args.task(client_pid, input);
+            if let Err(err) = task.check_paths() {
+                log::error!("{err}");
+                continue
+            }
+            let msg = codec.encode(&messaging::ClientRequest::ProcFile(Box::new(task.clone()))).unwrap_or_else(|err| {
+                log::error!("Could not serialize request. Error: {:?}", err);
+                exit(1);
+            });
+            backoff
+                .retry(|| messaging::send_message(listener, &msg, server))
+                .unwrap_or_else(|err| unreachable("sdstored: Could not send to UdSocket", err, backoff));
+            log::info!("submitted request {} for {:?}", task.request_id, task.input_filepath());
+            waiting.insert(task.request_id, task);
+        }
+        if waiting.is_empty() {
+            continue
+        }
+
+        if let Err(err) = listener.set_read_timeout(Some(WATCH_DIR_POLL)) {
+            log::warn!("Could not set timeout on UdSocket. Error: {:?}", err);
+        }
+        let (request_id, msg) = match notifications.recv_any(listener) {
+            Err(err) if err.kind() == io::ErrorKind::InvalidData => {
+                log::warn!("Error deserializing message from socket: {:?}", err);
+                continue
+            },
+            Err(err) if timed_out(&err) => continue,
+            Err(err) => {
+                log::error!("Could not read from UdSocket. Error: {:?}", err);
+                exit(1);
+            },
+            Ok(val) => val,
+        };
+
+        if request_id.is_nil() {
+            output.print(log::Level::Error, &msg);
+            for (request_id, task) in waiting.drain() {
+                log::error!("failed: {} (request {request_id})", task.input_filepath().display());
+            }
+            continue
+        }
+        let Some(task) = waiting.get(&request_id) else {
+            log::warn!("dropping notification about request {request_id}");
+            continue
+        };
+        match output {
+            OutputFormat::Human => log::info!("{}: {msg}", task.input_filepath().display()),
+            OutputFormat::Json => output.print(log::Level::Info, &msg),
+        }
+
+        match &msg {
+            // The server numbers its messages from the start once it restarts.
+            MessageToClient::Suspended => notifications.restart_sequence(request_id),
+            MessageToClient::Concluded(_) | MessageToClient::BatchConcluded(_) => {
+                if args.remove_input {
+                    if let Err(err) = fs::remove_file(task.input_filepath()) {
+                        log::warn!("Could not remove {:?}. Error: {:?}", task.input_filepath(), err);
+                    }
+                }
+                waiting.remove(&request_id);
+            },
+            msg if msg.is_last() => {
+                log::error!("failed: {} (request {request_id})", task.input_filepath().display());
+                waiting.remove(&request_id);
+            },
+            _ => {},
+        }
+    }
+}
+
+/// Submit a serialized `proc-file --stream` request over the server's stream socket, in `udsock_dir`
+/// and bound in `namespace`, followed by the contents of its input file. Connecting is retried as
+/// `backoff` allows.
+///
+/// Returns the stream, over which the server sends the output back once the request concludes.
+fn stream_request(
+    udsock_dir: &Path,
+    namespace: SocketNamespace,
+    request: &[u8],
+    task: &ClientTask,
+    backoff: Backoff
+) -> UnixStream {
+    refuse_clobbering(task);
+    let input = fs::File::open(task.input_filepath()).unwrap_or_else(|err| {
+        log::error!("Could not open input file {:?}. Error: {:?}", task.input_filepath(), err);
+        exit(1);
+    });
+    let mut stream = backoff
+        .retry(|| namespace.connect(&udsock_dir.join(STREAM_SOCKET)))
+        .unwrap_or_else(|err| unreachable("Could not connect to server stream socket", err, backoff));
+
+    let sent = framing::write_frame(&mut stream, request)
+        .and_then(|_| framing::send_file(io::BufReader::new(input), &mut stream));
+    match sent {
+        Err(err) => {
+            log::error!("Could not stream request to server. Error: {:?}", err);
+            exit(1);
+        },
+        Ok(n) => log::info!("sdstore: streamed {n} bytes of input to server"),
+    }
+
+    stream
+}
+
+/// Receive the output of a concluded streamed request from `stream`, into its output file.
+fn receive_output(stream: UnixStream, task: &ClientTask) -> io::Result<u64> {
+    framing::receive_file(io::BufReader::new(stream), io::BufWriter::new(create_output(task)?))
+}
+
+/// Create the output file of `task`, whose output the server sends back, streamed or inline.
+fn create_output(task: &ClientTask) -> io::Result<fs::File> {
+    let output = match task.no_clobber {
+        true => fs::File::options().write(true).create_new(true).open(task.output_filepath())?,
+        false => fs::File::create(task.output_filepath())?,
+    };
+    // The server leaves the modes of the outputs it sends back to the client, which writes them.
+    if let Some(mode) = task.mode {
+        output.set_permissions(fs::Permissions::from_mode(mode & MODE_BITS))?;
+    }
+    Ok(output)
+}
+
+/// Exit if the output of `task`, which the server sends back, exists, and mustn't be replaced.
+/// The server only ever writes to its own copy of the output, so can't check this itself.
+fn refuse_clobbering(task: &ClientTask) {
+    if task.no_clobber && task.output_filepath().exists() {
+        let failure = RequestFailure::OutputExists(task.output_filepath().to_path_buf());
+        log::error!("{}", MessageToClient::Failed(failure));
+        exit(1);
+    }
+}
+
+/// Read the input of the inline `task` into it, to be sent in its request. Only a single file
+/// can be, not a batch's.
+fn read_inline_input(task: &mut ClientTask) {
+    if batch::is_batch(task.input_filepath()) {
+        log::error!("Only a single file can be sent inline, not {:?}", task.input_filepath());
+        exit(1);
+    }
+    refuse_clobbering(task);
+    let input = fs::read(task.input_filepath()).unwrap_or_else(|err| {
+        log::error!("Could not read input file {:?}. Error: {:?}", task.input_filepath(), err);
+        exit(1);
+    });
+    task.inline = Some(input.into_boxed_slice());
+}
+
+/// Write the output of the inline `task`, sent back by the server, to its output file.
+fn write_inline_output(task: &ClientTask, bytes: &[u8]) {
+    match create_output(task).and_then(|mut output| output.write_all(bytes)) {
+        Ok(()) => log::info!("received {} bytes of output into {:?}", bytes.len(), task.output_filepath()),
+        Err(err) => {
+            log::error!("Could not write output to {:?}. Error: {:?}", task.output_filepath(), err);
+            exit(1);
+        },
+    }
 }
 
 fn main() {
     rust_sdstore::util::init_logging_infrastructure(
         None, 
-        log::LevelFilter::Trace
+        log::LevelFilter::Trace,
+        &[],
+        rust_sdstore::util::LogFormat::Text,
+        rust_sdstore::util::Rotation::default(),
+        rust_sdstore::util::LogSink::Terminal
     ).unwrap_or_else(|err| {
         eprintln!("Could not init logging infrastructure! Error: {:?}", err);
         eprintln!("Exiting");
-        process::exit(1);
+        exit(1);
     });
 
     let client_pid = process::id();
 
-    let udsock_dir = std::env::current_dir().unwrap_or_else(|err| {
-            log::error!("Could not get pwd. Error {:?}", err);
-            process::exit(1);
-        })
-        .parent()
-        // TODO: fix this unwrap
-        .unwrap()
-        .join("tmp");
+    // Usage errors, and `--help`, are output by clap, which exits.
+    let cli = ClientCli::try_parse_args(std::env::args_os()).unwrap_or_else(|err| err.exit());
+    if let ClientCommand::Completions { shell } = cli.command {
+        if let Err(err) = ClientCli::completions(shell, &mut io::stdout()) {
+            log::error!("Could not write completions. Error: {:?}", err);
+            exit(1);
+        }
+        exit(0);
+    }
+    let (timeouts, output, backoff) = (Timeouts::new(cli.timeout), cli.output, cli.backoff());
+    let no_wait = matches!(&cli.command, ClientCommand::ProcFile(args) if args.no_wait);
+    let watch_interval = match &cli.command {
+        ClientCommand::Watch { interval } => Some(Duration::from_secs(*interval)),
+        _ => None,
+    };
+    // Watching fails before connecting if the directory can't be.
+    let watch_dir = match &cli.command {
+        ClientCommand::WatchDir(args) => Some((args, DropFolder::watch(&args.dir).unwrap_or_else(|err| {
+            log::error!("Could not watch {:?}. Error: {:?}", args.dir, err);
+            exit(1);
+        }))),
+        _ => None,
+    };
+    // Only messages from the server, and errors, are output as JSON.
+    if output == OutputFormat::Json {
+        log::set_max_level(log::LevelFilter::Error);
+    }
+
+    let udsock_dir = paths::socket_dir(cli.socket_dir.as_deref());
     log::info!("dir to be used for udsock is {:?}", udsock_dir);
 
-    let client_udsock = udsock_dir.join(format!("sdstore_{}.sock", client_pid));
-    let listener = UnixDatagram::bind(client_udsock.as_path()).unwrap_or_else(|err| {
-        log::error!("sdstored: Could not create listener on socket. Error: {:?}", err);
-        process::exit(1);
+    let transport_mode = TransportMode::from_env().unwrap_or_else(|err| {
+        log::error!("Invalid transport mode in {}. Error: {:?}", TRANSPORT_MODE_VAR, err);
+        exit(1);
+    });
+    let namespace = SocketNamespace::from_env().unwrap_or_else(|err| {
+        log::error!("Invalid socket namespace in {}. Error: {:?}", SOCKET_NAMESPACE_VAR, err);
+        exit(1);
     });
-    log::info!("client listening on Unix datagram socket: {:?}", listener);
-
-    let server_udsock = udsock_dir.join("sdstored.sock");
 
-    let request =
-        messaging::ClientRequest::build(env::args(), client_pid)
-            .unwrap_or_else(|err| {
-                log::error!("Could not parse request from arguments. Error: {:?}", err);
-                process::exit(1);
+    let mut requests = cli.requests(client_pid);
+    // Paths the server can't use are better reported before submitting any task.
+    for request in &mut requests {
+        if let messaging::ClientRequest::ProcFile(task) = request {
+            task.check_paths().unwrap_or_else(|err| {
+                log::error!("{err}");
+                exit(1);
             });
+            if task.inline.is_some() {
+                read_inline_input(task);
+            }
+        }
+    }
+    // Only a `proc-file` of several files makes more than one request, see `proc_files_msg`.
+    let request = requests[0].clone();
+    let request_id = request.request_id().unwrap_or_default();
 
-    let msg = bincode::serialize(&request)
+    let codec = WireFormat::from_env().unwrap_or_else(|err| {
+        log::error!("Invalid wire format in {}. Error: {:?}", messaging::WIRE_FORMAT_VAR, err);
+        exit(1);
+    });
+    let msg = codec.encode(&request)
         .unwrap_or_else(|err| {
             log::error!("Could not serialize request. Error: {:?}", err);
-            process::exit(1);
+            exit(1);
         });
-    listener.send_to(msg.as_slice(), server_udsock).unwrap_or_else(|err| {
-        log::error!("sdstored: Could not send to UdSocket. Error: {:?}", err);
-        process::exit(1);
+
+    // Only datagram sockets are bound, and need be removed on exit, if they are files.
+    let (listener, server_udsock): (Box<dyn Transport>, _) = match transport_mode {
+        TransportMode::Datagram => {
+            let client_udsock = udsock_dir.join(format!("sdstore_{}.sock", client_pid));
+            let listener = namespace.bind_datagram(client_udsock.as_path()).unwrap_or_else(|err| {
+                log::error!("sdstored: Could not create listener on socket. Error: {:?}", err);
+                exit(1);
+            });
+            log::info!("client listening on Unix datagram socket: {:?}", listener);
+            let server_udsock = udsock_dir.join("sdstored.sock");
+            if namespace.has_files() {
+                if let Err(err) = transport::allow_server_replies(&client_udsock, &server_udsock) {
+                    log::warn!("Could not let the server reply to {:?}. Error: {:?}", client_udsock, err);
+                }
+                let _ = CLIENT_UDSOCK.set(client_udsock);
+            }
+            (Box::new(listener), namespace.peer(server_udsock))
+        },
+        TransportMode::Stream => {
+            let server_udsock = udsock_dir.join(CONNECTION_SOCKET);
+            let stream = backoff
+                .retry(|| namespace.connect(server_udsock.as_path()))
+                .unwrap_or_else(|err| unreachable("Could not connect to server connection socket", err, backoff));
+            log::info!("client connected over Unix stream socket: {:?}", stream);
+            (Box::new(stream), namespace.peer(server_udsock))
+        },
+    };
+
+    match &request {
+        messaging::ClientRequest::Subscribe(..) => {
+            let unsubscribe = codec.encode(&messaging::ClientRequest::Unsubscribe(client_pid))
+                .unwrap_or_else(|err| {
+                    log::error!("Could not serialize request. Error: {:?}", err);
+                    exit(1);
+                });
+            unsubscribe_on_signal(transport_mode, namespace, &udsock_dir, unsubscribe);
+        },
+        _ => exit_on_signal(),
+    }
+
+    // The server doesn't reply to a subscription until a task event happens, nor to a wait
+    // until the awaited request concludes.
+    let first_timeout = match &request {
+        messaging::ClientRequest::Subscribe(..) | messaging::ClientRequest::Wait(..) => timeouts.idle,
+        _ => timeouts.first,
+    };
+    listener.set_read_timeout(first_timeout).unwrap_or_else(|err| {
+        log::error!("Could not set timeout on UdSocket. Error: {:?}", err);
+        exit(1);
     });
-    log::info!("sdstore: wrote\n{:?} to UdSocket", request);
 
     match &request {
-        messaging::ClientRequest::Status(_) => {
-            status_msg(&listener)
+        messaging::ClientRequest::ProcFile(_) if requests.len() > 1 => {
+            let mut tasks = Vec::new();
+            for request in requests {
+                let messaging::ClientRequest::ProcFile(task) = request else { continue };
+                let msg = codec.encode(&messaging::ClientRequest::ProcFile(task.clone()))
+                    .unwrap_or_else(|err| {
+                        log::error!("Could not serialize request. Error: {:?}", err);
+                        exit(1);
+                    });
+                backoff
+                    .retry(|| messaging::send_message(listener.as_ref(), &msg, &server_udsock))
+                    .unwrap_or_else(|err| unreachable("sdstored: Could not send to UdSocket", err, backoff));
+                log::info!("submitted request {} for {:?}", task.request_id, task.input_filepath());
+                tasks.push(*task);
+            }
+            let notifications = NotificationReceiver::new(codec, client_pid, Uuid::nil(), server_udsock);
+            if !proc_files_msg(listener.as_ref(), notifications, &tasks, timeouts, output, no_wait) {
+                exit(1);
+            }
+        },
+        messaging::ClientRequest::Connect(_) => {
+            // Tasks are submitted, and notifications sent, over the transport connected with.
+            backoff
+                .retry(|| messaging::send_message(listener.as_ref(), &msg, &server_udsock))
+                .unwrap_or_else(|err| unreachable("sdstored: Could not send to UdSocket", err, backoff));
+            // Only watching a directory connects without a request to follow.
+            let Some((args, folder)) = &watch_dir else { exit(1) };
+            let notifications = NotificationReceiver::new(codec, client_pid, Uuid::nil(), server_udsock.clone());
+            watch_dir_msg(listener.as_ref(), notifications, folder, args, codec, client_pid, &server_udsock, backoff, output)
         },
-        messaging::ClientRequest::ProcFile(_) => {
-            proc_file_msg(&listener)
+        messaging::ClientRequest::ProcFile(task) if task.stream => {
+            // Notifications are sent over the transport, rather than the stream.
+            let connect = codec.encode(&messaging::ClientRequest::Connect(client_pid))
+                .unwrap_or_else(|err| {
+                    log::error!("Could not serialize request. Error: {:?}", err);
+                    exit(1);
+                });
+            backoff
+                .retry(|| messaging::send_message(listener.as_ref(), &connect, &server_udsock))
+                .unwrap_or_else(|err| unreachable("sdstored: Could not send to UdSocket", err, backoff));
+            let stream = stream_request(&udsock_dir, namespace, &msg, task, backoff);
+            log::info!("submitted request {request_id}");
+            let notifications = NotificationReceiver::new(codec, client_pid, request_id, server_udsock);
+            if proc_file_msg(listener.as_ref(), notifications, timeouts, output, false, progress_bar(task, output), None) {
+                match receive_output(stream, task) {
+                    Err(err) => log::error!("Could not receive output from server. Error: {:?}", err),
+                    Ok(n) => log::info!("received {n} bytes of output into {:?}", task.output_filepath()),
+                }
+            }
+        },
+        _ => {
+            backoff
+                .retry(|| messaging::send_message(listener.as_ref(), &msg, &server_udsock))
+                .unwrap_or_else(|err| unreachable("sdstored: Could not send to UdSocket", err, backoff));
+            log::info!("sdstore: wrote\n{:?} to UdSocket", request);
+
+            match &request {
+                messaging::ClientRequest::Status(..) if watch_interval.is_some() => {
+                    let notifications = NotificationReceiver::new(codec, client_pid, request_id, server_udsock.clone());
+                    let interval = watch_interval.unwrap_or_default();
+                    watch_msg(listener.as_ref(), notifications, codec, client_pid, &server_udsock, interval, backoff, output)
+                },
+                messaging::ClientRequest::Status(..) | messaging::ClientRequest::History(..) |
+                messaging::ClientRequest::Query(..) | messaging::ClientRequest::Logs(..) |
+                messaging::ClientRequest::ServerInfo(..) | messaging::ClientRequest::Report(..) => {
+                    let notifications = NotificationReceiver::new(codec, client_pid, request_id, server_udsock);
+                    reply_msg(listener.as_ref(), notifications, output)
+                },
+                messaging::ClientRequest::ProcFile(task) => {
+                    log::info!("submitted request {request_id}");
+                    let notifications = NotificationReceiver::new(codec, client_pid, request_id, server_udsock);
+                    let progress = progress_bar(task, output);
+                    let inline = task.inline.is_some().then_some(task.as_ref());
+                    proc_file_msg(listener.as_ref(), notifications, timeouts, output, no_wait, progress, inline);
+                },
+                messaging::ClientRequest::Wait(..) => {
+                    let notifications = NotificationReceiver::new(codec, client_pid, request_id, server_udsock);
+                    wait_msg(listener.as_ref(), notifications, output)
+                },
+                messaging::ClientRequest::Ping(..) => {
+                    let notifications = NotificationReceiver::new(codec, client_pid, request_id, server_udsock);
+                    ping_msg(listener.as_ref(), notifications, output)
+                },
+                messaging::ClientRequest::Health(..) => {
+                    let notifications = NotificationReceiver::new(codec, client_pid, request_id, server_udsock);
+                    health_msg(listener.as_ref(), notifications, output)
+                },
+                messaging::ClientRequest::Subscribe(..) => {
+                    let notifications = NotificationReceiver::new(codec, client_pid, request_id, server_udsock);
+                    subscribe_msg(listener.as_ref(), notifications, output);
+                },
+                messaging::ClientRequest::Reexec(..) => {
+                    let notifications = NotificationReceiver::new(codec, client_pid, request_id, server_udsock);
+                    reexec_msg(listener.as_ref(), notifications, output)
+                },
+                messaging::ClientRequest::Suspend(..) | messaging::ClientRequest::Resume(..) => {
+                    let notifications = NotificationReceiver::new(codec, client_pid, request_id, server_udsock);
+                    suspend_msg(listener.as_ref(), notifications, output)
+                },
+                // The cancelled request's own client is told it failed, rather than this one.
+                messaging::ClientRequest::Cancel(_, cancelled) => log::info!("asked the server to cancel request {cancelled}"),
+                // Only ever sent on the client's own.
+                messaging::ClientRequest::Ack(..) | messaging::ClientRequest::Connect(_) |
+                messaging::ClientRequest::Unsubscribe(_) => {},
+            }
         }
     }
 
     log::info!("Exiting!");
     drop(listener);
-    // TODO If the client receives e.g. `SIGKILL` while waiting for a message, the socket file
-    // will not be deleted.
-    //
-    // this can be fixed with the `signal_hook` crate, enabling us to install signal handlers.
-    fs::remove_file(client_udsock).unwrap_or_else(|err| {
-        log::error!("Error deleting client udsocket file: {:?}", err);
-        process::exit(1);
-    });
+    // A client killed with `SIGKILL` can't remove its socket file, unlike one exiting on any of
+    // `EXIT_SIGNALS`.
+    exit(0);
 }
\ No newline at end of file
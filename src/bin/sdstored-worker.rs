@@ -0,0 +1,347 @@
+use std::{collections::HashMap, ffi::CStr, net::TcpStream, path::Path, process, sync::Arc, thread, time::{Duration, Instant}};
+
+use clap::Parser;
+use tokio::{
+    signal::unix::{signal, Signal, SignalKind},
+    sync::mpsc::{self, Receiver, Sender},
+};
+
+use rust_sdstore::core::{
+    client_task::ClientTask,
+    limits::RunningFilters,
+    messaging::{MessageToServer, RequestFailure},
+    monitor::{Monitor, MonitorResult, TaskSummary},
+    server::{
+        cli::{ServerEnv, WorkerCli},
+        config::{ServerCfgParseError, ServerConfig},
+        coordinator::{self, CoordinatorMessage, RegisterError, WorkerMessage, HEARTBEAT_INTERVAL},
+        monitor_pool::MonitorPool,
+        pool::WorkerPool,
+        state::MESSAGE_BACKLOG,
+        wasm::WasmRuntime,
+    },
+};
+
+/// How long the worker waits before registering with the server again, once it could not
+/// reach it, or was disconnected from it.
+const REGISTER_RETRY_DELAY: Duration = Duration::from_secs(2);
+
+/// How many messages from the server may be pending, as the worker's event loop is busy.
+const COORDINATOR_BACKLOG: usize = 64;
+
+fn main() {
+    // The worker is configured as the server is, its limits being those it registers with.
+    let cli = WorkerCli::parse();
+    let server_config = ServerConfig::build(&cli.server_cli(), &ServerEnv::read());
+
+    let log_config = server_config.as_ref().map(|config| config.log.clone()).unwrap_or_default();
+    let log_file = log_config.file.as_deref().and_then(Path::to_str);
+    rust_sdstore::util::init_logging_infrastructure(
+        log_file, log_config.level, &log_config.targets, log_config.format, log_config.rotation, log_config.sink
+    ).unwrap_or_else(|err| {
+        eprintln!("Could not init logging infrastructure! Error: {:?}", err);
+        eprintln!("Exiting");
+        process::exit(1);
+    });
+
+    let server_config = server_config.unwrap_or_else(|err| {
+        match err {
+            ServerCfgParseError::MissingExecutables(missing) => {
+                for (filter, path) in missing {
+                    log::error!("No executable for filter {filter}: {:?} is missing, or not executable", path);
+                }
+            },
+            err => log::error!("Problem parsing config: {:?}", err),
+        }
+        process::exit(1);
+    });
+    log::info!("Read config:\n{:?}", server_config);
+    let name = cli.name.clone().unwrap_or_else(hostname);
+
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .unwrap_or_else(|err| {
+            log::error!("Could not build the worker's async runtime. Error: {:?}", err);
+            process::exit(1);
+        });
+    let _runtime = runtime.enter();
+
+    let mut worker = Worker::start(server_config).unwrap_or_else(|err| {
+        log::error!("Could not start the worker. Error: {err}");
+        process::exit(1);
+    });
+    let mut signals = signal(SignalKind::interrupt())
+        .and_then(|interrupt| Ok((interrupt, signal(SignalKind::terminate())?)))
+        .unwrap_or_else(|err| {
+            log::error!("Could not set up handling of termination signals. Error: {:?}", err);
+            process::exit(1);
+        });
+
+    runtime.block_on(async {
+        loop {
+            let limits = worker.config.filters_config.clone();
+            let token = worker.config.worker_token.clone();
+            let registered = coordinator::register(&cli.coordinator, &name, token, limits, worker.max_tasks());
+            let stream = match registered {
+                Err(RegisterError::Refused(reason)) => {
+                    log::error!("The server at {} refused the worker: {reason}", cli.coordinator);
+                    process::exit(1);
+                },
+                Err(RegisterError::Io(err)) => {
+                    log::warn!("could not register with the server at {}: {:?}", cli.coordinator, err);
+                    tokio::select! {
+                        _ = tokio::time::sleep(REGISTER_RETRY_DELAY) => continue,
+                        signal = next_signal(&mut signals) => {
+                            log::info!("received signal {signal}, shutting down");
+                            break;
+                        },
+                    }
+                },
+                Ok((stream, id)) => {
+                    log::info!("registered with the server at {} as worker #{id}, named {name}", cli.coordinator);
+                    stream
+                },
+            };
+
+            let stop = worker.serve(stream, &mut signals).await;
+            // The server runs the tasks again elsewhere, which must not race the ones left here.
+            worker.abandon_tasks().await;
+            if stop {
+                break;
+            }
+            tokio::time::sleep(REGISTER_RETRY_DELAY).await;
+        }
+    });
+    log::info!("worker shut down");
+}
+
+/// The worker's state: the tasks it was handed by the server, and what it runs them with.
+struct Worker {
+    config: ServerConfig,
+    monitors: MonitorPool,
+    pool: Option<Arc<WorkerPool>>,
+    wasm: Option<Arc<WasmRuntime>>,
+    /// Tasks running, by the numbers the server gave them.
+    running: HashMap<usize, Monitor>,
+    /// Count of the filters of the tasks running.
+    filters_count: RunningFilters,
+    /// Channel monitors tell the worker of their tasks' progress and results through.
+    sender: Sender<MessageToServer>,
+    receiver: Receiver<MessageToServer>,
+}
+
+impl Worker {
+    /// Start the threads tasks run on, as the server's, see [`ServerConfig::monitor_threads`].
+    fn start(config: ServerConfig) -> Result<Self, String> {
+        let monitors = MonitorPool::new(config.monitor_threads)
+            .map_err(|err| format!("could not start the pool of monitor threads: {:?}", err))?;
+        let pool = match config.pool_size {
+            0 => None,
+            size => Some(Arc::new(
+                WorkerPool::new(size, &config).map_err(|err| format!("could not start the pool of filter workers: {:?}", err))?
+            )),
+        };
+        let modules = config.wasm_modules();
+        let wasm = match modules.is_empty() {
+            true => None,
+            false => Some(Arc::new(
+                WasmRuntime::load(&modules).map_err(|err| format!("could not load the WASM filters: {:?}", err))?
+            )),
+        };
+        let (sender, receiver) = mpsc::channel(MESSAGE_BACKLOG);
+        Ok(Worker {
+            config, monitors, pool, wasm, sender, receiver, running: HashMap::new(), filters_count: RunningFilters::default()
+        })
+    }
+
+    /// Most tasks the worker runs at once, one per thread of its monitor pool.
+    fn max_tasks(&self) -> usize {
+        self.monitors.size()
+    }
+
+    /// Run the tasks the server hands the worker over `stream`, telling it of their progress
+    /// and results, until either disconnects, or the worker is to stop, when `true` is returned.
+    async fn serve(&mut self, stream: TcpStream, signals: &mut (Signal, Signal)) -> bool {
+        let (coordinator_sender, mut coordinator) = mpsc::channel(COORDINATOR_BACKLOG);
+        let reader = stream.try_clone().and_then(|reader| thread::Builder::new()
+            .name(String::from("sdstored_worker_reader"))
+            .spawn(move || loop {
+                let msg = coordinator::receive::<CoordinatorMessage>(&reader);
+                let failed = msg.is_err();
+                if coordinator_sender.blocking_send(msg).is_err() || failed {
+                    break;
+                }
+            })
+        );
+        if let Err(err) = reader {
+            log::error!("could not spawn thread to read from the server: {:?}", err);
+            return false
+        }
+
+        let mut heartbeat = tokio::time::interval(HEARTBEAT_INTERVAL);
+        let stop = loop {
+            let sent = tokio::select! {
+                msg = coordinator.recv() => match msg {
+                    Some(Ok(msg)) => self.handle_coordinator_message(&stream, msg),
+                    Some(Err(err)) => {
+                        log::warn!("disconnected from the server: {:?}", err);
+                        break false
+                    },
+                    None => break false,
+                },
+                msg = self.receiver.recv() => match msg {
+                    Some(msg) => self.handle_monitor_message(&stream, msg),
+                    None => break true,
+                },
+                _ = heartbeat.tick() => coordinator::send(&stream, &WorkerMessage::Heartbeat),
+                signal = next_signal(signals) => {
+                    log::info!("received signal {signal}, shutting down");
+                    break true
+                },
+            };
+            if let Err(err) = sent {
+                log::warn!("could not write to the server, disconnecting: {:?}", err);
+                break false
+            }
+        };
+        // Which ends the thread reading from the server.
+        let _ = stream.shutdown(std::net::Shutdown::Both);
+        stop
+    }
+
+    fn handle_coordinator_message(&mut self, stream: &TcpStream, msg: CoordinatorMessage) -> std::io::Result<()> {
+        match msg {
+            CoordinatorMessage::Run { task_number, task, waited } => {
+                log::info!("running task #{task_number} by client PID {}", task.client_pid);
+                if let Err(reason) = self.run(task_number, *task, waited) {
+                    log::warn!("declined task #{task_number}: {reason}");
+                    return coordinator::send(stream, &WorkerMessage::Declined { task_number, reason })
+                }
+            },
+            CoordinatorMessage::Cancel(task_number) => match self.running.get(&task_number) {
+                None => log::debug!("task #{task_number} to be cancelled is not running"),
+                Some(monitor) => if let Err(err) = monitor.cancel() {
+                    log::warn!("could not cancel task #{task_number}: {:?}", err);
+                },
+            },
+            CoordinatorMessage::Heartbeat => {},
+            msg => log::warn!("unexpected message from the server: {:?}", msg),
+        }
+        Ok(())
+    }
+
+    /// Start running `task` as task #`task_number`, unless it doesn't fit within the worker's
+    /// limits, or can't be started, for the reason returned.
+    fn run(&mut self, task_number: usize, mut task: ClientTask, waited: Duration) -> Result<(), String> {
+        if !coordinator::is_eligible(&task) {
+            return Err(String::from("the task can only be run by the server"))
+        }
+        if self.running.len() >= self.max_tasks() {
+            return Err(format!("the worker runs {} tasks already", self.running.len()))
+        }
+        if !self.filters_count.can_run_pipeline(&self.config.filters_config, &task.transformations) {
+            return Err(String::from("the task's filters exceed the worker's limits"))
+        }
+
+        task.received_at = Instant::now().checked_sub(waited);
+        let task = Arc::new(task);
+        let executors = task
+            .transformations
+            .iter()
+            .map(|filter| self.config.filter_executor(filter))
+            .collect();
+        let monitor = Monitor::build(
+            Arc::clone(&task),
+            task_number,
+            executors,
+            self.config.resource_limits.for_priority(task.priority),
+            self.config.sandbox,
+            self.config.environment.for_task(&task),
+            self.sender.clone(),
+            None,
+            // Pooled workers are started in the worker's environment, see `WorkerPool::new`.
+            self.pool.clone().filter(|_| task.env.is_empty() && task.cwd.is_none()),
+            None,
+            self.wasm.clone(),
+            self.config.progress_interval,
+            self.config.pipe_buffer,
+            self.config.path_policy.for_task(&task),
+            &self.monitors
+        ).map_err(|err| format!("the task could not be started: {:?}", err))?;
+        self.filters_count += &task.transformations;
+        self.running.insert(task_number, monitor);
+        Ok(())
+    }
+
+    fn handle_monitor_message(&mut self, stream: &TcpStream, msg: MessageToServer) -> std::io::Result<()> {
+        match msg {
+            MessageToServer::Progress(progress) => {
+                let bytes_out = progress.bytes_out;
+                coordinator::send(stream, &WorkerMessage::Progress { task_number: progress.task_number, bytes_out })
+            },
+            MessageToServer::Monitor(res) => match self.conclude(res) {
+                Some(finished) => coordinator::send(stream, &finished),
+                None => Ok(()),
+            },
+            _ => Ok(()),
+        }
+    }
+
+    /// Count out the task a monitor reported the result of, returning the message telling
+    /// the server of it, unless the task was abandoned.
+    fn conclude(&mut self, res: MonitorResult) -> Option<WorkerMessage> {
+        let MonitorResult { task_number, result, partial_output } = res;
+        let monitor = self.running.remove(&task_number)?;
+        self.filters_count -= &monitor.task.transformations;
+        if let Some(partial_output) = partial_output {
+            log::info!("partial output of task #{task_number}: {:?}", partial_output);
+        }
+        let result = match result {
+            Ok(TaskSummary::File(success)) => Ok(success),
+            Ok(TaskSummary::Batch(_)) => Err(RequestFailure::Internal(String::from("workers don't run batches"))),
+            Err(err) => Err(RequestFailure::from(err)),
+        };
+        log::info!("task #{task_number} concluded: {}", if result.is_ok() { "succeeded" } else { "failed" });
+        Some(WorkerMessage::Finished { task_number, result })
+    }
+
+    /// Kill every task running, waiting for their monitors to be done with them, as the
+    /// server no longer expects their results.
+    async fn abandon_tasks(&mut self) {
+        for (task_number, monitor) in &self.running {
+            if let Err(err) = monitor.kill() {
+                log::warn!("could not kill task #{task_number}: {:?}", err);
+            }
+        }
+        while !self.running.is_empty() {
+            match self.receiver.recv().await {
+                Some(MessageToServer::Monitor(res)) => {
+                    self.conclude(res);
+                },
+                Some(_) => {},
+                None => break,
+            }
+        }
+    }
+}
+
+/// Wait for `SIGINT` or `SIGTERM`, returning its name.
+async fn next_signal((interrupt, terminate): &mut (Signal, Signal)) -> &'static str {
+    tokio::select! {
+        _ = interrupt.recv() => "SIGINT",
+        _ = terminate.recv() => "SIGTERM",
+    }
+}
+
+/// The name of the host, which workers are named after by default.
+fn hostname() -> String {
+    let mut buf = [0u8; 256];
+    // SAFETY: `buf` is valid for writes of its whole length.
+    let named = unsafe { libc::gethostname(buf.as_mut_ptr().cast(), buf.len()) } == 0;
+    CStr::from_bytes_until_nul(&buf)
+        .ok()
+        .filter(|_| named)
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_else(|| String::from("worker"))
+}
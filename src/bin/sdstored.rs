@@ -1,122 +1,121 @@
-use std::{
-    env, process, fs, io, os::unix::net::UnixDatagram
-};
+use std::{process, path::Path};
 
 
-use rust_sdstore::{
-    core::{
-        messaging::ClientRequest,
-        server::{config, state::ServerState},
-        messaging::MessageToServer
-    }
+use clap::Parser;
+
+use rust_sdstore::core::server::{
+    check, cli::{ServerCli, ServerEnv}, config, daemon::{self, LockError}, embed::{Server, SpawnError}, handover, systemd,
 };
 
 fn main() {
-    // Init logging
-    rust_sdstore::util::init_logging_infrastructure(
-        None,
-        log::LevelFilter::Trace
-    ).unwrap_or_else(|err| {
-        eprintln!("Could not init logging infrastructure! Error: {:?}", err);
+    // Read the server's configs from its command line, the config file it names, and its environment
+    let cli = ServerCli::parse();
+    let server_config = config::ServerConfig::build(&cli, &ServerEnv::read());
+    if cli.check_config {
+        process::exit(check_config(server_config));
+    }
+
+    // Init logging, as configured, or by default if the config is to be reported as invalid
+    let log_config = server_config.as_ref().map(|config| config.log.clone()).unwrap_or_default();
+    let log_file = log_config.file.as_deref().and_then(Path::to_str);
+    let logging = match log_config.tracing {
+        false => rust_sdstore::util::init_logging_infrastructure(
+            log_file, log_config.level, &log_config.targets, log_config.format, log_config.rotation, log_config.sink
+        ).map_err(|err| format!("{:?}", err)),
+        true => rust_sdstore::util::init_tracing(
+            log_file, log_config.level, &log_config.targets, log_config.format, log_config.rotation
+        ).map_err(|err| format!("{:?}", err)),
+    };
+    logging.unwrap_or_else(|err| {
+        eprintln!("Could not init logging infrastructure! Error: {err}");
         eprintln!("Exiting");
         std::process::exit(1);
     });
 
-    // Read the server's configs from args: file with max filter definitions, and binary folder path
-    let server_config = config::ServerConfig::build(&mut env::args())
+    let server_config = server_config
         .unwrap_or_else(|err| {
-            log::error!("Problem parsing config: {:?}", err);
+            match err {
+                config::ServerCfgParseError::MissingExecutables(missing) => {
+                    for (filter, path) in missing {
+                        log::error!("No executable for filter {filter}: {:?} is missing, or not executable", path);
+                    }
+                },
+                err => log::error!("Problem parsing config: {:?}", err),
+            }
             process::exit(1);
         });
     log::info!("Read config:\n{:?}", server_config);
 
-    let curr_dir = std::env::current_dir().unwrap_or_else(|err| {
-        log::error!("Could not get pwd. Error {:?}", err);
-        process::exit(1);
+    // Read before any thread is spawned, as the environment is changed, see `Notifier::from_env`
+    let notifier = systemd::Notifier::from_env().unwrap_or_else(|err| {
+        log::warn!("Could not set up notifying systemd. Error: {:?}", err);
+        None
     });
-    // Init socket file
-    // TODO: fix this unwrap
-    let udsock_dir = curr_dir.parent().unwrap().join("tmp");
-    log::info!("dir to be used for udsock is {:?}", udsock_dir);
+    if let Some(watchdog) = notifier.as_ref().and_then(systemd::Notifier::watchdog) {
+        log::info!("systemd expects a watchdog ping every {:.1}s", watchdog.as_secs_f64());
+    }
 
-    // Init the Unix domain socket
-    let server_udsock = udsock_dir.join("sdstored.sock");
-    match fs::remove_file(server_udsock.clone()) {
-        Err(ref e) if e.kind() == io::ErrorKind::NotFound => {},
-        Err(err) => {
-            log::error!("could not unlink existing server udsocket. Error: {:?}", err);
+    // Only the config's errors, and those setting up the server, are output to the terminal
+    // unless in the foreground, see `daemon::daemonize`. A server taking over from another
+    // runs wherever that one did.
+    let readiness = (!cli.foreground && !handover::InheritedFds::in_env()).then(|| {
+        daemon::daemonize().unwrap_or_else(|err| {
+            log::error!("Could not run the server in the background. Error: {:?}", err);
             process::exit(1);
-        },
-        Ok(_) => {}
-    };
-    let listener =
-        UnixDatagram::bind(server_udsock.as_path())
-            .unwrap_or_else(|err| {
-                log::error!("Could not create listener on socket. Error: {:?}", err);
-                process::exit(1);
-            });
-    log::info!("server listening on Unix datagram socket: {:?}", listener);
-
-    let mut server_state = ServerState::new(listener, udsock_dir);
+        })
+    });
 
-    server_state
-        .spawn_udsock_mngr("sdstored_udsock_listener")
+    let server = Server::builder()
+        .config(server_config)
+        .handle_signals(true)
+        .notifier(notifier)
+        .reexec(true)
+        .build()
         .unwrap_or_else(|err| {
-            log::error!("Could not spawn UdSocket listening thread. Error: {:?}", err);
+            match err {
+                SpawnError::Locked(lock, LockError::Held(pid)) => log::error!(
+                    "Another server is running with the same socket dir{}, holding {:?}",
+                    pid.map(|pid| format!(", as PID {pid}")).unwrap_or_default(), lock
+                ),
+                err => log::error!("Could not set up the server. Error: {:?}", err),
+            }
+            process::exit(1);
+        });
+    if let Some(readiness) = readiness {
+        readiness.notify().unwrap_or_else(|err| {
+            log::error!("Could not detach from the terminal. Error: {:?}", err);
             process::exit(1);
         });
+    }
 
     // Loop the processing clients' and monitors' messages.
-    loop {
-        let msg = match server_state.receiver.recv() {
-            Err(err) => {
-                log::warn!("could not read from message receiver. Error: {:?}", err);
-                break;
-            },
-            Ok(t) => t
-        };
-        match msg {
-            MessageToServer::Client(ClientRequest::Status(client_pid)) => {
-                log::info!("status request by client PID {client_pid}");
-                match server_state.fmt_client_status(&server_config, client_pid) {
-                    Err(err) =>
-                        log::warn!("failed to sever status request by client PID {client_pid} with error {:?}", err),
-                    _ => log::trace!("served status request to client PID {client_pid}"),
-                };
-            }
-            MessageToServer::Client(ClientRequest::ProcFile(task)) => {
-                let client_pid = task.client_pid;
-                log::info!("Attempting to queueing received task:\n{:?}", task);
-                match server_state.new_task(task) {
-                    Ok(_) => log::info!("Successfully queued task by client PID {client_pid}"),
-                    Err(err) => log::error!("Failed to queue task by client PID {client_pid}: {:?}", err),
-                }
-            }
-            MessageToServer::Monitor(res) => {
-                let t_id = res.thread;
-                let cl_pid = match server_state.client_pid_from_monitor_id(&t_id) {
-                    None => {
-                        log::error!("message received from nonexistent monitor!");
-                        break;
-                    }
-                    Some(t) => t
-                };
-                match server_state.handle_task_result(res) {
-                    Err(err) => log::error!("Monitor {:?} for task by client {cl_pid} failed: {:?}", t_id, err),
-                    Ok(_)  => log::info!("Monitor {:?} for task by client {cl_pid} succeeded.", t_id)
-                }
-            }
-        }
+    server.run();
+}
 
-        while let Some(task) = server_state.try_pop_task(&server_config) {
-            let client_pid = task.client_pid;
-            log::info!("Executing task popped from pqueue:\n{:?}", task);
-            match server_state.process_task(&server_config, task) {
-                Err(err) => log::error!("Failed to process task by client PID {client_pid}: {:?}", err),
-                Ok((mon_id, task_num)) =>
-                    log::info!("Task by client {client_pid} assigned number {task_num} and monitor {:?}", mon_id)
-            }
-        }
+/// Report the problems with the server's config, or else summarize it, to the terminal
+/// rather than the logs, returning the exit code of `--check-config`: `1` if there are
+/// problems, see [`check::check_config`].
+fn check_config(server_config: Result<config::ServerConfig, config::ServerCfgParseError>) -> i32 {
+    let problems = match server_config {
+        Err(config::ServerCfgParseError::MissingExecutables(missing)) => missing
+            .into_iter()
+            .map(|(filter, path)| format!("no executable for filter {filter} at {}", path.display()))
+            .collect(),
+        Err(err) => vec![format!("problem parsing config: {:?}", err)],
+        Ok(server_config) => {
+            print!("{}", check::summary(&server_config));
+            check::check_config(&server_config)
+        },
+    };
 
+    for problem in &problems {
+        eprintln!("error: {problem}");
+    }
+    match problems.is_empty() {
+        true => {
+            println!("config OK");
+            0
+        },
+        false => 1,
     }
-}
\ No newline at end of file
+}
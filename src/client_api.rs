@@ -0,0 +1,375 @@
+//! A client of the `sdstored` server for other Rust programs, which submit and follow
+//! requests through [`SdstoreClient`], rather than by running the `sdstore` binary.
+
+use std::{
+    collections::{HashMap, VecDeque}, fs, io, os::unix::net::{UnixDatagram, UnixStream},
+    path::{Path, PathBuf}, process, time::{Duration, Instant},
+};
+
+use uuid::Uuid;
+
+#[cfg(feature = "async-client")]
+pub mod async_client;
+
+use crate::core::{
+    accounting::Report,
+    client_task::ClientTask,
+    messaging::{self, ClientRequest, Codec, CodecError, MessageToClient, NotificationReceiver, RequestFailure, TaskId, WireFormat},
+    health::Health,
+    server_info::ServerInfo,
+    status::ServerStatus,
+    transport::{self, Peer, Transport, TransportMode, CONNECTION_SOCKET}
+};
+
+/// Errors making requests to the server, or following them, see [`SdstoreClient`].
+#[derive(Debug)]
+pub enum ClientError {
+    Io(io::Error),
+    Codec(CodecError),
+    /// The request failed, for this reason.
+    Failed(RequestFailure),
+    /// The server refused the request, for this reason.
+    Refused(String),
+    /// The server replied to the request with a message it doesn't reply to it with.
+    Unexpected(Box<MessageToClient>),
+    /// Streamed tasks can only be submitted with the `sdstore` binary, see [`ClientTask::stream`].
+    StreamingUnsupported,
+}
+
+impl From<io::Error> for ClientError {
+    fn from(err: io::Error) -> Self {
+        Self::Io(err)
+    }
+}
+
+impl From<CodecError> for ClientError {
+    fn from(err: CodecError) -> Self {
+        Self::Codec(err)
+    }
+}
+
+/// A task submitted to the server, whose progress and result are received through the
+/// [`SdstoreClient`] that submitted it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct TaskHandle {
+    request_id: Uuid,
+}
+
+impl TaskHandle {
+    /// ID of the request the task was submitted in, see [`ClientRequest::request_id`].
+    pub fn request_id(&self) -> Uuid {
+        self.request_id
+    }
+}
+
+/// A client of the server, which may have several requests in flight at once: the server's
+/// messages about each of them are kept until they are asked for.
+///
+/// Only one client may be connected per process, since the server tells clients apart by
+/// their PID. The server opens the paths of submitted tasks itself, relative to its own
+/// working directory, so absolute paths should be used.
+pub struct SdstoreClient {
+    transport: Box<dyn Transport>,
+    /// The server, to which requests are sent.
+    server: Peer,
+    codec: WireFormat,
+    client_pid: u32,
+    notifications: NotificationReceiver<MessageToClient>,
+    /// Messages received about each request, yet to be asked for.
+    inbox: HashMap<Uuid, VecDeque<MessageToClient>>,
+    /// The client's datagram socket file, if it bound one, removed when it is dropped.
+    socket_file: Option<PathBuf>,
+}
+
+impl SdstoreClient {
+    /// Connect to the server whose sockets are in `udsock_dir`, over `transport_mode`, and
+    /// encoding messages with `codec`, both of which must match the server's.
+    pub fn connect(udsock_dir: &Path, transport_mode: TransportMode, codec: WireFormat) -> io::Result<Self> {
+        let client_pid = process::id();
+        let (transport, server, socket_file): (Box<dyn Transport>, _, _) = match transport_mode {
+            TransportMode::Datagram => {
+                let socket_file = udsock_dir.join(format!("sdstore_{client_pid}.sock"));
+                let socket = UnixDatagram::bind(&socket_file)?;
+                let server = udsock_dir.join("sdstored.sock");
+                transport::allow_server_replies(&socket_file, &server)?;
+                (Box::new(socket), Peer::Path(server), Some(socket_file))
+            },
+            TransportMode::Stream => {
+                let server = udsock_dir.join(CONNECTION_SOCKET);
+                (Box::new(UnixStream::connect(&server)?), Peer::Path(server), None)
+            },
+        };
+
+        Ok(SdstoreClient {
+            transport,
+            notifications: NotificationReceiver::new(codec, client_pid, Uuid::nil(), server.clone()),
+            server,
+            codec,
+            client_pid,
+            inbox: HashMap::new(),
+            socket_file,
+        })
+    }
+
+    /// Submit `task` to the server, returning a handle to follow it with, see
+    /// [`SdstoreClient::wait`] and [`SdstoreClient::poll`]. The task is submitted as this
+    /// client's, in a request of its own.
+    pub fn submit(&mut self, mut task: ClientTask) -> Result<TaskHandle, ClientError> {
+        if task.stream {
+            return Err(ClientError::StreamingUnsupported)
+        }
+        task.client_pid = self.client_pid;
+        task.request_id = Uuid::new_v4();
+        let handle = TaskHandle { request_id: task.request_id };
+        self.send(&ClientRequest::ProcFile(Box::new(task)))?;
+        Ok(handle)
+    }
+
+    /// Ask for the server's status, waiting for its reply.
+    pub fn status(&mut self) -> Result<ServerStatus, ClientError> {
+        let request_id = Uuid::new_v4();
+        self.send(&ClientRequest::Status(self.client_pid, request_id))?;
+        match self.recv(request_id, None)? {
+            Some(MessageToClient::Status(status)) => Ok(status),
+            Some(msg) => Err(unexpected(msg)),
+            None => Err(io::Error::from(io::ErrorKind::TimedOut).into()),
+        }
+    }
+
+    /// Ask whether the server is ready to take requests, waiting for its reply.
+    pub fn health(&mut self) -> Result<Health, ClientError> {
+        let request_id = Uuid::new_v4();
+        self.send(&ClientRequest::Health(self.client_pid, request_id))?;
+        match self.recv(request_id, None)? {
+            Some(MessageToClient::Health(health)) => Ok(health),
+            Some(msg) => Err(unexpected(msg)),
+            None => Err(io::Error::from(io::ErrorKind::TimedOut).into()),
+        }
+    }
+
+    /// Ask what the server runs with, e.g. its filters' limits, waiting for its reply.
+    pub fn server_info(&mut self) -> Result<ServerInfo, ClientError> {
+        let request_id = Uuid::new_v4();
+        self.send(&ClientRequest::ServerInfo(self.client_pid, request_id))?;
+        match self.recv(request_id, None)? {
+            Some(MessageToClient::ServerInfo(info)) => Ok(info),
+            Some(msg) => Err(unexpected(msg)),
+            None => Err(io::Error::from(io::ErrorKind::TimedOut).into()),
+        }
+    }
+
+    /// Ask what the server's tasks that concluded in the last `window`, or every task it
+    /// still accounts for, added up to, waiting for its reply. Only root, and the server's
+    /// own user, may ask, others' requests failing with [`ClientError::Refused`].
+    pub fn report(&mut self, window: Option<Duration>) -> Result<Report, ClientError> {
+        let request_id = Uuid::new_v4();
+        self.send(&ClientRequest::Report(self.client_pid, request_id, window))?;
+        match self.recv(request_id, None)? {
+            Some(MessageToClient::Report(report)) => Ok(report),
+            Some(msg) => Err(unexpected(msg)),
+            None => Err(io::Error::from(io::ErrorKind::TimedOut).into()),
+        }
+    }
+
+    /// Suspend the running task `task`, its filters no longer counting against the server's
+    /// limits until it is resumed, waiting for the server's reply, with the number the task
+    /// runs as. Tasks that aren't running on the server fail with [`RequestFailure::NotRunning`].
+    pub fn suspend(&mut self, task: TaskId) -> Result<usize, ClientError> {
+        let request_id = Uuid::new_v4();
+        self.send(&ClientRequest::Suspend(self.client_pid, request_id, task))?;
+        match self.recv(request_id, None)? {
+            Some(MessageToClient::TaskSuspended { task_number }) => Ok(task_number),
+            Some(msg) => Err(unexpected(msg)),
+            None => Err(io::Error::from(io::ErrorKind::TimedOut).into()),
+        }
+    }
+
+    /// Resume the suspended task `task`, as [`SdstoreClient::suspend`] suspends it.
+    pub fn resume(&mut self, task: TaskId) -> Result<usize, ClientError> {
+        let request_id = Uuid::new_v4();
+        self.send(&ClientRequest::Resume(self.client_pid, request_id, task))?;
+        match self.recv(request_id, None)? {
+            Some(MessageToClient::TaskResumed { task_number }) => Ok(task_number),
+            Some(msg) => Err(unexpected(msg)),
+            None => Err(io::Error::from(io::ErrorKind::TimedOut).into()),
+        }
+    }
+
+    /// Cancel the request `request_id`, e.g. of a [`TaskHandle`], which then fails with
+    /// [`RequestFailure::Cancelled`], unless it concluded already.
+    pub fn cancel(&mut self, request_id: Uuid) -> Result<(), ClientError> {
+        self.send(&ClientRequest::Cancel(self.client_pid, request_id))
+    }
+
+    /// Wait for the task of `handle` to conclude, returning the server's last message about
+    /// it: either [`MessageToClient::Concluded`], [`MessageToClient::BatchConcluded`], or the
+    /// report of a dry run. Messages about its progress are skipped.
+    pub fn wait(&mut self, handle: &TaskHandle) -> Result<MessageToClient, ClientError> {
+        loop {
+            match self.recv(handle.request_id, None)? {
+                Some(msg @ (MessageToClient::Concluded(_) | MessageToClient::BatchConcluded(_) |
+                    MessageToClient::DryRun(_))) => return Ok(msg),
+                Some(msg @ (MessageToClient::Failed(_) | MessageToClient::Refused(_))) => return Err(unexpected(msg)),
+                Some(_) => continue,
+                None => return Err(io::Error::from(io::ErrorKind::TimedOut).into()),
+            }
+        }
+    }
+
+    /// Wait up to `timeout` for the server's next message about the task of `handle`,
+    /// returning `None` if none arrives in time.
+    pub fn poll(&mut self, handle: &TaskHandle, timeout: Duration) -> Result<Option<MessageToClient>, ClientError> {
+        self.recv(handle.request_id, Some(timeout))
+    }
+
+    /// Wait up to `timeout` for the server's next message about any of this client's tasks,
+    /// returning it along with the task's handle, or `None` if none arrives in time. Messages
+    /// kept as others were waited on come first, and those about no task in particular come
+    /// with a handle whose request ID is nil.
+    pub fn next(&mut self, timeout: Duration) -> Result<Option<(TaskHandle, MessageToClient)>, ClientError> {
+        let request_id = match self.inbox.keys().next() {
+            Some(request_id) => *request_id,
+            None if timeout.is_zero() => return Ok(None),
+            None => match self.receive(Some(timeout))? {
+                None => return Ok(None),
+                Some((request_id, msg)) => return Ok(Some((TaskHandle { request_id }, msg))),
+            },
+        };
+        Ok(self.recv(request_id, None)?.map(|msg| (TaskHandle { request_id }, msg)))
+    }
+
+    /// Encode and send `request` to the server.
+    fn send(&self, request: &ClientRequest) -> Result<(), ClientError> {
+        let bytes = self.codec.encode(request)?;
+        messaging::send_message(self.transport.as_ref(), &bytes, &self.server)?;
+        Ok(())
+    }
+
+    /// Wait up to `timeout`, if given, for the next message about `request_id`, keeping the
+    /// messages about other requests received meanwhile. Messages about no request in
+    /// particular, with a nil ID, are taken to be about `request_id`, see
+    /// [`NotificationReceiver`].
+    fn recv(&mut self, request_id: Uuid, timeout: Option<Duration>) -> Result<Option<MessageToClient>, ClientError> {
+        let deadline = timeout.map(|timeout| Instant::now() + timeout);
+        loop {
+            if let Some(messages) = self.inbox.get_mut(&request_id) {
+                let msg = messages.pop_front();
+                if messages.is_empty() {
+                    self.inbox.remove(&request_id);
+                }
+                if msg.is_some() {
+                    return Ok(msg)
+                }
+            }
+
+            let remaining = match deadline.map(|deadline| deadline.saturating_duration_since(Instant::now())) {
+                Some(remaining) if remaining.is_zero() => return Ok(None),
+                remaining => remaining,
+            };
+            match self.receive(remaining)? {
+                None => return Ok(None),
+                Some((id, msg)) if id.is_nil() => self.inbox.entry(request_id).or_default().push_back(msg),
+                Some((id, msg)) => self.inbox.entry(id).or_default().push_back(msg),
+            }
+        }
+    }
+
+    /// Wait up to `timeout`, if given, for the server's next message, with the ID of the
+    /// request it is about.
+    fn receive(&mut self, timeout: Option<Duration>) -> Result<Option<(Uuid, MessageToClient)>, ClientError> {
+        self.transport.set_read_timeout(timeout)?;
+        let (id, msg) = match self.notifications.recv_any(self.transport.as_ref()) {
+            Err(err) if matches!(err.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut) =>
+                return Ok(None),
+            Err(err) => return Err(err.into()),
+            Ok(received) => received,
+        };
+        // The server numbers its messages about the request from the start once it restarts.
+        if matches!(msg, MessageToClient::Suspended) {
+            self.notifications.restart_sequence(id);
+        }
+        Ok(Some((id, msg)))
+    }
+}
+
+impl Drop for SdstoreClient {
+    fn drop(&mut self) {
+        if let Some(socket_file) = &self.socket_file {
+            if let Err(err) = fs::remove_file(socket_file) {
+                log::warn!("could not remove client socket file {:?}: {:?}", socket_file, err);
+            }
+        }
+    }
+}
+
+/// The error for a message a request wasn't expected to be replied to with, that is, but
+/// for failures and refusals, which have errors of their own.
+fn unexpected(msg: MessageToClient) -> ClientError {
+    match msg {
+        MessageToClient::Failed(failure) => ClientError::Failed(failure),
+        MessageToClient::Refused(reason) => ClientError::Refused(reason),
+        msg => ClientError::Unexpected(Box::new(msg)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use crate::core::{filter::Filter, messaging::{MessageReceiver, Sequenced}, testing::TempDir};
+
+    use super::*;
+
+    #[test]
+    fn replies_are_told_apart_by_request() {
+        let dir = TempDir::new("client_api");
+        let server = UnixDatagram::bind(dir.join("sdstored.sock")).unwrap();
+        let codec = WireFormat::default();
+        let mut client = SdstoreClient::connect(&dir, TransportMode::Datagram, codec).unwrap();
+        let client_udsock = Peer::Path(dir.join(format!("sdstore_{}.sock", process::id())));
+        let status = ServerStatus {
+            running: vec![],
+            queued: vec![],
+            filters: vec![],
+            queues: vec![],
+            uptime: Duration::from_secs(1),
+            stats: Default::default(),
+        };
+
+        // Replies about the task arrive around the reply to the status request, and each
+        // request's replies are numbered on their own.
+        let expected_status = status.clone();
+        let fake_server = thread::spawn(move || {
+            let mut requests = MessageReceiver::default();
+            let mut recv = || loop {
+                match codec.decode::<ClientRequest>(&requests.recv(&server).unwrap()).unwrap() {
+                    ClientRequest::Ack(..) => continue,
+                    request => break request,
+                }
+            };
+            let reply = |request_id, seq, message: MessageToClient| {
+                let bytes = codec.encode(&Sequenced { seq, request_id, message }).unwrap();
+                messaging::send_message(&server, &bytes, &client_udsock).unwrap();
+            };
+
+            let ClientRequest::ProcFile(task) = recv() else { panic!("expected the task") };
+            let ClientRequest::Status(_, status_id) = recv() else { panic!("expected a status request") };
+            reply(task.request_id, 0, MessageToClient::Processing);
+            reply(status_id, 0, MessageToClient::Status(status));
+            assert_eq!(recv(), ClientRequest::Cancel(process::id(), task.request_id));
+            reply(task.request_id, 1, MessageToClient::Failed(RequestFailure::Cancelled));
+        });
+
+        let task = ClientTask::new(0, 1, PathBuf::from("/in"), PathBuf::from("/out"), vec![Filter::Nop]);
+        let handle = client.submit(task).unwrap();
+        assert_eq!(client.status().unwrap(), expected_status);
+        client.cancel(handle.request_id()).unwrap();
+        assert_eq!(client.poll(&handle, Duration::from_secs(5)).unwrap(), Some(MessageToClient::Processing));
+        assert!(matches!(client.wait(&handle), Err(ClientError::Failed(RequestFailure::Cancelled))));
+        assert_eq!(client.poll(&handle, Duration::from_millis(10)).unwrap(), None);
+        fake_server.join().unwrap();
+
+        drop(client);
+        assert!(!dir.join(format!("sdstore_{}.sock", process::id())).exists());
+    }
+}
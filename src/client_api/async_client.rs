@@ -0,0 +1,341 @@
+//! An async variant of [`SdstoreClient`](super::SdstoreClient), for programs running on a
+//! `tokio` runtime, enabled by the `async-client` feature.
+
+use std::{
+    collections::HashMap, fs, io, path::{Path, PathBuf}, process, sync::{Arc, Mutex, MutexGuard}, time::Duration,
+};
+
+use tokio::{net::UnixDatagram, sync::mpsc, task::JoinHandle};
+use uuid::Uuid;
+
+use crate::core::{
+    accounting::Report,
+    client_task::ClientTask,
+    messaging::{self, ClientRequest, Codec, MessageReceiver, MessageToClient, NotificationReceiver, TaskId, WireFormat},
+    health::Health,
+    server_info::ServerInfo,
+    status::ServerStatus,
+    transport::{self, Peer},
+};
+
+use super::{unexpected, ClientError};
+
+/// Senders of the messages about each request in flight, to the handles following them.
+type Followers = Arc<Mutex<HashMap<Uuid, mpsc::UnboundedSender<MessageToClient>>>>;
+
+/// A task submitted to the server, through which its progress and result are awaited.
+pub struct AsyncTaskHandle {
+    request_id: Uuid,
+    messages: mpsc::UnboundedReceiver<MessageToClient>,
+}
+
+impl AsyncTaskHandle {
+    /// ID of the request the task was submitted in, see [`ClientRequest::request_id`].
+    pub fn request_id(&self) -> Uuid {
+        self.request_id
+    }
+
+    /// Wait for the server's next message about the task, or `None` once its last one was
+    /// received, or the client stopped receiving them.
+    pub async fn next(&mut self) -> Option<MessageToClient> {
+        self.messages.recv().await
+    }
+
+    /// Wait for the task to conclude, returning the server's last message about it, as
+    /// [`SdstoreClient::wait`](super::SdstoreClient::wait) does.
+    pub async fn wait(mut self) -> Result<MessageToClient, ClientError> {
+        loop {
+            match self.next().await {
+                Some(msg @ (MessageToClient::Concluded(_) | MessageToClient::BatchConcluded(_) |
+                    MessageToClient::DryRun(_))) => return Ok(msg),
+                Some(msg @ (MessageToClient::Failed(_) | MessageToClient::Refused(_))) => return Err(unexpected(msg)),
+                Some(_) => continue,
+                None => return Err(stopped_receiving().into()),
+            }
+        }
+    }
+}
+
+/// An async client of the server, over [`TransportMode::Datagram`](crate::core::transport::TransportMode).
+///
+/// A task spawned on the runtime receives the server's messages, and hands each to the
+/// [`AsyncTaskHandle`] of the request it is about, so that any number of requests may be
+/// awaited at once, without a thread each. The client may be shared, e.g. in an [`Arc`].
+///
+/// As with [`SdstoreClient`](super::SdstoreClient), only one client may be connected per
+/// process, and tasks' paths should be absolute.
+pub struct AsyncSdstoreClient {
+    socket: Arc<UnixDatagram>,
+    /// The server's socket, to which requests are sent.
+    server: PathBuf,
+    codec: WireFormat,
+    client_pid: u32,
+    followers: Followers,
+    receiver: JoinHandle<()>,
+    /// The client's socket file, removed when it is dropped.
+    socket_file: PathBuf,
+}
+
+impl AsyncSdstoreClient {
+    /// Connect to the server whose sockets are in `udsock_dir`, encoding messages with
+    /// `codec`, which must match the server's.
+    ///
+    /// Must be called from within a `tokio` runtime, on which the client's receiving task
+    /// is spawned.
+    pub fn connect(udsock_dir: &Path, codec: WireFormat) -> io::Result<Self> {
+        let client_pid = process::id();
+        let socket_file = udsock_dir.join(format!("sdstore_{client_pid}.sock"));
+        let socket = Arc::new(UnixDatagram::bind(&socket_file)?);
+        let server = udsock_dir.join("sdstored.sock");
+        transport::allow_server_replies(&socket_file, &server)?;
+        let followers = Followers::default();
+        let notifications = NotificationReceiver::new(codec, client_pid, Uuid::nil(), Peer::Path(server.clone()));
+        let receiver = tokio::spawn(receive(socket.clone(), server.clone(), notifications, followers.clone()));
+
+        Ok(AsyncSdstoreClient { socket, server, codec, client_pid, followers, receiver, socket_file })
+    }
+
+    /// Submit `task` to the server, in a request of its own, returning a handle to await
+    /// its progress and result with.
+    pub async fn submit(&self, mut task: ClientTask) -> Result<AsyncTaskHandle, ClientError> {
+        if task.stream {
+            return Err(ClientError::StreamingUnsupported)
+        }
+        task.client_pid = self.client_pid;
+        task.request_id = Uuid::new_v4();
+        let handle = self.follow(task.request_id);
+        self.send(&ClientRequest::ProcFile(Box::new(task))).await?;
+        Ok(handle)
+    }
+
+    /// Ask for the server's status, waiting for its reply.
+    pub async fn status(&self) -> Result<ServerStatus, ClientError> {
+        let request_id = Uuid::new_v4();
+        let mut handle = self.follow(request_id);
+        self.send(&ClientRequest::Status(self.client_pid, request_id)).await?;
+        match handle.next().await {
+            Some(MessageToClient::Status(status)) => Ok(status),
+            Some(msg) => Err(unexpected(msg)),
+            None => Err(stopped_receiving().into()),
+        }
+    }
+
+    /// Ask whether the server is ready to take requests, waiting for its reply.
+    pub async fn health(&self) -> Result<Health, ClientError> {
+        let request_id = Uuid::new_v4();
+        let mut handle = self.follow(request_id);
+        self.send(&ClientRequest::Health(self.client_pid, request_id)).await?;
+        match handle.next().await {
+            Some(MessageToClient::Health(health)) => Ok(health),
+            Some(msg) => Err(unexpected(msg)),
+            None => Err(stopped_receiving().into()),
+        }
+    }
+
+    /// Ask what the server runs with, e.g. its filters' limits, waiting for its reply.
+    pub async fn server_info(&self) -> Result<ServerInfo, ClientError> {
+        let request_id = Uuid::new_v4();
+        let mut handle = self.follow(request_id);
+        self.send(&ClientRequest::ServerInfo(self.client_pid, request_id)).await?;
+        match handle.next().await {
+            Some(MessageToClient::ServerInfo(info)) => Ok(info),
+            Some(msg) => Err(unexpected(msg)),
+            None => Err(stopped_receiving().into()),
+        }
+    }
+
+    /// Ask what the server's recent tasks added up to, as [`SdstoreClient::report`](super::SdstoreClient::report) does.
+    pub async fn report(&self, window: Option<Duration>) -> Result<Report, ClientError> {
+        let request_id = Uuid::new_v4();
+        let mut handle = self.follow(request_id);
+        self.send(&ClientRequest::Report(self.client_pid, request_id, window)).await?;
+        match handle.next().await {
+            Some(MessageToClient::Report(report)) => Ok(report),
+            Some(msg) => Err(unexpected(msg)),
+            None => Err(stopped_receiving().into()),
+        }
+    }
+
+    /// Suspend the running task `task`, as [`SdstoreClient::suspend`](super::SdstoreClient::suspend) does.
+    pub async fn suspend(&self, task: TaskId) -> Result<usize, ClientError> {
+        let request_id = Uuid::new_v4();
+        let mut handle = self.follow(request_id);
+        self.send(&ClientRequest::Suspend(self.client_pid, request_id, task)).await?;
+        match handle.next().await {
+            Some(MessageToClient::TaskSuspended { task_number }) => Ok(task_number),
+            Some(msg) => Err(unexpected(msg)),
+            None => Err(stopped_receiving().into()),
+        }
+    }
+
+    /// Resume the suspended task `task`, as [`SdstoreClient::resume`](super::SdstoreClient::resume) does.
+    pub async fn resume(&self, task: TaskId) -> Result<usize, ClientError> {
+        let request_id = Uuid::new_v4();
+        let mut handle = self.follow(request_id);
+        self.send(&ClientRequest::Resume(self.client_pid, request_id, task)).await?;
+        match handle.next().await {
+            Some(MessageToClient::TaskResumed { task_number }) => Ok(task_number),
+            Some(msg) => Err(unexpected(msg)),
+            None => Err(stopped_receiving().into()),
+        }
+    }
+
+    /// Cancel the request `request_id`, as [`SdstoreClient::cancel`](super::SdstoreClient::cancel) does.
+    pub async fn cancel(&self, request_id: Uuid) -> Result<(), ClientError> {
+        self.send(&ClientRequest::Cancel(self.client_pid, request_id)).await
+    }
+
+    /// Start handing the messages about `request_id` to a new handle, before the request
+    /// is sent, so that none are missed.
+    fn follow(&self, request_id: Uuid) -> AsyncTaskHandle {
+        let (sender, messages) = mpsc::unbounded_channel();
+        lock(&self.followers).insert(request_id, sender);
+        AsyncTaskHandle { request_id, messages }
+    }
+
+    /// Encode and send `request` to the server.
+    async fn send(&self, request: &ClientRequest) -> Result<(), ClientError> {
+        let bytes = self.codec.encode(request)?;
+        send_message(&self.socket, &bytes, &self.server).await?;
+        Ok(())
+    }
+}
+
+impl Drop for AsyncSdstoreClient {
+    fn drop(&mut self) {
+        self.receiver.abort();
+        if let Err(err) = fs::remove_file(&self.socket_file) {
+            log::warn!("could not remove client socket file {:?}: {:?}", self.socket_file, err);
+        }
+    }
+}
+
+/// Send the serialized message `bytes` over `socket` to `server`, see [`messaging::send_message`].
+async fn send_message(socket: &UnixDatagram, bytes: &[u8], server: &Path) -> io::Result<()> {
+    for datagram in messaging::datagrams(bytes)? {
+        socket.send_to(&datagram, server).await?;
+    }
+    Ok(())
+}
+
+/// Receive the server's messages over `socket`, acknowledging each of them, and hand them
+/// to the handles of `followers`, in order, until the socket fails.
+///
+/// Messages about no request in particular, with a nil ID, are handed to every handle, as
+/// there's no telling which request they are about, see [`NotificationReceiver`].
+async fn receive(
+    socket: Arc<UnixDatagram>,
+    server: PathBuf,
+    mut notifications: NotificationReceiver<MessageToClient>,
+    followers: Followers,
+) {
+    let mut messages = MessageReceiver::default();
+    let mut buf = vec![0; messaging::MAX_DATAGRAM_PAYLOAD + 1];
+    let err = loop {
+        let (n, sender) = match socket.recv_from(&mut buf).await {
+            Ok(received) => received,
+            Err(err) => break err,
+        };
+        let sender = sender.as_pathname().map_or(Peer::Unnamed, |path| Peer::Path(path.to_path_buf()));
+        let ack = match messages.push(&buf[..n], &sender) {
+            Ok(None) => continue,
+            Ok(Some(message)) => notifications.accept(&message),
+            Err(err) => Err(err),
+        };
+        let ack = match ack {
+            Ok(ack) => ack,
+            Err(err) => {
+                log::warn!("dropping unreadable message from server: {:?}", err);
+                continue
+            },
+        };
+        // The server sends the message again otherwise.
+        if let Err(err) = send_message(&socket, &ack, &server).await {
+            log::warn!("could not acknowledge notification: {:?}", err);
+        }
+
+        while let Some((request_id, msg)) = notifications.next_in_order() {
+            // The server numbers its messages about the request from the start once it restarts.
+            if matches!(msg, MessageToClient::Suspended) {
+                notifications.restart_sequence(request_id);
+            }
+            let mut followers = lock(&followers);
+            if request_id.is_nil() {
+                followers.retain(|_, follower| follower.send(msg.clone()).is_ok());
+                continue
+            }
+            let last = msg.is_last();
+            // Messages about requests whose handle was dropped are of no use to anyone.
+            let delivered = followers.get(&request_id).is_some_and(|follower| follower.send(msg).is_ok());
+            if !delivered || last {
+                followers.remove(&request_id);
+            }
+        }
+    };
+
+    log::error!("stopped receiving messages from server: {:?}", err);
+    // Handles waiting on messages are told none will come, once their senders are dropped.
+    lock(&followers).clear();
+}
+
+fn lock(followers: &Followers) -> MutexGuard<'_, HashMap<Uuid, mpsc::UnboundedSender<MessageToClient>>> {
+    followers.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+/// The error for a handle whose messages stopped before its request's last one.
+fn stopped_receiving() -> io::Error {
+    io::Error::new(io::ErrorKind::ConnectionAborted, "the client stopped receiving messages from the server")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{os::unix::net, thread};
+
+    use crate::core::{filter::Filter, messaging::{RequestFailure, Sequenced}, testing::TempDir};
+
+    use super::*;
+
+    #[test]
+    fn tasks_are_awaited_at_once() {
+        let dir = TempDir::new("async_client");
+        let server = net::UnixDatagram::bind(dir.join("sdstored.sock")).unwrap();
+        let codec = WireFormat::default();
+        let client_udsock = Peer::Path(dir.join(format!("sdstore_{}.sock", process::id())));
+
+        // Both tasks are submitted before either is replied to, and concluded in reverse.
+        let fake_server = thread::spawn(move || {
+            let mut requests = MessageReceiver::default();
+            let mut recv = || loop {
+                match codec.decode::<ClientRequest>(&requests.recv(&server).unwrap()).unwrap() {
+                    ClientRequest::Ack(..) => continue,
+                    ClientRequest::ProcFile(task) => break task.request_id,
+                    request => panic!("unexpected request {request:?}"),
+                }
+            };
+            let reply = |request_id, seq, message: MessageToClient| {
+                let bytes = codec.encode(&Sequenced { seq, request_id, message }).unwrap();
+                messaging::send_message(&server, &bytes, &client_udsock).unwrap();
+            };
+
+            let (first, second) = (recv(), recv());
+            reply(first, 0, MessageToClient::Processing);
+            reply(second, 0, MessageToClient::Failed(RequestFailure::Cancelled));
+            reply(first, 1, MessageToClient::Failed(RequestFailure::ShuttingDown));
+        });
+
+        let runtime = tokio::runtime::Builder::new_current_thread().enable_io().build().unwrap();
+        runtime.block_on(async {
+            let client = AsyncSdstoreClient::connect(&dir, codec).unwrap();
+            let task = || ClientTask::new(0, 1, PathBuf::from("/in"), PathBuf::from("/out"), vec![Filter::Nop]);
+            let mut first = client.submit(task()).await.unwrap();
+            let second = client.submit(task()).await.unwrap();
+
+            assert!(matches!(second.wait().await, Err(ClientError::Failed(RequestFailure::Cancelled))));
+            assert_eq!(first.next().await, Some(MessageToClient::Processing));
+            assert!(matches!(first.wait().await, Err(ClientError::Failed(RequestFailure::ShuttingDown))));
+        });
+        fake_server.join().unwrap();
+
+        assert!(!dir.join(format!("sdstore_{}.sock", process::id())).exists());
+    }
+}
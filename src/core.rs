@@ -1,6 +1,27 @@
+pub mod accounting;
+pub mod batch;
+pub mod bench;
+pub mod builtin;
+pub mod checkpoint;
+pub mod chunking;
+pub mod cli;
 pub mod client_task;
+pub mod drop_folder;
 pub mod filter;
+pub mod framing;
+#[cfg(feature = "fuse")]
+pub mod fuse;
+pub mod health;
 pub mod limits;
 pub mod messaging;
 pub mod monitor;
-pub mod server;
\ No newline at end of file
+pub mod paths;
+pub mod progress;
+pub mod remote;
+pub mod server;
+pub mod server_info;
+pub mod status;
+pub mod task_log;
+#[cfg(test)]
+pub mod testing;
+pub mod transport;
\ No newline at end of file
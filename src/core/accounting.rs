@@ -0,0 +1,190 @@
+//! What the server's tasks added up to, by client and by filter, sent to administrators that
+//! ask for it with `./sdstore report`, see [`Report`].
+
+use std::{collections::{HashMap, HashSet, VecDeque}, fmt::Display, time::{Duration, Instant}};
+
+use serde::{Serialize, Deserialize};
+
+use super::{client_task::ClientTask, filter::Filter, messaging::MessageToClient};
+
+/// Number of concluded tasks the server accounts for, the oldest being forgotten first.
+pub const ACCOUNTING_LEN: usize = 10_000;
+
+/// Cumulative figures of some of the server's concluded tasks, see [`Report`].
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct Totals {
+    pub tasks: usize,
+    /// How many of the tasks failed, or were rejected.
+    pub failed: usize,
+    /// Size of the inputs processed successfully, in bytes.
+    pub bytes_in: u64,
+    /// Size of the outputs, in bytes.
+    pub bytes_out: u64,
+    /// CPU time the tasks' filters spent, in user and kernel mode.
+    pub cpu_time: Duration,
+}
+
+impl Totals {
+    fn add(&mut self, record: &Record) {
+        self.tasks += 1;
+        self.failed += usize::from(record.failed);
+        self.bytes_in += record.bytes_in;
+        self.bytes_out += record.bytes_out;
+        self.cpu_time += record.cpu_time;
+    }
+}
+
+impl Display for Totals {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f, "{} task(s), {} failed, {} bytes in, {} bytes out, {:.3}s of CPU time",
+            self.tasks, self.failed, self.bytes_in, self.bytes_out, self.cpu_time.as_secs_f64()
+        )
+    }
+}
+
+/// The totals of the tasks that concluded over a window of time, overall, by client, and by
+/// filter, see [`Accounting::report`].
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct Report {
+    /// How far back the report goes, or `None` for as far as the server remembers, that is
+    /// its last [`ACCOUNTING_LEN`] tasks.
+    pub window: Option<Duration>,
+    pub total: Totals,
+    /// By the UID of the client's user, if the server could tell it, and the client's PID,
+    /// in that order.
+    pub by_client: Vec<(Option<u32>, u32, Totals)>,
+    /// By filter, in the order of their names. Each counts every task whose pipeline has it,
+    /// so that these don't add up to the total.
+    pub by_filter: Vec<(Filter, Totals)>,
+}
+
+/// Formats the report as a line for the total, and one per client and per filter, e.g.
+///
+/// ```text
+/// total over the last 3600s: 3 task(s), 1 failed, 2048 bytes in, 1024 bytes out, 0.250s of CPU time
+/// client UID 1000, PID 4242: 2 task(s), 1 failed, 1024 bytes in, 512 bytes out, 0.125s of CPU time
+/// client PID 4343: 1 task(s), 0 failed, 1024 bytes in, 512 bytes out, 0.125s of CPU time
+/// filter gcompress: 3 task(s), 1 failed, 2048 bytes in, 1024 bytes out, 0.250s of CPU time
+/// ```
+impl Display for Report {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.window {
+            None => write!(f, "total: {}", self.total)?,
+            Some(window) => write!(f, "total over the last {}s: {}", window.as_secs(), self.total)?,
+        }
+        for (uid, pid, totals) in &self.by_client {
+            match uid {
+                None => write!(f, "\nclient PID {pid}: {totals}")?,
+                Some(uid) => write!(f, "\nclient UID {uid}, PID {pid}: {totals}")?,
+            }
+        }
+        for (filter, totals) in &self.by_filter {
+            write!(f, "\nfilter {filter}: {totals}")?;
+        }
+        Ok(())
+    }
+}
+
+/// What a concluded task amounted to, see [`Accounting::record`].
+#[derive(Debug)]
+struct Record {
+    concluded_at: Instant,
+    client_uid: Option<u32>,
+    client_pid: u32,
+    filters: Vec<Filter>,
+    failed: bool,
+    bytes_in: u64,
+    bytes_out: u64,
+    cpu_time: Duration,
+}
+
+/// The server's account of its last [`ACCOUNTING_LEN`] tasks to conclude, oldest first, to
+/// report on, see [`Accounting::report`].
+#[derive(Debug, Default)]
+pub struct Accounting {
+    records: VecDeque<Record>,
+}
+
+impl Accounting {
+    /// Account for `task`, which just concluded, its client having been sent `outcome` as
+    /// the last message about it. Tasks that were rejected count as failed.
+    pub fn record(&mut self, task: &ClientTask, outcome: &MessageToClient) {
+        let (failed, bytes_in, bytes_out, usage) = match outcome {
+            MessageToClient::Concluded(summary) => (false, summary.bytes_in, summary.bytes_out, Some(summary.resource_usage)),
+            MessageToClient::BatchConcluded(summary) => (false, summary.bytes_in, summary.bytes_out, Some(summary.resource_usage)),
+            MessageToClient::Failed(_) => (true, 0, 0, None),
+            _ => (false, 0, 0, None),
+        };
+        if self.records.len() == ACCOUNTING_LEN {
+            self.records.pop_front();
+        }
+        self.records.push_back(Record {
+            concluded_at: Instant::now(),
+            client_uid: task.client_uid,
+            client_pid: task.client_pid,
+            filters: task.transformations.clone(),
+            failed,
+            bytes_in,
+            bytes_out,
+            cpu_time: usage.map(|usage| usage.user_time + usage.system_time).unwrap_or_default(),
+        });
+    }
+
+    /// Report on the tasks that concluded in the last `window`, or on every task accounted
+    /// for if `None`.
+    pub fn report(&self, window: Option<Duration>) -> Report {
+        let mut total = Totals::default();
+        let mut by_client = HashMap::<_, Totals>::new();
+        let mut by_filter = HashMap::<_, Totals>::new();
+        let recent = self
+            .records
+            .iter()
+            .rev()
+            .take_while(|record| window.is_none_or(|window| record.concluded_at.elapsed() <= window));
+        for record in recent {
+            total.add(record);
+            by_client.entry((record.client_uid, record.client_pid)).or_default().add(record);
+            for filter in record.filters.iter().collect::<HashSet<_>>() {
+                by_filter.entry(filter).or_default().add(record);
+            }
+        }
+
+        let mut by_client = by_client.into_iter().map(|((uid, pid), totals)| (uid, pid, totals)).collect::<Vec<_>>();
+        by_client.sort_by_key(|&(uid, pid, _)| (uid, pid));
+        let mut by_filter = by_filter.into_iter().map(|(filter, totals)| (filter.clone(), totals)).collect::<Vec<_>>();
+        by_filter.sort_by_key(|(filter, _)| filter.to_string());
+        Report { window, total, by_client, by_filter }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::messaging::RequestFailure;
+
+    #[test]
+    fn tasks_are_totalled_by_client_and_filter() {
+        let filter = |name: &str| Filter::try_from(name.to_string()).unwrap();
+        let task = |uid, pid, filters: &[&str]| {
+            let mut task = ClientTask::new(pid, 0, "in".into(), "out".into(), filters.iter().map(|name| filter(name)).collect());
+            task.client_uid = uid;
+            task
+        };
+        let mut accounting = Accounting::default();
+        accounting.record(&task(Some(1000), 42, &["nop", "gcompress", "nop"]), &MessageToClient::Failed(RequestFailure::Cancelled));
+        accounting.record(&task(None, 7, &["nop"]), &MessageToClient::Failed(RequestFailure::Cancelled));
+        accounting.record(&task(Some(1000), 42, &["gcompress"]), &MessageToClient::Failed(RequestFailure::Cancelled));
+
+        let report = accounting.report(None);
+        assert_eq!(report.total, Totals { tasks: 3, failed: 3, ..Totals::default() });
+        let failed = |tasks| Totals { tasks, failed: tasks, ..Totals::default() };
+        assert_eq!(report.by_client, vec![(None, 7, failed(1)), (Some(1000), 42, failed(2))]);
+        assert_eq!(report.by_filter, vec![(filter("gcompress"), failed(2)), (filter("nop"), failed(2))]);
+        assert_eq!(report.to_string().lines().nth(1), Some(
+            "client PID 7: 1 task(s), 1 failed, 0 bytes in, 0 bytes out, 0.000s of CPU time"
+        ));
+
+        assert_eq!(accounting.report(Some(Duration::ZERO)).total.tasks, 0);
+    }
+}
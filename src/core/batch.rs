@@ -0,0 +1,133 @@
+use std::{fs, io, path::{Path, PathBuf}};
+
+use super::remote;
+
+/// Characters which make the last component of a task's input a pattern, see [`is_batch`].
+const WILDCARDS: [char; 2] = ['*', '?'];
+
+/// Whether a task with this input is a batch, which runs its pipeline on several files:
+/// either every file in a directory, or every file matching a pattern, where `*` matches
+/// any sequence of characters, and `?` any single character.
+///
+/// Only the input's last component may be a pattern, e.g. `inputs/*.log`. Remote inputs,
+/// see [`remote`](super::remote), are never batches.
+pub fn is_batch(input: &Path) -> bool {
+    !remote::is_remote(input) && (input.is_dir() || input
+        .file_name()
+        .and_then(|name| name.to_str())
+        .is_some_and(|name| name.contains(WILDCARDS)))
+}
+
+/// The files of a batch whose input is `input`, see [`is_batch`], each alongside where
+/// its output goes: a file of the same name in `output_dir`.
+///
+/// Only regular files are included, in the order of their names. Hidden files are only
+/// matched by patterns beginning with `.`.
+pub fn expand(input: &Path, output_dir: &Path) -> io::Result<Vec<(PathBuf, PathBuf)>> {
+    let (dir, pattern) = match input.is_dir() {
+        true => (input, "*"),
+        false => (
+            input.parent().filter(|dir| !dir.as_os_str().is_empty()).unwrap_or(Path::new(".")),
+            input.file_name().and_then(|name| name.to_str()).unwrap_or_default(),
+        ),
+    };
+
+    let mut files = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let name = entry.file_name();
+        let matches = name
+            .to_str()
+            .is_some_and(|name| (pattern.starts_with('.') || !name.starts_with('.')) && matches(pattern, name));
+        if matches && entry.file_type()?.is_file() {
+            files.push((entry.path(), output_dir.join(&name)));
+        }
+    }
+
+    files.sort();
+    Ok(files)
+}
+
+/// Total size, in bytes, of the files of a batch, or of the single input of any other
+/// task. Files which can't be `stat`ed count as empty.
+pub fn input_size(input: &Path) -> u64 {
+    let size = |path: &Path| fs::metadata(path).map(|meta| meta.len()).unwrap_or(0);
+    match is_batch(input) {
+        false => size(input),
+        true => expand(input, Path::new(""))
+            .map(|files| files.iter().map(|(input, _)| size(input)).sum())
+            .unwrap_or(0),
+    }
+}
+
+/// Whether `name` matches the wildcard `pattern`.
+fn matches(pattern: &str, name: &str) -> bool {
+    let (pattern, name) = (pattern.chars().collect::<Vec<_>>(), name.chars().collect::<Vec<_>>());
+    // Where to resume on a mismatch: right after the last `*`, which then matches one more character.
+    let mut backtrack = None;
+    let (mut p, mut n) = (0, 0);
+
+    while n < name.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                backtrack = Some((p + 1, n));
+                p += 1;
+            },
+            Some(c) if *c == '?' || *c == name[n] => {
+                p += 1;
+                n += 1;
+            },
+            _ => match backtrack {
+                None => return false,
+                Some((star_p, star_n)) => {
+                    backtrack = Some((star_p, star_n + 1));
+                    (p, n) = (star_p, star_n + 1);
+                },
+            },
+        }
+    }
+
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::core::testing::TempDir;
+
+    use super::*;
+
+    #[test]
+    fn wildcard_matching() {
+        assert!(matches("*.log", "server.log"));
+        assert!(matches("*.log", ".log"));
+        assert!(matches("a?c*", "abcdef"));
+        assert!(matches("*a*b", "xaxxab"));
+        assert!(!matches("*.log", "server.log.gz"));
+        assert!(!matches("a?c", "ac"));
+    }
+
+    #[test]
+    fn batch_expansion() {
+        let dir = TempDir::new("batch");
+        fs::create_dir_all(dir.join("sub.log")).unwrap();
+        for name in ["b.log", "a.log", "c.txt", ".hidden.log"] {
+            fs::write(dir.join(name), name).unwrap();
+        }
+
+        let out = Path::new("out");
+        let names = |files: Vec<(PathBuf, PathBuf)>| files
+            .into_iter()
+            .map(|(input, output)| {
+                assert_eq!(output, out.join(input.file_name().unwrap()));
+                input.file_name().unwrap().to_str().unwrap().to_string()
+            })
+            .collect::<Vec<_>>();
+
+        assert!(is_batch(&dir.join("*.log")));
+        assert!(is_batch(&dir));
+        assert!(!is_batch(&dir.join("a.log")));
+        assert_eq!(names(expand(&dir.join("*.log"), out).unwrap()), ["a.log", "b.log"]);
+        assert_eq!(names(expand(&dir, out).unwrap()), ["a.log", "b.log", "c.txt"]);
+        assert_eq!(input_size(&dir.join("*.log")), 10);
+    }
+}
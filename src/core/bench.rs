@@ -0,0 +1,380 @@
+//! The `sdstore-bench` load generator's command line, and what it submits and reports: the
+//! synthetic inputs, the mix of pipelines requests run, and the percentiles of their
+//! latencies, see [`BenchCli`].
+
+use std::{fmt::Display, fs, io::{self, BufWriter, Write}, path::{Path, PathBuf}, time::Duration};
+
+use clap::Parser;
+use serde::{Serialize, Serializer};
+
+use super::filter::Filter;
+
+/// Words the synthetic inputs are made of, so that they compress about as well as text.
+const WORDS: [&str; 16] = [
+    "the", "server", "applies", "filters", "to", "files", "on", "behalf", "of", "its", "clients",
+    "which", "submit", "requests", "and", "wait",
+];
+
+/// Submit requests to the `sdstored` server at a steady rate, each running one of a mix of
+/// pipelines on a synthetic input, then report its throughput, and the percentiles of the
+/// requests' queue wait and end-to-end latency.
+///
+/// Runs with the same options and seed submit the same requests, at the same times, so that
+/// changes to the server's scheduling or limits can be compared.
+#[derive(Debug, Parser)]
+#[command(name = "sdstore-bench", version)]
+pub struct BenchCli {
+    /// Pipeline requests may run, as its filters separated by commas, optionally followed by
+    /// `:<weight>`, its share of the requests, as in `gcompress,encrypt:3`. May be repeated.
+    /// Defaults to `nop`.
+    #[arg(long = "pipeline", value_name = "FILTERS[:WEIGHT]", value_parser = parse_pipeline)]
+    pub pipelines: Vec<WeightedPipeline>,
+    /// Requests submitted per second.
+    #[arg(long, default_value_t = 10.0, value_parser = parse_rate)]
+    pub rate: f64,
+    /// Requests submitted in all.
+    #[arg(long, default_value_t = 100)]
+    pub requests: usize,
+    /// Space requests as a Poisson process would, at random, rather than evenly.
+    #[arg(long)]
+    pub poisson: bool,
+    /// Bytes of each synthetic input.
+    #[arg(long, value_name = "BYTES", default_value_t = 1 << 20)]
+    pub input_size: u64,
+    /// Synthetic inputs, which requests take turns reading.
+    #[arg(long, default_value_t = 8, value_parser = clap::value_parser!(u64).range(1..))]
+    pub inputs: u64,
+    /// Priority requests are submitted with.
+    #[arg(long, default_value_t = 0)]
+    pub priority: usize,
+    /// Seed of the inputs' contents, of when requests are submitted, with `--poisson`, and of
+    /// which pipeline each runs.
+    #[arg(long, default_value_t = 0)]
+    pub seed: u64,
+    /// Directory to write the inputs, and outputs, to, which the server must be able to read
+    /// and write. Defaults to one in the temporary directory, removed afterwards.
+    #[arg(long, value_name = "DIR")]
+    pub dir: Option<PathBuf>,
+    /// Seconds to wait for the requests still running once every one was submitted, before
+    /// reporting them as unfinished.
+    #[arg(long, value_name = "SECONDS", default_value_t = 60)]
+    pub drain_timeout: u64,
+    /// Output the report as JSON, rather than text.
+    #[arg(long)]
+    pub json: bool,
+    /// Directory of the server's sockets, which must match the server's. Defaults to
+    /// `$SDSTORE_SOCK_DIR`, if set, or else to `sdstore` in `$XDG_RUNTIME_DIR`, or else to
+    /// `/run/sdstore`.
+    #[arg(long, value_name = "DIR")]
+    pub socket_dir: Option<PathBuf>,
+}
+
+/// A pipeline of the mix requests are drawn from, see [`BenchCli::pipelines`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WeightedPipeline {
+    pub filters: Vec<Filter>,
+    /// Share of the requests running the pipeline, relative to the others' weights.
+    pub weight: u32,
+}
+
+/// Formats the pipeline as its filters separated by commas, as given.
+impl Display for WeightedPipeline {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let filters = self.filters.iter().map(Filter::to_string).collect::<Vec<_>>();
+        write!(f, "{}", filters.join(","))
+    }
+}
+
+fn parse_pipeline(s: &str) -> Result<WeightedPipeline, String> {
+    let (filters, weight) = match s.rsplit_once(':') {
+        None => (s, 1),
+        Some((filters, weight)) => match weight.parse() {
+            Ok(weight) if weight > 0 => (filters, weight),
+            _ => return Err(format!("invalid weight {weight:?}, which must be a positive integer")),
+        },
+    };
+    let filters = filters
+        .split(',')
+        .map(|filter| filter.parse().map_err(|_| format!("invalid filter {filter:?}")))
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(WeightedPipeline { filters, weight })
+}
+
+fn parse_rate(s: &str) -> Result<f64, String> {
+    match s.parse() {
+        Ok(rate) if rate > 0.0 && f64::is_finite(rate) => Ok(rate),
+        _ => Err(format!("invalid rate {s:?}, which must be a positive number")),
+    }
+}
+
+/// Generator of the pseudorandom numbers a run is drawn from, SplitMix64, which is enough
+/// to pick pipelines and arrivals, and the same given the same seed.
+#[derive(Debug, Clone)]
+pub struct Rng(u64);
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        Rng(seed)
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// A number uniformly distributed in `[0, 1)`.
+    pub fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    /// The index of one of the `pipelines`, each drawn as often as its weight says.
+    pub fn pick(&mut self, pipelines: &[WeightedPipeline]) -> usize {
+        let total = pipelines.iter().map(|pipeline| pipeline.weight as u64).sum::<u64>();
+        let mut drawn = self.next_u64() % total.max(1);
+        pipelines
+            .iter()
+            .position(|pipeline| match drawn.checked_sub(pipeline.weight as u64) {
+                None => true,
+                Some(rest) => {
+                    drawn = rest;
+                    false
+                },
+            })
+            .unwrap_or(0)
+    }
+
+    /// Time until the next request, at `rate` requests per second: exponentially distributed,
+    /// as between the events of a Poisson process, if `poisson`, or else always the same.
+    pub fn interval(&mut self, rate: f64, poisson: bool) -> Duration {
+        match poisson {
+            false => Duration::from_secs_f64(1.0 / rate),
+            true => Duration::from_secs_f64(-(1.0 - self.next_f64()).ln() / rate),
+        }
+    }
+}
+
+/// Write a synthetic input of `size` bytes to `path`: lines of words drawn by `rng`.
+pub fn write_input(path: &Path, size: u64, rng: &mut Rng) -> io::Result<()> {
+    let mut file = BufWriter::new(fs::File::create(path)?);
+    let mut written = 0;
+    while written < size {
+        let mut line = (0..12).map(|_| WORDS[rng.next_u64() as usize % WORDS.len()]).collect::<Vec<_>>().join(" ");
+        line.push('\n');
+        let len = line.len().min((size - written) as usize);
+        file.write_all(&line.as_bytes()[..len])?;
+        written += len as u64;
+    }
+    file.flush()
+}
+
+/// Percentiles of a sample of durations, by the nearest-rank method.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct Percentiles {
+    #[serde(serialize_with = "seconds")]
+    pub p50: Duration,
+    #[serde(serialize_with = "seconds")]
+    pub p90: Duration,
+    #[serde(serialize_with = "seconds")]
+    pub p99: Duration,
+    #[serde(serialize_with = "seconds")]
+    pub max: Duration,
+}
+
+impl Percentiles {
+    /// Percentiles of `sample`, which is sorted, or `None` if it's empty.
+    pub fn of(sample: &mut [Duration]) -> Option<Self> {
+        sample.sort_unstable();
+        let rank = |percentile: usize| sample[(sample.len() * percentile).div_ceil(100).max(1) - 1];
+        (!sample.is_empty()).then(|| Percentiles { p50: rank(50), p90: rank(90), p99: rank(99), max: rank(100) })
+    }
+}
+
+/// Formats the percentiles in milliseconds, as in `p50 1.2ms, p90 3.4ms, p99 5.6ms, max 7.8ms`.
+impl Display for Percentiles {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let ms = |duration: Duration| duration.as_secs_f64() * 1000.0;
+        write!(
+            f, "p50 {:.1}ms, p90 {:.1}ms, p99 {:.1}ms, max {:.1}ms",
+            ms(self.p50), ms(self.p90), ms(self.p99), ms(self.max)
+        )
+    }
+}
+
+fn seconds<S: Serializer>(duration: &Duration, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_f64(duration.as_secs_f64())
+}
+
+/// What became of the requests running one of the pipelines, see [`Report`].
+#[derive(Debug, Clone, Default)]
+pub struct Outcomes {
+    /// How long each request that concluded waited in its queue, as the server reports.
+    pub queue_waits: Vec<Duration>,
+    /// How long each request that concluded took, from being submitted to concluding.
+    pub latencies: Vec<Duration>,
+    /// Bytes of input of the requests that concluded.
+    pub bytes_in: u64,
+    /// Requests that failed, or were refused.
+    pub failed: usize,
+}
+
+/// Statistics of the requests running a pipeline, or of every request, see [`Report`].
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Stats {
+    pub concluded: usize,
+    pub failed: usize,
+    pub queue_wait: Option<Percentiles>,
+    pub latency: Option<Percentiles>,
+}
+
+impl Outcomes {
+    fn stats(&mut self) -> Stats {
+        Stats {
+            concluded: self.latencies.len(),
+            failed: self.failed,
+            queue_wait: Percentiles::of(&mut self.queue_waits),
+            latency: Percentiles::of(&mut self.latencies),
+        }
+    }
+}
+
+/// Report of a run, output once every request concluded, or the rest timed out.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Report {
+    pub submitted: usize,
+    /// Requests that hadn't concluded when the run ended.
+    pub unfinished: usize,
+    /// Seconds from the first request being submitted to the last concluding.
+    #[serde(serialize_with = "seconds")]
+    pub elapsed: Duration,
+    /// Requests concluded per second.
+    pub throughput: f64,
+    /// Bytes of input of the requests concluded per second.
+    pub bytes_per_sec: f64,
+    #[serde(flatten)]
+    pub total: Stats,
+    /// Statistics of the requests of each pipeline, by pipeline, see [`WeightedPipeline`].
+    pub pipelines: Vec<(String, Stats)>,
+}
+
+impl Report {
+    /// Report of a run of `elapsed`, of `submitted` requests, with the `outcomes` of those
+    /// running each of the `pipelines`.
+    pub fn new(submitted: usize, elapsed: Duration, pipelines: &[WeightedPipeline], outcomes: &[Outcomes]) -> Self {
+        let mut total = outcomes.iter().fold(Outcomes::default(), |mut total, outcomes| {
+            total.queue_waits.extend(&outcomes.queue_waits);
+            total.latencies.extend(&outcomes.latencies);
+            total.bytes_in += outcomes.bytes_in;
+            total.failed += outcomes.failed;
+            total
+        });
+        let secs = elapsed.as_secs_f64().max(f64::EPSILON);
+        Report {
+            submitted,
+            unfinished: submitted - total.latencies.len() - total.failed,
+            elapsed,
+            throughput: total.latencies.len() as f64 / secs,
+            bytes_per_sec: total.bytes_in as f64 / secs,
+            total: total.stats(),
+            pipelines: pipelines
+                .iter()
+                .zip(outcomes)
+                .map(|(pipeline, outcomes)| (pipeline.to_string(), outcomes.clone().stats()))
+                .collect(),
+        }
+    }
+}
+
+impl Display for Report {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let stats = |f: &mut std::fmt::Formatter<'_>, stats: &Stats| {
+            if let Some(queue_wait) = &stats.queue_wait {
+                writeln!(f, "  queue wait: {queue_wait}")?;
+            }
+            match &stats.latency {
+                Some(latency) => writeln!(f, "  latency:    {latency}"),
+                None => Ok(()),
+            }
+        };
+        writeln!(
+            f, "{} requests in {:.3}s: {} concluded, {} failed, {} unfinished",
+            self.submitted, self.elapsed.as_secs_f64(), self.total.concluded, self.total.failed, self.unfinished
+        )?;
+        writeln!(
+            f, "  throughput: {:.2} requests/s, {:.2} MiB/s of input",
+            self.throughput, self.bytes_per_sec / (1 << 20) as f64
+        )?;
+        stats(f, &self.total)?;
+        // Only worth breaking down if there's more than one.
+        for (pipeline, pipeline_stats) in self.pipelines.iter().filter(|_| self.pipelines.len() > 1) {
+            writeln!(f, "{pipeline}: {} concluded, {} failed", pipeline_stats.concluded, pipeline_stats.failed)?;
+            stats(f, pipeline_stats)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bench_cli_parsing_works() {
+        let cli = BenchCli::try_parse_from(
+            "sdstore-bench --pipeline gcompress,encrypt:3 --pipeline nop --rate 2.5 --poisson".split_ascii_whitespace()
+        ).unwrap();
+        assert_eq!(cli.pipelines, vec![
+            WeightedPipeline { filters: vec![Filter::Gcompress, Filter::Encrypt], weight: 3 },
+            WeightedPipeline { filters: vec![Filter::Nop], weight: 1 },
+        ]);
+        assert_eq!(cli.pipelines[0].to_string(), "gcompress,encrypt");
+        assert!(cli.poisson && cli.rate == 2.5);
+
+        for args in ["--pipeline nop:0", "--pipeline nop,../gzip", "--rate 0", "--inputs 0"] {
+            let args = format!("sdstore-bench {args}");
+            assert!(BenchCli::try_parse_from(args.split_ascii_whitespace()).is_err(), "{args} should not parse");
+        }
+    }
+
+    #[test]
+    fn runs_are_reproducible() {
+        let pipelines = [
+            WeightedPipeline { filters: vec![Filter::Nop], weight: 1 },
+            WeightedPipeline { filters: vec![Filter::Gcompress], weight: 3 },
+        ];
+        let draw = |seed| {
+            let mut rng = Rng::new(seed);
+            (0..1000).map(|_| (rng.pick(&pipelines), rng.interval(10.0, true))).collect::<Vec<_>>()
+        };
+        let drawn = draw(7);
+        assert_eq!(drawn, draw(7));
+        assert_ne!(drawn, draw(8));
+        let gcompress = drawn.iter().filter(|(pipeline, _)| *pipeline == 1).count();
+        assert!((700..800).contains(&gcompress), "{gcompress} of 1000 requests ran gcompress");
+        let mean = drawn.iter().map(|(_, interval)| interval.as_secs_f64()).sum::<f64>() / 1000.0;
+        assert!((0.09..0.11).contains(&mean), "mean interval of {mean}s");
+
+        let path = std::env::temp_dir().join(format!("sdstore_bench_test_{}", std::process::id()));
+        write_input(&path, 1000, &mut Rng::new(1)).unwrap();
+        let input = fs::read(&path).unwrap();
+        write_input(&path, 1000, &mut Rng::new(1)).unwrap();
+        assert_eq!((input.len(), fs::read(&path).unwrap()), (1000, input));
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn percentiles_are_nearest_ranks() {
+        let mut sample = (1..=200).rev().map(Duration::from_millis).collect::<Vec<_>>();
+        let percentiles = Percentiles::of(&mut sample).unwrap();
+        assert_eq!(percentiles, Percentiles {
+            p50: Duration::from_millis(100),
+            p90: Duration::from_millis(180),
+            p99: Duration::from_millis(198),
+            max: Duration::from_millis(200),
+        });
+        assert_eq!(Percentiles::of(&mut [Duration::from_secs(1)]).unwrap().p50, Duration::from_secs(1));
+        assert_eq!(Percentiles::of(&mut []), None);
+    }
+}
@@ -0,0 +1,109 @@
+use std::io::{self, Read, Write};
+
+use bzip2::read::{BzEncoder, MultiBzDecoder};
+use flate2::read::{GzEncoder, MultiGzDecoder};
+
+use super::filter::Filter;
+
+/// Key used by the builtin `encrypt`/`decrypt` filters, the same one the `bin/`
+/// executables hand to `ccrypt`.
+///
+/// Note that the builtin filters XOR data with this key, so their output cannot be
+/// decrypted by the `bin/decrypt` executable, nor vice-versa.
+const XOR_KEY: &[u8] = b"123456";
+
+/// Reader that XORs the bytes of the underlying reader with a repeating key.
+///
+/// XOR being its own inverse, this serves both the builtin `encrypt` and `decrypt`.
+struct XorReader<R> {
+    inner: R,
+    key: &'static [u8],
+    pos: usize,
+}
+
+impl<R: Read> Read for XorReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        for byte in &mut buf[..n] {
+            *byte ^= self.key[self.pos];
+            self.pos = (self.pos + 1) % self.key.len();
+        }
+        Ok(n)
+    }
+}
+
+/// Whether `filter` has a builtin implementation. The `zstd` and `xz` filters don't, nor
+/// do custom ones, and are only ever run by executing their binary.
+pub fn has_builtin(filter: &Filter) -> bool {
+    !matches!(
+        filter,
+        Filter::Zcompress | Filter::Zdecompress | Filter::Xcompress | Filter::Xdecompress | Filter::Custom(_)
+    )
+}
+
+/// Wrap `input` in a reader that applies `filter` to it, which must have a builtin
+/// implementation, see [`has_builtin`].
+///
+/// The compression filters produce data in the same formats as `gzip` and `bzip2`,
+/// so the builtin and the `bin/` implementations of those filters can be mixed freely.
+pub fn filter_reader<'a>(filter: &Filter, input: Box<dyn Read + Send + 'a>) -> Box<dyn Read + Send + 'a> {
+    match filter {
+        Filter::Nop         => input,
+        Filter::Bcompress   => Box::new(BzEncoder::new(input, bzip2::Compression::default())),
+        Filter::Bdecompress => Box::new(MultiBzDecoder::new(input)),
+        Filter::Gcompress   => Box::new(GzEncoder::new(input, flate2::Compression::default())),
+        Filter::Gdecompress => Box::new(MultiGzDecoder::new(input)),
+        Filter::Encrypt | Filter::Decrypt =>
+            Box::new(XorReader { inner: input, key: XOR_KEY, pos: 0 }),
+        Filter::Zcompress | Filter::Zdecompress | Filter::Xcompress | Filter::Xdecompress | Filter::Custom(_) =>
+            unreachable!("{filter} has no builtin implementation"),
+    }
+}
+
+/// Apply `filter` to everything read from `input`, writing the result to `output`.
+///
+/// Returns the number of bytes written.
+pub fn run_filter(
+    filter: &Filter,
+    input: impl Read + Send,
+    mut output: impl Write
+) -> io::Result<u64> {
+    let mut reader = filter_reader(filter, Box::new(input));
+    let n = io::copy(&mut reader, &mut output)?;
+    output.flush()?;
+    Ok(n)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn apply(filters: &[Filter], input: &[u8]) -> Vec<u8> {
+        let mut data = input.to_vec();
+        for filter in filters {
+            let mut output = Vec::new();
+            run_filter(filter, data.as_slice(), &mut output).unwrap();
+            data = output;
+        }
+        data
+    }
+
+    #[test]
+    fn builtin_round_trips() {
+        let input = b"hello, friend\n".repeat(100);
+        let pairs = [
+            (Filter::Bcompress, Filter::Bdecompress),
+            (Filter::Gcompress, Filter::Gdecompress),
+            (Filter::Encrypt, Filter::Decrypt),
+            (Filter::Nop, Filter::Nop),
+        ];
+
+        for (forward, backward) in pairs {
+            let transformed = apply(std::slice::from_ref(&forward), &input);
+            if forward != Filter::Nop {
+                assert_ne!(transformed, input, "{forward} should change its input");
+            }
+            assert_eq!(apply(&[backward], &transformed), input);
+        }
+    }
+}
@@ -0,0 +1,145 @@
+use std::{
+    fs, io::{self, Write}, path::{Path, PathBuf}, time::{SystemTime, UNIX_EPOCH},
+};
+
+use serde::{Serialize, Deserialize};
+
+use super::client_task::ClientTask;
+
+/// Name of the directory, next to the server's socket, where the checkpoints of running
+/// tasks are kept.
+pub const CHECKPOINT_DIR: &str = "sdstored_checkpoints";
+
+/// Input bytes a checkpointed pipeline processes between two checkpoints.
+pub const CHECKPOINT_INTERVAL: u64 = 16 << 20;
+
+/// Extension of checkpoint files, see [`new_path`].
+const CHECKPOINT_EXT: &str = "ckpt";
+
+/// How far a task whose filters are all restartable got: its pipeline is run on its input
+/// in segments of [`CHECKPOINT_INTERVAL`] bytes, each appending to its temporary output,
+/// and a checkpoint is saved after each of them.
+///
+/// Should the server be interrupted, the task is resumed once it restarts, by running the
+/// pipeline on the rest of its input, from `input_offset`, appending to the first
+/// `output_len` bytes of its temporary output.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct Checkpoint {
+    pub task: ClientTask,
+    /// Temporary output the pipeline appends to.
+    pub tmp_output: PathBuf,
+    /// Size and modification time of the input when the task started: a task whose input
+    /// changed since can't be resumed.
+    pub input_len: u64,
+    pub input_modified: SystemTime,
+    /// Input bytes processed so far.
+    pub input_offset: u64,
+    /// Output bytes written for them.
+    pub output_len: u64,
+}
+
+impl Checkpoint {
+    /// Checkpoint of a task that is yet to process any of its input, given its metadata.
+    pub fn new(task: ClientTask, tmp_output: PathBuf, input: &fs::Metadata) -> io::Result<Self> {
+        Ok(Checkpoint {
+            task,
+            tmp_output,
+            input_len: input.len(),
+            input_modified: input.modified()?,
+            input_offset: 0,
+            output_len: 0,
+        })
+    }
+
+    /// Whether the task's input, given its metadata, is still the one it started with.
+    pub fn matches_input(&self, input: &fs::Metadata) -> bool {
+        input.len() == self.input_len && input.modified().is_ok_and(|modified| modified == self.input_modified)
+    }
+
+    /// Read the checkpoint saved at `path`.
+    pub fn load(path: &Path) -> io::Result<Self> {
+        bincode::deserialize(&fs::read(path)?)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+    }
+
+    /// Save the checkpoint to `path`, atomically: a checkpoint is either replaced as a
+    /// whole, or not at all.
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let bytes = bincode::serialize(self)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+
+        let tmp = path.with_extension("tmp");
+        let mut file = fs::File::create(&tmp)?;
+        file.write_all(&bytes)?;
+        file.sync_all()?;
+        fs::rename(&tmp, path)
+    }
+}
+
+/// Path of a new checkpoint, in `checkpoint_dir`, for task #`task_number`.
+///
+/// Task numbers start over when the server restarts, so the current time is part of it.
+pub fn new_path(checkpoint_dir: &Path, task_number: usize) -> PathBuf {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+    checkpoint_dir.join(format!("task_{}_{task_number}.{CHECKPOINT_EXT}", now.as_nanos()))
+}
+
+/// Every checkpoint in `checkpoint_dir`, alongside its path, or why it couldn't be read.
+pub fn load_all(checkpoint_dir: &Path) -> io::Result<Vec<(PathBuf, io::Result<Checkpoint>)>> {
+    let mut paths = Vec::new();
+    for entry in fs::read_dir(checkpoint_dir)? {
+        let path = entry?.path();
+        if path.extension().is_some_and(|ext| ext == CHECKPOINT_EXT) {
+            paths.push(path);
+        }
+    }
+
+    // The task numbers, and so the order tasks were started in, are part of the names.
+    paths.sort();
+    Ok(paths
+        .into_iter()
+        .map(|path| {
+            let checkpoint = Checkpoint::load(&path);
+            (path, checkpoint)
+        })
+        .collect())
+}
+
+/// Remove the checkpoint at `path`, once its task finished or can't be resumed.
+pub fn remove(path: &Path) {
+    match fs::remove_file(path) {
+        Err(err) if err.kind() != io::ErrorKind::NotFound =>
+            log::warn!("could not remove checkpoint {:?}: {:?}", path, err),
+        _ => {},
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::core::{filter::Filter, testing::TempDir};
+
+    use super::*;
+
+    #[test]
+    fn checkpoints_round_trip() {
+        let dir = TempDir::new("checkpoint");
+        let input = dir.join("input");
+        fs::write(&input, "input").unwrap();
+
+        let task = ClientTask::new(1, 0, input.clone(), dir.join("output"), vec![Filter::Gcompress]);
+        let mut checkpoint = Checkpoint::new(task, dir.join("tmp"), &fs::metadata(&input).unwrap()).unwrap();
+        checkpoint.input_offset = 3;
+        checkpoint.output_len = 7;
+
+        let path = new_path(&dir, 4);
+        checkpoint.save(&path).unwrap();
+        let loaded = load_all(&dir).unwrap();
+        assert_eq!(loaded.len(), 1);
+        assert_eq!(loaded[0].0, path);
+        assert_eq!(loaded[0].1.as_ref().unwrap(), &checkpoint);
+        assert!(checkpoint.matches_input(&fs::metadata(&input).unwrap()));
+
+        fs::write(&input, "changed input").unwrap();
+        assert!(!checkpoint.matches_input(&fs::metadata(&input).unwrap()));
+    }
+}
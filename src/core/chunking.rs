@@ -0,0 +1,58 @@
+use super::filter::Filter;
+
+/// Smallest chunk an input is split into, see [`chunk_ranges`]: below this, starting
+/// another pipeline costs more than it saves.
+pub const MIN_CHUNK_LEN: u64 = 1 << 20;
+
+/// Whether a pipeline of `filters` may run on an input split into chunks, with its
+/// output being the concatenation of its outputs for each chunk.
+///
+/// This holds for `nop`, and for the compression filters, since `gzip`, `bzip2`, `zstd` and
+/// `xz` all decompress concatenated streams into the concatenation of their contents.
+/// Decompressing needs the whole of a stream, and encrypting depends on the position in the
+/// input.
+pub fn is_splittable(filters: &[Filter]) -> bool {
+    filters.iter().all(|filter| matches!(
+        filter,
+        Filter::Nop | Filter::Bcompress | Filter::Gcompress | Filter::Zcompress | Filter::Xcompress
+    ))
+}
+
+/// Offsets and lengths of the contiguous chunks an input of `len` bytes is split into:
+/// at most `chunks` of them, of nearly equal lengths, no shorter than [`MIN_CHUNK_LEN`],
+/// unless the input as a whole is.
+pub fn chunk_ranges(len: u64, chunks: usize) -> Vec<(u64, u64)> {
+    let chunks = (chunks as u64).min(len / MIN_CHUNK_LEN).max(1);
+    let (chunk_len, longer) = (len / chunks, len % chunks);
+
+    let mut offset = 0;
+    (0..chunks)
+        .map(|chunk| {
+            let range = (offset, chunk_len + u64::from(chunk < longer));
+            offset += range.1;
+            range
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn chunk_ranges_cover_input() {
+        let len = 4 * MIN_CHUNK_LEN + 3;
+        let ranges = chunk_ranges(len, 3);
+        assert_eq!(ranges.len(), 3);
+        assert_eq!(ranges[0], (0, len / 3 + 1));
+        for window in ranges.windows(2) {
+            assert_eq!(window[0].0 + window[0].1, window[1].0);
+        }
+        assert_eq!(ranges.iter().map(|(_, len)| len).sum::<u64>(), len);
+
+        // Chunks are never made too short to be worth it.
+        assert_eq!(chunk_ranges(len, 16).len(), 4);
+        assert_eq!(chunk_ranges(10, 4), [(0, 10)]);
+        assert_eq!(chunk_ranges(0, 4), [(0, 0)]);
+    }
+}
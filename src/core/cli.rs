@@ -0,0 +1,665 @@
+//! The `sdstore` client's command line, from which its requests to the server are built, see
+//! [`ClientCli::requests`].
+
+use std::{collections::HashMap, ffi::OsString, io::{self, Write}, path::PathBuf, str::FromStr, time::Duration};
+
+use clap::{builder::PossibleValuesParser, error::ErrorKind, Arg, Args, CommandFactory, Parser, Subcommand, ValueEnum};
+use clap_complete::Shell;
+use uuid::Uuid;
+
+use super::{
+    client_task::{parse_mode, ClientTask},
+    filter::{Filter, FilterParseError},
+    messaging::{ClientRequest, MessageToClient, TaskId},
+    transport::Backoff,
+};
+
+/// Submit files to be transformed by the `sdstored` server, and follow its requests.
+#[derive(Debug, Parser)]
+#[command(name = "sdstore", version, arg_required_else_help = true)]
+pub struct ClientCli {
+    /// Seconds to wait for each of the server's replies, or 0 to wait forever. By default,
+    /// only the first reply is waited on, for 10 seconds.
+    #[arg(long, global = true, value_name = "SECONDS")]
+    pub timeout: Option<u64>,
+    /// How to output the server's replies.
+    // Named apart from `proc-file`'s output file, as global options share their subcommands' names.
+    #[arg(id = "output_format", long = "output", value_name = "FORMAT", global = true, value_enum, default_value_t)]
+    pub output: OutputFormat,
+    /// Directory of the server's sockets, which must match the server's. Defaults to
+    /// `$SDSTORE_SOCK_DIR`, if set, or else to `sdstore` in `$XDG_RUNTIME_DIR`, or else to
+    /// `/run/sdstore`.
+    #[arg(long, value_name = "DIR", global = true)]
+    pub socket_dir: Option<PathBuf>,
+    /// Times to retry reaching a server whose socket isn't available, e.g. as it restarts.
+    #[arg(long, value_name = "N", global = true, default_value_t = 4)]
+    pub retries: u32,
+    /// Milliseconds to wait before the first retry, each later one waiting twice as long as
+    /// the last.
+    #[arg(long, value_name = "MILLISECONDS", global = true, default_value_t = 250)]
+    pub retry_delay: u64,
+    #[command(subcommand)]
+    pub command: ClientCommand,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum ClientCommand {
+    /// Apply a sequence of filters to a file, or to every file of a batch, or to several files
+    /// at once, each in a request of its own.
+    #[command(override_usage = "sdstore proc-file [OPTIONS] <INPUT> <OUTPUT> <FILTER>...\n       \
+        sdstore proc-file [OPTIONS] --out-dir <DIR> <INPUT>... -- <FILTER>...\n       \
+        sdstore proc-file [OPTIONS] --store <INPUT> <FILTER>...")]
+    ProcFile(ProcFileArgs),
+    /// Watch a directory, applying a sequence of filters to every file written into it, each in
+    /// a request of its own, into a file of the same name in another, until interrupted.
+    WatchDir(WatchDirArgs),
+    /// Show the server's running and pending tasks, and its filters' usage.
+    Status,
+    /// Show the server's status as `status` does, redrawn as it changes, until interrupted.
+    Watch {
+        /// Seconds between each update.
+        #[arg(long, value_name = "SECONDS", default_value_t = 1, value_parser = clap::value_parser!(u64).range(1..))]
+        interval: u64,
+    },
+    /// Output every task's lifecycle, as it is queued, starts, finishes or fails, until
+    /// interrupted.
+    Subscribe,
+    /// Check that the server is alive, and which version it runs.
+    Ping,
+    /// Check that the server is alive, and ready to take requests, exiting with an error if
+    /// it isn't, as container probes and watchdog scripts expect.
+    Health,
+    /// Show what the server runs with: its version, the filters it may run, how many of each
+    /// at once, how it queues requests, and its optional features.
+    ServerInfo,
+    /// Cancel a pending or running request, whose client is told it failed.
+    Cancel {
+        /// ID of the request, as logged by the client that submitted it.
+        request_id: Uuid,
+    },
+    /// Show the tasks that most recently finished or failed.
+    History,
+    /// Show where a task is: pending, running, or how it recently finished or failed.
+    Query {
+        /// ID of the task's request, as logged by the client that submitted it, or the number
+        /// it started running as, e.g. `3` or `#3`, as the status and history show.
+        #[arg(value_name = "TASK_ID")]
+        task: TaskId,
+    },
+    /// Wait for a request, e.g. one submitted with `proc-file --no-wait`, to finish or fail,
+    /// and show how.
+    Wait {
+        /// ID of the request, as logged by the client that submitted it.
+        request_id: Uuid,
+    },
+    /// Show what the server did with a request: how it was scheduled, the commands its
+    /// filters ran, what they wrote to stderr, and how long each took.
+    Logs {
+        /// ID of the request, as logged by the client that submitted it.
+        request_id: Uuid,
+    },
+    /// Show what the server's recent tasks added up to: how many there were, how many
+    /// failed, the bytes they processed and the CPU time they took, in total, by client and
+    /// by filter. Only root, and the server's own user, may ask.
+    Report {
+        /// Only count the tasks that concluded in the last this many seconds, rather than
+        /// every task the server still accounts for.
+        #[arg(long, value_name = "SECONDS")]
+        since: Option<u64>,
+    },
+    /// Restart the server from its executable, e.g. once upgraded, without dropping its
+    /// pending requests: once its running tasks conclude, it execs itself afresh, handing
+    /// its sockets and pending requests over. Only root, and the server's own user, may ask.
+    Reexec,
+    /// Suspend a running task, stopping its filters, which no longer count against the
+    /// limits, so that other tasks may run meanwhile, until it is resumed.
+    Suspend {
+        /// ID of the task's request, or the number it started running as, as for `query`.
+        #[arg(value_name = "TASK_ID")]
+        task: TaskId,
+    },
+    /// Resume a suspended task, whose filters count against the limits again.
+    Resume {
+        /// ID of the task's request, or the number it started running as, as for `query`.
+        #[arg(value_name = "TASK_ID")]
+        task: TaskId,
+    },
+    /// Output a script completing the client's commands, options and filters in a shell, to
+    /// be sourced by it, e.g. `source <(sdstore completions bash)`.
+    Completions {
+        /// The shell to complete in.
+        shell: Shell,
+    },
+}
+
+#[derive(Debug, Args)]
+pub struct ProcFileArgs {
+    /// Priority of the request, higher ones running first.
+    #[arg(short, long, default_value_t = 0)]
+    pub priority: usize,
+    /// Queue to submit the request to, as configured in the server.
+    #[arg(long)]
+    pub queue: Option<String>,
+    /// Only check that the request could run, and report how.
+    #[arg(long)]
+    pub dry_run: bool,
+    /// Replace an existing output file, as is the default.
+    #[arg(long, overrides_with = "no_clobber")]
+    pub overwrite: bool,
+    /// Fail rather than replace an existing output file.
+    #[arg(long, overrides_with = "overwrite")]
+    pub no_clobber: bool,
+    /// Send the input to the server, and receive the output back, over its stream socket.
+    #[arg(long)]
+    pub stream: bool,
+    /// Send the input in the request itself, and receive the output back in a reply, for a
+    /// small file the server can't open, up to its `inline-max-size`.
+    #[arg(long, conflicts_with_all = ["stream", "no_wait", "idempotency_key", "out_dir", "store"])]
+    pub inline: bool,
+    /// Exit once the server queued the request, rather than wait for it to finish, after
+    /// which `wait` and `query` follow it.
+    #[arg(long, conflicts_with = "stream")]
+    pub no_wait: bool,
+    /// Split a large input in up to this many chunks, each processed by a pipeline of its own.
+    #[arg(long, default_value_t = 1, value_parser = clap::value_parser!(u32).range(1..))]
+    pub chunks: u32,
+    /// Key identifying the request across retries: the server runs it once, and follows the
+    /// request first submitted with the key, rather than run it again.
+    #[arg(long, value_name = "KEY", conflicts_with_all = ["stream", "out_dir"])]
+    pub idempotency_key: Option<String>,
+    /// Transform every path given, each in a request of its own, into a file of the same name
+    /// in this directory. The filters then follow `--`.
+    #[arg(long, value_name = "DIR", conflicts_with = "stream")]
+    pub out_dir: Option<PathBuf>,
+    /// Write the transformed file to the server's store, which replies with its path, named
+    /// by the SHA-256 hash of its contents. No output is then given, only the filters.
+    #[arg(long, conflicts_with_all = ["stream", "out_dir", "no_clobber"])]
+    pub store: bool,
+    /// Permission bits, in octal, to give the transformed file, e.g. `640`, rather than the
+    /// server's default, or its umask's.
+    #[arg(long, value_name = "MODE", value_parser = parse_mode, conflicts_with = "store")]
+    pub mode: Option<u32>,
+    /// Set an environment variable for the external filters, among those the server allows,
+    /// e.g. `--env LANG=C.UTF-8`. May be given several times.
+    #[arg(long = "env", value_name = "NAME=VALUE", value_parser = parse_env_var)]
+    pub env: Vec<(String, String)>,
+    /// Directory the external filters run in, rather than the server's.
+    #[arg(long, value_name = "DIR")]
+    pub cwd: Option<PathBuf>,
+    /// The file to transform, a directory, or a pattern such as `'inputs/*.log'`, followed by
+    /// where to write the transformed file, or a directory, for a batch, and the filters to
+    /// apply, in order. With `--out-dir`, only the files to transform.
+    #[arg(required = true, value_name = "ARGS")]
+    pub args: Vec<OsString>,
+    /// Filters to apply, in order, if given after `--`, as they must be with `--out-dir`.
+    #[arg(last = true, value_name = "FILTER", value_parser = parse_filter)]
+    pub filters: Vec<Filter>,
+    /// Each input, alongside where its output goes, as told by the arguments, see
+    /// [`ProcFileArgs::resolve`].
+    #[arg(skip)]
+    pub files: Vec<(PathBuf, PathBuf)>,
+    /// The filters to apply to every input, whether given before `--` or after.
+    #[arg(skip)]
+    pub pipeline: Vec<Filter>,
+}
+
+#[derive(Debug, Args)]
+pub struct WatchDirArgs {
+    /// Priority of each request, higher ones running first.
+    #[arg(short, long, default_value_t = 0)]
+    pub priority: usize,
+    /// Queue to submit each request to, as configured in the server.
+    #[arg(long)]
+    pub queue: Option<String>,
+    /// Fail rather than replace an existing output file.
+    #[arg(long)]
+    pub no_clobber: bool,
+    /// Permission bits, in octal, to give each transformed file, e.g. `640`.
+    #[arg(long, value_name = "MODE", value_parser = parse_mode)]
+    pub mode: Option<u32>,
+    /// Remove each file from the watched directory once it was transformed, leaving only
+    /// those yet to be, or that failed to be.
+    #[arg(long)]
+    pub remove_input: bool,
+    /// The directory to watch. Only files written, or moved, into it once it is watched are
+    /// transformed, and not hidden ones, so that a file may be written under a name starting
+    /// with `.`, then renamed once complete.
+    pub dir: PathBuf,
+    /// Directory to write the transformed files in, which can't be the watched one.
+    pub out_dir: PathBuf,
+    /// Filters to apply, in order.
+    #[arg(required = true, value_parser = parse_filter)]
+    pub filters: Vec<Filter>,
+}
+
+impl ClientCli {
+    /// Parse the client's command line from `args`, as [`Parser::try_parse_from`] does, and
+    /// then what clap can't tell apart on its own: `proc-file`'s files and filters, see
+    /// [`ProcFileArgs::resolve`].
+    pub fn try_parse_args<I, T>(args: I) -> Result<Self, clap::Error>
+    where
+        I: IntoIterator<Item = T>,
+        T: Into<OsString> + Clone,
+    {
+        let mut cli = Self::try_parse_from(args)?;
+        match &mut cli.command {
+            ClientCommand::ProcFile(args) => args.resolve()?,
+            ClientCommand::WatchDir(args) => args.check()?,
+            _ => {},
+        }
+        Ok(cli)
+    }
+
+    /// Write the script completing the client's command line in `shell` to `out`, see
+    /// [`ClientCommand::Completions`].
+    ///
+    /// Filters are completed with the names of those every server knows of, see
+    /// [`Filter::ALL`], as those registered in a server's config are only known to it.
+    pub fn completions(shell: Shell, out: &mut dyn Write) -> io::Result<()> {
+        let names = Filter::ALL.map(String::from);
+        let filters = |arg: Arg| arg.value_parser(PossibleValuesParser::new(names.clone()));
+        let mut command = Self::command()
+            .mut_subcommand("proc-file", |proc_file| proc_file.mut_arg("filters", filters))
+            .mut_subcommand("watch-dir", |watch_dir| watch_dir.mut_arg("filters", filters));
+        clap_complete::generate(shell, &mut command, "sdstore", out);
+
+        // Fish completions leave every positional argument to completing paths.
+        if shell == Shell::Fish {
+            for subcommand in ["proc-file", "watch-dir"] {
+                writeln!(out, "complete -c sdstore -n \"__fish_seen_subcommand_from {subcommand}\" -a \"{}\"", names.join(" "))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// How to retry reaching the server, as given with `--retries` and `--retry-delay`.
+    pub fn backoff(&self) -> Backoff {
+        Backoff { retries: self.retries, delay: Duration::from_millis(self.retry_delay) }
+    }
+
+    /// The requests to make to the server, as the client with `client_pid`, each identified by
+    /// a random ID: a single one, but for a `proc-file` of several files, with one per file.
+    /// Watching a directory first connects, its tasks being submitted as files turn up, see
+    /// [`WatchDirArgs::task`].
+    pub fn requests(&self, client_pid: u32) -> Vec<ClientRequest> {
+        let request_id = Uuid::new_v4();
+        let request = match &self.command {
+            ClientCommand::ProcFile(args) => return args
+                .tasks(client_pid)
+                .into_iter()
+                .map(|task| ClientRequest::ProcFile(Box::new(task)))
+                .collect(),
+            ClientCommand::WatchDir(_) => ClientRequest::Connect(client_pid),
+            // Watching asks for the status again at each update.
+            ClientCommand::Status | ClientCommand::Watch { .. } => ClientRequest::Status(client_pid, request_id),
+            ClientCommand::Subscribe => ClientRequest::Subscribe(client_pid, request_id),
+            ClientCommand::Ping => ClientRequest::Ping(client_pid, request_id),
+            ClientCommand::Health => ClientRequest::Health(client_pid, request_id),
+            ClientCommand::ServerInfo => ClientRequest::ServerInfo(client_pid, request_id),
+            ClientCommand::Cancel { request_id } => ClientRequest::Cancel(client_pid, *request_id),
+            ClientCommand::History => ClientRequest::History(client_pid, request_id),
+            ClientCommand::Query { task } => ClientRequest::Query(client_pid, request_id, *task),
+            ClientCommand::Wait { request_id: awaited } => ClientRequest::Wait(client_pid, request_id, *awaited),
+            ClientCommand::Logs { request_id: logged } => ClientRequest::Logs(client_pid, request_id, *logged),
+            ClientCommand::Report { since } =>
+                ClientRequest::Report(client_pid, request_id, since.map(Duration::from_secs)),
+            ClientCommand::Reexec => ClientRequest::Reexec(client_pid, request_id),
+            ClientCommand::Suspend { task } => ClientRequest::Suspend(client_pid, request_id, *task),
+            ClientCommand::Resume { task } => ClientRequest::Resume(client_pid, request_id, *task),
+            // Output by the client itself, without asking the server, see `ClientCli::completions`.
+            ClientCommand::Completions { .. } => return Vec::new(),
+        };
+        vec![request]
+    }
+}
+
+impl ProcFileArgs {
+    /// Tell the inputs, outputs and filters apart in the arguments, into
+    /// [`ProcFileArgs::files`] and [`ProcFileArgs::pipeline`]: the input, output, and filters
+    /// in turn, or, with `--out-dir`, only inputs, each output being named after its input,
+    /// or, with `--store`, the input and filters, the output being the server's to name.
+    ///
+    /// Filters may follow `--` in either case, e.g. for an input named as a filter.
+    pub fn resolve(&mut self) -> Result<(), clap::Error> {
+        let mut command = ClientCli::command();
+        command.build();
+        let mut command = command.find_subcommand("proc-file").cloned().unwrap_or(command);
+        let mut error = |kind, msg: String| command.error(kind, msg);
+        let mut args = self.args.iter().map(PathBuf::from);
+        self.pipeline = self.filters.clone();
+
+        match &self.out_dir {
+            Some(out_dir) => {
+                // Two inputs named alike would overwrite each other's output.
+                let mut inputs = HashMap::new();
+                for input in args {
+                    let Some(name) = input.file_name() else {
+                        return Err(error(ErrorKind::ValueValidation, format!("input {} has no file name", input.display())))
+                    };
+                    let output = out_dir.join(name);
+                    if let Some(other) = inputs.insert(output.clone(), input.clone()) {
+                        return Err(error(ErrorKind::ValueValidation, format!(
+                            "inputs {} and {} would both be written to {}", other.display(), input.display(), output.display()
+                        )))
+                    }
+                    self.files.push((input, output));
+                }
+            },
+            None => {
+                let input = args.next();
+                let output = match self.store {
+                    true => Some(PathBuf::new()),
+                    false => args.next(),
+                };
+                let (Some(input), Some(output)) = (input, output) else {
+                    return Err(error(ErrorKind::MissingRequiredArgument, String::from("an output file must follow the input")))
+                };
+                self.files.push((input, output));
+                let filters = self.args[if self.store { 1 } else { 2 }..]
+                    .iter()
+                    .map(|filter| parse_filter(&filter.to_string_lossy()))
+                    .collect::<Result<Vec<_>, _>>()
+                    .map_err(|err| error(ErrorKind::ValueValidation, err))?;
+                self.pipeline.splice(0..0, filters);
+            },
+        }
+
+        if self.pipeline.is_empty() {
+            let msg = match self.out_dir {
+                Some(_) => "filters must follow `--` with --out-dir",
+                None if self.store => "at least one filter must follow the input file with --store",
+                None => "at least one filter must follow the output file",
+            };
+            return Err(error(ErrorKind::MissingRequiredArgument, String::from(msg)))
+        }
+        Ok(())
+    }
+
+    /// A task for each of the files, see [`ProcFileArgs::resolve`], as the client with
+    /// `client_pid`, each in a request of its own, with a random ID.
+    pub fn tasks(&self, client_pid: u32) -> Vec<ClientTask> {
+        self.files
+            .iter()
+            .map(|(input, output)| {
+                let mut task = ClientTask::new(
+                    client_pid, self.priority, input.clone(), output.clone(), self.pipeline.clone()
+                );
+                task.request_id = Uuid::new_v4();
+                task.queue = self.queue.clone();
+                task.dry_run = self.dry_run;
+                task.no_clobber = self.no_clobber;
+                task.stream = self.stream;
+                // Read by the client before it submits the task.
+                task.inline = self.inline.then(Box::default);
+                task.chunks = self.chunks as usize;
+                task.idempotency_key = self.idempotency_key.clone();
+                task.store = self.store;
+                task.mode = self.mode;
+                task.env = self.env.iter().cloned().collect();
+                task.cwd = self.cwd.clone();
+                task
+            })
+            .collect()
+    }
+}
+
+impl WatchDirArgs {
+    /// Check that the transformed files aren't written in the watched directory, where each
+    /// would be transformed again in turn.
+    pub fn check(&self) -> Result<(), clap::Error> {
+        let resolve = |dir: &PathBuf| std::fs::canonicalize(dir).or_else(|_| std::path::absolute(dir)).ok();
+        match resolve(&self.dir) == resolve(&self.out_dir) {
+            true => {
+                let mut command = ClientCli::command();
+                command.build();
+                let mut command = command.find_subcommand("watch-dir").cloned().unwrap_or(command);
+                Err(command.error(ErrorKind::ValueValidation, "the output directory can't be the watched one"))
+            },
+            false => Ok(()),
+        }
+    }
+
+    /// The task transforming the file `input`, of the watched directory, into a file of the
+    /// same name in the output directory, as the client with `client_pid`, in a request of
+    /// its own, with a random ID.
+    pub fn task(&self, client_pid: u32, input: PathBuf) -> ClientTask {
+        let output = self.out_dir.join(input.file_name().unwrap_or_default());
+        let mut task = ClientTask::new(client_pid, self.priority, input, output, self.filters.clone());
+        task.request_id = Uuid::new_v4();
+        task.queue = self.queue.clone();
+        task.no_clobber = self.no_clobber;
+        task.mode = self.mode;
+        task
+    }
+}
+
+/// Parse a filter's name, which the server may not know of, as it may have filters registered
+/// in its config, see [`Filter::Custom`].
+fn parse_filter(s: &str) -> Result<Filter, String> {
+    Filter::from_str(s).map_err(|FilterParseError(filter)| {
+        let filters = Filter::ALL.iter().map(Filter::to_string).collect::<Vec<_>>();
+        format!("invalid filter name {filter:?}, expected a word such as: {}", filters.join(", "))
+    })
+}
+
+/// Parse an environment variable set for the filters, as `NAME=VALUE`, where the name can't
+/// be empty, and neither may have a NUL byte.
+fn parse_env_var(s: &str) -> Result<(String, String), String> {
+    match s.split_once('=') {
+        Some((name, value)) if !name.is_empty() && !s.contains('\0') => Ok((name.to_string(), value.to_string())),
+        _ => Err(format!("{s:?} isn't of the form NAME=VALUE")),
+    }
+}
+
+/// How the client outputs the messages it receives from the server, chosen with
+/// `--output <format>`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum OutputFormat {
+    /// Log lines, for people to read.
+    #[default]
+    Human,
+    /// One JSON object per message, on its own line of stdout, for scripts to parse. Only
+    /// errors are logged, to stderr.
+    Json,
+}
+
+impl OutputFormat {
+    /// Output `msg`, logged at `level` if formatted for humans.
+    pub fn print(self, level: log::Level, msg: &MessageToClient) {
+        match (self, msg) {
+            (OutputFormat::Human, MessageToClient::Status(status)) =>
+                log::log!(level, "Server current status is: \n{status}"),
+            (OutputFormat::Human, msg) => log::log!(level, "{msg}"),
+            (OutputFormat::Json, msg) => match serde_json::to_string(msg) {
+                Err(err) => log::error!("Could not serialize message to JSON. Error: {:?}", err),
+                Ok(json) => println!("{json}"),
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(command: &str) -> Result<ClientCli, clap::Error> {
+        ClientCli::try_parse_args(command.split_ascii_whitespace())
+    }
+
+    /// The tasks of a `proc-file` command, with nil request IDs, to compare them.
+    fn parse_tasks(command: &str) -> Vec<ClientTask> {
+        let mut tasks = match &parse(command).unwrap().command {
+            ClientCommand::ProcFile(args) => args.tasks(0),
+            command => panic!("expected a proc-file command, got {:?}", command),
+        };
+        tasks.iter_mut().for_each(|task| task.request_id = Uuid::nil());
+        tasks
+    }
+
+    fn parse_task(command: &str) -> ClientTask {
+        let [task] = parse_tasks(command).try_into().unwrap();
+        task
+    }
+
+    #[test]
+    fn task_parsing_works() {
+        let task = ClientTask::new(
+            0,
+            5,
+            PathBuf::from("samples/file-a"),
+            PathBuf::from("outputs/file-a-output"),
+            vec![Filter::Bcompress, Filter::Nop, Filter::Gcompress, Filter::Encrypt, Filter::Nop]
+        );
+        let command = "./sdstore proc-file --priority 5 samples/file-a outputs/file-a-output bcompress nop gcompress encrypt nop";
+        assert_eq!(parse_task(command), task);
+        assert_eq!(parse_task("./sdstore proc-file in out nop").priority, 0);
+        assert_eq!(parse_task("./sdstore proc-file in out nop -- bcompress").transformations, [Filter::Nop, Filter::Bcompress]);
+        assert_eq!(parse_task("./sdstore proc-file nop out -- nop").input_filepath(), PathBuf::from("nop"));
+    }
+
+    #[test]
+    fn several_files_parsing_works() {
+        let tasks = parse_tasks("./sdstore proc-file -p 3 --out-dir outputs/ a/file1 file2 -- gcompress encrypt");
+        let task = |input: &str, output: &str| {
+            ClientTask::new(0, 3, PathBuf::from(input), PathBuf::from(output), vec![Filter::Gcompress, Filter::Encrypt])
+        };
+        assert_eq!(tasks, [task("a/file1", "outputs/file1"), task("file2", "outputs/file2")]);
+
+        let ClientCommand::ProcFile(args) = parse("./sdstore proc-file --out-dir out a b -- nop").unwrap().command else {
+            panic!("expected a proc-file command")
+        };
+        let ids = args.tasks(0).iter().map(|task| task.request_id).collect::<Vec<_>>();
+        assert_ne!(ids[0], ids[1]);
+    }
+
+    #[test]
+    fn watch_dir_parsing_works() {
+        let cli = parse("./sdstore watch-dir -p 2 --no-clobber --remove-input inbox outbox gcompress encrypt").unwrap();
+        let ClientCommand::WatchDir(args) = &cli.command else { panic!("expected a watch-dir command") };
+        assert!(args.remove_input);
+        assert!(matches!(cli.requests(7)[..], [ClientRequest::Connect(7)]));
+
+        let mut task = args.task(7, PathBuf::from("/srv/inbox/a.log"));
+        assert_ne!(task.request_id, Uuid::nil());
+        task.request_id = Uuid::nil();
+        let mut expected = ClientTask::new(
+            7, 2, PathBuf::from("/srv/inbox/a.log"), PathBuf::from("outbox/a.log"), vec![Filter::Gcompress, Filter::Encrypt]
+        );
+        expected.no_clobber = true;
+        assert_eq!(task, expected);
+    }
+
+    #[test]
+    fn requests_parsing_works() {
+        let request = |command: &str| {
+            let [request] = parse(command).unwrap().requests(7).try_into().unwrap();
+            request
+        };
+
+        assert!(matches!(request("./sdstore status"), ClientRequest::Status(7, _)));
+        assert!(matches!(request("./sdstore ping"), ClientRequest::Ping(7, _)));
+        assert!(matches!(request("./sdstore health"), ClientRequest::Health(7, _)));
+        assert!(matches!(request("./sdstore server-info"), ClientRequest::ServerInfo(7, _)));
+        assert!(matches!(request("./sdstore history"), ClientRequest::History(7, _)));
+        assert!(matches!(request("./sdstore report"), ClientRequest::Report(7, _, None)));
+        assert!(matches!(request("./sdstore reexec"), ClientRequest::Reexec(7, _)));
+        assert!(matches!(
+            request("./sdstore report --since 3600"), ClientRequest::Report(7, _, Some(window)) if window.as_secs() == 3600
+        ));
+        assert!(matches!(request("./sdstore watch --interval 5"), ClientRequest::Status(7, _)));
+
+        let cancelled = Uuid::new_v4();
+        let cancel = request(&format!("./sdstore cancel {cancelled}"));
+        assert_eq!(cancel, ClientRequest::Cancel(7, cancelled));
+        assert_eq!(cancel.request_id(), None);
+        let query = request(&format!("./sdstore query {cancelled}"));
+        assert!(matches!(query, ClientRequest::Query(7, request_id, queried) if request_id != cancelled && queried == TaskId::Request(cancelled)));
+        assert!(matches!(request("./sdstore query 3"), ClientRequest::Query(7, _, TaskId::Number(3))));
+        assert!(matches!(request("./sdstore query #3"), ClientRequest::Query(7, _, TaskId::Number(3))));
+        assert!(matches!(request("./sdstore suspend #3"), ClientRequest::Suspend(7, _, TaskId::Number(3))));
+        assert!(matches!(request("./sdstore resume 3"), ClientRequest::Resume(7, _, TaskId::Number(3))));
+        assert!(parse("./sdstore query three").is_err());
+        assert!(matches!(request(&format!("./sdstore wait {cancelled}")), ClientRequest::Wait(7, _, awaited) if awaited == cancelled));
+        assert!(matches!(request(&format!("./sdstore logs {cancelled}")), ClientRequest::Logs(7, _, logged) if logged == cancelled));
+    }
+
+    #[test]
+    fn completions_include_filters() {
+        assert!(parse("./sdstore completions bash").is_ok_and(|cli| cli.requests(7).is_empty()));
+        for shell in [Shell::Bash, Shell::Zsh, Shell::Fish] {
+            let mut script = Vec::new();
+            ClientCli::completions(shell, &mut script).unwrap();
+            let script = String::from_utf8(script).unwrap();
+            assert!(script.contains("proc-file") && script.contains("gcompress") && script.contains("xdecompress"));
+        }
+    }
+
+    #[test]
+    fn options_parsing_works() {
+        let cli = parse("./sdstore --timeout 5 status --output json --socket-dir /run/sdstore").unwrap();
+        assert_eq!((cli.timeout, cli.output), (Some(5), OutputFormat::Json));
+        assert_eq!(cli.socket_dir, Some(PathBuf::from("/run/sdstore")));
+        assert_eq!(cli.backoff(), Backoff { retries: 4, delay: Duration::from_millis(250) });
+        let cli = parse("./sdstore ping --retries 0 --retry-delay 10").unwrap();
+        assert_eq!(cli.backoff(), Backoff { retries: 0, delay: Duration::from_millis(10) });
+
+        let task = parse_task("./sdstore proc-file --dry-run --queue batch -p 1 --chunks 4 in out nop");
+        assert!(task.dry_run);
+        assert_eq!((task.queue_name(), task.priority, task.chunks), ("batch", 1, 4));
+        let task = parse_task("./sdstore proc-file --idempotency-key nightly-backup in out nop");
+        assert_eq!(task.idempotency_key.as_deref(), Some("nightly-backup"));
+        let task = parse_task("./sdstore proc-file --store in gcompress encrypt");
+        assert!(task.store && task.input_filepath() == "in");
+        assert_eq!(task.transformations, [Filter::Gcompress, Filter::Encrypt]);
+
+        assert!(!parse_task("./sdstore proc-file in out nop").no_clobber);
+        assert!(parse_task("./sdstore proc-file --no-clobber in out nop").no_clobber);
+        assert!(!parse_task("./sdstore proc-file --no-clobber --overwrite in out nop").no_clobber);
+
+        assert_eq!(parse_task("./sdstore proc-file --mode 640 in out nop").mode, Some(0o640));
+        assert_eq!(parse_task("./sdstore proc-file --mode 0o600 in out nop").mode, Some(0o600));
+        assert!(parse("./sdstore proc-file --mode 4755 in out nop").is_err());
+        assert!(parse("./sdstore proc-file --mode rw-r----- in out nop").is_err());
+
+        let task = parse_task("./sdstore proc-file --env LANG=C.UTF-8 --env TZ= --cwd /var/tmp in out nop");
+        assert_eq!(task.env.get("LANG").map(String::as_str), Some("C.UTF-8"));
+        assert_eq!(task.env.get("TZ").map(String::as_str), Some(""));
+        assert_eq!(task.cwd.as_deref(), Some(std::path::Path::new("/var/tmp")));
+        assert!(parse("./sdstore proc-file --env =C in out nop").is_err());
+        assert!(parse("./sdstore proc-file --env LANG in out nop").is_err());
+
+        let cli = parse("./sdstore proc-file --no-wait in out nop").unwrap();
+        assert!(matches!(cli.command, ClientCommand::ProcFile(ProcFileArgs { no_wait: true, .. })));
+    }
+
+    #[test]
+    fn request_parsing_fails() {
+        let kind = |command| parse(command).unwrap_err().kind();
+
+        assert_eq!(kind("./sdstore"), ErrorKind::DisplayHelpOnMissingArgumentOrSubcommand);
+        assert_eq!(kind("./sdstore abcdef"), ErrorKind::InvalidSubcommand);
+        assert_eq!(kind("./sdstore proc-file"), ErrorKind::MissingRequiredArgument);
+        assert_eq!(kind("./sdstore proc-file samples/file-a outputs/file-a-output"), ErrorKind::MissingRequiredArgument);
+        assert_eq!(kind("./sdstore proc-file --priority 5a in out nop"), ErrorKind::ValueValidation);
+        assert_eq!(kind("./sdstore proc-file in out no/p"), ErrorKind::ValueValidation);
+        assert_eq!(kind("./sdstore proc-file --chunks 0 in out nop"), ErrorKind::ValueValidation);
+        assert_eq!(kind("./sdstore proc-file --queue"), ErrorKind::InvalidValue);
+        assert_eq!(kind("./sdstore proc-file --dry-runn in out nop"), ErrorKind::UnknownArgument);
+        assert_eq!(kind("./sdstore cancel 42"), ErrorKind::ValueValidation);
+        assert_eq!(kind("./sdstore wait"), ErrorKind::MissingRequiredArgument);
+        assert_eq!(kind("./sdstore watch --interval 0"), ErrorKind::ValueValidation);
+        assert_eq!(kind("./sdstore proc-file --stream --no-wait in out nop"), ErrorKind::ArgumentConflict);
+        assert_eq!(kind("./sdstore proc-file in out"), ErrorKind::MissingRequiredArgument);
+        assert_eq!(kind("./sdstore proc-file --out-dir out a b"), ErrorKind::MissingRequiredArgument);
+        assert_eq!(kind("./sdstore proc-file --out-dir out a x/a -- nop"), ErrorKind::ValueValidation);
+        assert_eq!(kind("./sdstore proc-file --out-dir out .. -- nop"), ErrorKind::ValueValidation);
+        assert_eq!(kind("./sdstore proc-file in out nop -- no/p"), ErrorKind::ValueValidation);
+        assert_eq!(kind("./sdstore --output yaml status"), ErrorKind::InvalidValue);
+        assert_eq!(kind("./sdstore proc-file --store in"), ErrorKind::MissingRequiredArgument);
+        assert_eq!(kind("./sdstore proc-file --store --stream in nop"), ErrorKind::ArgumentConflict);
+        assert_eq!(kind("./sdstore proc-file --inline --stream in out nop"), ErrorKind::ArgumentConflict);
+        assert_eq!(kind("./sdstore proc-file --inline --no-wait in out nop"), ErrorKind::ArgumentConflict);
+        assert_eq!(kind("./sdstore watch-dir inbox outbox"), ErrorKind::MissingRequiredArgument);
+        assert_eq!(kind("./sdstore watch-dir inbox ./inbox nop"), ErrorKind::ValueValidation);
+    }
+}
@@ -1,8 +1,32 @@
-use std::{hash::Hash, path::{Path, PathBuf}, num::ParseIntError, str::FromStr};
+use std::{
+    collections::BTreeMap, ffi::CString, fmt::Display, fs, hash::Hash, io,
+    os::unix::{ffi::OsStrExt, fs::MetadataExt},
+    path::{self, Path, PathBuf},
+    time::Instant,
+};
 
 use serde::{Serialize, Deserialize};
+use uuid::Uuid;
 
-use super::filter::{Filter, FilterParseError};
+use super::{batch, filter::Filter, remote};
+
+/// Name of the queue tasks are submitted to when the client doesn't choose one.
+pub const DEFAULT_QUEUE: &str = "default";
+
+/// Permission bits an output may be given, see [`ClientTask::mode`]: its owner's, group's and
+/// others', but not the set-user-ID, set-group-ID or sticky bits.
+pub const MODE_BITS: u32 = 0o777;
+
+/// Parse permission bits in octal, as `chmod` and `umask` take them, e.g. `640`, `0640` or
+/// `0o640`, which can't go beyond [`MODE_BITS`].
+pub fn parse_mode(s: &str) -> Result<u32, String> {
+    let digits = s.strip_prefix("0o").unwrap_or(s);
+    match u32::from_str_radix(digits, 8) {
+        Ok(mode) if mode & !MODE_BITS == 0 => Ok(mode),
+        Ok(_) => Err(format!("{s:?} has bits beyond the owner's, group's and others' permissions, {MODE_BITS:o}")),
+        Err(_) => Err(format!("{s:?} isn't an octal mode, such as 640")),
+    }
+}
 
 /// This `struct` represents a request, to the `sdstore` server, to apply a sequence
 /// of filters to the input file, thereby producing the output at the specified location.
@@ -16,10 +40,69 @@ use super::filter::{Filter, FilterParseError};
 #[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone, Hash)]
 pub struct ClientTask {
     pub client_pid: u32,
+    /// Identifies the request the task was submitted in, and is echoed back in every
+    /// notification about it, see [`Sequenced`](super::messaging::Sequenced).
+    pub request_id: Uuid,
     pub priority: usize,
     input: PathBuf,
     output: PathBuf,
-    pub transformations: Vec<Filter>
+    pub transformations: Vec<Filter>,
+    /// Queue, or QoS class, the task was submitted to. `None` for the [`DEFAULT_QUEUE`].
+    pub queue: Option<String>,
+    /// Whether the server should only validate the task, and report what would happen,
+    /// rather than run it.
+    pub dry_run: bool,
+    /// Whether the task must fail rather than replace an existing output file.
+    pub no_clobber: bool,
+    /// Whether the client streams the input to the server, and receives the output back,
+    /// over the server's stream socket, rather than have the server open the files' paths.
+    pub stream: bool,
+    /// The input, if the client sends it in the request itself, and receives the output back
+    /// in a reply, see [`MessageToClient::InlineOutput`](super::messaging::MessageToClient::InlineOutput),
+    /// so that small files needn't be on a filesystem the server shares. The server spools
+    /// it before queueing the task, leaving it empty. `None` for other tasks.
+    pub inline: Option<Box<[u8]>>,
+    /// Number of chunks the input may be split in, to run that many pipelines on them at
+    /// once, see [`chunking`](super::chunking). `1` for a single pipeline.
+    pub chunks: usize,
+    /// Key the client attached to the request, so that the server runs it once, however many
+    /// times it's submitted, within its
+    /// [`idempotency_window`](super::server::config::ServerConfig::idempotency_window).
+    pub idempotency_key: Option<String>,
+    /// Whether the output is written to the server's store, named by the hash of its
+    /// contents, rather than to a path of the client's choosing, see
+    /// [`OutputStore`](super::server::store::OutputStore). The output's path is then the
+    /// server's to set.
+    pub store: bool,
+    /// Permission bits the output is given, e.g. `0o640`, whatever the server's umask, see
+    /// [`MODE_BITS`]. `None` for the server's default, if it has one, or else its umask's.
+    pub mode: Option<u32>,
+    /// Environment variables set for the task's external filters, on top of the server's,
+    /// among those it allows, see
+    /// [`FilterEnvironment`](super::server::environment::FilterEnvironment).
+    pub env: BTreeMap<String, String>,
+    /// Directory the task's external filters run in. `None` for the server's.
+    pub cwd: Option<PathBuf>,
+    /// When the server received the task, to measure how long it waited to be run.
+    /// Only set by the server, it is never sent over the socket.
+    #[serde(skip)]
+    pub received_at: Option<Instant>,
+    /// Checkpoint the task resumes from, if it was interrupted by the server restarting,
+    /// see [`Checkpoint`](super::checkpoint::Checkpoint). Only set by the server.
+    #[serde(skip)]
+    pub checkpoint: Option<PathBuf>,
+    /// UID of the client's user, if the transport could tell, see
+    /// [`Credentials`](super::transport::Credentials). Only set by the server.
+    #[serde(skip)]
+    pub client_uid: Option<u32>,
+    /// GID of the client's user, alongside [`ClientTask::client_uid`]. Only set by the server.
+    #[serde(skip)]
+    pub client_gid: Option<u32>,
+    /// Whether the server relocated the task's files to its own copies, as it does for
+    /// streamed, and inline, tasks, see [`ClientTask::is_spooled`]. Only set by the server,
+    /// which never trusts the client's `stream` flag for it.
+    #[serde(skip)]
+    pub spooled: bool
 }
 
 impl ClientTask {
@@ -32,17 +115,72 @@ impl ClientTask {
     {
         ClientTask {
             client_pid,
+            request_id: Uuid::nil(),
             priority,
             input,
             output,
-            transformations
+            transformations,
+            queue: None,
+            dry_run: false,
+            no_clobber: false,
+            stream: false,
+            inline: None,
+            chunks: 1,
+            idempotency_key: None,
+            store: false,
+            mode: None,
+            env: BTreeMap::new(),
+            cwd: None,
+            received_at: None,
+            checkpoint: None,
+            client_uid: None,
+            client_gid: None,
+            spooled: false
+        }
+    }
+}
+
+/// Why a task's files can't be used, as the client finds before submitting it, see
+/// [`ClientTask::check_paths`].
+#[derive(Debug)]
+pub enum TaskPathError {
+    /// The path couldn't be made absolute, as the working directory is unknown.
+    Unresolvable(PathBuf, io::Error),
+    /// The input file, or a batch's input directory, can't be read.
+    InputUnreadable(PathBuf, io::Error),
+    /// The batch input matches no files, see [`batch::is_batch`].
+    NoInputFiles(PathBuf),
+    /// The output would be written in this directory, which doesn't exist, or can't be
+    /// written to.
+    OutputDirNotWritable(PathBuf, io::Error),
+    /// The output is the input file itself, see [`ClientTask::output_is_input`].
+    OutputIsInput(PathBuf),
+    /// The batch input can't be transformed into the server's store, which only takes
+    /// single files, see [`ClientTask::store`].
+    BatchToStore(PathBuf),
+    /// The batch input can't be transformed into a remote output, which is a single file,
+    /// see [`remote`].
+    BatchToRemote(PathBuf),
+}
+
+impl Display for TaskPathError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Unresolvable(path, err) => write!(f, "could not resolve {}: {err}", path.display()),
+            Self::InputUnreadable(path, err) => write!(f, "the input {} is not readable: {err}", path.display()),
+            Self::NoInputFiles(path) => write!(f, "the batch input {} matches no files", path.display()),
+            Self::OutputDirNotWritable(path, err) =>
+                write!(f, "the output directory {} is not writable: {err}", path.display()),
+            Self::OutputIsInput(path) => write!(f, "the output {} is the input file", path.display()),
+            Self::BatchToStore(path) => write!(f, "the batch input {} can't be written to the store", path.display()),
+            Self::BatchToRemote(path) => write!(f, "the batch input {} can't be written to a URL", path.display()),
         }
     }
 }
 
 impl PartialOrd for ClientTask {
     fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
-        Some(self.priority.cmp(&other.priority))
+        Some(self.cmp(other))
     }
 }
 
@@ -52,91 +190,195 @@ impl Ord for ClientTask {
     }
 }
 
-/// When parsing the client's request from the CLI to turn it into a [`Task`], this enum encodes
-/// the errors that may occur.
-#[derive(Debug, PartialEq, Eq)]
-pub enum TaskParseError {
-    InvalidPriority(ParseIntError),
-    NoPriorityProvided,
-    InvalidInputOutputPaths,
-    NoFiltersProvided,
-    InvalidFilterProvided(FilterParseError)
-}
-
 impl ClientTask {
-    /// Build a [`Task`] from `main`'s `args` iterator, parsing the user's input
-    /// to construct a request to the server.
-    ///
-    /// This method is meant to be called from the homologous [`ClientRequest`]
-    /// method, and not by itself.
-    pub fn build(
-        mut args: impl Iterator<Item = String>,
-        client_pid: u32
-    ) -> Result<Self, TaskParseError> {
-        // A task is only ever parsed from the CLI as part of a client
-        // request, so the `args` iterator here has already been moved to
-        // the priority section of the request.
-
-        let priority: usize = match args.next() {
-            None => return Err(TaskParseError::NoPriorityProvided),
-            Some(prio) => {
-                match prio.trim().parse() {
-                    Err(err) => return Err(TaskParseError::InvalidPriority(err)),
-                    Ok(p) => p
-                }
-            }
-        };
+    /// A span for the server's work on the task, which its every thread enters, so that
+    /// what each logs can be told apart by task, see [`init_tracing`](crate::util::init_tracing).
+    /// Its `task_number` is recorded once the task starts.
+    pub fn span(&self) -> tracing::Span {
+        tracing::info_span!(
+            "task",
+            request_id = %self.request_id,
+            client_pid = self.client_pid,
+            task_number = tracing::field::Empty
+        )
+    }
 
-        let (input, output) = match (args.next(), args.next()) {
-            (None, _) | (_, None) => return Err(TaskParseError::InvalidInputOutputPaths),
-            (Some(input_path), Some(output_path)) =>
-                (PathBuf::from(input_path), PathBuf::from(output_path))
-        };
+    /// Filters the task runs at once: its pipeline, once for each chunk of its input.
+    pub fn filter_demand(&self) -> Vec<Filter> {
+        (0..self.chunks)
+            .flat_map(|_| self.transformations.iter().cloned())
+            .collect()
+    }
+
+    pub fn input_filepath(&self) -> &Path {
+        self.input.as_path()
+    }
+
+    pub fn output_filepath(&self) -> &Path {
+        self.output.as_path()
+    }
+
+    /// Whether the task's files are the server's own copies, rather than the paths its
+    /// client gave: if it was streamed, or sent inline, and the server relocated its files.
+    pub fn is_spooled(&self) -> bool {
+        self.spooled
+    }
+
+    /// Make the task read `input` and write `output` instead, e.g. the server's copies of
+    /// a streamed, or inline, task's files.
+    pub fn relocate(&mut self, input: PathBuf, output: PathBuf) {
+        self.input = input;
+        self.output = output;
+    }
 
-        let mut transformations: Vec<Filter> = Vec::new();
-        for filter in args {
-            match Filter::from_str(filter.as_str()) {
-                Err(err) => return Err(TaskParseError::InvalidFilterProvided(err)),
-                Ok(f) => transformations.push(f),
+    /// Make the task's paths absolute, its filters' working directory included, as the server
+    /// opens them from its own working directory, and check that its input can be read, and
+    /// its output written, failing fast rather than have the server find out.
+    ///
+    /// A batch's output directory is created by the server if need be, so only its closest
+    /// existing ancestor must be writable. The output of a task written to the store is the
+    /// server's, and isn't checked. The checks are made as the client's user, who may not be
+    /// the server's.
+    ///
+    /// URLs, see [`remote`], are left as they are, as only the server can tell whether they
+    /// can be read or written.
+    pub fn check_paths(&mut self) -> Result<(), TaskPathError> {
+        let absolute = |path: &Path| path::absolute(path).map_err(|err| TaskPathError::Unresolvable(path.to_path_buf(), err));
+        if let Some(cwd) = &self.cwd {
+            self.cwd = Some(absolute(cwd)?);
+        }
+        let remote_input = remote::is_remote(&self.input);
+        if !remote_input {
+            self.input = absolute(&self.input)?;
+        }
+        let check_input = |input: &Path| match remote_input {
+            true => Ok(()),
+            false => fs::File::open(input).map(|_| ()).map_err(|err| TaskPathError::InputUnreadable(input.to_path_buf(), err)),
+        };
+        if self.store {
+            if batch::is_batch(&self.input) {
+                return Err(TaskPathError::BatchToStore(self.input.clone()))
+            }
+            return check_input(&self.input)
+        }
+        if remote::is_remote(&self.output) {
+            if batch::is_batch(&self.input) {
+                return Err(TaskPathError::BatchToRemote(self.input.clone()))
             }
+            return check_input(&self.input)
         }
-        if transformations.is_empty() { return Err(TaskParseError::NoFiltersProvided) }
+        self.output = absolute(&self.output)?;
 
-        let task = ClientTask {
-            client_pid,
-            priority,
-            input,
-            output,
-            transformations
+        let output_dir = match batch::is_batch(&self.input) {
+            true => {
+                let files = batch::expand(&self.input, &self.output)
+                    .map_err(|err| TaskPathError::InputUnreadable(self.input.clone(), err))?;
+                if files.is_empty() {
+                    return Err(TaskPathError::NoInputFiles(self.input.clone()))
+                }
+                self.output.ancestors().find(|dir| dir.is_dir()).unwrap_or(Path::new("/"))
+            },
+            false => {
+                check_input(&self.input)?;
+                if self.output_is_input() {
+                    return Err(TaskPathError::OutputIsInput(self.output.clone()))
+                }
+                self.output.parent().unwrap_or(Path::new("/"))
+            },
         };
-        Ok(task)
+        check_writable_dir(output_dir).map_err(|err| TaskPathError::OutputDirNotWritable(output_dir.to_path_buf(), err))
     }
 
-    pub fn get_transformations(&self) -> Vec<Filter> {
-        self.transformations.clone()
+    /// Whether the task's output is the very file of its input, under the same path or not,
+    /// e.g. through a symlink or a hard link, so that writing the one would destroy the other.
+    pub fn output_is_input(&self) -> bool {
+        match (fs::metadata(&self.input), fs::metadata(&self.output)) {
+            (Ok(input), Ok(output)) => (input.dev(), input.ino()) == (output.dev(), output.ino()),
+            _ => false,
+        }
     }
 
-    pub fn input_filepath(&self) -> &Path {
-        self.input.as_path()
+    /// Name of the queue this task was submitted to.
+    pub fn queue_name(&self) -> &str {
+        self.queue.as_deref().unwrap_or(DEFAULT_QUEUE)
     }
+}
 
-    pub fn output_filepath(&self) -> &Path {
-        self.output.as_path()
+/// Check that `dir` is a directory the process may create files in.
+fn check_writable_dir(dir: &Path) -> io::Result<()> {
+    if !fs::metadata(dir)?.is_dir() {
+        return Err(io::Error::new(io::ErrorKind::NotADirectory, "not a directory"))
+    }
+    let dir = CString::new(dir.as_os_str().as_bytes())?;
+    match unsafe { libc::access(dir.as_ptr(), libc::W_OK | libc::X_OK) } {
+        0 => Ok(()),
+        _ => Err(io::Error::last_os_error()),
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use std::str::FromStr;
+
+    use crate::core::{filter::FilterParseError, testing::TempDir};
+
     use super::*;
 
+    #[test]
+    fn paths_are_checked() {
+        let dir = TempDir::new("client_task");
+        fs::create_dir_all(dir.join("inputs")).unwrap();
+        fs::write(dir.join("inputs/in"), b"data").unwrap();
+        let task = |input: &str, output: &str| {
+            ClientTask::new(0, 0, dir.join(input), dir.join(output), vec![Filter::Nop])
+        };
+
+        let mut file = task("inputs/in", "out");
+        file.check_paths().unwrap();
+        assert!(file.input_filepath().is_absolute());
+        task("inputs/*", "outputs/new").check_paths().unwrap();
+
+        let err = task("missing", "out").check_paths().unwrap_err();
+        assert!(matches!(err, TaskPathError::InputUnreadable(_, err) if err.kind() == io::ErrorKind::NotFound));
+        let err = task("inputs/*.log", "out").check_paths().unwrap_err();
+        assert!(matches!(err, TaskPathError::NoInputFiles(_)));
+        let err = task("inputs/in", "missing/out").check_paths().unwrap_err();
+        assert!(matches!(err, TaskPathError::OutputDirNotWritable(..)));
+        let err = task("inputs/in", "inputs/in/out").check_paths().unwrap_err();
+        assert!(matches!(err, TaskPathError::OutputDirNotWritable(..)));
+        let err = task("inputs/in", "inputs/../inputs/in").check_paths().unwrap_err();
+        assert!(matches!(err, TaskPathError::OutputIsInput(_)));
+        fs::hard_link(dir.join("inputs/in"), dir.join("link")).unwrap();
+        assert!(task("inputs/in", "link").output_is_input() && !task("inputs/in", "out").output_is_input());
+
+        let mut stored = task("inputs/in", "");
+        stored.store = true;
+        stored.check_paths().unwrap();
+        let mut stored = task("inputs", "");
+        stored.store = true;
+        assert!(matches!(stored.check_paths(), Err(TaskPathError::BatchToStore(_))));
+
+        let mut remote = ClientTask::new(0, 0, PathBuf::from("https://example.com/in"), PathBuf::from("s3://bucket/out"), vec![]);
+        remote.check_paths().unwrap();
+        assert!(remote.input_filepath() == Path::new("https://example.com/in") && remote.output_filepath() == Path::new("s3://bucket/out"));
+        let mut remote = task("inputs", "");
+        remote.relocate(dir.join("inputs"), PathBuf::from("s3://bucket/out"));
+        assert!(matches!(remote.check_paths(), Err(TaskPathError::BatchToRemote(_))));
+
+        let mut relative = ClientTask::new(0, 0, PathBuf::from("in"), PathBuf::from("out"), vec![Filter::Nop]);
+        assert!(matches!(relative.check_paths(), Err(TaskPathError::InputUnreadable(path, _)) if path.is_absolute()));
+    }
+
     #[test]
     fn filter_parsing_works() {
         let str_filters = vec![
-            "nop", "bcompress", "bdecompress", "gcompress", "gdecompress", "encrypt", "decrypt"
+            "nop", "bcompress", "bdecompress", "gcompress", "gdecompress", "encrypt", "decrypt",
+            "zcompress", "zdecompress", "xcompress", "xdecompress"
         ];
         let expected = vec![
             Filter::Nop ,Filter::Bcompress, Filter::Bdecompress, Filter::Gcompress,
-            Filter::Gdecompress, Filter::Encrypt, Filter::Decrypt
+            Filter::Gdecompress, Filter::Encrypt, Filter::Decrypt, Filter::Zcompress,
+            Filter::Zdecompress, Filter::Xcompress, Filter::Xdecompress
         ];
 
         let actual = str_filters
@@ -145,17 +387,20 @@ mod tests {
             .map(Result::unwrap)
             .collect::<Vec<_>>();
 
-        for (expected, actual) in expected.into_iter().zip(actual.into_iter()) {
+        for (expected, actual) in expected.into_iter().zip(actual) {
             assert_eq!(expected, actual);
         }
     }
 
     #[test]
     fn filter_parsing_fails() {
-        let str = "bcompres";
-        let expected = FilterParseError("bcompres".to_string());
+        let str = "bcompress/";
+        let expected = FilterParseError("bcompress/".to_string());
         let actual = Filter::from_str(str).unwrap_err();
 
         assert_eq!(expected, actual);
+        // Other words name filters the server may have registered.
+        assert_eq!(Filter::from_str("bcompres"), Ok(Filter::Custom(String::from("bcompres"))));
+        assert!(Filter::from_str("-v").is_err());
     }
 }
\ No newline at end of file
@@ -0,0 +1,138 @@
+//! Directories into which files are dropped to be transformed, which `sdstore watch-dir`
+//! watches with inotify, submitting a task for each new file, see [`DropFolder`].
+
+use std::{
+    ffi::{CString, OsStr},
+    fs, io,
+    os::{fd::{AsRawFd, FromRawFd, OwnedFd}, unix::ffi::OsStrExt},
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+/// Events a file is ready to be transformed on: once it was written and closed, or moved into
+/// the directory whole, as it should be when written elsewhere first.
+const READY_EVENTS: u32 = libc::IN_CLOSE_WRITE | libc::IN_MOVED_TO;
+
+/// Size of the header of each event read from inotify, followed by its file's name.
+const EVENT_HEADER_LEN: usize = std::mem::size_of::<libc::inotify_event>();
+
+/// A directory watched for files to transform, as they are written into it.
+///
+/// Only the files that are ready after it started being watched are told of, see
+/// [`READY_EVENTS`], and not those already in it. Hidden files, whose names start with `.`,
+/// are left out, so that a file may be written under such a name, then renamed once complete.
+pub struct DropFolder {
+    dir: PathBuf,
+    inotify: OwnedFd,
+}
+
+impl DropFolder {
+    /// Start watching the directory `dir`.
+    pub fn watch(dir: &Path) -> io::Result<Self> {
+        let dir = fs::canonicalize(dir)?;
+        // SAFETY: the call has no memory safety requirement, and its descriptor is owned below.
+        let fd = unsafe { libc::inotify_init1(libc::IN_NONBLOCK | libc::IN_CLOEXEC) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error())
+        }
+        // SAFETY: `fd` is a descriptor just opened, and owned by nothing else.
+        let inotify = unsafe { OwnedFd::from_raw_fd(fd) };
+
+        let path = CString::new(dir.as_os_str().as_bytes())?;
+        // SAFETY: `path` is a NUL-terminated string, which outlives the call.
+        let watch = unsafe { libc::inotify_add_watch(inotify.as_raw_fd(), path.as_ptr(), READY_EVENTS | libc::IN_ONLYDIR) };
+        if watch < 0 {
+            return Err(io::Error::last_os_error())
+        }
+        Ok(DropFolder { dir, inotify })
+    }
+
+    /// The directory watched, as an absolute path.
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Wait up to `timeout` for files to be ready, returning the paths of every one that
+    /// was since last called, if any.
+    ///
+    /// Fails once the directory is removed, or moved, as it can no longer be watched.
+    pub fn ready_files(&self, timeout: Duration) -> io::Result<Vec<PathBuf>> {
+        let mut poll_fd = libc::pollfd { fd: self.inotify.as_raw_fd(), events: libc::POLLIN, revents: 0 };
+        let timeout = timeout.as_millis().try_into().unwrap_or(libc::c_int::MAX);
+        // SAFETY: `poll_fd` is a single valid `pollfd`, which outlives the call.
+        if unsafe { libc::poll(&mut poll_fd, 1, timeout) } < 0 {
+            let err = io::Error::last_os_error();
+            return match err.kind() {
+                io::ErrorKind::Interrupted => Ok(Vec::new()),
+                _ => Err(err),
+            }
+        }
+
+        let mut files = Vec::new();
+        // Room for many events, each at most `NAME_MAX` bytes longer than its header.
+        let mut buf = vec![0u8; 64 * (EVENT_HEADER_LEN + libc::NAME_MAX as usize + 1)];
+        loop {
+            // SAFETY: `buf` is valid for writes of its whole length.
+            let n = unsafe { libc::read(self.inotify.as_raw_fd(), buf.as_mut_ptr().cast(), buf.len()) };
+            if n < 0 {
+                let err = io::Error::last_os_error();
+                return match err.kind() {
+                    io::ErrorKind::WouldBlock => Ok(files),
+                    io::ErrorKind::Interrupted => continue,
+                    _ => Err(err),
+                }
+            }
+            for (mask, name) in events(&buf[..n as usize]) {
+                if mask & libc::IN_IGNORED != 0 {
+                    return Err(io::Error::new(io::ErrorKind::NotFound, format!("{} is no longer there", self.dir.display())))
+                }
+                let hidden = name.as_bytes().first() == Some(&b'.');
+                if mask & READY_EVENTS != 0 && mask & libc::IN_ISDIR == 0 && !name.is_empty() && !hidden {
+                    files.push(self.dir.join(name));
+                }
+            }
+        }
+    }
+}
+
+/// The mask and file name of each of the inotify events read into `buf`, which may be empty if
+/// the event is about the directory itself.
+fn events(mut buf: &[u8]) -> impl Iterator<Item = (u32, &OsStr)> {
+    std::iter::from_fn(move || {
+        let header = buf.get(..EVENT_HEADER_LEN)?;
+        let field = |offset: usize| u32::from_ne_bytes(header[offset..offset + 4].try_into().unwrap_or_default());
+        // Laid out as `wd`, `mask`, `cookie` and `len`, each 4 bytes long.
+        let (mask, len) = (field(4), field(12) as usize);
+        let name = buf.get(EVENT_HEADER_LEN..EVENT_HEADER_LEN + len)?;
+        buf = &buf[EVENT_HEADER_LEN + len..];
+        // The name is padded with NULs, up to an alignment boundary.
+        let name = &name[..name.iter().position(|&byte| byte == 0).unwrap_or(name.len())];
+        Some((mask, OsStr::from_bytes(name)))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::core::testing::TempDir;
+
+    use super::*;
+
+    #[test]
+    fn ready_files_are_told_of() {
+        let dir = TempDir::new("drop_folder");
+        fs::create_dir_all(dir.join("sub")).unwrap();
+        fs::write(dir.join("before"), "").unwrap();
+        let folder = DropFolder::watch(&dir).unwrap();
+        assert_eq!(folder.ready_files(Duration::ZERO).unwrap(), Vec::<PathBuf>::new());
+
+        fs::write(dir.join("written"), "data").unwrap();
+        fs::write(dir.join(".partial"), "data").unwrap();
+        fs::rename(dir.join(".partial"), dir.join("moved")).unwrap();
+        fs::create_dir(dir.join("new_dir")).unwrap();
+        fs::write(dir.join("sub/nested"), "").unwrap();
+        assert_eq!(folder.ready_files(Duration::from_secs(1)).unwrap(), vec![dir.join("written"), dir.join("moved")]);
+
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(folder.ready_files(Duration::from_secs(1)).unwrap_err().kind(), io::ErrorKind::NotFound);
+    }
+}
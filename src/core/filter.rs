@@ -6,8 +6,13 @@ use serde::{Serialize, Deserialize};
 /// to a file.
 ///
 /// For each of these variants, there will be a corresponding `.c` source and
-/// executable in the `bin/` folder, in the root of this project.
+/// executable in the `bin/` folder, in the root of this project, but for
+/// [`Filter::Custom`] ones.
+///
+/// Filters are sent to the server by name, so that it may know of filters its
+/// clients don't.
 #[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone, Hash)]
+#[serde(into = "String", try_from = "String")]
 pub enum Filter {
     Nop,
     Bcompress,
@@ -15,7 +20,31 @@ pub enum Filter {
     Gcompress,
     Gdecompress,
     Encrypt,
-    Decrypt
+    Decrypt,
+    Zcompress,
+    Zdecompress,
+    Xcompress,
+    Xdecompress,
+    /// A filter registered in the server's config, by name, see
+    /// [`parse_limits`](crate::core::server::config::parse_limits).
+    Custom(String)
+}
+
+impl Filter {
+    /// Every filter the server knows of, but those registered in its config.
+    pub const ALL: [Filter; 11] = [
+        Filter::Nop,
+        Filter::Bcompress,
+        Filter::Bdecompress,
+        Filter::Gcompress,
+        Filter::Gdecompress,
+        Filter::Encrypt,
+        Filter::Decrypt,
+        Filter::Zcompress,
+        Filter::Zdecompress,
+        Filter::Xcompress,
+        Filter::Xdecompress,
+    ];
 }
 
 impl Display for Filter {
@@ -28,6 +57,11 @@ impl Display for Filter {
             Filter::Gdecompress => write!(f, "gdecompress"),
             Filter::Encrypt => write!(f, "encrypt"),
             Filter::Decrypt => write!(f, "decrypt"),
+            Filter::Zcompress => write!(f, "zcompress"),
+            Filter::Zdecompress => write!(f, "zdecompress"),
+            Filter::Xcompress => write!(f, "xcompress"),
+            Filter::Xdecompress => write!(f, "xdecompress"),
+            Filter::Custom(name) => write!(f, "{name}"),
         }
     }
 }
@@ -36,6 +70,26 @@ impl Display for Filter {
 #[derive(Debug, PartialEq, Eq)]
 pub struct FilterParseError(pub String);
 
+impl Display for FilterParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "invalid filter name {:?}", self.0)
+    }
+}
+
+impl From<Filter> for String {
+    fn from(filter: Filter) -> Self {
+        filter.to_string()
+    }
+}
+
+impl TryFrom<String> for Filter {
+    type Error = FilterParseError;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
 impl FromStr for Filter {
     type Err = FilterParseError;
 
@@ -48,6 +102,14 @@ impl FromStr for Filter {
             "gdecompress" => Filter::Gdecompress,
             "encrypt"     => Filter::Encrypt,
             "decrypt"     => Filter::Decrypt,
+            "zcompress"   => Filter::Zcompress,
+            "zdecompress" => Filter::Zdecompress,
+            "xcompress"   => Filter::Xcompress,
+            "xdecompress" => Filter::Xdecompress,
+            // Names which are a single word, and safe in paths, as that of its executable.
+            s if s.starts_with(|c: char| c.is_ascii_alphabetic()) &&
+                s.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+                          => Filter::Custom(s.to_string()),
             s             => return Err(FilterParseError(s.to_string()))
         };
 
@@ -0,0 +1,123 @@
+use std::io::{self, Read, Write};
+
+/// Largest frame sent or accepted over a stream socket.
+pub const MAX_FRAME_LEN: usize = 64 * 1024;
+
+/// Write `bytes` to `writer` as a single frame: its length, as a little-endian `u32`,
+/// followed by the bytes themselves.
+pub fn write_frame(mut writer: impl Write, bytes: &[u8]) -> io::Result<()> {
+    if bytes.len() > MAX_FRAME_LEN {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "frame too long"))
+    }
+    writer.write_all(&(bytes.len() as u32).to_le_bytes())?;
+    writer.write_all(bytes)
+}
+
+/// Read a single frame written by [`write_frame`] from `reader`.
+pub fn read_frame(mut reader: impl Read) -> io::Result<Vec<u8>> {
+    let mut len = [0; 4];
+    reader.read_exact(&mut len)?;
+    let len = u32::from_le_bytes(len) as usize;
+    if len > MAX_FRAME_LEN {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "frame too long"))
+    }
+
+    let mut frame = vec![0; len];
+    reader.read_exact(&mut frame)?;
+    Ok(frame)
+}
+
+/// Take the first frame written by [`write_frame`] off the front of `bytes`, as read so far
+/// from a stream that isn't waited on, if it was read whole.
+pub fn take_frame(bytes: &mut Vec<u8>) -> io::Result<Option<Vec<u8>>> {
+    let Some(len) = bytes.first_chunk::<4>() else {
+        return Ok(None)
+    };
+    let len = u32::from_le_bytes(*len) as usize;
+    if len > MAX_FRAME_LEN {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "frame too long"))
+    }
+    if bytes.len() < 4 + len {
+        return Ok(None)
+    }
+
+    let frame = bytes[4..4 + len].to_vec();
+    bytes.drain(..4 + len);
+    Ok(Some(frame))
+}
+
+/// Send everything read from `input` to `writer`, in frames, followed by an empty frame
+/// marking its end.
+///
+/// Returns the number of bytes sent, excluding the frames' lengths.
+pub fn send_file(mut input: impl Read, mut writer: impl Write) -> io::Result<u64> {
+    let mut buf = vec![0; MAX_FRAME_LEN];
+    let mut sent = 0;
+    loop {
+        let n = match input.read(&mut buf) {
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+            res => res?,
+        };
+        write_frame(&mut writer, &buf[..n])?;
+        if n == 0 {
+            writer.flush()?;
+            return Ok(sent)
+        }
+        sent += n as u64;
+    }
+}
+
+/// Receive frames sent by [`send_file`] from `reader`, writing them to `output` until the
+/// empty frame marking their end.
+///
+/// Returns the number of bytes received. The stream ending before the empty frame is an
+/// [`io::ErrorKind::UnexpectedEof`] error: the sender did not get to send everything.
+pub fn receive_file(mut reader: impl Read, mut output: impl Write) -> io::Result<u64> {
+    let mut received = 0;
+    loop {
+        let frame = read_frame(&mut reader)?;
+        if frame.is_empty() {
+            output.flush()?;
+            return Ok(received)
+        }
+        output.write_all(&frame)?;
+        received += frame.len() as u64;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn files_round_trip() {
+        let input = (0..3 * MAX_FRAME_LEN + 7).map(|i| i as u8).collect::<Vec<_>>();
+
+        let mut frames = Vec::new();
+        assert_eq!(send_file(input.as_slice(), &mut frames).unwrap(), input.len() as u64);
+
+        let mut output = Vec::new();
+        assert_eq!(receive_file(frames.as_slice(), &mut output).unwrap(), input.len() as u64);
+        assert_eq!(output, input);
+
+        // A transfer cut short is an error, rather than a shorter file.
+        let err = receive_file(&frames[..frames.len() - 4], Vec::new()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn frames_are_taken_once_whole() {
+        let mut written = Vec::new();
+        write_frame(&mut written, b"first").unwrap();
+        write_frame(&mut written, b"").unwrap();
+
+        let mut bytes = written[..6].to_vec();
+        assert_eq!(take_frame(&mut bytes).unwrap(), None);
+        bytes.extend_from_slice(&written[6..]);
+        assert_eq!(take_frame(&mut bytes).unwrap().as_deref(), Some(b"first".as_slice()));
+        assert_eq!((take_frame(&mut bytes).unwrap(), bytes.len()), (Some(Vec::new()), 0));
+
+        let mut bytes = ((MAX_FRAME_LEN + 1) as u32).to_le_bytes().to_vec();
+        assert_eq!(take_frame(&mut bytes).unwrap_err().kind(), io::ErrorKind::InvalidData);
+    }
+}
@@ -0,0 +1,476 @@
+//! A FUSE filesystem, mounted by `sdstore-fuse`, through which files are read transformed by
+//! the server, for tools which only understand files, see [`FuseMount`].
+//!
+//! Its root holds a directory for each of the server's filters, and any pipeline of them may
+//! be looked up by name, as their names separated by commas. Each mirrors a source directory,
+//! read-only: opening `gcompress,encrypt/foo` submits a task running the pipeline on the
+//! source's `foo`, and reads what the task wrote once it concluded. Tasks are run as any
+//! other, and so their outputs are cached by the server, if it has a result cache.
+//!
+//! The mount speaks the kernel's protocol over `/dev/fuse` itself, see [`abi`], and so needs
+//! the privilege to mount filesystems, there being no `fusermount` to mount it otherwise.
+
+pub mod abi;
+
+use std::{
+    collections::HashMap,
+    ffi::{CString, OsStr},
+    fs,
+    io::{self, Read, Write},
+    os::unix::{ffi::OsStrExt, fs::{FileExt, MetadataExt, OpenOptionsExt}, io::AsRawFd},
+    path::{Path, PathBuf},
+    sync::{mpsc::{self, Receiver, Sender, TryRecvError}, Arc, Mutex},
+    thread,
+    time::{Duration, SystemTime},
+};
+
+use clap::Parser;
+use uuid::Uuid;
+
+use crate::{client_api::SdstoreClient, core::{client_task::ClientTask, filter::Filter, messaging::MessageToClient}};
+
+use abi::{Attr, Fields, InHeader, ReadIn, Reply};
+
+/// How long the kernel may cache the mount's names and attributes for, before asking again.
+const TTL: Duration = Duration::from_secs(1);
+
+/// How long the thread following tasks waits for the server's messages at a time, before
+/// submitting those opened since.
+const TASK_POLL: Duration = Duration::from_millis(50);
+
+/// Mount a filesystem through which files are read transformed by the `sdstored` server:
+/// reading `<mountpoint>/gcompress,encrypt/foo` runs `gcompress` then `encrypt` on the source
+/// directory's `foo`. Unmounted on `SIGINT` or `SIGTERM`.
+#[derive(Debug, Parser)]
+#[command(name = "sdstore-fuse", version)]
+pub struct FuseCli {
+    /// Directory to mount the filesystem on.
+    pub mountpoint: PathBuf,
+    /// Directory whose files are read transformed. Defaults to the working directory.
+    #[arg(long, value_name = "DIR")]
+    pub source: Option<PathBuf>,
+    /// Directory the tasks write their outputs to, while they're open, which the server must be
+    /// able to write. Defaults to one in the temporary directory, removed once unmounted.
+    #[arg(long, value_name = "DIR")]
+    pub output_dir: Option<PathBuf>,
+    /// Priority tasks are submitted with.
+    #[arg(long, default_value_t = 0)]
+    pub priority: usize,
+    /// Directory of the server's sockets, which must match the server's. Defaults to
+    /// `$SDSTORE_SOCK_DIR`, if set, or else to `sdstore` in `$XDG_RUNTIME_DIR`, or else to
+    /// `/run/sdstore`.
+    #[arg(long, value_name = "DIR")]
+    pub socket_dir: Option<PathBuf>,
+}
+
+/// A node of the mount, see [`FuseMount`].
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct Node {
+    /// Pipeline of the node's directory, or `None` for the root.
+    pipeline: Option<Vec<Filter>>,
+    /// Path of the node in the source directory, relative to it.
+    path: PathBuf,
+}
+
+/// The mount's nodes, numbered for as long as it lasts, as the kernel looks them up.
+#[derive(Debug)]
+struct Nodes {
+    by_id: HashMap<u64, Node>,
+    ids: HashMap<Node, u64>,
+}
+
+impl Nodes {
+    fn new() -> Self {
+        let root = Node { pipeline: None, path: PathBuf::new() };
+        Nodes { by_id: HashMap::from([(abi::ROOT_ID, root.clone())]), ids: HashMap::from([(root, abi::ROOT_ID)]) }
+    }
+
+    /// The ID of `node`, numbering it if it's new.
+    fn id(&mut self, node: Node) -> u64 {
+        if let Some(id) = self.ids.get(&node) {
+            return *id
+        }
+        let id = abi::ROOT_ID + self.by_id.len() as u64;
+        self.by_id.insert(id, node.clone());
+        self.ids.insert(node, id);
+        id
+    }
+}
+
+/// A file being read, the output of the task run on it.
+struct OpenFile {
+    output: PathBuf,
+    file: fs::File,
+}
+
+/// A file being opened, whose task is yet to conclude.
+struct Opening {
+    /// ID of the `open` request, replied to once the task concludes.
+    unique: u64,
+    fh: u64,
+    ino: u64,
+    /// Modification time of the source file when the task was submitted.
+    mtime: SystemTime,
+    output: PathBuf,
+}
+
+/// State shared with the thread following the tasks of opened files, see [`follow_tasks`].
+struct Shared {
+    dev: fs::File,
+    open: Mutex<HashMap<u64, OpenFile>>,
+    /// Size of the last output of each file, with the modification time of its source then,
+    /// so that it's only told while the source is unchanged.
+    sizes: Mutex<HashMap<u64, (SystemTime, u64)>>,
+}
+
+impl Shared {
+    /// Write `reply` to the kernel, which fails if the request was interrupted.
+    fn reply(&self, reply: Reply) -> io::Result<()> {
+        (&self.dev).write_all(&reply.finish())
+    }
+}
+
+/// A mounted FUSE filesystem, through which the files of its source directory are read
+/// transformed by the server, see [the module's documentation](self).
+///
+/// Requests are handled one at a time by [`FuseMount::serve`], but for opens, replied to once
+/// their tasks conclude by a thread of their own, so that files are transformed at once.
+pub struct FuseMount {
+    mountpoint: PathBuf,
+    source: PathBuf,
+    output_dir: PathBuf,
+    priority: usize,
+    /// Filters the server has, whose directories are in the root.
+    filters: Vec<Filter>,
+    nodes: Nodes,
+    next_fh: u64,
+    shared: Arc<Shared>,
+    tasks: Sender<(ClientTask, Opening)>,
+    /// UID and GID of the mount's own directories.
+    owner: (u32, u32),
+}
+
+impl FuseMount {
+    /// Mount the filesystem on `cli.mountpoint`, through which files are transformed by the
+    /// server `client` is connected to.
+    pub fn mount(cli: &FuseCli, mut client: SdstoreClient) -> io::Result<Self> {
+        let filters = client
+            .status()
+            .map_err(|err| io::Error::other(format!("could not ask for the server's status: {err:?}")))?
+            .filters
+            .into_iter()
+            .map(|usage| usage.filter)
+            .collect();
+        // The server opens the tasks' files relative to its own working directory.
+        let source = fs::canonicalize(cli.source.as_deref().unwrap_or(Path::new(".")))?;
+        let output_dir = match &cli.output_dir {
+            Some(dir) => dir.clone(),
+            None => std::env::temp_dir().join(format!("sdstore_fuse_{}", std::process::id())),
+        };
+        fs::create_dir_all(&output_dir)?;
+        let output_dir = fs::canonicalize(output_dir)?;
+
+        let dev = fs::OpenOptions::new().read(true).write(true).custom_flags(libc::O_CLOEXEC).open("/dev/fuse")?;
+        // SAFETY: neither call has any memory safety requirement.
+        let owner = unsafe { (libc::getuid(), libc::getgid()) };
+        let options = format!(
+            "fd={},rootmode={:o},user_id={},group_id={},default_permissions,allow_other",
+            dev.as_raw_fd(), libc::S_IFDIR, owner.0, owner.1
+        );
+        let mountpoint = CString::new(cli.mountpoint.as_os_str().as_bytes())?;
+        let options = CString::new(options)?;
+        // SAFETY: every string is nul-terminated, and outlives the call.
+        let mounted = unsafe {
+            libc::mount(
+                c"sdstore".as_ptr(), mountpoint.as_ptr(), c"fuse.sdstore".as_ptr(),
+                libc::MS_NOSUID | libc::MS_NODEV, options.as_ptr().cast(),
+            )
+        };
+        if mounted != 0 {
+            return Err(io::Error::last_os_error())
+        }
+
+        let shared = Arc::new(Shared { dev, open: Mutex::new(HashMap::new()), sizes: Mutex::new(HashMap::new()) });
+        let (tasks, submitted) = mpsc::channel();
+        let following = Arc::clone(&shared);
+        thread::spawn(move || follow_tasks(&mut client, &following, &submitted));
+
+        Ok(FuseMount {
+            mountpoint: cli.mountpoint.clone(),
+            source,
+            output_dir,
+            priority: cli.priority,
+            filters,
+            nodes: Nodes::new(),
+            next_fh: 1,
+            shared,
+            tasks,
+            owner,
+        })
+    }
+
+    /// Handle the kernel's requests until the filesystem is unmounted, see [`unmount`].
+    pub fn serve(&mut self) -> io::Result<()> {
+        let mut buf = vec![0; abi::MAX_REQUEST_SIZE];
+        loop {
+            let len = match (&self.shared.dev).read(&mut buf) {
+                Ok(len) => len,
+                // The request was interrupted before it was read.
+                Err(err) if matches!(err.raw_os_error(), Some(libc::ENOENT | libc::EINTR)) => continue,
+                Err(err) if err.raw_os_error() == Some(libc::ENODEV) => return Ok(()),
+                Err(err) => return Err(err),
+            };
+            let Some(header) = InHeader::read(&buf[..len]) else { continue };
+            let args = &buf[InHeader::SIZE..len];
+            let reply = match header.opcode {
+                abi::FUSE_INIT => Some(Reply::init(header.unique, args)),
+                abi::FUSE_DESTROY => {
+                    let _ = self.shared.reply(Reply::ok(header.unique));
+                    return Ok(())
+                },
+                // Nodes are kept for as long as the mount lasts, and requests run to completion.
+                abi::FUSE_FORGET | abi::FUSE_BATCH_FORGET | abi::FUSE_INTERRUPT => None,
+                opcode => self.handle(opcode, header, args).unwrap_or_else(|errno| Some(Reply::error(header.unique, errno))),
+            };
+            if let Some(reply) = reply {
+                // The request was interrupted, and its reply is of no use.
+                if let Err(err) = self.shared.reply(reply) {
+                    log::debug!("Could not reply to request {}. Error: {:?}", header.unique, err);
+                }
+            }
+        }
+    }
+
+    /// Handle request `opcode`, returning its reply, if it's replied to at once, or the errno
+    /// it fails with.
+    fn handle(&mut self, opcode: u32, header: InHeader, args: &[u8]) -> Result<Option<Reply>, i32> {
+        let unique = header.unique;
+        let node = self.nodes.by_id.get(&header.nodeid).cloned().ok_or(libc::ENOENT)?;
+        match opcode {
+            abi::FUSE_LOOKUP => {
+                let child = self.child(&node, OsStr::from_bytes(Fields(args).name()))?;
+                let attr = self.attr(child)?;
+                Ok(Some(Reply::entry(unique, &attr, TTL)))
+            },
+            abi::FUSE_GETATTR => Ok(Some(Reply::attr(unique, &self.attr(node)?, TTL))),
+            abi::FUSE_OPENDIR => match self.attr(node)?.mode & libc::S_IFMT {
+                libc::S_IFDIR => Ok(Some(Reply::open(unique, 0, 0))),
+                _ => Err(libc::ENOTDIR),
+            },
+            abi::FUSE_READDIR => {
+                let read = ReadIn::read(args).ok_or(libc::EINVAL)?;
+                self.readdir(unique, header.nodeid, node, read).map(Some)
+            },
+            abi::FUSE_OPEN => {
+                let flags = Fields(args).u32() as i32;
+                self.open(unique, header.nodeid, node, flags).map(|_| None)
+            },
+            abi::FUSE_READ => {
+                let read = ReadIn::read(args).ok_or(libc::EINVAL)?;
+                let open = self.shared.open.lock().unwrap();
+                let file = &open.get(&read.fh).ok_or(libc::EBADF)?.file;
+                let mut data = vec![0; read.size as usize];
+                let len = file.read_at(&mut data, read.offset).map_err(|err| err.raw_os_error().unwrap_or(libc::EIO))?;
+                let mut reply = Reply::ok(unique);
+                reply.bytes(&data[..len]);
+                Ok(Some(reply))
+            },
+            abi::FUSE_RELEASE => {
+                let fh = Fields(args).u64();
+                if let Some(open) = self.shared.open.lock().unwrap().remove(&fh) {
+                    let _ = fs::remove_file(open.output);
+                }
+                Ok(Some(Reply::ok(unique)))
+            },
+            abi::FUSE_RELEASEDIR | abi::FUSE_FLUSH => Ok(Some(Reply::ok(unique))),
+            abi::FUSE_STATFS => Ok(Some(Reply::statfs(unique))),
+            _ => Err(libc::ENOSYS),
+        }
+    }
+
+    /// The child `name` of directory `node`: a pipeline of the server's filters, in the root,
+    /// or else the source's file at the same path.
+    fn child(&self, node: &Node, name: &OsStr) -> Result<Node, i32> {
+        match &node.pipeline {
+            None => {
+                let pipeline = name.to_str().ok_or(libc::ENOENT)?.split(',')
+                    .map(|filter| filter.parse().ok().filter(|filter| self.filters.contains(filter)))
+                    .collect::<Option<Vec<_>>>()
+                    .ok_or(libc::ENOENT)?;
+                Ok(Node { pipeline: Some(pipeline), path: PathBuf::new() })
+            },
+            Some(pipeline) => Ok(Node { pipeline: Some(pipeline.clone()), path: node.path.join(name) }),
+        }
+    }
+
+    /// Attributes of `node`, numbering it if it's new: those of its source, made read-only,
+    /// but for the mount's own directories.
+    fn attr(&mut self, node: Node) -> Result<Attr, i32> {
+        let (uid, gid) = self.owner;
+        let own = Attr { ino: 0, size: 0, mtime: SystemTime::UNIX_EPOCH, mode: libc::S_IFDIR | 0o555, uid, gid };
+        let attr = match node.pipeline {
+            Some(_) if !node.path.as_os_str().is_empty() => {
+                let metadata = fs::metadata(self.source.join(&node.path)).map_err(|err| err.raw_os_error().unwrap_or(libc::EIO))?;
+                let mode = match metadata.is_dir() {
+                    true => libc::S_IFDIR,
+                    false if metadata.is_file() => libc::S_IFREG,
+                    false => return Err(libc::ENOENT),
+                };
+                let mtime = metadata.modified().unwrap_or(SystemTime::UNIX_EPOCH);
+                Attr { size: 0, mtime, mode: mode | (metadata.mode() & 0o555), uid: metadata.uid(), gid: metadata.gid(), ..own }
+            },
+            _ => own,
+        };
+        let ino = self.nodes.id(node);
+        // Files' sizes are only known once they were read, and while their sources are unchanged.
+        let size = match self.shared.sizes.lock().unwrap().get(&ino) {
+            Some((mtime, size)) if attr.mode & libc::S_IFMT == libc::S_IFREG && *mtime == attr.mtime => *size,
+            _ => 0,
+        };
+        Ok(Attr { ino, size, ..attr })
+    }
+
+    /// Reply to a read of directory `node`, from the entry at `read.offset`.
+    fn readdir(&mut self, unique: u64, ino: u64, node: Node, read: ReadIn) -> Result<Reply, i32> {
+        let names = match &node.pipeline {
+            None => self.filters.iter().map(|filter| filter.to_string().into()).collect(),
+            Some(_) => {
+                let entries = fs::read_dir(self.source.join(&node.path)).map_err(|err| err.raw_os_error().unwrap_or(libc::EIO))?;
+                let mut names = entries.filter_map(|entry| Some(entry.ok()?.file_name())).collect::<Vec<_>>();
+                names.sort();
+                names
+            },
+        };
+        let mut reply = Reply::ok(unique);
+        let dots = [(ino, libc::DT_DIR as u32, OsStr::new(".")), (ino, libc::DT_DIR as u32, OsStr::new(".."))];
+        let mut entries = dots.into_iter().map(Ok::<_, i32>).chain(names.iter().map(|name| {
+            let child = self.child(&node, name)?;
+            Ok((self.nodes.id(child.clone()), self.attr(child)?.kind(), name.as_os_str()))
+        }));
+        let mut offset = 0;
+        for entry in entries.by_ref() {
+            offset += 1;
+            if offset <= read.offset {
+                continue
+            }
+            // Entries removed since they were listed are skipped.
+            let Ok((ino, kind, name)) = entry else { continue };
+            if !reply.dirent(read.size, ino, offset, kind, name.as_bytes()) {
+                break
+            }
+        }
+        Ok(reply)
+    }
+
+    /// Submit a task running the pipeline of file `node` on its source, which the `open`
+    /// request `unique` is replied to once it concludes.
+    fn open(&mut self, unique: u64, ino: u64, node: Node, flags: i32) -> Result<(), i32> {
+        if flags & libc::O_ACCMODE != libc::O_RDONLY {
+            return Err(libc::EACCES)
+        }
+        let attr = self.attr(node.clone())?;
+        let (Some(pipeline), libc::S_IFREG) = (node.pipeline, attr.mode & libc::S_IFMT) else { return Err(libc::EISDIR) };
+        let fh = self.next_fh;
+        self.next_fh += 1;
+        let output = self.output_dir.join(format!("{fh}"));
+        let task = ClientTask::new(0, self.priority, self.source.join(&node.path), output.clone(), pipeline);
+        let opening = Opening { unique, fh, ino, mtime: attr.mtime, output };
+        self.tasks.send((task, opening)).map_err(|_| libc::EIO)
+    }
+}
+
+impl Drop for FuseMount {
+    fn drop(&mut self) {
+        let _ = unmount(&self.mountpoint);
+        if let Ok(entries) = fs::read_dir(&self.output_dir) {
+            entries.flatten().for_each(|entry| { let _ = fs::remove_file(entry.path()); });
+        }
+        let _ = fs::remove_dir(&self.output_dir);
+    }
+}
+
+/// Unmount the filesystem mounted on `mountpoint`, lazily, so that it's gone once the files
+/// open on it are closed, after which [`FuseMount::serve`] returns.
+pub fn unmount(mountpoint: &Path) -> io::Result<()> {
+    let mountpoint = CString::new(mountpoint.as_os_str().as_bytes())?;
+    // SAFETY: the path is nul-terminated, and outlives the call.
+    match unsafe { libc::umount2(mountpoint.as_ptr(), libc::MNT_DETACH) } {
+        0 => Ok(()),
+        _ => Err(io::Error::last_os_error()),
+    }
+}
+
+/// Submit the tasks of the files opened, replying to their `open` requests as they conclude,
+/// until the mount is dropped.
+fn follow_tasks(client: &mut SdstoreClient, shared: &Shared, submitted: &Receiver<(ClientTask, Opening)>) {
+    let mut pending = HashMap::<Uuid, Opening>::new();
+    loop {
+        // Nothing is waited for while no file is being opened.
+        let received = match pending.is_empty() {
+            true => submitted.recv().map_err(|_| TryRecvError::Disconnected),
+            false => submitted.try_recv(),
+        };
+        match received {
+            Ok((task, opening)) => match client.submit(task) {
+                Ok(handle) => {
+                    pending.insert(handle.request_id(), opening);
+                },
+                Err(err) => {
+                    log::error!("Could not submit the task of {:?}. Error: {:?}", opening.output, err);
+                    let _ = shared.reply(Reply::error(opening.unique, libc::EIO));
+                },
+            },
+            Err(TryRecvError::Disconnected) => return,
+            Err(TryRecvError::Empty) => (),
+        }
+
+        let (handle, msg) = match client.next(TASK_POLL) {
+            Ok(Some(received)) => received,
+            Ok(None) => continue,
+            Err(err) => {
+                log::error!("Lost the server. Error: {:?}", err);
+                pending.drain().for_each(|(_, opening)| { let _ = shared.reply(Reply::error(opening.unique, libc::EIO)); });
+                continue
+            },
+        };
+        // Suspended tasks are restarted, once the server is.
+        if !msg.is_last() || matches!(msg, MessageToClient::Suspended) {
+            continue
+        }
+        let Some(opening) = pending.remove(&handle.request_id()) else { continue };
+        let reply = match msg {
+            MessageToClient::Concluded(summary) => match fs::File::open(&opening.output) {
+                Ok(file) => {
+                    shared.sizes.lock().unwrap().insert(opening.ino, (opening.mtime, summary.bytes_out));
+                    shared.open.lock().unwrap().insert(opening.fh, OpenFile { output: opening.output.clone(), file });
+                    Reply::open(opening.unique, opening.fh, abi::FOPEN_DIRECT_IO)
+                },
+                Err(err) => Reply::error(opening.unique, err.raw_os_error().unwrap_or(libc::EIO)),
+            },
+            msg => {
+                log::warn!("The task of {:?} failed: {msg}", opening.output);
+                Reply::error(opening.unique, libc::EIO)
+            },
+        };
+        // The file won't be released if its opening was interrupted.
+        if shared.reply(reply).is_err() {
+            shared.open.lock().unwrap().remove(&opening.fh);
+            let _ = fs::remove_file(&opening.output);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn nodes_are_numbered_once() {
+        let mut nodes = Nodes::new();
+        let node = |path: &str| Node { pipeline: Some(vec![Filter::Gcompress]), path: path.into() };
+        let foo = nodes.id(node("foo"));
+        assert_eq!((foo, nodes.id(node("bar")), nodes.id(node("foo"))), (2, 3, 2));
+        assert_eq!(nodes.by_id[&foo], node("foo"));
+        assert_eq!(nodes.id(Node { pipeline: None, path: PathBuf::new() }), abi::ROOT_ID);
+    }
+}
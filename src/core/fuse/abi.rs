@@ -0,0 +1,266 @@
+//! The parts of the kernel's FUSE protocol the mount speaks, as of `linux/fuse.h`: the
+//! requests it reads from `/dev/fuse`, and the replies it writes back.
+//!
+//! Structures are read and written field by field, in native byte order, as the kernel lays
+//! them out, rather than transmuted.
+
+use std::time::{Duration, SystemTime};
+
+/// Version of the protocol the mount speaks, which the kernel falls back to if newer.
+pub const KERNEL_VERSION: u32 = 7;
+pub const KERNEL_MINOR_VERSION: u32 = 31;
+
+/// Node ID of the mount's root directory.
+pub const ROOT_ID: u64 = 1;
+
+pub const FUSE_LOOKUP: u32 = 1;
+pub const FUSE_FORGET: u32 = 2;
+pub const FUSE_GETATTR: u32 = 3;
+pub const FUSE_OPEN: u32 = 14;
+pub const FUSE_READ: u32 = 15;
+pub const FUSE_STATFS: u32 = 17;
+pub const FUSE_RELEASE: u32 = 18;
+pub const FUSE_FLUSH: u32 = 25;
+pub const FUSE_INIT: u32 = 26;
+pub const FUSE_OPENDIR: u32 = 27;
+pub const FUSE_READDIR: u32 = 28;
+pub const FUSE_RELEASEDIR: u32 = 29;
+pub const FUSE_INTERRUPT: u32 = 36;
+pub const FUSE_DESTROY: u32 = 38;
+pub const FUSE_BATCH_FORGET: u32 = 42;
+
+/// Flag of an opened file whose reads bypass the page cache, and so aren't cut short by a
+/// size the kernel was told before its contents were known.
+pub const FOPEN_DIRECT_IO: u32 = 1 << 0;
+
+/// Bytes of the largest request read from `/dev/fuse`, which are reads, and far smaller than
+/// this.
+pub const MAX_REQUEST_SIZE: usize = 1 << 20;
+
+/// Bytes of the largest write the kernel may send, which the mount never takes, though it
+/// must give some.
+const MAX_WRITE: u32 = 4096;
+
+/// Header of every request, see [`InHeader::read`].
+#[derive(Debug, Clone, Copy)]
+pub struct InHeader {
+    pub opcode: u32,
+    /// ID of the request, which its reply is sent with.
+    pub unique: u64,
+    /// Node the request is about.
+    pub nodeid: u64,
+}
+
+impl InHeader {
+    /// Bytes of the header, which the request's arguments follow.
+    pub const SIZE: usize = 40;
+
+    pub fn read(request: &[u8]) -> Option<Self> {
+        let mut fields = Fields(request.get(..Self::SIZE)?);
+        let _len = fields.u32();
+        Some(InHeader { opcode: fields.u32(), unique: fields.u64(), nodeid: fields.u64() })
+    }
+}
+
+/// Reads the fields of a request's arguments in order, which must be long enough, as the
+/// kernel's are.
+pub struct Fields<'a>(pub &'a [u8]);
+
+impl Fields<'_> {
+    pub fn u32(&mut self) -> u32 {
+        let (field, rest) = self.0.split_at(4);
+        self.0 = rest;
+        u32::from_ne_bytes(field.try_into().unwrap())
+    }
+
+    pub fn u64(&mut self) -> u64 {
+        let (field, rest) = self.0.split_at(8);
+        self.0 = rest;
+        u64::from_ne_bytes(field.try_into().unwrap())
+    }
+
+    /// A name, ending with a nul byte.
+    pub fn name(&mut self) -> &[u8] {
+        let name = self.0.split(|b| *b == 0).next().unwrap_or_default();
+        self.0 = &self.0[(name.len() + 1).min(self.0.len())..];
+        name
+    }
+}
+
+/// Arguments of a read, of a file or of a directory.
+#[derive(Debug, Clone, Copy)]
+pub struct ReadIn {
+    pub fh: u64,
+    pub offset: u64,
+    pub size: u32,
+}
+
+impl ReadIn {
+    pub fn read(args: &[u8]) -> Option<Self> {
+        let mut fields = Fields(args.get(..24)?);
+        Some(ReadIn { fh: fields.u64(), offset: fields.u64(), size: fields.u32() })
+    }
+}
+
+/// A reply, which its fields are appended to, as the kernel lays them out, see [`Reply::ok`].
+pub struct Reply(Vec<u8>);
+
+impl Reply {
+    /// Bytes of the header of every reply.
+    const HEADER_SIZE: usize = 16;
+
+    /// A successful reply to request `unique`.
+    pub fn ok(unique: u64) -> Self {
+        Self::with_error(unique, 0)
+    }
+
+    /// A reply to request `unique` failing with `errno`.
+    pub fn error(unique: u64, errno: i32) -> Self {
+        Self::with_error(unique, -errno)
+    }
+
+    fn with_error(unique: u64, error: i32) -> Self {
+        let mut reply = Reply(Vec::with_capacity(Self::HEADER_SIZE));
+        reply.u32(0).u32(error as u32).u64(unique);
+        reply
+    }
+
+    pub fn u32(&mut self, field: u32) -> &mut Self {
+        self.0.extend_from_slice(&field.to_ne_bytes());
+        self
+    }
+
+    pub fn u64(&mut self, field: u64) -> &mut Self {
+        self.0.extend_from_slice(&field.to_ne_bytes());
+        self
+    }
+
+    pub fn bytes(&mut self, bytes: &[u8]) -> &mut Self {
+        self.0.extend_from_slice(bytes);
+        self
+    }
+
+    /// A reply to `init`, agreeing to the kernel's version if it isn't older than 7, or else
+    /// asking for it, and to none of its optional features.
+    pub fn init(unique: u64, args: &[u8]) -> Self {
+        let mut fields = Fields(args);
+        let (major, _minor, max_readahead) = (fields.u32(), fields.u32(), fields.u32());
+        let mut reply = Reply::ok(unique);
+        reply.u32(KERNEL_VERSION).u32(KERNEL_MINOR_VERSION);
+        if major > KERNEL_VERSION {
+            return reply
+        }
+        // No flags, the default limit of background requests, and time granularity.
+        reply.u32(max_readahead).u32(0).u32(0).u32(MAX_WRITE).u32(0);
+        // Max pages, map alignment, flags2, max stack depth and unused fields.
+        reply.bytes(&[0; 36]);
+        reply
+    }
+
+    /// A reply to `lookup`, with the node's ID and attributes, valid for `ttl`.
+    pub fn entry(unique: u64, attr: &Attr, ttl: Duration) -> Self {
+        let mut reply = Reply::ok(unique);
+        reply.u64(attr.ino).u64(0).u64(ttl.as_secs()).u64(ttl.as_secs());
+        reply.u32(ttl.subsec_nanos()).u32(ttl.subsec_nanos());
+        attr.append(&mut reply);
+        reply
+    }
+
+    /// A reply to `getattr`, with the node's attributes, valid for `ttl`.
+    pub fn attr(unique: u64, attr: &Attr, ttl: Duration) -> Self {
+        let mut reply = Reply::ok(unique);
+        reply.u64(ttl.as_secs()).u32(ttl.subsec_nanos()).u32(0);
+        attr.append(&mut reply);
+        reply
+    }
+
+    /// A reply to `open` or `opendir`, with the handle of the opened file.
+    pub fn open(unique: u64, fh: u64, open_flags: u32) -> Self {
+        let mut reply = Reply::ok(unique);
+        reply.u64(fh).u32(open_flags).u32(0);
+        reply
+    }
+
+    /// A reply to `statfs`, of a filesystem with no space, whose names are at most 255 bytes.
+    pub fn statfs(unique: u64) -> Self {
+        let mut reply = Reply::ok(unique);
+        // Blocks, free blocks, available blocks, files and free files.
+        reply.u64(0).u64(0).u64(0).u64(0).u64(0);
+        // Block size, max name length, fragment size, padding and spare fields.
+        reply.u32(4096).u32(255).u32(4096).u32(0).bytes(&[0; 24]);
+        reply
+    }
+
+    /// Append an entry of a directory, at `offset`, to a reply to `readdir`, unless it
+    /// would then be longer than `size` bytes, returning whether it was.
+    pub fn dirent(&mut self, size: u32, ino: u64, offset: u64, kind: u32, name: &[u8]) -> bool {
+        let len = (24 + name.len()).next_multiple_of(8);
+        if self.0.len() - Self::HEADER_SIZE + len > size as usize {
+            return false
+        }
+        self.u64(ino).u64(offset).u32(name.len() as u32).u32(kind).bytes(name);
+        self.0.resize(self.0.len().next_multiple_of(8), 0);
+        true
+    }
+
+    /// The reply's bytes, its length filled in.
+    pub fn finish(mut self) -> Vec<u8> {
+        let len = self.0.len() as u32;
+        self.0[..4].copy_from_slice(&len.to_ne_bytes());
+        self.0
+    }
+}
+
+/// Attributes of a node, as replied to `lookup` and `getattr`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Attr {
+    pub ino: u64,
+    pub size: u64,
+    pub mtime: SystemTime,
+    /// Type and permissions, as in `st_mode`.
+    pub mode: u32,
+    pub uid: u32,
+    pub gid: u32,
+}
+
+impl Attr {
+    /// Type of the node, as in a `dirent`'s `d_type`.
+    pub fn kind(&self) -> u32 {
+        (self.mode & libc::S_IFMT) >> 12
+    }
+
+    fn append(&self, reply: &mut Reply) {
+        let mtime = self.mtime.duration_since(SystemTime::UNIX_EPOCH).unwrap_or_default();
+        let nlink = if self.mode & libc::S_IFMT == libc::S_IFDIR { 2 } else { 1 };
+        reply.u64(self.ino).u64(self.size).u64(self.size.div_ceil(512));
+        // Access, modification and change times, in seconds then nanoseconds.
+        reply.u64(mtime.as_secs()).u64(mtime.as_secs()).u64(mtime.as_secs());
+        reply.u32(mtime.subsec_nanos()).u32(mtime.subsec_nanos()).u32(mtime.subsec_nanos());
+        // Block size, and flags, follow the device number.
+        reply.u32(self.mode).u32(nlink).u32(self.uid).u32(self.gid).u32(0).u32(4096).u32(0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn replies_are_laid_out_as_the_kernel_expects() {
+        let attr = Attr { ino: 2, size: 1000, mtime: SystemTime::UNIX_EPOCH, mode: libc::S_IFREG | 0o444, uid: 0, gid: 0 };
+        assert_eq!(Reply::entry(7, &attr, Duration::from_secs(1)).finish().len(), 16 + 128);
+        assert_eq!(Reply::attr(7, &attr, Duration::from_secs(1)).finish().len(), 16 + 104);
+        assert_eq!(Reply::statfs(7).finish().len(), 16 + 80);
+
+        let init = [7u32, 39, 1 << 17, 0].iter().flat_map(|field| field.to_ne_bytes()).collect::<Vec<_>>();
+        let reply = Reply::init(7, &init).finish();
+        assert_eq!(reply.len(), 16 + 64);
+        assert_eq!(&reply[..8], [80u32, 0].iter().flat_map(|field| field.to_ne_bytes()).collect::<Vec<_>>());
+
+        let mut readdir = Reply::ok(7);
+        assert!(readdir.dirent(64, 2, 1, attr.kind(), b"foo"));
+        assert!(!readdir.dirent(64, 3, 2, attr.kind(), b"a-name-too-long-for-what-is-left"));
+        let readdir = readdir.finish();
+        assert_eq!((readdir.len(), u32::from_ne_bytes(readdir[36..40].try_into().unwrap())), (16 + 32, libc::DT_REG as u32));
+    }
+}
@@ -0,0 +1,103 @@
+//! The server's health, sent to clients that ask for it with `./sdstore health`, as
+//! container probes and watchdog scripts do, see [`Health`].
+
+use std::{fmt::Display, time::Duration};
+
+use serde::{Serialize, Deserialize};
+
+/// Whether the server is alive, as its replying tells, and ready to take requests, as each
+/// of its checks tells, see [`Health::is_ready`].
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct Health {
+    pub server_version: String,
+    /// How long the server has been running for.
+    pub uptime: Duration,
+    /// Whether the server still reads from its sockets, for requests and for the inputs of
+    /// streamed ones.
+    pub listeners: bool,
+    /// Whether the server schedules new requests, which it stops doing as it shuts down.
+    pub accepting: bool,
+    /// Filters the server may run whose executables can't be run, e.g. because the
+    /// transformations directory isn't accessible, each with the path of its executable.
+    pub missing_executables: Vec<(String, String)>,
+    /// Requests pending across queues.
+    pub pending: usize,
+    /// Most requests that may be pending at once, if bounded, see
+    /// [`ServerConfig::queue_capacity`](super::server::config::ServerConfig::queue_capacity).
+    pub capacity: Option<usize>,
+}
+
+impl Health {
+    /// Whether no more requests may be queued.
+    pub fn is_full(&self) -> bool {
+        self.capacity.is_some_and(|capacity| self.pending >= capacity)
+    }
+
+    /// Whether the server would run a request sent now, its every check passing.
+    pub fn is_ready(&self) -> bool {
+        self.listeners && self.accepting && self.missing_executables.is_empty() && !self.is_full()
+    }
+}
+
+/// Formats the health as a line per check, followed by whether the server is ready, e.g.
+///
+/// ```text
+/// server version 0.1.0, up for 12.5s
+/// listeners: ok
+/// scheduler: accepting requests
+/// transformations: ok
+/// queues: 2 pending, of at most 100
+/// ready
+/// ```
+impl Display for Health {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "server version {}, up for {:.1}s", self.server_version, self.uptime.as_secs_f64())?;
+        writeln!(f, "listeners: {}", if self.listeners { "ok" } else { "down" })?;
+        writeln!(f, "scheduler: {}", if self.accepting { "accepting requests" } else { "shutting down" })?;
+        match self.missing_executables.as_slice() {
+            [] => writeln!(f, "transformations: ok")?,
+            missing => {
+                let missing = missing.iter().map(|(filter, path)| format!("{filter} ({path})")).collect::<Vec<_>>();
+                writeln!(f, "transformations: missing executables: {}", missing.join(", "))?
+            },
+        }
+        let full = if self.is_full() { "full, " } else { "" };
+        match self.capacity {
+            None => writeln!(f, "queues: {} pending", self.pending)?,
+            Some(capacity) => writeln!(f, "queues: {full}{} pending, of at most {capacity}", self.pending)?,
+        }
+        write!(f, "{}", if self.is_ready() { "ready" } else { "not ready" })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn readiness_follows_checks() {
+        let health = Health {
+            server_version: String::from("0.1.0"),
+            uptime: Duration::from_millis(12_500),
+            listeners: true,
+            accepting: true,
+            missing_executables: Vec::new(),
+            pending: 2,
+            capacity: Some(100),
+        };
+        assert!(health.is_ready());
+        assert_eq!(health.to_string(), "\
+server version 0.1.0, up for 12.5s
+listeners: ok
+scheduler: accepting requests
+transformations: ok
+queues: 2 pending, of at most 100
+ready");
+
+        let full = Health { pending: 100, ..health.clone() };
+        assert!(!full.is_ready() && full.to_string().contains("queues: full, 100 pending, of at most 100\n"));
+        let missing = Health { missing_executables: vec![(String::from("nop"), String::from("bin/nop"))], ..health.clone() };
+        assert!(missing.to_string().ends_with("transformations: missing executables: nop (bin/nop)\nqueues: 2 pending, of at most 100\nnot ready"));
+        assert!(!Health { accepting: false, ..health }.is_ready());
+    }
+}
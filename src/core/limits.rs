@@ -3,7 +3,7 @@ use std::ops::{Add, AddAssign, SubAssign, Deref, DerefMut};
 use super::filter::Filter;
 use super::server::config::FiltersConfig;
 
-#[derive(Debug, PartialEq, Eq, Clone, PartialOrd)]
+#[derive(Debug, Default, PartialEq, Eq, Clone)]
 pub struct RunningFilters(FiltersConfig);
 
 impl Deref for RunningFilters {
@@ -30,6 +30,18 @@ impl RunningFilters {
             Filter::Gdecompress => self.gdecompress = op(self.gdecompress),
             Filter::Encrypt     => self.encrypt = op(self.encrypt),
             Filter::Decrypt     => self.decrypt = op(self.decrypt),
+            Filter::Zcompress   => self.zcompress = op(self.zcompress),
+            Filter::Zdecompress => self.zdecompress = op(self.zdecompress),
+            Filter::Xcompress   => self.xcompress = op(self.xcompress),
+            Filter::Xdecompress => self.xdecompress = op(self.xdecompress),
+            Filter::Custom(name) => {
+                let count = self.custom.entry(name.clone()).or_default();
+                *count = op(*count);
+                // So that counts that went back to none compare equal to those never counted.
+                if *count == 0 {
+                    self.custom.remove(name);
+                }
+            },
         }
     }
 
@@ -37,12 +49,14 @@ impl RunningFilters {
         self.change_filter(filter, |x| x + 1)
     }
 
+    /// Count one less `filter` as running. A count that would go below zero, which means the
+    /// accounting drifted, e.g. as a task was counted out twice, stays at zero instead, see
+    /// [`ServerState::reconcile_filters`](super::server::state::ServerState::reconcile_filters).
     fn decrement_filter(&mut self, filter: &Filter) {
-        self.change_filter(filter, |x| x - 1)
-    }
-
-    pub fn default() -> Self {
-        Self(FiltersConfig::default())
+        self.change_filter(filter, |x| x.checked_sub(1).unwrap_or_else(|| {
+            log::error!("filter {filter} counted out while none were counted as running");
+            0
+        }))
     }
 
     /// This method checks whether a client's requests can be executed, given the currently
@@ -51,7 +65,7 @@ impl RunningFilters {
         &self,
         server_cfg: &FiltersConfig,
         client_req: &Vec<Filter>
-    ) -> bool { (self + client_req).0 <= *server_cfg }
+    ) -> bool { (self + client_req).fits_within(server_cfg) }
 }
 
 /// The [`Add`] instance for [`RunningFilters`] takes a reference
@@ -85,4 +99,19 @@ impl SubAssign<&Vec<Filter>> for RunningFilters {
             self.decrement_filter(filter);
         }
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counts_never_go_below_zero() {
+        let mut running = RunningFilters::default();
+        running += &vec![Filter::Nop, Filter::Custom(String::from("lz4"))];
+        running -= &vec![Filter::Nop, Filter::Nop, Filter::Custom(String::from("lz4"))];
+        assert_eq!(running, RunningFilters::default());
+
+        running -= &vec![Filter::Custom(String::from("zstd"))];
+        assert_eq!(running, RunningFilters::default());
+    }
+}
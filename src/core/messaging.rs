@@ -1,44 +1,1102 @@
-use std::fmt::Display;
+use std::{
+    collections::{BTreeMap, HashMap}, env, fmt::Display, io::{self, Read, Write}, mem, os::unix::net::UnixStream,
+    path::PathBuf, str::FromStr, sync::Arc, time::Duration,
+};
 
-use serde::{Serialize, Deserialize};
+use flate2::{read::ZlibDecoder, write::ZlibEncoder, Compression};
+use serde::{de::DeserializeOwned, Serialize, Deserialize};
+use uuid::Uuid;
 
 use super::{
-    client_task::{ClientTask, TaskParseError},
-    monitor::MonitorResult
+    accounting::Report,
+    client_task::ClientTask,
+    filter::Filter,
+    health::Health,
+    server_info::ServerInfo,
+    monitor::{
+        BatchFileResult, BatchSummary, FailedStage, MonitorError, MonitorProgress, MonitorResult, MonitorSuccess
+    },
+    server::{config::FilterExecutor, coordinator::WorkerEvent, dry_run::DryRunReport},
+    status::{ProcFile, QueuedTask, RunningTask, ServerStatus},
+    task_log::TaskLog,
+    transport::{Credentials, Incoming, Peer, Transport}
 };
 
+/// Environment variable choosing the [`WireFormat`] of the messages exchanged by the server
+/// and its clients, which must agree on it.
+pub const WIRE_FORMAT_VAR: &str = "SDSTORE_WIRE_FORMAT";
+
+/// Encoding of the messages exchanged by the server and its clients.
+pub trait Codec {
+    fn encode<T: ?Sized + Serialize>(&self, message: &T) -> Result<Vec<u8>, CodecError>;
+    fn decode<T: DeserializeOwned>(&self, bytes: &[u8]) -> Result<T, CodecError>;
+}
+
+/// Errors encoding or decoding a message, see [`Codec`].
+#[derive(Debug)]
+pub enum CodecError {
+    Bincode(bincode::Error),
+    Json(serde_json::Error),
+}
+
+impl From<bincode::Error> for CodecError {
+    fn from(err: bincode::Error) -> Self {
+        Self::Bincode(err)
+    }
+}
+
+impl From<serde_json::Error> for CodecError {
+    fn from(err: serde_json::Error) -> Self {
+        Self::Json(err)
+    }
+}
+
+/// Compact binary encoding, with `bincode`.
+pub struct BincodeCodec;
+
+impl Codec for BincodeCodec {
+    fn encode<T: ?Sized + Serialize>(&self, message: &T) -> Result<Vec<u8>, CodecError> {
+        Ok(bincode::serialize(message)?)
+    }
+
+    fn decode<T: DeserializeOwned>(&self, bytes: &[u8]) -> Result<T, CodecError> {
+        Ok(bincode::deserialize(bytes)?)
+    }
+}
+
+/// JSON encoding, with `serde_json`, for tools not written in Rust, or reading captured
+/// traffic.
+pub struct JsonCodec;
+
+impl Codec for JsonCodec {
+    fn encode<T: ?Sized + Serialize>(&self, message: &T) -> Result<Vec<u8>, CodecError> {
+        Ok(serde_json::to_vec(message)?)
+    }
+
+    fn decode<T: DeserializeOwned>(&self, bytes: &[u8]) -> Result<T, CodecError> {
+        Ok(serde_json::from_slice(bytes)?)
+    }
+}
+
+/// The [`Codec`] in use, chosen at run time with [`WIRE_FORMAT_VAR`]: `bincode`, the
+/// default, or `json`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum WireFormat {
+    #[default]
+    Bincode,
+    Json,
+}
+
+/// Error for an unrecognized wire format name.
+#[derive(Debug, PartialEq, Eq)]
+pub struct WireFormatParseError(pub String);
+
+impl FromStr for WireFormat {
+    type Err = WireFormatParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "bincode" => Ok(WireFormat::Bincode),
+            "json"    => Ok(WireFormat::Json),
+            s         => Err(WireFormatParseError(s.to_string())),
+        }
+    }
+}
+
+impl WireFormat {
+    /// The wire format set by [`WIRE_FORMAT_VAR`], or the default one if it isn't set.
+    pub fn from_env() -> Result<Self, WireFormatParseError> {
+        match env::var(WIRE_FORMAT_VAR) {
+            Err(_) => Ok(WireFormat::default()),
+            Ok(name) => name.parse(),
+        }
+    }
+}
+
+impl Codec for WireFormat {
+    fn encode<T: ?Sized + Serialize>(&self, message: &T) -> Result<Vec<u8>, CodecError> {
+        match self {
+            WireFormat::Bincode => BincodeCodec.encode(message),
+            WireFormat::Json => JsonCodec.encode(message),
+        }
+    }
+
+    fn decode<T: DeserializeOwned>(&self, bytes: &[u8]) -> Result<T, CodecError> {
+        match self {
+            WireFormat::Bincode => BincodeCodec.decode(bytes),
+            WireFormat::Json => JsonCodec.decode(bytes),
+        }
+    }
+}
+
+/// Largest part of a message sent in a single datagram, see [`send_message`].
+///
+/// The size of a datagram is limited by the sending socket's buffer, so longer messages,
+/// such as the server's status, are split in several.
+pub const MAX_DATAGRAM_PAYLOAD: usize = 16 * 1024;
+
+/// Flags in the first byte of a datagram carrying part of a message, see [`send_message`]:
+/// whether more parts of the message follow it, or it is the last one, and whether the
+/// message is compressed, which is set in every one of its parts.
+const MORE_PARTS: u8 = 1;
+const LAST_PART: u8 = 0;
+const COMPRESSED: u8 = 2;
+
+/// Messages longer than this are compressed, see [`send_message`].
+pub const COMPRESS_ABOVE: usize = 4 * 1024;
+
+/// Most bytes a message may take while it's reassembled from its parts, see
+/// [`MessageReceiver`], beyond which it is dropped, rather than have its sender make the
+/// receiver buffer without bound.
+pub const MAX_MESSAGE: usize = 64 * 1024 * 1024;
+
+/// Send the serialized message `bytes` over `transport` to `destination`, in as many
+/// datagrams as it takes, each carrying up to [`MAX_DATAGRAM_PAYLOAD`] bytes of it.
+///
+/// Messages longer than [`COMPRESS_ABOVE`] are compressed with zlib, if that makes them
+/// shorter, which is flagged in their parts' headers. The message is reassembled, and
+/// decompressed, by a [`MessageReceiver`].
+///
+/// A datagram whose sending is interrupted by a signal is sent again.
+pub fn send_message(transport: &dyn Transport, bytes: &[u8], destination: &Peer) -> io::Result<()> {
+    for datagram in datagrams(bytes)? {
+        loop {
+            match transport.send_to(&datagram, destination) {
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                sent => break sent?,
+            }
+        }
+    }
+    Ok(())
+}
+
+/// The datagrams [`send_message`] sends the serialized message `bytes` in, for sockets
+/// other than a [`Transport`].
+pub fn datagrams(bytes: &[u8]) -> io::Result<Vec<Vec<u8>>> {
+    let compressed = match bytes.len() > COMPRESS_ABOVE {
+        false => None,
+        true => {
+            let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+            encoder.write_all(bytes)?;
+            Some(encoder.finish()?).filter(|compressed| compressed.len() < bytes.len())
+        },
+    };
+    let (bytes, flags) = match &compressed {
+        None => (bytes, 0),
+        Some(compressed) => (compressed.as_slice(), COMPRESSED),
+    };
+
+    let mut parts = bytes.chunks(MAX_DATAGRAM_PAYLOAD).peekable();
+    // An empty message still takes a datagram, marked as its last part.
+    if parts.peek().is_none() {
+        return Ok(vec![vec![LAST_PART]])
+    }
+    let mut datagrams = Vec::new();
+    while let Some(part) = parts.next() {
+        let mut datagram = Vec::with_capacity(part.len() + 1);
+        datagram.push(flags | if parts.peek().is_some() { MORE_PARTS } else { LAST_PART });
+        datagram.extend_from_slice(part);
+        datagrams.push(datagram);
+    }
+    Ok(datagrams)
+}
+
+/// A datagram longer than a part of a message, and its header, was received from `sender`,
+/// and truncated, see [`MessageReceiver::recv_from`].
+#[derive(Debug)]
+pub struct TruncatedDatagram {
+    pub sender: Peer,
+    pub credentials: Option<Credentials>,
+    /// Length of the datagram, before it was truncated.
+    pub len: usize,
+}
+
+impl Display for TruncatedDatagram {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f, "datagram of {} bytes from {:?} was truncated to {} bytes",
+            self.len, self.sender, MAX_DATAGRAM_PAYLOAD + 1
+        )
+    }
+}
+
+impl std::error::Error for TruncatedDatagram {}
+
+/// A message from `sender` was dropped before it was received whole, for this `reason`,
+/// see [`MessageReceiver::recv_from`].
+#[derive(Debug)]
+pub struct DroppedMessage {
+    pub sender: Peer,
+    pub credentials: Option<Credentials>,
+    pub reason: String,
+}
+
+impl Display for DroppedMessage {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "message from {:?} was dropped: {}", self.sender, self.reason)
+    }
+}
+
+impl std::error::Error for DroppedMessage {}
+
+/// A message received whole, alongside its sender, and their credentials, if known.
+type ReceivedMessage = (Vec<u8>, Peer, Option<Credentials>);
+
+/// Reassembles the messages sent over a [`Transport`] with [`send_message`] from their parts.
+///
+/// The parts sent by a peer arrive in order, but may be interleaved with those from
+/// others, so a message is kept for each sender until its last part arrives. Parts sent
+/// from unnamed sockets can't be told apart from one another's, so only messages sent in a
+/// single datagram are received from those.
+#[derive(Debug, Default)]
+pub struct MessageReceiver {
+    partial: HashMap<Peer, Vec<u8>>,
+    /// Datagrams are read into this buffer, kept from one message to the next.
+    scratch: Vec<u8>,
+}
+
+impl MessageReceiver {
+    /// Wait for the next message to be received whole over `transport`.
+    pub fn recv(&mut self, transport: &dyn Transport) -> io::Result<Vec<u8>> {
+        self.recv_from(transport).map(|(message, ..)| message)
+    }
+
+    /// Wait for the next message to be received whole over `transport`, returning it
+    /// alongside its sender, and their credentials, if known, as of its last part.
+    ///
+    /// A datagram too long to be part of a message, which was truncated, is an
+    /// [`io::ErrorKind::InvalidData`] error wrapping a [`TruncatedDatagram`], and the
+    /// message it was part of is dropped. A message dropped for any other reason, see
+    /// [`MessageReceiver::push`], is one wrapping a [`DroppedMessage`].
+    pub fn recv_from(&mut self, transport: &dyn Transport) -> io::Result<ReceivedMessage> {
+        let mut buf = self.take_scratch();
+        let received = loop {
+            let received = transport
+                .recv_from(&mut buf)
+                .and_then(|(n, sender, credentials)| self.receive(&buf, n, sender, credentials));
+            match received {
+                Ok(None) => continue,
+                Ok(Some(message)) => break Ok(message),
+                Err(err) => break Err(err),
+            }
+        };
+        self.scratch = buf;
+        received
+    }
+
+    /// Wait for the next message to be received whole by the server over `incoming`, as
+    /// [`MessageReceiver::recv_from`] does, without blocking the thread.
+    ///
+    /// The parts received so far are kept should the returned future be dropped before it
+    /// completes, see [`Incoming::recv_from`].
+    pub async fn recv_incoming(&mut self, incoming: &Incoming) -> io::Result<ReceivedMessage> {
+        // Should the future be dropped, the buffer is allocated anew by the next call.
+        let mut buf = self.take_scratch();
+        let received = loop {
+            let received = match incoming.recv_from(&mut buf).await {
+                Ok((n, sender, credentials)) => self.receive(&buf, n, sender, credentials),
+                Err(err) => Err(err),
+            };
+            match received {
+                Ok(None) => continue,
+                Ok(Some(message)) => break Ok(message),
+                Err(err) => break Err(err),
+            }
+        };
+        self.scratch = buf;
+        received
+    }
+
+    /// The buffer datagrams are read into, large enough for any, see [`MAX_DATAGRAM_PAYLOAD`].
+    fn take_scratch(&mut self) -> Vec<u8> {
+        let mut buf = mem::take(&mut self.scratch);
+        buf.resize(MAX_DATAGRAM_PAYLOAD + 1, 0);
+        buf
+    }
+
+    /// Add the datagram of length `n` read into `buf` from `sender` to the message it is part
+    /// of, returning the message if it was its last part, see [`MessageReceiver::recv_from`].
+    fn receive(
+        &mut self,
+        buf: &[u8],
+        n: usize,
+        sender: Peer,
+        credentials: Option<Credentials>
+    ) -> io::Result<Option<ReceivedMessage>> {
+        if n > buf.len() {
+            self.partial.remove(&sender);
+            let truncated = TruncatedDatagram { sender, credentials, len: n };
+            return Err(io::Error::new(io::ErrorKind::InvalidData, truncated))
+        }
+        match self.push(&buf[..n], &sender) {
+            Ok(message) => Ok(message.map(|message| (message, sender, credentials))),
+            Err(err) if err.kind() == io::ErrorKind::InvalidData => {
+                let dropped = DroppedMessage { sender, credentials, reason: err.to_string() };
+                Err(io::Error::new(io::ErrorKind::InvalidData, dropped))
+            },
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Add `datagram`, received from `sender`, to the message it is part of, returning the
+    /// message if it was its last part, for datagrams received other than over a [`Transport`].
+    ///
+    /// A message longer than [`MAX_MESSAGE`], compressed or not, or sent in parts from an
+    /// unnamed socket, is dropped, as an [`io::ErrorKind::InvalidData`] error.
+    pub fn push(&mut self, datagram: &[u8], sender: &Peer) -> io::Result<Option<Vec<u8>>> {
+        let (header, part) = datagram
+            .split_first()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "empty datagram"))?;
+
+        if header & MORE_PARTS == MORE_PARTS && *sender == Peer::Unnamed {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "message sent in parts from an unnamed socket"))
+        }
+        let message = self.partial.entry(sender.clone()).or_default();
+        if message.len() + part.len() > MAX_MESSAGE {
+            self.partial.remove(sender);
+            return Err(io::Error::new(io::ErrorKind::InvalidData, format!("message longer than {MAX_MESSAGE} bytes")))
+        }
+        message.extend_from_slice(part);
+        if header & MORE_PARTS == MORE_PARTS {
+            return Ok(None)
+        }
+        let message = self.partial.remove(sender).unwrap_or_default();
+        if header & COMPRESSED == 0 {
+            return Ok(Some(message))
+        }
+        // Decompressed no further than needed to tell it's too long, however well it compresses.
+        let mut decompressed = Vec::new();
+        ZlibDecoder::new(message.as_slice())
+            .take(MAX_MESSAGE as u64 + 1)
+            .read_to_end(&mut decompressed)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+        if decompressed.len() > MAX_MESSAGE {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, format!("message longer than {MAX_MESSAGE} bytes")))
+        }
+        Ok(Some(decompressed))
+    }
+}
+
+/// How long the server waits for a client to acknowledge a notification before sending it
+/// again, see [`Sequenced`], unless configured otherwise, see
+/// [`ServerConfig::retransmit_after`](crate::core::server::config::ServerConfig::retransmit_after).
+pub const DEFAULT_RETRANSMIT_AFTER: Duration = Duration::from_millis(500);
+
+/// How many times the server sends a notification before giving up on it, unless configured
+/// otherwise, see [`ServerConfig::max_transmissions`](crate::core::server::config::ServerConfig::max_transmissions).
+pub const DEFAULT_MAX_TRANSMISSIONS: u32 = 5;
+
+/// A notification from the server to a client, numbered in the order they were sent, so
+/// that the client can acknowledge it with a [`ClientRequest::Ack`].
+///
+/// Datagrams may be dropped, so the server sends every notification again until it is
+/// acknowledged, up to [`DEFAULT_MAX_TRANSMISSIONS`] times, unless configured otherwise.
+///
+/// Every notification carries the ID of the request it is about, see
+/// [`ClientRequest::request_id`], so that clients can tell apart replies to different
+/// requests, or to an earlier attempt at the same one. Notifications are numbered per
+/// request, from `0`, so that a client may make several requests at once.
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+pub struct Sequenced<T> {
+    pub seq: u64,
+    pub request_id: Uuid,
+    pub message: T,
+}
+
+/// Receives the notifications sent by the server to a client, acknowledging each of them,
+/// and delivering those about each request once, in the order they were sent, see
+/// [`Sequenced`].
+///
+/// With [`NotificationReceiver::recv`], only the notifications about the client's request,
+/// or about no request in particular, with a nil ID, are delivered, the others are dropped.
+/// The server uses a nil ID when it couldn't read the client's request, see
+/// [`MessageToServer::Unreadable`].
+pub struct NotificationReceiver<T> {
+    messages: MessageReceiver,
+    codec: WireFormat,
+    client_pid: u32,
+    request_id: Uuid,
+    /// The server, to which acknowledgements are sent.
+    server: Peer,
+    /// Number of the next notification to deliver about each request.
+    next_seq: HashMap<Uuid, u64>,
+    /// Notifications received ahead of some about the same request that are yet to be, by
+    /// request and number.
+    early: BTreeMap<(Uuid, u64), T>,
+    /// The ID of the last ping sent to the server, see [`NotificationReceiver::recv_alive`],
+    /// whose answer is yet to arrive.
+    ping: Option<Uuid>,
+}
+
+impl<T: DeserializeOwned> NotificationReceiver<T> {
+    pub fn new(codec: WireFormat, client_pid: u32, request_id: Uuid, server: Peer) -> Self {
+        NotificationReceiver {
+            messages: MessageReceiver::default(),
+            codec,
+            client_pid,
+            request_id,
+            server,
+            next_seq: HashMap::new(),
+            early: BTreeMap::new(),
+            ping: None,
+        }
+    }
+
+    /// ID of the client's request, whose notifications [`NotificationReceiver::recv`] delivers.
+    pub fn request_id(&self) -> Uuid {
+        self.request_id
+    }
+
+    /// Wait for the next notification about the client's request received over `transport`.
+    pub fn recv(&mut self, transport: &dyn Transport) -> io::Result<T> {
+        loop {
+            match self.recv_any(transport)? {
+                (request_id, message) if request_id == self.request_id || request_id.is_nil() => return Ok(message),
+                (request_id, _) => log::warn!("dropping notification about request {request_id}"),
+            }
+        }
+    }
+
+    /// Wait for the next notification about any request received over `transport`, returning
+    /// it alongside the ID of the request it is about.
+    pub fn recv_any(&mut self, transport: &dyn Transport) -> io::Result<(Uuid, T)> {
+        loop {
+            if let Some(next) = self.next_in_order() {
+                return Ok(next)
+            }
+
+            let bytes = self.messages.recv(transport)?;
+            let ack = self.accept(&bytes)?;
+            // The server may be gone by now, e.g. after its last message as it shuts down, and
+            // sends the notification again otherwise.
+            if let Err(err) = send_message(transport, &ack, &self.server) {
+                log::warn!("could not acknowledge notification: {:?}", err);
+            }
+        }
+    }
+
+    /// The next notification received about any request that is due for delivery, if any,
+    /// alongside the ID of the request it is about.
+    pub fn next_in_order(&mut self) -> Option<(Uuid, T)> {
+        let (request_id, seq) = self.early.keys().copied().find(|(request_id, seq)| *seq == self.expected_seq(request_id))?;
+        self.next_seq.insert(request_id, seq + 1);
+        self.early.remove(&(request_id, seq)).map(|message| (request_id, message))
+    }
+
+    /// Keep the notification `bytes`, received whole, until it is due for delivery, see
+    /// [`NotificationReceiver::next_in_order`], returning the acknowledgement to send the
+    /// server for it.
+    ///
+    /// Duplicates are acknowledged too, in case the first acknowledgement was dropped.
+    pub fn accept(&mut self, bytes: &[u8]) -> io::Result<Vec<u8>> {
+        let invalid = |err: CodecError| io::Error::new(io::ErrorKind::InvalidData, format!("{err:?}"));
+        let Sequenced { seq, request_id, message } = self.codec.decode::<Sequenced<T>>(bytes).map_err(invalid)?;
+        if seq >= self.expected_seq(&request_id) {
+            self.early.insert((request_id, seq), message);
+        }
+        self.codec.encode(&ClientRequest::Ack(self.client_pid, request_id, seq)).map_err(invalid)
+    }
+
+    /// Number of the next notification to deliver about `request_id`.
+    fn expected_seq(&self, request_id: &Uuid) -> u64 {
+        self.next_seq.get(request_id).copied().unwrap_or_default()
+    }
+
+    /// Expect the numbering of notifications about `request_id` to start over, as it does
+    /// when the server restarts, see [`MessageToClient::Suspended`].
+    pub fn restart_sequence(&mut self, request_id: Uuid) {
+        self.next_seq.remove(&request_id);
+        self.early.retain(|(id, _), _| *id != request_id);
+    }
+}
+
+impl NotificationReceiver<MessageToClient> {
+    /// Wait for the next notification about the client's request received over `transport`,
+    /// as [`NotificationReceiver::recv`] does, see [`NotificationReceiver::recv_any_alive`].
+    pub fn recv_alive(&mut self, transport: &dyn Transport, ping_after: Duration, deadline: Duration) -> io::Result<MessageToClient> {
+        loop {
+            match self.recv_any_alive(transport, ping_after, deadline)? {
+                (request_id, message) if request_id == self.request_id || request_id.is_nil() => return Ok(message),
+                (request_id, _) => log::warn!("dropping notification about request {request_id}"),
+            }
+        }
+    }
+
+    /// Wait for the next notification about any request received over `transport`, as
+    /// [`NotificationReceiver::recv_any`] does, however long it takes, as long as the server
+    /// is alive: whenever none arrives for `ping_after`, the server is pinged, see
+    /// [`ClientRequest::Ping`], and taken to be gone unless it answers within `deadline`,
+    /// which is an [`io::ErrorKind::TimedOut`] error.
+    ///
+    /// The transport's read timeout is left at `ping_after`.
+    pub fn recv_any_alive(
+        &mut self,
+        transport: &dyn Transport,
+        ping_after: Duration,
+        deadline: Duration
+    ) -> io::Result<(Uuid, MessageToClient)> {
+        let timed_out = |err: &io::Error| matches!(err.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut);
+        transport.set_read_timeout(Some(ping_after))?;
+        let mut pinged = false;
+        loop {
+            match self.recv_any(transport) {
+                // Answers to earlier pings too, which arrived after some other notification.
+                Ok((request_id, MessageToClient::Pong { .. })) if self.ping == Some(request_id) => {
+                    self.ping = None;
+                    pinged = false;
+                    transport.set_read_timeout(Some(ping_after))?;
+                },
+                Err(err) if timed_out(&err) && !pinged => {
+                    let ping = Uuid::new_v4();
+                    let bytes = self.codec
+                        .encode(&ClientRequest::Ping(self.client_pid, ping))
+                        .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, format!("{err:?}")))?;
+                    send_message(transport, &bytes, &self.server)?;
+                    (self.ping, pinged) = (Some(ping), true);
+                    transport.set_read_timeout(Some(deadline))?;
+                },
+                Err(err) if timed_out(&err) =>
+                    return Err(io::Error::new(io::ErrorKind::TimedOut, format!("the server didn't answer a ping within {deadline:?}"))),
+                received => {
+                    transport.set_read_timeout(Some(ping_after))?;
+                    return received
+                },
+            }
+        }
+    }
+}
+
 /// Messages sent by the server to each client to inform it of the stage
 /// at which its request is.
-#[derive(Debug, Serialize, Deserialize, PartialEq)]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub enum MessageToClient {
-    /// The request could not be started
-    RequestInitError,
-    /// The request could be assigned to a monitor and start execution, but the
-    /// exit status of its monitor was that of failure.
-    RequestError,
-    /// The request has been received, and is pending processing.
-    Pending,
+    /// The request failed, either before or after it started, for this reason.
+    Failed(RequestFailure),
+    /// The server optimized the request's pipeline, see
+    /// [`optimizer::optimize`](super::server::optimizer::optimize), and will run the
+    /// second pipeline instead of the first.
+    Optimized(Vec<Filter>, Vec<Filter>),
+    /// The request has been received, and is pending processing, behind `position` other
+    /// requests of its queue. `est_wait` is how long it should wait to start, and `est_done`
+    /// to conclude, given how long recent requests, and their filters, took, if the server
+    /// ran any yet.
+    Queued {
+        position: usize,
+        est_wait: Option<Duration>,
+        est_done: Option<Duration>
+    },
+    /// The request has the idempotency key of the earlier request `original`, which is in
+    /// this state, see [`ClientTask::idempotency_key`]. The server doesn't run it, and sends
+    /// the outcome of `original` instead, once it concludes.
+    Duplicate {
+        original: Uuid,
+        state: RequestState
+    },
     /// The request has been assigned to a `Monitor`, as has begun processing
     Processing,
+    /// The request is still processing, and its pipeline has written this many bytes
+    /// to its output so far.
+    Progress {
+        bytes_out: u64
+    },
+    /// The output of a request sent inline, see [`ClientTask::inline`], sent ahead of its
+    /// conclusion, which the client writes to its output file.
+    InlineOutput(Vec<u8>),
     /// The request was sucessfully completed
-    Concluded((u64, u64))
+    Concluded(MonitorSuccess),
+    /// The request is a batch, and its pipeline was run on the file `input`, writing to
+    /// `output`, with this result: either `Concluded`, or why it failed.
+    BatchFile {
+        input: PathBuf,
+        output: PathBuf,
+        result: Box<MessageToClient>
+    },
+    /// The request is a batch, whose pipeline was run on every one of its files.
+    BatchConcluded(BatchSummary),
+    /// The request was a dry run, which the server validated instead of running.
+    DryRun(DryRunReport),
+    /// The server shut down while the request was running. Its filters being restartable,
+    /// it resumes from its last checkpoint once the server restarts, see
+    /// [`Checkpoint`](super::checkpoint::Checkpoint).
+    Suspended,
+    /// The server refused the request, for this reason, see
+    /// [`auth::authenticate`](super::server::auth::authenticate).
+    Refused(String),
+    /// The server's status, as asked for by a [`ClientRequest::Status`].
+    Status(ServerStatus),
+    /// A task's lifecycle changed, as a client subscribed to be told of, see
+    /// [`ClientRequest::Subscribe`].
+    Event(TaskEvent),
+    /// The client is no longer subscribed, see [`ClientRequest::Unsubscribe`].
+    Unsubscribed,
+    /// The server is alive, as a [`ClientRequest::Ping`] asked, running this version, and
+    /// has been for `uptime`.
+    Pong {
+        server_version: String,
+        uptime: Duration
+    },
+    /// The tasks that most recently finished or failed, oldest first, as asked for by a
+    /// [`ClientRequest::History`].
+    History(Vec<TaskEvent>),
+    /// What became of a request, as asked for by a [`ClientRequest::Query`].
+    State(RequestState),
+    /// What the server did with a request, as asked for by a [`ClientRequest::Logs`].
+    Log(TaskLog),
+    /// Whether the server is ready to take requests, as asked for by a [`ClientRequest::Health`].
+    Health(Health),
+    /// What the server runs with, as asked for by a [`ClientRequest::ServerInfo`].
+    ServerInfo(ServerInfo),
+    /// What the server's tasks added up to, as asked for by a [`ClientRequest::Report`].
+    Report(Report),
+    /// The server is to hand over to a freshly exec'd one, as asked for by a
+    /// [`ClientRequest::Reexec`], once its `running` tasks conclude. Its pending ones wait
+    /// for the new server meanwhile.
+    HandingOver { running: usize },
+    /// A freshly exec'd server of `server_version` took over, with the `pending` requests
+    /// handed over to it, see [`ClientRequest::Reexec`].
+    TookOver { server_version: String, pending: usize },
+    /// Task #`task_number` is suspended, its filters no longer counted as running, as asked
+    /// for by a [`ClientRequest::Suspend`].
+    TaskSuspended { task_number: usize },
+    /// Task #`task_number` runs again, as asked for by a [`ClientRequest::Resume`].
+    TaskResumed { task_number: usize },
+}
+
+impl MessageToClient {
+    /// Whether this is the server's last message about a request, after which it sends no
+    /// more about it, until it restarts if it suspended the request.
+    pub fn is_last(&self) -> bool {
+        match self {
+            Self::Failed(_) | Self::Concluded(_) | Self::BatchConcluded(_) | Self::DryRun(_) | Self::Suspended |
+            Self::Refused(_) | Self::Status(_) | Self::Unsubscribed | Self::Pong { .. } | Self::History(_) |
+            Self::State(_) | Self::Log(_) | Self::Health(_) | Self::ServerInfo(_) |
+            Self::Report(_) | Self::TookOver { .. } | Self::TaskSuspended { .. } | Self::TaskResumed { .. } => true,
+            Self::Optimized(..) | Self::Queued { .. } | Self::Duplicate { .. } | Self::Processing | Self::Progress { .. } |
+            Self::InlineOutput(_) | Self::BatchFile { .. } | Self::Event(_) | Self::HandingOver { .. } => false,
+        }
+    }
 }
 
 impl Display for MessageToClient {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match &self {
-            Self::RequestInitError => write!(f, "the request failed to start. check server logs for information"),
-            Self::RequestError     => write!(f, "the request started, but failed. check server logs for information"),
-            Self::Pending          => write!(f, "pending"),
+            Self::Failed(failure) => write!(f, "the request failed: {failure}"),
+            Self::Optimized(original, optimized) => {
+                let fmt = |filters: &[Filter]| filters.iter().map(Filter::to_string).collect::<Vec<_>>().join(" ");
+                write!(f, "pipeline optimized from `{}` to `{}`", fmt(original), fmt(optimized))
+            },
+            Self::Queued { position, est_wait: None, .. } =>
+                write!(f, "pending, behind {position} request(s)"),
+            Self::Queued { position, est_wait: Some(est_wait), est_done: None } => write!(
+                f, "pending, behind {position} request(s), estimated to start in {:.1}s", est_wait.as_secs_f64()
+            ),
+            Self::Queued { position, est_wait: Some(est_wait), est_done: Some(est_done) } => write!(
+                f, "pending, behind {position} request(s), estimated to start in {:.1}s, and finish in {:.1}s",
+                est_wait.as_secs_f64(), est_done.as_secs_f64()
+            ),
+            Self::Duplicate { original, state } =>
+                write!(f, "duplicate of request {original}, followed instead: {state}"),
             Self::Processing       => write!(f, "processing"),
-            Self::Concluded((i, o)) => write!(f, "concluded (bytes-input: {}, bytes-output: {})", i, o),
+            Self::Progress { bytes_out } => write!(f, "processing ({} bytes written)", bytes_out),
+            Self::InlineOutput(bytes) => write!(f, "received {} bytes of output inline", bytes.len()),
+            Self::Concluded(summary) => {
+                write!(
+                    f,
+                    "concluded (bytes-input: {}, bytes-output: {})\nsha256-input: {}\nsha256-output: {}",
+                    summary.bytes_in, summary.bytes_out, summary.sha256_in, summary.sha256_out
+                )?;
+                write!(f, "\ntook {:.3}s", summary.elapsed.as_secs_f64())?;
+                if let Some(throughput) = summary.throughput {
+                    write!(f, ", {throughput:.2} MB/s")?;
+                }
+                match summary.ratio {
+                    Some(ratio) if ratio <= 1.0 => write!(f, ", ratio {ratio:.3} ({:.1}% smaller)", (1.0 - ratio) * 100.0)?,
+                    Some(ratio) => write!(f, ", ratio {ratio:.3} ({:.1}% larger)", (ratio - 1.0) * 100.0)?,
+                    None => {},
+                }
+                write!(f, "\nqueue wait: {:.3}s", summary.queue_wait.as_secs_f64())?;
+                if let Some(path) = &summary.stored {
+                    write!(f, "\nstored as {}", path.display())?;
+                }
+                if summary.cached {
+                    write!(f, "\noutput copied from the result cache")?;
+                }
+                write!(f, "\n{}", summary.resource_usage)?;
+                for (stage, timing) in summary.stage_timings.iter().enumerate() {
+                    write!(f, "\nstage {stage} ({}): {timing}", timing.filter)?;
+                }
+                Ok(())
+            },
+            Self::BatchFile { input, output, result } =>
+                write!(f, "{} -> {}: {}", input.display(), output.display(), result),
+            Self::BatchConcluded(summary) => write!(
+                f,
+                "concluded batch of {} file(s), {} failed (bytes-input: {}, bytes-output: {})\nqueue wait: {:.3}s\n{}",
+                summary.files, summary.failed, summary.bytes_in, summary.bytes_out,
+                summary.queue_wait.as_secs_f64(), summary.resource_usage
+            ),
+            Self::DryRun(report) => write!(f, "{}", report),
+            Self::Suspended => write!(f, "suspended by the server shutting down, until it restarts"),
+            Self::Refused(reason) => write!(f, "the server refused the request: {reason}"),
+            Self::Status(status) => write!(f, "{status}"),
+            Self::Event(event) => write!(f, "{event}"),
+            Self::Unsubscribed => write!(f, "unsubscribed from task events"),
+            Self::Pong { server_version, uptime } =>
+                write!(f, "pong: server version {server_version}, up for {:.1}s", uptime.as_secs_f64()),
+            Self::History(events) if events.is_empty() => write!(f, "no task finished yet"),
+            Self::History(events) => {
+                let events = events.iter().map(TaskEvent::to_string).collect::<Vec<_>>();
+                write!(f, "{}", events.join("\n"))
+            },
+            Self::State(state) => write!(f, "{state}"),
+            Self::Log(log) => write!(f, "{log}"),
+            Self::Health(health) => write!(f, "{health}"),
+            Self::ServerInfo(info) => write!(f, "{info}"),
+            Self::Report(report) => write!(f, "{report}"),
+            Self::HandingOver { running } =>
+                write!(f, "handing the server over once its {running} running task(s) conclude"),
+            Self::TookOver { server_version, pending } =>
+                write!(f, "server version {server_version} took over, with {pending} pending request(s)"),
+            Self::TaskSuspended { task_number } =>
+                write!(f, "task #{task_number} suspended, its filters no longer counted against the limits"),
+            Self::TaskResumed { task_number } => write!(f, "task #{task_number} resumed"),
+        }
+    }
+}
+
+/// A task, as a client names it when asking about it, see [`ClientRequest::Query`]: by the
+/// ID of its request, or by the number it started running as, e.g. `#3`, as the server's
+/// status and history show.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub enum TaskId {
+    Request(Uuid),
+    Number(usize),
+}
+
+impl FromStr for TaskId {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.strip_prefix('#').unwrap_or(s).parse() {
+            Ok(task_number) => Ok(TaskId::Number(task_number)),
+            Err(_) => Uuid::parse_str(s)
+                .map(TaskId::Request)
+                .map_err(|_| format!("{s:?} is neither a request ID nor a task number")),
+        }
+    }
+}
+
+impl Display for TaskId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Request(request_id) => write!(f, "request {request_id}"),
+            Self::Number(task_number) => write!(f, "task #{task_number}"),
+        }
+    }
+}
+
+/// Where a request is, as told to a client that queried it, see [`ClientRequest::Query`].
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub enum RequestState {
+    /// The request is pending in its queue.
+    Pending(QueuedTask),
+    /// The request is running, and has for `elapsed`, its pipeline having written
+    /// `bytes_out` bytes of output so far, as last reported by its monitor.
+    Running {
+        running: RunningTask,
+        elapsed: Duration,
+        bytes_out: u64
+    },
+    /// The request finished or failed, as this event tells, and its client was last sent
+    /// this message about it.
+    Done(TaskEvent, Box<MessageToClient>),
+}
+
+impl Display for RequestState {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Pending(QueuedTask { position, task }) => write!(f, "pending #{position}: {}", ProcFile(task)),
+            Self::Running { running: RunningTask { task_number, task }, elapsed, bytes_out } => write!(
+                f, "task #{task_number} running for {:.1}s, {bytes_out} bytes written: {}", elapsed.as_secs_f64(), ProcFile(task)
+            ),
+            // A failure is told by the event already.
+            Self::Done(event, outcome) if matches!(**outcome, MessageToClient::Failed(_)) => write!(f, "{event}"),
+            Self::Done(event, outcome) => write!(f, "{event}\n{outcome}"),
+        }
+    }
+}
+
+/// Why a request failed, for the client to act upon, see [`MessageToClient::Failed`].
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub enum RequestFailure {
+    /// The request was submitted to a queue the server doesn't have.
+    UnknownQueue(String),
+    /// The server shut down before the request could start.
+    ShuttingDown,
+    /// The request's input file doesn't exist.
+    InputNotFound,
+    /// The request's input file could not be read, for this reason.
+    InputUnreadable(String),
+    /// The request's output file could not be written, for this reason.
+    OutputNotWritable(String),
+    /// The request's output file exists, and the request forbade replacing it.
+    OutputExists(PathBuf),
+    /// The executable of one of the request's filters, at this path, doesn't exist.
+    FilterMissing(PathBuf),
+    /// This stage of the pipeline failed, and the filters wrote this excerpt of their
+    /// `stderr`, which may be empty.
+    StageFailed {
+        stage: FailedStage,
+        stderr: String
+    },
+    /// The request was cancelled by its client, see [`ClientRequest::Cancel`], or the server
+    /// killed it before it finished, as it does to those still running long after it was
+    /// asked to shut down, or for longer than its task timeout.
+    Cancelled,
+    /// Something went wrong within the server, as described here, and in its logs.
+    Internal(String),
+    /// The request was sent in a datagram of `len` bytes, longer than the `max` a datagram
+    /// may be, see [`send_message`].
+    MessageTooLarge {
+        len: usize,
+        max: usize
+    },
+    /// The request could not be deserialized, for this reason.
+    MalformedRequest(String),
+    /// The request asked about the request with this ID, which the server doesn't know of:
+    /// it never received it, or it finished long ago, see [`ClientRequest::Wait`].
+    UnknownRequest(Uuid),
+    /// The request asked about the task that started running with this number, which the
+    /// server doesn't know of, see [`ClientRequest::Query`].
+    UnknownTask(usize),
+    /// The server's queues already held this many pending tasks, as many as it allows.
+    QueueFull(usize),
+    /// The request uses a filter the server, or its queue, has a limit of `0` for, so that it
+    /// could never run.
+    FilterDisabled(Filter),
+    /// The request uses a filter the server knows nothing of, neither builtin to it nor
+    /// registered in its config.
+    UnknownFilter(Filter),
+    /// The request's pipeline has `len` filters, more than the `max` the server allows.
+    PipelineTooLong {
+        len: usize,
+        max: usize
+    },
+    /// The request's client couldn't be told of it anymore, and was taken to be gone, so the
+    /// server dropped the request rather than run it for no one.
+    ClientGone,
+    /// The request's output is its input file, which writing the output would destroy.
+    OutputIsInput,
+    /// The directory the request's output would be written in doesn't exist.
+    OutputDirMissing(PathBuf),
+    /// The request's input or output, at this path, is outside the directories the server
+    /// allows its files to be in.
+    PathNotAllowed(PathBuf),
+    /// The request's output was to be written to the server's store, but the server has
+    /// none, see [`ClientTask::store`].
+    NoStore,
+    /// The request's output was to be written to the server's store, which only takes the
+    /// output of a single file, not of a batch.
+    BatchToStore,
+    /// The request's input or output is a URL, but the server was built without support
+    /// for remote files, see [`remote`](super::remote).
+    RemoteUnsupported,
+    /// The request's output is a URL, which only takes the output of a single file, not of
+    /// a batch.
+    BatchToRemote,
+    /// The request's remote input couldn't be downloaded, or its output uploaded, for this
+    /// reason.
+    RemoteTransferFailed(String),
+    /// The request's input, or output, of `len` bytes, is longer than the `max` the server
+    /// sends inline, see [`ClientTask::inline`].
+    InlineTooLarge {
+        len: usize,
+        max: usize
+    },
+    /// The request's client submitted more requests than the server's rate limit allows,
+    /// and may submit another once `retry_after` elapsed.
+    RateLimited {
+        retry_after: Duration
+    },
+    /// The request asked to suspend, or resume, this task, which isn't running on the server:
+    /// it is pending, concluded, or runs on a worker, see [`ClientRequest::Suspend`].
+    NotRunning(TaskId),
+    /// The request set this environment variable for its filters, which the server doesn't
+    /// allow requests to, see [`ClientTask::env`].
+    EnvVarNotAllowed(String),
+    /// The directory the request's filters were to run in doesn't exist, see
+    /// [`ClientTask::cwd`].
+    WorkingDirMissing(PathBuf),
+    /// The request chose a directory for its filters to run in, whereas the server sandboxes
+    /// them in namespaces of their own, where it can't be.
+    WorkingDirSandboxed,
+}
+
+impl From<MonitorError> for RequestFailure {
+    fn from(err: MonitorError) -> Self {
+        match err {
+            MonitorError::InputFileError(err) if err.kind() == io::ErrorKind::NotFound => Self::InputNotFound,
+            MonitorError::InputFileError(err) | MonitorError::InputFileMetadataError(err) =>
+                Self::InputUnreadable(err.to_string()),
+            MonitorError::OutputFileError(err) | MonitorError::OutputRenameError(err) |
+            MonitorError::OutputModeError(err) =>
+                Self::OutputNotWritable(err.to_string()),
+            MonitorError::OutputExists(path) => Self::OutputExists(path),
+            MonitorError::PathNotAllowed(path) => Self::PathNotAllowed(path),
+            MonitorError::RemoteError(err) => Self::RemoteTransferFailed(err.to_string()),
+            MonitorError::StageSpawnError(FilterExecutor::External(path), err)
+                if err.kind() == io::ErrorKind::NotFound => Self::FilterMissing(path),
+            MonitorError::StageError { stage, stderr } => Self::StageFailed { stage, stderr },
+            MonitorError::Killed => Self::Cancelled,
+            MonitorError::NoTransformationsGiven => Self::Internal(String::from("the pipeline has no filters")),
+            MonitorError::Panicked(msg) => Self::Internal(format!("the task panicked: {msg}")),
+            MonitorError::WorkerFailed(failure) => failure,
+            MonitorError::StageSpawnError(_, err) | MonitorError::StderrFileError(err) |
+            MonitorError::PipeCreationError(err) | MonitorError::PipelineFailure(err) |
+            MonitorError::OutputFileMetadataError(err) | MonitorError::ChecksumError(err) |
+            MonitorError::CheckpointError(err) => Self::Internal(err.to_string()),
+        }
+    }
+}
+
+impl Display for RequestFailure {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::UnknownQueue(queue) => write!(f, "the server has no queue named `{queue}`"),
+            Self::ShuttingDown => write!(f, "the server shut down before the request could start"),
+            Self::InputNotFound => write!(f, "the input file does not exist"),
+            Self::InputUnreadable(reason) => write!(f, "the input file could not be read: {reason}"),
+            Self::OutputNotWritable(reason) => write!(f, "the output file could not be written: {reason}"),
+            Self::OutputExists(path) =>
+                write!(f, "the output file {} already exists, and --no-clobber was given", path.display()),
+            Self::FilterMissing(path) =>
+                write!(f, "the filter executable {} does not exist on the server", path.display()),
+            Self::StageFailed { stage, stderr } if stderr.is_empty() => write!(f, "{stage}"),
+            Self::StageFailed { stage, stderr } => write!(f, "{stage}. filter stderr:\n{stderr}"),
+            Self::Cancelled => write!(f, "the request was cancelled before it finished"),
+            Self::Internal(reason) => write!(f, "{reason}. check server logs for information"),
+            Self::MessageTooLarge { len, max } =>
+                write!(f, "the request's datagram of {len} bytes is longer than the {max} bytes allowed"),
+            Self::MalformedRequest(reason) => write!(f, "the request could not be read: {reason}"),
+            Self::UnknownRequest(request_id) =>
+                write!(f, "the server knows of no request {request_id}, pending, running or recently finished"),
+            Self::UnknownTask(task_number) =>
+                write!(f, "the server knows of no task #{task_number}, running or recently finished"),
+            Self::QueueFull(capacity) =>
+                write!(f, "the server's queues are full, with {capacity} pending request(s). try again later"),
+            Self::FilterDisabled(filter) =>
+                write!(f, "the server never runs filter {filter}, whose limit is 0"),
+            Self::UnknownFilter(filter) =>
+                write!(f, "the server knows of no filter {filter}"),
+            Self::PipelineTooLong { len, max } =>
+                write!(f, "the request's pipeline has {len} filters, more than the {max} the server allows"),
+            Self::ClientGone => write!(f, "the request's client could not be reached, so it was dropped"),
+            Self::OutputIsInput => write!(f, "the output file is the input file, which writing it would destroy"),
+            Self::OutputDirMissing(dir) => write!(f, "the output directory {} does not exist", dir.display()),
+            Self::PathNotAllowed(path) =>
+                write!(f, "{} is outside the directories the server allows requests to access", path.display()),
+            Self::NoStore => write!(f, "the server has no store to write the output to"),
+            Self::BatchToStore => write!(f, "the output of a batch can't be written to the server's store"),
+            Self::RemoteUnsupported => write!(f, "the server was built without support for remote inputs and outputs"),
+            Self::BatchToRemote => write!(f, "the output of a batch can't be written to a URL"),
+            Self::RemoteTransferFailed(reason) => write!(f, "the remote file could not be transferred: {reason}"),
+            Self::InlineTooLarge { len, max } =>
+                write!(f, "{len} bytes are too many to send inline, the server allows {max}. try without --inline"),
+            Self::RateLimited { retry_after } => {
+                let secs = retry_after.as_secs() + u64::from(retry_after.subsec_nanos() > 0);
+                write!(f, "rate limited, retry after {secs} second(s)")
+            },
+            Self::NotRunning(task) => write!(f, "{task} isn't running on the server, to be suspended or resumed"),
+            Self::EnvVarNotAllowed(name) => write!(f, "the server doesn't let requests set {name} for their filters"),
+            Self::WorkingDirMissing(dir) => write!(f, "the filters' working directory {} does not exist", dir.display()),
+            Self::WorkingDirSandboxed => write!(f, "the server sandboxes its filters, which can't be run in another directory"),
+        }
+    }
+}
+
+/// A change in the lifecycle of any client's task, sent to every subscribed client, see
+/// [`ClientRequest::Subscribe`].
+///
+/// Tasks suspended by the server shutting down are not reported until they resume.
+///
+/// The server shares each task between its events, and its queue or monitor.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub enum TaskEvent {
+    /// The task was received, and is pending in its queue.
+    Queued(Arc<ClientTask>),
+    /// The task started running, as task #`task_number`.
+    Started {
+        task_number: usize,
+        task: Arc<ClientTask>
+    },
+    /// Task #`task_number` concluded successfully.
+    Finished {
+        task_number: usize,
+        task: Arc<ClientTask>
+    },
+    /// The task failed for this reason, after it started running as task #`task_number`,
+    /// if it did.
+    Failed {
+        task_number: Option<usize>,
+        task: Arc<ClientTask>,
+        failure: RequestFailure
+    },
+}
+
+impl Display for TaskEvent {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Queued(task) => write!(f, "queued: {}", ProcFile(task)),
+            Self::Started { task_number, task } => write!(f, "task #{task_number} started: {}", ProcFile(task)),
+            Self::Finished { task_number, task } => write!(f, "task #{task_number} finished: {}", ProcFile(task)),
+            Self::Failed { task_number: None, task, failure } =>
+                write!(f, "failed: {}\n{failure}", ProcFile(task)),
+            Self::Failed { task_number: Some(task_number), task, failure } =>
+                write!(f, "task #{task_number} failed: {}\n{failure}", ProcFile(task)),
+        }
+    }
+}
+
+impl TaskEvent {
+    /// The task the event is about.
+    pub fn task(&self) -> &ClientTask {
+        match self {
+            Self::Queued(task) | Self::Started { task, .. } | Self::Finished { task, .. } |
+            Self::Failed { task, .. } => task,
+        }
+    }
+
+    /// The number the task started running as, if it did.
+    pub fn task_number(&self) -> Option<usize> {
+        match self {
+            Self::Queued(_) => None,
+            Self::Started { task_number, .. } | Self::Finished { task_number, .. } => Some(*task_number),
+            Self::Failed { task_number, .. } => *task_number,
         }
     }
 }
 
 pub enum MessageToServer {
-    Client(ClientRequest),
-    Monitor(MonitorResult)
+    /// A client's request, who sent it, to whom notifications are sent, and their credentials,
+    /// if known, see [`auth::authenticate`](super::server::auth::authenticate).
+    Client(ClientRequest, Peer, Option<Credentials>),
+    /// A streamed task, whose input was received, and over whose stream its output is to
+    /// be sent back, see [`ClientTask::stream`].
+    Streamed(ClientTask, UnixStream),
+    Monitor(MonitorResult),
+    /// A monitor reporting the progress of its pipeline, to be relayed to its client.
+    Progress(MonitorProgress),
+    /// A monitor reporting the result for one of the files of its batch task, to be
+    /// relayed to its client.
+    BatchFile(BatchFileResult),
+    /// A request from this peer, with these credentials, couldn't be read, for this reason,
+    /// see [`ServerState::reject_unreadable`](super::server::state::ServerState::reject_unreadable).
+    Unreadable(Peer, Option<Credentials>, RequestFailure),
+    /// A worker registered, sent a message, or left, see
+    /// [`coordinator`](super::server::coordinator).
+    Worker(WorkerEvent),
+    /// The server received this termination signal, and must shut down.
+    Shutdown(i32)
 }
 
 /// The kinds of requests a client may make to the server.
@@ -48,194 +1106,392 @@ pub enum MessageToServer {
 ///   and pending requests
 /// * request the processing of a file with a given priority, with the sequence of
 ///   filters listed in the request.
+/// * subscribe to be told of every task's lifecycle, until it unsubscribes.
+/// * check that the server is alive.
+/// * cancel a request, or ask for the tasks that most recently finished.
+/// * ask where a request is, or wait for it to conclude, e.g. one submitted by a client
+///   that didn't wait for it.
+/// * ask for the server's record of what it did with a request.
 #[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
 pub enum ClientRequest {
     /// Corresponds to `./sdtore status`.
     ///
-    /// This `u32` value is the PID of the client wishing to be informed.
-    Status(u32),
-    /// Corresponds to `./sdstore proc-file <priority> <input-file> <output-file> [filters]`
-    ProcFile(ClientTask)
-}
-
-/// Enum for errors that may occur while parsing the client's request from the CLI.
-#[derive(Debug, PartialEq, Eq)]
-pub enum ClientReqParseError {
-    IncorrectCommandProvided,
-    NoCommandProvided,
-    TaskParseError(TaskParseError),
+    /// This `u32` value is the PID of the client wishing to be informed, and the `Uuid` the
+    /// ID of the request, see [`ClientRequest::request_id`].
+    Status(u32, Uuid),
+    /// Corresponds to `./sdstore proc-file [options] <input-file> <output-file> [filters]`
+    ProcFile(Box<ClientTask>),
+    /// Acknowledgement, by the client with this PID, of the notification with this number
+    /// about the request with this ID, see [`Sequenced`]. Sent on the client's own, rather
+    /// than from the CLI.
+    Ack(u32, Uuid, u64),
+    /// The client with this PID is to be sent notifications over the transport this is
+    /// sent on, e.g. its connection to the server, rather than the one its request is
+    /// submitted on, see [`ClientTask::stream`]. Sent on the client's own.
+    Connect(u32),
+    /// Corresponds to `./sdstore subscribe`: the client with this PID is to be sent every
+    /// [`TaskEvent`], until it unsubscribes, in reply to the request with this ID.
+    Subscribe(u32, Uuid),
+    /// The client with this PID is no longer to be sent task events, see
+    /// [`ClientRequest::Subscribe`]. Sent on the client's own.
+    Unsubscribe(u32),
+    /// Corresponds to `./sdstore ping`: the client with this PID checks that the server is
+    /// alive, with the request with this ID, see [`MessageToClient::Pong`].
+    Ping(u32, Uuid),
+    /// Corresponds to `./sdstore cancel <request-id>`: the client with this PID cancels the
+    /// request with this ID, its own or another client's. A pending request is dropped, and a
+    /// running one killed, either failing with [`RequestFailure::Cancelled`].
+    Cancel(u32, Uuid),
+    /// Corresponds to `./sdstore history`: the client with this PID asks for the tasks that
+    /// most recently finished or failed, with the request with this ID, see
+    /// [`MessageToClient::History`].
+    History(u32, Uuid),
+    /// Corresponds to `./sdstore query <task-id>`: the client with this PID asks, with the
+    /// request with this ID, where the task is, see [`MessageToClient::State`]. A task unknown
+    /// to the server fails with [`RequestFailure::UnknownRequest`], or
+    /// [`RequestFailure::UnknownTask`].
+    Query(u32, Uuid, TaskId),
+    /// Corresponds to `./sdstore wait <request-id>`: the client with this PID asks, with the
+    /// request with the first ID, to be sent the last message about the request with the
+    /// second ID, once it concludes, as its own client is. A request unknown to the server
+    /// fails with [`RequestFailure::UnknownRequest`].
+    Wait(u32, Uuid, Uuid),
+    /// Corresponds to `./sdstore logs <request-id>`: the client with this PID asks, with the
+    /// request with the first ID, for the server's record of the request with the second ID,
+    /// see [`MessageToClient::Log`]. Only pending, running and recently concluded requests
+    /// are recorded, as [`ClientRequest::Query`] tells of.
+    Logs(u32, Uuid, Uuid),
+    /// Corresponds to `./sdstore health`: the client with this PID checks that the server is
+    /// alive, and ready to take requests, with the request with this ID, see
+    /// [`MessageToClient::Health`].
+    Health(u32, Uuid),
+    /// Corresponds to `./sdstore server-info`: the client with this PID asks what the server
+    /// runs with, with the request with this ID, see [`MessageToClient::ServerInfo`].
+    ServerInfo(u32, Uuid),
+    /// Corresponds to `./sdstore report [--since <seconds>]`: the client with this PID asks,
+    /// with the request with this ID, for the totals of the tasks that concluded within the
+    /// window, or of every task the server accounts for, see [`MessageToClient::Report`].
+    /// Only root, and the server's own user, may ask.
+    Report(u32, Uuid, Option<Duration>),
+    /// Corresponds to `./sdstore reexec`: the client with this PID asks, with the request
+    /// with this ID, for the server to exec its executable afresh, e.g. once upgraded,
+    /// handing its sockets and pending requests over, see [`MessageToClient::HandingOver`]
+    /// and [`MessageToClient::TookOver`]. Only root, and the server's own user, may ask.
+    Reexec(u32, Uuid),
+    /// Corresponds to `./sdstore suspend <task-id>`: the client with this PID asks, with the
+    /// request with this ID, for the task to be suspended, see [`MessageToClient::TaskSuspended`],
+    /// freeing its filters for other tasks until it is resumed. A task that isn't running
+    /// on the server fails with [`RequestFailure::NotRunning`].
+    Suspend(u32, Uuid, TaskId),
+    /// Corresponds to `./sdstore resume <task-id>`: the client with this PID asks, with the
+    /// request with this ID, for the suspended task to run again, see
+    /// [`MessageToClient::TaskResumed`], its filters counted as running again, even past
+    /// the limits.
+    Resume(u32, Uuid, TaskId),
 }
 
 impl ClientRequest {
-    /// Build a [`ClientRequest`] from `main`'s `args` iterator, parsing the user's input
-    /// to construct a request to the server.
-    pub fn build(mut args: impl Iterator<Item = String>, client_pid: u32) -> Result<Self, ClientReqParseError> {
-        // Move past executable name in args list
-        args.next();
-
-        let command = match args.next() {
-            Some(arg) => arg,
-            None => return Err(ClientReqParseError::NoCommandProvided),
-        };
-
-        match command.as_str() {
-            "status" => return Ok(Self::Status(client_pid)),
-            "proc-file" => {}
-            _  => return Err(ClientReqParseError::IncorrectCommandProvided),
-        };
-
-        let task = match ClientTask::build(args, client_pid) {
-            Err(err) => return Err(ClientReqParseError::TaskParseError(err)),
-            Ok(t) => t,
-        };
+    /// The PID the client making the request claims to have.
+    pub fn client_pid_mut(&mut self) -> &mut u32 {
+        match self {
+            Self::Status(client_pid, _) | Self::Ack(client_pid, ..) | Self::Connect(client_pid) |
+            Self::Subscribe(client_pid, _) | Self::Unsubscribe(client_pid) | Self::Ping(client_pid, _) |
+            Self::Cancel(client_pid, _) | Self::History(client_pid, _) | Self::Query(client_pid, ..) |
+            Self::Wait(client_pid, ..) | Self::Logs(client_pid, ..) | Self::Health(client_pid, _) |
+            Self::ServerInfo(client_pid, _) | Self::Report(client_pid, ..) | Self::Reexec(client_pid, _) |
+            Self::Suspend(client_pid, ..) | Self::Resume(client_pid, ..) => client_pid,
+            Self::ProcFile(task) => &mut task.client_pid,
+        }
+    }
 
-        Ok(ClientRequest::ProcFile(task))
+    /// ID of the request, which the server echoes back in its every reply to it, see
+    /// [`Sequenced`]. Requests the client sends on its own aren't replied to, and have none,
+    /// nor do cancellations, which are replied to as the request they cancel.
+    pub fn request_id(&self) -> Option<Uuid> {
+        match self {
+            Self::Status(_, request_id) | Self::Subscribe(_, request_id) | Self::Ping(_, request_id) |
+            Self::History(_, request_id) | Self::Query(_, request_id, _) | Self::Wait(_, request_id, _) |
+            Self::Logs(_, request_id, _) | Self::Health(_, request_id) | Self::ServerInfo(_, request_id) |
+            Self::Report(_, request_id, _) | Self::Reexec(_, request_id) | Self::Suspend(_, request_id, _) |
+            Self::Resume(_, request_id, _) =>
+                Some(*request_id),
+            Self::ProcFile(task) => Some(task.request_id),
+            Self::Ack(..) | Self::Connect(_) | Self::Unsubscribe(_) | Self::Cancel(..) => None,
+        }
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use std::path::PathBuf;
+    use std::{os::unix::net::UnixDatagram, path::PathBuf, sync::Arc, time::Duration};
 
-    use crate::core::{filter::{Filter, FilterParseError}, client_task::{ClientTask, TaskParseError}, messaging::{ClientRequest, ClientReqParseError}};
+    use uuid::Uuid;
 
-    #[test]
-    fn task_parsing_works() {
-        let command = String::from(
-            "./sdstore proc-file 5 samples/file-a outputs/file-a-output bcompress nop gcompress encrypt nop"
-        );
+    use crate::core::{
+        filter::Filter, client_task::ClientTask,
+        messaging::{
+            datagrams, send_message, ClientRequest, Codec, MessageReceiver, MessageToClient,
+            NotificationReceiver, RequestFailure, RequestState, Sequenced, TaskEvent, TruncatedDatagram, WireFormat, WireFormatParseError,
+            COMPRESSED, LAST_PART, MAX_DATAGRAM_PAYLOAD, MAX_MESSAGE, MORE_PARTS
+        },
+        monitor::{MonitorError, MonitorSuccess},
+        server::{config::FilterExecutor, testing},
+        testing::TempDir,
+        transport::Peer
+    };
 
-        let args = command
-            .split_ascii_whitespace()
-            .map(str::to_string);
-        // Move past executable name, and command type
-
-        let task = ClientTask::new(
-            0,
-            5,
-            PathBuf::from("samples/file-a"),
-            PathBuf::from("outputs/file-a-output"),
-            vec![Filter::Bcompress, Filter::Nop, Filter::Gcompress, Filter::Encrypt, Filter::Nop]
-        );
+    #[test]
+    fn long_messages_round_trip() {
+        let dir = TempDir::new("messaging");
+        let receiver = UnixDatagram::bind(dir.join("receiver.sock")).unwrap();
+        let senders = [UnixDatagram::bind(dir.join("a.sock")).unwrap(), UnixDatagram::bind(dir.join("b.sock")).unwrap()];
 
-        let mut args1 = args.clone();
-        args1.next();
-        args1.next();
-        assert_eq!(ClientTask::build(args1, 0).unwrap(), task);
+        // Random bytes, so that the message isn't compressed, and is sent in the parts below.
+        let mut state = 0x2545_f491_4f6c_dd1d_u64;
+        let long = (0..3 * MAX_DATAGRAM_PAYLOAD + 7)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                state as u8
+            })
+            .collect::<Vec<_>>();
+        let short = b"short".to_vec();
+        // The parts of the long message are interleaved with the short one, from another sender.
+        let (first, rest) = long.split_at(MAX_DATAGRAM_PAYLOAD);
+        let destination = Peer::Path(dir.join("receiver.sock"));
+        senders[0].send_to(&[&[1], first].concat(), dir.join("receiver.sock")).unwrap();
+        send_message(&senders[1], &short, &destination).unwrap();
+        send_message(&senders[0], rest, &destination).unwrap();
+        send_message(&senders[1], &[], &destination).unwrap();
 
-        let client_req = ClientRequest::ProcFile(task);
-        assert_eq!(ClientRequest::build(args, 0).unwrap(), client_req);
+        let mut messages = MessageReceiver::default();
+        assert_eq!(messages.recv(&receiver).unwrap(), short);
+        assert_eq!(messages.recv(&receiver).unwrap(), long);
+        assert_eq!(messages.recv(&receiver).unwrap(), Vec::<u8>::new());
     }
 
     #[test]
-    fn status_parsing_works() {
-        let command = String::from("./sdstore status");
-        let args = command
-            .split_ascii_whitespace()
-            .map(str::to_string);
+    fn truncated_datagrams_are_reported() {
+        let dir = TempDir::new("truncation");
+        let receiver = UnixDatagram::bind(dir.join("receiver.sock")).unwrap();
+        let sender = UnixDatagram::bind(dir.join("sender.sock")).unwrap();
 
-        assert!(matches!(ClientRequest::build(args, 0).unwrap(), ClientRequest::Status(_)));
+        // A message sent whole, rather than in parts, followed by a well-formed one.
+        sender.send_to(&vec![0; 2 * MAX_DATAGRAM_PAYLOAD], dir.join("receiver.sock")).unwrap();
+        send_message(&sender, b"short", &Peer::Path(dir.join("receiver.sock"))).unwrap();
+
+        let mut messages = MessageReceiver::default();
+        let err = messages.recv(&receiver).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+        let truncated = err.into_inner().unwrap().downcast::<TruncatedDatagram>().unwrap();
+        assert_eq!((truncated.sender, truncated.len), (Peer::Path(dir.join("sender.sock")), 2 * MAX_DATAGRAM_PAYLOAD));
+        assert_eq!(messages.recv(&receiver).unwrap(), b"short");
     }
 
     #[test]
-    fn request_parsing_fails1() {
-        let command = String::from("./sdstore abcdef");
-        let args = command
-            .split_ascii_whitespace()
-            .map(str::to_string);
+    fn unbounded_messages_are_dropped() {
+        let mut messages = MessageReceiver::default();
+        let part = [&[MORE_PARTS][..], &[0; MAX_DATAGRAM_PAYLOAD]].concat();
+        let sender = Peer::Path(PathBuf::from("sender.sock"));
 
-            assert_eq!(
-                ClientRequest::build(args, 0).unwrap_err(),
-                ClientReqParseError::IncorrectCommandProvided
-            );
+        // A message is dropped as soon as its parts add up to more than allowed.
+        for _ in 0..MAX_MESSAGE / MAX_DATAGRAM_PAYLOAD {
+            assert_eq!(messages.push(&part, &sender).unwrap(), None);
+        }
+        assert_eq!(messages.push(&part, &sender).unwrap_err().kind(), std::io::ErrorKind::InvalidData);
+        assert_eq!(messages.push(&[LAST_PART, 1], &sender).unwrap(), Some(vec![1]));
+
+        // The parts sent from unnamed sockets could be anyone's.
+        assert_eq!(messages.push(&part, &Peer::Unnamed).unwrap_err().kind(), std::io::ErrorKind::InvalidData);
+        assert_eq!(messages.push(&[LAST_PART, 2], &Peer::Unnamed).unwrap(), Some(vec![2]));
+
+        // Nor may it decompress to more, however short it is compressed.
+        let mut bomb = datagrams(&vec![0; MAX_MESSAGE + 1]).unwrap();
+        let last = bomb.pop().unwrap();
+        assert!(bomb.len() < 8 && last[0] == COMPRESSED | LAST_PART);
+        for part in bomb {
+            assert_eq!(messages.push(&part, &sender).unwrap(), None);
+        }
+        assert_eq!(messages.push(&last, &sender).unwrap_err().kind(), std::io::ErrorKind::InvalidData);
     }
 
     #[test]
-    fn request_parsing_fails2() {
-        let command = String::from("./sdstore");
-        let args = command
-            .split_ascii_whitespace()
-            .map(str::to_string);
+    fn large_messages_are_compressed() {
+        let dir = TempDir::new("compression");
+        let receiver = UnixDatagram::bind(dir.join("receiver.sock")).unwrap();
+        let sender = UnixDatagram::unbound().unwrap();
+        let destination = Peer::Path(dir.join("receiver.sock"));
 
-            assert_eq!(
-                ClientRequest::build(args, 0).unwrap_err(),
-                ClientReqParseError::NoCommandProvided
-            );
+        let long = b"task #1: proc-file 1 in out nop\n".repeat(2 * MAX_DATAGRAM_PAYLOAD);
+        send_message(&sender, &long, &destination).unwrap();
+        send_message(&sender, b"short", &destination).unwrap();
+
+        // The long message fits in a single compressed datagram.
+        let mut buf = vec![0; MAX_DATAGRAM_PAYLOAD + 1];
+        let n = receiver.recv(&mut buf).unwrap();
+        assert_eq!(buf[0], COMPRESSED | LAST_PART);
+        receiver.send_to(&buf[..n], dir.join("receiver.sock")).unwrap();
+
+        let mut messages = MessageReceiver::default();
+        assert_eq!(messages.recv(&receiver).unwrap(), b"short");
+        assert_eq!(messages.recv(&receiver).unwrap(), long);
     }
 
     #[test]
-    fn task_parsing_fails1() {
-        let command = String::from(
-            "./sdstore proc-file"
-        );
-        let args = command
-            .split_ascii_whitespace()
-            .map(str::to_string);
+    fn codecs_round_trip() {
+        let mut task = ClientTask::new(7, 2, PathBuf::from("in"), PathBuf::from("out"), vec![Filter::Nop, Filter::Zcompress]);
+        task.queue = Some(String::from("batch"));
+        let request = ClientRequest::ProcFile(Box::new(task));
+        let message = MessageToClient::Optimized(vec![Filter::Nop, Filter::Gcompress], vec![Filter::Gcompress]);
 
-        assert_eq!(
-            ClientRequest::build(args, 0).unwrap_err(),
-            ClientReqParseError::TaskParseError(TaskParseError::NoPriorityProvided)
-        );
+        for codec in [WireFormat::Bincode, WireFormat::Json] {
+            assert_eq!(codec.decode::<ClientRequest>(&codec.encode(&request).unwrap()).unwrap(), request);
+            assert_eq!(codec.decode::<MessageToClient>(&codec.encode(&message).unwrap()).unwrap(), message);
+        }
+
+        let json = String::from_utf8(WireFormat::Json.encode(&message).unwrap()).unwrap();
+        assert_eq!(json, r#"{"Optimized":[["nop","gcompress"],["gcompress"]]}"#);
+        let custom = vec![Filter::Custom(String::from("lz4"))];
+        assert_eq!(WireFormat::Bincode.decode::<Vec<Filter>>(&WireFormat::Bincode.encode(&custom).unwrap()).unwrap(), custom);
+        assert_eq!("JSON".parse(), Ok(WireFormat::Json));
+        assert_eq!("xml".parse::<WireFormat>(), Err(WireFormatParseError(String::from("xml"))));
     }
 
     #[test]
-    fn task_parsing_fails2() {
-        let command = String::from("./sdstore proc-file 5a");
-        let args = command
-            .split_ascii_whitespace()
-            .map(str::to_string);
+    fn monitor_errors_become_request_failures() {
+        let not_found = || std::io::Error::from(std::io::ErrorKind::NotFound);
+        let denied = || std::io::Error::from(std::io::ErrorKind::PermissionDenied);
 
+        assert_eq!(RequestFailure::from(MonitorError::InputFileError(not_found())), RequestFailure::InputNotFound);
+        assert!(matches!(RequestFailure::from(MonitorError::InputFileError(denied())), RequestFailure::InputUnreadable(_)));
+        assert!(matches!(RequestFailure::from(MonitorError::OutputFileError(denied())), RequestFailure::OutputNotWritable(_)));
+        assert_eq!(
+            RequestFailure::from(MonitorError::StageSpawnError(FilterExecutor::External(PathBuf::from("bin/nop")), not_found())),
+            RequestFailure::FilterMissing(PathBuf::from("bin/nop"))
+        );
         assert!(matches!(
-            ClientRequest::build(args, 0).unwrap_err(),
-            ClientReqParseError::TaskParseError(TaskParseError::InvalidPriority(_))
+            RequestFailure::from(MonitorError::StageSpawnError(FilterExecutor::Builtin(Filter::Nop), not_found())),
+            RequestFailure::Internal(_)
         ));
+        assert_eq!(RequestFailure::from(MonitorError::Killed), RequestFailure::Cancelled);
+
+        let failure = RequestFailure::OutputExists(PathBuf::from("out"));
+        let json = String::from_utf8(WireFormat::Json.encode(&MessageToClient::Failed(failure)).unwrap()).unwrap();
+        assert_eq!(json, r#"{"Failed":{"OutputExists":"out"}}"#);
     }
 
     #[test]
-    fn task_parsing_fails3() {
-        let command = String::from(
-            "./sdstore proc-file 5 samples/file-a"
-        );
-        let args = command
-            .split_ascii_whitespace()
-            .map(str::to_string);
+    fn task_events_formatting_works() {
+        let task = Arc::new(ClientTask::new(7, 2, PathBuf::from("in"), PathBuf::from("out"), vec![Filter::Nop]));
+        let started = TaskEvent::Started { task_number: 3, task: Arc::clone(&task) };
+        assert_eq!(started.to_string(), "task #3 started: proc-file 2 in out nop");
 
+        let failed = TaskEvent::Failed { task_number: None, task, failure: RequestFailure::ShuttingDown };
         assert_eq!(
-            ClientRequest::build(args, 0).unwrap_err(),
-            ClientReqParseError::TaskParseError(TaskParseError::InvalidInputOutputPaths)
+            failed.to_string(),
+            "failed: proc-file 2 in out nop\nthe server shut down before the request could start"
         );
+        let state = RequestState::Done(failed.clone(), Box::new(MessageToClient::Failed(RequestFailure::ShuttingDown)));
+        assert_eq!(state.to_string(), failed.to_string());
+        let finished = TaskEvent::Finished { task_number: 3, task: Arc::new(failed.task().clone()) };
+        let state = RequestState::Done(finished, Box::new(MessageToClient::Suspended));
+        assert_eq!(
+            state.to_string(),
+            "task #3 finished: proc-file 2 in out nop\nsuspended by the server shutting down, until it restarts"
+        );
+
+        let message = MessageToClient::Event(failed);
+        for codec in [WireFormat::Bincode, WireFormat::Json] {
+            assert_eq!(codec.decode::<MessageToClient>(&codec.encode(&message).unwrap()).unwrap(), message);
+        }
     }
 
     #[test]
-    fn task_parsing_fails4() {
-        let command = String::from(
-            "./sdstore proc-file 5 samples/file-a outputs/file-a-output"
-        );
-        let args = command
-            .split_ascii_whitespace()
-            .map(str::to_string);
+    fn concluded_summaries_formatting_works() {
+        let summary = MonitorSuccess {
+            bytes_in: 1_000_000,
+            bytes_out: 420_000,
+            ratio: Some(0.42),
+            elapsed: Duration::from_millis(500),
+            throughput: Some(2.0),
+            ..testing::success()
+        };
+        let lines = |summary| MessageToClient::Concluded(summary).to_string().lines().nth(3).unwrap().to_string();
+        assert_eq!(lines(summary.clone()), "took 0.500s, 2.00 MB/s, ratio 0.420 (58.0% smaller)");
+        assert_eq!(lines(MonitorSuccess { ratio: Some(1.5), ..summary.clone() }), "took 0.500s, 2.00 MB/s, ratio 1.500 (50.0% larger)");
+        // Nothing to compare an empty input to, nor to measure without time.
+        assert_eq!(lines(MonitorSuccess { ratio: None, throughput: None, ..summary }), "took 0.500s");
+    }
 
-        assert_eq!(
-            ClientRequest::build(args, 0).unwrap_err(),
-            ClientReqParseError::TaskParseError(TaskParseError::NoFiltersProvided)
-        );
+    #[test]
+    fn notifications_are_delivered_in_order_once() {
+        let dir = TempDir::new("notification");
+        let (server_path, client_path) = (dir.join("server.sock"), dir.join("client.sock"));
+        let server = UnixDatagram::bind(&server_path).unwrap();
+        let client = UnixDatagram::bind(&client_path).unwrap();
+
+        let codec = WireFormat::default();
+        let (request_id, other_request_id) = (Uuid::new_v4(), Uuid::new_v4());
+        // The second notification was dropped, and arrives after the third, and the first and
+        // third are sent again. The last one is about another request, numbered on its own.
+        let notifications = [
+            (0, "pending"), (2, "concluded"), (2, "concluded"), (0, "pending"), (1, "processing")
+        ].map(|(seq, message)| (seq, request_id, message));
+        for (seq, request_id, message) in notifications.into_iter().chain([(0, other_request_id, "stale")]) {
+            let bytes = codec.encode(&Sequenced { seq, request_id, message }).unwrap();
+            send_message(&server, &bytes, &Peer::Path(client_path.clone())).unwrap();
+        }
+
+        let mut notifications = NotificationReceiver::<String>::new(codec, 42, request_id, Peer::Path(server_path));
+        for expected in ["pending", "processing", "concluded"] {
+            assert_eq!(notifications.recv(&client).unwrap(), expected);
+        }
+        client.set_nonblocking(true).unwrap();
+        assert_eq!(notifications.recv(&client).unwrap_err().kind(), std::io::ErrorKind::WouldBlock);
+
+        let mut acks = MessageReceiver::default();
+        let acked = (0..6)
+            .map(|_| match codec.decode(&acks.recv(&server).unwrap()).unwrap() {
+                ClientRequest::Ack(42, _, seq) => seq,
+                other => panic!("unexpected request {other:?}"),
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(acked, [0, 2, 2, 0, 1, 0]);
     }
 
     #[test]
-    fn task_parsing_fails5() {
-        let command = String::from(
-            "./sdstore proc-file 5 samples/file-a outputs/file-a-output nopp"
-        );
-        let args = command
-            .split_ascii_whitespace()
-            .map(str::to_string);
-
-        let err = ClientReqParseError::TaskParseError(
-            TaskParseError::InvalidFilterProvided(
-                FilterParseError(String::from("nopp"))
-            )
-        );
+    fn servers_are_waited_on_while_alive() {
+        let dir = TempDir::new("liveness");
+        let (server_path, client_path) = (dir.join("server.sock"), dir.join("client.sock"));
+        let server = UnixDatagram::bind(&server_path).unwrap();
+        let client = UnixDatagram::bind(&client_path).unwrap();
+        let codec = WireFormat::default();
+        let request_id = Uuid::new_v4();
+
+        // The server tells the client its task is processing, answers its first ping, and
+        // then hangs, before it's killed.
+        let to_client = Peer::Path(client_path.clone());
+        let server = std::thread::spawn(move || {
+            let processing = codec.encode(&Sequenced { seq: 0, request_id, message: MessageToClient::Processing }).unwrap();
+            send_message(&server, &processing, &to_client).unwrap();
+            let mut requests = MessageReceiver::default();
+            loop {
+                if let ClientRequest::Ping(_, ping) = codec.decode(&requests.recv(&server).unwrap()).unwrap() {
+                    let pong = MessageToClient::Pong { server_version: String::new(), uptime: Duration::ZERO };
+                    send_message(&server, &codec.encode(&Sequenced { seq: 0, request_id: ping, message: pong }).unwrap(), &to_client).unwrap();
+                    return server
+                }
+            }
+        });
 
-        assert_eq!(ClientRequest::build(args, 0).unwrap_err(), err );
+        let mut notifications = NotificationReceiver::new(codec, 42, request_id, Peer::Path(server_path));
+        let (ping_after, deadline) = (Duration::from_millis(50), Duration::from_millis(200));
+        assert_eq!(notifications.recv_alive(&client, ping_after, deadline).unwrap(), MessageToClient::Processing);
+        let hung = notifications.recv_alive(&client, ping_after, deadline).unwrap_err();
+        assert_eq!(hung.kind(), std::io::ErrorKind::TimedOut);
+        drop(server.join().unwrap());
+        let killed = notifications.recv_alive(&client, ping_after, deadline).unwrap_err();
+        assert_eq!(killed.kind(), std::io::ErrorKind::ConnectionRefused);
     }
-}
\ No newline at end of file
+}
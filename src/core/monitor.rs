@@ -1,16 +1,49 @@
 use std::{
-    path::PathBuf, fs, io, thread::{self, Thread, ThreadId}, sync::mpsc::Sender,
+    any::Any, env, fmt::Display, path::{Path, PathBuf}, fs, io::{self, Read, Seek, Write},
+    panic::{self, AssertUnwindSafe},
+    os::{fd::{AsRawFd, OwnedFd}, unix::{fs::{MetadataExt, OpenOptionsExt, PermissionsExt}, process::{CommandExt, ExitStatusExt}}},
+    process::{Child, Command, ExitStatus},
+    sync::{
+        atomic::{AtomicBool, Ordering}, mpsc::{self, Receiver, RecvTimeoutError},
+        Arc, Mutex, MutexGuard, PoisonError,
+    },
+    thread::{self, JoinHandle}, time::{Duration, Instant},
 };
 
-use subprocess::{Exec, Pipeline, PopenError, ExitStatus};
+use serde::{Serialize, Deserialize};
+use sha2::{Digest, Sha256};
+use tokio::sync::mpsc::{error::TrySendError, Sender};
 
-use super::{client_task, messaging};
+use super::{
+    batch, builtin, checkpoint::{self, Checkpoint}, chunking, client_task, filter::Filter, messaging, remote,
+    server::{
+        auth::PathPolicy, cache::{self, ResultCache}, config::FilterExecutor, coordinator::{CoordinatorMessage, WorkerLink},
+        environment::StageEnvironment, monitor_pool::MonitorPool, pool::{Worker, WorkerPool},
+        resources::ResourceLimits, sandbox::Sandbox, wasm::WasmRuntime,
+    },
+};
+
+/// How many paths a task's temporary output is tried at before giving up, if there is
+/// already a file at each, see [`create_tmp_output`].
+const TMP_OUTPUT_ATTEMPTS: usize = 8;
+
+/// Maximum size, in bytes, of the excerpt of the filters' `stderr` reported to clients.
+pub const STDERR_EXCERPT_LEN: usize = 512;
+
+/// How often a monitor reports the progress of its pipeline, see [`MonitorProgress`], unless
+/// configured otherwise, see [`ServerConfig::progress_interval`](crate::core::server::config::ServerConfig::progress_interval).
+pub const DEFAULT_PROGRESS_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How often a monitor checks which stages of its pipeline have exited, and so the
+/// precision of their [`StageTiming`]s.
+const STAGE_POLL_INTERVAL: Duration = Duration::from_millis(10);
 
 /// Errors that may occur when spawning a monitor.
 #[derive(Debug)]
 pub enum MonitorBuildError {
-    /// Problem spawning the monitor's thread.
-    ThreadSpawnError(io::Error),
+    /// The pool of threads monitors run on isn't running: it wasn't started, or the
+    /// server is shutting down, see [`MonitorPool`].
+    PoolStopped,
 }
 
 /// A selection of the errors a monitor may enconter during a pipeline's execution.
@@ -21,20 +54,57 @@ pub enum MonitorError {
     NoTransformationsGiven,
     /// A problem opening/reading the input file.
     InputFileError(io::Error),
-    /// A problem creating/opening the output file.
+    /// A problem creating/opening the temporary output file.
     OutputFileError(io::Error),
+    /// The output file exists, and the task forbids replacing it, see
+    /// [`ClientTask::no_clobber`](client_task::ClientTask::no_clobber).
+    OutputExists(PathBuf),
+    /// The input, or output, file at this path was out of the directories the task's files
+    /// must be in once opened, though it wasn't as the task was queued, see [`PathPolicy`].
+    PathNotAllowed(PathBuf),
+    /// A problem creating the scratch files each filter's `stderr` is redirected to.
+    StderrFileError(io::Error),
+    /// A problem creating the pipe between two stages of the pipeline.
+    PipeCreationError(io::Error),
+    /// A stage of the pipeline, run by this executor, could not be started, e.g. because
+    /// its filter's executable is missing.
+    StageSpawnError(FilterExecutor, io::Error),
 
-    /// A general error may occurrs after `wait`ing for the process responsible for the last
-    /// step in the pipeline to finish.
-    PipelineFailure(PopenError),
-    /// The pipeline finished, but its exit status was not that of success.
-    PipelineExitStatusError(ExitStatus),
+    /// A general error, which may occur when starting a stage of the pipeline, or after
+    /// `wait`ing for it to finish.
+    PipelineFailure(io::Error),
+    /// The pipeline was killed before it could finish, see [`Monitor::kill`].
+    Killed,
+    /// The pipeline finished, but one of its stages did not succeed.
+    ///
+    /// `stderr` is an excerpt of what the pipeline's filters wrote to `stderr`, see
+    /// [`stderr_excerpt`].
+    StageError {
+        stage: FailedStage,
+        stderr: String
+    },
     /// A problem opening the input file's metadata to obtain its size.
     InputFileMetadataError(io::Error),
     /// A problem opening the output file's metadata to obtain its size.
     OutputFileMetadataError(io::Error),
-    /// Failed to inform the server of pipeline completion via the sending end of an `mpsc::channel`
-    MpscSenderError,
+    /// A problem reading the input or output file to compute its checksum.
+    ChecksumError(io::Error),
+    /// The pipeline succeeded, but its temporary output file could not be renamed
+    /// to the requested output path.
+    OutputRenameError(io::Error),
+    /// The temporary output file could not be given the task's mode, see
+    /// [`ClientTask::mode`](client_task::ClientTask::mode).
+    OutputModeError(io::Error),
+    /// A problem saving the checkpoint of a restartable task, see [`Checkpoint`].
+    CheckpointError(io::Error),
+    /// The monitor panicked, with this message.
+    Panicked(String),
+    /// The task's remote input couldn't be downloaded, or its output uploaded, see
+    /// [`Staging`](remote::Staging).
+    RemoteError(remote::RemoteError),
+    /// The task failed on the worker it was handed to, as the worker reported, see
+    /// [`Monitor::remote`].
+    WorkerFailed(messaging::RequestFailure),
 }
 
 pub struct Monitor {
@@ -43,143 +113,2175 @@ pub struct Monitor {
     /// and schedules it.
     pub task_number: usize,
 
-    /// Thread responsible for executing the pipeline contained in the task
-    thread: Thread,
+    /// Set once the monitor is done running on its thread of the [`MonitorPool`],
+    /// having reported its result to the server.
+    finished: Arc<AtomicBool>,
+
+    /// Client request the monitor is responsible for, shared with its thread, and with the
+    /// events and statuses that mention it.
+    pub task: Arc<client_task::ClientTask>,
+    /// When the monitor was started, to measure how long running its task took.
+    pub started_at: Instant,
+    /// Bytes of output the task's pipeline wrote so far, as it last reported.
+    pub bytes_out: u64,
+    /// Span the monitor's thread runs in, as do those it spawns, see [`Monitor::build`].
+    pub span: tracing::Span,
+
+    /// Shared with the pipeline, to kill it.
+    control: Arc<PipelineControl>,
+    /// The worker running the task, if it was handed to one, see [`Monitor::remote`].
+    worker: Option<Arc<WorkerLink>>,
+}
+
+/// State shared between a monitor and the pipeline it runs, with which the pipeline can
+/// be killed, and which has the server's pool of workers its stages may be taken from.
+///
+/// All the external stages of a pipeline are placed in the same process group, whose
+/// leader is the first of them to start: signalling the group takes down every stage,
+/// along with any processes the filters may have started themselves. Stages taken from
+/// the pool lead process groups of their own.
+#[derive(Default)]
+struct PipelineControl {
+    /// Set once the pipeline is killed. Builtin stages, which can't be signalled, check it
+    /// on every read, and no further stages are started once it is set.
+    killed: AtomicBool,
+    /// Set once the pipeline is killed as its task was cancelled, rather than by the server
+    /// shutting down, so that it isn't resumed from its checkpoint.
+    cancelled: AtomicBool,
+    /// Set while the pipeline is suspended, see [`Monitor::suspend`]. Builtin stages, which
+    /// can't be signalled, check it on every read, and wait for it to be cleared.
+    suspended: AtomicBool,
+    /// IDs of the process groups of the task's pipelines, each while it can be signalled:
+    /// from the moment its leader starts, until right before the leader is reaped. A task
+    /// runs several pipelines at once when its input is split in chunks.
+    ///
+    /// Stages are started with this held, so that a stage can't be started after the
+    /// pipeline was killed without being killed as well.
+    pgids: Mutex<Vec<u32>>,
+    /// Workers of external filters, started ahead of time, see [`WorkerPool`].
+    pool: Option<Arc<WorkerPool>>,
+    /// Outputs of past tasks, which the pipeline is skipped for, if it is cached, see
+    /// [`run_pipeline`].
+    cache: Option<Arc<ResultCache>>,
+    /// Engine WASM stages are run by, if the server has any WASM filter.
+    wasm: Option<Arc<WasmRuntime>>,
+    /// How often the pipeline's progress is reported, see [`report_progress`].
+    progress_interval: Duration,
+    /// Bytes of the buffers of the pipeline's pipes, if not the kernel's default, see [`pipe`].
+    pipe_buffer: Option<usize>,
+    /// How the pipeline's external stages are confined, see [`spawn_pipeline`].
+    sandbox: Sandbox,
+    /// Variables set for the pipeline's external stages, and the directory they run in.
+    environment: StageEnvironment,
+    /// Directories the files the pipeline opens must be in, see [`run_pipeline`].
+    path_policy: PathPolicy,
+}
+
+impl PipelineControl {
+    fn pgids(&self) -> MutexGuard<'_, Vec<u32>> {
+        // A `Vec<u32>` can't be left in an inconsistent state by a panicking thread.
+        self.pgids.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn is_killed(&self) -> bool {
+        self.killed.load(Ordering::SeqCst)
+    }
+
+    fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::SeqCst)
+    }
+
+    fn is_suspended(&self) -> bool {
+        self.suspended.load(Ordering::SeqCst)
+    }
+}
+
+/// Information returned by a monitor on a successful return, and relayed to the client.
+///
+/// Checksums let clients verify the integrity of the files, and e.g. detect accidental
+/// truncation, or check that an `encrypt`/`decrypt` round trip gives back the original file.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct MonitorSuccess {
+    /// Size of the input file in bytes.
+    pub bytes_in: u64,
+    /// Size of the output file in bytes.
+    pub bytes_out: u64,
+    /// Size of the output relative to the input's, `bytes_out / bytes_in`, e.g. below `1`
+    /// for a pipeline that compresses, unless the input is empty.
+    pub ratio: Option<f64>,
+    /// How long the task took to run, from its start to its output being committed.
+    pub elapsed: Duration,
+    /// Input processed per second over `elapsed`, in MB (10^6 bytes), unless no time was
+    /// measured.
+    pub throughput: Option<f64>,
+    /// Hex-encoded SHA-256 hash of the input file's contents.
+    pub sha256_in: String,
+    /// Hex-encoded SHA-256 hash of the output file's contents.
+    pub sha256_out: String,
+    /// How long the task waited between being received by the server and starting.
+    pub queue_wait: Duration,
+    /// When each stage of the pipeline ran, in pipeline order.
+    pub stage_timings: Vec<StageTiming>,
+    /// Resources used by the pipeline's filters.
+    pub resource_usage: ResourceUsage,
+    /// Excerpt of what the pipeline's filters wrote to `stderr`, see [`stderr_excerpt`],
+    /// which is usually empty.
+    pub stderr: String,
+    /// Whether the output was copied from the server's result cache, no filter being run,
+    /// see [`ResultCache`].
+    pub cached: bool,
+    /// Path of the output in the server's store, named by `sha256_out`, if it was written
+    /// there, see [`ClientTask::store`](client_task::ClientTask::store).
+    pub stored: Option<PathBuf>,
+}
+
+impl MonitorSuccess {
+    /// The summary of a task that took `elapsed` to run, with its throughput.
+    fn timed(self, elapsed: Duration) -> Self {
+        let secs = elapsed.as_secs_f64();
+        let throughput = (secs > 0.0).then(|| self.bytes_in as f64 / 1e6 / secs);
+        MonitorSuccess { elapsed, throughput, ..self }
+    }
+}
+
+/// Information returned by a monitor on a successful return, depending on whether its task
+/// was a batch, see [`batch::is_batch`].
+#[derive(Debug)]
+pub enum TaskSummary {
+    File(MonitorSuccess),
+    Batch(BatchSummary),
+}
+
+/// Totals of a batch task, relayed to the client once every file was processed.
+///
+/// The results for each file are relayed as they are known, see [`BatchFileResult`].
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct BatchSummary {
+    /// Number of files the batch's input matched.
+    pub files: usize,
+    /// Number of those files the pipeline failed on.
+    pub failed: usize,
+    /// Total size of the input files the pipeline succeeded on, in bytes.
+    pub bytes_in: u64,
+    /// Total size of the output files, in bytes.
+    pub bytes_out: u64,
+    /// How long the task waited between being received by the server and starting.
+    pub queue_wait: Duration,
+    /// Resources used by the filters, across every file the pipeline succeeded on.
+    pub resource_usage: ResourceUsage,
+}
+
+/// Result of a batch task's pipeline on one of its files, sent by its monitor as soon as
+/// it is known.
+pub struct BatchFileResult {
+    pub task_number: usize,
+    pub input: PathBuf,
+    pub output: PathBuf,
+    pub result: Result<MonitorSuccess, MonitorError>,
+    pub partial_output: Option<PartialOutput>
+}
+
+/// Wall-clock timing of a stage of a pipeline, relative to the moment the pipeline started.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct StageTiming {
+    pub filter: Filter,
+    pub start: Duration,
+    pub end: Duration,
+}
 
-    /// Client request the monitor is responsible for.
-    pub task: client_task::ClientTask,
+impl Display for StageTiming {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{:.3}s-{:.3}s ({:.3}s)",
+            self.start.as_secs_f64(),
+            self.end.as_secs_f64(),
+            self.end.saturating_sub(self.start).as_secs_f64()
+        )
+    }
 }
 
-/// Information returned by a monitor on a successful return.
+/// Resources used by the external stages of a pipeline, as reported by `wait4` when each
+/// is reaped, so that their usage can be accounted for, and billed.
 ///
-/// Size of the input and output files in bytes.
-pub type MonitorSuccess = (u64, u64);
+/// Builtin stages run in the server's own process, and are not accounted for.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct ResourceUsage {
+    /// CPU time spent in user mode.
+    pub user_time: Duration,
+    /// CPU time spent in kernel mode.
+    pub system_time: Duration,
+    /// Largest resident set size of any one of the processes, in KiB.
+    pub max_rss_kib: u64,
+}
+
+impl ResourceUsage {
+    fn from_rusage(usage: &libc::rusage) -> Self {
+        let duration = |time: libc::timeval| {
+            Duration::from_secs(time.tv_sec as u64) + Duration::from_micros(time.tv_usec as u64)
+        };
+        ResourceUsage {
+            user_time: duration(usage.ru_utime),
+            system_time: duration(usage.ru_stime),
+            max_rss_kib: usage.ru_maxrss as u64,
+        }
+    }
+
+    /// Usage of both `self`'s and `other`'s processes: CPU times add up, but the largest
+    /// resident set size is that of a single process.
+    pub fn combine(self, other: ResourceUsage) -> Self {
+        ResourceUsage {
+            user_time: self.user_time + other.user_time,
+            system_time: self.system_time + other.system_time,
+            max_rss_kib: self.max_rss_kib.max(other.max_rss_kib),
+        }
+    }
+}
+
+impl Display for ResourceUsage {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "cpu time: {:.3}s user, {:.3}s system; max rss: {} KiB",
+            self.user_time.as_secs_f64(),
+            self.system_time.as_secs_f64(),
+            self.max_rss_kib
+        )
+    }
+}
+
+/// What a pipeline that succeeded reports: when each of its stages ran, the resources
+/// they used, and what they wrote to `stderr`.
+struct PipelineRun {
+    stage_timings: Vec<StageTiming>,
+    resource_usage: ResourceUsage,
+    stderr: String,
+}
+
+/// The stage of a pipeline to blame for its failure, relayed to the client.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct FailedStage {
+    /// Position of the stage in the pipeline, starting at 0.
+    pub index: usize,
+    pub filter: Filter,
+    pub failure: StageFailure,
+}
+
+/// How a stage of a pipeline failed.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub enum StageFailure {
+    /// The external filter exited with this code.
+    Exited(i32),
+    /// The external filter was killed by this signal.
+    Signaled(i32),
+    /// The builtin filter failed with this error.
+    Builtin(String),
+    /// The builtin filter's output was closed by the next stage before it was done writing.
+    BrokenPipe,
+    /// The WASM filter trapped, or couldn't be started, for this reason, see
+    /// [`WasmRuntime`].
+    Trapped(String),
+}
+
+impl StageFailure {
+    /// Whether the stage failed because the stage after it stopped reading its output.
+    ///
+    /// A stage failing usually makes the ones before it fail in this way, so such
+    /// failures are only blamed for the pipeline's failure when there is no other.
+    /// Shells report a process killed by `SIGPIPE` as having exited with code 141,
+    /// which covers filters that are shell scripts.
+    fn is_broken_pipe(&self) -> bool {
+        match self {
+            Self::Signaled(signal) => *signal == libc::SIGPIPE,
+            Self::Exited(code) => *code == 128 + libc::SIGPIPE,
+            Self::Builtin(_) | Self::Trapped(_) => false,
+            Self::BrokenPipe => true,
+        }
+    }
+}
+
+impl Display for FailedStage {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "stage {} ({}) ", self.index, self.filter)?;
+        match &self.failure {
+            StageFailure::Exited(code)    => write!(f, "exited with code {}", code),
+            StageFailure::Signaled(signal) => write!(f, "was killed by signal {}", signal),
+            StageFailure::Builtin(err)    => write!(f, "failed: {}", err),
+            StageFailure::BrokenPipe      => write!(f, "failed: broken pipe"),
+            StageFailure::Trapped(reason) => write!(f, "trapped: {}", reason),
+        }
+    }
+}
+
+/// What a monitor did with the partial output of a failed pipeline.
+#[derive(Debug)]
+pub enum PartialOutput {
+    /// The partial output at this path was deleted.
+    Removed(PathBuf),
+    /// The partial output at this path could not be deleted, and was left behind.
+    Left(PathBuf, io::Error),
+    /// The partial output at this path was kept, for the task to be resumed from its
+    /// checkpoint once the server restarts, see [`Checkpoint`].
+    Checkpointed(PathBuf),
+}
 
 /// Result type of a monitor. It'll return:
 ///
-/// * the thread ID of the monitor assigned to the task, and
-///   * either the `ExitStatus` of the the pipeline and the total of bytes read/written,
+/// * the number of the task, identifying its monitor, and
+///   * either a summary of the files the task read and wrote,
 ///   * or a `MonitorError`.
+/// * if the pipeline failed after creating its output, what became of it.
 pub struct MonitorResult {
-    pub thread: ThreadId,
-    pub result: Result<MonitorSuccess, MonitorError>
+    pub task_number: usize,
+    pub result: Result<TaskSummary, MonitorError>,
+    pub partial_output: Option<PartialOutput>
+}
+
+/// Progress of a running pipeline, periodically sent by its monitor while the
+/// pipeline's output grows.
+pub struct MonitorProgress {
+    pub task_number: usize,
+    /// Bytes written to the output so far, across every file for a batch task.
+    pub bytes_out: u64
 }
 
 impl Monitor {
+    /// Start a monitor running `task` on a thread of the `monitors` pool, where `executors`
+    /// says how to run each of the task's filters, in order, and external filters are
+    /// subject to `resource_limits`, confined by the `sandbox`, and run in the `environment`.
+    ///
+    /// Restartable tasks are given the path of their `checkpoint`, which they resume
+    /// from if it exists, see [`Checkpoint`]. External stages are taken from the `pool`,
+    /// if there is one, and it has idle workers. Outputs are looked up in, and added to,
+    /// the result `cache`, if there is one. Its progress is reported every
+    /// `progress_interval`, and its pipes are given buffers of `pipe_buffer` bytes, if any.
+    /// The files it opens must be in the directories of the `path_policy`, that of the task,
+    /// see [`PathPolicy::for_task`].
+    ///
+    /// The monitor runs in the span current as it is built, which the server makes its
+    /// task's, see [`ClientTask::span`](client_task::ClientTask::span).
+    #[allow(clippy::too_many_arguments)]
     pub fn build(
-        task: client_task::ClientTask,
+        task: Arc<client_task::ClientTask>,
         task_number: usize,
-        transformations_path: PathBuf,
-        sender: Sender<messaging::MessageToServer>
+        executors: Vec<FilterExecutor>,
+        resource_limits: ResourceLimits,
+        sandbox: Sandbox,
+        environment: StageEnvironment,
+        sender: Sender<messaging::MessageToServer>,
+        checkpoint: Option<PathBuf>,
+        pool: Option<Arc<WorkerPool>>,
+        cache: Option<Arc<ResultCache>>,
+        wasm: Option<Arc<WasmRuntime>>,
+        progress_interval: Duration,
+        pipe_buffer: Option<usize>,
+        path_policy: PathPolicy,
+        monitors: &MonitorPool
     ) -> Result<Self, MonitorBuildError> {
-        let task_clone = task.clone();
-        let path_clone = transformations_path.clone();
-        let thread = match thread::Builder
-            ::new()
-            .name(format!("Worker-{}", task.client_pid))
-            .spawn(move ||
-                start_pipeline_monitor(
-                    task_clone,
-                    path_clone,
-                    sender
-                ))
-            .map(|handle| handle.thread().clone()) {
-                Err(err) => return Err(MonitorBuildError::ThreadSpawnError(err)),
-                Ok(t) => t
-            };
+        let task_clone = Arc::clone(&task);
+        let control = Arc::new(PipelineControl {
+            pool, cache, wasm, progress_interval, pipe_buffer, sandbox, environment, path_policy, ..Default::default()
+        });
+        let control_clone = Arc::clone(&control);
+        let span = tracing::Span::current();
+        let span_clone = span.clone();
+        let finished = Arc::new(AtomicBool::new(false));
+        let finished_clone = Arc::clone(&finished);
+        let started = monitors.execute(move || {
+            span_clone.in_scope(|| start_pipeline_monitor(
+                task_clone,
+                task_number,
+                executors,
+                resource_limits,
+                control_clone,
+                sender,
+                checkpoint
+            ));
+            // The monitor catches its own panics, see `start_pipeline_monitor`.
+            finished_clone.store(true, Ordering::SeqCst);
+        });
+        if !started {
+            return Err(MonitorBuildError::PoolStopped)
+        }
 
         Ok(Monitor {
             task,
             task_number,
-            thread,
+            started_at: Instant::now(),
+            bytes_out: 0,
+            span,
+            finished,
+            control,
+            worker: None,
         })
     }
 
-    pub fn thread_id(&self) -> ThreadId {
-        self.thread.id()
+    /// A monitor standing for `task`, run as task #`task_number` by the `worker` it was handed
+    /// to, see [`coordinator`](super::server::coordinator), which reports its progress and
+    /// result instead.
+    ///
+    /// Its task is killed by telling the worker to, and it is never finished, its task only
+    /// concluding once the worker reports it did, or leaves.
+    pub fn remote(task: Arc<client_task::ClientTask>, task_number: usize, worker: Arc<WorkerLink>) -> Self {
+        Monitor {
+            task,
+            task_number,
+            started_at: Instant::now(),
+            bytes_out: 0,
+            span: tracing::Span::current(),
+            finished: Arc::new(AtomicBool::new(false)),
+            control: Arc::default(),
+            worker: Some(worker),
+        }
+    }
+
+    /// The worker running the task, if it was handed to one, see [`Monitor::remote`].
+    pub fn worker(&self) -> Option<&WorkerLink> {
+        self.worker.as_deref()
+    }
+
+    /// Whether the monitor is done running, having reported its result to the server.
+    pub fn is_finished(&self) -> bool {
+        self.finished.load(Ordering::SeqCst)
+    }
+
+    /// Kill every stage of the task's pipeline, and prevent the ones yet to start from
+    /// doing so.
+    ///
+    /// External stages are sent `SIGKILL` through their process group, and builtin ones
+    /// fail on their next read. The monitor still reports back to the server as usual,
+    /// with [`MonitorError::Killed`]. A worker running the task is told to kill it.
+    pub fn kill(&self) -> io::Result<()> {
+        self.control.killed.store(true, Ordering::SeqCst);
+        if let Some(worker) = &self.worker {
+            return worker.send(&CoordinatorMessage::Cancel(self.task_number))
+        }
+
+        self.control
+            .pgids()
+            .iter()
+            .map(|pgid| kill_process_group(*pgid))
+            .fold(Ok(()), Result::and)
+    }
+
+    /// Kill the task's pipeline, see [`Monitor::kill`], as its task was cancelled: unlike
+    /// when the server shuts down, the task's checkpoint, if any, is removed.
+    pub fn cancel(&self) -> io::Result<()> {
+        self.control.cancelled.store(true, Ordering::SeqCst);
+        self.kill()
+    }
+
+    /// Whether the task was cancelled already, see [`Monitor::cancel`].
+    pub fn is_cancelled(&self) -> bool {
+        self.control.is_cancelled()
+    }
+
+    /// Suspend every stage of the task's pipeline, until it is resumed, see
+    /// [`Monitor::resume`], including the ones yet to start.
+    ///
+    /// External stages are sent `SIGSTOP` through their process group, and builtin ones
+    /// wait on their next read. Returns whether the pipeline was running, rather than
+    /// suspended already. Tasks run by workers can't be suspended.
+    pub fn suspend(&self) -> io::Result<bool> {
+        self.signal_suspension(true, libc::SIGSTOP)
+    }
+
+    /// Resume the task's pipeline, once suspended, see [`Monitor::suspend`], sending its
+    /// external stages `SIGCONT`. Returns whether the pipeline was suspended.
+    pub fn resume(&self) -> io::Result<bool> {
+        self.signal_suspension(false, libc::SIGCONT)
+    }
+
+    /// Whether the task is suspended, see [`Monitor::suspend`].
+    pub fn is_suspended(&self) -> bool {
+        self.control.is_suspended()
+    }
+
+    /// Mark the pipeline as `suspended`, or not, sending `signal` to its process groups if
+    /// that changed anything.
+    fn signal_suspension(&self, suspended: bool, signal: libc::c_int) -> io::Result<bool> {
+        if self.worker.is_some() {
+            return Err(io::Error::new(io::ErrorKind::Unsupported, "tasks run by workers can't be suspended"))
+        }
+        // Held so that no stage starts in between, see `spawn_pipeline`.
+        let pgids = self.control.pgids();
+        if self.control.suspended.swap(suspended, Ordering::SeqCst) == suspended {
+            return Ok(false)
+        }
+        // Every group is signalled, even once one failed to be.
+        let mut signalled = Ok(true);
+        for pgid in pgids.iter() {
+            signalled = signalled.and(signal_process_group(*pgid, signal).map(|_| true));
+        }
+        signalled
     }
 }
 
-/// Given a client's task and the path to the transformations the server was given
-/// when it began execution, run the tasks to completion.
+/// Send `SIGKILL` to every process in a process group.
 ///
-/// Care is taken to create the necessary output file, and route the child processes'
-/// pipes in the correct order, so that each filter in the pipeline can pipe its output
-/// into the next filter's `STDIN`.
+/// A group whose processes have all exited already is not an error.
+fn kill_process_group(pgid: u32) -> io::Result<()> {
+    signal_process_group(pgid, libc::SIGKILL)
+}
+
+/// Send `signal` to every process in a process group, as [`kill_process_group`] does.
+fn signal_process_group(pgid: u32, signal: libc::c_int) -> io::Result<()> {
+    // SAFETY: `killpg` has no memory safety preconditions.
+    match unsafe { libc::killpg(pgid as libc::pid_t, signal) } {
+        0 => Ok(()),
+        _ => match io::Error::last_os_error() {
+            err if err.raw_os_error() == Some(libc::ESRCH) => Ok(()),
+            err => Err(err),
+        },
+    }
+}
+
+/// A stage of a pipeline, as it is being executed.
+struct RunningStage {
+    started: Instant,
+    process: StageProcess,
+}
+
+/// What executes a stage of a pipeline.
+enum StageProcess {
+    /// Process executing an external filter's binary, and whether it was reaped already,
+    /// see [`wait_stage`]: its ID may then belong to another process.
+    ///
+    /// A process taken from the pool comes with its own pipes, so it has threads copying
+    /// its input, output and `stderr` to and from the pipeline's, see [`start_pooled_stage`].
+    External {
+        child: Child,
+        reaped: bool,
+        copiers: Vec<JoinHandle<io::Result<u64>>>
+    },
+    /// Thread running a builtin filter, until it is joined.
+    Builtin(Option<JoinHandle<io::Result<u64>>>),
+    /// Thread running a WASM filter, until it is joined, see [`WasmRuntime::run`].
+    Wasm(Option<JoinHandle<Result<(), StageFailure>>>),
+}
+
+impl StageProcess {
+    /// Whether the stage has exited, without reaping it if it's a process.
+    fn has_exited(&self) -> bool {
+        match self {
+            StageProcess::External { copiers, .. } if !copiers.iter().all(JoinHandle::is_finished) => false,
+            StageProcess::External { reaped: true, .. } => true,
+            StageProcess::External { child, .. } => {
+                // SAFETY: `siginfo_t` is plain old data, for which all zeroes is valid.
+                let mut info: libc::siginfo_t = unsafe { std::mem::zeroed() };
+                let flags = libc::WEXITED | libc::WNOHANG | libc::WNOWAIT;
+                // SAFETY: `info` is a valid `siginfo_t`, which outlives the call.
+                match unsafe { libc::waitid(libc::P_PID, child.id(), &mut info, flags) } {
+                    // With `WNOHANG`, the PID is left at 0 if the process is still running.
+                    // SAFETY: `waitid` filled in `info`, as a `SIGCHLD` would be.
+                    0 => unsafe { info.si_pid() != 0 },
+                    // The process was already reaped.
+                    _ => true,
+                }
+            },
+            StageProcess::Builtin(handle) => handle.as_ref().is_none_or(JoinHandle::is_finished),
+            StageProcess::Wasm(handle) => handle.as_ref().is_none_or(JoinHandle::is_finished),
+        }
+    }
+}
+
+impl Drop for StageProcess {
+    /// Every process the monitor starts is reaped, so that none is left a zombie: an
+    /// external stage that wasn't waited on, e.g. because the monitor panicked, is killed
+    /// and waited on here.
+    ///
+    /// Builtin and WASM stages are threads, which end on their own once the pipes around
+    /// them close.
+    fn drop(&mut self) {
+        if let StageProcess::External { child, reaped: false, .. } = self {
+            if let Ok(None) = child.try_wait() {
+                let _ = child.kill();
+                let _ = child.wait();
+            }
+        }
+    }
+}
+
+/// Body of a monitor's thread: run the task's pipeline, see [`run_pipeline`], or each of
+/// a batch's pipelines, see [`run_batch`], and report back to the server.
+///
+/// The server is always sent a [`MonitorResult`], even if the monitor fails early, or
+/// panics: otherwise, the task would be considered running, and its filters in use, forever.
 fn start_pipeline_monitor(
-    task: client_task::ClientTask,
-    transformations_path: PathBuf,
-    sender: Sender<messaging::MessageToServer>
-) -> Result<(), MonitorError> {
-    let transfs_execs = task.get_transformations()
-        .iter()
-        .map(|filter| transformations_path.join(filter.to_string()))
-        .collect::<Vec<_>>();
+    task: Arc<client_task::ClientTask>,
+    task_number: usize,
+    executors: Vec<FilterExecutor>,
+    resource_limits: ResourceLimits,
+    control: Arc<PipelineControl>,
+    sender: Sender<messaging::MessageToServer>,
+    checkpoint: Option<PathBuf>
+) {
+    // The pipeline runs on local copies of remote files, downloaded before it starts, and
+    // uploaded once it succeeds.
+    let staging = remote::Staging::of(&task, task_number, &env::temp_dir());
+    let remote_output = task.output_filepath().to_path_buf();
+    let transferred = |result: Result<(), remote::RemoteError>| result.map_err(|err| match err {
+        _ if control.is_killed() => MonitorError::Killed,
+        remote::RemoteError::OutputExists => MonitorError::OutputExists(remote_output.clone()),
+        err => MonitorError::RemoteError(err),
+    });
+    let task = match &staging {
+        Some(staging) => Arc::new(staging.local_task(&task)),
+        None => task,
+    };
+    let mut tmp_output = tmp_output_path(task.output_filepath());
+
+    let result = panic::catch_unwind(AssertUnwindSafe(|| {
+        if let Some(staging) = &staging {
+            transferred(staging.download(&control.killed))?;
+        }
+        let summary = match batch::is_batch(task.input_filepath()) {
+            false => run_pipeline(
+                &task, task_number, &mut tmp_output, &executors, resource_limits, &control, &sender, 0, checkpoint.as_deref()
+            )
+                .map(TaskSummary::File),
+            true => run_batch(&task, task_number, &executors, resource_limits, &control, &sender)
+                .map(TaskSummary::Batch),
+        }?;
+        if let Some(staging) = &staging {
+            transferred(staging.upload(&control.killed))?;
+        }
+        Ok(summary)
+    }))
+    .unwrap_or_else(|payload| Err(MonitorError::Panicked(panic_message(payload.as_ref()))));
+    if let Some(staging) = staging {
+        staging.clean_up();
+    }
+
+    // On failure, the temporary output is at best incomplete: it must not be mistaken
+    // for a valid result. A restartable task killed by the server shutting down, rather
+    // than cancelled, is resumed from its checkpoint once it restarts, using what it
+    // output so far.
+    let partial_output = match (&result, &checkpoint) {
+        (Ok(_), _) => None,
+        (Err(MonitorError::Killed), Some(path)) if path.exists() && !control.is_cancelled() =>
+            Some(PartialOutput::Checkpointed(tmp_output)),
+        (Err(_), _) => remove_partial_output(tmp_output),
+    };
+    if let Some(path) = checkpoint.filter(|_| !matches!(partial_output, Some(PartialOutput::Checkpointed(_)))) {
+        checkpoint::remove(&path);
+    }
+
+    let monitor_result = MonitorResult {
+        task_number,
+        result,
+        partial_output
+    };
+
+    if sender.blocking_send(messaging::MessageToServer::Monitor(monitor_result)).is_err() {
+        log::error!("could not report result of task #{task_number} to the server");
+    }
+}
+
+/// Run the pipeline of a batch task on each of its files in turn, see [`batch::expand`],
+/// creating its output directory if there are any.
+///
+/// The result for each file is sent to the server as soon as it is known. A file failing
+/// does not stop the batch, but the pipeline being killed does.
+fn run_batch(
+    task: &client_task::ClientTask,
+    task_number: usize,
+    executors: &[FilterExecutor],
+    resource_limits: ResourceLimits,
+    control: &Arc<PipelineControl>,
+    sender: &Sender<messaging::MessageToServer>
+) -> Result<BatchSummary, MonitorError> {
+    let queue_wait = task.received_at.map(|at| at.elapsed()).unwrap_or_default();
+
+    let files = batch::expand(task.input_filepath(), task.output_filepath())
+        .map_err(MonitorError::InputFileError)?;
+    if !files.is_empty() {
+        fs::create_dir_all(task.output_filepath()).map_err(MonitorError::OutputFileError)?;
+    }
+
+    let mut summary = BatchSummary {
+        files: files.len(),
+        failed: 0,
+        bytes_in: 0,
+        bytes_out: 0,
+        queue_wait,
+        resource_usage: ResourceUsage::default()
+    };
+    for (input, output) in files {
+        let mut file_task = task.clone();
+        file_task.relocate(input.clone(), output.clone());
+        // The batch waited to be run, not each of its files.
+        file_task.received_at = None;
+
+        let mut tmp_output = tmp_output_path(&output);
+        let result = run_pipeline(
+            &file_task,
+            task_number,
+            &mut tmp_output,
+            executors,
+            resource_limits,
+            control,
+            sender,
+            summary.bytes_out,
+            None
+        );
+        let partial_output = match &result {
+            Ok(success) => {
+                summary.bytes_in += success.bytes_in;
+                summary.bytes_out += success.bytes_out;
+                summary.resource_usage = summary.resource_usage.combine(success.resource_usage);
+                None
+            },
+            Err(_) => {
+                summary.failed += 1;
+                remove_partial_output(tmp_output)
+            },
+        };
+
+        if control.is_killed() {
+            return Err(MonitorError::Killed)
+        }
+        let file_result = BatchFileResult { task_number, input, output, result, partial_output };
+        if sender.blocking_send(messaging::MessageToServer::BatchFile(file_result)).is_err() {
+            log::error!("could not report result of a file of task #{task_number} to the server");
+        }
+    }
+
+    Ok(summary)
+}
+
+/// Given a client's task and how each of its filters is to be run, run the pipeline to completion.
+///
+/// Care is taken to create the necessary output file, and route the stages' pipes in the
+/// correct order, so that each filter in the pipeline can pipe its output into the next
+/// filter's `STDIN`.
+///
+/// If the task asked for its input to be split in chunks, and its pipeline allows it, that
+/// many pipelines are run at once instead, see [`run_chunks`]. If `checkpoint` is given,
+/// the task is restartable: its pipeline runs on one segment of its input after the other,
+/// saving how far it got there after each, see [`run_checkpointed`].
+///
+/// The pipeline writes to a temporary file, `tmp_output`, see [`tmp_output_path`], which
+/// only replaces the requested output once the pipeline succeeds: a failed pipeline never
+/// destroys a pre-existing output. It is created anew, under another name if there is
+/// already a file by that name, see [`create_tmp_output`].
+///
+/// The task's paths were checked as it was queued, see [`PathPolicy::denied`], but may lead
+/// elsewhere by now, e.g. if a symlink on them was replaced: the files are checked again
+/// once opened, and only read and written through what was opened from then on.
+///
+/// If the server caches results, and the task isn't checkpointed, the output is copied
+/// from the cache instead, if it has the output of the same pipeline on the same input
+/// contents, see [`cache::key`]. Otherwise, the output is cached once committed.
+///
+/// Its progress is reported on top of `bytes_done`, the output of the previous files of
+/// a batch.
+#[allow(clippy::too_many_arguments)]
+fn run_pipeline(
+    task: &client_task::ClientTask,
+    task_number: usize,
+    tmp_output: &mut PathBuf,
+    executors: &[FilterExecutor],
+    resource_limits: ResourceLimits,
+    control: &Arc<PipelineControl>,
+    sender: &Sender<messaging::MessageToServer>,
+    bytes_done: u64,
+    checkpoint: Option<&Path>
+) -> Result<MonitorSuccess, MonitorError> {
+    let queue_wait = task.received_at.map(|at| at.elapsed()).unwrap_or_default();
+    let started = Instant::now();
 
     let input_fd = fs::File::options()
         .read(true)
         .open(task.input_filepath())
         .map_err(MonitorError::InputFileError)?;
-    let output_fd = fs::File::options()
-        .read(true)
-        .write(true)
-        .create(true)
-        .truncate(true)
-        .open(task.output_filepath())
-        .map_err(MonitorError::OutputFileError)?;
+    if !control.path_policy.allows_input(&input_fd) {
+        return Err(MonitorError::PathNotAllowed(task.input_filepath().to_path_buf()))
+    }
+    // Checked again, atomically, once the pipeline is done, see `commit_output`: failing
+    // early just avoids running a pipeline in vain.
+    if task.no_clobber && task.output_filepath().exists() {
+        return Err(MonitorError::OutputExists(task.output_filepath().to_path_buf()))
+    }
+    let (mut output_fd, checkpoint) = match checkpoint {
+        None => {
+            let output_fd = create_tmp_output(task.output_filepath(), tmp_output).map_err(MonitorError::OutputFileError)?;
+            (output_fd, None)
+        },
+        Some(path) => {
+            let (checkpoint, output_fd) = resume_checkpoint(task, path, tmp_output, &input_fd)?;
+            (output_fd, Some((path, checkpoint)))
+        },
+    };
+    if !control.path_policy.allows_output(&output_fd) {
+        return Err(MonitorError::PathNotAllowed(task.output_filepath().to_path_buf()))
+    }
 
-    if transfs_execs.is_empty() {
+    let tmp_output: &Path = tmp_output;
+    if executors.is_empty() {
         return Err(MonitorError::NoTransformationsGiven)
     }
 
-    let mut transformations: Vec<Exec> = Vec::new();
-    for transf in transfs_execs.iter() {
-        transformations.push(Exec::cmd(transf));
-    }
-
-    let result = if transformations.len() == 1 {
-        let mut exec = transformations.remove(0);
-        // The first and only filter in the pipeline must read from the file in the client's request,
-        // and write to the provided file as well.
-        exec = exec.stdin(input_fd);
-        exec = exec.stdout(output_fd);
-        exec.join()
-    } else {
-        let mut pipeline = Pipeline::from_exec_iter(transformations);
-        // The first filter in the pipeline must read from the file in the client's request
-        pipeline = pipeline.stdin(input_fd);
-        // The last filter writes to the created output file.
-        pipeline = pipeline.stdout(output_fd);
-    
-        pipeline.join()
-    }
-    .map_err(|err| { MonitorError::PipelineFailure(err) });
-
-    let result = match result {
-        Ok(status) if status.success() => {
-            let (bytes_in, bytes_out): (u64, u64) = (
-                match fs::metadata(task.input_filepath()) {
-                    Err(err) => return Err(MonitorError::InputFileMetadataError(err)),
-                    Ok(meta) => meta.len()
-                },
-                match fs::metadata(task.output_filepath()) {
-                    Err(err) => return Err(MonitorError::OutputFileMetadataError(err)),
-                    Ok(meta) => meta.len()
+    let cached = match control.cache.as_ref().filter(|_| checkpoint.is_none()) {
+        None => None,
+        Some(cache) => {
+            let sha256_in = sha256_file(&fd_path(&input_fd)).map_err(MonitorError::ChecksumError)?;
+            let key = cache::key(&sha256_in, &task.transformations, executors);
+            match cache.fetch(&key, &mut output_fd) {
+                Ok(true) => {
+                    log::info!("output of task #{task_number} copied from the result cache");
+                    commit_output(task, tmp_output, &output_fd)?;
+                    let elapsed = started.elapsed();
+                    return Ok(MonitorSuccess { queue_wait, cached: true, ..summarize_files(&input_fd, &output_fd)?.timed(elapsed) })
                 },
+                Ok(false) => {},
+                Err(err) => log::warn!("could not read the result cache for task #{task_number}: {:?}", err),
+            }
+            Some((cache, sha256_in, key))
+        },
+    };
+
+    let splittable = checkpoint.is_none() && chunking::is_splittable(&task.transformations);
+    let chunks = match task.chunks > 1 && splittable {
+        false => Vec::new(),
+        true => {
+            let input_len = input_fd.metadata().map_err(MonitorError::InputFileMetadataError)?.len();
+            chunking::chunk_ranges(input_len, task.chunks)
+        },
+    };
+    let outputs = match chunks.len() > 1 {
+        false => vec![tmp_output.to_path_buf()],
+        true => (0..chunks.len()).map(|chunk| chunk_output_path(tmp_output, chunk)).collect(),
+    };
+
+    // The output is handed over, and summarized, through its file once the pipeline is done
+    // with it, as is the input.
+    let tmp_output_fd = output_fd.try_clone().map_err(MonitorError::OutputFileError)?;
+    let input = &input_fd;
+    let pipeline_start = Instant::now();
+    let PipelineRun { stage_timings, resource_usage, stderr } = thread::scope(|scope| {
+        // Progress is reported until the last stage is reaped: it is no longer needed by then.
+        let (stop_progress, stopped) = mpsc::channel();
+        let progress_sender = sender.clone();
+        let (progress_outputs, progress_interval) = (&outputs, control.progress_interval);
+        let span = tracing::Span::current();
+        if let Err(err) = thread::Builder::new()
+            .name(format!("Progress-{}", task.client_pid))
+            .spawn_scoped(scope, move || span.in_scope(||
+                report_progress(task_number, progress_outputs, bytes_done, progress_interval, progress_sender, stopped)))
+        {
+            log::warn!("could not spawn thread to report progress of task #{task_number}: {:?}", err);
+        }
+
+        let run = match (checkpoint, chunks.len() > 1) {
+            (Some((path, checkpoint)), _) =>
+                run_checkpointed(task, path, checkpoint, input, output_fd, executors, &resource_limits, control, pipeline_start),
+            (None, false) => match input.try_clone() {
+                Ok(input) => execute_pipeline(task, input, output_fd, executors, &resource_limits, control, pipeline_start),
+                Err(err) => Err(MonitorError::InputFileError(err)),
+            },
+            (None, true) =>
+                run_chunks(task, &chunks, &outputs, input, output_fd, executors, &resource_limits, control, pipeline_start),
+        };
+
+        drop(stop_progress);
+        run
+    })?;
+
+    commit_output(task, tmp_output, &tmp_output_fd)?;
+    let summary = summarize_files(&input_fd, &tmp_output_fd)?.timed(started.elapsed());
+    // Not cached if the input changed while the pipeline ran, as the output isn't then that
+    // of the contents it would be keyed by.
+    if let Some((cache, _, key)) = cached.filter(|(_, sha256_in, _)| *sha256_in == summary.sha256_in) {
+        if let Err(err) = cache.store(&key, &fd_path(&tmp_output_fd)) {
+            log::warn!("could not cache the output of task #{task_number}: {:?}", err);
+        }
+    }
+    Ok(MonitorSuccess { queue_wait, stage_timings, resource_usage, stderr, ..summary })
+}
+
+/// Run a pipeline from `input` to `output`, returning when each of its stages ran,
+/// relative to `pipeline_start`, if it succeeded.
+fn execute_pipeline(
+    task: &client_task::ClientTask,
+    input: fs::File,
+    output: fs::File,
+    executors: &[FilterExecutor],
+    resource_limits: &ResourceLimits,
+    control: &Arc<PipelineControl>,
+    pipeline_start: Instant
+) -> Result<PipelineRun, MonitorError> {
+    // Each filter's `stderr` is sent to its own file, to be read after the pipeline finishes.
+    let mut stderr_files: Vec<fs::File> = Vec::new();
+    for stage in 0..executors.len() {
+        stderr_files.push(stderr_scratch_file(stage).map_err(MonitorError::StderrFileError)?);
+    }
+
+    let (mut stages, spawn_error) =
+        spawn_pipeline(task, executors, resource_limits, input, output, &stderr_files, control);
+
+    let filters = &task.transformations;
+    let stage_timings = wait_exits(&stages)
+        .into_iter()
+        .zip(&stages)
+        .zip(filters)
+        .map(|((ended, stage), filter)| StageTiming {
+            filter: filter.clone(),
+            start: stage.started - pipeline_start,
+            end: ended - pipeline_start,
+        })
+        .collect::<Vec<_>>();
+
+    // Stages are reaped last to first. This way the group leader, the first external stage,
+    // is reaped last: until then its ID can't be reused, and `Monitor::kill` can't signal
+    // some unrelated process group. Likewise, the groups of stages taken from the pool
+    // are forgotten before their leaders are reaped.
+    let mut stage_results = Vec::new();
+    let mut resource_usage = ResourceUsage::default();
+    for (stage, running) in stages.iter_mut().enumerate().rev() {
+        if let StageProcess::External { child, .. } = &running.process {
+            control.pgids().retain(|pgid| *pgid != child.id());
+        }
+        stage_results.push(
+            wait_stage(stage, &filters[stage], &mut running.process)
+                .map(|usage| resource_usage = resource_usage.combine(usage))
+        );
+    }
+    stage_results.reverse();
+
+    let stderrs = filters
+        .iter()
+        .cloned()
+        .zip(stderr_files.iter_mut().map(read_stderr_file))
+        .collect::<Vec<_>>();
+    for (stage, (filter, stderr)) in stderrs.iter().enumerate() {
+        if !stderr.is_empty() {
+            log::warn!(
+                "stage {stage} ({filter}) of task by client {} wrote to stderr:\n{stderr}",
+                task.client_pid
             );
-            Ok((bytes_in, bytes_out))
+        }
+    }
+
+    // A stage failing to start takes precedence, since it makes the others fail as well.
+    // Likewise, when the pipeline is killed, its stages' failures are only a consequence.
+    let first_failure = match spawn_error {
+        _ if control.is_killed() => Some(MonitorError::Killed),
+        Some(err) => Some(err),
+        None => blame_failure(stage_results),
+    };
+    match first_failure {
+        None => Ok(PipelineRun { stage_timings, resource_usage, stderr: stderr_excerpt(&stderrs) }),
+        Some(MonitorError::StageError { stage, .. }) =>
+            Err(MonitorError::StageError {
+                stage,
+                stderr: stderr_excerpt(&stderrs)
+            }),
+        Some(err) => Err(err),
+    }
+}
+
+/// Run a pipeline on each of the `chunks` of a task's input, at once, each writing to the
+/// corresponding file of `chunk_outputs`, then concatenate those into `output`.
+///
+/// Each chunk is fed to its pipeline through a pipe by a thread of its own. Should any
+/// pipeline fail, the first one to, in chunk order, is reported. The timings returned are,
+/// for each stage, from its earliest start to its latest end across every chunk.
+#[allow(clippy::too_many_arguments)]
+fn run_chunks(
+    task: &client_task::ClientTask,
+    chunks: &[(u64, u64)],
+    chunk_outputs: &[PathBuf],
+    input: &fs::File,
+    mut output: fs::File,
+    executors: &[FilterExecutor],
+    resource_limits: &ResourceLimits,
+    control: &Arc<PipelineControl>,
+    pipeline_start: Instant
+) -> Result<PipelineRun, MonitorError> {
+    let run_chunk = |range: (u64, u64), chunk_output: &Path| {
+        let chunk_output = create_new_output(chunk_output).map_err(MonitorError::OutputFileError)?;
+        execute_on_range(task, input, range, chunk_output, executors, resource_limits, control, pipeline_start)
+    };
+
+    let span = tracing::Span::current();
+    let results = thread::scope(|scope| {
+        let chunk_runs = chunks
+            .iter()
+            .zip(chunk_outputs)
+            .map(|(chunk, chunk_output)| scope.spawn(|| span.in_scope(|| run_chunk(*chunk, chunk_output))))
+            .collect::<Vec<_>>();
+        chunk_runs
+            .into_iter()
+            .map(|run| run.join().unwrap_or_else(|payload| Err(MonitorError::Panicked(panic_message(payload.as_ref())))))
+            .collect::<Vec<_>>()
+    });
+
+    let concatenated = match results.iter().any(Result::is_err) {
+        true => Ok(()),
+        false => chunk_outputs
+            .iter()
+            .try_for_each(|chunk_output| fs::File::open(chunk_output)
+                .and_then(|mut chunk_output| io::copy(&mut chunk_output, &mut output))
+                .map(|_| ()))
+            .map_err(MonitorError::OutputFileError),
+    };
+    for chunk_output in chunk_outputs {
+        if let Some(PartialOutput::Left(path, err)) = remove_partial_output(chunk_output.clone()) {
+            log::warn!("could not remove chunk output {:?}: {:?}", path, err);
+        }
+    }
+
+    if control.is_killed() {
+        return Err(MonitorError::Killed)
+    }
+    let chunk_runs = results.into_iter().collect::<Result<Vec<_>, _>>()?;
+    concatenated?;
+
+    Ok(merge_runs(task, &chunk_runs))
+}
+
+/// Run a pipeline on the input of a task whose filters are all restartable, from where
+/// `checkpoint`, saved at `checkpoint_path`, says it got to, appending to `output`.
+///
+/// The input is processed in segments of [`CHECKPOINT_INTERVAL`](checkpoint::CHECKPOINT_INTERVAL)
+/// bytes, each by a pipeline of its own, and the checkpoint saved once each segment's
+/// output is on disk.
+#[allow(clippy::too_many_arguments)]
+fn run_checkpointed(
+    task: &client_task::ClientTask,
+    checkpoint_path: &Path,
+    mut checkpoint: Checkpoint,
+    input: &fs::File,
+    output: fs::File,
+    executors: &[FilterExecutor],
+    resource_limits: &ResourceLimits,
+    control: &Arc<PipelineControl>,
+    pipeline_start: Instant
+) -> Result<PipelineRun, MonitorError> {
+    let mut segment_runs = Vec::new();
+
+    // An empty input still goes through the pipeline once, e.g. for `gcompress` to write
+    // the header of an empty stream.
+    while checkpoint.input_offset < checkpoint.input_len || checkpoint.input_len == 0 && segment_runs.is_empty() {
+        let len = checkpoint::CHECKPOINT_INTERVAL.min(checkpoint.input_len - checkpoint.input_offset);
+        let segment_output = output.try_clone().map_err(MonitorError::OutputFileError)?;
+        segment_runs.push(execute_on_range(
+            task, input, (checkpoint.input_offset, len), segment_output, executors, resource_limits, control, pipeline_start
+        )?);
+
+        output.sync_data().map_err(MonitorError::OutputFileError)?;
+        checkpoint.input_offset += len;
+        checkpoint.output_len = output.metadata().map_err(MonitorError::OutputFileMetadataError)?.len();
+        checkpoint.save(checkpoint_path).map_err(MonitorError::CheckpointError)?;
+    }
+
+    Ok(merge_runs(task, &segment_runs))
+}
+
+/// Resume the task checkpointed at `checkpoint_path`, if it was interrupted, moving what
+/// it output so far to `tmp_output`, and start it over otherwise: if it never ran, or if
+/// its input changed since.
+///
+/// Returns the checkpoint to run it from, see [`run_checkpointed`], and its temporary
+/// output, to be appended to.
+fn resume_checkpoint(
+    task: &client_task::ClientTask,
+    checkpoint_path: &Path,
+    tmp_output: &mut PathBuf,
+    input: &fs::File
+) -> Result<(Checkpoint, fs::File), MonitorError> {
+    let input_meta = input.metadata().map_err(MonitorError::InputFileMetadataError)?;
+
+    let mut output = create_tmp_output(task.output_filepath(), tmp_output).map_err(MonitorError::OutputFileError)?;
+    let mut checkpoint = match Checkpoint::load(checkpoint_path) {
+        Ok(checkpoint) if checkpoint.matches_input(&input_meta) &&
+            fs::rename(&checkpoint.tmp_output, &*tmp_output).is_ok() => {
+            log::info!(
+                "resuming task by client {} from byte {} of its input",
+                task.client_pid, checkpoint.input_offset
+            );
+            // What was output so far took the place of the file just created.
+            output = fs::File::options()
+                .write(true)
+                .custom_flags(libc::O_NOFOLLOW)
+                .open(&*tmp_output)
+                .map_err(MonitorError::OutputFileError)?;
+            checkpoint
+        },
+        loaded => {
+            if let Ok(stale) = loaded {
+                log::warn!("input of task by client {} changed since it was interrupted, starting over", task.client_pid);
+                remove_partial_output(stale.tmp_output);
+            }
+            Checkpoint::new(task.clone(), tmp_output.to_path_buf(), &input_meta)
+                .map_err(MonitorError::InputFileMetadataError)?
         },
-        Ok(status) => Err(MonitorError::PipelineExitStatusError(status)),
-        Err(err) => Err(err)
     };
+    checkpoint.tmp_output = tmp_output.clone();
 
-    let thread = thread::current().id();
-    let monitor_result = MonitorResult {
-        thread,
-        result
+    // Whatever was output after the checkpoint was saved is output again.
+    output.set_len(checkpoint.output_len).map_err(MonitorError::OutputFileError)?;
+    output.seek(io::SeekFrom::End(0)).map_err(MonitorError::OutputFileError)?;
+
+    checkpoint.save(checkpoint_path).map_err(MonitorError::CheckpointError)?;
+    Ok((checkpoint, output))
+}
+
+/// Run a pipeline on the `(offset, len)` range of a task's `input`, fed to it through a
+/// pipe by a thread of its own, writing to `output`.
+#[allow(clippy::too_many_arguments)]
+fn execute_on_range(
+    task: &client_task::ClientTask,
+    input: &fs::File,
+    (offset, len): (u64, u64),
+    output: fs::File,
+    executors: &[FilterExecutor],
+    resource_limits: &ResourceLimits,
+    control: &Arc<PipelineControl>,
+    pipeline_start: Instant
+) -> Result<PipelineRun, MonitorError> {
+    // Opened anew, so as to be read from an offset of its own, as other chunks may be at once.
+    let mut input = fs::File::open(fd_path(input)).map_err(MonitorError::InputFileError)?;
+    input.seek(io::SeekFrom::Start(offset)).map_err(MonitorError::InputFileError)?;
+    let (reader, mut writer) = pipe(control.pipe_buffer).map_err(MonitorError::PipeCreationError)?;
+
+    thread::scope(|scope| {
+        // A pipeline failing early closes the pipe, which stops the feeder as well.
+        let feeder = scope.spawn(move || io::copy(&mut input.take(len), &mut writer));
+        let reader = fs::File::from(OwnedFd::from(reader));
+        let result = execute_pipeline(task, reader, output, executors, resource_limits, control, pipeline_start);
+        match feeder.join() {
+            Ok(Err(err)) if result.is_ok() => Err(MonitorError::InputFileError(err)),
+            _ => result,
+        }
+    })
+}
+
+/// What a task's pipeline run as several, on parts of its input, reports as a whole: for
+/// each stage, from its earliest start to its latest end, the resources they all used, and
+/// what each wrote to `stderr`.
+fn merge_runs(task: &client_task::ClientTask, runs: &[PipelineRun]) -> PipelineRun {
+    let stage_timings = task.transformations
+        .iter()
+        .enumerate()
+        .map(|(stage, filter)| StageTiming {
+            filter: filter.clone(),
+            start: runs.iter().map(|run| run.stage_timings[stage].start).min().unwrap_or_default(),
+            end: runs.iter().map(|run| run.stage_timings[stage].end).max().unwrap_or_default(),
+        })
+        .collect();
+    let resource_usage = runs
+        .iter()
+        .fold(ResourceUsage::default(), |usage, run| usage.combine(run.resource_usage));
+    let stderr = runs
+        .iter()
+        .map(|run| run.stderr.as_str())
+        .filter(|stderr| !stderr.is_empty())
+        .collect::<Vec<_>>()
+        .join("\n");
+
+    PipelineRun { stage_timings, resource_usage, stderr: truncate_excerpt(stderr) }
+}
+
+/// Move the temporary output of a successful pipeline, open as `tmp_output_fd`, to the
+/// task's requested output.
+///
+/// If the task forbids replacing an existing output, the output is hard linked instead of
+/// renamed, which fails if it exists, however recently it was created.
+///
+/// It is only moved if `tmp_output` still leads to the file checked once it was opened, see
+/// [`run_pipeline`]: the path may lead elsewhere by now.
+fn commit_output(task: &client_task::ClientTask, tmp_output: &Path, tmp_output_fd: &fs::File) -> Result<(), MonitorError> {
+    let output = task.output_filepath();
+    let opened = tmp_output_fd.metadata().map_err(MonitorError::OutputFileMetadataError)?;
+    let at_path = fs::symlink_metadata(tmp_output).map_err(MonitorError::OutputRenameError)?;
+    if (opened.dev(), opened.ino()) != (at_path.dev(), at_path.ino()) {
+        return Err(MonitorError::PathNotAllowed(output.to_path_buf()))
+    }
+    hand_over_output(task, tmp_output_fd)?;
+    if !task.no_clobber {
+        return fs::rename(tmp_output, output).map_err(MonitorError::OutputRenameError)
+    }
+
+    match fs::hard_link(tmp_output, output) {
+        Err(err) if err.kind() == io::ErrorKind::AlreadyExists =>
+            Err(MonitorError::OutputExists(output.to_path_buf())),
+        Err(err) => Err(MonitorError::OutputRenameError(err)),
+        // The temporary output is only another name for the output, now.
+        Ok(_) => fs::remove_file(tmp_output).map_err(MonitorError::OutputRenameError),
+    }
+}
+
+/// Give the temporary output of a task the mode it asked for, if any, and hand it over to
+/// the task's client's user, if the server could tell who that is, before it takes the
+/// place of the requested output.
+///
+/// Both are changed through the open file, not its path, which someone else could have
+/// replaced with a symlink by then.
+///
+/// Only a server with the privilege to, e.g. running as root, can give its files away, so
+/// failing to is only warned of. The outputs of streamed, and inline, tasks, sent back to
+/// their client, and those in the server's store, stay the server's.
+fn hand_over_output(task: &client_task::ClientTask, tmp_output: &fs::File) -> Result<(), MonitorError> {
+    if task.is_spooled() || task.store {
+        return Ok(())
+    }
+    // SAFETY: `geteuid` always succeeds.
+    if let Some(uid) = task.client_uid.filter(|uid| *uid != unsafe { libc::geteuid() }) {
+        if let Err(err) = std::os::unix::fs::fchown(tmp_output, Some(uid), task.client_gid) {
+            log::warn!("could not hand the output {:?} over to UID {uid}: {err}", task.output_filepath());
+        }
+    }
+    // Set after the owner, as changing it clears some of the bits.
+    match task.mode {
+        Some(mode) => tmp_output.set_permissions(fs::Permissions::from_mode(mode & client_task::MODE_BITS))
+            .map_err(MonitorError::OutputModeError),
+        None => Ok(()),
+    }
+}
+
+/// Remove the temporary output of a failed pipeline, reporting what became of it.
+///
+/// There is nothing to report if the pipeline failed before creating it.
+fn remove_partial_output(tmp_output: PathBuf) -> Option<PartialOutput> {
+    match fs::remove_file(&tmp_output) {
+        Ok(_) => Some(PartialOutput::Removed(tmp_output)),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => None,
+        Err(err) => Some(PartialOutput::Left(tmp_output, err)),
+    }
+}
+
+/// The message a thread panicked with, from the payload of the panic.
+fn panic_message(payload: &(dyn Any + Send)) -> String {
+    match (payload.downcast_ref::<&str>(), payload.downcast_ref::<String>()) {
+        (Some(msg), _) => msg.to_string(),
+        (None, Some(msg)) => msg.clone(),
+        (None, None) => String::from("unknown panic payload"),
+    }
+}
+
+/// Have the kernel give `pipe` a buffer of at least `size` bytes, with `F_SETPIPE_SZ`, so that
+/// a stage writing large outputs blocks, and the next one is woken up, less often. Returns the
+/// size the buffer was given, a power of two number of pages.
+///
+/// Unprivileged processes can't go past `/proc/sys/fs/pipe-max-size`, 1 MiB by default.
+pub fn set_pipe_buffer(pipe: &impl AsRawFd, size: usize) -> io::Result<usize> {
+    let size = libc::c_int::try_from(size).unwrap_or(libc::c_int::MAX);
+    // SAFETY: `F_SETPIPE_SZ` takes an integer, and no pointers.
+    match unsafe { libc::fcntl(pipe.as_raw_fd(), libc::F_SETPIPE_SZ, size) } {
+        given if given >= 0 => Ok(given as usize),
+        _ => Err(io::Error::last_os_error()),
+    }
+}
+
+/// A pipe, with a buffer of `buffer` bytes if given, see [`set_pipe_buffer`].
+///
+/// A buffer that can't be made as large is left as it is: the server checks whether it can
+/// be as it starts, and warns if it can't.
+fn pipe(buffer: Option<usize>) -> io::Result<(io::PipeReader, io::PipeWriter)> {
+    let (reader, writer) = io::pipe()?;
+    if let Some(size) = buffer {
+        if let Err(err) = set_pipe_buffer(&writer, size) {
+            log::debug!("could not give pipe a buffer of {size} bytes: {:?}", err);
+        }
+    }
+    Ok((reader, writer))
+}
+
+/// Start every stage of a pipeline, connecting each stage's output to the next one's
+/// input, the first stage's input to `input` and the last stage's output to `output`.
+///
+/// If a stage cannot be started, the stages after it are not either, and the error is
+/// returned alongside the stages already running. Those must still be waited on: since
+/// every file descriptor not handed to a stage is closed when this function returns,
+/// they'll see the end of their input, or a broken pipe, and exit.
+///
+/// External stages are placed in the pipeline's process group, see [`PipelineControl`],
+/// and run with `resource_limits`, confined to the `task`'s input file and output directory
+/// if the server sandboxes filters, unless they are taken from the pool, see
+/// [`start_pooled_stage`].
+fn spawn_pipeline(
+    task: &client_task::ClientTask,
+    executors: &[FilterExecutor],
+    resource_limits: &ResourceLimits,
+    input: fs::File,
+    output: fs::File,
+    stderr_files: &[fs::File],
+    control: &Arc<PipelineControl>
+) -> (Vec<RunningStage>, Option<MonitorError>) {
+    let mut stages = Vec::new();
+    let mut pgid = None;
+    let mut stage_input = input;
+    let mut output = Some(output);
+
+    for (stage, (executor, stderr)) in executors.iter().zip(stderr_files).enumerate() {
+        let (stage_output, next_input) = if stage == executors.len() - 1 {
+            // Only the last stage gets here, and it's the only one to take the output.
+            (output.take().unwrap(), None)
+        } else {
+            match pipe(control.pipe_buffer) {
+                Err(err) => return (stages, Some(MonitorError::PipeCreationError(err))),
+                Ok((reader, writer)) => (
+                    fs::File::from(OwnedFd::from(writer)),
+                    Some(fs::File::from(OwnedFd::from(reader)))
+                ),
+            }
+        };
+
+        let stderr = match stderr.try_clone() {
+            Err(err) => return (stages, Some(MonitorError::StderrFileError(err))),
+            Ok(f) => f,
+        };
+
+        let mut pgids = control.pgids();
+        if control.is_killed() {
+            return (stages, Some(MonitorError::Killed));
+        }
+
+        let started = Instant::now();
+        let worker = match executor {
+            FilterExecutor::External(path) => control.pool.as_ref().and_then(|pool| pool.take(path)),
+            FilterExecutor::Builtin(_) | FilterExecutor::Wasm(_) => None,
+        };
+        let process = match (executor, worker) {
+            (_, Some(worker)) => {
+                let pid = worker.child.id();
+                start_pooled_stage(worker, stage_input, stage_output, stderr)
+                    .inspect(|_| pgids.push(pid))
+            },
+            (FilterExecutor::External(path), None) => {
+                let output_dir = match task.output_filepath().parent() {
+                    Some(dir) if !dir.as_os_str().is_empty() => dir,
+                    _ => Path::new("."),
+                };
+                control.sandbox.confine(path, task.input_filepath(), output_dir).and_then(|confinement| {
+                    let mut command = Command::new(path);
+                    command
+                        .stdin(stage_input)
+                        .stdout(stage_output)
+                        .stderr(stderr)
+                        // A process group of 0 makes the first external stage the group's leader.
+                        .process_group(pgid.unwrap_or(0) as i32);
+                    control.environment.apply(&mut command);
+                    let resource_limits = *resource_limits;
+                    // SAFETY: `ResourceLimits::apply` and `Confinement::enter` only make system
+                    // calls, which are async-signal-safe.
+                    unsafe {
+                        command.pre_exec(move || {
+                            resource_limits.apply()?;
+                            confinement.enter()
+                        })
+                    };
+                    command
+                        .spawn()
+                        .inspect(|child| if pgid.is_none() {
+                            pgid = Some(child.id());
+                            pgids.push(child.id());
+                        })
+                        .map(|child| StageProcess::External { child, reaped: false, copiers: Vec::new() })
+                })
+            },
+            (FilterExecutor::Builtin(filter), None) => {
+                let filter = filter.clone();
+                let control = Arc::clone(control);
+                thread::Builder::new()
+                    .name(format!("Builtin-{filter}"))
+                    .spawn(move ||
+                        run_builtin_stage(filter, stage_input, stage_output, stderr, control))
+                    .map(|handle| StageProcess::Builtin(Some(handle)))
+            },
+            (FilterExecutor::Wasm(path), None) => match &control.wasm {
+                None => Err(io::Error::new(io::ErrorKind::Unsupported, "the server has no WASM runtime")),
+                Some(runtime) => {
+                    let (runtime, path) = (Arc::clone(runtime), path.clone());
+                    let name = path.file_stem().unwrap_or_default().to_string_lossy().into_owned();
+                    let input = KillableReader { inner: stage_input, control: Arc::clone(control) };
+                    let control = Arc::clone(control);
+                    thread::Builder::new()
+                        .name(format!("Wasm-{name}"))
+                        .spawn(move || runtime.run(&name, &path, input, stage_output, stderr, move || control.is_killed()))
+                        .map(|handle| StageProcess::Wasm(Some(handle)))
+                },
+            },
+        };
+
+        match process {
+            Err(err) => return (stages, Some(MonitorError::StageSpawnError(executor.clone(), err))),
+            Ok(process) => stages.push(RunningStage { started, process }),
+        }
+        // A stage started while the pipeline is suspended is stopped along with its group.
+        if control.is_suspended() {
+            for pgid in pgids.iter() {
+                if let Err(err) = signal_process_group(*pgid, libc::SIGSTOP) {
+                    log::warn!("could not suspend process group {pgid}: {:?}", err);
+                }
+            }
+        }
+
+        match next_input {
+            None => break,
+            Some(next_input) => stage_input = next_input,
+        }
+    }
+
+    (stages, None)
+}
+
+/// Run a stage of a pipeline on a `worker` taken from the pool, with threads copying the
+/// stage's `input` to the worker's, and the worker's output and `stderr` to `output` and
+/// `stderr`.
+///
+/// The copying threads end on their own once the worker exits, and are joined when the
+/// stage is waited on, see [`wait_stage`].
+fn start_pooled_stage(
+    worker: Worker,
+    input: fs::File,
+    output: fs::File,
+    stderr: fs::File
+) -> io::Result<StageProcess> {
+    let Worker { child, stdin, stdout, stderr: worker_stderr } = worker;
+    let pipes = [
+        (input, fs::File::from(OwnedFd::from(stdin))),
+        (fs::File::from(OwnedFd::from(stdout)), output),
+        (fs::File::from(OwnedFd::from(worker_stderr)), stderr),
+    ];
+
+    // Dropped, and so killed and reaped, if the copying threads can't all be started.
+    let mut stage = StageProcess::External { child, reaped: false, copiers: Vec::new() };
+    for (mut from, mut to) in pipes {
+        let copier = thread::Builder::new()
+            .name(String::from("Pooled-stage-copier"))
+            .spawn(move || io::copy(&mut from, &mut to))?;
+        if let StageProcess::External { copiers, .. } = &mut stage {
+            copiers.push(copier);
+        }
+    }
+    Ok(stage)
+}
+
+/// Body of the thread reporting the progress of the pipeline of task #`task_number`,
+/// which writes to `outputs`, one per chunk of its input: every `interval` until
+/// `stop` is signalled, or its sender dropped, the total size of the outputs, plus
+/// `bytes_done`, is sent to the server, if it changed since last time.
+///
+/// Reports are dropped while the server's channel is full, the next one superseding them.
+fn report_progress(
+    task_number: usize,
+    outputs: &[PathBuf],
+    bytes_done: u64,
+    interval: Duration,
+    sender: Sender<messaging::MessageToServer>,
+    stop: Receiver<()>
+) {
+    let mut last_bytes_out = bytes_done;
+
+    while let Err(RecvTimeoutError::Timeout) = stop.recv_timeout(interval) {
+        let bytes_out = bytes_done + outputs
+            .iter()
+            .filter_map(|output| fs::metadata(output).ok())
+            .map(|meta| meta.len())
+            .sum::<u64>();
+        if bytes_out == last_bytes_out {
+            continue;
+        }
+        last_bytes_out = bytes_out;
+
+        let progress = MonitorProgress { task_number, bytes_out };
+        match sender.try_send(messaging::MessageToServer::Progress(progress)) {
+            Err(TrySendError::Closed(_)) => break,
+            Err(TrySendError::Full(_)) => log::trace!("dropped progress report of task #{task_number}, the server being busy"),
+            Ok(()) => {},
+        }
+    }
+}
+
+/// How often builtin stages check whether their suspended pipeline was resumed, see
+/// [`KillableReader`].
+const SUSPENDED_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Reader failing as soon as its pipeline is killed, for builtin stages to stop early, and
+/// waiting while it is suspended.
+struct KillableReader<R> {
+    inner: R,
+    control: Arc<PipelineControl>,
+}
+
+impl<R: Read> Read for KillableReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.control.is_suspended() && !self.control.is_killed() {
+            thread::sleep(SUSPENDED_POLL_INTERVAL);
+        }
+        if self.control.is_killed() {
+            return Err(io::Error::other("pipeline was killed"));
+        }
+        self.inner.read(buf)
+    }
+}
+
+/// Body of the thread running a builtin pipeline stage.
+///
+/// Errors are also written to the stage's `stderr` file, like an external filter would.
+fn run_builtin_stage(
+    filter: Filter,
+    input: fs::File,
+    output: fs::File,
+    mut stderr: fs::File,
+    control: Arc<PipelineControl>
+) -> io::Result<u64> {
+    let input = KillableReader { inner: input, control };
+    builtin::run_filter(&filter, input, output).inspect_err(|err| {
+        // Nowhere left to report this to: the stage's failure is reported regardless.
+        let _ = writeln!(stderr, "{err}");
+    })
+}
+
+/// Wait for the pipeline stage at position `index`, running `filter`, to finish,
+/// returning the resources it used, or an error if it failed.
+fn wait_stage(index: usize, filter: &Filter, stage: &mut StageProcess) -> Result<ResourceUsage, MonitorError> {
+    let failure = match stage {
+        // Only a stage that was already waited on is reaped.
+        StageProcess::External { reaped: true, .. } => return Ok(ResourceUsage::default()),
+        StageProcess::External { child, reaped, copiers } => match wait_process(child.id()) {
+            Err(err) => return Err(MonitorError::PipelineFailure(err)),
+            Ok((status, usage)) if status.success() => {
+                *reaped = true;
+                join_copiers(copiers)?;
+                return Ok(usage)
+            },
+            Ok((status, _)) => match (status.code(), status.signal()) {
+                (Some(code), _) => StageFailure::Exited(code),
+                (None, Some(signal)) => StageFailure::Signaled(signal),
+                // A process that was waited on either exited or was killed by a signal.
+                (None, None) => unreachable!(),
+            },
+        },
+        StageProcess::Builtin(handle) => match handle.take().map(JoinHandle::join) {
+            // Only a stage that was already waited on is missing its handle.
+            None => return Ok(ResourceUsage::default()),
+            Some(Err(_)) => StageFailure::Builtin(String::from("builtin filter panicked")),
+            Some(Ok(Err(err))) if err.kind() == io::ErrorKind::BrokenPipe => StageFailure::BrokenPipe,
+            Some(Ok(Err(err))) => StageFailure::Builtin(err.to_string()),
+            Some(Ok(Ok(_))) => return Ok(ResourceUsage::default()),
+        },
+        StageProcess::Wasm(handle) => match handle.take().map(JoinHandle::join) {
+            // Only a stage that was already waited on is missing its handle.
+            None | Some(Ok(Ok(()))) => return Ok(ResourceUsage::default()),
+            Some(Err(_)) => StageFailure::Trapped(String::from("WASM filter panicked")),
+            Some(Ok(Err(failure))) => failure,
+        },
     };
+    if let StageProcess::External { reaped, .. } = stage {
+        *reaped = true;
+    }
+
+    Err(MonitorError::StageError {
+        stage: FailedStage { index, filter: filter.clone(), failure },
+        stderr: String::new()
+    })
+}
 
-    let result = messaging::MessageToServer::Monitor(monitor_result);
+/// Wait for the threads copying to and from a stage taken from the pool to finish, see
+/// [`start_pooled_stage`].
+///
+/// The stage's input being cut short by it exiting is not an error of the copy: whether
+/// the stage failed is up to its exit status.
+fn join_copiers(copiers: &mut Vec<JoinHandle<io::Result<u64>>>) -> Result<(), MonitorError> {
+    for copier in copiers.drain(..) {
+        match copier.join() {
+            Err(_) => return Err(MonitorError::PipelineFailure(io::Error::other("copying thread panicked"))),
+            Ok(Err(err)) if err.kind() != io::ErrorKind::BrokenPipe => return Err(MonitorError::PipelineFailure(err)),
+            Ok(_) => {},
+        }
+    }
+    Ok(())
+}
+
+/// Wait for the child process `pid` to exit, and reap it, returning its exit status and
+/// the resources it used.
+fn wait_process(pid: u32) -> io::Result<(ExitStatus, ResourceUsage)> {
+    let mut status = 0;
+    // SAFETY: `rusage` is plain old data, for which all zeroes is valid.
+    let mut usage: libc::rusage = unsafe { std::mem::zeroed() };
+    loop {
+        // SAFETY: `status` and `usage` are valid, and outlive the call.
+        match unsafe { libc::wait4(pid as libc::pid_t, &mut status, 0, &mut usage) } {
+            -1 => match io::Error::last_os_error() {
+                err if err.kind() == io::ErrorKind::Interrupted => continue,
+                err => return Err(err),
+            },
+            _ => return Ok((ExitStatus::from_raw(status), ResourceUsage::from_rusage(&usage))),
+        }
+    }
+}
 
-    sender.send(result).map_err(|_| MonitorError::MpscSenderError)
+/// Wait for every stage of a pipeline to exit, without reaping any, returning when each
+/// of them did, as observed every [`STAGE_POLL_INTERVAL`].
+fn wait_exits(stages: &[RunningStage]) -> Vec<Instant> {
+    let mut ended = vec![None; stages.len()];
+    loop {
+        let now = Instant::now();
+        for (stage, ended) in stages.iter().zip(ended.iter_mut()) {
+            if ended.is_none() && stage.process.has_exited() {
+                *ended = Some(now);
+            }
+        }
+
+        if ended.iter().all(Option::is_some) {
+            return ended.into_iter().flatten().collect()
+        }
+        thread::sleep(STAGE_POLL_INTERVAL);
+    }
+}
+
+/// Pick, among the results of a pipeline's stages, in pipeline order, the failure to
+/// report for the whole pipeline, if any.
+///
+/// That is the first one not caused by a later stage exiting early, see
+/// [`StageFailure::is_broken_pipe`], or the first one if they all are.
+fn blame_failure(stage_results: Vec<Result<(), MonitorError>>) -> Option<MonitorError> {
+    let mut failures = stage_results
+        .into_iter()
+        .filter_map(Result::err)
+        .collect::<Vec<_>>();
+    let culprit = failures
+        .iter()
+        .position(|err| !matches!(
+            err,
+            MonitorError::StageError { stage, .. } if stage.failure.is_broken_pipe()
+        ))
+        .unwrap_or(0);
+
+    match failures.is_empty() {
+        true => None,
+        false => Some(failures.swap_remove(culprit)),
+    }
+}
+
+/// Path of the file the pipeline running on chunk `chunk` of a task's input writes to:
+/// `<tmp_output>.<chunk>`.
+fn chunk_output_path(tmp_output: &Path, chunk: usize) -> PathBuf {
+    let mut chunk_output = tmp_output.as_os_str().to_owned();
+    chunk_output.push(format!(".{chunk}"));
+    PathBuf::from(chunk_output)
+}
+
+/// Path of a temporary file a task's pipeline may write to: `<output>.tmp.<random>`.
+///
+/// It lives in the same directory as the requested output, so that it can be
+/// atomically renamed into place. That directory may be writable by others, who must not
+/// be able to guess the path and put a file, or a symlink, there first.
+pub fn tmp_output_path(output: &Path) -> PathBuf {
+    let mut tmp_output = output.as_os_str().to_owned();
+    tmp_output.push(format!(".tmp.{}", uuid::Uuid::new_v4().simple()));
+    PathBuf::from(tmp_output)
+}
+
+/// Create the temporary output of a task at `tmp_output`, see [`create_new_output`], or at
+/// another path for its requested `output` if there is already a file there, up to
+/// [`TMP_OUTPUT_ATTEMPTS`] times, updating `tmp_output` to the path it was created at.
+fn create_tmp_output(output: &Path, tmp_output: &mut PathBuf) -> io::Result<fs::File> {
+    let mut attempts = 1;
+    loop {
+        match create_new_output(tmp_output) {
+            Err(err) if err.kind() == io::ErrorKind::AlreadyExists && attempts < TMP_OUTPUT_ATTEMPTS => {
+                attempts += 1;
+                *tmp_output = tmp_output_path(output);
+            },
+            created => return created,
+        }
+    }
+}
+
+/// Create a file a pipeline writes to, failing if there is already one at `path`, even a
+/// symlink: one put there by someone else would have the server write wherever it points.
+fn create_new_output(path: &Path) -> io::Result<fs::File> {
+    fs::File::options()
+        .read(true)
+        .write(true)
+        .create_new(true)
+        .custom_flags(libc::O_NOFOLLOW)
+        .open(path)
+}
+
+/// Size, in bytes, and checksums of a finished task's `input` and `output` files, without
+/// timings.
+fn summarize_files(input: &fs::File, output: &fs::File) -> Result<MonitorSuccess, MonitorError> {
+    let bytes_in = input.metadata().map_err(MonitorError::InputFileMetadataError)?.len();
+    let bytes_out = output.metadata().map_err(MonitorError::OutputFileMetadataError)?.len();
+
+    let sha256_in = sha256_file(&fd_path(input)).map_err(MonitorError::ChecksumError)?;
+    let sha256_out = sha256_file(&fd_path(output)).map_err(MonitorError::ChecksumError)?;
+
+    Ok(MonitorSuccess {
+        bytes_in,
+        bytes_out,
+        ratio: (bytes_in > 0).then(|| bytes_out as f64 / bytes_in as f64),
+        elapsed: Duration::ZERO,
+        throughput: None,
+        sha256_in,
+        sha256_out,
+        queue_wait: Duration::ZERO,
+        stage_timings: Vec::new(),
+        resource_usage: ResourceUsage::default(),
+        stderr: String::new(),
+        cached: false,
+        stored: None
+    })
+}
+
+/// Path through which the open `file` is opened anew: the file itself, wherever the path it
+/// was opened at leads by now.
+fn fd_path(file: &fs::File) -> PathBuf {
+    Path::new("/proc/self/fd").join(file.as_raw_fd().to_string())
+}
+
+/// Hex-encoded SHA-256 hash of a file's contents.
+pub fn sha256_file(path: &Path) -> io::Result<String> {
+    let mut hasher = Sha256::new();
+    io::copy(&mut fs::File::open(path)?, &mut hasher)?;
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+/// Create the file a pipeline stage's `stderr` is redirected to.
+///
+/// The file is unlinked right after being created, so that it disappears with the
+/// monitor's file descriptors no matter how the pipeline ends.
+fn stderr_scratch_file(stage: usize) -> io::Result<fs::File> {
+    let path = std::env::temp_dir().join(format!(
+        "sdstore_stderr_{}_{:?}_{}",
+        std::process::id(),
+        thread::current().id(),
+        stage
+    ));
+    let file = fs::File::options()
+        .read(true)
+        .write(true)
+        .create_new(true)
+        .open(&path)?;
+    fs::remove_file(&path)?;
+
+    Ok(file)
+}
+
+/// Read back everything a pipeline stage wrote to its `stderr` file.
+///
+/// Failing to do so is not worth failing the task over, so it's only logged.
+fn read_stderr_file(file: &mut fs::File) -> String {
+    let mut bytes = Vec::new();
+    if let Err(err) = file.rewind().and_then(|_| file.read_to_end(&mut bytes)) {
+        log::warn!("could not read filter stderr: {:?}", err);
+    }
+
+    String::from_utf8_lossy(&bytes).trim_end().to_string()
+}
+
+/// Join the `stderr` of every stage that wrote to it, prefixed by the stage's filter,
+/// into an excerpt of at most [`STDERR_EXCERPT_LEN`] bytes to be sent to the client.
+pub fn stderr_excerpt(stderrs: &[(Filter, String)]) -> String {
+    let excerpt = stderrs
+        .iter()
+        .filter(|(_, stderr)| !stderr.is_empty())
+        .map(|(filter, stderr)| format!("{filter}: {stderr}"))
+        .collect::<Vec<_>>()
+        .join("\n");
+    truncate_excerpt(excerpt)
+}
+
+/// `excerpt`, cut short to [`STDERR_EXCERPT_LEN`] bytes, and marked as such, if it is longer.
+fn truncate_excerpt(mut excerpt: String) -> String {
+    if excerpt.len() > STDERR_EXCERPT_LEN {
+        let mut end = STDERR_EXCERPT_LEN;
+        while !excerpt.is_char_boundary(end) {
+            end -= 1;
+        }
+        excerpt.truncate(end);
+        excerpt.push_str("...");
+    }
+
+    excerpt
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::sync::mpsc::{channel, error::TryRecvError};
+
+    use crate::core::{server::cache::{CacheConfig, DEFAULT_CACHE_MAX_SIZE}, testing::TempDir};
+
+    use super::*;
+
+    #[test]
+    fn stderr_excerpt_skips_empty_and_truncates() {
+        let stderrs = vec![
+            (Filter::Nop, String::new()),
+            (Filter::Encrypt, String::from("bad key")),
+            (Filter::Decrypt, "é".repeat(STDERR_EXCERPT_LEN)),
+        ];
+
+        let excerpt = stderr_excerpt(&stderrs);
+        assert!(excerpt.starts_with("encrypt: bad key\ndecrypt: é"));
+        assert!(excerpt.ends_with("..."));
+        assert!(excerpt.len() <= STDERR_EXCERPT_LEN + "...".len());
+    }
+
+    #[test]
+    fn pipes_are_given_larger_buffers() {
+        let (_, writer) = pipe(Some(256 << 10)).unwrap();
+        // SAFETY: `F_GETPIPE_SZ` takes no argument.
+        assert_eq!(unsafe { libc::fcntl(writer.as_raw_fd(), libc::F_GETPIPE_SZ) }, 256 << 10);
+        // Buffers are a power of two number of pages.
+        assert_eq!(set_pipe_buffer(&writer, (512 << 10) - 1).unwrap(), 512 << 10);
+    }
+
+    fn receive_result(receiver: &mut tokio::sync::mpsc::Receiver<messaging::MessageToServer>) -> MonitorResult {
+        let deadline = Instant::now() + Duration::from_secs(10);
+        loop {
+            match receiver.try_recv() {
+                Ok(messaging::MessageToServer::Monitor(result)) => return result,
+                Ok(_) => panic!("expected a monitor result"),
+                Err(TryRecvError::Empty) if Instant::now() < deadline => thread::sleep(Duration::from_millis(10)),
+                Err(err) => panic!("no monitor result: {err:?}"),
+            }
+        }
+    }
+
+    #[test]
+    fn monitor_always_reports_back() {
+        let dir = TempDir::new("report");
+        let input = dir.join("input");
+        fs::write(&input, "some input").unwrap();
+        let monitors = MonitorPool::new(1).unwrap();
+        let run = |input: PathBuf, executors| {
+            let task = client_task::ClientTask::new(0, 0, input, dir.join("output"), vec![Filter::Nop]);
+            let (sender, mut receiver) = channel(16);
+            Monitor::build(Arc::new(task), 0, executors, ResourceLimits::default(), Sandbox::default(), StageEnvironment::default(), sender, None, None, None, None, DEFAULT_PROGRESS_INTERVAL, None, PathPolicy::default(), &monitors).unwrap();
+            receive_result(&mut receiver)
+        };
+
+        // Failing before the pipeline starts.
+        let result = run(dir.join("missing"), vec![FilterExecutor::Builtin(Filter::Nop)]);
+        assert!(matches!(result.result, Err(MonitorError::InputFileError(_))));
+        assert!(result.partial_output.is_none());
+
+        // Panicking, here because there are more executors than filters.
+        let executors = vec![FilterExecutor::Builtin(Filter::Nop), FilterExecutor::Builtin(Filter::Nop)];
+        let result = run(input, executors);
+        assert!(matches!(result.result, Err(MonitorError::Panicked(_))));
+        assert!(matches!(result.partial_output, Some(PartialOutput::Removed(_))));
+    }
+
+    #[test]
+    fn cached_outputs_skip_the_pipeline() {
+        let dir = TempDir::new("cached");
+        let config = CacheConfig { dir: dir.join("cache"), max_size: DEFAULT_CACHE_MAX_SIZE };
+        let cache = Arc::new(ResultCache::open(config).unwrap());
+        fs::write(dir.join("input"), "cached input").unwrap();
+        let monitors = MonitorPool::new(1).unwrap();
+        let run = |output: &str| {
+            let task = client_task::ClientTask::new(0, 0, dir.join("input"), dir.join(output), vec![Filter::Nop]);
+            let (sender, mut receiver) = channel(16);
+            let executors = vec![FilterExecutor::Builtin(Filter::Nop)];
+            Monitor::build(Arc::new(task), 0, executors, ResourceLimits::default(), Sandbox::default(), StageEnvironment::default(), sender, None, None, Some(Arc::clone(&cache)), None, DEFAULT_PROGRESS_INTERVAL, None, PathPolicy::default(), &monitors)
+                .unwrap();
+            match receive_result(&mut receiver).result {
+                Ok(TaskSummary::File(summary)) => summary,
+                result => panic!("task failed: {result:?}"),
+            }
+        };
+
+        let first = run("first");
+        assert!(!first.cached && !first.stage_timings.is_empty());
+        let second = run("second");
+        assert!(second.cached && second.stage_timings.is_empty());
+        assert_eq!((second.sha256_in, second.sha256_out), (first.sha256_in, first.sha256_out));
+        assert_eq!(fs::read_to_string(dir.join("second")).unwrap(), "cached input");
+    }
+
+    #[test]
+    fn outputs_are_handed_over() {
+        use std::os::unix::fs::MetadataExt;
+
+        let dir = TempDir::new("hand_over");
+        let tmp_output = dir.join("output.tmp.0");
+        fs::write(&tmp_output, "output").unwrap();
+        fs::set_permissions(&tmp_output, fs::Permissions::from_mode(0o644)).unwrap();
+        let mode = || fs::metadata(&tmp_output).unwrap().mode() & 0o7777;
+
+        let mut task = client_task::ClientTask::new(0, 0, dir.join("input"), dir.join("output"), vec![Filter::Nop]);
+        task.store = true;
+        task.mode = Some(0o600);
+        hand_over_output(&task, &fs::File::open(&tmp_output).unwrap()).unwrap();
+        assert_eq!(mode(), 0o644);
+
+        task.store = false;
+        (task.client_uid, task.client_gid) = (Some(65534), Some(65534));
+        hand_over_output(&task, &fs::File::open(&tmp_output).unwrap()).unwrap();
+        assert_eq!(mode(), 0o600);
+        // Only a privileged server can give its files away, which is then only warned of.
+        // SAFETY: `geteuid` always succeeds.
+        if unsafe { libc::geteuid() } == 0 {
+            let metadata = fs::metadata(&tmp_output).unwrap();
+            assert_eq!((metadata.uid(), metadata.gid()), (65534, 65534));
+        }
+    }
+
+    #[test]
+    fn tmp_outputs_are_created_anew() {
+        let dir = TempDir::new("tmp_output");
+        let (output, target) = (dir.join("output"), dir.join("target"));
+        fs::write(&target, "not the server's").unwrap();
+
+        // Whether a symlink or a file someone else put there first, it is left alone.
+        let mut tmp_output = tmp_output_path(&output);
+        std::os::unix::fs::symlink(&target, &tmp_output).unwrap();
+        assert_eq!(create_new_output(&tmp_output).unwrap_err().kind(), io::ErrorKind::AlreadyExists);
+        let taken = tmp_output.clone();
+        create_tmp_output(&output, &mut tmp_output).unwrap().write_all(b"output").unwrap();
+
+        assert_ne!(tmp_output, taken);
+        assert!(tmp_output.starts_with(&dir));
+        assert_eq!(fs::read_to_string(&tmp_output).unwrap(), "output");
+        assert_eq!(fs::read_to_string(&target).unwrap(), "not the server's");
+    }
+
+    #[test]
+    fn files_are_checked_once_opened() {
+        let dir = TempDir::new("opened");
+        for sub in ["inputs", "outputs", "secrets"] {
+            fs::create_dir_all(dir.join(sub)).unwrap();
+        }
+        fs::write(dir.join("inputs/in"), "input").unwrap();
+        fs::write(dir.join("secrets/in"), "secret").unwrap();
+        // Both were in the allowed directories as the task was queued, but lead out of them by
+        // the time it runs.
+        std::os::unix::fs::symlink(dir.join("secrets/in"), dir.join("inputs/swapped")).unwrap();
+        std::os::unix::fs::symlink(dir.join("secrets"), dir.join("outputs/swapped")).unwrap();
+        let policy = PathPolicy { inputs: Some(vec![dir.join("inputs")]), outputs: Some(vec![dir.join("outputs")]) };
+        let monitors = MonitorPool::new(1).unwrap();
+        let run = |input: &str, output: &str| {
+            let task = client_task::ClientTask::new(0, 0, dir.join(input), dir.join(output), vec![Filter::Nop]);
+            let (sender, mut receiver) = channel(16);
+            let executors = vec![FilterExecutor::Builtin(Filter::Nop)];
+            Monitor::build(Arc::new(task), 0, executors, ResourceLimits::default(), Sandbox::default(), StageEnvironment::default(), sender, None, None, None, None, DEFAULT_PROGRESS_INTERVAL, None, policy.clone(), &monitors)
+                .unwrap();
+            receive_result(&mut receiver).result
+        };
+
+        assert!(run("inputs/in", "outputs/out").is_ok());
+        assert!(matches!(run("inputs/swapped", "outputs/out"), Err(MonitorError::PathNotAllowed(path)) if path == dir.join("inputs/swapped")));
+        assert!(matches!(run("inputs/in", "outputs/swapped/out"), Err(MonitorError::PathNotAllowed(path)) if path == dir.join("outputs/swapped/out")));
+        assert_eq!(fs::read_dir(dir.join("secrets")).unwrap().count(), 1);
+
+        // Nor is a temporary output moved once its path leads elsewhere.
+        let task = client_task::ClientTask::new(0, 0, dir.join("inputs/in"), dir.join("outputs/out"), vec![Filter::Nop]);
+        let tmp_output = tmp_output_path(&dir.join("outputs/out"));
+        let tmp_output_fd = create_new_output(&tmp_output).unwrap();
+        fs::remove_file(&tmp_output).unwrap();
+        fs::write(&tmp_output, "replaced").unwrap();
+        assert!(matches!(commit_output(&task, &tmp_output, &tmp_output_fd), Err(MonitorError::PathNotAllowed(_))));
+        assert_eq!(fs::read_to_string(dir.join("outputs/out")).unwrap(), "input");
+    }
+
+    #[test]
+    fn checkpointed_task_resumes() {
+        let dir = TempDir::new("resume");
+        let (input, output) = (dir.join("input"), dir.join("output"));
+        fs::write(&input, "resumed input").unwrap();
+        let task = client_task::ClientTask::new(0, 0, input.clone(), output.clone(), vec![Filter::Nop]);
+
+        // Interrupted after its first 8 bytes, and some of the output for the next ones.
+        let old_tmp_output = dir.join("old_tmp");
+        fs::write(&old_tmp_output, "resumed garbage").unwrap();
+        let mut checkpoint = Checkpoint::new(task.clone(), old_tmp_output, &fs::metadata(&input).unwrap()).unwrap();
+        checkpoint.input_offset = 8;
+        checkpoint.output_len = 8;
+        let checkpoint_path = dir.join("task.ckpt");
+        checkpoint.save(&checkpoint_path).unwrap();
+
+        let (sender, mut receiver) = channel(16);
+        let executors = vec![FilterExecutor::Builtin(Filter::Nop)];
+        let monitors = MonitorPool::new(1).unwrap();
+        Monitor::build(Arc::new(task), 0, executors, ResourceLimits::default(), Sandbox::default(), StageEnvironment::default(), sender, Some(checkpoint_path.clone()), None, None, None, DEFAULT_PROGRESS_INTERVAL, None, PathPolicy::default(), &monitors)
+            .unwrap();
+        let result = receive_result(&mut receiver);
+
+        assert!(matches!(result.result, Ok(TaskSummary::File(_))));
+        assert_eq!(fs::read_to_string(&output).unwrap(), "resumed input");
+        assert!(!checkpoint_path.exists());
+    }
+
+    fn stage_error(index: usize, failure: StageFailure) -> Result<(), MonitorError> {
+        Err(MonitorError::StageError {
+            stage: FailedStage { index, filter: Filter::Nop, failure },
+            stderr: String::new()
+        })
+    }
+
+    #[test]
+    fn blame_skips_broken_pipes() {
+        let blamed = |results| match blame_failure(results) {
+            Some(MonitorError::StageError { stage, .. }) => Some((stage.index, stage.failure)),
+            None => None,
+            Some(err) => panic!("unexpected error {:?}", err),
+        };
+
+        assert_eq!(blamed(vec![Ok(()), Ok(())]), None);
+        assert_eq!(
+            blamed(vec![
+                stage_error(0, StageFailure::Signaled(libc::SIGPIPE)),
+                stage_error(1, StageFailure::BrokenPipe),
+                stage_error(2, StageFailure::Exited(3)),
+                stage_error(3, StageFailure::Exited(1)),
+            ]),
+            Some((2, StageFailure::Exited(3)))
+        );
+        assert_eq!(
+            blamed(vec![
+                Ok(()),
+                stage_error(1, StageFailure::Exited(141)),
+                stage_error(2, StageFailure::BrokenPipe),
+            ]),
+            Some((1, StageFailure::Exited(141)))
+        );
+    }
+
+    #[test]
+    fn kill_takes_down_every_stage() {
+        use std::time::{Duration, Instant};
+
+        let dir = TempDir::new("kill");
+        // The filter's own child must be killed too, or it'd hold the pipe open.
+        let filter = dir.join("slow");
+        let pids = dir.join("pids");
+        fs::write(&filter, format!("#!/bin/sh\necho $$ >> {}\nsleep 30 && cat\n", pids.display())).unwrap();
+        fs::set_permissions(&filter, fs::Permissions::from_mode(0o755)).unwrap();
+        let input = dir.join("input");
+        fs::write(&input, "some input").unwrap();
+
+        let task = client_task::ClientTask::new(
+            0,
+            0,
+            input,
+            dir.join("output"),
+            vec![Filter::Nop, Filter::Nop, Filter::Nop]
+        );
+        let executors = vec![
+            FilterExecutor::External(filter.clone()),
+            FilterExecutor::Builtin(Filter::Nop),
+            FilterExecutor::External(filter),
+        ];
+        let (sender, mut receiver) = channel(16);
+        let monitors = MonitorPool::new(1).unwrap();
+        let monitor =
+            Monitor::build(Arc::new(task), 0, executors, ResourceLimits::default(), Sandbox::default(), StageEnvironment::default(), sender, None, None, None, None, DEFAULT_PROGRESS_INTERVAL, None, PathPolicy::default(), &monitors).unwrap();
+
+        // Give the pipeline time to start.
+        thread::sleep(Duration::from_millis(200));
+        let start = Instant::now();
+        monitor.kill().unwrap();
+        let result = receive_result(&mut receiver);
+
+        assert!(matches!(result.result, Err(MonitorError::Killed)));
+        assert!(matches!(result.partial_output, Some(PartialOutput::Removed(_))));
+        assert!(start.elapsed() < Duration::from_secs(10));
+        assert!(!dir.join("output").exists());
+        for pid in fs::read_to_string(&pids).unwrap().lines() {
+            assert_reaped(pid.parse().unwrap());
+        }
+    }
+
+    #[test]
+    fn external_stages_run_in_their_environment() {
+        let dir = TempDir::new("environment");
+        fs::create_dir_all(dir.join("cwd")).unwrap();
+        let filter = dir.join("greet");
+        fs::write(&filter, "#!/bin/sh\ncat > /dev/null\necho \"$GREETING from $(pwd)\"\n").unwrap();
+        fs::set_permissions(&filter, fs::Permissions::from_mode(0o755)).unwrap();
+        let input = dir.join("input");
+        fs::write(&input, "some input").unwrap();
+
+        let task = client_task::ClientTask::new(0, 0, input, dir.join("output"), vec![Filter::Nop]);
+        let environment = StageEnvironment {
+            vars: [(String::from("GREETING"), String::from("hello"))].into(),
+            cwd: Some(dir.join("cwd")),
+        };
+        let (sender, mut receiver) = channel(16);
+        let monitors = MonitorPool::new(1).unwrap();
+        let _monitor =
+            Monitor::build(Arc::new(task), 0, vec![FilterExecutor::External(filter)], ResourceLimits::default(), Sandbox::default(), environment, sender, None, None, None, None, DEFAULT_PROGRESS_INTERVAL, None, PathPolicy::default(), &monitors).unwrap();
+
+        assert!(receive_result(&mut receiver).result.is_ok());
+        let cwd = fs::canonicalize(dir.join("cwd")).unwrap();
+        assert_eq!(fs::read_to_string(dir.join("output")).unwrap(), format!("hello from {}\n", cwd.display()));
+    }
+
+    #[test]
+    fn suspended_pipelines_stop_until_resumed() {
+        use std::time::Duration;
+
+        let dir = TempDir::new("suspend");
+        let filter = dir.join("slow");
+        let pids = dir.join("pids");
+        fs::write(&filter, format!("#!/bin/sh\necho $$ >> {}\nsleep 1 && cat\n", pids.display())).unwrap();
+        fs::set_permissions(&filter, fs::Permissions::from_mode(0o755)).unwrap();
+        let input = dir.join("input");
+        fs::write(&input, "some input").unwrap();
+
+        let task = client_task::ClientTask::new(0, 0, input, dir.join("output"), vec![Filter::Nop, Filter::Nop]);
+        let executors = vec![FilterExecutor::External(filter), FilterExecutor::Builtin(Filter::Nop)];
+        let (sender, mut receiver) = channel(16);
+        let monitors = MonitorPool::new(1).unwrap();
+        let monitor =
+            Monitor::build(Arc::new(task), 0, executors, ResourceLimits::default(), Sandbox::default(), StageEnvironment::default(), sender, None, None, None, None, DEFAULT_PROGRESS_INTERVAL, None, PathPolicy::default(), &monitors).unwrap();
+
+        thread::sleep(Duration::from_millis(200));
+        assert!(monitor.suspend().unwrap());
+        assert!(!monitor.suspend().unwrap());
+        let pid = fs::read_to_string(&pids).unwrap().trim().to_string();
+        // Signals are delivered asynchronously, so the filter may take a moment to stop.
+        let is_stopped = || {
+            let stat = fs::read_to_string(format!("/proc/{pid}/stat")).unwrap();
+            stat.rsplit(") ").next().unwrap().starts_with('T')
+        };
+        let mut stopped = is_stopped();
+        for _ in 0..100 {
+            if stopped {
+                break
+            }
+            thread::sleep(Duration::from_millis(10));
+            stopped = is_stopped();
+        }
+        if !stopped {
+            // Else the pool would wait for the suspended pipeline as it's dropped.
+            monitor.resume().unwrap();
+            panic!("filter {pid} wasn't stopped");
+        }
+
+        // Well past the filter's sleep, the task is still to conclude.
+        thread::sleep(Duration::from_millis(1500));
+        assert!(matches!(receiver.try_recv(), Err(TryRecvError::Empty)));
+        assert!(monitor.resume().unwrap());
+        let result = receive_result(&mut receiver);
+
+        assert!(result.result.is_ok());
+        assert_eq!(fs::read_to_string(dir.join("output")).unwrap(), "some input");
+    }
+
+    /// Assert the child process `pid` was reaped, i.e. that it's no longer a child of
+    /// this process, even a zombie one.
+    fn assert_reaped(pid: libc::pid_t) {
+        // SAFETY: `waitpid` may be given a null pointer for the status.
+        let ret = unsafe { libc::waitpid(pid, std::ptr::null_mut(), libc::WNOHANG) };
+        assert_eq!(ret, -1, "process {pid} was not reaped");
+        assert_eq!(io::Error::last_os_error().raw_os_error(), Some(libc::ECHILD));
+    }
+
+    #[test]
+    fn dropped_stages_are_reaped() {
+        let child = Command::new("sleep").arg("30").spawn().unwrap();
+        let pid = child.id() as libc::pid_t;
+
+        drop(StageProcess::External { child, reaped: false, copiers: Vec::new() });
+        assert_reaped(pid);
+    }
 }
@@ -0,0 +1,103 @@
+//! Where the server's and its clients' sockets are: the socket directory, see [`socket_dir`],
+//! which also holds the server's checkpoints and spooled inputs.
+
+use std::{
+    env, ffi::OsString, fs::{self, DirBuilder, Permissions},
+    io, os::unix::fs::{DirBuilderExt, PermissionsExt}, path::{Path, PathBuf},
+};
+
+/// Environment variable choosing the directory the server's and its clients' sockets are in,
+/// see [`socket_dir`].
+pub const SOCKET_DIR_VAR: &str = "SDSTORE_SOCK_DIR";
+
+/// Environment variable holding the user's runtime directory, as per the XDG Base Directory
+/// Specification, in which the socket directory is by default, see [`socket_dir`].
+pub const RUNTIME_DIR_VAR: &str = "XDG_RUNTIME_DIR";
+
+/// Name of the socket directory in the user's runtime directory.
+const RUNTIME_SUBDIR: &str = "sdstore";
+
+/// The socket directory of users with no runtime directory, e.g. of a server run as a
+/// system service, which is shared by every user, see [`prepare_socket_dir`].
+pub const SYSTEM_SOCKET_DIR: &str = "/run/sdstore";
+
+/// The directory the server's and its clients' sockets are in: `flag`, as given with
+/// `--socket-dir`, if it was, or else [`SOCKET_DIR_VAR`], if set. Otherwise, it is `sdstore`
+/// in the user's runtime directory, see [`RUNTIME_DIR_VAR`], or [`SYSTEM_SOCKET_DIR`] if
+/// they have none.
+pub fn socket_dir(flag: Option<&Path>) -> PathBuf {
+    resolve_socket_dir(flag, env::var_os(SOCKET_DIR_VAR), env::var_os(RUNTIME_DIR_VAR))
+}
+
+/// [`socket_dir`], given the values of [`SOCKET_DIR_VAR`] and [`RUNTIME_DIR_VAR`].
+fn resolve_socket_dir(flag: Option<&Path>, socket_dir_var: Option<OsString>, runtime_dir_var: Option<OsString>) -> PathBuf {
+    if let Some(dir) = flag {
+        return dir.to_path_buf()
+    }
+    if let Some(dir) = socket_dir_var.filter(|dir| !dir.is_empty()) {
+        return PathBuf::from(dir)
+    }
+    // The specification has relative runtime directories be ignored.
+    match runtime_dir_var.map(PathBuf::from).filter(|dir| dir.is_absolute()) {
+        Some(runtime_dir) => runtime_dir.join(RUNTIME_SUBDIR),
+        None => PathBuf::from(SYSTEM_SOCKET_DIR),
+    }
+}
+
+/// Create the socket directory `dir`, as the server does on startup, if it doesn't exist.
+///
+/// It is only accessible to its owner, as the user's runtime directory is, unless it is
+/// [`SYSTEM_SOCKET_DIR`]: every user may then bind their clients' sockets in it, but only
+/// remove their own, as with `/tmp`. Existing directories are left as they are.
+pub fn prepare_socket_dir(dir: &Path) -> io::Result<()> {
+    match fs::metadata(dir) {
+        Ok(meta) if meta.is_dir() => return Ok(()),
+        Ok(_) => return Err(io::Error::new(io::ErrorKind::AlreadyExists, format!("{dir:?} is not a directory"))),
+        Err(err) if err.kind() != io::ErrorKind::NotFound => return Err(err),
+        Err(_) => {},
+    }
+
+    let mode = match dir == Path::new(SYSTEM_SOCKET_DIR) {
+        true => 0o1777,
+        false => 0o700,
+    };
+    DirBuilder::new().recursive(true).mode(mode).create(dir)?;
+    // The mode given on creation is masked by the umask.
+    fs::set_permissions(dir, Permissions::from_mode(mode))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::core::testing::TempDir;
+
+    use super::*;
+
+    #[test]
+    fn socket_dir_resolution_works() {
+        let var = |dir: &str| Some(OsString::from(dir));
+        let resolve = |flag: Option<&str>, socket_dir_var, runtime_dir_var| {
+            resolve_socket_dir(flag.map(Path::new), socket_dir_var, runtime_dir_var)
+        };
+
+        assert_eq!(resolve(Some("/flag"), var("/var"), var("/run/user/1000")), PathBuf::from("/flag"));
+        assert_eq!(resolve(None, var("/var"), var("/run/user/1000")), PathBuf::from("/var"));
+        assert_eq!(resolve(None, var(""), var("/run/user/1000")), PathBuf::from("/run/user/1000/sdstore"));
+        assert_eq!(resolve(None, None, var("relative")), PathBuf::from(SYSTEM_SOCKET_DIR));
+        assert_eq!(resolve(None, None, None), PathBuf::from(SYSTEM_SOCKET_DIR));
+    }
+
+    #[test]
+    fn socket_dirs_are_private() {
+        let dir = TempDir::new("paths");
+        let socket_dir = dir.join("sdstore");
+        prepare_socket_dir(&socket_dir).unwrap();
+        assert_eq!(fs::metadata(&socket_dir).unwrap().permissions().mode() & 0o7777, 0o700);
+
+        // Existing directories are left as they are, and other files refused.
+        fs::set_permissions(&socket_dir, Permissions::from_mode(0o750)).unwrap();
+        prepare_socket_dir(&socket_dir).unwrap();
+        assert_eq!(fs::metadata(&socket_dir).unwrap().permissions().mode() & 0o7777, 0o750);
+        fs::write(dir.join("file"), b"").unwrap();
+        assert!(prepare_socket_dir(&dir.join("file")).is_err());
+    }
+}
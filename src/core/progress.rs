@@ -0,0 +1,123 @@
+//! The progress bar the `sdstore` client draws as the server reports a task's progress, see
+//! [`MessageToClient::Progress`](super::messaging::MessageToClient::Progress).
+
+use std::{io::{self, IsTerminal, Write}, time::{Duration, Instant}};
+
+/// Width, in characters, of the bar itself.
+const BAR_WIDTH: usize = 30;
+
+/// A progress bar, redrawn in place on stdout, with how much of its output the task wrote,
+/// how fast, and how long it should take to finish.
+///
+/// The task's output is measured against the size of its input, as the server only reports
+/// the former: the estimates are exact for filters which keep the size of their input, such
+/// as `nop`, and only a rough guide for the others.
+pub struct ProgressBar {
+    /// Size of the task's input, in bytes, if known and non-zero.
+    total: Option<u64>,
+    /// When the task started running, from which its throughput is measured.
+    started: Instant,
+    /// Whether the bar is drawn on the current line, which must be ended before anything
+    /// else is output.
+    drawn: bool,
+}
+
+impl ProgressBar {
+    /// A bar for a task whose input is `total` bytes long, if stdout is a terminal, and
+    /// `None` otherwise, e.g. for a file the bar would clutter.
+    pub fn new(total: u64) -> Option<Self> {
+        io::stdout().is_terminal().then(|| ProgressBar {
+            total: Some(total).filter(|total| *total > 0),
+            started: Instant::now(),
+            drawn: false,
+        })
+    }
+
+    /// Measure the task's throughput from now on, as it just started running.
+    pub fn start(&mut self) {
+        self.started = Instant::now();
+    }
+
+    /// Redraw the bar, the task having written `bytes_out` bytes so far.
+    pub fn update(&mut self, bytes_out: u64) {
+        let mut stdout = io::stdout().lock();
+        // Clears the line, and moves the cursor back to its start.
+        let drawn = write!(stdout, "\r\x1b[2K{}", self.render(bytes_out, self.started.elapsed()))
+            .and_then(|_| stdout.flush());
+        if let Err(err) = drawn {
+            log::warn!("Could not write to stdout. Error: {:?}", err);
+        }
+        self.drawn = true;
+    }
+
+    /// End the bar's line, if it was drawn, for what is output next.
+    pub fn finish(&mut self) {
+        if std::mem::take(&mut self.drawn) {
+            println!();
+        }
+    }
+
+    /// The bar, the task having written `bytes_out` bytes in `elapsed`.
+    fn render(&self, bytes_out: u64, elapsed: Duration) -> String {
+        let rate = match elapsed.as_secs_f64() {
+            secs if secs > 0.0 => bytes_out as f64 / secs,
+            _ => 0.0,
+        };
+        let Some(total) = self.total else {
+            return format!("{} written, {}/s", fmt_bytes(bytes_out as f64), fmt_bytes(rate))
+        };
+
+        let done = bytes_out.min(total);
+        let filled = (done as f64 / total as f64 * BAR_WIDTH as f64) as usize;
+        let mut bar = format!(
+            "[{}{}] {:>3}% {}/{}, {}/s",
+            "#".repeat(filled),
+            "-".repeat(BAR_WIDTH - filled),
+            done * 100 / total,
+            fmt_bytes(bytes_out as f64),
+            fmt_bytes(total as f64),
+            fmt_bytes(rate),
+        );
+        if rate > 0.0 && done < total {
+            bar.push_str(&format!(", ETA {:.0}s", (total - done) as f64 / rate));
+        }
+        bar
+    }
+}
+
+/// `bytes` in the largest binary unit it's at least one of.
+fn fmt_bytes(bytes: f64) -> String {
+    const UNITS: [&str; 4] = ["KiB", "MiB", "GiB", "TiB"];
+    if bytes < 1024.0 {
+        return format!("{bytes:.0} B")
+    }
+    let mut value = bytes / 1024.0;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    format!("{value:.1} {}", UNITS[unit])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn progress_rendering_works() {
+        let bar = |total| ProgressBar { total, started: Instant::now(), drawn: false };
+        let elapsed = Duration::from_secs(2);
+
+        assert_eq!(
+            bar(Some(4 << 20)).render(1 << 20, elapsed),
+            "[#######-----------------------]  25% 1.0 MiB/4.0 MiB, 512.0 KiB/s, ETA 6s"
+        );
+        // Outputs may outgrow their input, as decompressed ones do.
+        assert_eq!(
+            bar(Some(1000)).render(1500, elapsed),
+            "[##############################] 100% 1.5 KiB/1000 B, 750 B/s"
+        );
+        assert_eq!(bar(None).render(0, Duration::ZERO), "0 B written, 0 B/s");
+    }
+}
@@ -0,0 +1,404 @@
+//! Inputs and outputs of tasks given as URLs rather than paths, see [`RemoteUrl`], which the
+//! server downloads to, and uploads from, local copies its pipelines run on, see [`Staging`].
+//!
+//! Transfers need the server to be built with the `remote` feature, without which it refuses
+//! such tasks, see [`SUPPORTED`].
+
+use std::{
+    fmt::Display, fs, io,
+    path::{Path, PathBuf},
+    sync::atomic::AtomicBool,
+};
+
+use super::client_task::ClientTask;
+
+#[cfg(feature = "remote")]
+use transfer::{download, upload};
+
+/// Whether the server can transfer remote files, having been built with the `remote` feature.
+pub const SUPPORTED: bool = cfg!(feature = "remote");
+
+/// Environment variables of the server S3 objects are accessed with: the access key's ID and
+/// secret, the session token of temporary credentials, if any, and the region of the buckets,
+/// `us-east-1` if not set.
+pub const AWS_ACCESS_KEY_ID_VAR: &str = "AWS_ACCESS_KEY_ID";
+pub const AWS_SECRET_ACCESS_KEY_VAR: &str = "AWS_SECRET_ACCESS_KEY";
+pub const AWS_SESSION_TOKEN_VAR: &str = "AWS_SESSION_TOKEN";
+pub const AWS_REGION_VAR: &str = "AWS_REGION";
+
+/// Environment variable of the server giving the URL of an S3-compatible service to use
+/// instead of AWS, e.g. `http://localhost:9000`, whose objects are addressed by path.
+pub const AWS_ENDPOINT_URL_VAR: &str = "AWS_ENDPOINT_URL";
+
+/// A task's input or output, given as a URL.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RemoteUrl {
+    /// An `http://` or `https://` URL, downloaded with `GET`, and uploaded with `PUT`.
+    Http(String),
+    /// An `s3://<bucket>/<key>` URL, of an object accessed with the server's credentials,
+    /// see [`AWS_ACCESS_KEY_ID_VAR`].
+    S3 {
+        bucket: String,
+        key: String,
+    },
+}
+
+impl RemoteUrl {
+    /// The URL `path` is, if it is one.
+    pub fn parse(path: &Path) -> Option<Self> {
+        let url = path.to_str()?;
+        if url.starts_with("http://") || url.starts_with("https://") {
+            return Some(RemoteUrl::Http(url.to_string()))
+        }
+        let (bucket, key) = url.strip_prefix("s3://")?.split_once('/')?;
+        (!bucket.is_empty() && !key.is_empty()).then(|| RemoteUrl::S3 { bucket: bucket.to_string(), key: key.to_string() })
+    }
+}
+
+impl Display for RemoteUrl {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Http(url) => write!(f, "{url}"),
+            Self::S3 { bucket, key } => write!(f, "s3://{bucket}/{key}"),
+        }
+    }
+}
+
+/// Whether `path` is a URL rather than a path, see [`RemoteUrl`].
+pub fn is_remote(path: &Path) -> bool {
+    RemoteUrl::parse(path).is_some()
+}
+
+/// Errors that may happen while transferring a remote file.
+#[derive(Debug)]
+pub enum RemoteError {
+    /// The server was built without the `remote` feature, see [`SUPPORTED`].
+    Unsupported,
+    /// Reading or writing the local copy failed, or the transfer was interrupted as the
+    /// task was killed.
+    Io(io::Error),
+    /// The remote host couldn't be reached, or the transfer broke off, for this reason.
+    Transport(String),
+    /// The remote host answered the request for the URL with this HTTP status.
+    Status {
+        url: String,
+        status: u16,
+    },
+    /// The output exists, and the task forbids replacing it.
+    OutputExists,
+    /// S3 objects are accessed, but the server has no credentials to access them with.
+    MissingCredentials,
+}
+
+impl From<io::Error> for RemoteError {
+    fn from(err: io::Error) -> Self {
+        Self::Io(err)
+    }
+}
+
+impl Display for RemoteError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Unsupported => write!(f, "the server was built without support for remote files"),
+            Self::Io(err) => write!(f, "{err}"),
+            Self::Transport(reason) => write!(f, "{reason}"),
+            Self::Status { url, status } => write!(f, "{url} answered with HTTP status {status}"),
+            Self::OutputExists => write!(f, "the output exists"),
+            Self::MissingCredentials =>
+                write!(f, "the server has no S3 credentials, in {AWS_ACCESS_KEY_ID_VAR} and {AWS_SECRET_ACCESS_KEY_VAR}"),
+        }
+    }
+}
+
+/// Local copies of a task's remote files, which its pipeline runs on instead: the input
+/// is downloaded before it starts, and the output uploaded once it succeeds.
+pub struct Staging {
+    input: Option<(RemoteUrl, PathBuf)>,
+    output: Option<(RemoteUrl, PathBuf)>,
+    /// Whether the output may not replace an existing one, see [`ClientTask::no_clobber`].
+    no_clobber: bool,
+}
+
+impl Staging {
+    /// Where task #`task_number` keeps local copies of its remote files, in `dir`, if it has
+    /// any.
+    pub fn of(task: &ClientTask, task_number: usize, dir: &Path) -> Option<Self> {
+        let local = |url: RemoteUrl, suffix| (url, dir.join(format!("sdstored_remote_{}_{task_number}.{suffix}", std::process::id())));
+        let staging = Staging {
+            input: RemoteUrl::parse(task.input_filepath()).map(|url| local(url, "in")),
+            output: RemoteUrl::parse(task.output_filepath()).map(|url| local(url, "out")),
+            no_clobber: task.no_clobber,
+        };
+        (staging.input.is_some() || staging.output.is_some()).then_some(staging)
+    }
+
+    /// `task`, reading and writing the local copies of its remote files.
+    pub fn local_task(&self, task: &ClientTask) -> ClientTask {
+        let mut local = task.clone();
+        let input = self.input.as_ref().map_or(task.input_filepath(), |(_, path)| path);
+        let output = self.output.as_ref().map_or(task.output_filepath(), |(_, path)| path);
+        local.relocate(input.to_path_buf(), output.to_path_buf());
+        // Whether the remote output exists is only known as it is uploaded.
+        local.no_clobber &= self.output.is_none();
+        local
+    }
+
+    /// Download the remote input, if any, to its local copy, failing once `killed` is set.
+    pub fn download(&self, killed: &AtomicBool) -> Result<(), RemoteError> {
+        match &self.input {
+            None => Ok(()),
+            Some((url, path)) => {
+                let bytes = download(url, path, killed)?;
+                log::info!("downloaded {bytes} bytes from {url}");
+                Ok(())
+            },
+        }
+    }
+
+    /// Upload the local copy of the output to the remote one, if any, failing once `killed`
+    /// is set.
+    pub fn upload(&self, killed: &AtomicBool) -> Result<(), RemoteError> {
+        match &self.output {
+            None => Ok(()),
+            Some((url, path)) => {
+                upload(path, url, self.no_clobber, killed)?;
+                log::info!("uploaded {} to {url}", path.display());
+                Ok(())
+            },
+        }
+    }
+
+    /// Remove the local copies, whether the task succeeded or not.
+    pub fn clean_up(self) {
+        for (_, path) in self.input.iter().chain(&self.output) {
+            match fs::remove_file(path) {
+                Err(err) if err.kind() != io::ErrorKind::NotFound =>
+                    log::warn!("could not remove local copy {}: {:?}", path.display(), err),
+                _ => {},
+            }
+        }
+    }
+}
+
+#[cfg(not(feature = "remote"))]
+fn download(_: &RemoteUrl, _: &Path, _: &AtomicBool) -> Result<u64, RemoteError> {
+    Err(RemoteError::Unsupported)
+}
+
+#[cfg(not(feature = "remote"))]
+fn upload(_: &Path, _: &RemoteUrl, _: bool, _: &AtomicBool) -> Result<(), RemoteError> {
+    Err(RemoteError::Unsupported)
+}
+
+/// Transfers over HTTP, with S3 requests signed as AWS expects.
+#[cfg(feature = "remote")]
+mod transfer {
+    use std::{
+        env, fs, io::{self, Read},
+        path::Path,
+        sync::atomic::{AtomicBool, Ordering},
+        time::Duration,
+    };
+
+    use super::{RemoteError, RemoteUrl, AWS_ACCESS_KEY_ID_VAR, AWS_ENDPOINT_URL_VAR, AWS_REGION_VAR, AWS_SECRET_ACCESS_KEY_VAR, AWS_SESSION_TOKEN_VAR};
+
+    /// How long connecting to a remote host, or waiting for it to send or take more data, may
+    /// take before the transfer fails.
+    const TRANSFER_TIMEOUT: Duration = Duration::from_secs(60);
+
+    /// Reads from a remote file, or from a local copy to upload, until `killed` is set, after
+    /// which reads fail, as builtin stages' do.
+    struct Interruptible<'a, R> {
+        inner: R,
+        killed: &'a AtomicBool,
+    }
+
+    impl<R: Read> Read for Interruptible<'_, R> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            if self.killed.load(Ordering::SeqCst) {
+                return Err(io::Error::other("the task was killed"))
+            }
+            self.inner.read(buf)
+        }
+    }
+
+    /// Download `url` to the file `path`, returning its size in bytes.
+    pub(super) fn download(url: &RemoteUrl, path: &Path, killed: &AtomicBool) -> Result<u64, RemoteError> {
+        let response = request("GET", url)?.call().map_err(|err| http_error(url, err))?;
+        let mut file = fs::File::create(path)?;
+        Ok(io::copy(&mut Interruptible { inner: response.into_reader(), killed }, &mut file)?)
+    }
+
+    /// Upload the file `path` to `url`, unless `no_clobber` and it exists, as far as the remote
+    /// host can tell.
+    pub(super) fn upload(path: &Path, url: &RemoteUrl, no_clobber: bool, killed: &AtomicBool) -> Result<(), RemoteError> {
+        let file = fs::File::open(path)?;
+        let len = file.metadata()?.len();
+        // The file is streamed as it's read, rather than read in memory first.
+        let mut request = request("PUT", url)?.set("Content-Length", &len.to_string());
+        if no_clobber {
+            request = request.set("If-None-Match", "*");
+        }
+        match request.send(Interruptible { inner: file, killed }) {
+            Err(ureq::Error::Status(412, _)) => Err(RemoteError::OutputExists),
+            Err(err) => Err(http_error(url, err)),
+            Ok(_) => Ok(()),
+        }
+    }
+
+    fn http_error(url: &RemoteUrl, err: ureq::Error) -> RemoteError {
+        match err {
+            ureq::Error::Status(status, _) => RemoteError::Status { url: url.to_string(), status },
+            ureq::Error::Transport(transport) => RemoteError::Transport(transport.to_string()),
+        }
+    }
+
+    /// A `method` request for `url`, signed with the server's credentials if it's an S3 object,
+    /// see [`s3_request`].
+    fn request(method: &str, url: &RemoteUrl) -> Result<ureq::Request, RemoteError> {
+        let agent = ureq::AgentBuilder::new()
+            .timeout_connect(TRANSFER_TIMEOUT)
+            .timeout_read(TRANSFER_TIMEOUT)
+            .timeout_write(TRANSFER_TIMEOUT)
+            .build();
+        match url {
+            RemoteUrl::Http(url) => Ok(agent.request(method, url)),
+            RemoteUrl::S3 { bucket, key } => s3_request(&agent, method, bucket, key),
+        }
+    }
+
+    /// A `method` request for the S3 object `key` of `bucket`, signed with AWS Signature
+    /// Version 4, its payload unsigned, as S3 allows, so that it can be streamed.
+    fn s3_request(agent: &ureq::Agent, method: &str, bucket: &str, key: &str) -> Result<ureq::Request, RemoteError> {
+        use hmac::{Hmac, Mac};
+        use sha2::{Digest, Sha256};
+
+        let var = |name| env::var(name).ok().filter(|value| !value.is_empty());
+        let (Some(key_id), Some(secret)) = (var(AWS_ACCESS_KEY_ID_VAR), var(AWS_SECRET_ACCESS_KEY_VAR)) else {
+            return Err(RemoteError::MissingCredentials)
+        };
+        let region = var(AWS_REGION_VAR).unwrap_or_else(|| String::from("us-east-1"));
+
+        let key = uri_encode(key);
+        let (url, host, path) = match var(AWS_ENDPOINT_URL_VAR) {
+            Some(endpoint) => {
+                let endpoint = endpoint.trim_end_matches('/');
+                let host = endpoint.split_once("://").map_or(endpoint, |(_, host)| host);
+                (format!("{endpoint}/{bucket}/{key}"), host.to_string(), format!("/{bucket}/{key}"))
+            },
+            None => {
+                let host = format!("{bucket}.s3.{region}.amazonaws.com");
+                (format!("https://{host}/{key}"), host, format!("/{key}"))
+            },
+        };
+
+        let (date_time, date) = amz_date(std::time::SystemTime::now());
+        let mut headers = vec![
+            ("host", host),
+            ("x-amz-content-sha256", String::from("UNSIGNED-PAYLOAD")),
+            ("x-amz-date", date_time.clone()),
+        ];
+        if let Some(token) = var(AWS_SESSION_TOKEN_VAR) {
+            headers.push(("x-amz-security-token", token));
+        }
+        let signed_headers = headers.iter().map(|(name, _)| *name).collect::<Vec<_>>().join(";");
+        let canonical_headers = headers.iter().map(|(name, value)| format!("{name}:{value}\n")).collect::<String>();
+        let canonical_request = format!("{method}\n{path}\n\n{canonical_headers}\n{signed_headers}\nUNSIGNED-PAYLOAD");
+
+        let scope = format!("{date}/{region}/s3/aws4_request");
+        let string_to_sign = format!(
+            "AWS4-HMAC-SHA256\n{date_time}\n{scope}\n{:x}", Sha256::digest(canonical_request.as_bytes())
+        );
+        let hmac = |key: &[u8], data: &str| {
+            let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC takes keys of any length");
+            mac.update(data.as_bytes());
+            mac.finalize().into_bytes()
+        };
+        let signing_key = ["s3", "aws4_request"]
+            .into_iter()
+            .fold(hmac(hmac(format!("AWS4{secret}").as_bytes(), &date).as_slice(), &region), |key, data| hmac(&key, data));
+        let signature = hmac(&signing_key, &string_to_sign).iter().map(|byte| format!("{byte:02x}")).collect::<String>();
+
+        let mut request = agent.request(method, &url).set(
+            "Authorization",
+            &format!("AWS4-HMAC-SHA256 Credential={key_id}/{scope}, SignedHeaders={signed_headers}, Signature={signature}"),
+        );
+        // The host is set by the agent, from the URL.
+        for (name, value) in headers.iter().skip(1) {
+            request = request.set(name, value);
+        }
+        Ok(request)
+    }
+
+    /// `key`, with every byte but unreserved characters and `/` percent-encoded, as S3 expects
+    /// of object keys in paths.
+    fn uri_encode(key: &str) -> String {
+        key.bytes()
+            .map(|byte| match byte {
+                b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' | b'/' => char::from(byte).to_string(),
+                _ => format!("%{byte:02X}"),
+            })
+            .collect()
+    }
+
+    /// `time` in UTC, as `x-amz-date` has it, e.g. `20240102T030405Z`, and its date alone, e.g.
+    /// `20240102`.
+    fn amz_date(time: std::time::SystemTime) -> (String, String) {
+        let secs = time.duration_since(std::time::UNIX_EPOCH).unwrap_or_default().as_secs();
+        let (days, secs) = (secs / 86400, secs % 86400);
+
+        // Days since the epoch to a civil date, after Howard Hinnant's `civil_from_days`.
+        let z = days as i64 + 719_468;
+        let era = z.div_euclid(146_097);
+        let day_of_era = z.rem_euclid(146_097);
+        let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+        let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+        let mp = (5 * day_of_year + 2) / 153;
+        let day = day_of_year - (153 * mp + 2) / 5 + 1;
+        let month = if mp < 10 { mp + 3 } else { mp - 9 };
+        let year = year_of_era + era * 400 + i64::from(month <= 2);
+
+        let date = format!("{year:04}{month:02}{day:02}");
+        (format!("{date}T{:02}{:02}{:02}Z", secs / 3600, secs % 3600 / 60, secs % 60), date)
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use std::time::UNIX_EPOCH;
+
+        use super::*;
+
+        #[test]
+        fn s3_requests_are_encoded_and_dated() {
+            assert_eq!(uri_encode("dir/a b+c~.log"), "dir/a%20b%2Bc~.log");
+            let (date_time, date) = amz_date(UNIX_EPOCH + Duration::from_secs(1_709_251_445));
+            assert_eq!((date_time.as_str(), date.as_str()), ("20240301T000405Z", "20240301"));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+
+    use super::*;
+
+    #[test]
+    fn urls_are_told_from_paths() {
+        assert_eq!(RemoteUrl::parse(Path::new("https://example.com/in?sig=1")), Some(RemoteUrl::Http(String::from("https://example.com/in?sig=1"))));
+        let s3 = RemoteUrl::parse(Path::new("s3://bucket/dir/in.log")).unwrap();
+        assert_eq!(s3, RemoteUrl::S3 { bucket: String::from("bucket"), key: String::from("dir/in.log") });
+        assert_eq!(s3.to_string(), "s3://bucket/dir/in.log");
+        assert!(!is_remote(Path::new("s3://bucket")) && !is_remote(Path::new("inputs/https:")));
+    }
+
+    #[test]
+    fn remote_files_are_staged() {
+        let dir = env::temp_dir();
+        let task = ClientTask::new(0, 0, PathBuf::from("s3://bucket/in"), PathBuf::from("out"), vec![]);
+        let staging = Staging::of(&task, 3, &dir).unwrap();
+        let local = staging.local_task(&task);
+        assert!(local.input_filepath().starts_with(&dir) && local.output_filepath() == Path::new("out"));
+        assert!(Staging::of(&ClientTask::new(0, 0, PathBuf::from("in"), PathBuf::from("out"), vec![]), 3, &dir).is_none());
+    }
+}
@@ -1,2 +1,30 @@
+pub mod audit;
+pub mod auth;
+pub mod cache;
+pub mod check;
+pub mod cli;
 pub mod config;
-pub mod state;
\ No newline at end of file
+pub mod config_file;
+pub mod coordinator;
+pub mod daemon;
+pub mod dry_run;
+pub mod embed;
+pub mod environment;
+pub mod handover;
+pub mod inline;
+pub mod monitor_pool;
+pub mod numbering;
+pub mod optimizer;
+pub mod pool;
+pub mod privileges;
+pub mod rate_limit;
+pub mod resources;
+pub mod sandbox;
+pub mod scheduler;
+pub mod store;
+pub mod streaming;
+pub mod systemd;
+pub mod state;
+#[cfg(test)]
+pub mod testing;
+pub mod wasm;
\ No newline at end of file
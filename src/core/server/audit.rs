@@ -0,0 +1,150 @@
+//! The server's audit log: a record of every change in the lifecycle of every task, kept
+//! apart from its logs, for review, see [`AuditLog`].
+
+use std::{io::{self, Write}, path::{Path, PathBuf}, time::SystemTime};
+
+use serde::Serialize;
+use uuid::Uuid;
+
+use crate::{core::{client_task::ClientTask, filter::Filter}, util::{RotatingFile, Rotation}};
+
+/// Size the audit file may grow to before it is rotated, unless configured otherwise, see
+/// [`AuditConfig::max_size`].
+pub const DEFAULT_AUDIT_MAX_SIZE: u64 = 10 * 1024 * 1024;
+
+/// Number of rotated audit files kept, unless configured otherwise, see [`AuditConfig::keep`].
+pub const DEFAULT_AUDIT_KEEP: usize = 5;
+
+/// Where the audit log is kept, and how it is rotated.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AuditConfig {
+    /// File records are appended to.
+    pub file: PathBuf,
+    /// Size, in bytes, past which the file is rotated: renamed to `<file>.1`, the file
+    /// previously there to `<file>.2`, and so on.
+    pub max_size: u64,
+    /// Number of rotated files kept, the oldest being removed. `0` if the file is removed
+    /// instead.
+    pub keep: usize,
+}
+
+/// A change in the lifecycle of a task, as recorded in the audit log.
+#[derive(Debug, Serialize, PartialEq)]
+#[serde(tag = "event", rename_all = "kebab-case")]
+pub enum AuditEvent<'a> {
+    /// The server received the task.
+    Received {
+        input: &'a Path,
+        output: &'a Path,
+        filters: &'a [Filter],
+        queue: &'a str,
+        priority: usize,
+    },
+    /// The task is pending in its queue.
+    Queued,
+    /// The task started running, as task #`task_number`.
+    Started { task_number: usize },
+    /// Stage `stage` of task #`task_number`, running `filter`, succeeded, or failed as told
+    /// by `failure`, after `duration_ms`, if known.
+    Stage {
+        task_number: usize,
+        stage: usize,
+        filter: &'a Filter,
+        duration_ms: Option<u64>,
+        failure: Option<String>,
+    },
+    /// The client with `by_pid` asked for the task to be cancelled.
+    CancelRequested { by_pid: u32 },
+    /// Task #`task_number` succeeded.
+    Finished { task_number: usize },
+    /// The task failed for this reason, after it started running as task #`task_number`,
+    /// if it did.
+    Failed {
+        task_number: Option<usize>,
+        failure: String,
+    },
+}
+
+/// A line of the audit log: an event about a task, when it happened, in seconds since the
+/// Unix epoch, and who asked for the task.
+#[derive(Debug, Serialize)]
+struct AuditRecord<'a> {
+    time: f64,
+    request_id: Uuid,
+    client_pid: u32,
+    /// `None` if the transport couldn't tell, see [`ClientTask::client_uid`].
+    client_uid: Option<u32>,
+    #[serde(flatten)]
+    event: AuditEvent<'a>,
+}
+
+/// An append-only file, with a JSON object per line for each [`AuditEvent`], rotated once
+/// it grows past its maximum size, see [`AuditConfig`].
+pub struct AuditLog {
+    config: AuditConfig,
+    file: RotatingFile,
+}
+
+impl AuditLog {
+    /// Open the audit file, creating it if it doesn't exist, to append to it.
+    pub fn open(config: AuditConfig) -> io::Result<Self> {
+        let rotation = Rotation { max_size: Some(config.max_size), max_age: None, keep: config.keep };
+        let file = RotatingFile::open(config.file.clone(), rotation)?;
+        Ok(AuditLog { config, file })
+    }
+
+    /// Append `event`, about `task`, to the audit file, rotating the file first if it is
+    /// full. Failing to do either is logged, as the server carries on regardless.
+    pub fn record(&mut self, task: &ClientTask, event: AuditEvent) {
+        let record = AuditRecord {
+            time: SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap_or_default().as_secs_f64(),
+            request_id: task.request_id,
+            client_pid: task.client_pid,
+            client_uid: task.client_uid,
+            event,
+        };
+        // A record has no maps with keys that aren't strings, so it can't fail to serialize.
+        let mut line = serde_json::to_vec(&record).unwrap_or_default();
+        line.push(b'\n');
+
+        if let Err(err) = self.file.rotate_if_due(line.len()) {
+            log::warn!("could not rotate audit file {}: {:?}", self.config.file.display(), err);
+        }
+        if let Err(err) = self.file.write_all(&line) {
+            log::error!("could not write to audit file {}: {:?}", self.config.file.display(), err);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use crate::core::testing::TempDir;
+
+    use super::*;
+
+    #[test]
+    fn audit_files_are_rotated() {
+        let dir = TempDir::new("audit");
+        let file = dir.join("audit.log");
+        let mut task = ClientTask::new(42, 0, PathBuf::from("in"), PathBuf::from("out"), vec![Filter::Nop]);
+        task.client_uid = Some(1000);
+
+        let mut audit = AuditLog::open(AuditConfig { file: file.clone(), max_size: 200, keep: 2 }).unwrap();
+        audit.record(&task, AuditEvent::Started { task_number: 3 });
+        let record: serde_json::Value = serde_json::from_str(fs::read_to_string(&file).unwrap().trim()).unwrap();
+        assert_eq!(
+            (&record["event"], &record["task_number"], &record["client_pid"], &record["client_uid"]),
+            (&"started".into(), &3.into(), &42.into(), &1000.into())
+        );
+
+        for task_number in 0..6 {
+            audit.record(&task, AuditEvent::Finished { task_number });
+        }
+        assert!(dir.join("audit.log.1").exists() && dir.join("audit.log.2").exists());
+        assert!(!dir.join("audit.log.3").exists());
+        let lines = fs::read_to_string(&file).unwrap();
+        assert!(lines.len() <= 200 && lines.ends_with("\"task_number\":5}\n"));
+    }
+}
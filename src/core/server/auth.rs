@@ -0,0 +1,286 @@
+use std::{fmt::Display, fs, os::fd::AsRawFd, path::{Component, Path, PathBuf}};
+
+use crate::core::{batch, client_task::ClientTask, remote, transport::Credentials};
+
+/// Why a client's request was refused, see [`authenticate`].
+#[derive(Debug, PartialEq, Eq)]
+pub enum AuthError {
+    /// Only some users may make requests, and the transport doesn't know who sent this one.
+    NoCredentials,
+    /// The user with this UID may not make requests.
+    UidNotAllowed(u32),
+    /// The user with this UID may make requests, but not administrative ones.
+    NotAdmin(u32),
+    /// The user with this UID may make requests, but not about another user's task.
+    NotOwner(u32),
+}
+
+impl Display for AuthError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::NoCredentials => write!(f, "the server could not tell who made the request"),
+            Self::UidNotAllowed(uid) => write!(f, "the user with UID {uid} may not make requests"),
+            Self::NotAdmin(uid) => write!(f, "the user with UID {uid} may not make administrative requests"),
+            Self::NotOwner(uid) => write!(f, "the user with UID {uid} may not control another user's task"),
+        }
+    }
+}
+
+/// Check that the client with `credentials` may make a request, in which it claims to
+/// have the PID `claimed_pid`: that its user is among the `allowed_uids`, if only some are.
+///
+/// The claimed PID is replaced by the client's actual one, so that no client can pass for
+/// another, unless the client is in a PID namespace the server can't see, where its PID is
+/// `0`. Requests without credentials are trusted as they are, unless only some users are
+/// allowed.
+pub fn authenticate(
+    claimed_pid: &mut u32,
+    credentials: Option<Credentials>,
+    allowed_uids: Option<&[u32]>
+) -> Result<(), AuthError> {
+    let Some(credentials) = credentials else {
+        return match allowed_uids {
+            None => Ok(()),
+            Some(_) => Err(AuthError::NoCredentials),
+        }
+    };
+
+    if allowed_uids.is_some_and(|uids| !uids.contains(&credentials.uid)) {
+        return Err(AuthError::UidNotAllowed(credentials.uid))
+    }
+    if credentials.pid != 0 && credentials.pid != *claimed_pid {
+        log::warn!("client claimed PID {claimed_pid}, but has PID {}", credentials.pid);
+        *claimed_pid = credentials.pid;
+    }
+    Ok(())
+}
+
+/// Check that the client with `credentials`, already authenticated, may make administrative
+/// requests, such as [`ClientRequest::Report`](crate::core::messaging::ClientRequest::Report):
+/// that its user is root, or the server's own user, whose UID is `server_uid`. Unlike other
+/// requests, these are refused if the transport doesn't know who sent them.
+pub fn authorize_admin(credentials: Option<Credentials>, server_uid: u32) -> Result<(), AuthError> {
+    match credentials {
+        None => Err(AuthError::NoCredentials),
+        Some(Credentials { uid, .. }) if uid != 0 && uid != server_uid => Err(AuthError::NotAdmin(uid)),
+        Some(_) => Ok(()),
+    }
+}
+
+/// Check that the client with `credentials`, already authenticated, may control a task
+/// submitted by the user whose UID is `owner_uid`, e.g. to
+/// [suspend](crate::core::messaging::ClientRequest::Suspend) it: that it's that user's own
+/// task, or that it may make administrative requests, see [`authorize_admin`]. Requests whose
+/// sender, or task's owner, the transport doesn't know are refused, unless made by admins.
+pub fn authorize_owner(credentials: Option<Credentials>, owner_uid: Option<u32>, server_uid: u32) -> Result<(), AuthError> {
+    match credentials {
+        Some(Credentials { uid, .. }) if owner_uid == Some(uid) => Ok(()),
+        _ => authorize_admin(credentials, server_uid).map_err(|err| match err {
+            AuthError::NotAdmin(uid) => AuthError::NotOwner(uid),
+            err => err,
+        }),
+    }
+}
+
+/// Directories the files clients' tasks read and write must be in, for a server whose user
+/// may access more than its clients should, see [`PathPolicy::denied`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PathPolicy {
+    /// Directories inputs may be read from. `None` if any may be.
+    pub inputs: Option<Vec<PathBuf>>,
+    /// Directories outputs may be written in. `None` if any may be.
+    pub outputs: Option<Vec<PathBuf>>,
+}
+
+impl PathPolicy {
+    /// The first path of `task` out of the directories it is allowed, if any is: its input,
+    /// or a batch's input directory, then its output, or a batch's output directory.
+    ///
+    /// Paths are compared once resolved, symlinks and `..` included, so that none may lead
+    /// out of its directories. The part of a path that doesn't exist yet, such as an output
+    /// file, may not have `..`, which can't be resolved. Streamed, and inline, tasks only
+    /// access the server's own copies of their files, and are always allowed, as are the
+    /// outputs of tasks written to the server's store. URLs, see [`remote`](crate::core::remote), are
+    /// in no directory, and so are denied wherever directories are configured.
+    ///
+    /// As filters may write files in their working directory, a task's, if it has one, is
+    /// checked first, as an output directory, whatever the task.
+    ///
+    /// This is only checked as the task is queued: its paths may have changed by the time it
+    /// runs, so the files it opens then are checked again, see [`PathPolicy::allows_input`].
+    pub fn denied<'a>(&self, task: &'a ClientTask) -> Option<&'a Path> {
+        let cwd = task.cwd.as_deref();
+        if let Some(cwd) = cwd.filter(|cwd| self.outputs.as_ref().is_some_and(|allowed| !is_within(cwd, allowed))) {
+            return Some(cwd)
+        }
+        let policy = self.for_task(task);
+
+        let input = task.input_filepath();
+        let input_dir = match batch::is_batch(input) && !input.is_dir() {
+            true => input.parent().unwrap_or(Path::new("")),
+            false => input,
+        };
+        [(input_dir, input, policy.inputs), (task.output_filepath(), task.output_filepath(), policy.outputs)]
+            .into_iter()
+            .find(|(path, _, allowed)| allowed.as_ref().is_some_and(|allowed| !is_within(path, allowed)))
+            .map(|(_, path, _)| path)
+    }
+
+    /// The directories the input and output files of `task` must be in: none for the server's
+    /// own copies of streamed, and inline, tasks, nor for outputs written to its store.
+    pub fn for_task(&self, task: &ClientTask) -> PathPolicy {
+        match task.is_spooled() {
+            true => PathPolicy::default(),
+            false => PathPolicy {
+                inputs: self.inputs.clone(),
+                outputs: self.outputs.clone().filter(|_| !task.store),
+            },
+        }
+    }
+
+    /// Whether `file`, opened as a task's input, is within the directories inputs may be
+    /// read from: wherever its path led when it was opened, which is what the task reads,
+    /// whatever it leads to now.
+    pub fn allows_input(&self, file: &fs::File) -> bool {
+        is_opened_within(file, self.inputs.as_deref())
+    }
+
+    /// Whether `file`, opened as a task's output, is within the directories outputs may be
+    /// written in, see [`PathPolicy::allows_input`].
+    pub fn allows_output(&self, file: &fs::File) -> bool {
+        is_opened_within(file, self.outputs.as_deref())
+    }
+}
+
+/// Whether `path` resolves to within any of the directories `allowed`, which URLs never do.
+fn is_within(path: &Path, allowed: &[PathBuf]) -> bool {
+    !remote::is_remote(path) && resolve(path).is_some_and(|path| is_resolved_within(&path, allowed))
+}
+
+/// Whether the open `file` is within any of the directories `allowed`, if some are, going by
+/// the path the kernel has for it, already resolved.
+fn is_opened_within(file: &fs::File, allowed: Option<&[PathBuf]>) -> bool {
+    let Some(allowed) = allowed else { return true };
+    fs::read_link(Path::new("/proc/self/fd").join(file.as_raw_fd().to_string()))
+        .is_ok_and(|path| is_resolved_within(&path, allowed))
+}
+
+/// Whether the resolved `path` is within any of the directories `allowed`.
+fn is_resolved_within(path: &Path, allowed: &[PathBuf]) -> bool {
+    allowed.iter().any(|dir| path.starts_with(resolve(dir).unwrap_or_else(|| dir.clone())))
+}
+
+/// `path`, with its symlinks, `.` and `..` resolved as far as it exists, followed by the
+/// rest of it, unless the rest has components other than names, which can't be resolved.
+fn resolve(path: &Path) -> Option<PathBuf> {
+    let (existing, resolved) = path.ancestors().find_map(|existing| {
+        let dir = if existing.as_os_str().is_empty() { Path::new(".") } else { existing };
+        fs::canonicalize(dir).ok().map(|resolved| (existing, resolved))
+    })?;
+    let rest = path.strip_prefix(existing).ok()?;
+    match rest.components().all(|component| matches!(component, Component::Normal(_))) {
+        true => Some(resolved.join(rest)),
+        false => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::core::{filter::Filter, testing::TempDir};
+
+    use super::*;
+
+    #[test]
+    fn authentication_works() {
+        let credentials = Credentials { pid: 42, uid: 1000, gid: 1000 };
+
+        let mut pid = 7;
+        assert_eq!(authenticate(&mut pid, Some(credentials), None), Ok(()));
+        assert_eq!(pid, 42);
+
+        let mut pid = 7;
+        assert_eq!(authenticate(&mut pid, Some(Credentials { pid: 0, ..credentials }), Some(&[0, 1000])), Ok(()));
+        assert_eq!(pid, 7);
+
+        assert_eq!(authenticate(&mut pid, Some(credentials), Some(&[0])), Err(AuthError::UidNotAllowed(1000)));
+        assert_eq!(authenticate(&mut pid, None, Some(&[1000])), Err(AuthError::NoCredentials));
+        assert_eq!(authenticate(&mut pid, None, None), Ok(()));
+        assert_eq!(pid, 7);
+    }
+
+    #[test]
+    fn only_root_and_the_server_user_are_admins() {
+        let credentials = |uid| Some(Credentials { pid: 42, uid, gid: uid });
+        assert_eq!(authorize_admin(credentials(0), 1000), Ok(()));
+        assert_eq!(authorize_admin(credentials(1000), 1000), Ok(()));
+        assert_eq!(authorize_admin(credentials(1001), 1000), Err(AuthError::NotAdmin(1001)));
+        assert_eq!(authorize_admin(None, 1000), Err(AuthError::NoCredentials));
+    }
+
+    #[test]
+    fn tasks_are_controlled_by_their_owners_and_admins() {
+        let credentials = |uid| Some(Credentials { pid: 42, uid, gid: uid });
+        assert_eq!(authorize_owner(credentials(1001), Some(1001), 1000), Ok(()));
+        assert_eq!(authorize_owner(credentials(1000), Some(1001), 1000), Ok(()));
+        assert_eq!(authorize_owner(credentials(1002), Some(1001), 1000), Err(AuthError::NotOwner(1002)));
+        assert_eq!(authorize_owner(credentials(1002), None, 1000), Err(AuthError::NotOwner(1002)));
+        assert_eq!(authorize_owner(None, None, 1000), Err(AuthError::NoCredentials));
+    }
+
+    #[test]
+    fn paths_are_allowed_within_their_directories() {
+        let dir = TempDir::new("auth");
+        for sub in ["inputs", "outputs", "secrets"] {
+            fs::create_dir_all(dir.join(sub)).unwrap();
+        }
+        std::os::unix::fs::symlink(dir.join("secrets"), dir.join("inputs/link")).unwrap();
+        let policy = PathPolicy { inputs: Some(vec![dir.join("inputs")]), outputs: Some(vec![dir.join("outputs")]) };
+        let task = |input: &str, output: &str| ClientTask::new(0, 0, dir.join(input), dir.join(output), vec![Filter::Nop]);
+
+        assert_eq!(policy.denied(&task("inputs/in", "outputs/out")), None);
+        assert_eq!(policy.denied(&task("inputs/*.log", "outputs/new/dir")), None);
+        assert_eq!(policy.denied(&task("secrets/in", "outputs/out")), Some(dir.join("secrets/in").as_path()));
+        assert_eq!(policy.denied(&task("inputs/link/in", "outputs/out")), Some(dir.join("inputs/link/in").as_path()));
+        assert_eq!(policy.denied(&task("inputs/../secrets/in", "outputs/out")), Some(dir.join("inputs/../secrets/in").as_path()));
+        assert_eq!(policy.denied(&task("inputs/in", "outputs/new/../../secrets/out")), Some(dir.join("outputs/new/../../secrets/out").as_path()));
+        assert_eq!(policy.denied(&task("inputs/in", "inputs/out")), Some(dir.join("inputs/out").as_path()));
+
+        // Only the server's own copies are exempt, whatever the client claims of its task.
+        let mut streamed = task("secrets/in", "secrets/out");
+        streamed.stream = true;
+        assert_eq!(policy.denied(&streamed), Some(dir.join("secrets/in").as_path()));
+        streamed.spooled = true;
+        assert_eq!(policy.denied(&streamed), None);
+        streamed.cwd = Some(dir.join("secrets"));
+        assert_eq!(policy.denied(&streamed), Some(dir.join("secrets").as_path()));
+        let mut stored = task("inputs/in", "store/tmp");
+        stored.store = true;
+        assert_eq!(policy.denied(&stored), None);
+        let remote = ClientTask::new(0, 0, dir.join("inputs/in"), PathBuf::from("s3://bucket/out"), vec![Filter::Nop]);
+        assert_eq!(policy.denied(&remote), Some(Path::new("s3://bucket/out")));
+        assert_eq!(PathPolicy::default().denied(&task("secrets/in", "secrets/out")), None);
+    }
+
+    #[test]
+    fn opened_files_are_allowed_where_they_really_are() {
+        let dir = TempDir::new("auth_opened");
+        for sub in ["inputs", "secrets"] {
+            fs::create_dir_all(dir.join(sub)).unwrap();
+        }
+        fs::write(dir.join("inputs/in"), b"").unwrap();
+        fs::write(dir.join("secrets/in"), b"").unwrap();
+        std::os::unix::fs::symlink(dir.join("secrets/in"), dir.join("inputs/link")).unwrap();
+        let policy = PathPolicy { inputs: Some(vec![dir.join("inputs")]), outputs: Some(vec![dir.join("inputs")]) };
+
+        assert!(policy.allows_input(&fs::File::open(dir.join("inputs/in")).unwrap()));
+        assert!(!policy.allows_input(&fs::File::open(dir.join("inputs/link")).unwrap()));
+        assert!(!policy.allows_output(&fs::File::open(dir.join("secrets/in")).unwrap()));
+        assert!(PathPolicy::default().allows_input(&fs::File::open(dir.join("secrets/in")).unwrap()));
+
+        let mut stored = ClientTask::new(0, 0, dir.join("inputs/in"), dir.join("store/out"), vec![Filter::Nop]);
+        stored.store = true;
+        assert_eq!(policy.for_task(&stored), PathPolicy { outputs: None, ..policy.clone() });
+        stored.spooled = true;
+        assert_eq!(policy.for_task(&stored), PathPolicy::default());
+    }
+}
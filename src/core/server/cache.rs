@@ -0,0 +1,174 @@
+//! The server's cache of the outputs of tasks, by their input's contents and pipeline, so
+//! that running a pipeline again on an unchanged file doesn't run any filter, see
+//! [`ResultCache`].
+
+use std::{
+    cmp::Reverse, fs, io,
+    path::{Path, PathBuf},
+    sync::{Mutex, PoisonError},
+    time::SystemTime,
+};
+
+use sha2::{Digest, Sha256};
+use uuid::Uuid;
+
+use crate::core::filter::Filter;
+
+use super::config::FilterExecutor;
+
+/// Size the cache may grow to before its least recently used outputs are evicted, unless
+/// configured otherwise, see [`CacheConfig::max_size`].
+pub const DEFAULT_CACHE_MAX_SIZE: u64 = 1 << 30;
+
+/// Prefix of the files outputs are copied to, before they are renamed to their entry.
+const TMP_PREFIX: &str = "tmp.";
+
+/// Where the cache is kept, and how large it may grow.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CacheConfig {
+    /// Directory holding a file per cached output, named by its key, see [`key`].
+    pub dir: PathBuf,
+    /// Size, in bytes, past which the least recently used outputs are removed.
+    pub max_size: u64,
+}
+
+/// Outputs of past tasks, each in a file of the cache's directory named by its [`key`].
+///
+/// Outputs are copied in and out of the cache, rather than hard linked, so that changing an
+/// output in place doesn't change what later tasks are given. The modification time of an
+/// entry is when it was last used, which eviction goes by.
+pub struct ResultCache {
+    config: CacheConfig,
+    /// Held while the cache's size is checked, so that outputs are evicted once.
+    evicting: Mutex<()>,
+}
+
+/// Key of the output of running `filters`, with `executors`, on an input with the
+/// hex-encoded SHA-256 hash `sha256_in`.
+///
+/// The executors are part of the key, as the same filter run by another executable may
+/// well give another output.
+pub fn key(sha256_in: &str, filters: &[Filter], executors: &[FilterExecutor]) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(sha256_in);
+    for (filter, executor) in filters.iter().zip(executors) {
+        hasher.update(format!("\n{filter} {executor}"));
+    }
+    format!("{:x}", hasher.finalize())
+}
+
+impl ResultCache {
+    /// Open the cache's directory, creating it if it doesn't exist, and removing the copies
+    /// left over by a server that stopped while storing outputs.
+    pub fn open(config: CacheConfig) -> io::Result<Self> {
+        fs::create_dir_all(&config.dir)?;
+        for entry in fs::read_dir(&config.dir)? {
+            let entry = entry?;
+            if entry.file_name().to_string_lossy().starts_with(TMP_PREFIX) {
+                fs::remove_file(entry.path())?;
+            }
+        }
+        Ok(ResultCache { config, evicting: Mutex::new(()) })
+    }
+
+    /// Copy the output cached under `key`, if there is one, to `output`, an open file
+    /// rather than a path, as it may be in a directory others can write to.
+    ///
+    /// Returns whether there was one.
+    pub fn fetch(&self, key: &str, output: &mut fs::File) -> io::Result<bool> {
+        let entry = self.config.dir.join(key);
+        match fs::File::open(&entry).and_then(|mut cached| io::copy(&mut cached, output)) {
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(false),
+            Err(err) => Err(err),
+            Ok(_) => {
+                // The entry may have been evicted since, in which case it needn't be touched.
+                if let Ok(file) = fs::File::options().write(true).open(&entry) {
+                    file.set_modified(SystemTime::now())?;
+                }
+                Ok(true)
+            },
+        }
+    }
+
+    /// Cache a copy of `output` under `key`, replacing any output already there, then
+    /// evict the least recently used outputs, if the cache grew too large.
+    pub fn store(&self, key: &str, output: &Path) -> io::Result<()> {
+        // Copied aside first, so that the entry is never seen half written.
+        let tmp = self.config.dir.join(format!("{TMP_PREFIX}{}", Uuid::new_v4()));
+        if let Err(err) = fs::copy(output, &tmp).and_then(|_| fs::rename(&tmp, self.config.dir.join(key))) {
+            let _ = fs::remove_file(&tmp);
+            return Err(err)
+        }
+        self.evict()
+    }
+
+    /// Remove the least recently used outputs until the cache is no larger than its
+    /// maximum size.
+    fn evict(&self) -> io::Result<()> {
+        let _evicting = self.evicting.lock().unwrap_or_else(PoisonError::into_inner);
+
+        let mut entries = Vec::new();
+        for entry in fs::read_dir(&self.config.dir)? {
+            let entry = entry?;
+            if entry.file_name().to_string_lossy().starts_with(TMP_PREFIX) {
+                continue
+            }
+            // Entries may be evicted by another server sharing the directory meanwhile.
+            if let Ok(metadata) = entry.metadata() {
+                entries.push((metadata.modified()?, metadata.len(), entry.path()));
+            }
+        }
+        entries.sort_by_key(|(used_at, ..)| Reverse(*used_at));
+
+        let mut size = 0;
+        for (_, len, path) in entries {
+            size += len;
+            if size > self.config.max_size {
+                match fs::remove_file(&path) {
+                    Err(err) if err.kind() != io::ErrorKind::NotFound => return Err(err),
+                    _ => log::debug!("evicted {} from the result cache", path.display()),
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use crate::core::testing::TempDir;
+
+    use super::*;
+
+    #[test]
+    fn outputs_are_cached_until_evicted() {
+        let dir = TempDir::new("cache");
+        let cache = ResultCache::open(CacheConfig { dir: dir.join("cache"), max_size: 12 }).unwrap();
+        let (output, fetched) = (dir.join("output"), dir.join("fetched"));
+        let fetch = |key: &str| cache.fetch(key, &mut fs::File::create(&fetched).unwrap()).unwrap();
+
+        let nop = [FilterExecutor::Builtin(Filter::Nop)];
+        let external = [FilterExecutor::External(PathBuf::from("bin/nop"))];
+        let (first, second) = (key("aa", &[Filter::Nop], &nop), key("bb", &[Filter::Nop], &nop));
+        assert_ne!(first, key("aa", &[Filter::Nop], &external));
+        assert_ne!(first, key("aa", &[Filter::Nop, Filter::Nop], &[nop.clone(), nop.clone()].concat()));
+
+        assert!(!fetch(&first));
+        fs::write(&output, "first").unwrap();
+        cache.store(&first, &output).unwrap();
+        assert!(fetch(&first));
+        assert_eq!(fs::read_to_string(&fetched).unwrap(), "first");
+
+        // The first output is used after the second is stored, so the second is evicted.
+        fs::write(&output, "second").unwrap();
+        cache.store(&second, &output).unwrap();
+        std::thread::sleep(Duration::from_millis(10));
+        assert!(fetch(&first));
+        fs::write(&output, "third").unwrap();
+        cache.store(&key("cc", &[Filter::Nop], &nop), &output).unwrap();
+        assert!(!fetch(&second));
+        assert!(fetch(&first));
+    }
+}
@@ -0,0 +1,145 @@
+//! Validating the server's config without starting it, as `sdstored --check-config` does,
+//! see [`check_config`].
+
+use std::{
+    ffi::CString, fmt::Write, fs, os::unix::{ffi::OsStrExt, fs::PermissionsExt}, path::Path,
+};
+
+use super::{config::ServerConfig, numbering::TaskCounter, wasm::WasmRuntime};
+
+/// Everything in `config` that would keep the server from serving requests, beyond what
+/// building it checks, see [`ServerConfig::build`]:
+///
+/// * the transformations path must be a directory;
+/// * every filter the server may run must have an executable, if it isn't builtin;
+/// * the modules of WASM filters must compile, and the server support them;
+/// * the directories tasks' files are allowed in, if only some are, must exist;
+/// * the socket directory must be a directory the server may bind sockets in, which other
+///   users may not remove, unless it is sticky, as `/tmp` is;
+/// * the task counter's file, if it exists yet, must hold the number of the next task.
+///
+/// Empty if no problem was found.
+pub fn check_config(config: &ServerConfig) -> Vec<String> {
+    let mut problems = Vec::new();
+
+    let transformations_path = config.transformations_path();
+    if !transformations_path.is_dir() {
+        problems.push(format!("the transformations path {} is not a directory", transformations_path.display()));
+    }
+
+    for (filter, path) in config.missing_executables() {
+        problems.push(format!("no executable for filter {filter} at {}", path.display()));
+    }
+    // Missing modules were just reported.
+    let modules = config.wasm_modules().into_iter().filter(|path| path.is_file()).collect::<Vec<_>>();
+    if !modules.is_empty() {
+        if let Err(err) = WasmRuntime::load(&modules) {
+            problems.push(err.to_string());
+        }
+    }
+
+    let allowed = [&config.path_policy.inputs, &config.path_policy.outputs];
+    for dir in allowed.into_iter().flatten().flatten().filter(|dir| !dir.is_dir()) {
+        problems.push(format!("the allowed directory {} is not a directory", dir.display()));
+    }
+
+    problems.extend(check_socket_dir(&config.socket_dir));
+    if let Err(err) = TaskCounter::open(&config.counter_file) {
+        problems.push(format!("the task counter {} can't be read: {err}", config.counter_file.display()));
+    }
+    problems
+}
+
+/// Why the server could not use `dir` as its socket directory, if it couldn't.
+fn check_socket_dir(dir: &Path) -> Option<String> {
+    let meta = match fs::metadata(dir) {
+        Ok(meta) if meta.is_dir() => meta,
+        Ok(_) => return Some(format!("the socket directory {} is not a directory", dir.display())),
+        Err(err) => return Some(format!("the socket directory {} can't be read: {err}", dir.display())),
+    };
+
+    let writable = CString::new(dir.as_os_str().as_bytes())
+        // SAFETY: `path` is a valid C string.
+        .map(|path| unsafe { libc::access(path.as_ptr(), libc::W_OK | libc::X_OK) } == 0)
+        .unwrap_or(false);
+    let mode = meta.permissions().mode();
+    match (writable, mode & 0o002 != 0 && mode & 0o1000 == 0) {
+        (false, _) => Some(format!("the socket directory {} is not writable by the server", dir.display())),
+        (true, true) => Some(format!(
+            "the socket directory {} is writable by every user, but not sticky, so that they may remove the server's sockets",
+            dir.display()
+        )),
+        (true, false) => None,
+    }
+}
+
+/// What the server would run with `config`: its paths, queues, and the filters it may run,
+/// with their limits and how they are run.
+pub fn summary(config: &ServerConfig) -> String {
+    let mut summary = String::new();
+    // Writing to a `String` can't fail.
+    let _ = writeln!(summary, "transformations: {}", config.transformations_path().display());
+    let _ = writeln!(summary, "socket dir: {} ({:?})", config.socket_dir.display(), config.socket_namespace);
+    let _ = writeln!(summary, "task counter: {}", config.counter_file.display());
+    let _ = writeln!(summary, "scheduling policy: {}", config.scheduling_policy);
+    if let Some(limit) = config.rate_limit {
+        let _ = writeln!(summary, "rate limit: bursts of {} request(s) per client, refilled every {:?}", limit.burst, limit.refill);
+    }
+    if let Some(account) = &config.account {
+        let _ = writeln!(summary, "runs as: {account}");
+    }
+    if let Some(umask) = config.umask {
+        let _ = writeln!(summary, "umask: {umask:03o}");
+    }
+    if let Some(output_mode) = config.output_mode {
+        let _ = writeln!(summary, "output mode: {output_mode:03o}");
+    }
+    if config.sandbox.is_enabled() {
+        let sandbox = [(config.sandbox.namespaces, "namespaces"), (config.sandbox.seccomp, "seccomp")];
+        let enabled = sandbox.iter().filter(|(enabled, _)| *enabled).map(|(_, name)| *name).collect::<Vec<_>>();
+        let _ = writeln!(summary, "sandbox: {}", enabled.join(", "));
+    }
+    let environment = &config.environment;
+    if !environment.vars.is_empty() {
+        let vars = environment.vars.iter().map(|(name, value)| format!("{name}={value}")).collect::<Vec<_>>();
+        let _ = writeln!(summary, "filter environment: {}", vars.join(" "));
+    }
+    if let Some(cwd) = &environment.cwd {
+        let _ = writeln!(summary, "filter working dir: {}", cwd.display());
+    }
+    if !environment.allowed.is_empty() {
+        let allowed = environment.allowed.iter().map(String::as_str).collect::<Vec<_>>();
+        let _ = writeln!(summary, "tasks may set: {}", allowed.join(", "));
+    }
+    for queue in &config.queues {
+        let _ = writeln!(summary, "queue {}: weight {}", queue.name, queue.weight);
+    }
+
+    for filter in config.filters_config.filters().iter().filter(|filter| config.filters_config.limit(filter) > 0) {
+        let executor = config.filter_executor(filter);
+        let _ = writeln!(summary, "filter {filter}: limit {}, {executor}", config.filters_config.limit(filter));
+    }
+    summary
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::Permissions;
+
+    use crate::core::testing::TempDir;
+
+    use super::*;
+
+    #[test]
+    fn socket_dir_checks_work() {
+        let dir = TempDir::new("check");
+
+        for (mode, ok) in [(0o700, true), (0o1777, true), (0o777, false)] {
+            fs::set_permissions(&dir, Permissions::from_mode(mode)).unwrap();
+            assert_eq!(check_socket_dir(&dir).is_none(), ok, "mode {mode:o}");
+        }
+        fs::write(dir.join("file"), b"").unwrap();
+        assert!(check_socket_dir(&dir.join("file")).is_some());
+        assert!(check_socket_dir(&dir.join("missing")).is_some());
+    }
+}
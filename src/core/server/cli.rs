@@ -0,0 +1,276 @@
+//! The `sdstored` server's command line, and environment, from which its config is built,
+//! see [`ServerConfig::build`](super::config::ServerConfig::build).
+
+use std::{env, net::SocketAddr, path::PathBuf};
+
+use clap::{builder::PossibleValuesParser, Parser};
+
+use crate::core::client_task::parse_mode;
+
+use super::coordinator::{WorkerToken, TOKEN_VAR};
+
+/// Names of the scheduling policies, see [`SchedulingPolicy`](super::scheduler::SchedulingPolicy).
+const SCHEDULING_POLICIES: [&str; 5] = ["priority", "fifo", "shortest-file", "priority-shortest-file", "weighted-fair"];
+
+/// Names of the levels the server may log at, from the least verbose.
+const LOG_LEVELS: [&str; 6] = ["off", "error", "warn", "info", "debug", "trace"];
+
+/// Names of the formats the server may log in, see [`LogFormat`](crate::util::LogFormat).
+const LOG_FORMATS: [&str; 2] = ["text", "json"];
+
+/// Names of the sinks the server may log to, see [`LogSink`](crate::util::LogSink).
+const LOG_SINKS: [&str; 3] = ["terminal", "journald", "syslog"];
+
+/// Environment variable giving the server's limits file, see [`ServerEnv`].
+pub const LIMITS_FILE_VAR: &str = "SDSTORED_LIMITS_FILE";
+
+/// Environment variable giving the directory of the filters' executables, see [`ServerEnv`].
+pub const TRANSFORMATIONS_DIR_VAR: &str = "SDSTORED_TRANSFORMATIONS_DIR";
+
+/// Environment variable giving the server's socket directory, see [`ServerEnv`].
+pub const SOCKET_DIR_VAR: &str = "SDSTORED_SOCKET_DIR";
+
+/// Environment variable giving the most verbose level the server logs at, see [`ServerEnv`].
+pub const LOG_LEVEL_VAR: &str = "SDSTORED_LOG_LEVEL";
+
+/// Environment variable giving the format the server logs in, see [`ServerEnv`].
+pub const LOG_FORMAT_VAR: &str = "SDSTORED_LOG_FORMAT";
+
+/// Run the `sdstored` server, applying filters to files on behalf of its `sdstore` clients.
+///
+/// Settings may be read from a TOML file with `--config`, which the other options override.
+/// Some may also be given by `SDSTORED_*` environment variables, which both override.
+#[derive(Debug, Default, Parser)]
+#[command(name = "sdstored", version)]
+pub struct ServerCli {
+    /// TOML file to read the server's settings from.
+    #[arg(long, value_name = "FILE")]
+    pub config: Option<PathBuf>,
+    /// File of the server's filter limits, queues, and other settings. Replaces the config
+    /// file's `[limits]`, `[executables]`, `[filters]` and `[queues]`. Defaults to
+    /// `$SDSTORED_LIMITS_FILE`.
+    #[arg(long, value_name = "FILE")]
+    pub limits_file: Option<PathBuf>,
+    /// Directory of the filters' executables. Defaults to `$SDSTORED_TRANSFORMATIONS_DIR`.
+    #[arg(long, value_name = "DIR")]
+    pub transformations_dir: Option<PathBuf>,
+    /// Policy deciding which pending request runs next. Defaults to `priority`.
+    #[arg(long, value_name = "POLICY", value_parser = PossibleValuesParser::new(SCHEDULING_POLICIES))]
+    pub scheduling_policy: Option<String>,
+    /// Directory of the server's sockets, and its clients'. Defaults to `$SDSTORED_SOCKET_DIR`,
+    /// or `$SDSTORE_SOCK_DIR`, if set, or else to `sdstore` in `$XDG_RUNTIME_DIR`, or else to
+    /// `/run/sdstore`.
+    #[arg(long, visible_alias = "socket-path", value_name = "DIR")]
+    pub socket_dir: Option<PathBuf>,
+    /// Most verbose level to log at. Defaults to `$SDSTORED_LOG_LEVEL`, or else to `trace`.
+    #[arg(long, value_name = "LEVEL", value_parser = PossibleValuesParser::new(LOG_LEVELS))]
+    pub log_level: Option<String>,
+    /// Most verbose level to log the messages of a module at, and of its submodules, as in
+    /// `rust_sdstore::core::server::state=info`, over `--log-level`. May be repeated.
+    #[arg(long = "log-target", value_name = "TARGET=LEVEL")]
+    pub log_targets: Vec<String>,
+    /// Format to log in: `text`, or `json`, an object per line, for log pipelines to index.
+    /// Defaults to `$SDSTORED_LOG_FORMAT`, or else to `text`.
+    #[arg(long, value_name = "FORMAT", value_parser = PossibleValuesParser::new(LOG_FORMATS))]
+    pub log_format: Option<String>,
+    /// Log through a `tracing` subscriber, with what is logged about each task in a span
+    /// of its own, whichever thread logs it, rather than with `simplelog`.
+    #[arg(long)]
+    pub log_tracing: bool,
+    /// Where to log, other than to the `--log-file`: `terminal`, or `journald` or `syslog`, as
+    /// when run as a systemd service. Defaults to `terminal`.
+    #[arg(long, value_name = "SINK", value_parser = PossibleValuesParser::new(LOG_SINKS))]
+    pub log_sink: Option<String>,
+    /// File to write logs to, as well as to the terminal.
+    #[arg(long, value_name = "FILE")]
+    pub log_file: Option<PathBuf>,
+    /// File to append a record of every change in the lifecycle of every task to, apart from
+    /// the logs, rotated as the config file's `[audit]` says.
+    #[arg(long, value_name = "FILE")]
+    pub audit_file: Option<PathBuf>,
+    /// Directory to cache the outputs of tasks in, by their input's contents and pipeline,
+    /// sized as the config file's `[cache]` says.
+    #[arg(long, value_name = "DIR")]
+    pub cache_dir: Option<PathBuf>,
+    /// Directory of the store that requests made with `proc-file --store` write their
+    /// outputs to, each named by the SHA-256 hash of its contents.
+    #[arg(long, value_name = "DIR")]
+    pub store_dir: Option<PathBuf>,
+    /// Address to listen for `sdstored-worker`s on, over TCP, which register to run pending
+    /// requests within their own filter limits, e.g. `0.0.0.0:7070`. Workers must give the
+    /// token in `$SDSTORED_WORKER_TOKEN`, if set.
+    #[arg(long, value_name = "ADDR")]
+    pub worker_listen: Option<SocketAddr>,
+    /// File to write the server's PID to once it's ready to take requests, removed once it
+    /// shuts down.
+    #[arg(long, value_name = "FILE")]
+    pub pid_file: Option<PathBuf>,
+    /// File the number of the server's next task is kept in, so that tasks are numbered on
+    /// from one run to the next. Defaults to `sdstored_counter` in the socket directory.
+    #[arg(long, value_name = "FILE")]
+    pub counter_file: Option<PathBuf>,
+    /// User to run as once the server's sockets are bound, by name or UID, when started as
+    /// root, as to bind them in `/run/sdstore`.
+    #[arg(long, value_name = "USER")]
+    pub user: Option<String>,
+    /// Group to run as along with `--user`, by name or GID. Defaults to the user's own.
+    #[arg(long, value_name = "GROUP", requires = "user")]
+    pub group: Option<String>,
+    /// Permission bits, in octal, of the outputs of requests that don't give their own with
+    /// `proc-file --mode`, e.g. `640`, rather than those the umask leaves.
+    #[arg(long, value_name = "MODE", value_parser = parse_mode)]
+    pub output_mode: Option<u32>,
+    /// Umask, in octal, to create files with once the server's sockets are bound, e.g. `027`.
+    #[arg(long, value_name = "MASK", value_parser = parse_mode)]
+    pub umask: Option<u32>,
+    /// Stay attached to the terminal, rather than running in the background once ready to
+    /// take requests.
+    #[arg(long)]
+    pub foreground: bool,
+    /// Only check the config, the filters' executables and the socket directory, printing a
+    /// summary, and exit: with an error if there are problems.
+    #[arg(long)]
+    pub check_config: bool,
+}
+
+/// Run an `sdstored-worker`, which registers with an `sdstored` server listening for workers,
+/// see its `--worker-listen`, and runs the pending requests it is handed, within its own
+/// filter limits.
+///
+/// The worker's limits and filters are read as the server's are, from `--config` and the
+/// `SDSTORED_*` environment variables, which must point at the same storage the server's
+/// clients' files are on.
+#[derive(Debug, Parser)]
+#[command(name = "sdstored-worker", version)]
+pub struct WorkerCli {
+    /// Address of the server to register with, as `HOST:PORT`.
+    #[arg(long, value_name = "ADDR")]
+    pub coordinator: String,
+    /// Name the server knows the worker by, in its logs. Defaults to the host's name.
+    #[arg(long)]
+    pub name: Option<String>,
+    /// TOML file to read the worker's settings from, as the server's.
+    #[arg(long, value_name = "FILE")]
+    pub config: Option<PathBuf>,
+    /// File of the worker's filter limits. Defaults to `$SDSTORED_LIMITS_FILE`.
+    #[arg(long, value_name = "FILE")]
+    pub limits_file: Option<PathBuf>,
+    /// Directory of the filters' executables. Defaults to `$SDSTORED_TRANSFORMATIONS_DIR`.
+    #[arg(long, value_name = "DIR")]
+    pub transformations_dir: Option<PathBuf>,
+    /// Most verbose level to log at. Defaults to `$SDSTORED_LOG_LEVEL`, or else to `trace`.
+    #[arg(long, value_name = "LEVEL", value_parser = PossibleValuesParser::new(LOG_LEVELS))]
+    pub log_level: Option<String>,
+    /// Format to log in: `text`, or `json`. Defaults to `$SDSTORED_LOG_FORMAT`, or else to `text`.
+    #[arg(long, value_name = "FORMAT", value_parser = PossibleValuesParser::new(LOG_FORMATS))]
+    pub log_format: Option<String>,
+    /// File to write logs to, as well as to the terminal.
+    #[arg(long, value_name = "FILE")]
+    pub log_file: Option<PathBuf>,
+}
+
+impl WorkerCli {
+    /// The command line of a server with the worker's settings, from which its config is
+    /// built, see [`ServerConfig::build`](super::config::ServerConfig::build).
+    pub fn server_cli(&self) -> ServerCli {
+        ServerCli {
+            config: self.config.clone(),
+            limits_file: self.limits_file.clone(),
+            transformations_dir: self.transformations_dir.clone(),
+            log_level: self.log_level.clone(),
+            log_format: self.log_format.clone(),
+            log_file: self.log_file.clone(),
+            foreground: true,
+            ..Default::default()
+        }
+    }
+}
+
+/// Settings of the server given by environment variables, e.g. to a container, for those
+/// neither given on the command line nor in the config file, see [`ServerCli`].
+///
+/// Variables that are empty are ignored, as if unset.
+#[derive(Debug, Default)]
+pub struct ServerEnv {
+    /// See [`LIMITS_FILE_VAR`].
+    pub limits_file: Option<PathBuf>,
+    /// See [`TRANSFORMATIONS_DIR_VAR`].
+    pub transformations_dir: Option<PathBuf>,
+    /// See [`SOCKET_DIR_VAR`].
+    pub socket_dir: Option<PathBuf>,
+    /// See [`LOG_LEVEL_VAR`], which is checked as the config is built.
+    pub log_level: Option<String>,
+    /// See [`LOG_FORMAT_VAR`], which is checked as the config is built.
+    pub log_format: Option<String>,
+    /// See [`TOKEN_VAR`].
+    pub worker_token: Option<WorkerToken>,
+}
+
+impl ServerEnv {
+    /// Read the server's settings from the process' environment.
+    pub fn read() -> Self {
+        let var = |name| env::var_os(name).filter(|value| !value.is_empty());
+        ServerEnv {
+            limits_file: var(LIMITS_FILE_VAR).map(PathBuf::from),
+            transformations_dir: var(TRANSFORMATIONS_DIR_VAR).map(PathBuf::from),
+            socket_dir: var(SOCKET_DIR_VAR).map(PathBuf::from),
+            log_level: var(LOG_LEVEL_VAR).map(|level| level.to_string_lossy().into_owned()),
+            log_format: var(LOG_FORMAT_VAR).map(|format| format.to_string_lossy().into_owned()),
+            worker_token: var(TOKEN_VAR).map(|token| WorkerToken(token.to_string_lossy().into_owned())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::error::ErrorKind;
+
+    use super::*;
+
+    fn parse(command: &str) -> Result<ServerCli, clap::Error> {
+        ServerCli::try_parse_from(command.split_ascii_whitespace())
+    }
+
+    #[test]
+    fn server_cli_parsing_works() {
+        let cli = parse("sdstored --limits-file limits.txt --transformations-dir bin --socket-path /run/sdstore \
+            --scheduling-policy fifo --log-level info --log-target sdstored=debug --log-target rust_sdstore=warn \
+            --log-format json --log-tracing --log-sink journald --foreground").unwrap();
+        assert_eq!(cli.limits_file, Some(PathBuf::from("limits.txt")));
+        assert_eq!(cli.transformations_dir, Some(PathBuf::from("bin")));
+        assert_eq!(cli.socket_dir, Some(PathBuf::from("/run/sdstore")));
+        assert_eq!((cli.scheduling_policy.as_deref(), cli.log_level.as_deref()), (Some("fifo"), Some("info")));
+        assert_eq!(cli.log_targets, ["sdstored=debug", "rust_sdstore=warn"]);
+        assert_eq!((cli.log_format.as_deref(), cli.log_sink.as_deref()), (Some("json"), Some("journald")));
+        assert!(cli.log_tracing && cli.foreground);
+
+        let cli = parse("sdstored --config sdstored.toml --check-config").unwrap();
+        assert_eq!((cli.config, cli.limits_file, cli.foreground), (Some(PathBuf::from("sdstored.toml")), None, false));
+        assert!(cli.check_config);
+
+        // The limits and filters may also be given by the environment, see `ServerEnv`.
+        assert_eq!(parse("sdstored --limits-file limits.txt").unwrap().transformations_dir, None);
+        assert_eq!(
+            parse("sdstored --config sdstored.toml --log-level loud").unwrap_err().kind(),
+            ErrorKind::InvalidValue
+        );
+        assert_eq!(parse("sdstored limits.txt bin").unwrap_err().kind(), ErrorKind::UnknownArgument);
+        assert_eq!(parse("sdstored --worker-listen 0.0.0.0:7070").unwrap().worker_listen, "0.0.0.0:7070".parse().ok());
+        assert_eq!(parse("sdstored --worker-listen host").unwrap_err().kind(), ErrorKind::ValueValidation);
+    }
+
+    #[test]
+    fn worker_cli_parsing_works() {
+        let cli = WorkerCli::try_parse_from(
+            "sdstored-worker --coordinator main:7070 --name w1 --limits-file limits.txt --log-level info".split_ascii_whitespace()
+        ).unwrap();
+        assert_eq!((cli.coordinator.as_str(), cli.name.as_deref()), ("main:7070", Some("w1")));
+        let server_cli = cli.server_cli();
+        assert_eq!(server_cli.limits_file, Some(PathBuf::from("limits.txt")));
+        assert_eq!((server_cli.log_level.as_deref(), server_cli.worker_listen), (Some("info"), None));
+        assert!(server_cli.foreground);
+
+        let missing = WorkerCli::try_parse_from(["sdstored-worker", "--limits-file", "limits.txt"]);
+        assert_eq!(missing.unwrap_err().kind(), ErrorKind::MissingRequiredArgument);
+    }
+}
@@ -1,10 +1,40 @@
-use std::{fs, io, path::PathBuf};
+use std::{collections::{BTreeMap, HashMap}, fmt::Display, fs, io, net::SocketAddr, num::{NonZeroU64, NonZeroUsize}, os::unix::fs::PermissionsExt, path::{Path, PathBuf}, time::Duration};
+
+use serde::{Deserialize, Serialize};
+
+use crate::core::{
+    batch, builtin, chunking, client_task::{ClientTask, DEFAULT_QUEUE, MODE_BITS}, filter::{Filter, FilterParseError},
+    messaging::{WireFormat, WireFormatParseError, DEFAULT_MAX_TRANSMISSIONS, DEFAULT_RETRANSMIT_AFTER},
+    monitor::DEFAULT_PROGRESS_INTERVAL,
+    paths,
+    remote,
+    server_info::{FilterInfo, ServerInfo},
+    transport::{SocketNamespace, SocketNamespaceParseError, TransportMode, TransportModeParseError},
+};
+use crate::util::{LogFormat, LogFormatParseError, LogSink, LogSinkParseError, Rotation};
+
+use super::{
+    audit::{AuditConfig, DEFAULT_AUDIT_KEEP, DEFAULT_AUDIT_MAX_SIZE},
+    auth::PathPolicy,
+    cache::{CacheConfig, DEFAULT_CACHE_MAX_SIZE},
+    cli::{ServerCli, ServerEnv},
+    config_file::{ConfigFile, ConfigFileError},
+    coordinator::WorkerToken,
+    environment::{self, FilterEnvironment},
+    numbering,
+    privileges::{Account, AccountError},
+    rate_limit::{RateLimit, DEFAULT_REFILL},
+    resources::{ResourceLimits, ResourceLineParseError, RESOURCE_KEYWORDS},
+    sandbox::{Sandbox, SandboxLineParseError, SANDBOX_KEYWORD},
+    scheduler::{SchedulingPolicy, SchedulingPolicyParseError},
+    wasm,
+};
 
 /// Representation of the maximum allowed concurrent instances of each filter
 /// the server is permitted to run.
 ///
 /// This is to be read from a file passed to the server executable.
-#[derive(Debug, Clone, PartialEq, Eq, PartialOrd)]
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
 pub struct FiltersConfig {
     pub nop: usize,
     pub bcompress: usize,
@@ -12,7 +42,13 @@ pub struct FiltersConfig {
     pub gcompress: usize,
     pub gdecompress: usize,
     pub encrypt: usize,
-    pub decrypt: usize
+    pub decrypt: usize,
+    pub zcompress: usize,
+    pub zdecompress: usize,
+    pub xcompress: usize,
+    pub xdecompress: usize,
+    /// Limits of the filters registered in the config, by name, see [`Filter::Custom`].
+    pub custom: BTreeMap<String, usize>
 }
 
 /// Errors that may happen when parsing a server's filter limits config file.
@@ -20,33 +56,99 @@ pub struct FiltersConfig {
 pub enum FilterCfgParseError {
     LineParseError,
     FilterLimitParseError(String),
-    NoConfigFileProvided,
+    /// A limit was given for a filter that doesn't exist, e.g. misspelled.
+    UnknownFilter(String),
+    /// A `queue <name> <weight>` line was malformed, or its weight was `0`.
+    QueueLineParseError(String),
+    /// The same queue was defined twice.
+    DuplicateQueue(String),
+    /// A `builtin <filter>+` line named an unknown filter.
+    BuiltinLineParseError(FilterParseError),
+    /// A `builtin <filter>+` line named a filter without a builtin implementation, see
+    /// [`builtin::has_builtin`].
+    NoBuiltin(Filter),
+    /// A `restartable <filter>+` line named an unknown filter.
+    RestartableLineParseError(FilterParseError),
+    /// A filter whose output can't be resumed was marked restartable, see
+    /// [`ServerConfig::is_restartable`].
+    NotRestartable(Filter),
+    /// A resource limit line was malformed, see [`ResourceLimits::parse_line`].
+    ResourceLineParseError(ResourceLineParseError),
+    /// A `pool <size>` line was malformed.
+    PoolLineParseError(String),
+    /// A sandbox line was malformed, see [`Sandbox::parse_line`].
+    SandboxLineParseError(SandboxLineParseError),
+    /// Filters were both sandboxed and pooled, whereas pooled processes are started before
+    /// the tasks whose files they'd be confined to are known.
+    SandboxedPool,
+    /// An `allow-uids <uid>+` line was malformed.
+    AllowUidsLineParseError(String),
+    /// An `allow-inputs <dir>+` or `allow-outputs <dir>+` line was malformed.
+    AllowPathsLineParseError(String),
+    /// An `executable <filter> <path>` line was malformed, or named an unknown filter.
+    ExecutableLineParseError(String),
+    /// A `filter <name> <limit> [<path>]` line was malformed, or named a filter the server
+    /// already knows of.
+    FilterLineParseError(String),
     ConfigFileReadError(io::Error)
 }
 
 impl FiltersConfig {
-    pub fn default() -> Self {
-        FiltersConfig {
-            nop: 0,
-            bcompress: 0,
-            bdecompress: 0,
-            gcompress: 0,
-            gdecompress: 0,
-            encrypt: 0,
-            decrypt: 0
-        }
-    }
-
     /// Parse a `FilterConfig` from a file provided by the user.
     ///
     /// The file must be composed of lines of ASCII, where each line
     /// is of the form:
     ///
     /// `<filter-name> <nonnegative-integer>`
+    ///
+    /// or `default <nonnegative-integer>`, the limit of the filters with no line of their
+    /// own, which is otherwise `0`.
+    ///
+    /// Unknown filter names are an error, rather than have a misspelled filter's
+    /// limit be `0`.
     pub fn parse(s: &str) -> Result<Self, FilterCfgParseError> {
-        let mut conf = Self::default();
+        Self::default().parse_lines(s.lines())
+    }
+
+    /// This config, but with every filter's limit being `limit`, registered ones included.
+    fn uniform(&self, limit: usize) -> Self {
+        FiltersConfig {
+            nop: limit,
+            bcompress: limit,
+            bdecompress: limit,
+            gcompress: limit,
+            gdecompress: limit,
+            encrypt: limit,
+            decrypt: limit,
+            zcompress: limit,
+            zdecompress: limit,
+            xcompress: limit,
+            xdecompress: limit,
+            custom: self.custom.keys().map(|name| (name.clone(), limit)).collect()
+        }
+    }
 
-        for l in s.lines() {
+    /// Override this config's limits with those read from `lines`, each of the
+    /// form accepted by [`FiltersConfig::parse`].
+    ///
+    /// A `default` line overrides the limits of the filters `lines` don't list, wherever it
+    /// is among them, the last one winning if there are several.
+    fn parse_lines<'a>(
+        mut self,
+        lines: impl Iterator<Item = &'a str>
+    ) -> Result<Self, FilterCfgParseError> {
+        let (default_lines, lines): (Vec<_>, Vec<_>) = lines
+            .partition(|l| l.split_whitespace().next() == Some("default"));
+        if let Some(l) = default_lines.last() {
+            let mut words = l.split_whitespace().skip(1);
+            match (words.next().map(str::parse), words.next()) {
+                (Some(Ok(limit)), None) => self = self.uniform(limit),
+                _ => return Err(FilterCfgParseError::FilterLimitParseError(String::from("default"))),
+            }
+        }
+        let conf = &mut self;
+
+        for l in lines {
             let mut words = l.split_whitespace();
             let opt_filter = words.next();
             let opt_count = words.next();
@@ -68,68 +170,959 @@ impl FiltersConfig {
                 "gdecompress" => conf.gdecompress = count,
                 "encrypt" => conf.encrypt = count,
                 "decrypt" => conf.decrypt = count,
-                _ => {}
+                "zcompress" => conf.zcompress = count,
+                "zdecompress" => conf.zdecompress = count,
+                "xcompress" => conf.xcompress = count,
+                "xdecompress" => conf.xdecompress = count,
+                name if conf.custom.contains_key(name) => {
+                    conf.custom.insert(name.to_string(), count);
+                },
+                _ => return Err(FilterCfgParseError::UnknownFilter(filter.to_string())),
             }
         }
 
-        Ok(conf)
+        Ok(self)
     }
 
-    pub fn build(args: &mut impl Iterator<Item = String>) -> Result<Self, FilterCfgParseError> {
-        let file_path = match args.next() {
-            Some(arg) => arg,
-            None => return Err(FilterCfgParseError::NoConfigFileProvided),
-        };
+    /// The limit for a single filter.
+    pub fn limit(&self, filter: &Filter) -> usize {
+        match filter {
+            Filter::Nop         => self.nop,
+            Filter::Bcompress   => self.bcompress,
+            Filter::Bdecompress => self.bdecompress,
+            Filter::Gcompress   => self.gcompress,
+            Filter::Gdecompress => self.gdecompress,
+            Filter::Encrypt     => self.encrypt,
+            Filter::Decrypt     => self.decrypt,
+            Filter::Zcompress   => self.zcompress,
+            Filter::Zdecompress => self.zdecompress,
+            Filter::Xcompress   => self.xcompress,
+            Filter::Xdecompress => self.xdecompress,
+            Filter::Custom(name) => self.custom.get(name).copied().unwrap_or_default(),
+        }
+    }
+
+    /// Every filter the server knows of: those of [`Filter::ALL`], followed by those
+    /// registered in its config.
+    pub fn filters(&self) -> Vec<Filter> {
+        Filter::ALL
+            .into_iter()
+            .chain(self.custom.keys().cloned().map(Filter::Custom))
+            .collect()
+    }
 
+    /// Whether `filter` is one the server knows of, see [`FiltersConfig::filters`].
+    pub fn knows(&self, filter: &Filter) -> bool {
+        match filter {
+            Filter::Custom(name) => self.custom.contains_key(name),
+            _ => true,
+        }
+    }
+
+    /// Whether every filter count in `self` is within the corresponding limit in `limits`.
+    pub fn fits_within(&self, limits: &FiltersConfig) -> bool {
+        self.nop <= limits.nop &&
+        self.bcompress <= limits.bcompress &&
+        self.bdecompress <= limits.bdecompress &&
+        self.gcompress <= limits.gcompress &&
+        self.gdecompress <= limits.gdecompress &&
+        self.encrypt <= limits.encrypt &&
+        self.decrypt <= limits.decrypt &&
+        self.zcompress <= limits.zcompress &&
+        self.zdecompress <= limits.zdecompress &&
+        self.xcompress <= limits.xcompress &&
+        self.xdecompress <= limits.xdecompress &&
+        self.custom.iter().all(|(name, count)| *count <= limits.custom.get(name).copied().unwrap_or_default())
+    }
+
+    /// Read the limits file at `file_path`, see [`parse_limits`].
+    pub fn read(file_path: &Path) -> Result<LimitsFile, FilterCfgParseError> {
         let file = match fs::read_to_string(file_path) {
             Err(io_err) => return Err(FilterCfgParseError::ConfigFileReadError(io_err)),
             Ok(fd) => fd,
         };
 
-        FiltersConfig::parse(&file)
+        parse_limits(&file)
+    }
+}
+
+/// Parse the name of a filter the server knows of, builtin to it or `registered`.
+fn parse_known_filter(registered: &FiltersConfig, name: &str) -> Result<Filter, FilterParseError> {
+    name.parse().and_then(|filter| match registered.knows(&filter) {
+        true => Ok(filter),
+        false => Err(FilterParseError(name.to_string())),
+    })
+}
+
+/// A named queue, or QoS class, to which clients may submit their tasks.
+///
+/// Each queue has its own budget of concurrent filters, on top of the server-wide
+/// limits, so that e.g. batch jobs cannot take every filter slot from interactive users.
+/// When tasks from several queues could be run, the server shares filter slots
+/// between them in proportion to their weight.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct QueueConfig {
+    pub name: String,
+    pub weight: usize,
+    pub filters_config: FiltersConfig
+}
+
+impl QueueConfig {
+    /// The queue used by tasks that don't name one: weight `1`, and only bound by the
+    /// server-wide limits.
+    pub fn default_queue(filters_config: &FiltersConfig) -> Self {
+        QueueConfig {
+            name: String::from(DEFAULT_QUEUE),
+            weight: 1,
+            filters_config: filters_config.clone()
+        }
+    }
+}
+
+/// Everything read from the limits file given to the server.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LimitsFile {
+    /// Server-wide filter limits.
+    pub filters_config: FiltersConfig,
+    /// Queues clients may submit to, starting with the [`DEFAULT_QUEUE`].
+    pub queues: Vec<QueueConfig>,
+    /// Filters to be run in-process, rather than by executing a binary.
+    pub builtin_filters: Vec<Filter>,
+    /// Limits on the resources of the filters the server runs.
+    pub resource_limits: ResourceLimits,
+    /// Whether pipelines are optimized before being run, see [`optimize`](super::optimizer::optimize).
+    pub optimize_pipelines: bool,
+    /// Filters whose pipelines are checkpointed, to be resumed if the server restarts.
+    pub restartable_filters: Vec<Filter>,
+    /// Workers kept for each external filter, see [`WorkerPool`](super::pool::WorkerPool).
+    /// `0` if the server has no pool.
+    pub pool_size: usize,
+    /// How the processes of external filters are confined.
+    pub sandbox: Sandbox,
+    /// Users allowed to make requests, by UID. `None` if any user is.
+    pub allowed_uids: Option<Vec<u32>>,
+    /// Directories tasks may read and write files in.
+    pub path_policy: PathPolicy,
+    /// Executables of the filters found elsewhere than in the transformations path.
+    pub executables: HashMap<Filter, PathBuf>
+}
+
+/// Parse a limits file: the server-wide filter limits, followed by any number of
+/// queue definitions, each beginning with a line of the form
+///
+/// `queue <name> <positive-integer-weight>`
+///
+/// and followed by filter limit lines for that queue, in the same format as
+/// [`FiltersConfig::parse`]. A queue's unlisted filters get the server-wide limit.
+///
+/// Among the server-wide limits, lines of the form
+///
+/// `builtin <filter-name>+`
+///
+/// select filters to be run with their in-process implementation, see
+/// [`builtin`](crate::core::builtin), and lines such as `nice 10` limit the resources
+/// of the filters the server runs, see [`ResourceLimits::parse_line`]. A line consisting of
+/// `optimize` makes the server drop stages that would not change a pipeline's output, see
+/// [`optimize`](super::optimizer::optimize).
+///
+/// Lines of the form
+///
+/// `restartable <filter-name>+`
+///
+/// mark filters as restartable: a task whose filters all are is checkpointed as it runs,
+/// and resumed from its last checkpoint if the server restarts, see
+/// [`ServerConfig::is_restartable`]. Only filters whose output may be concatenated may be,
+/// see [`chunking::is_splittable`].
+///
+/// A line of the form `pool <size>` has the server keep `size` processes of each external
+/// filter started ahead of time, see [`WorkerPool`](super::pool::WorkerPool).
+///
+/// Lines of the form `sandbox [namespaces] [seccomp]` confine the processes of external
+/// filters, see [`Sandbox::parse_line`], which pools can't be.
+///
+/// Lines of the form `allow-uids <uid>+` restrict the users allowed to make requests to
+/// those listed, see [`auth::authenticate`](super::auth::authenticate). Lines of the form
+/// `allow-inputs <dir>+` and `allow-outputs <dir>+` restrict the files tasks may read and
+/// write to those within the directories listed, see [`PathPolicy::denied`].
+///
+/// Lines of the form `executable <filter-name> <path>` have the server run that filter's
+/// executable at `path`, which may contain spaces, rather than the one named after it in
+/// the transformations path, see [`ServerConfig::filter_executor`].
+///
+/// Lines of the form `filter <name> <limit> [<path>]` register a filter the server doesn't
+/// otherwise know of, see [`Filter::Custom`], with its server-wide limit, and optionally its
+/// executable, as with an `executable` line. It may then be used as any other filter, in the
+/// lines that follow, and by clients.
+///
+/// The returned queues always include the [`DEFAULT_QUEUE`], first.
+pub fn parse_limits(s: &str) -> Result<LimitsFile, FilterCfgParseError> {
+    let mut lines = s.lines().peekable();
+    let is_queue_line = |l: &&str| l.split_whitespace().next() == Some("queue");
+    let is_builtin_line = |l: &&str| l.split_whitespace().next() == Some("builtin");
+    let is_optimize_line = |l: &&str| l.trim() == "optimize";
+    let is_restartable_line = |l: &&str| l.split_whitespace().next() == Some("restartable");
+    let is_pool_line = |l: &&str| l.split_whitespace().next() == Some("pool");
+    let is_sandbox_line = |l: &&str| l.split_whitespace().next() == Some(SANDBOX_KEYWORD);
+    let is_allow_uids_line = |l: &&str| l.split_whitespace().next() == Some("allow-uids");
+    let is_allow_paths_line = |l: &&str| matches!(l.split_whitespace().next(), Some("allow-inputs" | "allow-outputs"));
+    let is_executable_line = |l: &&str| l.split_whitespace().next() == Some("executable");
+    let is_filter_line = |l: &&str| l.split_whitespace().next() == Some("filter");
+    let is_resource_line = |l: &&str| l
+        .split_whitespace()
+        .next()
+        .is_some_and(|word| RESOURCE_KEYWORDS.contains(&word));
+
+    let global_lines = std::iter::from_fn(|| lines.next_if(|l| !is_queue_line(l)))
+        .collect::<Vec<_>>();
+
+    // Registered filters' limits are read as those of any other filter, see below.
+    let mut registered = FiltersConfig::default();
+    let mut registered_limits = Vec::new();
+    let mut executables = HashMap::new();
+    for l in global_lines.iter().filter(|l| is_filter_line(l)) {
+        let invalid = || FilterCfgParseError::FilterLineParseError(l.to_string());
+        let rest = l.trim().trim_start_matches("filter").trim_start();
+        let (name, rest) = rest.split_once(char::is_whitespace).ok_or_else(invalid)?;
+        let (limit, path) = match rest.trim_start().split_once(char::is_whitespace) {
+            Some((limit, path)) => (limit, Some(path.trim_start())),
+            None => (rest.trim_start(), None),
+        };
+        let (name, limit) = match (name.parse(), limit.parse::<usize>()) {
+            (Ok(Filter::Custom(name)), Ok(limit)) => (name, limit),
+            _ => return Err(invalid()),
+        };
+        if registered.custom.insert(name.clone(), 0).is_some() {
+            return Err(invalid())
+        }
+        if let Some(path) = path {
+            executables.insert(Filter::Custom(name.clone()), PathBuf::from(path));
+        }
+        registered_limits.push(format!("{name} {limit}"));
+    }
+
+    let mut builtin_filters = Vec::new();
+    for l in global_lines.iter().filter(|l| is_builtin_line(l)) {
+        for filter in l.split_whitespace().skip(1) {
+            let filter = parse_known_filter(&registered, filter).map_err(FilterCfgParseError::BuiltinLineParseError)?;
+            if !builtin::has_builtin(&filter) {
+                return Err(FilterCfgParseError::NoBuiltin(filter))
+            }
+            builtin_filters.push(filter);
+        }
+    }
+
+    let mut restartable_filters = Vec::new();
+    for l in global_lines.iter().filter(|l| is_restartable_line(l)) {
+        for filter in l.split_whitespace().skip(1) {
+            let filter = parse_known_filter(&registered, filter).map_err(FilterCfgParseError::RestartableLineParseError)?;
+            if !chunking::is_splittable(std::slice::from_ref(&filter)) {
+                return Err(FilterCfgParseError::NotRestartable(filter))
+            }
+            restartable_filters.push(filter);
+        }
+    }
+
+    let mut resource_limits = ResourceLimits::default();
+    for l in global_lines.iter().filter(|l| is_resource_line(l)) {
+        resource_limits.parse_line(l).map_err(FilterCfgParseError::ResourceLineParseError)?;
+    }
+
+    let optimize_pipelines = global_lines.iter().any(is_optimize_line);
+
+    let mut pool_size = 0;
+    for l in global_lines.iter().filter(|l| is_pool_line(l)) {
+        let mut words = l.split_whitespace().skip(1);
+        pool_size = match (words.next().map(str::parse), words.next()) {
+            (Some(Ok(size)), None) => size,
+            _ => return Err(FilterCfgParseError::PoolLineParseError(l.to_string())),
+        };
+    }
+
+    let mut sandbox = Sandbox::default();
+    for l in global_lines.iter().filter(|l| is_sandbox_line(l)) {
+        sandbox.parse_line(l).map_err(FilterCfgParseError::SandboxLineParseError)?;
+    }
+    if sandbox.is_enabled() && pool_size > 0 {
+        return Err(FilterCfgParseError::SandboxedPool)
+    }
+
+    let mut allowed_uids: Option<Vec<u32>> = None;
+    for l in global_lines.iter().filter(|l| is_allow_uids_line(l)) {
+        let uids = l
+            .split_whitespace()
+            .skip(1)
+            .map(str::parse::<u32>)
+            .collect::<Result<Vec<_>, _>>()
+            .ok()
+            .filter(|uids| !uids.is_empty())
+            .ok_or_else(|| FilterCfgParseError::AllowUidsLineParseError(l.to_string()))?;
+        allowed_uids.get_or_insert_with(Vec::new).extend(uids);
+    }
+
+    let mut path_policy = PathPolicy::default();
+    for l in global_lines.iter().filter(|l| is_allow_paths_line(l)) {
+        let mut words = l.split_whitespace();
+        let allowed = match words.next() {
+            Some("allow-inputs") => &mut path_policy.inputs,
+            _ => &mut path_policy.outputs,
+        };
+        let dirs = words.map(PathBuf::from).collect::<Vec<_>>();
+        if dirs.is_empty() {
+            return Err(FilterCfgParseError::AllowPathsLineParseError(l.to_string()))
+        }
+        allowed.get_or_insert_with(Vec::new).extend(dirs);
+    }
+
+    for l in global_lines.iter().filter(|l| is_executable_line(l)) {
+        let invalid = || FilterCfgParseError::ExecutableLineParseError(l.to_string());
+        let rest = l.trim().trim_start_matches("executable").trim_start();
+        let (filter, path) = rest.split_once(char::is_whitespace).ok_or_else(invalid)?;
+        let filter = parse_known_filter(&registered, filter).map_err(|_| invalid())?;
+        executables.insert(filter, PathBuf::from(path.trim_start()));
+    }
+
+    let global = registered.parse_lines(
+        registered_limits
+            .iter()
+            .map(String::as_str)
+            .chain(global_lines.into_iter().filter(|l| {
+                !is_builtin_line(l) && !is_restartable_line(l) && !is_resource_line(l) &&
+                !is_optimize_line(l) && !is_pool_line(l) && !is_sandbox_line(l) && !is_allow_uids_line(l) &&
+                !is_allow_paths_line(l) &&
+                !is_executable_line(l) && !is_filter_line(l)
+            }))
+    )?;
+
+    let mut queues = vec![QueueConfig::default_queue(&global)];
+    let mut default_redefined = false;
+    while let Some(header) = lines.next() {
+        let mut words = header.split_whitespace().skip(1);
+        let (name, weight) = match (words.next(), words.next().map(str::parse)) {
+            (Some(name), Some(Ok(weight))) if weight > 0 => (name.to_string(), weight),
+            _ => return Err(FilterCfgParseError::QueueLineParseError(header.to_string())),
+        };
+
+        let queue_lines = std::iter::from_fn(|| lines.next_if(|l| !is_queue_line(l)));
+        let filters_config = global.clone().parse_lines(queue_lines)?;
+        let queue = QueueConfig { name, weight, filters_config };
+
+        match queues.iter().position(|q| q.name == queue.name) {
+            // The default queue can be redefined, but only once, even the same way.
+            Some(0) if !default_redefined => {
+                default_redefined = true;
+                queues[0] = queue;
+            },
+            Some(_) => return Err(FilterCfgParseError::DuplicateQueue(queue.name)),
+            None => queues.push(queue),
+        }
+    }
+
+    Ok(LimitsFile {
+        filters_config: global,
+        queues,
+        builtin_filters,
+        resource_limits,
+        optimize_pipelines,
+        restartable_filters,
+        pool_size,
+        sandbox,
+        allowed_uids,
+        path_policy,
+        executables
+    })
+}
+
+/// How the server runs a given filter.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FilterExecutor {
+    /// By executing the binary at this path.
+    External(PathBuf),
+    /// In-process, see [`builtin`](crate::core::builtin).
+    Builtin(Filter),
+    /// In-process, by running the WASI module at this path, see [`WasmRuntime`](super::wasm::WasmRuntime).
+    Wasm(PathBuf),
+}
+
+/// Formats the executor as the path of its executable or module, or as `builtin`.
+impl Display for FilterExecutor {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::External(path) | Self::Wasm(path) => write!(f, "{}", path.display()),
+            Self::Builtin(_) => write!(f, "builtin"),
+        }
     }
 }
 
-/// Full configuration for a server: filters, and path to filter executables.
+/// How long running tasks are given to finish on shutdown, before they are killed, unless
+/// configured otherwise, see [`ServerConfig::shutdown_timeout`].
+pub const DEFAULT_SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// How long requests are remembered by their idempotency key, unless configured otherwise,
+/// see [`ServerConfig::idempotency_window`].
+pub const DEFAULT_IDEMPOTENCY_WINDOW: Duration = Duration::from_secs(600);
+
+/// Most filters a task's pipeline may have, unless configured otherwise, see
+/// [`ServerConfig::max_transformations`].
+pub const DEFAULT_MAX_TRANSFORMATIONS: usize = 64;
+
+/// Most bytes of the input, or output, of a task sent inline, unless configured otherwise,
+/// see [`ServerConfig::inline_max_size`]: a few datagrams' worth, which a client's socket
+/// queues while it's busy.
+pub const DEFAULT_INLINE_MAX_SIZE: usize = 64 * 1024;
+
+/// Bytes asked of the kernel for the receive buffer of the server's socket, unless configured
+/// otherwise, see [`ServerConfig::recv_buffer`]: room for hundreds of requests.
+pub const DEFAULT_RECV_BUFFER: usize = 1 << 20;
+
+/// Number of rotated log files kept, unless configured otherwise, see [`LogConfig::rotation`].
+pub const DEFAULT_LOG_KEEP: usize = 5;
+
+/// Where, and how much, the server logs.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LogConfig {
+    /// File logs are written to, as well as to the terminal.
+    pub file: Option<PathBuf>,
+    pub level: log::LevelFilter,
+    /// Levels of the modules logged at other than `level`, and their submodules, by module
+    /// path, see [`target_level`](crate::util::target_level).
+    pub targets: Vec<(String, log::LevelFilter)>,
+    pub format: LogFormat,
+    /// Whether to log through a `tracing` subscriber, in each task's span, rather than with
+    /// `simplelog`, see [`init_tracing`](crate::util::init_tracing).
+    pub tracing: bool,
+    /// When the log file is rotated, if ever. A file that is rotated is appended to when
+    /// the server starts, rather than replaced.
+    pub rotation: Rotation,
+    /// Where logs are written, other than to `file`.
+    pub sink: LogSink,
+}
+
+impl Default for LogConfig {
+    fn default() -> Self {
+        LogConfig {
+            file: None,
+            level: log::LevelFilter::Trace,
+            targets: Vec::new(),
+            format: LogFormat::Text,
+            tracing: false,
+            rotation: Rotation::default(),
+            sink: LogSink::default(),
+        }
+    }
+}
+
+/// Full configuration for a server: filters, queues, path to filter executables, and the
+/// policy used to schedule pending tasks.
 #[derive(Debug)]
 pub struct ServerConfig {
     pub filters_config: FiltersConfig,
-    transformations_path: PathBuf
+    pub queues: Vec<QueueConfig>,
+    pub builtin_filters: Vec<Filter>,
+    pub resource_limits: ResourceLimits,
+    pub optimize_pipelines: bool,
+    pub restartable_filters: Vec<Filter>,
+    pub pool_size: usize,
+    /// How the processes of external filters are confined, see [`Sandbox`].
+    pub sandbox: Sandbox,
+    /// Users allowed to make requests, by UID. `None` if any user is.
+    pub allowed_uids: Option<Vec<u32>>,
+    /// Directories tasks may read and write files in, see [`PathPolicy::denied`].
+    pub path_policy: PathPolicy,
+    transformations_path: PathBuf,
+    /// Executable of every filter: as configured, see [`LimitsFile::executables`], or else
+    /// the one named after it in the transformations path.
+    executables: HashMap<Filter, PathBuf>,
+    pub scheduling_policy: SchedulingPolicy,
+    /// Encoding of the messages exchanged with clients, see [`WireFormat::from_env`].
+    pub wire_format: WireFormat,
+    /// How clients connect to the server, see [`TransportMode::from_env`].
+    pub transport_mode: TransportMode,
+    /// Namespace the server's sockets, and its clients', are bound in, see
+    /// [`SocketNamespace::from_env`].
+    pub socket_namespace: SocketNamespace,
+    /// Directory the server's sockets are in, and its clients', see [`paths::socket_dir`].
+    pub socket_dir: PathBuf,
+    pub log: LogConfig,
+    /// Audit log of the lifecycle of every task, apart from the logs. `None` if none is kept.
+    pub audit: Option<AuditConfig>,
+    /// Cache of the outputs of tasks, by their input's contents and pipeline, see
+    /// [`ResultCache`](super::cache::ResultCache). `None` if outputs aren't cached.
+    pub cache: Option<CacheConfig>,
+    /// How fast each client may submit requests, see
+    /// [`RateLimiter`](super::rate_limit::RateLimiter). `None` if they aren't rate limited.
+    pub rate_limit: Option<RateLimit>,
+    /// Variables set for external filters, and the directory they run in, as well as the
+    /// variables tasks may set for their own, see [`FilterEnvironment`].
+    pub environment: FilterEnvironment,
+    /// Directory of the store outputs may be written to, see
+    /// [`OutputStore`](super::store::OutputStore). `None` if there is no store.
+    pub store_dir: Option<PathBuf>,
+    /// Most tasks that may be pending at once, across queues, beyond which requests are
+    /// refused. `None` if there's no limit.
+    pub queue_capacity: Option<usize>,
+    /// Most filters a task's pipeline may have, beyond which requests are refused, as each
+    /// stage may take a process.
+    pub max_transformations: usize,
+    /// How many threads monitors run on, and so how many tasks may run at once, see
+    /// [`MonitorPool`](super::monitor_pool::MonitorPool). Defaults to the sum of the
+    /// server-wide filter limits, as no more tasks could run at once anyway.
+    pub monitor_threads: usize,
+    /// Bytes asked of the kernel for the receive buffer of the server's socket, or of each
+    /// connection, in which requests wait while the server is busy, see
+    /// [`transport::set_recv_buffer`](crate::core::transport::set_recv_buffer).
+    pub recv_buffer: usize,
+    /// Bytes of the buffers of the pipes between the stages of tasks' pipelines, see
+    /// [`monitor::set_pipe_buffer`](crate::core::monitor::set_pipe_buffer). `None` if they're
+    /// left as the kernel makes them, 64 KiB, which has the stages of long pipelines over
+    /// large files take turns, and so switch contexts, every few reads.
+    pub pipe_buffer: Option<usize>,
+    /// How long running tasks are given to finish on shutdown, before they are killed.
+    pub shutdown_timeout: Duration,
+    /// How many pending tasks past the head of a queue the server looks at for one it can
+    /// run, when the head can't, given the filters running. `0` if the head must run first.
+    ///
+    /// Tasks that fit run ahead of it, so a large task may wait for longer than it would
+    /// have, but filters don't sit idle behind it.
+    pub scan_depth: usize,
+    /// How long tasks may run for, before they are cancelled. `None` if there's no limit.
+    pub task_timeout: Option<Duration>,
+    /// How long after a request with an idempotency key is submitted another with the same
+    /// key, by the same user, is taken to be a retry of it, and isn't run, see
+    /// [`ClientTask::idempotency_key`]. Zero if they never are.
+    pub idempotency_window: Duration,
+    /// Most bytes of the input, or output, of a task sent inline, in its request and reply,
+    /// see [`ClientTask::inline`], beyond which the task fails.
+    pub inline_max_size: usize,
+    /// How often running tasks report their progress, see [`DEFAULT_PROGRESS_INTERVAL`].
+    pub progress_interval: Duration,
+    /// How long clients are given to acknowledge a notification, before it is sent again,
+    /// see [`DEFAULT_RETRANSMIT_AFTER`].
+    pub retransmit_after: Duration,
+    /// How many times a notification is sent, at most, see [`DEFAULT_MAX_TRANSMISSIONS`].
+    pub max_transmissions: u32,
+    /// Address workers register at, to be handed pending tasks, see
+    /// [`coordinator`](super::coordinator). `None` if the server runs every task itself.
+    pub worker_listen: Option<SocketAddr>,
+    /// Token workers must register with, see [`TOKEN_VAR`](super::coordinator::TOKEN_VAR).
+    /// Always given if workers are listened for, see [`ServerConfig::worker_listen`].
+    pub worker_token: Option<WorkerToken>,
+    /// File the server's PID is written to once it's ready, see
+    /// [`PidFile`](super::daemon::PidFile). `None` if it isn't.
+    pub pid_file: Option<PathBuf>,
+    /// File the number of the server's next task is kept in, see
+    /// [`TaskCounter`](super::numbering::TaskCounter).
+    pub counter_file: PathBuf,
+    /// Account the server runs as once its sockets are bound, see [`Account::assume`]. `None`
+    /// if it keeps running as the one it was started as.
+    pub account: Option<Account>,
+    /// Permission bits given to the outputs of tasks that don't ask for their own, see
+    /// [`ClientTask::mode`]. `None` if they're left to the umask.
+    pub output_mode: Option<u32>,
+    /// Umask the server sets once its sockets are bound, for the files it creates from then
+    /// on, such as outputs. `None` if it keeps the one it was started with.
+    pub umask: Option<u32>
 }
 
 impl ServerConfig {
     pub fn transformations_path(&self) -> PathBuf {
         self.transformations_path.clone()
     }
+
+    /// How `filter` is to be run: in-process if it was configured as builtin,
+    /// otherwise by its executable, as configured, or else in the transformations path.
+    /// An executable with the extension `.wasm` is a WASI module, run in-process too, see
+    /// [`wasm::is_module`].
+    pub fn filter_executor(&self, filter: &Filter) -> FilterExecutor {
+        if self.builtin_filters.contains(filter) {
+            FilterExecutor::Builtin(filter.clone())
+        } else {
+            // Filters the server doesn't know of would be named after their executable.
+            let path = self.executables.get(filter).cloned();
+            let path = path.unwrap_or_else(|| self.transformations_path.join(filter.to_string()));
+            match wasm::is_module(&path) {
+                true => FilterExecutor::Wasm(path),
+                false => FilterExecutor::External(path),
+            }
+        }
+    }
+
+    /// WASI modules of the filters the server may run, i.e. with a nonzero limit, see
+    /// [`FilterExecutor::Wasm`], each once.
+    pub fn wasm_modules(&self) -> Vec<PathBuf> {
+        let mut modules = self.filters_config
+            .filters()
+            .iter()
+            .filter(|filter| self.filters_config.limit(filter) > 0)
+            .filter_map(|filter| match self.filter_executor(filter) {
+                FilterExecutor::Wasm(path) => Some(path),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        modules.sort();
+        modules.dedup();
+        modules
+    }
+
+    /// Whether `task` is checkpointed as it runs, see [`Checkpoint`](crate::core::checkpoint::Checkpoint):
+    /// if every one of its filters is restartable, and it processes a single file, in a
+    /// single chunk, which the server can still read if it restarts, i.e. isn't streamed,
+    /// nor sent inline, nor remote, as its local copy is removed.
+    pub fn is_restartable(&self, task: &ClientTask) -> bool {
+        task.chunks == 1 &&
+        !task.is_spooled() &&
+        !batch::is_batch(task.input_filepath()) &&
+        !remote::is_remote(task.input_filepath()) &&
+        !remote::is_remote(task.output_filepath()) &&
+        task.transformations.iter().all(|filter| self.restartable_filters.contains(filter))
+    }
+
+    /// Filters the server may run, i.e. with a nonzero limit, but whose executable
+    /// does not exist, or is not executable, alongside the path where it was expected.
+    ///
+    /// Builtin filters need no executable, and WASM filters only a module file.
+    pub fn missing_executables(&self) -> Vec<(Filter, PathBuf)> {
+        self.filters_config
+            .filters()
+            .iter()
+            .filter(|filter| self.filters_config.limit(filter) > 0)
+            .filter_map(|filter| match self.filter_executor(filter) {
+                FilterExecutor::External(path) if !is_executable(&path) => Some((filter.clone(), path)),
+                FilterExecutor::Wasm(path) if !path.is_file() => Some((filter.clone(), path)),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// What clients are told the server runs with, see [`ServerInfo`]: the filters it may
+    /// run, i.e. with a nonzero limit, how it schedules requests, and which of its optional
+    /// features it was built with, then which are enabled.
+    pub fn info(&self) -> ServerInfo {
+        let filters = self.filters_config
+            .filters()
+            .iter()
+            .filter(|filter| self.filters_config.limit(filter) > 0)
+            .map(|filter| FilterInfo {
+                name: filter.to_string(),
+                limit: self.filters_config.limit(filter),
+                runs: self.filter_executor(filter).to_string(),
+            })
+            .collect();
+        let features = [
+            (cfg!(feature = "remote"), "remote"),
+            (cfg!(feature = "wasm"), "wasm"),
+            (self.optimize_pipelines, "optimize"),
+            (!self.restartable_filters.is_empty(), "restartable"),
+            (self.pool_size > 0, "pool"),
+            (self.sandbox.is_enabled(), "sandbox"),
+            (self.environment.is_enabled(), "environment"),
+            (self.allowed_uids.is_some(), "allow-uids"),
+            (self.audit.is_some(), "audit"),
+            (self.cache.is_some(), "cache"),
+            (self.store_dir.is_some(), "store"),
+            (self.worker_listen.is_some(), "workers"),
+        ];
+        ServerInfo {
+            server_version: env!("CARGO_PKG_VERSION").to_string(),
+            transformations_path: self.transformations_path.display().to_string(),
+            filters,
+            scheduling_policy: self.scheduling_policy.to_string(),
+            queues: self.queues.iter().map(|queue| (queue.name.clone(), queue.weight)).collect(),
+            queue_capacity: self.queue_capacity,
+            max_transformations: self.max_transformations,
+            monitor_threads: self.monitor_threads,
+            features: features.iter().filter(|(enabled, _)| *enabled).map(|(_, name)| name.to_string()).collect(),
+        }
+    }
+}
+
+/// Whether `path` is a regular file that can be executed by someone.
+pub(super) fn is_executable(path: &Path) -> bool {
+    fs::metadata(path)
+        .map(|meta| meta.is_file() && meta.permissions().mode() & 0o111 != 0)
+        .unwrap_or(false)
 }
 
 #[derive(Debug)]
 pub enum ServerCfgParseError {
     NoTransformationsPathGiven,
-    FilterCfgParseError(FilterCfgParseError)
+    /// No limits file was given, nor a config file.
+    NoLimitsGiven,
+    FilterCfgParseError(FilterCfgParseError),
+    InvalidSchedulingPolicy(SchedulingPolicyParseError),
+    InvalidWireFormat(WireFormatParseError),
+    InvalidTransportMode(TransportModeParseError),
+    InvalidSocketNamespace(SocketNamespaceParseError),
+    ConfigFileError(ConfigFileError),
+    /// The log level isn't one of `off`, `error`, `warn`, `info`, `debug` or `trace`.
+    InvalidLogLevel(String),
+    /// A log target isn't of the form `<module-path>=<level>`, see [`LogConfig::targets`].
+    InvalidLogTarget(String),
+    InvalidLogFormat(LogFormatParseError),
+    InvalidLogSink(LogSinkParseError),
+    /// Logging through `tracing` was asked for along with this sink, whereas its subscriber
+    /// only writes to the terminal, and the log file, see [`LogConfig::tracing`].
+    TracingToSink(LogSink),
+    /// The socket directory could not be created, see [`paths::prepare_socket_dir`].
+    NoSocketDir(io::Error),
+    /// The account to run as could not be looked up, see [`Account::lookup`].
+    AccountError(AccountError),
+    /// The output mode, or umask, of the config file has bits beyond [`MODE_BITS`].
+    InvalidMode(u32),
+    /// Some filters the server may run have no executable, see [`ServerConfig::missing_executables`].
+    MissingExecutables(Vec<(Filter, PathBuf)>),
+    /// A variable of the config file's `[environment]` table, set or allowed, has this name,
+    /// which can't be a variable's, see [`environment::is_var_name`].
+    InvalidEnvVar(String),
+    /// The directory filters are to run in, see [`FilterEnvironment::cwd`], isn't one.
+    MissingWorkingDir(PathBuf),
+    /// Filters were given a directory to run in, whereas those sandboxed in namespaces run
+    /// in their jail's root, see [`Sandbox::namespaces`].
+    SandboxedWorkingDir,
+    /// Workers are listened for at this address, whereas they aren't given a token to
+    /// register with, see [`TOKEN_VAR`](super::coordinator::TOKEN_VAR), so that anyone who
+    /// could connect, even any local user over the loopback interface, could register as one.
+    UnauthenticatedWorkers(SocketAddr),
 }
 
 impl ServerConfig {
-    pub fn build(args: &mut impl Iterator<Item = String>) -> Result<Self, ServerCfgParseError> {
-        // Move past executable name in args list
-        args.next();
+    /// Build the server's config from its command line, see [`ServerCli`], and the config
+    /// file it names, if any, see [`ConfigFile`], whose settings its options override. Both
+    /// override those given by environment variables, see [`ServerEnv`]: the limits file
+    /// given by the environment is only read if the config file has no limits.
+    ///
+    /// The scheduling policy defaults to [`SchedulingPolicy::Priority`]. The wire format,
+    /// transport mode and socket namespace are read from the environment, see
+    /// [`WireFormat::from_env`], [`TransportMode::from_env`] and [`SocketNamespace::from_env`],
+    /// as is the socket directory, unless given, see [`paths::socket_dir`], which is created if
+    /// it doesn't exist.
+    ///
+    /// Building fails if an executable is missing for any filter the server may run,
+    /// rather than having every task using it fail at runtime.
+    pub fn build(cli: &ServerCli, env: &ServerEnv) -> Result<Self, ServerCfgParseError> {
+        let config_file = match &cli.config {
+            None => ConfigFile::default(),
+            Some(path) => ConfigFile::read(path).map_err(ServerCfgParseError::ConfigFileError)?,
+        };
+
+        let socket_dir = cli.socket_dir.clone().or(config_file.socket_dir.clone()).or(env.socket_dir.clone());
+        let socket_dir = paths::socket_dir(socket_dir.as_deref());
+        paths::prepare_socket_dir(&socket_dir).map_err(ServerCfgParseError::NoSocketDir)?;
+        let counter_file = cli
+            .counter_file
+            .clone()
+            .or(config_file.counter_file.clone())
+            .unwrap_or_else(|| socket_dir.join(numbering::COUNTER_FILE));
 
-        let filters_config = match FiltersConfig::build(args) {
+        let limits_file = match (&cli.limits_file, &env.limits_file) {
+            (Some(path), _) => FiltersConfig::read(path),
+            (None, Some(path)) if !config_file.has_limits() => FiltersConfig::read(path),
+            _ if cli.config.is_some() => config_file
+                .limits()
+                .map_err(ServerCfgParseError::ConfigFileError)
+                .map(|limits| parse_limits(&limits))?,
+            _ => return Err(ServerCfgParseError::NoLimitsGiven),
+        };
+        let LimitsFile {
+            filters_config,
+            queues,
+            builtin_filters,
+            resource_limits,
+            optimize_pipelines,
+            restartable_filters,
+            pool_size,
+            sandbox,
+            allowed_uids,
+            path_policy,
+            mut executables
+        } = match limits_file {
             Err(err) => return Err(ServerCfgParseError::FilterCfgParseError(err)),
             Ok(f) => f,
         };
 
-        let transformations_path = match args.next() {
+        let transformations_path = match cli
+            .transformations_dir
+            .clone()
+            .or(config_file.transformations)
+            .or(env.transformations_dir.clone())
+        {
             None => return Err(ServerCfgParseError::NoTransformationsPathGiven),
-            Some(s) => PathBuf::from(s),
+            Some(path) => path,
+        };
+        let executables = filters_config
+            .filters()
+            .into_iter()
+            .map(|filter| {
+                let path = executables.remove(&filter).unwrap_or_else(|| transformations_path.join(filter.to_string()));
+                (filter, path)
+            })
+            .collect();
+
+        let scheduling_policy = match cli.scheduling_policy.clone().or(config_file.scheduling_policy) {
+            None => SchedulingPolicy::default(),
+            Some(s) => s.parse().map_err(ServerCfgParseError::InvalidSchedulingPolicy)?,
+        };
+
+        let wire_format = WireFormat::from_env().map_err(ServerCfgParseError::InvalidWireFormat)?;
+        let transport_mode = TransportMode::from_env().map_err(ServerCfgParseError::InvalidTransportMode)?;
+        let socket_namespace = SocketNamespace::from_env().map_err(ServerCfgParseError::InvalidSocketNamespace)?;
+
+        let level = match cli.log_level.clone().or(config_file.log.level).or(env.log_level.clone()) {
+            None => LogConfig::default().level,
+            Some(level) => level.parse().map_err(|_| ServerCfgParseError::InvalidLogLevel(level))?,
+        };
+        // The command line's targets come last, to override the config file's.
+        let targets = config_file
+            .log
+            .targets
+            .into_iter()
+            .chain(cli.log_targets.iter().map(|target| match target.split_once('=') {
+                Some((target, level)) => (target.to_string(), level.to_string()),
+                None => (target.clone(), String::new()),
+            }))
+            .map(|(target, level)| match (target.is_empty(), level.parse()) {
+                (false, Ok(level)) => Ok((target, level)),
+                _ => Err(ServerCfgParseError::InvalidLogTarget(format!("{target}={level}"))),
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let format = match cli.log_format.clone().or(config_file.log.format).or(env.log_format.clone()) {
+            None => LogFormat::default(),
+            Some(format) => format.parse().map_err(ServerCfgParseError::InvalidLogFormat)?,
+        };
+        let tracing = cli.log_tracing || config_file.log.tracing;
+        let sink = match cli.log_sink.clone().or(config_file.log.sink) {
+            None => LogSink::default(),
+            Some(sink) => sink.parse().map_err(ServerCfgParseError::InvalidLogSink)?,
+        };
+        if tracing && sink != LogSink::Terminal {
+            return Err(ServerCfgParseError::TracingToSink(sink))
+        }
+        let rotation = Rotation {
+            max_size: config_file.log.max_size,
+            max_age: config_file.log.max_age.map(|secs| Duration::from_secs(secs.get())),
+            keep: config_file.log.keep.unwrap_or(DEFAULT_LOG_KEEP),
+        };
+        let log = LogConfig {
+            file: cli.log_file.clone().or(config_file.log.file),
+            level,
+            targets,
+            format,
+            tracing,
+            rotation,
+            sink,
+        };
+        let audit = cli.audit_file.clone().or(config_file.audit.file).map(|file| AuditConfig {
+            file,
+            max_size: config_file.audit.max_size.unwrap_or(DEFAULT_AUDIT_MAX_SIZE),
+            keep: config_file.audit.keep.unwrap_or(DEFAULT_AUDIT_KEEP),
+        });
+        let cache = cli.cache_dir.clone().or(config_file.cache.dir).map(|dir| CacheConfig {
+            dir,
+            max_size: config_file.cache.max_size.unwrap_or(DEFAULT_CACHE_MAX_SIZE),
+        });
+        let rate_limit = config_file.rate_limit.burst.map(|burst| RateLimit {
+            burst: burst.get(),
+            refill: config_file.rate_limit.refill_ms.map_or(DEFAULT_REFILL, |ms| Duration::from_millis(ms.get())),
+        });
+        let environment = FilterEnvironment {
+            vars: config_file.environment.vars,
+            cwd: config_file.environment.cwd,
+            allowed: config_file.environment.allowed.into_iter().collect(),
+        };
+        let names = environment.vars.keys().chain(&environment.allowed);
+        if let Some(name) = names.into_iter().find(|name| !environment::is_var_name(name)) {
+            return Err(ServerCfgParseError::InvalidEnvVar(name.clone()))
+        }
+        match &environment.cwd {
+            Some(_) if sandbox.namespaces => return Err(ServerCfgParseError::SandboxedWorkingDir),
+            Some(cwd) if !cwd.is_dir() => return Err(ServerCfgParseError::MissingWorkingDir(cwd.clone())),
+            _ => {},
+        }
+        let shutdown_timeout = config_file.shutdown_timeout.map_or(DEFAULT_SHUTDOWN_TIMEOUT, Duration::from_secs);
+        let millis = |ms: NonZeroU64| Duration::from_millis(ms.get());
+        let progress_interval = config_file.progress_interval_ms.map_or(DEFAULT_PROGRESS_INTERVAL, millis);
+        let retransmit_after = config_file.retransmit_after_ms.map_or(DEFAULT_RETRANSMIT_AFTER, millis);
+
+        let monitor_threads = config_file.monitor_threads.map_or_else(
+            || filters_config.filters().iter().map(|filter| filters_config.limit(filter)).sum::<usize>().max(1),
+            NonZeroUsize::get
+        );
+
+        let account = match (cli.user.clone().or(config_file.user), cli.group.clone().or(config_file.group)) {
+            (Some(user), group) => Some(Account::lookup(&user, group.as_deref()).map_err(ServerCfgParseError::AccountError)?),
+            (None, Some(group)) => return Err(ServerCfgParseError::AccountError(AccountError::GroupWithoutUser(group))),
+            (None, None) => None,
+        };
+
+        let [output_mode, umask] = [(cli.output_mode, config_file.output_mode), (cli.umask, config_file.umask)]
+            .map(|(cli, config_file)| cli.or(config_file));
+        if let Some(mode) = [output_mode, umask].into_iter().flatten().find(|mode| mode & !MODE_BITS != 0) {
+            return Err(ServerCfgParseError::InvalidMode(mode))
+        }
+
+        let worker_listen = cli.worker_listen.or(config_file.worker_listen);
+        if let Some(addr) = worker_listen.filter(|_| env.worker_token.is_none()) {
+            return Err(ServerCfgParseError::UnauthenticatedWorkers(addr))
+        }
+
+        let config = ServerConfig {
+            filters_config,
+            queues,
+            builtin_filters,
+            resource_limits,
+            optimize_pipelines,
+            restartable_filters,
+            pool_size,
+            sandbox,
+            allowed_uids,
+            path_policy,
+            transformations_path,
+            executables,
+            scheduling_policy,
+            wire_format,
+            transport_mode,
+            socket_namespace,
+            socket_dir,
+            log,
+            audit,
+            cache,
+            rate_limit,
+            environment,
+            store_dir: cli.store_dir.clone().or(config_file.store_dir),
+            queue_capacity: config_file.queue_capacity,
+            max_transformations: config_file.max_transformations.unwrap_or(DEFAULT_MAX_TRANSFORMATIONS),
+            monitor_threads,
+            recv_buffer: config_file.recv_buffer.map_or(DEFAULT_RECV_BUFFER, NonZeroUsize::get),
+            pipe_buffer: config_file.pipe_buffer.map(NonZeroUsize::get),
+            shutdown_timeout,
+            scan_depth: config_file.scan_depth.unwrap_or(0),
+            task_timeout: config_file.task_timeout.map(|secs| Duration::from_secs(secs.get())),
+            idempotency_window: config_file.idempotency_window.map_or(DEFAULT_IDEMPOTENCY_WINDOW, Duration::from_secs),
+            inline_max_size: config_file.inline_max_size.unwrap_or(DEFAULT_INLINE_MAX_SIZE),
+            progress_interval,
+            retransmit_after,
+            max_transmissions: config_file.max_transmissions.unwrap_or(DEFAULT_MAX_TRANSMISSIONS),
+            worker_listen,
+            worker_token: env.worker_token.clone(),
+            pid_file: cli.pid_file.clone().or(config_file.pid_file),
+            counter_file,
+            account,
+            output_mode,
+            umask
         };
 
-        Ok(ServerConfig { filters_config, transformations_path })
+        let missing = config.missing_executables();
+        if !missing.is_empty() {
+            return Err(ServerCfgParseError::MissingExecutables(missing))
+        }
+
+        Ok(config)
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use crate::core::{server::resources::IoClass, testing::TempDir};
+
     use super::*;
 
     #[test]
@@ -141,7 +1134,12 @@ mod tests {
             gcompress: 2,
             gdecompress: 2,
             encrypt: 2,
-            decrypt: 2
+            decrypt: 2,
+            zcompress: 1,
+            zdecompress: 1,
+            xcompress: 0,
+            xdecompress: 0,
+            custom: BTreeMap::new()
         };
 
         let config_txt = "nop 3
@@ -150,7 +1148,9 @@ mod tests {
         gcompress 2
         gdecompress 2
         encrypt 2
-        decrypt 2";
+        decrypt 2
+        zcompress 1
+        zdecompress 1";
 
         let read_config = FiltersConfig::parse(config_txt).expect("parsing should succeed");
         assert_eq!(expected_config, read_config);
@@ -174,4 +1174,354 @@ mod tests {
 
         assert!(matches!(FiltersConfig::parse(config_txt).unwrap_err(), FilterCfgParseError::LineParseError))
     }
+
+    #[test]
+    fn config_parsing_fails3() {
+        let config_txt = "nop 3\nbcompres 4";
+
+        assert!(matches!(
+            FiltersConfig::parse(config_txt).unwrap_err(),
+            FilterCfgParseError::UnknownFilter(filter) if filter == "bcompres"
+        ));
+        assert!(matches!(parse_limits("nop 3\nqueue batch 1\ngzip 1").unwrap_err(), FilterCfgParseError::UnknownFilter(_)));
+    }
+
+    #[test]
+    fn default_limit_parsing_works() {
+        let read_config = FiltersConfig::parse("nop 3\ndefault 2\nxcompress 0").expect("parsing should succeed");
+        assert_eq!(read_config, FiltersConfig { nop: 3, xcompress: 0, ..FiltersConfig::default().uniform(2) });
+
+        // Queues' defaults apply to the filters they don't list, over the server-wide limits.
+        let limits = parse_limits("default 2\nqueue batch 1\ndefault 1\nnop 2").expect("parsing should succeed");
+        assert_eq!(limits.queues[1].filters_config, FiltersConfig { nop: 2, ..FiltersConfig::default().uniform(1) });
+
+        for config_txt in ["default", "default two", "default 1 2"] {
+            assert!(matches!(
+                FiltersConfig::parse(config_txt).unwrap_err(),
+                FilterCfgParseError::FilterLimitParseError(_)
+            ));
+        }
+    }
+
+    #[test]
+    fn queue_parsing_works() {
+        let config_txt = "nop 3
+        bcompress 4
+        queue interactive 3
+        queue batch 1
+        bcompress 1";
+
+        let LimitsFile { filters_config: global, queues, .. } =
+            parse_limits(config_txt).expect("parsing should succeed");
+        let names = queues.iter().map(|q| q.name.as_str()).collect::<Vec<_>>();
+        assert_eq!(names, vec![DEFAULT_QUEUE, "interactive", "batch"]);
+
+        assert_eq!(queues[1].weight, 3);
+        assert_eq!(queues[1].filters_config, global);
+        assert_eq!(queues[2].filters_config, FiltersConfig { bcompress: 1, ..global });
+    }
+
+    #[test]
+    fn queue_parsing_fails() {
+        assert!(matches!(
+            parse_limits("nop 3\nqueue batch 0").unwrap_err(),
+            FilterCfgParseError::QueueLineParseError(_)
+        ));
+        assert!(matches!(
+            parse_limits("queue batch 1\nqueue batch 2").unwrap_err(),
+            FilterCfgParseError::DuplicateQueue(_)
+        ));
+        for repeated in ["queue default 1\nqueue default 1", "queue default 2\nnop 1\nqueue default 3"] {
+            assert!(matches!(
+                parse_limits(&format!("nop 3\n{repeated}")).unwrap_err(),
+                FilterCfgParseError::DuplicateQueue(name) if name == DEFAULT_QUEUE
+            ));
+        }
+        assert_eq!(parse_limits("nop 3\nqueue default 2").unwrap().queues[0].weight, 2);
+    }
+
+    #[test]
+    fn builtin_parsing_works() {
+        let config_txt = "nop 3
+        builtin gcompress gdecompress
+        gcompress 2";
+
+        let limits = parse_limits(config_txt).expect("parsing should succeed");
+        assert_eq!(limits.builtin_filters, vec![Filter::Gcompress, Filter::Gdecompress]);
+        assert_eq!(limits.filters_config.gcompress, 2);
+
+        assert!(matches!(
+            parse_limits("builtin lcompress").unwrap_err(),
+            FilterCfgParseError::BuiltinLineParseError(_)
+        ));
+        assert!(matches!(
+            parse_limits("builtin zcompress").unwrap_err(),
+            FilterCfgParseError::NoBuiltin(Filter::Zcompress)
+        ));
+    }
+
+    #[test]
+    fn restartable_parsing_works() {
+        let limits = parse_limits("nop 3\nrestartable nop gcompress").expect("parsing should succeed");
+        assert_eq!(limits.restartable_filters, vec![Filter::Nop, Filter::Gcompress]);
+        assert_eq!(limits.filters_config, FiltersConfig { nop: 3, ..Default::default() });
+
+        assert!(matches!(
+            parse_limits("restartable encrypt").unwrap_err(),
+            FilterCfgParseError::NotRestartable(Filter::Encrypt)
+        ));
+    }
+
+    #[test]
+    fn pool_parsing_works() {
+        let limits = parse_limits("nop 3\npool 2").expect("parsing should succeed");
+        assert_eq!(limits.pool_size, 2);
+        assert_eq!(limits.filters_config, FiltersConfig { nop: 3, ..Default::default() });
+        assert_eq!(parse_limits("nop 3").unwrap().pool_size, 0);
+
+        assert!(matches!(
+            parse_limits("pool many").unwrap_err(),
+            FilterCfgParseError::PoolLineParseError(_)
+        ));
+    }
+
+    #[test]
+    fn sandbox_parsing_works() {
+        let limits = parse_limits("nop 3\nsandbox seccomp").expect("parsing should succeed");
+        assert_eq!(limits.sandbox, Sandbox { namespaces: false, seccomp: true });
+        assert_eq!(limits.filters_config, FiltersConfig { nop: 3, ..Default::default() });
+
+        assert!(matches!(parse_limits("sandbox jail").unwrap_err(), FilterCfgParseError::SandboxLineParseError(_)));
+        // Pooled processes are started before the tasks they'd be confined to are known.
+        assert!(matches!(parse_limits("sandbox\npool 2").unwrap_err(), FilterCfgParseError::SandboxedPool));
+    }
+
+    #[test]
+    fn allow_uids_parsing_works() {
+        let limits = parse_limits("nop 3\nallow-uids 1000 1001\nallow-uids 0").expect("parsing should succeed");
+        assert_eq!(limits.allowed_uids, Some(vec![1000, 1001, 0]));
+        assert_eq!(limits.filters_config, FiltersConfig { nop: 3, ..Default::default() });
+        assert_eq!(parse_limits("nop 3").unwrap().allowed_uids, None);
+
+        for line in ["allow-uids", "allow-uids root"] {
+            assert!(matches!(
+                parse_limits(line).unwrap_err(),
+                FilterCfgParseError::AllowUidsLineParseError(_)
+            ));
+        }
+    }
+
+    #[test]
+    fn allow_paths_parsing_works() {
+        let limits = parse_limits("nop 3\nallow-inputs /srv/in /home\nallow-outputs /srv/out\nallow-inputs /tmp")
+            .expect("parsing should succeed");
+        let dirs = |dirs: &[&str]| Some(dirs.iter().map(PathBuf::from).collect::<Vec<_>>());
+        assert_eq!(limits.path_policy.inputs, dirs(&["/srv/in", "/home", "/tmp"]));
+        assert_eq!(limits.path_policy.outputs, dirs(&["/srv/out"]));
+        assert_eq!(limits.filters_config, FiltersConfig { nop: 3, ..Default::default() });
+        assert_eq!(parse_limits("nop 3").unwrap().path_policy, PathPolicy::default());
+
+        assert!(matches!(
+            parse_limits("allow-outputs").unwrap_err(),
+            FilterCfgParseError::AllowPathsLineParseError(_)
+        ));
+    }
+
+    #[test]
+    fn executable_parsing_works() {
+        let limits = parse_limits("nop 3\nexecutable nop /usr/local/bin/sdstore nop\nexecutable gcompress  bin/gz")
+            .expect("parsing should succeed");
+        assert_eq!(limits.executables.get(&Filter::Nop), Some(&PathBuf::from("/usr/local/bin/sdstore nop")));
+        assert_eq!(limits.executables.get(&Filter::Gcompress), Some(&PathBuf::from("bin/gz")));
+        assert_eq!(limits.filters_config, FiltersConfig { nop: 3, ..Default::default() });
+
+        for line in ["executable", "executable nop", "executable lz4 /usr/bin/lz4"] {
+            assert!(matches!(
+                parse_limits(line).unwrap_err(),
+                FilterCfgParseError::ExecutableLineParseError(_)
+            ));
+        }
+    }
+
+    #[test]
+    fn filter_registration_works() {
+        let limits = parse_limits("nop 1\nfilter lz4 2 /opt/lz4 filter\nfilter brotli 1\ndefault 3\nqueue batch 1\nlz4 1")
+            .expect("parsing should succeed");
+        let lz4 = Filter::Custom(String::from("lz4"));
+        assert_eq!(limits.filters_config.limit(&lz4), 2);
+        assert_eq!(limits.filters_config.limit(&Filter::Custom(String::from("brotli"))), 1);
+        assert_eq!((limits.filters_config.nop, limits.filters_config.gcompress), (1, 3));
+        assert_eq!(limits.queues[1].filters_config.limit(&lz4), 1);
+        assert_eq!(limits.executables.get(&lz4), Some(&PathBuf::from("/opt/lz4 filter")));
+        assert_eq!(limits.filters_config.filters().len(), Filter::ALL.len() + 2);
+
+        // Filters must be registered before being used, and only once.
+        for config_txt in ["filter nop 1", "filter lz4", "filter lz4 2\nfilter lz4 1", "filter lz4 many"] {
+            assert!(matches!(parse_limits(config_txt).unwrap_err(), FilterCfgParseError::FilterLineParseError(_)));
+        }
+        assert!(matches!(parse_limits("lz4 1").unwrap_err(), FilterCfgParseError::UnknownFilter(_)));
+        assert!(matches!(
+            parse_limits("executable lz4 /opt/lz4").unwrap_err(),
+            FilterCfgParseError::ExecutableLineParseError(_)
+        ));
+    }
+
+    #[test]
+    fn resource_limits_parsing_works() {
+        let config_txt = "nop 3
+        nice 10
+        ionice idle
+        queue batch 1";
+
+        let limits = parse_limits(config_txt).expect("parsing should succeed");
+        assert_eq!(limits.filters_config, FiltersConfig { nop: 3, ..Default::default() });
+        assert_eq!(limits.resource_limits.nice, Some(10));
+        assert_eq!(limits.resource_limits.io_class, Some(IoClass::Idle));
+
+        assert!(matches!(
+            parse_limits("cpu-time forever").unwrap_err(),
+            FilterCfgParseError::ResourceLineParseError(_)
+        ));
+    }
+
+    #[test]
+    fn config_files_are_overridden_by_args() {
+        let dir = TempDir::new("config");
+        let (config_path, limits_path) = (dir.join("sdstored.toml"), dir.join("limits.txt"));
+        fs::write(&config_path, format!(r#"
+            transformations = "filters"
+            scheduling-policy = "fifo"
+            socket-dir = "{}"
+            queue-capacity = 10
+            max-transformations = 8
+            shutdown-timeout = 5
+            task-timeout = 60
+            progress-interval-ms = 200
+            output-mode = 0o640
+
+            [log]
+            level = "warn"
+            max-size = 1024
+            sink = "journald"
+            targets = {{ sdstored = "debug" }}
+
+            [limits]
+            gcompress = 2
+            builtin = ["gcompress"]
+
+            [executables]
+            nop = "/opt/sdstore/nop"
+        "#, dir.join("sockets").display())).unwrap();
+        fs::write(&limits_path, "gdecompress 1\nbuiltin gdecompress").unwrap();
+        let cli = ServerCli { config: Some(config_path), ..Default::default() };
+
+        let config = ServerConfig::build(&cli, &ServerEnv::default()).expect("building should succeed");
+        assert_eq!(config.filters_config, FiltersConfig { gcompress: 2, ..Default::default() });
+        assert_eq!((config.transformations_path(), config.scheduling_policy), (PathBuf::from("filters"), SchedulingPolicy::Fifo));
+        assert_eq!((config.queue_capacity, config.shutdown_timeout), (Some(10), Duration::from_secs(5)));
+        assert_eq!((config.max_transformations, config.monitor_threads), (8, 2));
+        assert_eq!((config.recv_buffer, config.pipe_buffer), (DEFAULT_RECV_BUFFER, None));
+        assert_eq!((config.scan_depth, config.task_timeout), (0, Some(Duration::from_secs(60))));
+        assert_eq!((config.idempotency_window, config.inline_max_size), (DEFAULT_IDEMPOTENCY_WINDOW, DEFAULT_INLINE_MAX_SIZE));
+        assert_eq!((config.progress_interval, config.retransmit_after), (Duration::from_millis(200), DEFAULT_RETRANSMIT_AFTER));
+        assert_eq!((config.output_mode, config.umask), (Some(0o640), None));
+        assert_eq!(config.log, LogConfig {
+            file: None,
+            level: log::LevelFilter::Warn,
+            targets: vec![(String::from("sdstored"), log::LevelFilter::Debug)],
+            format: LogFormat::Text,
+            tracing: false,
+            rotation: Rotation { max_size: Some(1024), max_age: None, keep: DEFAULT_LOG_KEEP },
+            sink: LogSink::Journald,
+        });
+        assert!(dir.join("sockets").is_dir());
+        assert_eq!(config.filter_executor(&Filter::Nop), FilterExecutor::External(PathBuf::from("/opt/sdstore/nop")));
+        assert_eq!(config.filter_executor(&Filter::Encrypt), FilterExecutor::External(PathBuf::from("filters/encrypt")));
+
+        let socket_dir = dir.join("other");
+        let cli = ServerCli {
+            limits_file: Some(limits_path),
+            transformations_dir: Some(PathBuf::from("bin")),
+            socket_dir: Some(socket_dir.clone()),
+            log_level: Some(String::from("info")),
+            log_targets: vec![String::from("sdstored=off")],
+            output_mode: Some(0o600),
+            ..cli
+        };
+        let config = ServerConfig::build(&cli, &ServerEnv::default()).expect("building should succeed");
+        assert_eq!(config.filters_config, FiltersConfig { gdecompress: 1, ..Default::default() });
+        // The limits file replaces the config file's executables too.
+        assert_eq!(config.filter_executor(&Filter::Nop), FilterExecutor::External(PathBuf::from("bin/nop")));
+        assert_eq!((config.transformations_path(), config.socket_dir), (PathBuf::from("bin"), socket_dir));
+        assert_eq!((config.scheduling_policy, config.log.level), (SchedulingPolicy::Fifo, log::LevelFilter::Info));
+        assert_eq!(config.log.targets[1], (String::from("sdstored"), log::LevelFilter::Off));
+        assert_eq!(config.output_mode, Some(0o600));
+
+        let cli = ServerCli { log_targets: vec![String::from("sdstored")], ..cli };
+        assert!(matches!(
+            ServerConfig::build(&cli, &ServerEnv::default()).unwrap_err(),
+            ServerCfgParseError::InvalidLogTarget(_)
+        ));
+        let cli = ServerCli { log_level: Some(String::from("loud")), log_targets: Vec::new(), ..cli };
+        assert!(matches!(
+            ServerConfig::build(&cli, &ServerEnv::default()).unwrap_err(),
+            ServerCfgParseError::InvalidLogLevel(_)
+        ));
+        // The config file logs to journald, where `tracing` can't.
+        let cli = ServerCli { log_level: None, log_tracing: true, ..cli };
+        assert!(matches!(
+            ServerConfig::build(&cli, &ServerEnv::default()).unwrap_err(),
+            ServerCfgParseError::TracingToSink(LogSink::Journald)
+        ));
+    }
+
+    #[test]
+    fn env_settings_are_overridden() {
+        let dir = TempDir::new("env");
+        let (config_path, limits_path) = (dir.join("sdstored.toml"), dir.join("limits.txt"));
+        fs::write(&config_path, "transformations = \"filters\"\n[log]\nlevel = \"warn\"").unwrap();
+        fs::write(&limits_path, "gcompress 2\nbuiltin gcompress").unwrap();
+        let env = ServerEnv {
+            limits_file: Some(limits_path),
+            transformations_dir: Some(PathBuf::from("bin")),
+            socket_dir: Some(dir.join("sockets")),
+            log_level: Some(String::from("debug")),
+            log_format: Some(String::from("json")),
+            worker_token: Some(WorkerToken(String::from("secret"))),
+        };
+
+        // Without a command line, as in a container.
+        let config = ServerConfig::build(&ServerCli::default(), &env).expect("building should succeed");
+        assert_eq!(config.filters_config, FiltersConfig { gcompress: 2, ..Default::default() });
+        assert_eq!((config.transformations_path(), config.log.level), (PathBuf::from("bin"), log::LevelFilter::Debug));
+        assert_eq!((config.socket_dir, config.log.format), (dir.join("sockets"), LogFormat::Json));
+        assert_eq!(config.worker_token, Some(WorkerToken(String::from("secret"))));
+
+        // The config file only overrides the limits file if it has limits of its own.
+        let cli = ServerCli { config: Some(config_path.clone()), ..Default::default() };
+        let config = ServerConfig::build(&cli, &env).expect("building should succeed");
+        assert_eq!(config.filters_config, FiltersConfig { gcompress: 2, ..Default::default() });
+        assert_eq!((config.transformations_path(), config.log.level), (PathBuf::from("filters"), log::LevelFilter::Warn));
+        fs::write(&config_path, "transformations = \"filters\"\n[limits]\ngdecompress = 1\nbuiltin = \"gdecompress\"").unwrap();
+        let config = ServerConfig::build(&cli, &env).expect("building should succeed");
+        assert_eq!(config.filters_config, FiltersConfig { gdecompress: 1, ..Default::default() });
+
+        let cli = ServerCli { transformations_dir: Some(PathBuf::from("bin")), ..Default::default() };
+        assert!(matches!(
+            ServerConfig::build(&cli, &ServerEnv::default()).unwrap_err(),
+            ServerCfgParseError::NoLimitsGiven
+        ));
+
+        // Workers never go without a token, even over the loopback interface.
+        let exposed = ServerCli { worker_listen: "0.0.0.0:7070".parse().ok(), ..Default::default() };
+        assert!(ServerConfig::build(&exposed, &env).is_ok());
+        let env = ServerEnv { worker_token: None, ..env };
+        for addr in ["0.0.0.0:7070", "127.0.0.1:7070"].map(|addr| addr.parse().unwrap()) {
+            let cli = ServerCli { worker_listen: Some(addr), ..Default::default() };
+            assert!(matches!(
+                ServerConfig::build(&cli, &env).unwrap_err(),
+                ServerCfgParseError::UnauthenticatedWorkers(unauthenticated) if unauthenticated == addr
+            ));
+        }
+    }
 }
@@ -0,0 +1,446 @@
+//! The server's TOML config file, given with `--config`, see [`ConfigFile`].
+
+use std::{collections::BTreeMap, fs, io, net::SocketAddr, num::{NonZeroU32, NonZeroU64, NonZeroUsize}, path::{Path, PathBuf}};
+
+use serde::Deserialize;
+
+/// Settings of the server read from a TOML file, any of which the command line overrides,
+/// see [`ServerConfig::build`](super::config::ServerConfig::build):
+///
+/// ```toml
+/// transformations = "bin/sdstore-transformations"
+/// scheduling-policy = "priority"
+/// socket-dir = "/run/sdstore"
+/// queue-capacity = 100
+/// max-transformations = 64
+/// monitor-threads = 16
+/// recv-buffer = 1048576
+/// pipe-buffer = 1048576
+/// shutdown-timeout = 30
+/// scan-depth = 4
+/// task-timeout = 3600
+/// idempotency-window = 600
+/// store-dir = "/srv/sdstore"
+/// inline-max-size = 65536
+/// progress-interval-ms = 1000
+/// retransmit-after-ms = 500
+/// max-transmissions = 5
+/// worker-listen = "0.0.0.0:7070"
+/// pid-file = "/run/sdstore/sdstored.pid"
+/// counter-file = "/var/lib/sdstore/counter"
+/// user = "sdstore"
+/// output-mode = 0o640
+/// umask = 0o027
+///
+/// [log]
+/// file = "sdstored.log"
+/// level = "info"
+/// format = "json"
+/// tracing = true
+/// sink = "journald"
+/// max-size = 10485760
+/// max-age = 86400
+/// keep = 5
+/// targets = { "rust_sdstore::core::server::state" = "warn" }
+///
+/// [audit]
+/// file = "/var/log/sdstored/audit.log"
+/// max-size = 10485760
+/// keep = 5
+///
+/// [cache]
+/// dir = "/var/cache/sdstored"
+/// max-size = 1073741824
+///
+/// [rate-limit]
+/// burst = 20
+/// refill-ms = 500
+///
+/// [environment]
+/// vars = { LANG = "C.UTF-8", TMPDIR = "/var/tmp/sdstore" }
+/// cwd = "/var/tmp/sdstore"
+/// allowed = ["LANG", "TZ"]
+///
+/// [limits]
+/// nop = 3
+/// gcompress = 2
+/// builtin = ["gcompress"]
+///
+/// [executables]
+/// gcompress = "/usr/local/bin/sdstore-gcompress"
+///
+/// [filters.lz4]
+/// limit = 2
+/// executable = "/usr/local/bin/sdstore-lz4"
+///
+/// [queues.batch]
+/// weight = 1
+/// nop = 1
+/// ```
+///
+/// Relative paths are relative to the server's working directory, as on the command line.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct ConfigFile {
+    /// Directory of the filters' executables.
+    pub transformations: Option<PathBuf>,
+    /// Name of the policy pending tasks are scheduled by.
+    pub scheduling_policy: Option<String>,
+    /// Directory of the server's sockets, see [`paths::socket_dir`](crate::core::paths::socket_dir).
+    pub socket_dir: Option<PathBuf>,
+    /// Most tasks that may be pending at once, across queues.
+    pub queue_capacity: Option<usize>,
+    /// Most filters a task's pipeline may have.
+    pub max_transformations: Option<usize>,
+    /// Threads monitors run on, and so most tasks that may run at once.
+    pub monitor_threads: Option<NonZeroUsize>,
+    /// Bytes asked of the kernel for the receive buffer of the server's socket.
+    pub recv_buffer: Option<NonZeroUsize>,
+    /// Bytes of the buffers of the pipes between the stages of tasks' pipelines.
+    pub pipe_buffer: Option<NonZeroUsize>,
+    /// Seconds running tasks are given to finish on shutdown, before they are killed.
+    pub shutdown_timeout: Option<u64>,
+    /// Pending tasks past the head of each queue that may run ahead of it, if it can't.
+    pub scan_depth: Option<usize>,
+    /// Seconds tasks may run for, before they are cancelled.
+    pub task_timeout: Option<NonZeroU64>,
+    /// Seconds requests are remembered by their idempotency key, to not run them twice.
+    pub idempotency_window: Option<u64>,
+    /// Directory of the store tasks may write their outputs to, named by their contents.
+    pub store_dir: Option<PathBuf>,
+    /// Most bytes of the input, or output, of a task sent inline, in its request and reply.
+    pub inline_max_size: Option<usize>,
+    /// Milliseconds between reports of a running task's progress.
+    pub progress_interval_ms: Option<NonZeroU64>,
+    /// Milliseconds clients are given to acknowledge a notification, before it is resent.
+    pub retransmit_after_ms: Option<NonZeroU64>,
+    /// Times a notification is sent, at most.
+    pub max_transmissions: Option<u32>,
+    /// Address workers register at, to run pending tasks.
+    pub worker_listen: Option<SocketAddr>,
+    /// File the server's PID is written to.
+    pub pid_file: Option<PathBuf>,
+    /// File the number of the server's next task is kept in.
+    pub counter_file: Option<PathBuf>,
+    /// User the server runs as once its sockets are bound.
+    pub user: Option<String>,
+    /// Group the server runs as along with its user.
+    pub group: Option<String>,
+    /// Permission bits of the outputs of tasks that don't ask for their own.
+    pub output_mode: Option<u32>,
+    /// Umask the server sets once its sockets are bound.
+    pub umask: Option<u32>,
+    pub log: LogSection,
+    pub audit: AuditSection,
+    pub cache: CacheSection,
+    pub rate_limit: RateLimitSection,
+    pub environment: EnvironmentSection,
+    /// Server-wide filter limits, and settings, see [`ConfigFile::limits`].
+    limits: toml::Table,
+    /// Paths of the filters' executables, by filter, for those not in `transformations`.
+    executables: toml::Table,
+    /// Filters registered with the server, by name, each with its `limit`, and optionally
+    /// its `executable`.
+    filters: toml::Table,
+    /// Queues, by name, each with its `weight`, and filter limits.
+    queues: toml::Table,
+}
+
+/// The `[log]` table of a [`ConfigFile`].
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct LogSection {
+    /// File logs are written to, as well as to the terminal.
+    pub file: Option<PathBuf>,
+    /// Most verbose level logged, e.g. `info`.
+    pub level: Option<String>,
+    /// Format logs are written in, `text` or `json`, see [`LogFormat`](crate::util::LogFormat).
+    pub format: Option<String>,
+    /// Whether logs are written by a `tracing` subscriber, in each task's span, see
+    /// [`init_tracing`](crate::util::init_tracing).
+    pub tracing: bool,
+    /// Where logs are written, other than to `file`: `terminal`, `journald` or `syslog`, see
+    /// [`LogSink`](crate::util::LogSink).
+    pub sink: Option<String>,
+    /// Size, in bytes, past which the file is rotated, see [`Rotation`](crate::util::Rotation).
+    pub max_size: Option<u64>,
+    /// Seconds the file is written to before it is rotated.
+    pub max_age: Option<NonZeroU64>,
+    /// Number of rotated files kept.
+    pub keep: Option<usize>,
+    /// Most verbose level logged by some modules, and their submodules, by module path.
+    pub targets: BTreeMap<String, String>,
+}
+
+/// The `[audit]` table of a [`ConfigFile`], see [`AuditConfig`](super::audit::AuditConfig).
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct AuditSection {
+    /// File the lifecycle of every task is recorded in. No audit log is kept without it.
+    pub file: Option<PathBuf>,
+    /// Size, in bytes, past which the file is rotated.
+    pub max_size: Option<u64>,
+    /// Number of rotated files kept.
+    pub keep: Option<usize>,
+}
+
+/// The `[cache]` table of a [`ConfigFile`], see [`CacheConfig`](super::cache::CacheConfig).
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct CacheSection {
+    /// Directory the outputs of tasks are cached in. No outputs are cached without it.
+    pub dir: Option<PathBuf>,
+    /// Size, in bytes, past which the least recently used outputs are evicted.
+    pub max_size: Option<u64>,
+}
+
+/// The `[rate-limit]` table of a [`ConfigFile`], see [`RateLimit`](super::rate_limit::RateLimit).
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct RateLimitSection {
+    /// Most requests a client may submit at once. Requests aren't rate limited without it.
+    pub burst: Option<NonZeroU32>,
+    /// Milliseconds it takes a client to be given back a request.
+    pub refill_ms: Option<NonZeroU64>,
+}
+
+/// The `[environment]` table of a [`ConfigFile`], see
+/// [`FilterEnvironment`](super::environment::FilterEnvironment).
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct EnvironmentSection {
+    /// Variables set for every external filter, by name.
+    pub vars: BTreeMap<String, String>,
+    /// Directory external filters run in, rather than the server's working directory.
+    pub cwd: Option<PathBuf>,
+    /// Names of the variables tasks may set for their own filters.
+    pub allowed: Vec<String>,
+}
+
+/// Errors that may happen when reading a [`ConfigFile`].
+#[derive(Debug)]
+pub enum ConfigFileError {
+    ReadError(io::Error),
+    ParseError(toml::de::Error),
+    /// The setting with this key, in `[limits]` or a queue, has a value no line of a limits
+    /// file could have, see [`ConfigFile::limits`].
+    InvalidValue(String),
+    /// The registered filter with this name isn't a table with a `limit`, and at most an
+    /// `executable`.
+    InvalidFilter(String),
+    /// The queue with this name isn't a table.
+    InvalidQueue(String),
+}
+
+impl From<io::Error> for ConfigFileError {
+    fn from(err: io::Error) -> Self {
+        ConfigFileError::ReadError(err)
+    }
+}
+
+impl From<toml::de::Error> for ConfigFileError {
+    fn from(err: toml::de::Error) -> Self {
+        ConfigFileError::ParseError(err)
+    }
+}
+
+impl ConfigFile {
+    /// Read the config file at `path`.
+    pub fn read(path: &Path) -> Result<Self, ConfigFileError> {
+        Self::parse(&fs::read_to_string(path)?)
+    }
+
+    pub fn parse(s: &str) -> Result<Self, ConfigFileError> {
+        Ok(toml::from_str(s)?)
+    }
+
+    /// Whether the file has any of the `[limits]`, `[executables]`, `[filters]` and `[queues]`
+    /// tables, see [`ConfigFile::limits`].
+    pub fn has_limits(&self) -> bool {
+        !(self.limits.is_empty() && self.executables.is_empty() && self.filters.is_empty() && self.queues.is_empty())
+    }
+
+    /// The `[limits]`, `[executables]`, `[filters]` and `[queues]` tables, as the contents of a limits file, see
+    /// [`parse_limits`](super::config::parse_limits).
+    ///
+    /// Each setting is a line of its key, followed by its value, or values if it's a list,
+    /// as in `nop 3` or `builtin gcompress gdecompress`. Settings that are `true` are just
+    /// their key, as in `optimize`, and those that are `false` are left out. Executables are
+    /// `executable` lines, as in `executable gcompress /usr/bin/sdstore-gcompress`, and
+    /// registered filters `filter` lines, as in `filter lz4 2 /usr/bin/sdstore-lz4`.
+    pub fn limits(&self) -> Result<String, ConfigFileError> {
+        let mut lines = table_lines(&self.limits)?;
+        for (filter, path) in &self.executables {
+            lines.push(format!("executable {filter} {}", word(filter, path)?));
+        }
+        for (name, filter) in &self.filters {
+            let invalid = || ConfigFileError::InvalidFilter(name.clone());
+            let toml::Value::Table(filter) = filter else {
+                return Err(invalid())
+            };
+            if filter.keys().any(|key| key != "limit" && key != "executable") {
+                return Err(invalid())
+            }
+            let mut line = match filter.get("limit") {
+                Some(limit @ toml::Value::Integer(_)) => format!("filter {name} {}", word(name, limit)?),
+                _ => return Err(invalid()),
+            };
+            if let Some(path) = filter.get("executable") {
+                line.push(' ');
+                line.push_str(&word(name, path)?);
+            }
+            lines.push(line);
+        }
+        for (name, queue) in &self.queues {
+            let toml::Value::Table(queue) = queue else {
+                return Err(ConfigFileError::InvalidQueue(name.clone()))
+            };
+            let mut header = format!("queue {name}");
+            if let Some(weight) = queue.get("weight") {
+                header.push(' ');
+                header.push_str(&word("weight", weight)?);
+            }
+            let mut limits = queue.clone();
+            limits.remove("weight");
+            lines.push(header);
+            lines.extend(table_lines(&limits)?);
+        }
+
+        Ok(lines.join("\n"))
+    }
+}
+
+/// The limits file lines of the settings in `table`, see [`ConfigFile::limits`].
+fn table_lines(table: &toml::Table) -> Result<Vec<String>, ConfigFileError> {
+    let mut lines = Vec::new();
+    for (key, value) in table {
+        let line = match value {
+            toml::Value::Boolean(false) => continue,
+            toml::Value::Boolean(true) => key.clone(),
+            toml::Value::Array(values) => {
+                let words = values.iter().map(|value| word(key, value)).collect::<Result<Vec<_>, _>>()?;
+                format!("{key} {}", words.join(" "))
+            },
+            value => format!("{key} {}", word(key, value)?),
+        };
+        lines.push(line);
+    }
+    Ok(lines)
+}
+
+/// `value`, of the setting `key`, as a word of a limits file line.
+fn word(key: &str, value: &toml::Value) -> Result<String, ConfigFileError> {
+    let word = match value {
+        toml::Value::Integer(n) => n.to_string(),
+        toml::Value::String(s) => s.clone(),
+        _ => return Err(ConfigFileError::InvalidValue(key.to_string())),
+    };
+    // Which would have the value be read as several lines.
+    match word.contains('\n') || key.contains(char::is_whitespace) {
+        true => Err(ConfigFileError::InvalidValue(key.to_string())),
+        false => Ok(word),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn config_file_parsing_works() {
+        let config = ConfigFile::parse(r#"
+            transformations = "bin/sdstore-transformations"
+            queue-capacity = 100
+            scan-depth = 4
+            idempotency-window = 60
+            store-dir = "store"
+            inline-max-size = 4096
+            monitor-threads = 8
+            recv-buffer = 4194304
+            pipe-buffer = 1048576
+            retransmit-after-ms = 250
+            worker-listen = "127.0.0.1:7070"
+            pid-file = "sdstored.pid"
+            counter-file = "counter"
+            user = "sdstore"
+            group = "sdstore"
+            output-mode = 0o640
+            umask = 0o027
+
+            [log]
+            level = "info"
+            tracing = true
+            max-age = 3600
+            sink = "syslog"
+            targets = { sdstored = "debug" }
+
+            [audit]
+            file = "audit.log"
+            keep = 2
+
+            [cache]
+            dir = "cache"
+
+            [rate-limit]
+            burst = 5
+
+            [environment]
+            vars = { LANG = "C.UTF-8" }
+            allowed = ["TZ"]
+
+            [limits]
+            nop = 3
+            builtin = ["gcompress", "gdecompress"]
+            ionice = "best-effort 4"
+            optimize = true
+            pool = 0
+
+            [executables]
+            gcompress = "/opt/sdstore/gcompress"
+
+            [filters.lz4]
+            limit = 2
+
+            [queues.batch]
+            weight = 2
+            nop = 1
+        "#).expect("parsing should succeed");
+
+        assert_eq!(config.transformations, Some(PathBuf::from("bin/sdstore-transformations")));
+        assert_eq!((config.queue_capacity, config.shutdown_timeout), (Some(100), None));
+        assert_eq!((config.scan_depth, config.task_timeout), (Some(4), None));
+        assert_eq!((config.idempotency_window, config.store_dir.as_deref()), (Some(60), Some(Path::new("store"))));
+        assert_eq!(config.inline_max_size, Some(4096));
+        assert_eq!((config.retransmit_after_ms, config.monitor_threads), (NonZeroU64::new(250), NonZeroUsize::new(8)));
+        assert_eq!((config.recv_buffer, config.pipe_buffer), (NonZeroUsize::new(4 << 20), NonZeroUsize::new(1 << 20)));
+        assert_eq!(config.worker_listen, "127.0.0.1:7070".parse().ok());
+        assert_eq!((config.pid_file.as_deref(), config.counter_file.as_deref()), (Some(Path::new("sdstored.pid")), Some(Path::new("counter"))));
+        assert_eq!((config.user.as_deref(), config.group.as_deref()), (Some("sdstore"), Some("sdstore")));
+        assert_eq!((config.output_mode, config.umask), (Some(0o640), Some(0o027)));
+        assert_eq!(config.log.level.as_deref(), Some("info"));
+        assert!(config.log.tracing);
+        assert_eq!(config.log.sink.as_deref(), Some("syslog"));
+        assert_eq!((config.log.max_size, config.log.max_age, config.log.keep), (None, NonZeroU64::new(3600), None));
+        assert_eq!(config.log.targets.get("sdstored").map(String::as_str), Some("debug"));
+        assert_eq!((config.audit.file.as_deref(), config.audit.max_size, config.audit.keep), (Some(Path::new("audit.log")), None, Some(2)));
+        assert_eq!((config.cache.dir.as_deref(), config.cache.max_size), (Some(Path::new("cache")), None));
+        assert_eq!((config.rate_limit.burst, config.rate_limit.refill_ms), (NonZeroU32::new(5), None));
+        assert_eq!(config.environment.vars.get("LANG").map(String::as_str), Some("C.UTF-8"));
+        assert_eq!((config.environment.cwd.as_deref(), config.environment.allowed.as_slice()), (None, [String::from("TZ")].as_slice()));
+        assert!(config.has_limits() && !ConfigFile::parse("queue-capacity = 1").unwrap().has_limits());
+        assert_eq!(
+            config.limits().unwrap(),
+            "builtin gcompress gdecompress\nionice best-effort 4\nnop 3\noptimize\npool 0\nexecutable gcompress /opt/sdstore/gcompress\nfilter lz4 2\nqueue batch 2\nnop 1"
+        );
+
+        assert!(matches!(ConfigFile::parse("timeout = 3"), Err(ConfigFileError::ParseError(_))));
+        assert!(matches!(ConfigFile::parse("progress-interval-ms = 0"), Err(ConfigFileError::ParseError(_))));
+        assert!(matches!(ConfigFile::parse("monitor-threads = 0"), Err(ConfigFileError::ParseError(_))));
+        let invalid = ConfigFile::parse("[limits]\nnop = 1.5").unwrap();
+        assert!(matches!(invalid.limits(), Err(ConfigFileError::InvalidValue(key)) if key == "nop"));
+        let invalid = ConfigFile::parse("[filters.lz4]\nexecutable = \"/opt/lz4\"").unwrap();
+        assert!(matches!(invalid.limits(), Err(ConfigFileError::InvalidFilter(name)) if name == "lz4"));
+        let injected = ConfigFile::parse("[limits]\nnop = \"1\\nqueue batch 1\"").unwrap();
+        assert!(matches!(injected.limits(), Err(ConfigFileError::InvalidValue(_))));
+    }
+}
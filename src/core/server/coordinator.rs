@@ -0,0 +1,367 @@
+//! Workers that run the server's pending tasks on its behalf, as their coordinator: each
+//! `sdstored-worker` connects to the address the server listens for workers on, over TCP,
+//! registers with its own filter limits, and is then handed the tasks that fit within them,
+//! see [`listen`].
+//!
+//! Workers open the tasks' files at the paths their clients gave, so they must share the
+//! server's storage, mounted at the same paths.
+
+use std::{
+    fmt::Debug, io, net::{Shutdown, TcpListener, TcpStream, ToSocketAddrs}, sync::{atomic::{AtomicUsize, Ordering}, Arc},
+    thread, time::Duration,
+};
+
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use tokio::sync::mpsc::Sender;
+
+use crate::core::{
+    batch,
+    client_task::ClientTask,
+    framing,
+    limits::RunningFilters,
+    messaging::{Codec, MessageToServer, RequestFailure, WireFormat},
+    monitor::MonitorSuccess,
+};
+
+use super::config::FiltersConfig;
+
+/// Environment variable giving the token workers must register with, on the server, and
+/// that they register with, on workers, see [`WorkerToken`].
+pub const TOKEN_VAR: &str = "SDSTORED_WORKER_TOKEN";
+
+/// Version of the messages exchanged with workers, which only register if theirs is the
+/// server's, see [`WorkerMessage::Register`].
+pub const PROTOCOL_VERSION: u32 = 1;
+
+/// How often the server and its workers tell each other they are still there, when they
+/// have nothing else to say, see [`CoordinatorMessage::Heartbeat`].
+pub const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(5);
+
+/// How long the server, or a worker, goes without hearing from the other before taking it
+/// to be gone.
+pub const WORKER_TIMEOUT: Duration = Duration::from_secs(15);
+
+/// How long the thread accepting workers waits after failing to accept one.
+const ACCEPT_RETRY_DELAY: Duration = Duration::from_millis(100);
+
+/// Messages exchanged with workers are always encoded with `bincode`, whatever the wire
+/// format of the server's clients.
+const CODEC: WireFormat = WireFormat::Bincode;
+
+/// Secret shared by the server and its workers, which workers register with, read from
+/// [`TOKEN_VAR`]. Kept out of logs, and compared in constant time.
+#[derive(Clone, Eq, Serialize, Deserialize)]
+pub struct WorkerToken(pub String);
+
+impl PartialEq for WorkerToken {
+    /// Whether both tokens are the same, taking as long whichever byte they first differ
+    /// at, so that workers can't guess the server's token from how long they take to be
+    /// refused. Only its length isn't kept secret.
+    fn eq(&self, other: &Self) -> bool {
+        let (token, other) = (self.0.as_bytes(), other.0.as_bytes());
+        token.len() == other.len()
+            && token.iter().zip(other).fold(0, |diff, (byte, other)| std::hint::black_box(diff | (byte ^ other))) == 0
+    }
+}
+
+impl Debug for WorkerToken {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "WorkerToken(..)")
+    }
+}
+
+/// Messages a worker sends to the server.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub enum WorkerMessage {
+    /// Sent first, for the worker to be handed tasks that, along with those it runs, fit
+    /// within `limits`, and at most `max_tasks` at once.
+    Register {
+        protocol: u32,
+        name: String,
+        token: Option<WorkerToken>,
+        limits: FiltersConfig,
+        max_tasks: usize
+    },
+    /// Task #`task_number` wrote this many bytes of its output so far.
+    Progress {
+        task_number: usize,
+        bytes_out: u64
+    },
+    /// Task #`task_number` concluded, as its client is to be told.
+    Finished {
+        task_number: usize,
+        result: Result<MonitorSuccess, RequestFailure>
+    },
+    /// Task #`task_number` could not be started by the worker, for this reason, and is to be
+    /// run elsewhere.
+    Declined {
+        task_number: usize,
+        reason: String
+    },
+    Heartbeat,
+}
+
+/// Messages the server sends to a worker.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub enum CoordinatorMessage {
+    /// The worker registered, and is known to the server by this ID.
+    Registered(usize),
+    /// The worker could not register, for this reason, and is disconnected.
+    Refused(String),
+    /// Run `task` as task #`task_number`, which waited this long in its queue.
+    Run {
+        task_number: usize,
+        task: Box<ClientTask>,
+        waited: Duration
+    },
+    /// Kill task #`task_number`, which then concludes as cancelled.
+    Cancel(usize),
+    Heartbeat,
+}
+
+/// What happened with a worker, as the server's event loop is told, see
+/// [`MessageToServer::Worker`].
+#[derive(Debug)]
+pub enum WorkerEvent {
+    /// A worker registered, with its limits, and most tasks it runs at once.
+    Joined(Arc<WorkerLink>, FiltersConfig, usize),
+    /// The worker with this ID sent a message.
+    Message(usize, WorkerMessage),
+    /// The worker with this ID disconnected, or went quiet for longer than [`WORKER_TIMEOUT`].
+    Left(usize),
+}
+
+/// Connection to a registered worker, over which it is sent [`CoordinatorMessage`]s.
+#[derive(Debug)]
+pub struct WorkerLink {
+    pub id: usize,
+    pub name: String,
+    stream: TcpStream,
+}
+
+impl WorkerLink {
+    /// Send `msg` to the worker.
+    pub fn send(&self, msg: &CoordinatorMessage) -> io::Result<()> {
+        send(&self.stream, msg)
+    }
+
+    /// Disconnect the worker, which is then told of as having left, see [`WorkerEvent::Left`].
+    pub fn close(&self) {
+        if let Err(err) = self.stream.shutdown(Shutdown::Both) {
+            log::debug!("could not disconnect worker {}: {:?}", self.name, err);
+        }
+    }
+}
+
+/// A worker registered with the server, and the tasks it is running.
+#[derive(Debug)]
+pub struct RemoteWorker {
+    pub link: Arc<WorkerLink>,
+    /// The worker's own filter limits, within which it is handed tasks.
+    pub limits: FiltersConfig,
+    /// Most tasks the worker runs at once.
+    pub max_tasks: usize,
+    /// Count of the filters of the tasks the worker is running.
+    pub filters_count: RunningFilters,
+    /// Number of tasks the worker is running.
+    pub tasks: usize,
+}
+
+impl RemoteWorker {
+    pub fn new(link: Arc<WorkerLink>, limits: FiltersConfig, max_tasks: usize) -> Self {
+        RemoteWorker { link, limits, max_tasks, filters_count: RunningFilters::default(), tasks: 0 }
+    }
+}
+
+/// Whether `task` may be handed to a worker, which only runs tasks on single files, that
+/// it can open at the paths their clients gave: not streamed, or inline, tasks, whose files
+/// are the server's copies, nor those written to its store, nor batches, nor those resuming from
+/// a checkpoint, which is the server's.
+pub fn is_eligible(task: &ClientTask) -> bool {
+    !task.is_spooled() && !task.store && task.checkpoint.is_none() && !batch::is_batch(task.input_filepath())
+}
+
+/// Errors that may happen while a worker registers with the server, see [`register`].
+#[derive(Debug)]
+pub enum RegisterError {
+    /// Connecting to the server, or exchanging messages with it, failed.
+    Io(io::Error),
+    /// The server refused the worker, for this reason.
+    Refused(String),
+}
+
+impl From<io::Error> for RegisterError {
+    fn from(err: io::Error) -> Self {
+        Self::Io(err)
+    }
+}
+
+/// Connect to the server listening for workers at `addr`, and register as `name`, with
+/// `token`, to be handed tasks that fit within `limits`, at most `max_tasks` at once.
+///
+/// Returns the connection, over which the worker is then sent tasks, and the ID the
+/// server knows it by.
+pub fn register(
+    addr: impl ToSocketAddrs,
+    name: &str,
+    token: Option<WorkerToken>,
+    limits: FiltersConfig,
+    max_tasks: usize
+) -> Result<(TcpStream, usize), RegisterError> {
+    let stream = TcpStream::connect(addr)?;
+    configure(&stream)?;
+    let name = name.to_string();
+    send(&stream, &WorkerMessage::Register { protocol: PROTOCOL_VERSION, name, token, limits, max_tasks })?;
+    match receive(&stream)? {
+        CoordinatorMessage::Registered(id) => Ok((stream, id)),
+        CoordinatorMessage::Refused(reason) => Err(RegisterError::Refused(reason)),
+        msg => Err(RegisterError::Io(io::Error::new(io::ErrorKind::InvalidData, format!("unexpected reply {:?}", msg)))),
+    }
+}
+
+/// Accept workers on `listener`, each served by a thread of its own, see [`serve_worker`],
+/// which tells the server's event loop of it through `sender`.
+///
+/// Workers must register with `token`, whichever address the server listens at: even a
+/// loopback one can be connected to by any local user.
+pub fn listen(listener: TcpListener, token: WorkerToken, sender: Sender<MessageToServer>) {
+    let next_id = AtomicUsize::new(1);
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Err(err) => {
+                log::warn!("could not accept worker: {:?}", err);
+                thread::sleep(ACCEPT_RETRY_DELAY);
+                continue;
+            },
+            Ok(stream) => stream,
+        };
+
+        let (id, token, sender) = (next_id.fetch_add(1, Ordering::Relaxed), token.clone(), sender.clone());
+        let server = thread::Builder::new()
+            .name(format!("sdstored_worker_{id}"))
+            .spawn(move || {
+                let peer = stream.peer_addr();
+                if let Err(err) = serve_worker(stream, id, &token, &sender) {
+                    log::warn!("worker #{id} at {:?} disconnected: {:?}", peer, err);
+                }
+                // The server only stops receiving as it exits.
+                let _ = sender.blocking_send(MessageToServer::Worker(WorkerEvent::Left(id)));
+            });
+        if let Err(err) = server {
+            log::warn!("could not spawn thread to serve worker: {:?}", err);
+        }
+    }
+}
+
+/// Register the worker connected over `stream` as worker #`id`, if it has the right
+/// `token`, and relay every message it sends to the server's event loop, until it
+/// disconnects.
+fn serve_worker(stream: TcpStream, id: usize, token: &WorkerToken, sender: &Sender<MessageToServer>) -> io::Result<()> {
+    configure(&stream)?;
+    let (name, limits, max_tasks) = match receive(&stream)? {
+        WorkerMessage::Register { protocol, .. } if protocol != PROTOCOL_VERSION => {
+            let reason = format!("the server speaks protocol version {PROTOCOL_VERSION}, not {protocol}");
+            return refuse(&stream, reason)
+        },
+        // Compared in constant time, see `WorkerToken::eq`.
+        WorkerMessage::Register { token: given, .. } if given.as_ref() != Some(token) =>
+            return refuse(&stream, String::from("the worker's token is not the server's")),
+        WorkerMessage::Register { name, limits, max_tasks, .. } => (name, limits, max_tasks),
+        _ => return refuse(&stream, String::from("the worker did not register first")),
+    };
+
+    send(&stream, &CoordinatorMessage::Registered(id))?;
+    let link = Arc::new(WorkerLink { id, name, stream: stream.try_clone()? });
+    let gone = || io::Error::new(io::ErrorKind::BrokenPipe, "the server is shutting down");
+    sender
+        .blocking_send(MessageToServer::Worker(WorkerEvent::Joined(link, limits, max_tasks)))
+        .map_err(|_| gone())?;
+    loop {
+        let msg = receive(&stream)?;
+        sender
+            .blocking_send(MessageToServer::Worker(WorkerEvent::Message(id, msg)))
+            .map_err(|_| gone())?;
+    }
+}
+
+/// Tell the worker connected over `stream` that it can't register, for `reason`.
+fn refuse(stream: &TcpStream, reason: String) -> io::Result<()> {
+    send(stream, &CoordinatorMessage::Refused(reason.clone()))?;
+    Err(io::Error::new(io::ErrorKind::PermissionDenied, reason))
+}
+
+/// Set up a connection between the server and a worker: either takes the other to be gone
+/// once it hasn't heard from it for [`WORKER_TIMEOUT`].
+fn configure(stream: &TcpStream) -> io::Result<()> {
+    stream.set_nodelay(true)?;
+    stream.set_read_timeout(Some(WORKER_TIMEOUT))?;
+    stream.set_write_timeout(Some(WORKER_TIMEOUT))
+}
+
+/// Send `msg` over `stream`, in a single frame.
+pub fn send(stream: &TcpStream, msg: &impl Serialize) -> io::Result<()> {
+    let bytes = CODEC.encode(msg).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, format!("{:?}", err)))?;
+    framing::write_frame(stream, &bytes)
+}
+
+/// Wait for the next message sent over `stream`, in a single frame, see [`send`].
+pub fn receive<T: DeserializeOwned>(stream: &TcpStream) -> io::Result<T> {
+    let bytes = framing::read_frame(stream)?;
+    CODEC.decode(&bytes).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, format!("{:?}", err)))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use tokio::sync::mpsc;
+
+    use super::*;
+
+    #[test]
+    fn tokens_are_equal_only_if_every_byte_is() {
+        let token = |token: &str| WorkerToken(String::from(token));
+        assert_eq!(token("secret"), token("secret"));
+        assert_ne!(token("secret"), token("secreT"));
+        assert_ne!(token("secret"), token("Secret"));
+        assert_ne!(token("secret"), token("secret2"));
+        assert_ne!(token(""), token("secret"));
+    }
+
+    #[test]
+    fn workers_register_with_the_server_token() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (sender, mut receiver) = mpsc::channel(16);
+        let token = WorkerToken(String::from("secret"));
+        let server_token = token.clone();
+        thread::spawn(move || listen(listener, server_token, sender));
+
+        let limits = FiltersConfig { nop: 2, ..Default::default() };
+        for wrong in [Some(WorkerToken(String::from("guess"))), None] {
+            assert!(matches!(register(addr, "w1", wrong, limits.clone(), 1), Err(RegisterError::Refused(_))));
+            assert!(matches!(receiver.blocking_recv(), Some(MessageToServer::Worker(WorkerEvent::Left(_)))));
+        }
+
+        let (stream, id) = register(addr, "w2", Some(token), limits.clone(), 3).unwrap();
+        match receiver.blocking_recv() {
+            Some(MessageToServer::Worker(WorkerEvent::Joined(link, joined_limits, 3))) => {
+                assert_eq!((link.id, link.name.as_str(), joined_limits), (id, "w2", limits));
+                let task = ClientTask::new(1, 0, PathBuf::from("in"), PathBuf::from("out"), Vec::new());
+                let run = CoordinatorMessage::Run { task_number: 7, task: Box::new(task), waited: Duration::ZERO };
+                link.send(&run).unwrap();
+                assert_eq!(receive::<CoordinatorMessage>(&stream).unwrap(), run);
+            },
+            _ => panic!("worker did not join"),
+        }
+
+        send(&stream, &WorkerMessage::Progress { task_number: 7, bytes_out: 10 }).unwrap();
+        assert!(matches!(
+            receiver.blocking_recv(),
+            Some(MessageToServer::Worker(WorkerEvent::Message(sent_by, WorkerMessage::Progress { task_number: 7, .. })))
+                if sent_by == id
+        ));
+        drop(stream);
+        assert!(matches!(receiver.blocking_recv(), Some(MessageToServer::Worker(WorkerEvent::Left(left))) if left == id));
+    }
+}
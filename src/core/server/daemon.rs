@@ -0,0 +1,199 @@
+//! Running the server in the background, detached from the terminal it was started from,
+//! see [`daemonize`], and as the only server of its socket directory, see [`InstanceLock`].
+
+use std::{
+    fs::{self, File, OpenOptions}, io::{self, Read, Seek, Write}, os::fd::{AsRawFd, FromRawFd, RawFd},
+    path::{Path, PathBuf}, process,
+};
+
+/// Name of the file, in the socket directory, locked by the server using it, see
+/// [`InstanceLock`].
+pub const LOCK_FILE: &str = "sdstored.lock";
+
+/// The forked server's end of a pipe to the process it was started as, which waits on it
+/// to be ready, see [`daemonize`].
+pub struct Readiness(File);
+
+/// Fork the server into the background, returning in the forked process only. The process
+/// the server was started as waits for it to be ready, see [`Readiness::notify`], and exits:
+/// successfully if it was, or with an error if the forked process exited first, having
+/// logged why.
+///
+/// The server is forked twice, the first fork starting a session of its own, and exiting
+/// once it forked the second, so that the server is no session leader, and can never take a
+/// terminal as its controlling one.
+///
+/// Must be called before any thread is spawned, as only the calling thread is forked. The
+/// working directory is kept, as the paths of the config and of tasks are relative to it.
+pub fn daemonize() -> io::Result<Readiness> {
+    let mut fds = [0; 2];
+    // SAFETY: `fds` is valid for writes of two file descriptors.
+    if unsafe { libc::pipe2(fds.as_mut_ptr(), libc::O_CLOEXEC) } == -1 {
+        return Err(io::Error::last_os_error())
+    }
+    // SAFETY: the pipe's ends were just opened, and nothing else owns them.
+    let (mut reader, writer) = unsafe { (File::from_raw_fd(fds[0]), File::from_raw_fd(fds[1])) };
+
+    // SAFETY: only the calling thread is running, see above.
+    match unsafe { libc::fork() } {
+        -1 => Err(io::Error::last_os_error()),
+        0 => {
+            drop(reader);
+            // SAFETY: `setsid` has no preconditions.
+            if unsafe { libc::setsid() } == -1 {
+                return Err(io::Error::last_os_error())
+            }
+            // SAFETY: the forked process is single-threaded, as its parent was.
+            match unsafe { libc::fork() } {
+                -1 => Err(io::Error::last_os_error()),
+                0 => Ok(Readiness(writer)),
+                _ => process::exit(0),
+            }
+        },
+        pid => {
+            // Reading ends once the server is done with its end, if it wasn't ready.
+            drop(writer);
+            // SAFETY: `pid` is that of a child of this process, which exits once it forked.
+            unsafe { libc::waitpid(pid, std::ptr::null_mut(), 0) };
+            let mut ready = [0; 4];
+            match reader.read(&mut ready) {
+                Ok(4) => {
+                    log::info!("server running in the background, as PID {}", u32::from_ne_bytes(ready));
+                    process::exit(0)
+                },
+                _ => {
+                    log::error!("server exited before it was ready to take requests");
+                    process::exit(1)
+                },
+            }
+        },
+    }
+}
+
+impl Readiness {
+    /// Let the process the server was started as exit, as the server is ready to take
+    /// requests. The terminal is left alone from now on: the server's standard streams are
+    /// redirected to `/dev/null`, so that only logs written to a file are kept.
+    pub fn notify(mut self) -> io::Result<()> {
+        let null = OpenOptions::new().read(true).write(true).open("/dev/null")?;
+        for fd in [libc::STDIN_FILENO, libc::STDOUT_FILENO, libc::STDERR_FILENO] {
+            // SAFETY: both file descriptors are open.
+            if unsafe { libc::dup2(null.as_raw_fd(), fd) } == -1 {
+                return Err(io::Error::last_os_error())
+            }
+        }
+        self.0.write_all(&process::id().to_ne_bytes())
+    }
+}
+
+/// Errors taking the lock of the server's socket directory, see [`InstanceLock::acquire`].
+#[derive(Debug)]
+pub enum LockError {
+    /// Another server holds the lock, as the process of this PID, if it could be read.
+    Held(Option<u32>),
+    /// The lock file could not be opened, locked or written to.
+    Io(io::Error),
+}
+
+impl From<io::Error> for LockError {
+    fn from(err: io::Error) -> Self {
+        Self::Io(err)
+    }
+}
+
+/// Exclusive lock of a socket directory, held by the server using it for as long as it
+/// runs, so that a second server refuses to start there rather than remove the sockets of
+/// the first, and take over its clients.
+///
+/// The lock is an `flock` of the directory's [`LOCK_FILE`], into which the server writes its
+/// PID, and is released by the kernel once the server exits, however it does. The file is
+/// left in place, as removing it would let another server lock a new one at the same path.
+#[derive(Debug)]
+pub struct InstanceLock {
+    _file: File,
+}
+
+impl InstanceLock {
+    /// Take the lock of the socket directory `dir`, failing at once if another process
+    /// holds it.
+    pub fn acquire(dir: &Path) -> Result<Self, LockError> {
+        let mut file = OpenOptions::new().read(true).write(true).create(true).truncate(false).open(dir.join(LOCK_FILE))?;
+        // SAFETY: the file is open, and stays so for the lock's lifetime.
+        if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) } == -1 {
+            let err = io::Error::last_os_error();
+            if err.raw_os_error() != Some(libc::EWOULDBLOCK) {
+                return Err(err.into())
+            }
+            let mut pid = String::new();
+            return Err(LockError::Held(file.read_to_string(&mut pid).ok().and_then(|_| pid.trim().parse().ok())))
+        }
+        file.set_len(0)?;
+        file.rewind()?;
+        writeln!(file, "{}", process::id())?;
+        Ok(InstanceLock { _file: file })
+    }
+
+    /// The lock held through `fd`, which a server taking over from another inherited, see
+    /// [`InheritedFds`](super::handover::InheritedFds).
+    ///
+    /// # Safety
+    ///
+    /// `fd` must be an open FD of the lock file, owned by nothing else.
+    pub unsafe fn inherit(fd: RawFd) -> Self {
+        // SAFETY: as the caller guarantees.
+        InstanceLock { _file: unsafe { File::from_raw_fd(fd) } }
+    }
+}
+
+impl AsRawFd for InstanceLock {
+    fn as_raw_fd(&self) -> RawFd {
+        self._file.as_raw_fd()
+    }
+}
+
+/// File holding the server's PID, for scripts and service managers to signal it by, which
+/// is removed once it is dropped, see [`PidFile::create`].
+#[derive(Debug)]
+pub struct PidFile {
+    path: PathBuf,
+}
+
+impl PidFile {
+    /// Write the PID of the server to the file at `path`, replacing whatever was there, as
+    /// a server that's running holds the [`InstanceLock`] of its socket directory instead.
+    pub fn create(path: &Path) -> io::Result<Self> {
+        fs::write(path, format!("{}\n", process::id()))?;
+        Ok(PidFile { path: path.to_path_buf() })
+    }
+}
+
+impl Drop for PidFile {
+    fn drop(&mut self) {
+        if let Err(err) = fs::remove_file(&self.path) {
+            log::warn!("could not remove the pid file {:?}: {:?}", self.path, err);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::core::testing::TempDir;
+
+    use super::*;
+
+    #[test]
+    fn only_one_server_locks_a_socket_dir() {
+        let dir = TempDir::new("lock");
+        let lock = InstanceLock::acquire(&dir).unwrap();
+        // Locks are of open files, so the same process can't take it twice either.
+        assert!(matches!(InstanceLock::acquire(&dir), Err(LockError::Held(Some(pid))) if pid == process::id()));
+        drop(lock);
+        let _lock = InstanceLock::acquire(&dir).unwrap();
+        assert_eq!(fs::read_to_string(dir.join(LOCK_FILE)).unwrap(), format!("{}\n", process::id()));
+
+        let pid_file = PidFile::create(&dir.join("sdstored.pid")).unwrap();
+        assert_eq!(fs::read_to_string(dir.join("sdstored.pid")).unwrap(), format!("{}\n", process::id()));
+        drop(pid_file);
+        assert!(!dir.join("sdstored.pid").exists());
+    }
+}
@@ -0,0 +1,185 @@
+use std::{fmt::Display, fs, path::Path};
+
+use serde::{Serialize, Deserialize};
+
+use crate::core::{batch, client_task::ClientTask, filter::Filter, limits::RunningFilters, remote};
+
+use super::config::{is_executable, FilterExecutor, FiltersConfig, ServerConfig};
+
+/// What the server would do with a task submitted with `--dry-run`, which it validates
+/// instead of running.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct DryRunReport {
+    /// Queue the task would be submitted to.
+    pub queue: String,
+    /// How each of the task's filters would be run, in order: `builtin`, or the path of
+    /// the filter's executable.
+    pub stages: Vec<(Filter, String)>,
+    /// Why the task could never run, or would fail. Empty if no problem was found.
+    pub problems: Vec<String>,
+    /// Whether the task would start right away, rather than wait for pending tasks, or
+    /// for filters in use to be freed.
+    pub would_start_now: bool,
+}
+
+impl Display for DryRunReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "dry run in queue {}:", self.queue)?;
+        for (stage, (filter, executor)) in self.stages.iter().enumerate() {
+            write!(f, "\nstage {stage} ({filter}): {executor}")?;
+        }
+
+        match self.problems.is_empty() {
+            true if self.would_start_now => write!(f, "\nthe request would start right away"),
+            true => write!(f, "\nthe request would wait for other requests to finish"),
+            false => {
+                write!(f, "\nthe request would fail:")?;
+                for problem in &self.problems {
+                    write!(f, "\n  {problem}")?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Everything that would prevent `task` from succeeding, given the server's config, but
+/// not its current state:
+///
+/// * its queue must exist, and the server must know of its filters, which may not be more
+///   than it allows;
+/// * every filter must have an executable, if it isn't builtin;
+/// * the server-wide and queue limits must allow running the whole pipeline at once;
+/// * the input must be readable, and the output writable, not be the input itself, and
+///   not exist if it may not be replaced; for batches, the input must match some files,
+///   and the output directory be writable; all of them must be within the directories
+///   the server allows;
+/// * if the output is written to the store, the server must have one, and the input must
+///   not be a batch.
+pub fn check_task(task: &ClientTask, config: &ServerConfig) -> Vec<String> {
+    let mut problems = Vec::new();
+
+    let queue = config.queues.iter().find(|q| q.name == task.queue_name());
+    if queue.is_none() {
+        problems.push(format!("the server has no queue {}", task.queue_name()));
+    }
+
+    if task.transformations.len() > config.max_transformations {
+        problems.push(format!(
+            "the pipeline has {} filters, more than the {} the server allows",
+            task.transformations.len(), config.max_transformations
+        ));
+    }
+    for filter in task.transformations.iter().filter(|f| !config.filters_config.knows(f)) {
+        problems.push(format!("the server knows of no filter {filter}"));
+    }
+    for filter in config.filters_config.filters().iter().filter(|f| task.transformations.contains(f)) {
+        match config.filter_executor(filter) {
+            FilterExecutor::External(path) if !is_executable(&path) =>
+                problems.push(format!("no executable for filter {filter} at {}", path.display())),
+            FilterExecutor::Wasm(path) if !path.is_file() =>
+                problems.push(format!("no WASM module for filter {filter} at {}", path.display())),
+            _ => {},
+        }
+    }
+
+    let needed = &RunningFilters::default() + &task.filter_demand();
+    problems.extend(exceeded_limits(&needed, &config.filters_config, "server-wide"));
+    // Queue limits default to the server-wide ones, in which case they were just checked.
+    if let Some(queue) = queue.filter(|q| q.filters_config != config.filters_config) {
+        problems.extend(exceeded_limits(&needed, &queue.filters_config, &format!("queue {}", queue.name)));
+    }
+
+    // Files out of the allowed directories aren't looked at, so as not to tell of them.
+    let remote_output = remote::is_remote(task.output_filepath());
+    match (config.path_policy.denied(task), batch::is_batch(task.input_filepath())) {
+        (Some(path), _) => problems.push(format!("{} is outside the directories the server allows", path.display())),
+        (None, true) if remote_output => problems.push(String::from("the output of a batch can't be written to a URL")),
+        (None, true) => problems.extend(check_batch_files(task)),
+        (None, false) => problems.extend(check_files(task)),
+    }
+    if !remote::SUPPORTED && (remote::is_remote(task.input_filepath()) || remote_output) {
+        problems.push(String::from("the server was built without support for remote inputs and outputs"));
+    }
+    if task.store && config.store_dir.is_none() {
+        problems.push(String::from("the server has no store to write the output to"));
+    }
+    if task.store && batch::is_batch(task.input_filepath()) {
+        problems.push(String::from("the output of a batch can't be written to the store"));
+    }
+
+    problems
+}
+
+/// Problems with the input and output files of a task that isn't a batch.
+fn check_files(task: &ClientTask) -> Vec<String> {
+    let mut problems = Vec::new();
+    // Remote files are only looked at once the task runs.
+    if !remote::is_remote(task.input_filepath()) {
+        if let Err(err) = fs::File::open(task.input_filepath()) {
+            problems.push(format!("input file {} is not readable: {err}", task.input_filepath().display()));
+        }
+    }
+    if remote::is_remote(task.output_filepath()) {
+        return problems
+    }
+    if task.output_is_input() {
+        problems.push(format!("output file {} is the input file", task.output_filepath().display()));
+    }
+    if task.no_clobber && task.output_filepath().exists() {
+        problems.push(format!("output file {} already exists", task.output_filepath().display()));
+    }
+    if let Err(err) = check_writable(task.output_filepath()) {
+        problems.push(format!("output file {} is not writable: {err}", task.output_filepath().display()));
+    }
+    problems
+}
+
+/// Problems with the files of a batch task, see [`batch::expand`]: its input must match
+/// files, and its output directory exist and be writable, or be creatable.
+fn check_batch_files(task: &ClientTask) -> Vec<String> {
+    let (input, output_dir) = (task.input_filepath(), task.output_filepath());
+    let mut problems = Vec::new();
+    match batch::expand(input, output_dir) {
+        Err(err) => problems.push(format!("batch input {} is not readable: {err}", input.display())),
+        Ok(files) if files.is_empty() => problems.push(format!("batch input {} matches no files", input.display())),
+        Ok(files) => problems.extend(files
+            .iter()
+            .filter(|(_, output)| task.no_clobber && output.exists())
+            .map(|(_, output)| format!("output file {} already exists", output.display()))),
+    }
+
+    // A missing output directory is created, under its closest existing ancestor.
+    let existing_dir = output_dir
+        .ancestors()
+        .find(|dir| dir.is_dir())
+        .unwrap_or(Path::new("."));
+    if let Err(err) = check_writable(&existing_dir.join("probe")) {
+        problems.push(format!("output directory {} is not writable: {err}", output_dir.display()));
+    }
+    problems
+}
+
+/// A problem for each filter the pipeline needs more of than `limits` ever allow.
+fn exceeded_limits(needed: &FiltersConfig, limits: &FiltersConfig, scope: &str) -> Vec<String> {
+    limits
+        .filters()
+        .iter()
+        .filter(|filter| needed.limit(filter) > limits.limit(filter))
+        .map(|filter| format!(
+            "the request needs {} {filter} filter(s), but the {scope} limit is {}",
+            needed.limit(filter),
+            limits.limit(filter)
+        ))
+        .collect()
+}
+
+/// Check that a file could be written at `output`, by creating, then removing, a file
+/// next to it.
+fn check_writable(output: &Path) -> std::io::Result<()> {
+    let mut probe = output.as_os_str().to_owned();
+    probe.push(format!(".dry-run.{}", std::process::id()));
+
+    fs::File::options().write(true).create_new(true).open(&probe)?;
+    fs::remove_file(&probe)
+}
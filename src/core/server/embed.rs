@@ -0,0 +1,793 @@
+//! The server as a library, for programs that run it in-process, such as integration tests
+//! and host applications, rather than exec'ing the `sdstored` binary, see [`Server::builder`].
+
+use std::{
+    env, fs, io,
+    os::{fd::{AsRawFd, FromRawFd}, unix::net::{UnixDatagram, UnixListener}},
+    path::{Path, PathBuf},
+    sync::{mpsc as std_mpsc, Arc},
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+use tokio::{runtime::Runtime, sync::mpsc::{error::TrySendError, Sender}};
+use uuid::Uuid;
+
+use crate::core::{
+    client_task::ClientTask,
+    messaging::{ClientRequest, MessageToClient, MessageToServer},
+    monitor,
+    paths,
+    transport::{self, ConnectionListener, Credentials, Incoming, SocketNamespace, TransportMode, CONNECTION_SOCKET},
+};
+
+use super::{
+    auth,
+    config::ServerConfig,
+    daemon::{self, InstanceLock, LockError, PidFile},
+    handover::{self, Handover, InheritedFds},
+    state::{ServerError, ServerState},
+    streaming, systemd,
+};
+
+/// How long [`ServerHandle::shutdown`] waits for the server to have room for its message,
+/// when it is too busy, before trying again.
+const SHUTDOWN_RETRY_DELAY: Duration = Duration::from_millis(10);
+
+/// Errors that may happen while setting up the server, see [`ServerBuilder::build`].
+#[derive(Debug)]
+pub enum SpawnError {
+    /// The server was given no config, see [`ServerBuilder::config`].
+    MissingConfig,
+    /// Building the server's async runtime failed.
+    RuntimeError(io::Error),
+    /// Spawning the thread the server runs on failed, see [`ServerBuilder::spawn`].
+    ThreadSpawnError(io::Error),
+    /// The lock of the socket directory, at this path, could not be taken, as another server
+    /// holds it, see [`InstanceLock`].
+    Locked(PathBuf, LockError),
+    /// The socket a previous server left at this path could not be removed.
+    StaleSocket(PathBuf, io::Error),
+    /// The pid file at this path could not be written, see [`PidFile`].
+    PidFileError(PathBuf, io::Error),
+    /// The server could not run as its account, nor hand it its sockets, see
+    /// [`Account::assume`](super::privileges::Account::assume).
+    PrivilegesError(io::Error),
+    /// Binding the socket at this path failed.
+    BindError(PathBuf, io::Error),
+    /// Setting up the server's state failed.
+    ServerError(ServerError),
+    /// The server's thread panicked while the server was set up.
+    Panicked,
+}
+
+impl From<ServerError> for SpawnError {
+    fn from(err: ServerError) -> Self {
+        Self::ServerError(err)
+    }
+}
+
+/// Settings of a server to be run in-process, see [`Server::builder`].
+#[derive(Default)]
+pub struct ServerBuilder {
+    config: Option<ServerConfig>,
+    signals: bool,
+    notifier: Option<systemd::Notifier>,
+    reexec: bool,
+}
+
+impl ServerBuilder {
+    /// Run the server with `config`, which is required.
+    pub fn config(mut self, config: ServerConfig) -> Self {
+        self.config = Some(config);
+        self
+    }
+
+    /// Whether the server shuts down once the process is sent `SIGINT` or `SIGTERM`, as
+    /// `sdstored` does. Off by default, as those are the host application's to handle.
+    pub fn handle_signals(mut self, signals: bool) -> Self {
+        self.signals = signals;
+        self
+    }
+
+    /// Tell systemd through `notifier` once the server is ready, or stopping, and ping its
+    /// watchdog, see [`systemd::Notifier`].
+    pub fn notifier(mut self, notifier: Option<systemd::Notifier>) -> Self {
+        self.notifier = notifier;
+        self
+    }
+
+    /// Whether clients may have the server exec its executable afresh, e.g. once upgraded,
+    /// handing it its sockets and pending requests, see [`handover`], as `sdstored` does.
+    /// Off by default, as the executable is the host application's. A server that may be
+    /// re-exec'd takes over from the one that exec'd it, if any.
+    pub fn reexec(mut self, reexec: bool) -> Self {
+        self.reexec = reexec;
+        self
+    }
+
+    /// Set up the server, binding its sockets and starting its threads, to be run on this
+    /// thread, see [`Server::run`]. A server taking over from another inherits its sockets,
+    /// and lock, instead, see [`ServerBuilder::reexec`].
+    pub fn build(self) -> Result<Server, SpawnError> {
+        let ServerBuilder { config, signals, notifier, reexec } = self;
+        let server_config = config.ok_or(SpawnError::MissingConfig)?;
+        let inherited = reexec.then(InheritedFds::from_env).flatten();
+
+        // The server's event loop runs on a single thread, from which its sockets are read,
+        // so the runtime is only built once in the background, see `ServerState::next_message`.
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .map_err(SpawnError::RuntimeError)?;
+        let _runtime = runtime.enter();
+
+        let udsock_dir = server_config.socket_dir.clone();
+        log::info!("dir to be used for udsock is {:?}", udsock_dir);
+        let namespace = server_config.socket_namespace;
+        // Sockets left in the directory are only stale if no other server is still using them.
+        let lock = match inherited {
+            // SAFETY: the server that exec'd this one handed its lock over, to nothing else.
+            Some(inherited) => unsafe { InstanceLock::inherit(inherited.lock) },
+            None => InstanceLock::acquire(&udsock_dir)
+                .map_err(|err| SpawnError::Locked(udsock_dir.join(daemon::LOCK_FILE), err))?,
+        };
+        if inherited.is_some() {
+            log::info!("taking over from the server that exec'd this one, with its sockets");
+        }
+
+        // Init the Unix domain socket, or the one accepting clients' connections
+        let (server_udsock, (incoming, incoming_fd)) = match server_config.transport_mode {
+            TransportMode::Datagram => {
+                let server_udsock = udsock_dir.join("sdstored.sock");
+                let listener = match inherited {
+                    // SAFETY: the server that exec'd this one handed its socket over, to nothing else.
+                    Some(inherited) => Ok(unsafe { UnixDatagram::from_raw_fd(inherited.incoming) }),
+                    None => {
+                        remove_stale_socket(namespace, &server_udsock)?;
+                        namespace.bind_datagram(server_udsock.as_path())
+                    },
+                };
+                let listener = listener
+                    .and_then(|listener| transport::pass_credentials(&listener).map(|_| listener))
+                    .and_then(|listener| {
+                        let recv_buffer = transport::set_recv_buffer(&listener, server_config.recv_buffer)?;
+                        log::info!("server listening on Unix datagram socket: {:?}", listener);
+                        log::info!("socket receive buffer of {} bytes", recv_buffer);
+                        let fd = listener.as_raw_fd();
+                        Incoming::datagram(listener).map(|incoming| (incoming, fd))
+                    })
+                    .map_err(|err| SpawnError::BindError(server_udsock.clone(), err))?;
+                (server_udsock, listener)
+            },
+            TransportMode::Stream => {
+                let server_udsock = udsock_dir.join(CONNECTION_SOCKET);
+                let listener = match inherited {
+                    // SAFETY: as above.
+                    Some(inherited) => Ok(unsafe { UnixListener::from_raw_fd(inherited.incoming) }),
+                    None => {
+                        remove_stale_socket(namespace, &server_udsock)?;
+                        namespace.bind_listener(server_udsock.as_path())
+                    },
+                };
+                let (listener, fd) = listener
+                    .and_then(|listener| {
+                        let recv_buffer = transport::set_recv_buffer(&listener, server_config.recv_buffer)?;
+                        log::info!("server listening for connections on Unix stream socket: {:?}", listener);
+                        log::info!("connections' receive buffer of {} bytes", recv_buffer);
+                        let fd = listener.as_raw_fd();
+                        ConnectionListener::new(listener).map(|listener| (listener, fd))
+                    })
+                    .map_err(|err| SpawnError::BindError(server_udsock.clone(), err))?;
+                (server_udsock, (Incoming::Connections(Arc::new(listener)), fd))
+            },
+        };
+
+        // Init the Unix stream socket, for streamed tasks
+        let stream_udsock = udsock_dir.join(streaming::STREAM_SOCKET);
+        let stream_listener = match inherited {
+            // SAFETY: as above.
+            Some(inherited) => unsafe { UnixListener::from_raw_fd(inherited.stream_listener) },
+            None => {
+                remove_stale_socket(namespace, &stream_udsock)?;
+                namespace.bind_listener(stream_udsock.as_path())
+                    .map_err(|err| SpawnError::BindError(stream_udsock.clone(), err))?
+            },
+        };
+        let fds = InheritedFds { incoming: incoming_fd, stream_listener: stream_listener.as_raw_fd(), lock: lock.as_raw_fd() };
+        log::info!("server listening on Unix stream socket: {:?}", stream_listener);
+        // Written while the server may still write where it was told to, e.g. in `/run`.
+        let pid_file = server_config.pid_file.as_deref()
+            .map(|path| PidFile::create(path).map_err(|err| SpawnError::PidFileError(path.to_path_buf(), err)))
+            .transpose()?;
+
+        // No client's data is read before the server runs as its account, if given one, which
+        // is handed what it was bound, or locked, as, unless it was by the server it takes
+        // over from, already running as the account.
+        if let Some(account) = server_config.account.as_ref().filter(|_| inherited.is_none()) {
+            let mut owned = vec![udsock_dir.join(daemon::LOCK_FILE)];
+            owned.extend(server_config.pid_file.clone());
+            if namespace.has_files() {
+                owned.extend([server_udsock.clone(), stream_udsock.clone()]);
+            }
+            // The server keeps its spools in its socket dir, unless it's shared by every user.
+            if udsock_dir != Path::new(paths::SYSTEM_SOCKET_DIR) {
+                owned.push(udsock_dir.clone());
+            }
+            owned.iter().try_for_each(|path| account.own(path)).map_err(SpawnError::PrivilegesError)?;
+            account.assume().map_err(SpawnError::PrivilegesError)?;
+            log::info!("server running as {account}");
+        }
+        // Set once the sockets are bound, which clients must still be able to write to.
+        if let Some(umask) = server_config.umask {
+            // SAFETY: `umask` has no memory safety requirement, and always succeeds.
+            unsafe { libc::umask(umask) };
+        }
+
+        // Check that pipelines' pipes can be given the buffers configured, which they'd otherwise go without
+        if let Some(pipe_buffer) = server_config.pipe_buffer {
+            match io::pipe().and_then(|(_, writer)| monitor::set_pipe_buffer(&writer, pipe_buffer)) {
+                Ok(given) => log::info!("pipelines' pipes given buffers of {} bytes", given),
+                Err(err) => log::warn!("pipelines' pipes can't be given buffers of {} bytes. Error: {:?}", pipe_buffer, err),
+            }
+        }
+
+        let mut server_state = ServerState::new(incoming, udsock_dir, &server_config);
+        server_state.spawn_stream_listener("sdstored_stream_listener", stream_listener)?;
+        server_state.listen_for_workers(&server_config)?;
+        if signals {
+            server_state.listen_for_signals()?;
+        }
+        server_state.open_audit_log(&server_config)?;
+        server_state.open_result_cache(&server_config)?;
+        server_state.open_output_store(&server_config)?;
+        server_state.open_task_counter(&server_config)?;
+        server_state.load_wasm_filters(&server_config)?;
+        server_state.start_monitor_pool(&server_config)?;
+        server_state.start_worker_pool(&server_config)?;
+        if let Err(err) = server_state.resume_checkpointed(&server_config) {
+            log::error!("Could not resume interrupted tasks from their checkpoints. Error: {:?}", err);
+        }
+        if reexec {
+            match env::current_exe() {
+                Ok(executable) => server_state.enable_reexec(executable),
+                Err(err) => log::warn!("Could not tell the server's executable, which can't be re-exec'd. Error: {:?}", err),
+            }
+        }
+        if inherited.is_some() {
+            match Handover::load(&server_config.socket_dir) {
+                Ok(handover) => server_state.take_over(handover),
+                Err(err) => log::error!("Could not take over the pending tasks of the previous server. Error: {:?}", err),
+            }
+        }
+        // Abstract sockets have no files, their names being released once they're closed.
+        let sockets = [server_udsock, stream_udsock].into_iter().filter(|_| namespace.has_files()).collect();
+        Ok(Server { runtime, server_state, server_config, notifier, sockets, fds, pid_file, lock })
+    }
+
+    /// Set up the server, as [`ServerBuilder::build`] does, and run it on a thread of its
+    /// own, returning once it is ready for clients.
+    pub fn spawn(self) -> Result<ServerHandle, SpawnError> {
+        let (ready_sender, ready) = std_mpsc::channel();
+        let thread = thread::Builder::new()
+            .name(String::from("sdstored"))
+            .spawn(move || {
+                let server = match self.build() {
+                    Ok(server) => server,
+                    Err(err) => return drop(ready_sender.send(Err(err))),
+                };
+                let handle = (server.server_state.get_sender(), server.server_config.socket_dir.clone());
+                if ready_sender.send(Ok(handle)).is_ok() {
+                    server.run();
+                }
+            })
+            .map_err(SpawnError::ThreadSpawnError)?;
+        match ready.recv() {
+            Ok(Ok((sender, socket_dir))) => Ok(ServerHandle { sender, socket_dir, thread }),
+            Ok(Err(err)) => Err(err),
+            Err(_) => Err(SpawnError::Panicked),
+        }
+    }
+}
+
+/// A server set up to run in-process, see [`Server::builder`].
+pub struct Server {
+    runtime: Runtime,
+    server_state: ServerState,
+    server_config: ServerConfig,
+    notifier: Option<systemd::Notifier>,
+    /// Socket files to be removed once the server shuts down.
+    sockets: Vec<PathBuf>,
+    /// The sockets, and lock, handed over should the server be re-exec'd.
+    fds: InheritedFds,
+    /// Removed once the server shuts down.
+    pid_file: Option<PidFile>,
+    /// Released once the server shut down, and removed its sockets.
+    lock: InstanceLock,
+}
+
+impl Server {
+    /// Settings of a server to be run in-process, as in
+    /// `Server::builder().config(config).spawn()`.
+    pub fn builder() -> ServerBuilder {
+        ServerBuilder::default()
+    }
+
+    /// Serve clients until the server is told to shut down, see [`ServerHandle::shutdown`],
+    /// or sent a termination signal, if it handles them, then shut it down. A server told
+    /// to hand over execs its executable instead, once ready to, see [`ServerBuilder::reexec`],
+    /// and only returns if it couldn't, having shut down.
+    pub fn run(self) {
+        let Server { runtime, mut server_state, server_config, mut notifier, sockets, fds, pid_file, lock } = self;
+        if let Some(Err(err)) = notifier.as_ref().map(systemd::Notifier::ready) {
+            log::warn!("Could not tell systemd the server is ready. Error: {:?}", err);
+        }
+        // The loop comes around often enough for the watchdog to be pinged in time, even when idle.
+        let tick = notifier
+            .as_ref()
+            .and_then(systemd::Notifier::watchdog)
+            .map_or(server_config.retransmit_after, |watchdog| server_config.retransmit_after.min(watchdog / 4));
+
+        // Loop the processing clients' and monitors' messages.
+        runtime.block_on(async {
+            let hand_over = loop {
+                if let Some(Err(err)) = notifier.as_mut().map(systemd::Notifier::keep_alive) {
+                    log::warn!("Could not ping systemd's watchdog. Error: {:?}", err);
+                }
+                if server_state.ready_to_hand_over(server_config.shutdown_timeout) {
+                    break true;
+                }
+
+                schedule(&mut server_state, &server_config);
+                let msg = match tokio::time::timeout(tick, server_state.next_message()).await {
+                    Err(_) => continue,
+                    Ok(None) => {
+                        log::warn!("could not read from message receiver, as every sender was dropped");
+                        break false;
+                    },
+                    Ok(Some(msg)) => msg
+                };
+                if !handle_message(&mut server_state, &server_config, msg) {
+                    break false;
+                }
+            };
+
+            if hand_over {
+                if let Some(Err(err)) = notifier.as_ref().map(systemd::Notifier::reloading) {
+                    log::warn!("Could not tell systemd the server is reloading. Error: {:?}", err);
+                }
+                let executable = server_state.executable().map(Path::to_path_buf).unwrap_or_default();
+                let handover = server_state.hand_over(&server_config).await;
+                let err = match handover.save(&server_config.socket_dir) {
+                    Err(err) => err,
+                    Ok(path) => {
+                        log::info!("handing {} pending task(s) over to {:?}", handover.tasks.len(), executable);
+                        let env = notifier.as_ref().map(systemd::Notifier::env).unwrap_or_default();
+                        let err = handover::reexec(&executable, fds, env);
+                        let _ = fs::remove_file(path);
+                        err
+                    },
+                };
+                log::error!("Could not hand the server over, shutting it down instead. Error: {:?}", err);
+                server_state.abandon_handover(handover, &err);
+            }
+            if let Some(Err(err)) = notifier.as_ref().map(systemd::Notifier::stopping) {
+                log::warn!("Could not tell systemd the server is stopping. Error: {:?}", err);
+            }
+            if !hand_over {
+                server_state.shutdown(&server_config, server_config.shutdown_timeout).await;
+            }
+        });
+        for udsock in sockets {
+            if let Err(err) = fs::remove_file(&udsock) {
+                log::warn!("could not remove server udsocket {:?}: {:?}", udsock, err);
+            }
+        }
+        drop((pid_file, lock));
+        log::info!("server shut down");
+    }
+}
+
+/// Handle to a server running on a thread of its own, see [`ServerBuilder::spawn`].
+///
+/// Dropping it leaves the server running, until the process exits.
+pub struct ServerHandle {
+    sender: Sender<MessageToServer>,
+    socket_dir: PathBuf,
+    thread: JoinHandle<()>,
+}
+
+impl ServerHandle {
+    /// The directory of the server's sockets, which its clients connect to.
+    pub fn socket_dir(&self) -> &Path {
+        &self.socket_dir
+    }
+
+    /// Shut the server down, as `SIGTERM` would, waiting for it to be done: running tasks
+    /// are given the server's shutdown timeout to finish, and pending ones are failed.
+    ///
+    /// Returns an error if the server's thread panicked.
+    pub fn shutdown(self) -> thread::Result<()> {
+        let mut shutdown = MessageToServer::Shutdown(libc::SIGTERM);
+        loop {
+            match self.sender.try_send(shutdown) {
+                // The server stopped already.
+                Ok(()) | Err(TrySendError::Closed(_)) => break,
+                Err(TrySendError::Full(msg)) => {
+                    shutdown = msg;
+                    thread::sleep(SHUTDOWN_RETRY_DELAY);
+                },
+            }
+        }
+        self.thread.join()
+    }
+}
+
+/// Start the pending tasks that can be, by the server, then by its workers, and see to
+/// the server's periodic work: resending unacknowledged notifications, pinging workers,
+/// reconciling filter counts and cancelling overdue tasks. Done before every message is
+/// waited for, see [`Server::run`].
+pub(crate) fn schedule(server_state: &mut ServerState, server_config: &ServerConfig) {
+    // Tasks resumed from their checkpoints are pending from the start.
+    while let Some(task) = server_state.try_pop_task(server_config) {
+        let client_pid = task.client_pid;
+        log::info!("Executing task popped from pqueue:\n{:?}", task);
+        match server_state.process_task(server_config, task) {
+            // Which the server recovers from, having dropped the task.
+            Err(ServerError::ClientGone(_)) => log::info!("Task by client PID {client_pid} dropped, its client gone"),
+            Err(err) => log::error!("Failed to process task by client PID {client_pid}: {:?}", err),
+            Ok(task_num) => log::info!("Task by client {client_pid} assigned number {task_num}")
+        }
+    }
+    // Then those the server can't run itself are handed to its workers, if any.
+    while let Some((worker_id, task)) = server_state.try_pop_remote_task() {
+        let client_pid = task.client_pid;
+        match server_state.dispatch_task(worker_id, task) {
+            Err(ServerError::ClientGone(_)) => log::info!("Task by client PID {client_pid} dropped, its client gone"),
+            Err(err) => log::error!("Failed to hand task by client PID {client_pid} to worker #{worker_id}: {:?}", err),
+            Ok(task_num) => log::info!("Task by client {client_pid} assigned number {task_num}, on worker #{worker_id}")
+        }
+    }
+
+    server_state.retransmit_unacked();
+    server_state.heartbeat_workers();
+    server_state.reconcile_filters();
+    if let Some(timeout) = server_config.task_timeout {
+        server_state.cancel_overdue(timeout);
+    }
+}
+
+/// Handle `msg`, the next message of the server's event loop, see [`Server::run`],
+/// returning whether the server is to go on, rather than shut down.
+pub(crate) fn handle_message(server_state: &mut ServerState, server_config: &ServerConfig, msg: MessageToServer) -> bool {
+    let Some(msg) = authenticate(server_state, server_config, msg) else { return true };
+    match msg {
+        MessageToServer::Client(ClientRequest::Ack(client_pid, request_id, seq), ..) =>
+            server_state.acknowledge(client_pid, request_id, seq),
+        MessageToServer::Client(ClientRequest::Connect(client_pid), peer, _) => {
+            log::info!("client PID {client_pid} connected as {:?}", peer);
+            server_state.register_peer(client_pid, peer);
+        }
+        MessageToServer::Client(ClientRequest::Status(client_pid, request_id), peer, _) => {
+            log::info!("status request {request_id} by client PID {client_pid}");
+            server_state.register_peer(client_pid, peer);
+            match server_state.send_status(server_config, client_pid, request_id) {
+                Err(err) =>
+                    log::warn!("failed to serve status request by client PID {client_pid} with error {:?}", err),
+                _ => log::trace!("served status request to client PID {client_pid}"),
+            };
+        }
+        MessageToServer::Client(ClientRequest::Ping(client_pid, request_id), peer, _) => {
+            log::trace!("ping by client PID {client_pid}");
+            server_state.register_peer(client_pid, peer);
+            if let Err(err) = server_state.send_pong(client_pid, request_id) {
+                log::warn!("failed to answer ping by client PID {client_pid} with error {:?}", err);
+            }
+        }
+        MessageToServer::Client(ClientRequest::Health(client_pid, request_id), peer, _) => {
+            log::trace!("health check by client PID {client_pid}");
+            server_state.register_peer(client_pid, peer);
+            if let Err(err) = server_state.send_health(server_config, client_pid, request_id) {
+                log::warn!("failed to answer health check by client PID {client_pid} with error {:?}", err);
+            }
+        }
+        MessageToServer::Client(ClientRequest::ServerInfo(client_pid, request_id), peer, _) => {
+            log::info!("server info request by client PID {client_pid}");
+            server_state.register_peer(client_pid, peer);
+            if let Err(err) = server_state.send_server_info(server_config, client_pid, request_id) {
+                log::warn!("failed to send server info to client PID {client_pid} with error {:?}", err);
+            }
+        }
+        MessageToServer::Client(ClientRequest::Report(client_pid, request_id, window), peer, credentials) => {
+            log::info!("report request {request_id} by client PID {client_pid}");
+            server_state.register_peer(client_pid, peer);
+            if !authorize_admin(server_state, client_pid, request_id, credentials) {
+                return true
+            }
+            if let Err(err) = server_state.send_report(client_pid, request_id, window) {
+                log::warn!("failed to send report to client PID {client_pid} with error {:?}", err);
+            }
+        }
+        MessageToServer::Client(ClientRequest::Reexec(client_pid, request_id), peer, credentials) => {
+            log::info!("re-exec request {request_id} by client PID {client_pid}");
+            server_state.register_peer(client_pid, peer);
+            if !authorize_admin(server_state, client_pid, request_id, credentials) {
+                return true
+            }
+            if let Err(err) = server_state.start_handover(client_pid, request_id) {
+                log::warn!("failed to answer re-exec request by client PID {client_pid} with error {:?}", err);
+            }
+        }
+        MessageToServer::Client(ClientRequest::History(client_pid, request_id), peer, _) => {
+            log::info!("history request {request_id} by client PID {client_pid}");
+            server_state.register_peer(client_pid, peer);
+            if let Err(err) = server_state.send_history(client_pid, request_id) {
+                log::warn!("failed to serve history request by client PID {client_pid} with error {:?}", err);
+            }
+        }
+        MessageToServer::Client(ClientRequest::Query(client_pid, request_id, queried), peer, _) => {
+            log::info!("query request {request_id} about {queried} by client PID {client_pid}");
+            server_state.register_peer(client_pid, peer);
+            if let Err(err) = server_state.send_request_state(client_pid, request_id, queried) {
+                log::warn!("failed to serve query request by client PID {client_pid} with error {:?}", err);
+            }
+        }
+        MessageToServer::Client(ClientRequest::Logs(client_pid, request_id, logged), peer, _) => {
+            log::info!("logs request {request_id} about request {logged} by client PID {client_pid}");
+            server_state.register_peer(client_pid, peer);
+            if let Err(err) = server_state.send_task_log(client_pid, request_id, logged) {
+                log::warn!("failed to serve logs request by client PID {client_pid} with error {:?}", err);
+            }
+        }
+        MessageToServer::Client(ClientRequest::Wait(client_pid, request_id, awaited), peer, _) => {
+            log::info!("client PID {client_pid} waiting for request {awaited}");
+            server_state.register_peer(client_pid, peer);
+            if let Err(err) = server_state.wait_for(client_pid, request_id, awaited) {
+                log::warn!("failed to serve wait request by client PID {client_pid} with error {:?}", err);
+            }
+        }
+        MessageToServer::Client(ClientRequest::Subscribe(client_pid, request_id), peer, _) => {
+            log::info!("client PID {client_pid} subscribed to task events");
+            server_state.register_peer(client_pid, peer);
+            server_state.subscribe(client_pid, request_id);
+        }
+        MessageToServer::Client(ClientRequest::Unsubscribe(client_pid), ..) => {
+            log::info!("client PID {client_pid} unsubscribed from task events");
+            if let Err(err) = server_state.unsubscribe(client_pid) {
+                log::warn!("failed to tell client PID {client_pid} it unsubscribed: {:?}", err);
+            }
+        }
+        MessageToServer::Client(ClientRequest::Suspend(client_pid, request_id, target), peer, credentials) => {
+            log::info!("suspend request {request_id} of {target} by client PID {client_pid}");
+            server_state.register_peer(client_pid, peer);
+            if let Err(err) = server_state.suspend(client_pid, request_id, target, credentials) {
+                log::warn!("failed to serve suspend request by client PID {client_pid} with error {:?}", err);
+            }
+        }
+        MessageToServer::Client(ClientRequest::Resume(client_pid, request_id, target), peer, credentials) => {
+            log::info!("resume request {request_id} of {target} by client PID {client_pid}");
+            server_state.register_peer(client_pid, peer);
+            if let Err(err) = server_state.resume(client_pid, request_id, target, credentials) {
+                log::warn!("failed to serve resume request by client PID {client_pid} with error {:?}", err);
+            }
+        }
+        MessageToServer::Client(ClientRequest::Cancel(client_pid, request_id), _, credentials) => {
+            log::info!("client PID {client_pid} cancelled request {request_id}");
+            if let Err(err) = server_state.cancel(client_pid, request_id, credentials) {
+                log::warn!("failed to cancel request {request_id} by client PID {client_pid}: {:?}", err);
+            }
+        }
+        MessageToServer::Client(ClientRequest::ProcFile(mut task), peer, credentials) => {
+            task.client_uid = credentials.map(|credentials| credentials.uid);
+            task.client_gid = credentials.map(|credentials| credentials.gid);
+            server_state.register_peer(task.client_pid, peer);
+            // Streamed tasks are only ever received over the stream socket, see `Streamed`.
+            match task.stream {
+                true => server_state.refuse_streamed(*task),
+                false => handle_proc_file(server_state, server_config, *task),
+            }
+        }
+        MessageToServer::Streamed(task, stream) => {
+            log::info!("received input of streamed task by client PID {}", task.client_pid);
+            server_state.add_stream(task.client_pid, stream);
+            handle_proc_file(server_state, server_config, task);
+        }
+        MessageToServer::Monitor(res) => {
+            let task_num = res.task_number;
+            let cl_pid = match server_state.client_pid_from_monitor_id(task_num) {
+                None => {
+                    log::error!("message received from nonexistent monitor!");
+                    return false;
+                }
+                Some(t) => t
+            };
+            match server_state.handle_task_result(res) {
+                Err(err) => log::error!("Monitor of task #{task_num} by client {cl_pid} failed: {:?}", err),
+                Ok(_)  => log::info!("Monitor of task #{task_num} by client {cl_pid} succeeded.")
+            }
+        }
+        MessageToServer::Unreadable(peer, credentials, failure) =>
+            server_state.reject_unreadable(peer, credentials, failure),
+        MessageToServer::BatchFile(file_result) => {
+            if let Err(err) = server_state.handle_batch_file(file_result) {
+                log::warn!("failed to relay batch file result to its client: {:?}", err);
+            }
+        }
+        MessageToServer::Progress(progress) => {
+            if let Err(err) = server_state.handle_task_progress(progress) {
+                log::warn!("failed to relay task progress to its client: {:?}", err);
+            }
+        }
+        MessageToServer::Worker(event) => {
+            if let Err(err) = server_state.handle_worker_event(event) {
+                log::warn!("failed to relay the progress or result of a task run by a worker: {:?}", err);
+            }
+        }
+        MessageToServer::Shutdown(signal) => {
+            log::info!("received signal {signal}, shutting down");
+            return false;
+        }
+    }
+    true
+}
+
+/// Remove the socket file a previous server may have left at `path`, if sockets are files
+/// in `namespace`.
+fn remove_stale_socket(namespace: SocketNamespace, path: &Path) -> Result<(), SpawnError> {
+    if !namespace.has_files() {
+        return Ok(())
+    }
+    match fs::remove_file(path) {
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+        Err(err) => Err(SpawnError::StaleSocket(path.to_path_buf(), err)),
+        Ok(_) => Ok(()),
+    }
+}
+
+/// Authenticate the client that sent `msg`, if it is a request, see [`auth::authenticate`],
+/// returning it, with the client's actual PID, unless the client was refused, in which case
+/// it is told so.
+fn authenticate(
+    server_state: &mut ServerState,
+    server_config: &ServerConfig,
+    msg: MessageToServer
+) -> Option<MessageToServer> {
+    let allowed_uids = server_config.allowed_uids.as_deref();
+    let (client_pid, request_id, err) = match msg {
+        MessageToServer::Client(mut request, peer, credentials) =>
+            match auth::authenticate(request.client_pid_mut(), credentials, allowed_uids) {
+                Ok(()) => return Some(MessageToServer::Client(request, peer, credentials)),
+                Err(err) => {
+                    log::warn!("refused request {:?} from {:?} with credentials {:?}: {err}", request, peer, credentials);
+                    let client_pid = *request.client_pid_mut();
+                    server_state.register_peer(client_pid, peer);
+                    match request.request_id() {
+                        Some(request_id) => (client_pid, request_id, err),
+                        // Requests sent on the client's own aren't replied to.
+                        None => return None,
+                    }
+                },
+            },
+        MessageToServer::Streamed(mut task, stream) => {
+            let credentials = transport::peer_credentials(&stream).ok();
+            match auth::authenticate(&mut task.client_pid, credentials, allowed_uids) {
+                Ok(()) => {
+                    task.client_uid = credentials.map(|credentials| credentials.uid);
+                    task.client_gid = credentials.map(|credentials| credentials.gid);
+                    return Some(MessageToServer::Streamed(task, stream))
+                },
+                Err(err) => {
+                    log::warn!("refused streamed task {:?} with credentials {:?}: {err}", task, credentials);
+                    (task.client_pid, task.request_id, err)
+                },
+            }
+        },
+        msg => return Some(msg),
+    };
+
+    let refused = MessageToClient::Refused(err.to_string());
+    if let Err(err) = server_state.send_msg_to_client(client_pid, request_id, &refused) {
+        log::warn!("failed to tell client PID {client_pid} its request was refused: {:?}", err);
+    }
+    None
+}
+
+/// Check that the client with `credentials`, and `client_pid`, may make administrative
+/// requests, see [`auth::authorize_admin`], telling it its request `request_id` was refused
+/// if it may not.
+fn authorize_admin(
+    server_state: &mut ServerState,
+    client_pid: u32,
+    request_id: Uuid,
+    credentials: Option<Credentials>
+) -> bool {
+    // SAFETY: the call has no memory safety requirement.
+    let Err(err) = auth::authorize_admin(credentials, unsafe { libc::geteuid() }) else { return true };
+    log::warn!("refused request {request_id} by client PID {client_pid}: {err}");
+    if let Err(err) = server_state.send_msg_to_client(client_pid, request_id, &MessageToClient::Refused(err.to_string())) {
+        log::warn!("failed to tell client PID {client_pid} its request was refused: {:?}", err);
+    }
+    false
+}
+
+/// Optimize a received `proc-file` task's pipeline, if the server is configured to, and
+/// fit its chunks to the server's limits, then either queue it, or only validate it if it
+/// is a dry run. Tasks duplicating an earlier one follow it instead, see
+/// [`ServerState::deduplicate`], and those sent inline have their input spooled first, see
+/// [`ServerState::spool_inline`]. Tasks of clients past the rate limit are rejected first, see
+/// [`ServerState::rate_limit`].
+fn handle_proc_file(server_state: &mut ServerState, server_config: &ServerConfig, mut task: ClientTask) {
+    let client_pid = task.client_pid;
+    if !server_state.rate_limit(&task) || !server_state.spool_inline(&mut task) {
+        return
+    }
+    match server_state.deduplicate(&task) {
+        Ok(false) => {},
+        Ok(true) => return,
+        Err(err) => return log::warn!("failed to tell client PID {client_pid} its request is a duplicate: {:?}", err),
+    }
+    if server_config.optimize_pipelines {
+        if let Err(err) = server_state.optimize_task(server_config, &mut task) {
+            log::warn!("failed to report optimized pipeline to client PID {client_pid}: {:?}", err);
+        }
+    }
+    server_state.fit_chunks(server_config, &mut task);
+    server_state.stage_in_store(&mut task);
+    task.mode = task.mode.or(server_config.output_mode);
+
+    if task.dry_run {
+        log::info!("dry run of task by client PID {client_pid}:\n{:?}", task);
+        if let Err(err) = server_state.dry_run_task(server_config, &task) {
+            log::warn!("failed to serve dry run by client PID {client_pid}: {:?}", err);
+        }
+        if let Err(err) = server_state.finish_stream(&task, false) {
+            log::warn!("failed to disconnect streaming client PID {client_pid}: {:?}", err);
+        }
+    } else {
+        log::info!("Attempting to queueing received task:\n{:?}", task);
+        match server_state.new_task(task) {
+            Ok(_) => log::info!("Successfully queued task by client PID {client_pid}"),
+            Err(ServerError::ClientGone(_)) => log::info!("Task by client PID {client_pid} dropped, its client gone"),
+            Err(err) => log::error!("Failed to queue task by client PID {client_pid}: {:?}", err),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        client_api::SdstoreClient,
+        core::{filter::Filter, server::cli::{ServerCli, ServerEnv}, testing::TempDir},
+    };
+
+    use super::*;
+
+    #[test]
+    fn spawned_servers_run_tasks_until_shut_down() {
+        let dir = TempDir::new("embed");
+        fs::write(dir.join("limits.txt"), "nop 1\nbuiltin nop").unwrap();
+        fs::write(dir.join("in"), "data").unwrap();
+        let env = ServerEnv {
+            limits_file: Some(dir.join("limits.txt")),
+            transformations_dir: Some(dir.to_path_buf()),
+            socket_dir: Some(dir.to_path_buf()),
+            ..Default::default()
+        };
+        let config = ServerConfig::build(&ServerCli::default(), &env).unwrap();
+        let (transport_mode, codec) = (config.transport_mode, config.wire_format);
+        assert!(matches!(Server::builder().spawn(), Err(SpawnError::MissingConfig)));
+
+        let server = Server::builder().config(config).spawn().unwrap();
+        let mut client = SdstoreClient::connect(server.socket_dir(), transport_mode, codec).unwrap();
+        let info = client.server_info().unwrap();
+        assert_eq!((info.filters.len(), info.filters[0].limit, info.filters[0].runs.as_str()), (1, 1, "builtin"));
+        let task = ClientTask::new(0, 1, dir.join("in"), dir.join("out"), vec![Filter::Nop]);
+        let handle = client.submit(task).unwrap();
+        assert!(matches!(client.wait(&handle), Ok(MessageToClient::Concluded(_))));
+        assert_eq!(fs::read_to_string(dir.join("out")).unwrap(), "data");
+        drop(client);
+
+        server.shutdown().unwrap();
+        assert!(!dir.join("sdstored.sock").exists());
+    }
+}
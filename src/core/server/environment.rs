@@ -0,0 +1,104 @@
+//! Environment the processes of external filters run in: variables set for them, by the
+//! server or by each task, from those the server allows, and the directory they run in,
+//! see [`FilterEnvironment`].
+
+use std::{collections::{BTreeMap, BTreeSet}, path::PathBuf, process::Command};
+
+use crate::core::client_task::ClientTask;
+
+/// Environment of the processes of external filters, as the config file's `[environment]`
+/// table sets it, on top of the server's own.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FilterEnvironment {
+    /// Variables set for every filter, e.g. `LANG` or `TMPDIR`.
+    pub vars: BTreeMap<String, String>,
+    /// Directory filters run in. `None` for the server's working directory.
+    pub cwd: Option<PathBuf>,
+    /// Names of the variables tasks may set for their own filters, see [`ClientTask::env`].
+    /// Tasks may set none if it's empty.
+    pub allowed: BTreeSet<String>,
+}
+
+impl FilterEnvironment {
+    /// Whether filters run in an environment of their own, or tasks may give them one.
+    pub fn is_enabled(&self) -> bool {
+        *self != FilterEnvironment::default()
+    }
+
+    /// The first variable `task` sets which tasks aren't allowed to, if any.
+    pub fn denied<'a>(&self, task: &'a ClientTask) -> Option<&'a str> {
+        task.env.keys().map(String::as_str).find(|name| !self.allowed.contains(*name))
+    }
+
+    /// The environment every filter runs in, whatever its task.
+    pub fn server_wide(&self) -> StageEnvironment {
+        StageEnvironment { vars: self.vars.clone(), cwd: self.cwd.clone() }
+    }
+
+    /// The environment the filters of `task` run in: the server's variables, along with the
+    /// task's, which override them, in the task's working directory, if it has one, or else
+    /// in the server's.
+    pub fn for_task(&self, task: &ClientTask) -> StageEnvironment {
+        let mut environment = self.server_wide();
+        environment.vars.extend(task.env.iter().map(|(name, value)| (name.clone(), value.clone())));
+        if let Some(cwd) = &task.cwd {
+            environment.cwd = Some(cwd.clone());
+        }
+        environment
+    }
+}
+
+/// Whether `name` may name an environment variable: it isn't empty, and has neither `=`
+/// nor a NUL byte.
+pub fn is_var_name(name: &str) -> bool {
+    !name.is_empty() && !name.contains(['=', '\0'])
+}
+
+/// Environment the external stages of a pipeline run in, see [`FilterEnvironment::for_task`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct StageEnvironment {
+    /// Variables set on top of the server's own environment.
+    pub vars: BTreeMap<String, String>,
+    /// Directory the stages run in. `None` for the server's working directory.
+    pub cwd: Option<PathBuf>,
+}
+
+impl StageEnvironment {
+    /// Have `command` run with the variables set, in the working directory, if any.
+    pub fn apply(&self, command: &mut Command) {
+        command.envs(&self.vars);
+        if let Some(cwd) = &self.cwd {
+            command.current_dir(cwd);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::core::filter::Filter;
+
+    use super::*;
+
+    #[test]
+    fn tasks_override_the_server_environment_with_allowed_vars() {
+        let environment = FilterEnvironment {
+            vars: BTreeMap::from([("LANG".into(), "C".into()), ("TMPDIR".into(), "/var/tmp".into())]),
+            cwd: Some(PathBuf::from("/srv")),
+            allowed: BTreeSet::from(["LANG".into()]),
+        };
+        let mut task = ClientTask::new(0, 0, "in".into(), "out".into(), vec![Filter::Nop]);
+        assert_eq!(environment.for_task(&task), environment.server_wide());
+
+        task.env.insert("LANG".into(), "C.UTF-8".into());
+        task.cwd = Some(PathBuf::from("/tmp"));
+        assert_eq!(environment.denied(&task), None);
+        let stage = environment.for_task(&task);
+        assert_eq!(stage.vars["LANG"], "C.UTF-8");
+        assert_eq!(stage.vars["TMPDIR"], "/var/tmp");
+        assert_eq!(stage.cwd.as_deref(), Some(std::path::Path::new("/tmp")));
+
+        task.env.insert("LD_PRELOAD".into(), "evil.so".into());
+        assert_eq!(environment.denied(&task), Some("LD_PRELOAD"));
+        assert!(is_var_name("TMPDIR") && !is_var_name("A=B") && !is_var_name(""));
+    }
+}
@@ -0,0 +1,167 @@
+//! Handing a running server over to a freshly exec'd one, e.g. of an upgraded `sdstored`,
+//! with its bound sockets and pending requests, so that no request is dropped as it
+//! restarts, see [`Handover`].
+
+use std::{
+    collections::HashMap, env, ffi::OsString, fs, io,
+    os::{fd::RawFd, unix::process::CommandExt},
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use serde::{Serialize, Deserialize};
+use uuid::Uuid;
+
+use crate::core::{client_task::ClientTask, transport::Peer};
+
+/// Environment variable telling an exec'd server that it takes over from the one that
+/// exec'd it, and the FDs it inherited from it, see [`InheritedFds`].
+pub const HANDOVER_VAR: &str = "SDSTORED_HANDOVER";
+
+/// Name of the file, in the server's socket dir, the server handing over saves its
+/// [`Handover`] to, for the one taking over to load.
+pub const HANDOVER_FILE: &str = "sdstored_handover";
+
+/// The sockets, and lock, of the server handing over, which the one taking over inherits,
+/// rather than bind, or take, its own, and which are never closed in between.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InheritedFds {
+    /// The socket requests are read from: the datagram socket, or the one accepting
+    /// clients' connections.
+    pub incoming: RawFd,
+    /// The socket accepting streamed tasks.
+    pub stream_listener: RawFd,
+    /// The lock of the socket dir, see [`InstanceLock`](super::daemon::InstanceLock).
+    pub lock: RawFd,
+}
+
+impl InheritedFds {
+    /// The FDs the server was exec'd with, if it takes over from another, as
+    /// [`HANDOVER_VAR`] tells. The variable is removed from the environment, so that the
+    /// filters the server runs don't see it. Must be called before any thread is spawned.
+    pub fn from_env() -> Option<Self> {
+        let var = env::var(HANDOVER_VAR).ok()?;
+        env::remove_var(HANDOVER_VAR);
+        match var.split(',').map(str::parse).collect::<Result<Vec<_>, _>>().as_deref() {
+            Ok(&[incoming, stream_listener, lock]) => Some(InheritedFds { incoming, stream_listener, lock }),
+            _ => {
+                log::error!("ignoring malformed {HANDOVER_VAR}={var:?}");
+                None
+            },
+        }
+    }
+
+    /// Whether the server takes over from another, without taking the FDs from the
+    /// environment, e.g. so as not to run in the background, which it runs in already.
+    pub fn in_env() -> bool {
+        env::var_os(HANDOVER_VAR).is_some()
+    }
+
+    fn to_var(self) -> String {
+        format!("{},{},{}", self.incoming, self.stream_listener, self.lock)
+    }
+}
+
+/// What a server hands over to the one that takes over from it: its pending requests, and
+/// what it takes to go on notifying their clients, as if the server hadn't restarted.
+///
+/// Running requests aren't handed over, but waited for, see
+/// [`ServerState::hand_over`](super::state::ServerState::hand_over).
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Handover {
+    /// Pending requests, in the order they were to be popped from each queue, each with the
+    /// UID and GID of its client's user, if known, which tasks themselves don't serialize,
+    /// see [`ClientTask::client_uid`].
+    pub tasks: Vec<(ClientTask, Option<(u32, u32)>)>,
+    /// Clients waiting for each request to conclude, by its ID, each by PID, with the ID of
+    /// its own request.
+    pub waiters: HashMap<Uuid, Vec<(u32, Uuid)>>,
+    /// Whom each of these clients, by PID, made its request from.
+    pub peers: HashMap<u32, Peer>,
+    /// Number of the next notification to each of these clients about each request.
+    pub next_seq: HashMap<(u32, Uuid), u64>,
+    /// Notifications sent to these clients that they are yet to acknowledge, by client,
+    /// request and number, to be sent again.
+    pub unacked: Vec<((u32, Uuid, u64), Vec<u8>)>,
+    /// The client that asked for the handover, by PID, with the ID of its request, which is
+    /// told once the server took over.
+    pub requested_by: Option<(u32, Uuid)>,
+}
+
+impl Handover {
+    /// Save the handover in `socket_dir`, for the server taking over to load.
+    pub fn save(&self, socket_dir: &Path) -> io::Result<PathBuf> {
+        let bytes = bincode::serialize(self)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+        let path = socket_dir.join(HANDOVER_FILE);
+        fs::write(&path, bytes)?;
+        Ok(path)
+    }
+
+    /// Load the handover saved in `socket_dir`, removing it, so that it is only taken over
+    /// from once.
+    pub fn load(socket_dir: &Path) -> io::Result<Self> {
+        let path = socket_dir.join(HANDOVER_FILE);
+        let loaded = fs::read(&path).and_then(|bytes| {
+            bincode::deserialize(&bytes).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+        });
+        fs::remove_file(&path)?;
+        loaded
+    }
+}
+
+/// Replace the server's process with `executable`, run with the same arguments, and the
+/// variables `env` added to its environment, which takes over from it with the `inherited`
+/// FDs, and the handover it saved.
+///
+/// Only returns should the exec fail, with the reason.
+pub fn reexec(executable: &Path, inherited: InheritedFds, env: Vec<(&str, OsString)>) -> io::Error {
+    for fd in [inherited.incoming, inherited.stream_listener, inherited.lock] {
+        // SAFETY: the call has no memory safety requirement, on an FD the server owns.
+        if unsafe { libc::fcntl(fd, libc::F_SETFD, 0) } == -1 {
+            return io::Error::last_os_error()
+        }
+    }
+    log::logger().flush();
+    Command::new(executable)
+        .args(env::args_os().skip(1))
+        .envs(env)
+        .env(HANDOVER_VAR, inherited.to_var())
+        .exec()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use crate::core::{filter::Filter, testing::TempDir};
+
+    use super::*;
+
+    #[test]
+    fn handovers_are_loaded_once_as_saved() {
+        let dir = TempDir::new("handover");
+        let (client_pid, request_id) = (42, Uuid::new_v4());
+        let mut task = ClientTask::new(client_pid, 3, PathBuf::from("in"), PathBuf::from("out"), vec![Filter::Nop]);
+        (task.request_id, task.client_uid, task.client_gid) = (request_id, Some(1000), Some(100));
+        let handover = Handover {
+            tasks: vec![(task.clone(), Some((1000, 100)))],
+            waiters: HashMap::from([(request_id, vec![(7, Uuid::new_v4())])]),
+            peers: HashMap::from([(client_pid, Peer::Path(PathBuf::from("sdstore_42.sock")))]),
+            next_seq: HashMap::from([((client_pid, request_id), 2)]),
+            unacked: vec![((client_pid, request_id, 1), b"queued".to_vec())],
+            requested_by: Some((1, Uuid::new_v4())),
+        };
+        handover.save(&dir).unwrap();
+
+        let loaded = Handover::load(&dir).unwrap();
+        let [(mut loaded_task, credentials)] = <[_; 1]>::try_from(loaded.tasks).unwrap();
+        // Credentials are carried alongside tasks, which skip them as they're serialized.
+        assert_eq!((loaded_task.client_uid, credentials), (None, Some((1000, 100))));
+        (loaded_task.client_uid, loaded_task.client_gid) = credentials.unzip();
+        assert_eq!(loaded_task, task);
+        assert_eq!((loaded.waiters, loaded.peers, loaded.next_seq), (handover.waiters, handover.peers, handover.next_seq));
+        assert_eq!((loaded.unacked, loaded.requested_by), (handover.unacked, handover.requested_by));
+        assert_eq!(Handover::load(&dir).unwrap_err().kind(), io::ErrorKind::NotFound);
+    }
+}
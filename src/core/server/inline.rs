@@ -0,0 +1,81 @@
+//! Tasks sent inline, whose input is in their request, and output in a reply to their client,
+//! so that small files needn't be on a filesystem the server shares, see [`ClientTask::inline`].
+//!
+//! Their input is spooled next to the server's socket before they are queued, and they run
+//! as any other task would, on the server's copies of their files.
+
+use std::{fs, io::{self, Read}, mem, path::{Path, PathBuf}};
+
+use uuid::Uuid;
+
+use crate::core::{client_task::ClientTask, messaging::RequestFailure};
+
+use super::streaming;
+
+/// Where the server keeps its copies of the input and output of the inline task submitted
+/// in the request `request_id`.
+pub fn inline_paths(spool_dir: &Path, request_id: Uuid) -> (PathBuf, PathBuf) {
+    (
+        spool_dir.join(format!("sdstored_inline_{request_id}.in")),
+        spool_dir.join(format!("sdstored_inline_{request_id}.out")),
+    )
+}
+
+/// Spool the input of the inline `task`, taking it out of the task, to the file given by
+/// [`inline_paths`], and have the task read it, and write its output next to it.
+pub fn spool_input(spool_dir: &Path, task: &mut ClientTask) -> io::Result<()> {
+    let (input, output) = inline_paths(spool_dir, task.request_id);
+    fs::write(&input, task.inline.as_mut().map(mem::take).unwrap_or_default())?;
+    task.relocate(input, output);
+    task.spooled = true;
+    Ok(())
+}
+
+/// The output of the inline `task`, which it fails with if it is longer than `max` bytes, as
+/// the server doesn't send more inline, or can't be read.
+pub fn read_output(task: &ClientTask, max: usize) -> Result<Vec<u8>, RequestFailure> {
+    let unreadable = |err: io::Error| RequestFailure::Internal(format!("could not read the output to send it inline: {err}"));
+    let output = fs::File::open(task.output_filepath()).map_err(unreadable)?;
+    let len = output.metadata().map_err(unreadable)?.len() as usize;
+    if len > max {
+        return Err(RequestFailure::InlineTooLarge { len, max })
+    }
+    let mut bytes = Vec::with_capacity(len);
+    output.take(max as u64).read_to_end(&mut bytes).map_err(unreadable)?;
+    Ok(bytes)
+}
+
+/// Remove the server's copies of the files of the inline task submitted in the request
+/// `request_id`, whether it ran or not.
+pub fn remove_spooled(spool_dir: &Path, request_id: Uuid) {
+    let (input, output) = inline_paths(spool_dir, request_id);
+    streaming::remove_spooled(&input);
+    streaming::remove_spooled(&output);
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::core::{filter::Filter, testing::TempDir};
+
+    use super::*;
+
+    #[test]
+    fn inline_files_are_spooled_and_removed() {
+        let dir = TempDir::new("inline");
+        let mut task = ClientTask::new(1, 1, PathBuf::from("in"), PathBuf::from("out"), vec![Filter::Nop]);
+        (task.request_id, task.inline) = (Uuid::new_v4(), Some(b"data".as_slice().into()));
+
+        spool_input(&dir, &mut task).unwrap();
+        let (input, output) = inline_paths(&dir, task.request_id);
+        assert_eq!((task.input_filepath(), task.output_filepath()), (input.as_path(), output.as_path()));
+        assert_eq!(task.inline.as_deref(), Some([].as_slice()));
+        assert_eq!(fs::read(&input).unwrap(), b"data");
+
+        fs::write(&output, b"output").unwrap();
+        assert_eq!(read_output(&task, 6).unwrap(), b"output");
+        assert_eq!(read_output(&task, 5), Err(RequestFailure::InlineTooLarge { len: 6, max: 5 }));
+
+        remove_spooled(&dir, task.request_id);
+        assert!(!input.exists() && !output.exists());
+    }
+}
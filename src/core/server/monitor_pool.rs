@@ -0,0 +1,152 @@
+use std::{
+    io, panic::{self, AssertUnwindSafe},
+    sync::{mpsc::{self, Receiver, Sender}, Arc, Mutex, PoisonError},
+    thread::{self, JoinHandle},
+};
+
+/// Work sent to the threads of a [`MonitorPool`]: running a task's monitor to completion.
+type Job = Box<dyn FnOnce() + Send>;
+
+/// Fixed set of threads that [`Monitor`](crate::core::monitor::Monitor)s run on, each
+/// taking the next monitor to run as soon as it's done with its last.
+///
+/// The server runs no more tasks at once than the pool has threads, see
+/// [`ServerConfig::monitor_threads`](super::config::ServerConfig::monitor_threads), so
+/// monitors don't wait for a thread in practice, and no thread is spawned per task.
+///
+/// Pipelines aren't spawned with `tokio::process` on the server's event loop: monitors
+/// block on their stages, which are wired up with pipes, and sandboxed, in their own process
+/// groups, through which the event loop kills them, see
+/// [`Monitor::kill`](crate::core::monitor::Monitor::kill).
+pub struct MonitorPool {
+    /// Sends jobs to the threads, until dropped, after which they exit.
+    jobs: Option<Sender<Job>>,
+    /// Shared by the threads, to take jobs from. `None` if the pool never runs them.
+    receiver: Option<Arc<Mutex<Receiver<Job>>>>,
+    threads: Vec<JoinHandle<()>>,
+    /// Number of monitors that may run at once.
+    size: usize,
+    /// Kept for the jobs of a pool that never runs them to be sent, see [`MonitorPool::held`].
+    #[cfg(test)]
+    _held: Option<Receiver<Job>>,
+}
+
+impl MonitorPool {
+    /// Spawn a pool of `size` threads, at least one.
+    pub fn new(size: usize) -> io::Result<Self> {
+        let (jobs, receiver) = mpsc::channel::<Job>();
+        let receiver = Arc::new(Mutex::new(receiver));
+
+        let mut pool = MonitorPool {
+            jobs: Some(jobs),
+            receiver: Some(receiver),
+            threads: Vec::new(),
+            size: size.max(1),
+            #[cfg(test)]
+            _held: None,
+        };
+        pool.reserve(0)?;
+        Ok(pool)
+    }
+
+    /// Spawn threads until the pool has `extra` more than its size, for as many monitors to
+    /// hold theirs without holding up others, as suspended ones do, see
+    /// [`Monitor::suspend`](crate::core::monitor::Monitor::suspend). Threads are kept once
+    /// spawned.
+    pub fn reserve(&mut self, extra: usize) -> io::Result<()> {
+        let Some(receiver) = &self.receiver else {
+            return Ok(())
+        };
+        while self.threads.len() < self.size + extra {
+            let receiver = Arc::clone(receiver);
+            let thread = thread::Builder::new()
+                .name(format!("sdstored_monitor_{}", self.threads.len()))
+                .spawn(move || run_jobs(&receiver))?;
+            self.threads.push(thread);
+        }
+        Ok(())
+    }
+
+    /// A pool of `size` monitors, whose jobs are kept rather than run, so that tasks are
+    /// started without running, their results being up to tests, see
+    /// [`testing`](super::testing).
+    #[cfg(test)]
+    pub fn held(size: usize) -> Self {
+        let (jobs, receiver) = mpsc::channel::<Job>();
+        MonitorPool { jobs: Some(jobs), receiver: None, threads: Vec::new(), size, _held: Some(receiver) }
+    }
+
+    /// Number of threads in the pool, and so of monitors that may run at once.
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// Run `job` on the first thread of the pool to be idle.
+    ///
+    /// Returns `false` if it can't be, as the pool is being dropped.
+    pub fn execute(&self, job: impl FnOnce() + Send + 'static) -> bool {
+        self.jobs.as_ref().is_some_and(|jobs| jobs.send(Box::new(job)).is_ok())
+    }
+}
+
+impl Drop for MonitorPool {
+    /// Wait for every job sent to the pool to be run, then for its threads to exit.
+    fn drop(&mut self) {
+        drop(self.jobs.take());
+        for thread in self.threads.drain(..) {
+            if thread.join().is_err() {
+                log::error!("monitor pool thread panicked");
+            }
+        }
+    }
+}
+
+/// Body of each thread of a [`MonitorPool`]: run the jobs taken from `receiver`, one at a
+/// time, until the pool's sender is dropped.
+fn run_jobs(receiver: &Mutex<Receiver<Job>>) {
+    loop {
+        // The lock is only held while waiting for a job, not while running it.
+        let job = receiver.lock().unwrap_or_else(PoisonError::into_inner).recv();
+        let Ok(job) = job else {
+            return
+        };
+        // Monitors catch their own panics, so this only keeps the thread for the next job.
+        if panic::catch_unwind(AssertUnwindSafe(job)).is_err() {
+            log::error!("job on monitor pool thread panicked");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use super::*;
+
+    #[test]
+    fn jobs_share_the_pool_threads() {
+        let pool = MonitorPool::new(2).unwrap();
+        assert_eq!((pool.size(), MonitorPool::new(0).unwrap().size()), (2, 1));
+        let mut reserved = MonitorPool::new(2).unwrap();
+        reserved.reserve(1).unwrap();
+        assert_eq!((reserved.size(), reserved.threads.len()), (2, 3));
+
+        let (done, threads) = (Arc::new(AtomicUsize::new(0)), Arc::new(Mutex::new(Vec::new())));
+        for n in 0..8 {
+            let (done, threads) = (Arc::clone(&done), Arc::clone(&threads));
+            assert!(pool.execute(move || {
+                threads.lock().unwrap().push(thread::current().id());
+                if n == 3 {
+                    panic!("job panicked");
+                }
+                done.fetch_add(1, Ordering::SeqCst);
+            }));
+        }
+        drop(pool);
+
+        let mut threads = threads.lock().unwrap().clone();
+        threads.sort_by_key(|id| format!("{id:?}"));
+        threads.dedup();
+        assert_eq!((done.load(Ordering::SeqCst), threads.len() <= 2), (7, true));
+    }
+}
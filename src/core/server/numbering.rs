@@ -0,0 +1,78 @@
+//! Numbering of the server's tasks, which carries on from one run of the server to the next,
+//! so that a task's number names it in status outputs and logs for good, see [`TaskCounter`].
+
+use std::{fs, io, path::{Path, PathBuf}};
+
+/// Name of the file, in the server's socket dir, the number of its next task is kept in,
+/// unless it's given another, see [`ServerConfig::counter_file`](super::config::ServerConfig::counter_file).
+pub const COUNTER_FILE: &str = "sdstored_counter";
+
+/// Counter numbering the server's tasks, saved to its file as every task is numbered, so that
+/// a server started afresh, or taking over, numbers its tasks after those of the last.
+#[derive(Debug, Default)]
+pub struct TaskCounter {
+    next: usize,
+    /// The file the counter is saved to. `None` if it's only kept in memory, as by tests.
+    path: Option<PathBuf>,
+}
+
+impl TaskCounter {
+    /// Open the counter saved in the file at `path`, starting from `0` if there is none yet.
+    pub fn open(path: &Path) -> io::Result<Self> {
+        let next = match fs::read_to_string(path) {
+            Ok(saved) => saved
+                .trim()
+                .parse()
+                .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, format!("{path:?} holds no task number: {err}")))?,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => 0,
+            Err(err) => return Err(err),
+        };
+        Ok(TaskCounter { next, path: Some(path.to_path_buf()) })
+    }
+
+    /// Number the next task, saving the counter past it.
+    ///
+    /// Should the counter fail to be saved, it's only logged: the task is still numbered, and
+    /// the counter saved along with the next one.
+    pub fn number_next(&mut self) -> usize {
+        let number = self.next;
+        self.next += 1;
+        if let Some(path) = &self.path {
+            if let Err(err) = save(path, self.next) {
+                log::warn!("could not save the task counter to {:?}. Error: {:?}", path, err);
+            }
+        }
+        number
+    }
+}
+
+/// Write `next` to the file at `path`, through a temporary file renamed over it, so that the
+/// file is never found half written.
+fn save(path: &Path, next: usize) -> io::Result<()> {
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+    fs::write(&tmp, format!("{next}\n"))?;
+    fs::rename(&tmp, path)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::core::testing::TempDir;
+
+    use super::*;
+
+    #[test]
+    fn numbering_carries_on_once_reopened() {
+        let dir = TempDir::new("numbering");
+        let path = dir.join(COUNTER_FILE);
+
+        let mut counter = TaskCounter::open(&path).unwrap();
+        assert_eq!((counter.number_next(), counter.number_next()), (0, 1));
+        let mut reopened = TaskCounter::open(&path).unwrap();
+        assert_eq!(reopened.number_next(), 2);
+        assert_eq!(fs::read_to_string(&path).unwrap(), "3\n");
+
+        fs::write(&path, "three").unwrap();
+        assert_eq!(TaskCounter::open(&path).unwrap_err().kind(), io::ErrorKind::InvalidData);
+    }
+}
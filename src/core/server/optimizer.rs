@@ -0,0 +1,77 @@
+use crate::core::filter::Filter;
+
+/// Pipeline equivalent to `filters`, but without stages that would not change the output:
+///
+/// * `nop` stages are dropped;
+/// * a filter immediately followed by one that undoes it, such as `gcompress` followed by
+///   `gdecompress`, is dropped along with it, which may in turn make the stages around
+///   them adjacent, and dropped as well.
+///
+/// Only compressing then decompressing, or encrypting then decrypting, cancels out:
+/// the other way around, the first stage may reject its input. `encrypt` and `decrypt` only
+/// cancel out if both are in `builtin_filters`, or neither is, since the two implementations
+/// differ.
+///
+/// A pipeline that cancels out entirely is reduced to a single `nop`, which copies its input.
+pub fn optimize(filters: &[Filter], builtin_filters: &[Filter]) -> Vec<Filter> {
+    let mut optimized: Vec<Filter> = Vec::with_capacity(filters.len());
+
+    for filter in filters.iter().filter(|f| **f != Filter::Nop) {
+        match optimized.last() {
+            Some(last) if undoes(filter, last, builtin_filters) => { optimized.pop(); },
+            _ => optimized.push(filter.clone()),
+        }
+    }
+
+    if optimized.is_empty() {
+        optimized.push(Filter::Nop);
+    }
+    optimized
+}
+
+/// Whether `second` restores the input of `first`, when run right after it.
+fn undoes(second: &Filter, first: &Filter, builtin_filters: &[Filter]) -> bool {
+    let is_builtin = |filter| builtin_filters.contains(&filter);
+    match (first, second) {
+        (Filter::Bcompress, Filter::Bdecompress) |
+        (Filter::Gcompress, Filter::Gdecompress) |
+        (Filter::Zcompress, Filter::Zdecompress) |
+        (Filter::Xcompress, Filter::Xdecompress) => true,
+        (Filter::Encrypt, Filter::Decrypt) => is_builtin(Filter::Encrypt) == is_builtin(Filter::Decrypt),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn optimized(pipeline: &str, builtin_filters: &[Filter]) -> String {
+        let filters = pipeline
+            .split_whitespace()
+            .map(|f| f.parse().unwrap())
+            .collect::<Vec<Filter>>();
+        optimize(&filters, builtin_filters)
+            .iter()
+            .map(Filter::to_string)
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+
+    #[test]
+    fn optimization_drops_useless_stages() {
+        assert_eq!(optimized("nop bcompress nop", &[]), "bcompress");
+        assert_eq!(optimized("gcompress encrypt nop decrypt gdecompress bcompress", &[]), "bcompress");
+        assert_eq!(optimized("gcompress gdecompress", &[]), "nop");
+        assert_eq!(optimized("zcompress xcompress xdecompress zdecompress", &[]), "nop");
+        // Decompressing first may fail, so is kept.
+        assert_eq!(optimized("gdecompress gcompress", &[]), "gdecompress gcompress");
+        assert_eq!(optimized("gcompress bdecompress", &[]), "gcompress bdecompress");
+    }
+
+    #[test]
+    fn optimization_keeps_mismatched_encryption() {
+        assert_eq!(optimized("encrypt decrypt", &[Filter::Encrypt]), "encrypt decrypt");
+        assert_eq!(optimized("encrypt decrypt", &[Filter::Encrypt, Filter::Decrypt]), "nop");
+    }
+}
@@ -0,0 +1,134 @@
+use std::{
+    collections::HashMap, io, os::unix::process::CommandExt, path::{Path, PathBuf},
+    process::{Child, ChildStderr, ChildStdin, ChildStdout, Command, Stdio},
+    sync::{mpsc::{self, Sender}, Arc, Mutex, MutexGuard, PoisonError},
+    thread::{self, JoinHandle},
+};
+
+use super::{config::{FilterExecutor, ServerConfig}, environment::StageEnvironment, resources::ResourceLimits};
+
+/// A process of an external filter, started ahead of time, and waiting for its input.
+///
+/// Filters exit once they reach the end of their input, so each worker only ever runs a
+/// single stage of a pipeline: the pool then starts another in its place.
+pub struct Worker {
+    /// The worker's process, leading a process group of its own.
+    pub child: Child,
+    pub stdin: ChildStdin,
+    pub stdout: ChildStdout,
+    pub stderr: ChildStderr,
+}
+
+/// Pool of resident [`Worker`]s, kept for each external filter the server may run, so
+/// that pipelines don't wait for their filters' processes to start, which dominates
+/// running them on small files.
+///
+/// Monitors take workers from the pool as they start their pipelines' stages, falling
+/// back on starting a process if none is idle. A thread of the pool's own starts a new
+/// worker in place of each one taken.
+pub struct WorkerPool {
+    /// Idle workers, by the path of the executable they run.
+    idle: Arc<Mutex<HashMap<PathBuf, Vec<Worker>>>>,
+    /// Paths of the executables to start another worker of.
+    refills: Option<Sender<PathBuf>>,
+    /// Thread starting workers, until `refills` is dropped.
+    refiller: Option<JoinHandle<()>>,
+}
+
+impl WorkerPool {
+    /// Start a pool of `size` workers for each external filter the server may run, or
+    /// as many as the filter's limit, if lower.
+    ///
+    /// Workers are started in the background: until they are, pipelines start their
+    /// own processes.
+    pub fn new(size: usize, server_config: &ServerConfig) -> io::Result<Self> {
+        let idle = Arc::new(Mutex::new(HashMap::new()));
+        let (refills, paths) = mpsc::channel::<PathBuf>();
+
+        let resource_limits = server_config.resource_limits;
+        let environment = server_config.environment.server_wide();
+        let idle_clone = Arc::clone(&idle);
+        let refiller = thread::Builder::new()
+            .name(String::from("sdstored_worker_pool"))
+            .spawn(move || {
+                for path in paths {
+                    match start_worker(&path, resource_limits, &environment) {
+                        Err(err) => log::warn!("could not start worker for {:?}: {:?}", path, err),
+                        Ok(worker) => lock(&idle_clone).entry(path).or_default().push(worker),
+                    }
+                }
+            })?;
+
+        for filter in server_config.filters_config.filters().iter() {
+            if let FilterExecutor::External(path) = server_config.filter_executor(filter) {
+                for _ in 0..size.min(server_config.filters_config.limit(filter)) {
+                    // The refiller only stops once the pool is dropped.
+                    let _ = refills.send(path.clone());
+                }
+            }
+        }
+
+        Ok(WorkerPool { idle, refills: Some(refills), refiller: Some(refiller) })
+    }
+
+    /// Take an idle worker running the executable at `path`, if there is one, and have
+    /// another started in its place.
+    ///
+    /// Workers that exited while idle are discarded, and replaced as well.
+    pub fn take(&self, path: &Path) -> Option<Worker> {
+        let mut idle = lock(&self.idle);
+        let workers = idle.get_mut(path)?;
+        while let Some(mut worker) = workers.pop() {
+            if let Some(refills) = &self.refills {
+                let _ = refills.send(path.to_path_buf());
+            }
+            match worker.child.try_wait() {
+                Ok(None) => return Some(worker),
+                exited => log::warn!("discarding worker for {:?}, which exited while idle: {:?}", path, exited),
+            }
+        }
+        None
+    }
+}
+
+impl Drop for WorkerPool {
+    /// Stop starting workers, then kill and reap the idle ones.
+    fn drop(&mut self) {
+        drop(self.refills.take());
+        if let Some(Err(_)) = self.refiller.take().map(JoinHandle::join) {
+            log::error!("worker pool thread panicked");
+        }
+
+        for mut worker in lock(&self.idle).drain().flat_map(|(_, workers)| workers) {
+            let _ = worker.child.kill();
+            let _ = worker.child.wait();
+        }
+    }
+}
+
+fn lock(idle: &Mutex<HashMap<PathBuf, Vec<Worker>>>) -> MutexGuard<'_, HashMap<PathBuf, Vec<Worker>>> {
+    // Workers are only ever moved in and out of the map, which can't be left inconsistent.
+    idle.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Start a worker running the executable at `path`, with `resource_limits`, in the server's
+/// `environment`, and in a process group of its own, so that the pipeline it ends up in can
+/// kill it.
+fn start_worker(path: &Path, resource_limits: ResourceLimits, environment: &StageEnvironment) -> io::Result<Worker> {
+    let mut command = Command::new(path);
+    command
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .process_group(0);
+    environment.apply(&mut command);
+    // SAFETY: `ResourceLimits::apply` only makes system calls, which are async-signal-safe.
+    unsafe { command.pre_exec(move || resource_limits.apply()) };
+
+    let mut child = command.spawn()?;
+    match (child.stdin.take(), child.stdout.take(), child.stderr.take()) {
+        (Some(stdin), Some(stdout), Some(stderr)) => Ok(Worker { child, stdin, stdout, stderr }),
+        // Every one of them was piped.
+        _ => unreachable!(),
+    }
+}
@@ -0,0 +1,193 @@
+//! Running the server as an unprivileged service account, once a server started as root
+//! bound its sockets, see [`Account`].
+
+use std::{
+    ffi::{CStr, CString},
+    fmt::Display,
+    io,
+    path::Path,
+};
+
+/// Errors looking up the service account the server is to run as, see [`Account::lookup`].
+#[derive(Debug)]
+pub enum AccountError {
+    /// There is no user of this name, or UID.
+    UnknownUser(String),
+    /// There is no group of this name, or GID.
+    UnknownGroup(String),
+    /// A group was given, but no user to run as with it.
+    GroupWithoutUser(String),
+    /// The user, or group, database could not be read.
+    LookupError(io::Error),
+}
+
+impl Display for AccountError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::UnknownUser(user) => write!(f, "there is no user {user:?}"),
+            Self::UnknownGroup(group) => write!(f, "there is no group {group:?}"),
+            Self::GroupWithoutUser(group) => write!(f, "the group {group:?} was given without a user to run as"),
+            Self::LookupError(err) => write!(f, "could not look up the account to run as: {err}"),
+        }
+    }
+}
+
+/// The account the server runs as once its sockets are bound, rather than the one it was
+/// started as, see [`Account::assume`]. Every thread, monitor and filter the server starts
+/// afterwards runs as it too.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Account {
+    /// Name of the user, whose supplementary groups are also taken, if it has one.
+    pub name: Option<CString>,
+    pub uid: libc::uid_t,
+    pub gid: libc::gid_t,
+}
+
+impl Account {
+    /// Look up the account of `user`, a name or a UID, with its primary group, unless
+    /// `group` is given, as a name or a GID.
+    pub fn lookup(user: &str, group: Option<&str>) -> Result<Self, AccountError> {
+        let unknown_user = || AccountError::UnknownUser(user.to_string());
+        let name = CString::new(user).map_err(|_| unknown_user())?;
+        let passwd = match lookup_passwd(Passwd::Name(&name)).map_err(AccountError::LookupError)? {
+            Some(passwd) => passwd,
+            // A UID no user has is run as too, with the group given.
+            None => match user.parse() {
+                Ok(uid) => lookup_passwd(Passwd::Uid(uid)).map_err(AccountError::LookupError)?.unwrap_or((None, uid, None)),
+                Err(_) => return Err(unknown_user()),
+            },
+        };
+        let (name, uid, primary_gid) = passwd;
+
+        let gid = match group {
+            None => primary_gid.ok_or_else(|| AccountError::UnknownGroup(format!("of UID {uid}")))?,
+            Some(group) => {
+                let unknown_group = || AccountError::UnknownGroup(group.to_string());
+                let group_name = CString::new(group).map_err(|_| unknown_group())?;
+                match (lookup_group(&group_name).map_err(AccountError::LookupError)?, group.parse()) {
+                    (Some(gid), _) | (None, Ok(gid)) => gid,
+                    (None, Err(_)) => return Err(unknown_group()),
+                }
+            },
+        };
+        Ok(Account { name, uid, gid })
+    }
+
+    /// Whether the server runs as the account already, and so needn't assume it.
+    pub fn is_current(&self) -> bool {
+        // SAFETY: neither call has any memory safety requirement.
+        unsafe { libc::geteuid() == self.uid && libc::getegid() == self.gid }
+    }
+
+    /// Hand the file at `path`, such as a socket the server bound while privileged, over to
+    /// the account, so that it can still remove it, or lock it.
+    pub fn own(&self, path: &Path) -> io::Result<()> {
+        std::os::unix::fs::chown(path, Some(self.uid), Some(self.gid))
+    }
+
+    /// Run as the account from now on, with its groups, for good: the server can't regain
+    /// the privileges of the account it was started as once this returns.
+    ///
+    /// Should be called before any thread is spawned, though the C library sees to every
+    /// thread of the process changing its IDs.
+    pub fn assume(&self) -> io::Result<()> {
+        if self.is_current() {
+            return Ok(())
+        }
+        // SAFETY: the name is nul-terminated, and `gid` is valid for reading one group.
+        let grouped = unsafe {
+            match &self.name {
+                Some(name) => libc::initgroups(name.as_ptr(), self.gid),
+                None => libc::setgroups(1, &self.gid),
+            }
+        };
+        // SAFETY: neither call has any memory safety requirement.
+        if grouped == -1 || unsafe { libc::setgid(self.gid) } == -1 || unsafe { libc::setuid(self.uid) } == -1 {
+            return Err(io::Error::last_os_error())
+        }
+        // SAFETY: as above.
+        if self.uid != 0 && unsafe { libc::setuid(0) } == 0 {
+            return Err(io::Error::other("root privileges could be regained after dropping them"))
+        }
+        Ok(())
+    }
+}
+
+impl Display for Account {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.name {
+            Some(name) => write!(f, "{} ({}:{})", name.to_string_lossy(), self.uid, self.gid),
+            None => write!(f, "{}:{}", self.uid, self.gid),
+        }
+    }
+}
+
+/// How a user is looked up, see [`lookup_passwd`].
+enum Passwd<'a> {
+    Name(&'a CStr),
+    Uid(libc::uid_t),
+}
+
+/// The name, UID and primary GID of a user, the latter unknown for a UID no user has.
+type PasswdEntry = (Option<CString>, libc::uid_t, Option<libc::gid_t>);
+
+/// The user `by`, if there is one.
+fn lookup_passwd(by: Passwd) -> io::Result<Option<PasswdEntry>> {
+    let mut buf = vec![0; 1024];
+    loop {
+        // SAFETY: `passwd` is only read once filled in by a successful lookup.
+        let mut passwd = unsafe { std::mem::zeroed::<libc::passwd>() };
+        let mut found = std::ptr::null_mut();
+        // SAFETY: every pointer is valid for the call, `buf` for its length.
+        let err = unsafe {
+            match by {
+                Passwd::Name(name) => libc::getpwnam_r(name.as_ptr(), &mut passwd, buf.as_mut_ptr(), buf.len(), &mut found),
+                Passwd::Uid(uid) => libc::getpwuid_r(uid, &mut passwd, buf.as_mut_ptr(), buf.len(), &mut found),
+            }
+        };
+        match err {
+            libc::ERANGE => buf.resize(buf.len() * 2, 0),
+            0 if found.is_null() => return Ok(None),
+            // SAFETY: the name points into `buf`, nul-terminated, as the lookup succeeded.
+            0 => return Ok(Some((Some(unsafe { CStr::from_ptr(passwd.pw_name) }.to_owned()), passwd.pw_uid, Some(passwd.pw_gid)))),
+            err => return Err(io::Error::from_raw_os_error(err)),
+        }
+    }
+}
+
+/// The GID of the group `name`, if there is one.
+fn lookup_group(name: &CStr) -> io::Result<Option<libc::gid_t>> {
+    let mut buf = vec![0; 1024];
+    loop {
+        // SAFETY: `group` is only read once filled in by a successful lookup.
+        let mut group = unsafe { std::mem::zeroed::<libc::group>() };
+        let mut found = std::ptr::null_mut();
+        // SAFETY: every pointer is valid for the call, `buf` for its length.
+        let err = unsafe { libc::getgrnam_r(name.as_ptr(), &mut group, buf.as_mut_ptr(), buf.len(), &mut found) };
+        match err {
+            libc::ERANGE => buf.resize(buf.len() * 2, 0),
+            0 if found.is_null() => return Ok(None),
+            0 => return Ok(Some(group.gr_gid)),
+            err => return Err(io::Error::from_raw_os_error(err)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn accounts_are_looked_up_by_name_or_id() {
+        let root = Account { name: Some(CString::new("root").unwrap()), uid: 0, gid: 0 };
+        assert_eq!(Account::lookup("root", None).unwrap(), root);
+        assert_eq!(Account::lookup("0", Some("0")).unwrap(), root);
+        assert_eq!(Account::lookup("root", Some("root")).unwrap(), root);
+        assert_eq!(Account::lookup("4000000", Some("4000000")).unwrap(), Account { name: None, uid: 4000000, gid: 4000000 });
+
+        assert!(matches!(Account::lookup("no-such-user", None), Err(AccountError::UnknownUser(_))));
+        assert!(matches!(Account::lookup("root", Some("no-such-group")), Err(AccountError::UnknownGroup(_))));
+        // A UID no user has has no primary group either.
+        assert!(matches!(Account::lookup("4000000", None), Err(AccountError::UnknownGroup(_))));
+    }
+}
@@ -0,0 +1,81 @@
+//! Limiting how fast each client may submit requests, so that a runaway script can't flood
+//! the server's queues, see [`RateLimiter`].
+
+use std::{collections::HashMap, time::{Duration, Instant}};
+
+/// How long it takes a client to be given back a request, by default, see
+/// [`RateLimit::refill`].
+pub const DEFAULT_REFILL: Duration = Duration::from_secs(1);
+
+/// How many requests each client may submit at once, and how fast it may go on submitting
+/// them, as a bucket of tokens, each taken by a request.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RateLimit {
+    /// Tokens a client's bucket holds when full, and so most requests it may submit at once.
+    pub burst: u32,
+    /// How long it takes a token to be added back to a client's bucket.
+    pub refill: Duration,
+}
+
+/// Whom a bucket is kept for: the user of clients the server can tell it of, so that a
+/// script doesn't get a bucket of its own for every client it spawns, or else a client
+/// by its PID.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum Client {
+    Uid(u32),
+    Pid(u32),
+}
+
+/// Each client's bucket of tokens, of its [`RateLimit`], kept as the instant the bucket is
+/// full again, as it only refills. Full buckets are forgotten.
+#[derive(Debug)]
+pub struct RateLimiter {
+    limit: RateLimit,
+    full_at: HashMap<Client, Instant>,
+}
+
+impl RateLimiter {
+    pub fn new(limit: RateLimit) -> Self {
+        RateLimiter { limit, full_at: HashMap::new() }
+    }
+
+    /// Take a token from the bucket of the client with `uid`, if the server could tell it,
+    /// and `pid`, at `now`. Should the bucket be empty, returns how long until a token is
+    /// added back to it.
+    pub fn acquire(&mut self, uid: Option<u32>, pid: u32, now: Instant) -> Result<(), Duration> {
+        self.full_at.retain(|_, full_at| *full_at > now);
+        let client = uid.map_or(Client::Pid(pid), Client::Uid);
+        let full_at = self.full_at.get(&client).copied().unwrap_or(now) + self.limit.refill;
+        let capacity = self.limit.refill.checked_mul(self.limit.burst).unwrap_or(Duration::MAX);
+        let empty_for = full_at - now;
+        if empty_for > capacity {
+            return Err(empty_for - capacity)
+        }
+        self.full_at.insert(client, full_at);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn buckets_allow_bursts_then_refill() {
+        let refill = Duration::from_secs(2);
+        let mut limiter = RateLimiter::new(RateLimit { burst: 2, refill });
+        let start = Instant::now();
+
+        assert_eq!(limiter.acquire(Some(1000), 42, start), Ok(()));
+        assert_eq!(limiter.acquire(Some(1000), 43, start), Ok(()));
+        assert_eq!(limiter.acquire(Some(1000), 44, start), Err(refill));
+        assert_eq!(limiter.acquire(Some(1000), 44, start + Duration::from_secs(1)), Err(Duration::from_secs(1)));
+        assert_eq!(limiter.acquire(Some(1000), 44, start + refill), Ok(()));
+        // Other users, and clients the server can't tell the user of, have buckets of their own.
+        assert_eq!(limiter.acquire(Some(1001), 45, start + refill), Ok(()));
+        assert_eq!(limiter.acquire(None, 46, start + refill), Ok(()));
+
+        assert_eq!(limiter.acquire(Some(1000), 44, start + refill * 3), Ok(()));
+        assert_eq!(limiter.full_at.len(), 1);
+    }
+}
@@ -0,0 +1,255 @@
+use std::{io, str::FromStr};
+
+/// Keywords of the limits file lines that configure [`ResourceLimits`].
+pub const RESOURCE_KEYWORDS: [&str; 5] = ["nice", "cpu-time", "file-size", "ionice", "priority-nice"];
+
+/// Highest niceness, which processes can't be made any nicer than.
+const MAX_NICE: i32 = 19;
+
+/// Scheduling class for a process' IO, see `ioprio_set(2)`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IoClass {
+    /// Served before anything else, at a level from 0 (highest) to 7.
+    Realtime(u8),
+    /// The default class, at a level from 0 (highest) to 7.
+    BestEffort(u8),
+    /// Only served when no other process needs the disk.
+    Idle,
+}
+
+impl IoClass {
+    /// Value of the class, as expected by `ioprio_set`.
+    fn ioprio(&self) -> libc::c_int {
+        const IOPRIO_CLASS_SHIFT: libc::c_int = 13;
+        let (class, level) = match self {
+            Self::Realtime(level)   => (1, *level),
+            Self::BestEffort(level) => (2, *level),
+            Self::Idle              => (3, 0),
+        };
+        (class << IOPRIO_CLASS_SHIFT) | level as libc::c_int
+    }
+
+    /// The class, served `levels` levels later, down to its lowest, 7.
+    fn lowered(self, levels: u8) -> Self {
+        match self {
+            Self::Realtime(level) => Self::Realtime(level.saturating_add(levels).min(7)),
+            Self::BestEffort(level) => Self::BestEffort(level.saturating_add(levels).min(7)),
+            Self::Idle => Self::Idle,
+        }
+    }
+}
+
+/// Limits on the resources of every external filter the server runs, so that long
+/// running pipelines don't starve the host's other processes.
+///
+/// Builtin filters run inside the server, and are not subject to these.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ResourceLimits {
+    /// Niceness filters are run with.
+    pub nice: Option<i32>,
+    /// Seconds of CPU time each filter may use before being killed.
+    pub cpu_time: Option<u64>,
+    /// Largest file, in bytes, a filter may write, which bounds the size of outputs.
+    pub file_size: Option<u64>,
+    /// IO scheduling class filters are run with. Only supported on Linux.
+    pub io_class: Option<IoClass>,
+    /// Levels of niceness requests' filters are run with on top of `nice`, fewer the higher
+    /// their priority, see [`ResourceLimits::for_priority`]. `None` if a request's priority
+    /// only decides when it runs.
+    pub priority_nice: Option<u32>,
+}
+
+/// A resource limit line of the limits file was malformed.
+#[derive(Debug, PartialEq, Eq)]
+pub struct ResourceLineParseError(pub String);
+
+impl ResourceLimits {
+    /// Set the limit given by a limits file line, of one of the forms
+    ///
+    /// * `nice <integer>`
+    /// * `cpu-time <seconds>`
+    /// * `file-size <bytes>`
+    /// * `ionice realtime <level>`, `ionice best-effort <level>` or `ionice idle`,
+    ///   with levels from 0 to 7
+    /// * `priority-nice <levels>`, with levels from 1 to 39
+    pub fn parse_line(&mut self, line: &str) -> Result<(), ResourceLineParseError> {
+        let err = || ResourceLineParseError(line.trim().to_string());
+        let words = line.split_whitespace().collect::<Vec<_>>();
+
+        match words.as_slice() {
+            ["nice", n] => self.nice = Some(n.parse().map_err(|_| err())?),
+            ["cpu-time", secs] => self.cpu_time = Some(secs.parse().map_err(|_| err())?),
+            ["file-size", bytes] => self.file_size = Some(bytes.parse().map_err(|_| err())?),
+            ["ionice", class @ ..] => self.io_class = Some(parse_io_class(class).ok_or_else(err)?),
+            ["priority-nice", levels] => self.priority_nice = Some(
+                levels.parse().ok().filter(|levels| (1..=39).contains(levels)).ok_or_else(err)?
+            ),
+            _ => return Err(err()),
+        }
+
+        Ok(())
+    }
+
+    /// The limits of the filters of a request of `priority`: with `priority-nice <levels>`,
+    /// requests of priority `levels` or higher run at `nice`, or the server's own niceness,
+    /// and those of each priority below one level nicer, up to 19, so that on a busy host
+    /// the CPU goes to the requests that matter most, rather than only their turn in the
+    /// queues.
+    ///
+    /// Their IO is served one level later every 5 levels of niceness, as the kernel derives
+    /// it from their niceness anyway when no `ionice` class is set.
+    pub fn for_priority(&self, priority: usize) -> Self {
+        let Some(levels) = self.priority_nice else {
+            return *self
+        };
+        let offset = (levels as usize).saturating_sub(priority) as i32;
+        if offset == 0 {
+            return *self
+        }
+        // SAFETY: `getpriority` has no memory safety preconditions, and can't fail for the
+        // calling process.
+        let base = self.nice.unwrap_or_else(|| unsafe { libc::getpriority(libc::PRIO_PROCESS, 0) });
+        ResourceLimits {
+            nice: Some(base.saturating_add(offset).min(MAX_NICE)),
+            io_class: self.io_class.map(|io_class| io_class.lowered((offset / 5) as u8)),
+            ..*self
+        }
+    }
+
+    /// Apply the limits to the calling process.
+    ///
+    /// This is meant to be run in a filter's process, between `fork` and `exec`: it only
+    /// makes system calls, which are safe to make there.
+    pub fn apply(&self) -> io::Result<()> {
+        if let Some(nice) = self.nice {
+            // SAFETY: `setpriority` has no memory safety preconditions.
+            check_err(unsafe { libc::setpriority(libc::PRIO_PROCESS, 0, nice) })?;
+        }
+        // Both the soft and the hard limit are set, so that filters can't raise them.
+        let rlimit = |value| libc::rlimit { rlim_cur: value, rlim_max: value };
+        if let Some(cpu_time) = self.cpu_time {
+            // SAFETY: the `struct rlimit` outlives the call.
+            check_err(unsafe { libc::setrlimit(libc::RLIMIT_CPU, &rlimit(cpu_time)) })?;
+        }
+        if let Some(file_size) = self.file_size {
+            // SAFETY: the `struct rlimit` outlives the call.
+            check_err(unsafe { libc::setrlimit(libc::RLIMIT_FSIZE, &rlimit(file_size)) })?;
+        }
+        if let Some(io_class) = self.io_class {
+            set_io_class(io_class)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl FromStr for ResourceLimits {
+    type Err = ResourceLineParseError;
+
+    /// Parse limits from lines, each of a form accepted by [`ResourceLimits::parse_line`].
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut limits = ResourceLimits::default();
+        for line in s.lines().filter(|l| !l.trim().is_empty()) {
+            limits.parse_line(line)?;
+        }
+        Ok(limits)
+    }
+}
+
+fn parse_io_class(words: &[&str]) -> Option<IoClass> {
+    let level = |level: &str| level.parse().ok().filter(|level| *level <= 7);
+    match words {
+        ["realtime", l]    => level(l).map(IoClass::Realtime),
+        ["best-effort", l] => level(l).map(IoClass::BestEffort),
+        ["idle"]           => Some(IoClass::Idle),
+        _ => None,
+    }
+}
+
+fn check_err(ret: libc::c_int) -> io::Result<()> {
+    match ret {
+        -1 => Err(io::Error::last_os_error()),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn set_io_class(io_class: IoClass) -> io::Result<()> {
+    const IOPRIO_WHO_PROCESS: libc::c_int = 1;
+    // SAFETY: `ioprio_set` has no memory safety preconditions.
+    let ret = unsafe { libc::syscall(libc::SYS_ioprio_set, IOPRIO_WHO_PROCESS, 0, io_class.ioprio()) };
+    check_err(ret as libc::c_int)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn set_io_class(_: IoClass) -> io::Result<()> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, "IO scheduling classes are only supported on Linux"))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{os::unix::process::CommandExt, process::Command};
+
+    use super::*;
+
+    #[test]
+    fn resource_limits_parsing() {
+        let limits = "nice 10\ncpu-time 60\nfile-size 4096\nionice best-effort 7"
+            .parse::<ResourceLimits>()
+            .expect("parsing should succeed");
+        assert_eq!(limits, ResourceLimits {
+            nice: Some(10),
+            cpu_time: Some(60),
+            file_size: Some(4096),
+            io_class: Some(IoClass::BestEffort(7)),
+            priority_nice: None,
+        });
+
+        let lines = ["nice", "nice x", "cpu-time -1", "ionice idle 3", "ionice realtime 8", "priority-nice 0", "priority-nice 40"];
+        for line in lines {
+            assert!(line.parse::<ResourceLimits>().is_err(), "{line} should not parse");
+        }
+    }
+
+    #[test]
+    fn lower_priorities_run_nicer() {
+        let limits = "nice 2\nionice best-effort 4\npriority-nice 10".parse::<ResourceLimits>().unwrap();
+        let by_priority = |priority| {
+            let limits = limits.for_priority(priority);
+            (limits.nice, limits.io_class)
+        };
+        assert_eq!(by_priority(0), (Some(12), Some(IoClass::BestEffort(6))));
+        assert_eq!(by_priority(6), (Some(6), Some(IoClass::BestEffort(4))));
+        assert_eq!(by_priority(10), (Some(2), Some(IoClass::BestEffort(4))));
+        assert_eq!(by_priority(100), by_priority(10));
+
+        let limits = ResourceLimits { nice: Some(15), io_class: Some(IoClass::Idle), priority_nice: Some(39), ..limits };
+        assert_eq!(limits.for_priority(0).nice, Some(MAX_NICE));
+        assert_eq!(limits.for_priority(0).io_class, Some(IoClass::Idle));
+        let limits = ResourceLimits { priority_nice: None, ..limits };
+        assert_eq!(limits.for_priority(0), limits);
+    }
+
+    #[test]
+    fn resource_limits_apply_to_children() {
+        let niceness = |output: Vec<u8>| String::from_utf8(output).unwrap().trim().parse::<i32>().unwrap();
+        let file = std::env::temp_dir().join(format!("sdstore_rlimit_test_{}", std::process::id()));
+
+        let limits = ResourceLimits { nice: Some(5), file_size: Some(4096), ..Default::default() };
+        let output = unsafe {
+            Command::new("/bin/sh")
+                .arg("-c")
+                .arg(format!("nice; head -c 8192 /dev/zero > {}", file.display()))
+                .pre_exec(move || limits.apply())
+                .output()
+                .unwrap()
+        };
+
+        let base_nice = niceness(Command::new("nice").output().unwrap().stdout);
+        assert_eq!(niceness(output.stdout), base_nice + 5);
+        // Writing past the file size limit fails.
+        assert!(!output.status.success());
+        assert_eq!(std::fs::metadata(&file).unwrap().len(), 4096);
+        std::fs::remove_file(&file).unwrap();
+    }
+}
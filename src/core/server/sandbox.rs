@@ -0,0 +1,445 @@
+//! Confinement of the processes of external filters, see [`Sandbox`].
+
+use std::{env, ffi::CString, fs, io, mem, os::unix::ffi::OsStrExt, path::{Path, PathBuf}};
+
+/// Keyword of the limits file lines that configure the [`Sandbox`].
+pub const SANDBOX_KEYWORD: &str = "sandbox";
+
+/// Paths of the host filters still see in their mount namespace, other than their
+/// executable, and their task's input file and output directory: those executables may
+/// need to be loaded, and the devices filters commonly open. Those missing are skipped.
+const SYSTEM_PATHS: [&str; 10] = [
+    "/bin", "/sbin", "/lib", "/lib32", "/lib64", "/usr", "/etc/ld.so.cache", "/dev/null", "/dev/zero", "/dev/urandom",
+];
+
+/// System calls denied to filters, which fail with `EPERM`: filtering a file needs none of
+/// them, whereas they could be used to get at other processes, the host's filesystems,
+/// or the network.
+const DENIED_SYSCALLS: [libc::c_long; 28] = [
+    libc::SYS_ptrace, libc::SYS_process_vm_readv, libc::SYS_process_vm_writev,
+    libc::SYS_mount, libc::SYS_umount2, libc::SYS_pivot_root, libc::SYS_chroot,
+    libc::SYS_unshare, libc::SYS_setns, libc::SYS_open_by_handle_at,
+    libc::SYS_kexec_load, libc::SYS_init_module, libc::SYS_finit_module, libc::SYS_delete_module,
+    libc::SYS_reboot, libc::SYS_swapon, libc::SYS_swapoff, libc::SYS_acct,
+    libc::SYS_settimeofday, libc::SYS_clock_settime, libc::SYS_sethostname, libc::SYS_setdomainname,
+    libc::SYS_bpf, libc::SYS_perf_event_open, libc::SYS_userfaultfd,
+    libc::SYS_keyctl, libc::SYS_add_key, libc::SYS_socket,
+];
+
+/// Value of `seccomp_data.arch` for the system calls of the server's architecture, see
+/// `<linux/audit.h>`: filters making those of another are killed.
+#[cfg(target_arch = "x86_64")]
+const AUDIT_ARCH: Option<u32> = Some(0xc000_003e);
+#[cfg(target_arch = "aarch64")]
+const AUDIT_ARCH: Option<u32> = Some(0xc000_00b7);
+#[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64")))]
+const AUDIT_ARCH: Option<u32> = None;
+
+/// How the processes of the external filters the server runs are confined, so that a
+/// filter can do no more than read its input and write its output, whatever it runs.
+///
+/// Builtin filters run inside the server, and WASM filters in a sandbox of their own, and
+/// are not subject to this.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Sandbox {
+    /// Whether filters run in mount and PID namespaces of their own, where the host's
+    /// filesystem is replaced by read-only binds of the system's directories, see
+    /// [`SYSTEM_PATHS`], the filter's executable, and its task's input file and output
+    /// directory, and where no other process can be seen, or signalled.
+    pub namespaces: bool,
+    /// Whether filters are denied the system calls they have no use for, see
+    /// [`DENIED_SYSCALLS`].
+    pub seccomp: bool,
+}
+
+/// A sandbox line of the limits file was malformed.
+#[derive(Debug, PartialEq, Eq)]
+pub struct SandboxLineParseError(pub String);
+
+impl Sandbox {
+    /// Enable the confinement given by a limits file line of the form
+    /// `sandbox [namespaces] [seccomp]`, where a bare `sandbox` enables both.
+    pub fn parse_line(&mut self, line: &str) -> Result<(), SandboxLineParseError> {
+        let err = || SandboxLineParseError(line.trim().to_string());
+        let mut words = line.split_whitespace();
+        if words.next() != Some(SANDBOX_KEYWORD) {
+            return Err(err())
+        }
+
+        let mut words = words.peekable();
+        if words.peek().is_none() {
+            *self = Sandbox { namespaces: true, seccomp: true };
+        }
+        for word in words {
+            match word {
+                "namespaces" => self.namespaces = true,
+                "seccomp" => self.seccomp = true,
+                _ => return Err(err()),
+            }
+        }
+        Ok(())
+    }
+
+    /// Whether filters are confined at all.
+    pub fn is_enabled(&self) -> bool {
+        self.namespaces || self.seccomp
+    }
+
+    /// Prepare the confinement of a filter running the `executable`, for a task reading
+    /// `input` and writing to `output_dir`, to be entered once its process is forked, see
+    /// [`Confinement::enter`].
+    pub fn confine(&self, executable: &Path, input: &Path, output_dir: &Path) -> io::Result<Confinement> {
+        let jail = match self.namespaces {
+            false => None,
+            true => Some(Jail::new(executable, input, output_dir)?),
+        };
+        let seccomp = match self.seccomp {
+            false => None,
+            true => Some(seccomp_program()?),
+        };
+        Ok(Confinement { jail, seccomp })
+    }
+}
+
+/// Everything needed to confine a filter's process, prepared ahead of forking it, as only
+/// system calls are safe to make between `fork` and `exec`.
+pub struct Confinement {
+    jail: Option<Jail>,
+    /// Program checking the filter's system calls, see [`DENIED_SYSCALLS`].
+    seccomp: Option<Vec<libc::sock_filter>>,
+}
+
+impl Confinement {
+    /// Confine the calling process.
+    ///
+    /// This is meant to be run in a filter's process, between `fork` and `exec`: it only
+    /// makes system calls. Entering a PID namespace forks the process once more, see
+    /// [`Jail::enter`]: it's the child that returns, to `exec` the filter.
+    pub fn enter(&self) -> io::Result<()> {
+        if let Some(jail) = &self.jail {
+            jail.enter()?;
+        }
+        if let Some(program) = &self.seccomp {
+            // Which unprivileged processes must set to install a seccomp filter.
+            // SAFETY: `prctl` has no memory safety preconditions with these arguments.
+            check_err(unsafe { libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0) })?;
+            let fprog = libc::sock_fprog {
+                len: program.len() as libc::c_ushort,
+                // The kernel only reads the program.
+                filter: program.as_ptr() as *mut libc::sock_filter,
+            };
+            // SAFETY: `fprog` points to the program, both outliving the call.
+            check_err(unsafe {
+                libc::prctl(libc::PR_SET_SECCOMP, libc::SECCOMP_MODE_FILTER, &fprog as *const libc::sock_fprog)
+            })?;
+        }
+        Ok(())
+    }
+}
+
+/// Filesystem a filter sees in its own mount namespace, built on a `tmpfs` mounted on
+/// `root`, and made read-only before the filter's process makes it its root.
+struct Jail {
+    root: CString,
+    /// Contents of `/proc/self/setgroups`, `uid_map` and `gid_map`, by path, which map the
+    /// server's user in the user namespace the namespaces are created in, if the server
+    /// isn't privileged enough to create them in its own.
+    id_maps: Vec<(CString, CString)>,
+    /// Directories to create under `root`, parents first, to mount binds on.
+    dirs: Vec<CString>,
+    /// Files to create under `root`, to mount binds on.
+    files: Vec<CString>,
+    /// Binds to mount, by source, and target under `root`.
+    binds: Vec<(CString, CString)>,
+}
+
+impl Jail {
+    fn new(executable: &Path, input: &Path, output_dir: &Path) -> io::Result<Self> {
+        // Only ever mounted on in filters' mount namespaces, so all of them may share it.
+        let root = env::temp_dir().join("sdstored-sandbox");
+        fs::create_dir_all(&root)?;
+
+        let mut paths = SYSTEM_PATHS.iter().map(PathBuf::from).filter(|path| path.exists()).collect::<Vec<_>>();
+        for path in [executable, input, output_dir] {
+            paths.push(fs::canonicalize(path)?);
+        }
+        // Paths within one that is already bound are not bound again.
+        paths.sort_by_key(|path| path.components().count());
+        let mut bound: Vec<PathBuf> = Vec::new();
+        let (mut dirs, mut files, mut binds) = (Vec::<PathBuf>::new(), Vec::new(), Vec::new());
+        for path in paths {
+            if bound.iter().any(|dir| path.starts_with(dir)) {
+                continue
+            }
+            let relative = path.strip_prefix("/").unwrap_or(&path);
+            let parents = relative.ancestors().skip(1).filter(|parent| !parent.as_os_str().is_empty());
+            for parent in parents.collect::<Vec<_>>().into_iter().rev() {
+                if !dirs.iter().any(|dir| dir == &root.join(parent)) {
+                    dirs.push(root.join(parent));
+                }
+            }
+            match fs::metadata(&path)?.is_dir() {
+                true => dirs.push(root.join(relative)),
+                false => files.push(root.join(relative)),
+            }
+            binds.push((c_path(&path)?, c_path(&root.join(relative))?));
+            bound.push(path);
+        }
+
+        // SAFETY: neither call has any memory safety preconditions.
+        let (uid, gid) = unsafe { (libc::geteuid(), libc::getegid()) };
+        let id_maps = match uid {
+            0 => Vec::new(),
+            _ => vec![
+                (CString::from(c"/proc/self/setgroups"), CString::from(c"deny")),
+                (CString::from(c"/proc/self/uid_map"), CString::new(format!("{uid} {uid} 1"))?),
+                (CString::from(c"/proc/self/gid_map"), CString::new(format!("{gid} {gid} 1"))?),
+            ],
+        };
+
+        Ok(Jail {
+            root: c_path(&root)?,
+            id_maps,
+            dirs: dirs.iter().map(|dir| c_path(dir)).collect::<Result<_, _>>()?,
+            files: files.iter().map(|file| c_path(file)).collect::<Result<_, _>>()?,
+            binds,
+        })
+    }
+
+    /// Move the calling process into new mount and PID namespaces, with the jail's
+    /// filesystem as its root.
+    ///
+    /// Only the children of a process join the PID namespace it creates: the process forks,
+    /// and only its child returns, as the namespace's first process. The parent waits for
+    /// the child, having closed every file descriptor, so as not to hold the pipeline's
+    /// pipes, or that `Command::spawn` waits on to know whether the filter was executed, and
+    /// exits as it does. The child is killed if the parent is.
+    fn enter(&self) -> io::Result<()> {
+        let mut namespaces = libc::CLONE_NEWNS | libc::CLONE_NEWPID;
+        if !self.id_maps.is_empty() {
+            namespaces |= libc::CLONE_NEWUSER;
+        }
+        // SAFETY: `unshare` has no memory safety preconditions.
+        check_err(unsafe { libc::unshare(namespaces) })?;
+        for (path, contents) in &self.id_maps {
+            write_file(path, contents)?;
+        }
+
+        // SAFETY: every pointer passed is either null, or to a string outliving the calls.
+        unsafe {
+            // Nothing mounted in the namespace is propagated to the host's.
+            check_err(libc::mount(
+                std::ptr::null(), c"/".as_ptr(), std::ptr::null(), libc::MS_REC | libc::MS_PRIVATE, std::ptr::null()
+            ))?;
+            check_err(libc::mount(
+                c"tmpfs".as_ptr(), self.root.as_ptr(), c"tmpfs".as_ptr(), libc::MS_NOSUID | libc::MS_NODEV,
+                c"mode=0755".as_ptr().cast()
+            ))?;
+            for dir in &self.dirs {
+                if libc::mkdir(dir.as_ptr(), 0o755) == -1 && *libc::__errno_location() != libc::EEXIST {
+                    return Err(io::Error::last_os_error())
+                }
+            }
+            for file in &self.files {
+                let fd = libc::open(file.as_ptr(), libc::O_CREAT | libc::O_WRONLY | libc::O_CLOEXEC, 0o644);
+                check_err(fd)?;
+                libc::close(fd);
+            }
+            for (source, target) in &self.binds {
+                check_err(libc::mount(
+                    source.as_ptr(), target.as_ptr(), std::ptr::null(), libc::MS_BIND | libc::MS_REC, std::ptr::null()
+                ))?;
+            }
+            make_read_only(&self.root)?;
+
+            check_err(libc::chdir(self.root.as_ptr()))?;
+            // Stacks the old root under the new one, from which it is then detached.
+            check_err(libc::syscall(libc::SYS_pivot_root, c".".as_ptr(), c".".as_ptr()) as libc::c_int)?;
+            check_err(libc::umount2(c".".as_ptr(), libc::MNT_DETACH))?;
+            check_err(libc::chdir(c"/".as_ptr()))?;
+
+            match libc::fork() {
+                -1 => Err(io::Error::last_os_error()),
+                0 => check_err(libc::prctl(libc::PR_SET_PDEATHSIG, libc::SIGKILL, 0, 0, 0)),
+                child => exit_with(child),
+            }
+        }
+    }
+}
+
+/// Layout of `struct mount_attr`, see `mount_setattr(2)`.
+#[repr(C)]
+struct MountAttr {
+    attr_set: u64,
+    attr_clr: u64,
+    propagation: u64,
+    userns_fd: u64,
+}
+
+/// Make the mount at `path`, and every mount under it, read-only, and ignore set-user-ID
+/// bits, with `mount_setattr`, which Linux has since 5.12.
+fn make_read_only(path: &CString) -> io::Result<()> {
+    const MOUNT_ATTR_RDONLY: u64 = 0x1;
+    const MOUNT_ATTR_NOSUID: u64 = 0x2;
+    let attr = MountAttr { attr_set: MOUNT_ATTR_RDONLY | MOUNT_ATTR_NOSUID, attr_clr: 0, propagation: 0, userns_fd: 0 };
+    // SAFETY: `path` and `attr` outlive the call, which is given the size of `attr`.
+    let ret = unsafe {
+        libc::syscall(
+            libc::SYS_mount_setattr, libc::AT_FDCWD, path.as_ptr(), libc::AT_RECURSIVE,
+            &attr as *const MountAttr, mem::size_of::<MountAttr>()
+        )
+    };
+    check_err(ret as libc::c_int)
+}
+
+/// Wait for `child`, then exit as it did: with the same code, or killed by the same signal.
+///
+/// # Safety
+///
+/// Every file descriptor of the process is closed: it must only ever make system calls
+/// afterwards, as it does.
+unsafe fn exit_with(child: libc::pid_t) -> ! {
+    libc::syscall(libc::SYS_close_range, 0, libc::c_uint::MAX, 0);
+    let mut status = 0;
+    while libc::waitpid(child, &mut status, 0) == -1 && *libc::__errno_location() == libc::EINTR {}
+    if libc::WIFSIGNALED(status) {
+        let signal = libc::WTERMSIG(status);
+        libc::signal(signal, libc::SIG_DFL);
+        libc::kill(libc::getpid(), signal);
+    }
+    match libc::WIFEXITED(status) {
+        true => libc::_exit(libc::WEXITSTATUS(status)),
+        false => libc::_exit(1),
+    }
+}
+
+/// Write `contents` to the file at `path`, which exists, in a single `write`, as
+/// `/proc/self/uid_map` and the like must be.
+fn write_file(path: &CString, contents: &CString) -> io::Result<()> {
+    // SAFETY: `path` outlives the call.
+    let fd = unsafe { libc::open(path.as_ptr(), libc::O_WRONLY | libc::O_CLOEXEC) };
+    check_err(fd)?;
+    let bytes = contents.as_bytes();
+    // SAFETY: `bytes` outlives the call, which is given its length.
+    let written = unsafe { libc::write(fd, bytes.as_ptr().cast(), bytes.len()) };
+    // SAFETY: `fd` was opened above, and isn't used afterwards.
+    unsafe { libc::close(fd) };
+    match written {
+        -1 => Err(io::Error::last_os_error()),
+        _ => Ok(()),
+    }
+}
+
+/// The seccomp program denying [`DENIED_SYSCALLS`], and killing the filter if it makes system
+/// calls of another architecture than the server's, which may be numbered differently.
+fn seccomp_program() -> io::Result<Vec<libc::sock_filter>> {
+    let Some(arch) = AUDIT_ARCH else {
+        return Err(io::Error::new(io::ErrorKind::Unsupported, "seccomp is not supported on this architecture"))
+    };
+    let statement = |code: u32, k: u32| libc::sock_filter { code: code as u16, jt: 0, jf: 0, k };
+    let jump = |code: u32, k: u32, jt: u8, jf: u8| libc::sock_filter { code: code as u16, jt, jf, k };
+    // Offsets of the fields of `struct seccomp_data`.
+    const NR: u32 = 0;
+    const ARCH: u32 = 4;
+
+    let mut program = vec![
+        statement(libc::BPF_LD | libc::BPF_W | libc::BPF_ABS, ARCH),
+        jump(libc::BPF_JMP | libc::BPF_JEQ | libc::BPF_K, arch, 1, 0),
+        statement(libc::BPF_RET | libc::BPF_K, libc::SECCOMP_RET_KILL_PROCESS),
+        statement(libc::BPF_LD | libc::BPF_W | libc::BPF_ABS, NR),
+    ];
+    if cfg!(target_arch = "x86_64") {
+        // The system calls of the x32 ABI share the architecture of x86-64's, but are
+        // numbered from this bit on.
+        const X32_SYSCALL_BIT: u32 = 0x4000_0000;
+        program.push(jump(libc::BPF_JMP | libc::BPF_JGE | libc::BPF_K, X32_SYSCALL_BIT, 0, 1));
+        program.push(statement(libc::BPF_RET | libc::BPF_K, libc::SECCOMP_RET_KILL_PROCESS));
+    }
+    for syscall in DENIED_SYSCALLS {
+        program.push(jump(libc::BPF_JMP | libc::BPF_JEQ | libc::BPF_K, syscall as u32, 0, 1));
+        program.push(statement(libc::BPF_RET | libc::BPF_K, libc::SECCOMP_RET_ERRNO | libc::EPERM as u32));
+    }
+    program.push(statement(libc::BPF_RET | libc::BPF_K, libc::SECCOMP_RET_ALLOW));
+    Ok(program)
+}
+
+fn c_path(path: &Path) -> io::Result<CString> {
+    CString::new(path.as_os_str().as_bytes()).map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))
+}
+
+fn check_err(ret: libc::c_int) -> io::Result<()> {
+    match ret {
+        -1 => Err(io::Error::last_os_error()),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{os::unix::process::CommandExt, process::Command};
+
+    use crate::core::testing::TempDir;
+
+    use super::*;
+
+    #[test]
+    fn sandbox_line_parsing() {
+        let parse = |line: &str| {
+            let mut sandbox = Sandbox::default();
+            sandbox.parse_line(line).map(|_| sandbox)
+        };
+        assert_eq!(parse("sandbox"), Ok(Sandbox { namespaces: true, seccomp: true }));
+        assert_eq!(parse("sandbox seccomp"), Ok(Sandbox { namespaces: false, seccomp: true }));
+        assert_eq!(parse("sandbox namespaces seccomp"), Ok(Sandbox { namespaces: true, seccomp: true }));
+        for line in ["sandbox chroot", "sandbox seccomp 1", "nice 10"] {
+            assert!(parse(line).is_err(), "{line} should not parse");
+        }
+        assert!(!Sandbox::default().is_enabled());
+    }
+
+    #[test]
+    #[ignore = "needs user namespaces, which not every host lets unprivileged users create"]
+    fn filters_only_see_their_files() {
+        let dir = TempDir::new("sandbox");
+        let output_dir = dir.join("out");
+        fs::create_dir_all(&output_dir).unwrap();
+        fs::write(dir.join("input"), "input").unwrap();
+        fs::write(dir.join("other"), "other").unwrap();
+
+        let sandbox = Sandbox { namespaces: true, seccomp: true };
+        let confinement = sandbox.confine(Path::new("/bin/sh"), &dir.join("input"), &output_dir).unwrap();
+        let script = format!(
+            "echo $$; cat {input}; test -e {other} || echo hidden; touch {out}/file 2>/dev/null || echo read-only",
+            input = dir.join("input").display(), other = dir.join("other").display(), out = output_dir.display()
+        );
+        let mut command = Command::new("/bin/sh");
+        command.args(["-c", &script]);
+        // SAFETY: `Confinement::enter` only makes system calls.
+        unsafe { command.pre_exec(move || confinement.enter()) };
+        let output = command.output();
+
+        let output = output.expect("could not run a sandboxed filter");
+        assert!(output.status.success(), "{output:?}");
+        assert_eq!(String::from_utf8(output.stdout).unwrap(), "1\ninputhidden\nread-only\n");
+    }
+
+    #[test]
+    fn filters_are_denied_system_calls() {
+        let confinement = Sandbox { namespaces: false, seccomp: true }
+            .confine(Path::new("/bin/sh"), Path::new("/"), Path::new("/"))
+            .unwrap();
+        let mut command = Command::new("/bin/sh");
+        command.args(["-c", "exit 0"]);
+        // SAFETY: `Confinement::enter` only makes system calls.
+        unsafe {
+            command.pre_exec(move || {
+                confinement.enter()?;
+                match libc::socket(libc::AF_UNIX, libc::SOCK_STREAM, 0) {
+                    -1 => Ok(()),
+                    _ => Err(io::Error::other("sockets should be denied")),
+                }
+            })
+        };
+        assert!(command.status().unwrap().success());
+    }
+}
@@ -0,0 +1,868 @@
+use std::{
+    borrow::Borrow, cmp::Reverse, collections::{hash_map::Entry, HashMap, HashSet, VecDeque}, hash::{Hash, Hasher},     fmt::Display, str::FromStr, sync::Arc, time::Duration,
+};
+
+use priority_queue::PriorityQueue;
+
+use crate::core::{batch, client_task::ClientTask, filter::Filter, limits::RunningFilters, monitor::StageTiming};
+
+use super::config::{FiltersConfig, QueueConfig};
+
+/// Policy used by the server to decide which pending task runs next.
+///
+/// Selected from the server's CLI on start-up, see [`ServerConfig`](super::config::ServerConfig).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SchedulingPolicy {
+    /// Highest client-given priority first. This is the behavior required by the
+    /// project's statement.
+    #[default]
+    Priority,
+    /// Tasks run in the order they were received, ignoring their priority.
+    Fifo,
+    /// Smallest input file first, with the task's priority breaking ties.
+    ShortestFileFirst,
+    /// Highest priority first, with the smallest input file first among tasks of the same
+    /// priority, so that a large file doesn't hold up many small ones behind it.
+    PriorityShortestFileFirst,
+    /// Weighted fair queueing between clients, where each task's priority is its weight.
+    WeightedFair,
+}
+
+/// Error for an unrecognized scheduling policy name.
+#[derive(Debug, PartialEq, Eq)]
+pub struct SchedulingPolicyParseError(pub String);
+
+impl FromStr for SchedulingPolicy {
+    type Err = SchedulingPolicyParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let res = match s.to_lowercase().as_str() {
+            "priority"               => SchedulingPolicy::Priority,
+            "fifo"                   => SchedulingPolicy::Fifo,
+            "shortest-file"          => SchedulingPolicy::ShortestFileFirst,
+            "priority-shortest-file" => SchedulingPolicy::PriorityShortestFileFirst,
+            "weighted-fair"          => SchedulingPolicy::WeightedFair,
+            s                        => return Err(SchedulingPolicyParseError(s.to_string()))
+        };
+
+        Ok(res)
+    }
+}
+
+impl Display for SchedulingPolicy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SchedulingPolicy::Priority                  => write!(f, "priority"),
+            SchedulingPolicy::Fifo                      => write!(f, "fifo"),
+            SchedulingPolicy::ShortestFileFirst         => write!(f, "shortest-file"),
+            SchedulingPolicy::PriorityShortestFileFirst => write!(f, "priority-shortest-file"),
+            SchedulingPolicy::WeightedFair              => write!(f, "weighted-fair"),
+        }
+    }
+}
+
+impl SchedulingPolicy {
+    /// Create an empty scheduler implementing this policy.
+    pub fn build(self) -> Box<dyn Scheduler> {
+        match self {
+            SchedulingPolicy::Priority                  => Box::<PriorityScheduler>::default(),
+            SchedulingPolicy::Fifo                      => Box::<FifoScheduler>::default(),
+            SchedulingPolicy::ShortestFileFirst         => Box::<ShortestFileScheduler>::default(),
+            SchedulingPolicy::PriorityShortestFileFirst => Box::<PriorityShortestFileScheduler>::default(),
+            SchedulingPolicy::WeightedFair              => Box::<WeightedFairScheduler>::default(),
+        }
+    }
+}
+
+/// Queueing logic of the server: stores the tasks clients send, and decides which
+/// of them is to be executed next.
+///
+/// Whether the next task can actually be run, given the server's running filters and
+/// limits, is up to the server: it only ever pops a scheduler's head task, so a task
+/// that cannot run blocks the ones behind it. This prevents large pipelines from starving.
+///
+/// Tasks are shared with the events and statuses that mention them, rather than cloned.
+pub trait Scheduler: Send {
+    /// Add a newly received task to the pending tasks.
+    fn push(&mut self, task: Arc<ClientTask>);
+
+    /// The next task to be executed, if any.
+    fn peek(&self) -> Option<&Arc<ClientTask>>;
+
+    /// Remove the next task to be executed, if any.
+    fn pop(&mut self) -> Option<Arc<ClientTask>>;
+
+    /// Remove the pending task for which `is_task` holds, if any, e.g. as it was cancelled.
+    fn remove(&mut self, is_task: &dyn Fn(&ClientTask) -> bool) -> Option<Arc<ClientTask>>;
+
+    /// Pending tasks, in the order this scheduler would pop them.
+    fn pending(&self) -> Vec<&Arc<ClientTask>>;
+
+    /// The first `n` pending tasks, in the order this scheduler would pop them, without
+    /// ordering the others.
+    fn ahead(&self, n: usize) -> Vec<&Arc<ClientTask>> {
+        self.pending().into_iter().take(n).collect()
+    }
+
+    /// Number of pending tasks.
+    fn len(&self) -> usize;
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// A task in a [`TaskPqueue`], told apart from the others by when it was pushed, rather than
+/// by its contents, so that tasks that are equal, as when submitted alike twice, are both kept.
+struct Pushed {
+    seq: u64,
+    task: Arc<ClientTask>,
+}
+
+impl PartialEq for Pushed {
+    fn eq(&self, other: &Self) -> bool {
+        self.seq == other.seq
+    }
+}
+
+impl Eq for Pushed {}
+
+impl Hash for Pushed {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.seq.hash(state)
+    }
+}
+
+impl Borrow<u64> for Pushed {
+    fn borrow(&self) -> &u64 {
+        &self.seq
+    }
+}
+
+/// Priority queue of tasks, popped by descending priority `P`, which the schedulers that
+/// order tasks by more than when they came are built on.
+struct TaskPqueue<P: Ord> {
+    pqueue: PriorityQueue<Pushed, P>,
+    /// Number of tasks pushed so far, the `seq` of the next, see [`Pushed`].
+    pushed: u64,
+}
+
+impl<P: Ord> Default for TaskPqueue<P> {
+    fn default() -> Self {
+        TaskPqueue { pqueue: PriorityQueue::new(), pushed: 0 }
+    }
+}
+
+impl<P: Ord> TaskPqueue<P> {
+    fn push(&mut self, task: Arc<ClientTask>, priority: P) {
+        self.pqueue.push(Pushed { seq: self.pushed, task }, priority);
+        self.pushed += 1;
+    }
+
+    fn peek(&self) -> Option<&Arc<ClientTask>> {
+        self.pqueue.peek().map(|(pushed, _)| &pushed.task)
+    }
+
+    fn pop(&mut self) -> Option<(Arc<ClientTask>, P)> {
+        self.pqueue.pop().map(|(pushed, priority)| (pushed.task, priority))
+    }
+
+    /// Remove the task for which `is_task` holds, if any.
+    fn remove(&mut self, is_task: &dyn Fn(&ClientTask) -> bool) -> Option<Arc<ClientTask>> {
+        let seq = self.pqueue.iter().find(|(pushed, _)| is_task(&pushed.task))?.0.seq;
+        self.pqueue.remove(&seq).map(|(pushed, _)| pushed.task)
+    }
+
+    /// `PriorityQueue::iter` yields elements in arbitrary order, so they're sorted
+    /// by descending priority to reflect the order in which they'd be popped.
+    fn sorted(&self) -> Vec<&Arc<ClientTask>> {
+        self.head(self.pqueue.len())
+    }
+
+    /// The first `n` tasks, sorted as by [`TaskPqueue::sorted`], which are only selected,
+    /// rather than sorted, among the others.
+    fn head(&self, n: usize) -> Vec<&Arc<ClientTask>> {
+        let mut head = self.pqueue.iter().collect::<Vec<_>>();
+        if n < head.len() {
+            head.select_nth_unstable_by(n, |(_, prio1), (_, prio2)| prio2.cmp(prio1));
+            head.truncate(n);
+        }
+        head.sort_by(|(_, prio1), (_, prio2)| prio2.cmp(prio1));
+        head.into_iter().map(|(pushed, _)| &pushed.task).collect()
+    }
+
+    fn len(&self) -> usize {
+        self.pqueue.len()
+    }
+
+    fn is_empty(&self) -> bool {
+        self.pqueue.is_empty()
+    }
+}
+
+/// Scheduler for [`SchedulingPolicy::Priority`].
+#[derive(Default)]
+pub struct PriorityScheduler {
+    task_pqueue: TaskPqueue<usize>,
+}
+
+impl Scheduler for PriorityScheduler {
+    fn push(&mut self, task: Arc<ClientTask>) {
+        let prio = task.priority;
+        self.task_pqueue.push(task, prio);
+    }
+
+    fn peek(&self) -> Option<&Arc<ClientTask>> {
+        self.task_pqueue.peek()
+    }
+
+    fn pop(&mut self) -> Option<Arc<ClientTask>> {
+        self.task_pqueue.pop().map(|(task, _)| task)
+    }
+
+    fn remove(&mut self, is_task: &dyn Fn(&ClientTask) -> bool) -> Option<Arc<ClientTask>> {
+        self.task_pqueue.remove(is_task)
+    }
+
+    fn pending(&self) -> Vec<&Arc<ClientTask>> {
+        self.task_pqueue.sorted()
+    }
+
+    fn ahead(&self, n: usize) -> Vec<&Arc<ClientTask>> {
+        self.task_pqueue.head(n)
+    }
+
+    fn len(&self) -> usize {
+        self.task_pqueue.len()
+    }
+}
+
+/// Scheduler for [`SchedulingPolicy::Fifo`].
+#[derive(Default)]
+pub struct FifoScheduler {
+    task_queue: VecDeque<Arc<ClientTask>>,
+}
+
+impl Scheduler for FifoScheduler {
+    fn push(&mut self, task: Arc<ClientTask>) {
+        self.task_queue.push_back(task);
+    }
+
+    fn peek(&self) -> Option<&Arc<ClientTask>> {
+        self.task_queue.front()
+    }
+
+    fn pop(&mut self) -> Option<Arc<ClientTask>> {
+        self.task_queue.pop_front()
+    }
+
+    fn remove(&mut self, is_task: &dyn Fn(&ClientTask) -> bool) -> Option<Arc<ClientTask>> {
+        let position = self.task_queue.iter().position(|task| is_task(task))?;
+        self.task_queue.remove(position)
+    }
+
+    fn pending(&self) -> Vec<&Arc<ClientTask>> {
+        self.task_queue.iter().collect()
+    }
+
+    fn ahead(&self, n: usize) -> Vec<&Arc<ClientTask>> {
+        self.task_queue.iter().take(n).collect()
+    }
+
+    fn len(&self) -> usize {
+        self.task_queue.len()
+    }
+}
+
+/// Scheduler for [`SchedulingPolicy::ShortestFileFirst`].
+///
+/// The size of the input file, or the total size of a batch's files, is read once, when
+/// the task is pushed, see [`batch::input_size`]. Tasks whose input cannot be `stat`ed are
+/// treated as empty, since they'll fail right away.
+#[derive(Default)]
+pub struct ShortestFileScheduler {
+    task_pqueue: TaskPqueue<(Reverse<u64>, usize)>,
+}
+
+impl Scheduler for ShortestFileScheduler {
+    fn push(&mut self, task: Arc<ClientTask>) {
+        let size = batch::input_size(task.input_filepath());
+        let prio = (Reverse(size), task.priority);
+        self.task_pqueue.push(task, prio);
+    }
+
+    fn peek(&self) -> Option<&Arc<ClientTask>> {
+        self.task_pqueue.peek()
+    }
+
+    fn pop(&mut self) -> Option<Arc<ClientTask>> {
+        self.task_pqueue.pop().map(|(task, _)| task)
+    }
+
+    fn remove(&mut self, is_task: &dyn Fn(&ClientTask) -> bool) -> Option<Arc<ClientTask>> {
+        self.task_pqueue.remove(is_task)
+    }
+
+    fn pending(&self) -> Vec<&Arc<ClientTask>> {
+        self.task_pqueue.sorted()
+    }
+
+    fn ahead(&self, n: usize) -> Vec<&Arc<ClientTask>> {
+        self.task_pqueue.head(n)
+    }
+
+    fn len(&self) -> usize {
+        self.task_pqueue.len()
+    }
+}
+
+/// Scheduler for [`SchedulingPolicy::PriorityShortestFileFirst`].
+///
+/// Input sizes are read once, when tasks are pushed, as by the [`ShortestFileScheduler`].
+#[derive(Default)]
+pub struct PriorityShortestFileScheduler {
+    task_pqueue: TaskPqueue<(usize, Reverse<u64>)>,
+}
+
+impl Scheduler for PriorityShortestFileScheduler {
+    fn push(&mut self, task: Arc<ClientTask>) {
+        let size = batch::input_size(task.input_filepath());
+        let prio = (task.priority, Reverse(size));
+        self.task_pqueue.push(task, prio);
+    }
+
+    fn peek(&self) -> Option<&Arc<ClientTask>> {
+        self.task_pqueue.peek()
+    }
+
+    fn pop(&mut self) -> Option<Arc<ClientTask>> {
+        self.task_pqueue.pop().map(|(task, _)| task)
+    }
+
+    fn remove(&mut self, is_task: &dyn Fn(&ClientTask) -> bool) -> Option<Arc<ClientTask>> {
+        self.task_pqueue.remove(is_task)
+    }
+
+    fn pending(&self) -> Vec<&Arc<ClientTask>> {
+        self.task_pqueue.sorted()
+    }
+
+    fn ahead(&self, n: usize) -> Vec<&Arc<ClientTask>> {
+        self.task_pqueue.head(n)
+    }
+
+    fn len(&self) -> usize {
+        self.task_pqueue.len()
+    }
+}
+
+/// Cost of a single filter, in virtual time, for a task of weight `1`.
+const WFQ_FILTER_COST: u64 = 1_000_000;
+
+/// Scheduler for [`SchedulingPolicy::WeightedFair`].
+///
+/// Each task is stamped with a virtual start time when pushed: the later of the current
+/// virtual time and the finish time of the same client's previous task. Its finish time
+/// adds the task's cost (its number of filters) divided by its weight (its priority, plus one).
+/// The task with the earliest finish time runs first, so a client flooding the server
+/// only delays its own tasks.
+#[derive(Default)]
+pub struct WeightedFairScheduler {
+    /// Tasks keyed by their virtual `(finish, start)` times.
+    task_pqueue: TaskPqueue<Reverse<(u64, u64)>>,
+    /// Start time of the last task popped.
+    virtual_time: u64,
+    /// Finish time of the last task pushed by each client.
+    last_finish: HashMap<u32, u64>,
+}
+
+impl Scheduler for WeightedFairScheduler {
+    fn push(&mut self, task: Arc<ClientTask>) {
+        let weight = task.priority as u64 + 1;
+        let cost = task.filter_demand().len() as u64 * WFQ_FILTER_COST / weight;
+
+        let start = self
+            .last_finish
+            .get(&task.client_pid)
+            .map_or(self.virtual_time, |&finish| finish.max(self.virtual_time));
+        let finish = start + cost;
+
+        self.last_finish.insert(task.client_pid, finish);
+        self.task_pqueue.push(task, Reverse((finish, start)));
+    }
+
+    fn peek(&self) -> Option<&Arc<ClientTask>> {
+        self.task_pqueue.peek()
+    }
+
+    fn pop(&mut self) -> Option<Arc<ClientTask>> {
+        let (task, Reverse((_, start))) = self.task_pqueue.pop()?;
+        self.virtual_time = self.virtual_time.max(start);
+        if self.task_pqueue.is_empty() {
+            // No client is backlogged anymore: their history is irrelevant.
+            self.last_finish.clear();
+        }
+
+        Some(task)
+    }
+
+    fn remove(&mut self, is_task: &dyn Fn(&ClientTask) -> bool) -> Option<Arc<ClientTask>> {
+        self.task_pqueue.remove(is_task)
+    }
+
+    fn pending(&self) -> Vec<&Arc<ClientTask>> {
+        self.task_pqueue.sorted()
+    }
+
+    fn ahead(&self, n: usize) -> Vec<&Arc<ClientTask>> {
+        self.task_pqueue.head(n)
+    }
+
+    fn len(&self) -> usize {
+        self.task_pqueue.len()
+    }
+}
+
+/// Virtual service a queue of weight `1` receives for each filter it runs.
+const QUEUE_FILTER_SERVICE: u64 = 1_000_000;
+
+/// A queue, or QoS class, of pending tasks: its configuration, the scheduler ordering
+/// its tasks, and the count of filters its running tasks are using.
+pub struct TaskQueue {
+    pub config: QueueConfig,
+    scheduler: Box<dyn Scheduler>,
+    pub filters_count: RunningFilters,
+    /// Virtual service this queue has received, i.e. filters run divided by its weight.
+    /// The server always pops from the runnable queue which received the least service.
+    service: u64,
+    /// Pending tasks past the head that may run ahead of it, see
+    /// [`ServerConfig::scan_depth`](super::config::ServerConfig::scan_depth).
+    scan_depth: usize,
+    /// Number of pending tasks by the filters they'd run, see [`ClientTask::filter_demand`].
+    /// Tasks with the same demand are as runnable as each other, so only each demand is
+    /// checked against the limits, rather than each task.
+    demands: HashMap<Vec<Filter>, usize>,
+}
+
+impl TaskQueue {
+    pub fn new(config: QueueConfig, scheduling_policy: SchedulingPolicy, scan_depth: usize) -> Self {
+        TaskQueue {
+            config,
+            scheduler: scheduling_policy.build(),
+            filters_count: RunningFilters::default(),
+            service: 0,
+            scan_depth,
+            demands: HashMap::new(),
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.config.name
+    }
+
+    /// Add a task to this queue.
+    ///
+    /// `min_service` is the least service received by any other backlogged queue: a queue
+    /// that was idle catches up to it, instead of using its idle time to monopolize the server.
+    pub fn push(&mut self, task: Arc<ClientTask>, min_service: Option<u64>) {
+        if self.scheduler.is_empty() {
+            if let Some(min_service) = min_service {
+                self.service = self.service.max(min_service);
+            }
+        }
+        *self.demands.entry(task.filter_demand()).or_default() += 1;
+        self.scheduler.push(task);
+    }
+
+    /// Service received by this queue, if it has pending tasks.
+    pub fn backlogged_service(&self) -> Option<u64> {
+        (!self.scheduler.is_empty()).then_some(self.service)
+    }
+
+    /// The first of this queue's next tasks that can be run, given both the server-wide running
+    /// filters and limits, and this queue's own budget, with its position among the pending
+    /// tasks: its head, or one of the `scan_depth` tasks after it, if the head can't be run.
+    ///
+    /// Only the distinct demands of the pending tasks are checked against the limits, so that
+    /// when none can run, as when the server is busy, the tasks aren't looked through at all.
+    pub fn next_runnable(&self, running: &RunningFilters, limits: &FiltersConfig) -> Option<(usize, Arc<ClientTask>)> {
+        self.next_where(&|demand| {
+            running.can_run_pipeline(limits, demand) && self.filters_count.can_run_pipeline(&self.config.filters_config, demand)
+        })
+    }
+
+    /// The first of this queue's next tasks that a worker can run, given the filters it runs
+    /// and its own limits, as [`TaskQueue::next_runnable`] finds, but regardless of this
+    /// queue's budget, which is its share of the server's own filters.
+    pub fn next_runnable_on(&self, running: &RunningFilters, limits: &FiltersConfig) -> Option<(usize, Arc<ClientTask>)> {
+        self.next_where(&|demand| running.can_run_pipeline(limits, demand))
+    }
+
+    /// The first of this queue's next tasks whose demand is `runnable`, with its position.
+    fn next_where(&self, runnable: &dyn Fn(&Vec<Filter>) -> bool) -> Option<(usize, Arc<ClientTask>)> {
+        let runnable = self.demands
+            .keys()
+            .filter(|demand| runnable(demand))
+            .collect::<HashSet<_>>();
+        if runnable.is_empty() {
+            return None
+        }
+        let ahead = match self.scan_depth {
+            // Unlike peeking, listing the tasks ahead may have to select them among the others.
+            0 => self.scheduler.peek().into_iter().collect(),
+            depth => self.scheduler.ahead(depth + 1),
+        };
+        ahead
+            .into_iter()
+            .enumerate()
+            .find(|(_, task)| runnable.contains(&task.filter_demand()))
+            .map(|(position, task)| (position, Arc::clone(task)))
+    }
+
+    /// Remove this queue's next task, charging the queue for the filters it'll use.
+    pub fn pop(&mut self) -> Option<Arc<ClientTask>> {
+        let task = self.scheduler.pop()?;
+        self.charge(&task);
+        Some(task)
+    }
+
+    /// Remove `runnable`, one of this queue's pending tasks, which may not be its next one,
+    /// see [`TaskQueue::next_runnable`], charging the queue for the filters it'll use.
+    pub fn pop_runnable(&mut self, runnable: &ClientTask) -> Option<Arc<ClientTask>> {
+        let task = match self.scheduler.peek() {
+            Some(next) if **next == *runnable => self.scheduler.pop()?,
+            // Tasks that are equal are just as runnable.
+            _ => self.scheduler.remove(&|task| *task == *runnable)?,
+        };
+        self.charge(&task);
+        Some(task)
+    }
+
+    /// Account for `task` having left the queue to run.
+    fn charge(&mut self, task: &ClientTask) {
+        let weight = self.config.weight.max(1) as u64;
+        self.service += task.filter_demand().len() as u64 * QUEUE_FILTER_SERVICE / weight;
+        self.forget_demand(task);
+    }
+
+    /// Remove the pending task for which `is_task` holds, if any, without charging the queue.
+    pub fn remove(&mut self, is_task: &dyn Fn(&ClientTask) -> bool) -> Option<Arc<ClientTask>> {
+        let task = self.scheduler.remove(is_task)?;
+        self.forget_demand(&task);
+        Some(task)
+    }
+
+    /// Count `task`, which left the queue, out of [`TaskQueue::demands`].
+    fn forget_demand(&mut self, task: &ClientTask) {
+        if let Entry::Occupied(mut count) = self.demands.entry(task.filter_demand()) {
+            *count.get_mut() -= 1;
+            if *count.get() == 0 {
+                count.remove();
+            }
+        }
+    }
+
+    /// Pending tasks, in the order this queue's scheduler would pop them.
+    pub fn pending(&self) -> Vec<&Arc<ClientTask>> {
+        self.scheduler.pending()
+    }
+}
+
+/// Number of concluded tasks, and of each filter's stages, whose durations are averaged,
+/// see [`TaskDurations`].
+const DURATION_HISTORY: usize = 32;
+
+/// How long the tasks the server concluded most recently took to run, and each filter's
+/// stages of them, to estimate how long pending ones will wait, and run.
+#[derive(Debug, Default)]
+pub struct TaskDurations {
+    recent: VecDeque<Duration>,
+    by_filter: HashMap<Filter, VecDeque<Duration>>,
+}
+
+impl TaskDurations {
+    /// Record that a task took `duration` to run, forgetting the oldest one recorded, if
+    /// there are too many.
+    pub fn record(&mut self, duration: Duration) {
+        push_duration(&mut self.recent, duration);
+    }
+
+    /// Record how long each of the `stages` of a task's pipeline ran, by its filter.
+    pub fn record_stages(&mut self, stages: &[StageTiming]) {
+        for stage in stages {
+            push_duration(self.by_filter.entry(stage.filter.clone()).or_default(), stage.end.saturating_sub(stage.start));
+        }
+    }
+
+    /// Mean duration of the recent tasks, if any concluded yet.
+    pub fn mean(&self) -> Option<Duration> {
+        mean_duration(&self.recent)
+    }
+
+    /// How long a pipeline of `filters` should take to run: as long as its slowest stage, as
+    /// they run at once, given how long each filter's recent stages took, or as long as recent
+    /// tasks took, if one of its filters is yet to run. `None` if no task concluded yet.
+    pub fn estimate_run(&self, filters: &[Filter]) -> Option<Duration> {
+        filters
+            .iter()
+            .map(|filter| self.by_filter.get(filter).and_then(mean_duration))
+            .try_fold(Duration::ZERO, |slowest, stage| stage.map(|stage| slowest.max(stage)))
+            .or_else(|| self.mean())
+    }
+
+    /// How long a task will wait to start behind the pipelines `ahead` of it in its queue,
+    /// while those `running`, for as long as each has, run: the server is assumed to keep
+    /// running as many tasks at once, each starting as soon as one of them is done, see
+    /// [`TaskDurations::estimate_run`].
+    pub fn estimate_wait(&self, ahead: &[&[Filter]], running: &[(&[Filter], Duration)]) -> Option<Duration> {
+        // When each of the server's slots for a task frees up.
+        let mut slots = running
+            .iter()
+            .map(|(filters, elapsed)| Some(self.estimate_run(filters)?.saturating_sub(*elapsed)))
+            .collect::<Option<Vec<_>>>()?;
+        if slots.is_empty() {
+            slots.push(Duration::ZERO);
+        }
+        for filters in ahead {
+            let run = self.estimate_run(filters)?;
+            *slots.iter_mut().min()? += run;
+        }
+        slots.into_iter().min()
+    }
+}
+
+/// Add `duration` to the most recent `durations`, forgetting the oldest one, if there are too
+/// many, see [`DURATION_HISTORY`].
+fn push_duration(durations: &mut VecDeque<Duration>, duration: Duration) {
+    if durations.len() == DURATION_HISTORY {
+        durations.pop_front();
+    }
+    durations.push_back(duration);
+}
+
+/// Mean of `durations`, if there are any.
+fn mean_duration(durations: &VecDeque<Duration>) -> Option<Duration> {
+    let count = u32::try_from(durations.len()).ok().filter(|&count| count > 0)?;
+    Some(durations.iter().sum::<Duration>() / count)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use crate::core::{filter::Filter, limits::RunningFilters, server::config::FiltersConfig, testing::TempDir};
+
+    use super::*;
+
+    fn task(client_pid: u32, priority: usize, transformations: Vec<Filter>) -> Arc<ClientTask> {
+        Arc::new(ClientTask::new(
+            client_pid,
+            priority,
+            PathBuf::from("in/file"),
+            PathBuf::from(format!("out/file-{client_pid}-{priority}-{}", transformations.len())),
+            transformations
+        ))
+    }
+
+    fn limits() -> FiltersConfig {
+        FiltersConfig { nop: 3, ..FiltersConfig::default() }
+    }
+
+    fn drain(scheduler: &mut dyn Scheduler) -> Vec<(u32, usize)> {
+        let running = RunningFilters::default();
+        let mut popped = Vec::new();
+        while let Some(task) = scheduler.peek() {
+            if !running.can_run_pipeline(&limits(), &task.transformations) {
+                break
+            }
+            let task = scheduler.pop().unwrap();
+            popped.push((task.client_pid, task.priority));
+        }
+        popped
+    }
+
+    #[test]
+    fn policy_parsing_works() {
+        assert_eq!("fifo".parse(), Ok(SchedulingPolicy::Fifo));
+        assert_eq!("Weighted-Fair".parse(), Ok(SchedulingPolicy::WeightedFair));
+        assert_eq!("priority-shortest-file".parse(), Ok(SchedulingPolicy::PriorityShortestFileFirst));
+        assert_eq!(
+            "lifo".parse::<SchedulingPolicy>(),
+            Err(SchedulingPolicyParseError(String::from("lifo")))
+        );
+    }
+
+    #[test]
+    fn priority_and_fifo_order() {
+        for policy in [SchedulingPolicy::Priority, SchedulingPolicy::Fifo] {
+            let mut scheduler = policy.build();
+            scheduler.push(task(1, 1, vec![Filter::Nop]));
+            scheduler.push(task(2, 5, vec![Filter::Nop]));
+            scheduler.push(task(3, 3, vec![Filter::Nop]));
+
+            let expected = match policy {
+                SchedulingPolicy::Priority => vec![(2, 5), (3, 3), (1, 1)],
+                _ => vec![(1, 1), (2, 5), (3, 3)],
+            };
+            let pending = scheduler
+                .pending()
+                .into_iter()
+                .map(|t| (t.client_pid, t.priority))
+                .collect::<Vec<_>>();
+            assert_eq!(pending, expected);
+            assert_eq!(drain(scheduler.as_mut()), expected);
+        }
+    }
+
+    #[test]
+    fn smaller_files_run_first_within_a_priority() {
+        let dir = TempDir::new("scheduler");
+        let sized = |client_pid, priority, size| {
+            let input = dir.join(format!("in-{client_pid}"));
+            std::fs::write(&input, vec![0; size]).unwrap();
+            Arc::new(ClientTask::new(client_pid, priority, input, dir.join("out"), vec![Filter::Nop]))
+        };
+
+        for policy in [SchedulingPolicy::ShortestFileFirst, SchedulingPolicy::PriorityShortestFileFirst] {
+            let mut scheduler = policy.build();
+            scheduler.push(sized(1, 1, 1000));
+            scheduler.push(sized(2, 1, 10));
+            scheduler.push(sized(3, 5, 100));
+
+            let expected = match policy {
+                SchedulingPolicy::ShortestFileFirst => vec![(2, 1), (3, 5), (1, 1)],
+                _ => vec![(3, 5), (2, 1), (1, 1)],
+            };
+            assert_eq!(drain(scheduler.as_mut()), expected);
+        }
+    }
+
+    #[test]
+    fn blocked_head_is_not_skipped() {
+        let mut scheduler = SchedulingPolicy::Priority.build();
+        scheduler.push(task(1, 5, vec![Filter::Nop; 4]));
+        scheduler.push(task(2, 1, vec![Filter::Nop]));
+
+        assert!(drain(scheduler.as_mut()).is_empty());
+        assert_eq!(scheduler.len(), 2);
+    }
+
+    #[test]
+    fn blocked_head_is_skipped_within_scan_depth() {
+        let config = QueueConfig { name: String::from("default"), weight: 1, filters_config: limits() };
+        let running = RunningFilters::default();
+        for (scan_depth, runnable) in [(0, None), (1, None), (2, Some(3))] {
+            let mut queue = TaskQueue::new(config.clone(), SchedulingPolicy::Fifo, scan_depth);
+            queue.push(task(1, 1, vec![Filter::Nop; 4]), None);
+            queue.push(task(2, 1, vec![Filter::Nop; 4]), None);
+            queue.push(task(3, 1, vec![Filter::Nop]), None);
+
+            let next = queue.next_runnable(&running, &limits());
+            assert_eq!(next.and_then(|(_, task)| queue.pop_runnable(&task)).map(|task| task.client_pid), runnable);
+        }
+    }
+
+    #[test]
+    fn runnable_tasks_are_found_by_demand() {
+        let config = QueueConfig { name: String::from("default"), weight: 1, filters_config: limits() };
+        let mut queue = TaskQueue::new(config, SchedulingPolicy::Priority, 8);
+        for client_pid in 1..=4 {
+            queue.push(task(client_pid, 10 - client_pid as usize, vec![Filter::Nop; 3]), None);
+        }
+        queue.push(task(5, 1, vec![Filter::Nop]), None);
+        assert_eq!(queue.demands.len(), 2);
+
+        let mut running = RunningFilters::default();
+        running += &vec![Filter::Nop; 3];
+        assert!(queue.next_runnable(&running, &limits()).is_none());
+
+        running -= &vec![Filter::Nop; 2];
+        let (position, next) = queue.next_runnable(&running, &limits()).unwrap();
+        assert_eq!((position, next.client_pid), (4, 5));
+        assert_eq!(queue.pop_runnable(&next).map(|task| task.client_pid), Some(5));
+        assert!(queue.next_runnable(&running, &limits()).is_none());
+
+        assert!(queue.remove(&|task| task.client_pid == 2).is_some());
+        let (position, next) = queue.next_runnable(&RunningFilters::default(), &limits()).unwrap();
+        assert_eq!((position, next.client_pid, queue.pending().len()), (0, 1, 3));
+        while queue.pop().is_some() {}
+        assert!(queue.demands.is_empty());
+    }
+
+    #[test]
+    fn equal_tasks_are_both_kept() {
+        for policy in [
+            SchedulingPolicy::Priority, SchedulingPolicy::ShortestFileFirst, SchedulingPolicy::PriorityShortestFileFirst,
+            SchedulingPolicy::WeightedFair
+        ] {
+            let mut scheduler = policy.build();
+            let submitted = task(1, 1, vec![Filter::Nop]);
+            scheduler.push(Arc::clone(&submitted));
+            scheduler.push(Arc::new(ClientTask::clone(&submitted)));
+            assert_eq!(scheduler.len(), 2);
+
+            assert!(scheduler.remove(&|task| *task == *submitted).is_some());
+            assert_eq!(drain(scheduler.as_mut()), [(1, 1)]);
+        }
+    }
+
+    #[test]
+    fn pending_tasks_can_be_removed() {
+        for policy in [SchedulingPolicy::Priority, SchedulingPolicy::Fifo, SchedulingPolicy::WeightedFair] {
+            let mut scheduler = policy.build();
+            for client_pid in 1..=3 {
+                scheduler.push(task(client_pid, 1, vec![Filter::Nop]));
+            }
+
+            let removed = scheduler.remove(&|task| task.client_pid == 2);
+            assert_eq!(removed.map(|task| task.client_pid), Some(2));
+            assert!(scheduler.remove(&|task| task.client_pid == 2).is_none());
+            let mut remaining = drain(scheduler.as_mut()).into_iter().map(|(pid, _)| pid).collect::<Vec<_>>();
+            remaining.sort();
+            assert_eq!(remaining, vec![1, 3]);
+        }
+    }
+
+    #[test]
+    fn weighted_fair_interleaves_clients() {
+        let mut scheduler = SchedulingPolicy::WeightedFair.build();
+        for priority in 0..3 {
+            scheduler.push(task(1, priority, vec![Filter::Nop; 2]));
+        }
+        scheduler.push(task(2, 0, vec![Filter::Nop; 3]));
+
+        // Client 2's task is interleaved with client 1's, rather than waiting for all of them.
+        let popped = drain(scheduler.as_mut());
+        assert_eq!(popped.iter().map(|(pid, _)| *pid).collect::<Vec<_>>(), vec![1, 2, 1, 1]);
+    }
+
+    #[test]
+    fn waits_are_estimated_from_recent_durations() {
+        let secs = Duration::from_secs;
+        let (nop, gcompress) = ([Filter::Nop].as_slice(), [Filter::Nop, Filter::Gcompress].as_slice());
+        let mut durations = TaskDurations::default();
+        assert_eq!(durations.estimate_wait(&[], &[]), Some(Duration::ZERO));
+        assert_eq!(durations.estimate_wait(&[nop], &[(nop, secs(0))]), None);
+
+        durations.record(secs(1));
+        durations.record(secs(3));
+        assert_eq!(durations.mean(), Some(secs(2)));
+        // Two running, one for 1s already, and three ahead, each run as soon as a slot frees up.
+        assert_eq!(durations.estimate_wait(&[nop; 3], &[(nop, secs(1)), (nop, secs(0))]), Some(secs(4)));
+        assert_eq!(durations.estimate_wait(&[], &[(nop, secs(0)), (nop, secs(0))]), Some(secs(2)));
+        assert_eq!(durations.estimate_wait(&[nop], &[]), Some(secs(2)));
+
+        // Pipelines take as long as their slowest stage, once each of its filters ran.
+        let stage = |filter, secs: u64| StageTiming { filter, start: Duration::ZERO, end: Duration::from_secs(secs) };
+        durations.record_stages(&[stage(Filter::Nop, 1), stage(Filter::Gcompress, 5)]);
+        durations.record_stages(&[stage(Filter::Nop, 3)]);
+        assert_eq!((durations.estimate_run(nop), durations.estimate_run(gcompress)), (Some(secs(2)), Some(secs(5))));
+        assert_eq!(durations.estimate_run(&[Filter::Bcompress]), Some(secs(2)));
+        assert_eq!(durations.estimate_wait(&[gcompress, nop], &[(nop, secs(1))]), Some(secs(8)));
+
+        for _ in 0..DURATION_HISTORY {
+            durations.record(Duration::from_secs(5));
+        }
+        assert_eq!(durations.mean(), Some(Duration::from_secs(5)));
+    }
+}
@@ -1,22 +1,76 @@
 use std::{
-    collections::HashMap, thread::{self, ThreadId, JoinHandle}, fmt::Write, io,
-    sync::{mpsc::{Receiver, Sender, self}, Arc},
-    os::unix::net::UnixDatagram, path::PathBuf, ops::{SubAssign, AddAssign},
+    collections::{hash_map::Entry, BTreeMap, HashMap, VecDeque}, thread::{self, JoinHandle}, fs, io, net::TcpListener,
+    sync::Arc, time::{Duration, Instant},
+    os::unix::net::{UnixListener, UnixStream}, path::{Path, PathBuf}, ops::{SubAssign, AddAssign},
 };
 
-use bincode::Error as BincodeError;
-use priority_queue::PriorityQueue;
+use libc::{SIGINT, SIGTERM};
+use tokio::{signal::unix::{signal, Signal, SignalKind}, sync::mpsc::{self, Receiver, Sender}};
+use uuid::Uuid;
 
 use crate::core::{
+    accounting::Accounting,
+    batch,
+    checkpoint,
+    chunking,
     client_task::ClientTask,
+    filter::Filter,
     limits::RunningFilters,
-    monitor::{Monitor, MonitorResult, MonitorError, MonitorBuildError, MonitorSuccess},
-    messaging::{self, MessageToClient, MessageToServer, ClientRequest}};
+    monitor::{
+        BatchFileResult, BatchSummary, Monitor, MonitorResult, MonitorError, MonitorBuildError,
+        MonitorProgress, MonitorSuccess, PartialOutput, TaskSummary
+    },
+    messaging::{
+        self, Codec, CodecError, MessageReceiver, MessageToClient, MessageToServer, ClientRequest, RequestFailure, RequestState, Sequenced,
+        TaskEvent, TaskId, TruncatedDatagram, DroppedMessage, WireFormat, MAX_DATAGRAM_PAYLOAD
+    },
+    health::Health,
+    remote,
+    status::{self, QueueStatus, QueuedTask, RunningTask, ServerStats, ServerStatus},
+    task_log::{TaskLogEvent, TaskLogs},
+    transport::{self, Credentials, Incoming, Peer, SocketNamespace, Transport}
+};
+
+use super::{
+    audit::{AuditEvent, AuditLog},
+    auth::{self, PathPolicy},
+    cache::ResultCache,
+    config::ServerConfig,
+    coordinator::{self, CoordinatorMessage, RemoteWorker, WorkerEvent, WorkerMessage},
+    dry_run::{self, DryRunReport},
+    environment::FilterEnvironment,
+    handover::Handover,
+    inline,
+    monitor_pool::MonitorPool,
+    numbering::TaskCounter,
+    rate_limit::RateLimiter,
+    optimizer,
+    pool::WorkerPool,
+    scheduler::{TaskDurations, TaskQueue},
+    store::OutputStore,
+    streaming,
+    wasm::{WasmError, WasmRuntime},
+};
+
+/// How many of the tasks that most recently finished or failed the server keeps, to tell
+/// clients of, see [`ServerState::send_history`].
+pub const HISTORY_LEN: usize = 100;
 
-use super::config::{ServerConfig, FiltersConfig};
+/// Most messages from monitors, and streamed tasks, that may wait for the server's event loop
+/// to handle them, see [`ServerState::next_message`].
+pub const MESSAGE_BACKLOG: usize = 1024;
 
-/// Type of the closure used to spawn the socket listener.
-pub type UdSocketListener = Box<dyn FnOnce() -> () + Send + 'static>;
+/// How often the server checks its running filter counts against its running tasks, see
+/// [`ServerState::reconcile_filters`].
+pub const RECONCILE_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Times in a row reading from the transport may fail before the server gives up on it, see
+/// [`ServerState::next_message`].
+pub const MAX_READ_FAILURES: u32 = 8;
+
+/// How long the server waits to read from the transport again after it first fails to, which
+/// doubles with every failure in a row, see [`MAX_READ_FAILURES`].
+pub const READ_RETRY_DELAY: Duration = Duration::from_millis(50);
 
 /// State a server needs to operate and communicate.
 ///
@@ -24,42 +78,140 @@ pub type UdSocketListener = Box<dyn FnOnce() -> () + Send + 'static>;
 /// [`ServerConfig`].
 pub struct ServerState {
     /// Counter assigned to each task after reception. Useful when reporting the server's
-    /// status to a client.
-    task_counter: usize,
+    /// status to a client. Carries on from the server's last run, if it was opened, see
+    /// [`ServerState::open_task_counter`].
+    task_counter: TaskCounter,
 
-    /// Priority queue of tasks sent by clients. All tasks must therefore have a `usize`
-    /// priority.
-    task_pqueue: PriorityQueue<ClientTask, usize>,
+    /// Queues of pending tasks sent by clients, each ordered according to the server's
+    /// configured [`SchedulingPolicy`](super::scheduler::SchedulingPolicy).
+    ///
+    /// The first queue is always the [`DEFAULT_QUEUE`](crate::core::client_task::DEFAULT_QUEUE).
+    queues: Vec<TaskQueue>,
 
-    /// Count of all the filters the server is currently running.
+    /// Count of all the filters the server is currently running, across all queues.
     filters_count: RunningFilters,
-    /// Association between `ThreadId`s and the `Monitor`s each represents, where a
+    /// Association between task numbers and the `Monitor`s running each task, where a
     /// `Monitor` is responsible for running a pipeline.
-    running_tasks: HashMap<ThreadId, Monitor>,
+    running_tasks: HashMap<usize, Monitor>,
+    /// When the server started, to report its uptime.
+    started_at: Instant,
+    /// How long recently concluded tasks took, to estimate how long pending ones will wait.
+    task_durations: TaskDurations,
+    /// Totals of the tasks the server handled since it started, reported in its status.
+    stats: ServerStats,
+    /// When the running filter counts were last checked, see [`ServerState::reconcile_filters`].
+    reconciled_at: Instant,
+    /// Workers registered with the server, by ID, which run pending tasks on its behalf,
+    /// within their own limits, see [`ServerState::listen_for_workers`]. Only tasks the
+    /// server runs itself count towards its own limits, while those of every task count
+    /// towards its queue's.
+    workers: BTreeMap<usize, RemoteWorker>,
+    /// When the workers were last sent a heartbeat, see [`ServerState::heartbeat_workers`].
+    heartbeat_at: Instant,
 
     /// MPSC sender to be given to:
     /// * each monitor in order to communicate pipeline results back to the server.
-    /// * the thread listening to the `UnixDatagram` socket, which uses this sender
-    ///   to inform the server of new requests.
+    /// * the thread accepting streamed tasks, which uses this sender to hand them over
+    ///   to the server.
     ///
-    /// The receiving end is on the server's main thread.
+    /// The receiving end is read by the server's event loop, see [`ServerState::next_message`].
+    /// It holds up to [`MESSAGE_BACKLOG`] messages, past which senders wait for room, but
+    /// for progress reports, which are dropped.
     sender: Sender<messaging::MessageToServer>,
-    /// Receiving end of the channel used to receive messages from monitors, and from
-    /// the unix datagram socket listening thread.
-    pub receiver: Receiver<messaging::MessageToServer>,
-
-    /// Unix datagram socket used to receive messages from clients.
-    /// The server's main thread doesn't use it, delegating this task to a thread that then
-    /// manages reading messages and sending them back to the main thread via an `mpsc::channel`
-    /// to take advantage of its static typing guarantees.
-    udsocket: Arc<UnixDatagram>,
-    /// Handle of the thread spawned to manage the `UnixDatagram` socket.
-    ///
-    /// TODO
-    /// It needs to be stored to allow the graceful termination of the server: as soon as the
-    /// main threads receives a e.g. `SIGINT/SIGTERM`, this thread will be responsible for
-    /// closing the socket and freeing resources.
-    udsock_mngr: Option<JoinHandle<()>>,
+    /// Receiving end of the channel used to receive messages from monitors, and from the
+    /// thread accepting streamed tasks.
+    receiver: Receiver<messaging::MessageToServer>,
+
+    /// Transport, e.g. a unix datagram socket, used to exchange messages with clients.
+    transport: Arc<dyn Transport>,
+    /// The transport, as read from by the server's event loop, alongside its other events,
+    /// see [`ServerState::next_message`]. `None` once reading from it failed too many times.
+    incoming: Option<Incoming>,
+    /// Times reading from `incoming` failed in a row, and when it's read from again, if it
+    /// last failed.
+    read_failures: (u32, Option<Instant>),
+    /// Reassembles the requests read from `incoming` from their parts.
+    messages: MessageReceiver,
+    /// Streams of the termination signals `SIGINT` and `SIGTERM`, once they're listened
+    /// for, see [`ServerState::listen_for_signals`].
+    signals: Option<(Signal, Signal)>,
+    /// Handle of the thread accepting the connections of streamed tasks, see
+    /// [`ServerState::spawn_stream_listener`].
+    stream_listener: Option<JoinHandle<()>>,
+    /// Handle of the thread accepting workers, if the server listens for any, see
+    /// [`ServerState::listen_for_workers`].
+    worker_listener: Option<JoinHandle<()>>,
+    /// Whether the server is shutting down, no longer scheduling new requests, see
+    /// [`ServerState::shutdown`].
+    shutting_down: bool,
+    /// Executable exec'd afresh on a [`ClientRequest::Reexec`], if the server may be, see
+    /// [`ServerState::enable_reexec`].
+    executable: Option<PathBuf>,
+    /// The handover the server waits for its running tasks to conclude before, if asked for
+    /// one, see [`ServerState::start_handover`].
+    handover: Option<PendingHandover>,
+    /// Encoding of the messages exchanged with clients.
+    codec: WireFormat,
+    /// Number of the next notification to each client, by PID, about each of its requests,
+    /// see [`Sequenced`]. Forgotten once a request's last notification is sent.
+    next_seq: HashMap<(u32, Uuid), u64>,
+    /// Notifications sent to each client, by PID, that it is yet to acknowledge, by request
+    /// and number, see [`ServerState::retransmit_unacked`].
+    unacked: HashMap<u32, BTreeMap<(Uuid, u64), Unacked>>,
+    /// Who each client, by PID, last made a request from, to whom its notifications are sent,
+    /// see [`ServerState::register_peer`].
+    peers: HashMap<u32, Peer>,
+    /// Clients subscribed to task events, by PID, with the ID of their subscription request,
+    /// see [`ServerState::subscribe`].
+    subscribers: HashMap<u32, Uuid>,
+    /// The last [`HISTORY_LEN`] tasks to finish or fail, oldest first, each with the last
+    /// message sent to its client, see [`ServerState::send_history`].
+    history: VecDeque<(TaskEvent, MessageToClient)>,
+    /// What the server did with each pending or running task, and with the last
+    /// [`HISTORY_LEN`] to finish or fail, see [`ServerState::send_task_log`].
+    task_logs: TaskLogs,
+    /// What the last tasks to finish or fail amounted to, see [`ServerState::send_report`].
+    accounting: Accounting,
+    /// Span of each pending or running task, by its request's ID, see [`ClientTask::span`].
+    task_spans: HashMap<Uuid, tracing::Span>,
+    /// Clients waiting for each pending or running request to conclude, by its ID, each by
+    /// PID, with the ID of its own request, see [`ServerState::wait_for`].
+    waiters: HashMap<Uuid, Vec<(u32, Uuid)>>,
+    /// The ID of each request submitted with an idempotency key, and when, by the key and
+    /// the UID of its client, see [`ServerState::deduplicate`].
+    idempotency_keys: HashMap<(Option<u32>, String), (Uuid, Instant)>,
+    /// See [`ServerConfig::idempotency_window`].
+    idempotency_window: Duration,
+    /// See [`ServerConfig::inline_max_size`].
+    inline_max_size: usize,
+    /// Each client's bucket of requests, if they are rate limited, see
+    /// [`ServerState::rate_limit`].
+    rate_limiter: Option<RateLimiter>,
+
+    /// Streams over which the outputs of streamed tasks are to be sent back, by the PID of
+    /// the client that sent each task, see [`ClientTask::stream`].
+    streams: HashMap<u32, UnixStream>,
+    /// Threads sending the outputs of finished streamed tasks back to their clients.
+    stream_senders: Vec<JoinHandle<()>>,
+
+    /// Workers of external filters, shared with every monitor, if the server was
+    /// configured with a pool, see [`ServerState::start_worker_pool`].
+    pool: Option<Arc<WorkerPool>>,
+    /// Threads monitors run on, which bound how many tasks run at once, once started, see
+    /// [`ServerState::start_monitor_pool`].
+    monitors: Option<MonitorPool>,
+    /// Record of every change in the lifecycle of every task, if the server was configured
+    /// with one, see [`ServerState::open_audit_log`].
+    audit: Option<AuditLog>,
+    /// Outputs of past tasks, shared with every monitor, if the server was configured to
+    /// cache them, see [`ServerState::open_result_cache`].
+    cache: Option<Arc<ResultCache>>,
+    /// Store of outputs, named by their contents, that tasks may be written to, if the
+    /// server was configured with one, see [`ServerState::open_output_store`].
+    store: Option<OutputStore>,
+    /// Engine WASM filters are run by, shared with every monitor, if the server has any,
+    /// see [`ServerState::load_wasm_filters`].
+    wasm: Option<Arc<WasmRuntime>>,
 
     /// Path to the folder where the server and clients operate from.
     ///
@@ -68,35 +220,120 @@ pub struct ServerState {
     /// non-temporary files created manually for server and client sockets, to
     /// assuming both know where to find each other; these are shortcuts - a
     /// serious project would never have this.
-    udsock_dir: PathBuf
+    udsock_dir: PathBuf,
+    /// Namespace the sockets in `udsock_dir` are bound in, see [`SocketNamespace`].
+    socket_namespace: SocketNamespace,
+    /// Most tasks that may be pending at once, see [`ServerConfig::queue_capacity`].
+    queue_capacity: Option<usize>,
+    /// Most filters a task's pipeline may have, see [`ServerConfig::max_transformations`].
+    max_transformations: usize,
+    /// Directories tasks may read and write files in, see [`ServerConfig::path_policy`].
+    path_policy: PathPolicy,
+    /// Variables tasks may set for their filters, see [`ServerConfig::environment`].
+    environment: FilterEnvironment,
+    /// Whether filters are sandboxed in namespaces, where tasks can't choose the directory
+    /// they run in, see [`Sandbox::namespaces`](super::sandbox::Sandbox::namespaces).
+    jailed_filters: bool,
+    /// See [`ServerConfig::retransmit_after`].
+    retransmit_after: Duration,
+    /// See [`ServerConfig::max_transmissions`].
+    max_transmissions: u32,
+    /// Filters with a server-wide limit of `0`, which tasks using them could never run.
+    disabled_filters: Vec<Filter>,
+    /// Every filter the server knows of, see [`FiltersConfig::filters`](super::config::FiltersConfig::filters).
+    known_filters: Vec<Filter>
+}
+
+/// A handover asked for by the client with `client_pid`, with its request `request_id`, at
+/// `started_at`, see [`ServerState::start_handover`].
+struct PendingHandover {
+    client_pid: u32,
+    request_id: Uuid,
+    started_at: Instant,
+    /// Whether the tasks still running were killed, having been waited for long enough.
+    killed: bool,
+}
+
+/// A notification sent to a client, which it is yet to acknowledge.
+struct Unacked {
+    bytes: Vec<u8>,
+    sent_at: Instant,
+    transmissions: u32,
 }
 
 /// Errors that a server's operations can raise.
 #[derive(Debug)]
 pub enum ServerError {
-    /// Spawning the thread that would manage the unix domain socket failed.
-    UdSocketManagerSpawnError(io::Error),
+    /// Spawning the thread accepting streamed tasks, or one sending back the output of
+    /// one, failed.
+    StreamThreadSpawnError(io::Error),
     /// Writing to the server's unix domain socket failed.
-    ///
-    /// Notice that `UnixDatagram::send_to` returning "`0` bytes written" could also
-    /// be an error, but it is not handled.
     UdSocketWriteError(io::Error),
-    /// The messages sent by the server are never empty, but `0` bytes were somehow
-    /// written into the Unix datagram socket.
-    UdSocket0BytesWritten,
     /// Could not serialize a message to be sent through the unix domain socket.
-    MsgSerializeError(BincodeError),
+    MsgSerializeError(CodecError),
     /// Could not deserialize a message read from the unix domain socket.
-    MsgDeserializeError(BincodeError),
+    MsgDeserializeError(CodecError),
+
+    /// A client submitted a task to a queue the server wasn't configured with.
+    UnknownQueue(String),
+    /// A client submitted a task while the server's queues held this many pending tasks,
+    /// as many as it allows, see [`ServerConfig::queue_capacity`].
+    QueueFull(usize),
+    /// A client submitted a task using a filter with a limit of `0`, server-wide or in the
+    /// task's queue.
+    FilterDisabled(Filter),
+    /// A client submitted a task using a filter the server doesn't know of.
+    UnknownFilter(Filter),
+    /// A client submitted a task with a pipeline of this many filters, more than allowed,
+    /// see [`ServerConfig::max_transformations`].
+    PipelineTooLong(usize),
+    /// A client submitted a task whose output can't be written at this path: it's the
+    /// task's input, or its directory doesn't exist.
+    InvalidOutput(PathBuf),
+    /// A client submitted a task reading or writing a file at this path, outside the
+    /// directories allowed, see [`ServerConfig::path_policy`].
+    PathNotAllowed(PathBuf),
+    /// A client submitted a task setting this environment variable for its filters, which
+    /// isn't allowed, see [`FilterEnvironment::allowed`].
+    EnvVarNotAllowed(String),
+    /// A client submitted a task whose filters can't run in this directory: it doesn't exist,
+    /// or filters are sandboxed in namespaces.
+    InvalidWorkingDir(PathBuf),
+    /// A client submitted a task reading or writing this URL, but the server was built
+    /// without support for remote files, see [`remote::SUPPORTED`].
+    RemoteUnsupported(PathBuf),
+    /// The client with this PID couldn't be told of its task, which was dropped, see
+    /// [`RequestFailure::ClientGone`].
+    ClientGone(u32),
+
+    /// Registering the handlers of termination signals failed.
+    SignalHandlerError(io::Error),
+    /// Spawning the thread starting the workers of the server's pool failed.
+    WorkerPoolSpawnError(io::Error),
+    /// Spawning the threads of the server's monitor pool failed.
+    MonitorPoolSpawnError(io::Error),
+    /// Opening the audit file failed, see [`ServerConfig::audit`].
+    AuditFileError(io::Error),
+    /// Opening the result cache's directory failed, see [`ServerConfig::cache`].
+    CacheDirError(io::Error),
+    /// Opening the output store's directory failed, see [`ServerConfig::store_dir`].
+    StoreDirError(io::Error),
+    /// Opening the file the task counter is saved to failed, see [`ServerConfig::counter_file`].
+    CounterFileError(io::Error),
+    /// Loading the server's WASM filters failed, see [`ServerConfig::wasm_modules`].
+    WasmError(WasmError),
+    /// Listening for workers, or spawning the thread accepting them, failed, see
+    /// [`ServerConfig::worker_listen`].
+    WorkerListenError(io::Error),
 
     /// Failed to spawn the monitor to whom a client's task would be assigned.
     MonitorSpawnError(MonitorBuildError),
-    /// When formatting a status message `String`, an error occurred.
-    StatusFmtError(std::fmt::Error)
+    /// A task was to be handed to the worker with this ID, which isn't registered.
+    UnknownWorker(usize),
 }
 
-impl From<BincodeError> for ServerError {
-    fn from(err: BincodeError) -> Self {
+impl From<CodecError> for ServerError {
+    fn from(err: CodecError) -> Self {
         Self::MsgSerializeError(err)
     }
 }
@@ -107,42 +344,94 @@ impl From<MonitorBuildError> for ServerError {
     }
 }
 
-impl From<std::fmt::Error> for ServerError {
-    fn from(err: std::fmt::Error) -> Self {
-        Self::StatusFmtError(err)
-    }
+/// Path of the datagram socket of the client with `client_pid`, in `udsock_dir`.
+fn udsock_dest(udsock_dir: &Path, client_pid: u32) -> PathBuf {
+    udsock_dir.join(
+        String::from("sdstore_") + &client_pid.to_string() + ".sock"
+    )
 }
 
-/// Closure passed to the server thread that will be spawned with the purpose of
-/// listening to the `UnixDatagram` socket.
-fn udsock_listen(
-    listener: Arc<UnixDatagram>,
-    sender: mpsc::Sender<MessageToServer>
-) -> () {
-    // Loop the processing of clients' requests.
-    let mut buf = [0; 1024];
-    loop {
-        let n = listener.recv(&mut buf).unwrap_or_else(|err| {
-            panic!("Failed to read from UnixDatagram: {:?}", err)
-        });
+/// Whom to send the notifications of the client with `client_pid` to, see
+/// [`ServerState::client_peer`].
+fn client_peer(peers: &HashMap<u32, Peer>, udsock_dir: &Path, namespace: SocketNamespace, client_pid: u32) -> Peer {
+    peers
+        .get(&client_pid)
+        .cloned()
+        .unwrap_or_else(|| namespace.peer(udsock_dest(udsock_dir, client_pid)))
+}
 
-        let request: ClientRequest = bincode::deserialize(&buf[..n])
-            .unwrap_or_else(|err| {
-                panic!("Failed to deserialize message from UnixDatagram: {:?}", err)
-            });
+/// Log `event`, with the fields log pipelines index, as logged in JSON, see
+/// [`LogFormat::Json`](crate::util::LogFormat::Json).
+fn log_event(event: &TaskEvent) {
+    let task = event.task();
+    let (name, task_number, outcome) = match event {
+        TaskEvent::Queued(_) => ("queued", None, None),
+        TaskEvent::Started { task_number, .. } => ("started", Some(*task_number), None),
+        TaskEvent::Finished { task_number, .. } => ("finished", Some(*task_number), Some(String::from("succeeded"))),
+        TaskEvent::Failed { task_number, failure, .. } => ("failed", *task_number, Some(failure.to_string())),
+    };
+    let filters = task.transformations.iter().map(Filter::to_string).collect::<Vec<_>>().join(" ");
+    let duration_ms = task.received_at.map(|at| at.elapsed().as_millis() as u64);
+    log::info!(
+        event = name, task_id:% = task.request_id, task_number, client_pid = task.client_pid, filters, duration_ms,
+        outcome;
+        "request {} by client {} {name}", task.request_id, task.client_pid
+    );
+}
 
-        sender.send(MessageToServer::Client(request)).unwrap_or_else(|err| {
-            panic!("Failed to send message to server via channel: {:?}", err)
-        });
+/// Wait for the next request to be read whole from `incoming`, decoding it with `codec`,
+/// or forever if there is no transport to read from.
+///
+/// Requests that are truncated, dropped while being reassembled, or can't be deserialized,
+/// are returned as [`MessageToServer::Unreadable`], for their senders to be told. Other
+/// errors mean the transport can't be read from.
+async fn read_request(
+    incoming: Option<&Incoming>,
+    messages: &mut MessageReceiver,
+    codec: WireFormat
+) -> io::Result<MessageToServer> {
+    let Some(incoming) = incoming else {
+        return std::future::pending().await
+    };
+    match messages.recv_incoming(incoming).await {
+        Ok((bytes, peer, credentials)) => match codec.decode::<ClientRequest>(&bytes) {
+            Ok(request) => Ok(MessageToServer::Client(request, peer, credentials)),
+            Err(err) => {
+                log::warn!("could not deserialize request from {:?}: {:?}", peer, err);
+                Ok(MessageToServer::Unreadable(peer, credentials, RequestFailure::MalformedRequest(format!("{err:?}"))))
+            },
+        },
+        Err(err) => {
+            let dropped = match err.downcast::<TruncatedDatagram>() {
+                Ok(truncated) => {
+                    log::warn!("{truncated}");
+                    let TruncatedDatagram { sender, credentials, len } = truncated;
+                    let failure = RequestFailure::MessageTooLarge { len, max: MAX_DATAGRAM_PAYLOAD + 1 };
+                    return Ok(MessageToServer::Unreadable(sender, credentials, failure))
+                },
+                Err(err) => err.downcast::<DroppedMessage>()?,
+            };
+            log::warn!("{dropped}");
+            let DroppedMessage { sender, credentials, reason } = dropped;
+            Ok(MessageToServer::Unreadable(sender, credentials, RequestFailure::MalformedRequest(reason)))
+        },
     }
 }
 
-impl ServerState {
-    /// Get a new strong reference to the server's unix datagram socket.
-    pub fn get_udsocket(&self) -> Arc<UnixDatagram> {
-        Arc::clone(&self.udsocket)
+/// Wait for the next of the termination `signals`, returning its number, or forever if
+/// they aren't listened for.
+async fn termination_signal(signals: &mut Option<(Signal, Signal)>) -> i32 {
+    let Some((interrupt, terminate)) = signals else {
+        return std::future::pending().await
+    };
+    tokio::select! {
+        Some(()) = interrupt.recv() => SIGINT,
+        Some(()) = terminate.recv() => SIGTERM,
+        else => std::future::pending().await,
     }
+}
 
+impl ServerState {
     /// Get a new sender of server messages; useful to give to monitors
     /// to communicate results.
     pub fn get_sender(&self) -> Sender<messaging::MessageToServer> {
@@ -151,126 +440,946 @@ impl ServerState {
 
     /// Get, increment, the server's task counter, used to number tasks.
     pub fn get_incr_task_counter(&mut self) -> usize {
-        let res = self.task_counter;
-        self.task_counter += 1;
-        res
+        self.task_counter.number_next()
     }
 
-    pub fn client_pid_from_monitor_id(&self, t_id: &ThreadId) -> Option<u32> {
-        self.running_tasks.get(t_id).map(|monitor| monitor.task.client_pid)
+    pub fn client_pid_from_monitor_id(&self, task_number: usize) -> Option<u32> {
+        self.running_tasks.get(&task_number).map(|monitor| monitor.task.client_pid)
     }
 
     /// Given a client's PID, construct the path of its datagram socket.
     ///
-    /// Both server and client sockets exist in a directory named `/tmp`
-    /// in the root of this project.
+    /// Both server and client sockets are in the socket directory, see
+    /// [`paths::socket_dir`](crate::core::paths::socket_dir).
     pub fn get_udsock_dest(&self, client_pid: u32) -> PathBuf {
-        self.udsock_dir.join(
-            String::from("sdstore_") + &client_pid.to_string() + &".sock"
-        )
+        udsock_dest(&self.udsock_dir, client_pid)
+    }
+
+    /// Send the notifications of the client with `client_pid` to `peer`, from which it
+    /// made a request.
+    pub fn register_peer(&mut self, client_pid: u32, peer: Peer) {
+        self.peers.insert(client_pid, peer);
+    }
+
+    /// Whom to send the notifications of the client with `client_pid` to: the peer it last
+    /// made a request from, or else its datagram socket, see [`ServerState::get_udsock_dest`].
+    pub fn client_peer(&self, client_pid: u32) -> Peer {
+        client_peer(&self.peers, &self.udsock_dir, self.socket_namespace, client_pid)
     }
 
-    /// Use the server's `UnixDatagram` to send a message to a client identified by its PID.
+    /// Use the server's [`Transport`] to send a message about its request `request_id` to a
+    /// client identified by its PID.
+    ///
+    /// The message is encoded with the server's [`WireFormat`]. Messages of any length are
+    /// sent, over as many datagrams as needed, see [`messaging::send_message`].
     ///
-    /// `bincode::serialize` is used to encode the message, which requires `serde`'s derivable traits.
-    pub fn send_msg_to_client<T>(
-        &self,
+    /// The message is numbered, and kept until the client acknowledges it, to be sent again
+    /// otherwise, see [`ServerState::retransmit_unacked`]. If it can't be sent at all, the
+    /// client is assumed gone, and its unacknowledged messages are dropped, unless the
+    /// client is yet to connect, see [`ClientRequest::Connect`].
+    pub fn send_msg_to_client(
+        &mut self,
         client_pid: u32,
-        message: &T
-    ) -> Result<(), ServerError>
-    where T: ?Sized + serde::Serialize,
-    {
-            let destination = self.get_udsock_dest(client_pid);
-            let bytes = bincode::serialize(&message)?;
-
-            match self
-                .udsocket
-                .send_to(&bytes, destination)
-            {
-                Err(err) => Err(ServerError::UdSocketWriteError(err)),
-                Ok(0) => Err(ServerError::UdSocket0BytesWritten),
-                _ => Ok(())
-            }
-    }
-
-    /// Create a new instance of `ServerState`, assuming an initialized `UnixDatagram`,
-    /// and given intended the path to the server's socket,
-    /// but creating new inter-thread `mpsc::channel`s.
-    pub fn new(udsocket: UnixDatagram, udsock_dir: PathBuf) -> Self {
+        request_id: Uuid,
+        message: &MessageToClient
+    ) -> Result<(), ServerError> {
+            let destination = self.client_peer(client_pid);
+            let seq = self.next_seq.get(&(client_pid, request_id)).copied().unwrap_or_default();
+            match message.is_last() {
+                true => self.next_seq.remove(&(client_pid, request_id)),
+                false => self.next_seq.insert((client_pid, request_id), seq + 1),
+            };
+            let bytes = self.codec.encode(&Sequenced { seq, request_id, message })?;
+
+            match messaging::send_message(self.transport.as_ref(), &bytes, &destination) {
+                // Sent again once the client connects.
+                Err(err) if err.kind() == io::ErrorKind::NotConnected =>
+                    log::debug!("client {client_pid} is yet to connect, delaying message #{seq}"),
+                Err(err) => {
+                    self.unacked.remove(&client_pid);
+                    return Err(ServerError::UdSocketWriteError(err))
+                },
+                Ok(()) => {},
+            }
+            let unacked = Unacked { bytes, sent_at: Instant::now(), transmissions: 1 };
+            self.unacked.entry(client_pid).or_default().insert((request_id, seq), unacked);
+            Ok(())
+    }
+
+    /// Tell the sender of a request that couldn't be read why, as `failure`, if its PID is
+    /// known from its `credentials`. The reply isn't about any request it can identify, and
+    /// has a nil request ID, see [`NotificationReceiver`](messaging::NotificationReceiver).
+    pub fn reject_unreadable(&mut self, peer: Peer, credentials: Option<Credentials>, failure: RequestFailure) {
+        let Some(client_pid) = credentials.map(|credentials| credentials.pid).filter(|pid| *pid != 0) else {
+            log::warn!("could not tell {:?} why its request was unreadable, as its PID is unknown", peer);
+            return
+        };
+        self.register_peer(client_pid, peer);
+        if let Err(err) = self.send_msg_to_client(client_pid, Uuid::nil(), &MessageToClient::Failed(failure)) {
+            log::warn!("could not tell client {client_pid} why its request was unreadable: {:?}", err);
+        }
+    }
+
+    /// Send every [`TaskEvent`] to the client with `client_pid` from now on, in reply to its
+    /// request `request_id`, until it unsubscribes, or can't be sent to anymore.
+    pub fn subscribe(&mut self, client_pid: u32, request_id: Uuid) {
+        self.subscribers.insert(client_pid, request_id);
+    }
+
+    /// Stop sending task events to the client with `client_pid`, and tell it so, see
+    /// [`ServerState::subscribe`].
+    pub fn unsubscribe(&mut self, client_pid: u32) -> Result<(), ServerError> {
+        match self.subscribers.remove(&client_pid) {
+            None => Ok(()),
+            Some(request_id) => self.send_msg_to_client(client_pid, request_id, &MessageToClient::Unsubscribed),
+        }
+    }
+
+    /// Send `event` to every subscribed client, unsubscribing those it can't be sent to.
+    fn publish(&mut self, event: TaskEvent) {
+        log_event(&event);
+        let audited = match &event {
+            TaskEvent::Queued(_) => AuditEvent::Queued,
+            TaskEvent::Started { task_number, .. } => AuditEvent::Started { task_number: *task_number },
+            TaskEvent::Finished { task_number, .. } => AuditEvent::Finished { task_number: *task_number },
+            TaskEvent::Failed { task_number, failure, .. } =>
+                AuditEvent::Failed { task_number: *task_number, failure: failure.to_string() },
+        };
+        self.audit(event.task(), audited);
+        let subscribers = self.subscribers.iter().map(|(pid, id)| (*pid, *id)).collect::<Vec<_>>();
+        let msg = MessageToClient::Event(event);
+        for (client_pid, request_id) in subscribers {
+            if let Err(err) = self.send_msg_to_client(client_pid, request_id, &msg) {
+                log::warn!("unsubscribing client {client_pid}, which could not be sent an event: {:?}", err);
+                self.subscribers.remove(&client_pid);
+            }
+        }
+    }
+
+    /// Forget the message numbered `seq` about the request `request_id`, which the client
+    /// with `client_pid` acknowledged.
+    pub fn acknowledge(&mut self, client_pid: u32, request_id: Uuid, seq: u64) {
+        if let Some(unacked) = self.unacked.get_mut(&client_pid) {
+            unacked.remove(&(request_id, seq));
+            if unacked.is_empty() {
+                self.unacked.remove(&client_pid);
+            }
+        }
+    }
+
+    /// Send again every message its client didn't acknowledge within
+    /// [`ServerConfig::retransmit_after`], giving up on those sent
+    /// [`ServerConfig::max_transmissions`] times already, and on the clients that can't be
+    /// sent to anymore.
+    pub fn retransmit_unacked(&mut self) {
+        let now = Instant::now();
+        let (retransmit_after, max_transmissions) = (self.retransmit_after, self.max_transmissions);
+        let (transport, peers, udsock_dir) = (self.transport.as_ref(), &self.peers, &self.udsock_dir);
+        let namespace = self.socket_namespace;
+        self.unacked.retain(|client_pid, unacked| {
+            let destination = client_peer(peers, udsock_dir, namespace, *client_pid);
+            let mut reachable = true;
+            unacked.retain(|(request_id, seq), message| {
+                if !reachable || now.duration_since(message.sent_at) < retransmit_after {
+                    return true
+                }
+                if message.transmissions >= max_transmissions {
+                    log::warn!("client {client_pid} never acknowledged message #{seq} about request {request_id}, giving up on it");
+                    return false
+                }
+                match messaging::send_message(transport, &message.bytes, &destination) {
+                    Err(err) if err.kind() == io::ErrorKind::NotConnected => {
+                        message.sent_at = now;
+                        message.transmissions += 1;
+                    },
+                    Err(err) => {
+                        log::warn!("could not resend message #{seq} about request {request_id} to client {client_pid}: {:?}", err);
+                        reachable = false;
+                    },
+                    Ok(()) => {
+                        message.sent_at = now;
+                        message.transmissions += 1;
+                    },
+                }
+                true
+            });
+            reachable && !unacked.is_empty()
+        });
+    }
+
+    /// Create a new instance of `ServerState`, assuming an initialized [`Transport`], read
+    /// from as `incoming`, and given intended the path to the server's socket and its queue
+    /// configuration, but creating a new `mpsc` channel for the messages of its monitors.
+    pub fn new(
+        incoming: Incoming,
+        udsock_dir: PathBuf,
+        server_config: &ServerConfig
+    ) -> Self {
+        Self::with_transport(incoming.transport(), Some(incoming), udsock_dir, server_config)
+    }
+
+    /// Create a new instance of `ServerState` replying to clients over `transport`, and
+    /// reading their requests from `incoming`, if any, or else only handling those it's
+    /// handed, as by tests.
+    pub(crate) fn with_transport(
+        transport: Arc<dyn Transport>,
+        incoming: Option<Incoming>,
+        udsock_dir: PathBuf,
+        server_config: &ServerConfig
+    ) -> Self {
         let (
             sender,
             receiver
-        ) = mpsc::channel::<messaging::MessageToServer>();
-        let udsocket = Arc::new(udsocket);
+        ) = mpsc::channel::<messaging::MessageToServer>(MESSAGE_BACKLOG);
 
         Self {
-            task_counter: 0,
-            task_pqueue: PriorityQueue::new(),
+            task_counter: TaskCounter::default(),
+            queues: server_config
+                .queues
+                .iter()
+                .map(|queue| TaskQueue::new(queue.clone(), server_config.scheduling_policy, server_config.scan_depth))
+                .collect(),
 
             filters_count: RunningFilters::default(),
             running_tasks: HashMap::new(),
+            started_at: Instant::now(),
+            task_durations: TaskDurations::default(),
+            stats: ServerStats::default(),
+            reconciled_at: Instant::now(),
+            workers: BTreeMap::new(),
+            heartbeat_at: Instant::now(),
 
             sender,
             receiver,
 
-            udsocket,
-            udsock_mngr: None,
-            udsock_dir
+            transport,
+            incoming,
+            read_failures: (0, None),
+            messages: MessageReceiver::default(),
+            signals: None,
+            stream_listener: None,
+            worker_listener: None,
+            shutting_down: false,
+            executable: None,
+            handover: None,
+            codec: server_config.wire_format,
+            next_seq: HashMap::new(),
+            unacked: HashMap::new(),
+            peers: HashMap::new(),
+            subscribers: HashMap::new(),
+            history: VecDeque::new(),
+            task_logs: TaskLogs::new(HISTORY_LEN),
+            accounting: Accounting::default(),
+            task_spans: HashMap::new(),
+            waiters: HashMap::new(),
+            idempotency_keys: HashMap::new(),
+            idempotency_window: server_config.idempotency_window,
+            inline_max_size: server_config.inline_max_size,
+            rate_limiter: server_config.rate_limit.map(RateLimiter::new),
+            udsock_dir,
+            socket_namespace: server_config.socket_namespace,
+            queue_capacity: server_config.queue_capacity,
+            max_transformations: server_config.max_transformations,
+            path_policy: server_config.path_policy.clone(),
+            environment: server_config.environment.clone(),
+            jailed_filters: server_config.sandbox.namespaces,
+            retransmit_after: server_config.retransmit_after,
+            max_transmissions: server_config.max_transmissions,
+            disabled_filters: server_config
+                .filters_config
+                .filters()
+                .into_iter()
+                .filter(|filter| server_config.filters_config.limit(filter) == 0)
+                .collect(),
+            known_filters: server_config.filters_config.filters(),
+
+            streams: HashMap::new(),
+            stream_senders: Vec::new(),
+
+            pool: None,
+            monitors: None,
+            audit: None,
+            cache: None,
+            wasm: None,
+            store: None
         }
     }
 
-    /// Spawn a thread to manage the unix datagram socket.
+    /// Wait for the next message for the server to handle, whichever comes first: a client's
+    /// request, read from the transport, a message from a monitor, or from the thread
+    /// accepting streamed tasks, or a termination signal.
     ///
-    /// The closure it is spawned with must give it ownership of a new `Arc` to the socket,
-    /// and likewise of a cloned `Sender<MessageToServer>`.
-    pub fn spawn_udsock_mngr(&mut self, thread_name: &str) -> Result<(), ServerError> {
-        let sender_clone = self.get_sender().clone();
-        let listener_clone = self.get_udsocket();
+    /// Should reading from the transport fail, it's read from again a while later, while the
+    /// server goes on with its other messages, see [`READ_RETRY_DELAY`], or right away if it
+    /// was only interrupted, see [`transport::is_transient`]. Only once it failed
+    /// [`MAX_READ_FAILURES`] times in a row isn't it read from anymore, and the server no
+    /// longer ready, see [`ServerState::health`]. Returns `None` if no more messages may
+    /// come, which can't happen while the server keeps a sender of its own.
+    pub async fn next_message(&mut self) -> Option<MessageToServer> {
+        let ServerState { receiver, incoming, read_failures, messages, signals, codec, .. } = self;
+        loop {
+            let retry_at = read_failures.1;
+            let request = tokio::select! {
+                msg = receiver.recv() => return msg,
+                signal = termination_signal(signals) => return Some(MessageToServer::Shutdown(signal)),
+                request = async {
+                    if let Some(retry_at) = retry_at {
+                        tokio::time::sleep_until(retry_at.into()).await;
+                    }
+                    read_request(incoming.as_ref(), messages, *codec).await
+                } => request,
+            };
+            match request {
+                Ok(msg) => {
+                    *read_failures = (0, None);
+                    return Some(msg)
+                },
+                // Nothing went wrong with the transport, so it's read from again right away.
+                Err(err) if transport::is_transient(&err) =>
+                    log::debug!("reading from the transport was interrupted: {:?}", err),
+                Err(err) if read_failures.0 + 1 >= MAX_READ_FAILURES => {
+                    log::error!("failed to read from the transport, which won't be read from anymore: {:?}", err);
+                    *incoming = None;
+                },
+                Err(err) => {
+                    let delay = READ_RETRY_DELAY * 2u32.pow(read_failures.0);
+                    log::error!("failed to read from the transport, reading again in {:?}: {:?}", delay, err);
+                    *read_failures = (read_failures.0 + 1, Some(Instant::now() + delay));
+                },
+            }
+        }
+    }
 
-        let udsocket_manager = thread::Builder::new()
+    /// Spawn a thread accepting streamed tasks on `listener`, see
+    /// [`streaming::stream_listen`]. Their inputs are spooled next to the server's socket.
+    pub fn spawn_stream_listener(&mut self, thread_name: &str, listener: UnixListener) -> Result<(), ServerError> {
+        let sender_clone = self.get_sender();
+        let spool_dir = self.udsock_dir.clone();
+        let codec = self.codec;
+
+        let stream_listener = thread::Builder::new()
             .name(String::from(thread_name))
-            .spawn(move || udsock_listen(listener_clone, sender_clone))
-            .map_err(|err| ServerError::UdSocketManagerSpawnError(err))?;
+            .spawn(move || streaming::stream_listen(listener, spool_dir, sender_clone, codec))
+            .map_err(ServerError::StreamThreadSpawnError)?;
 
-        self.udsock_mngr = Some(udsocket_manager);
+        self.stream_listener = Some(stream_listener);
 
         Ok(())
     }
 
-    /// Insert new inbound task in the priority queue, and inform the sending
-    /// client that it is now pending.
-    pub fn new_task(&mut self, task: ClientTask) -> Result<(), ServerError> {
-        let client_pid = task.client_pid;
-        let prio = task.priority;
-        self.task_pqueue.push(task, prio);
+    /// Listen for workers at the address the server was configured with, if any, accepting
+    /// them on a thread of its own, see [`coordinator::listen`]. They are handed pending
+    /// tasks once registered, see [`ServerState::try_pop_remote_task`].
+    pub fn listen_for_workers(&mut self, server_config: &ServerConfig) -> Result<(), ServerError> {
+        // The config has a token whenever it has an address, see `UnauthenticatedWorkers`.
+        let (Some(addr), Some(token)) = (server_config.worker_listen, server_config.worker_token.clone()) else {
+            return Ok(())
+        };
+        let listener = TcpListener::bind(addr).map_err(ServerError::WorkerListenError)?;
+        log::info!("server listening for workers on {:?}", listener);
+
+        let sender = self.get_sender();
+        let worker_listener = thread::Builder::new()
+            .name(String::from("sdstored_worker_listener"))
+            .spawn(move || coordinator::listen(listener, token, sender))
+            .map_err(ServerError::WorkerListenError)?;
+        self.worker_listener = Some(worker_listener);
+        Ok(())
+    }
 
-        let msg_to_client = MessageToClient::Pending;
-        self.send_msg_to_client(client_pid, &msg_to_client)
+    /// Keep the stream of a streamed task, over which its output will be sent back once
+    /// it has run, see [`ServerState::finish_stream`].
+    pub fn add_stream(&mut self, client_pid: u32, stream: UnixStream) {
+        self.streams.insert(client_pid, stream);
     }
 
-    /// Attempt to remove the highest priority task in the queue.
+    /// If `task` was streamed, send its output back to its client if it `succeeded`, or
+    /// just disconnect the client otherwise, and remove the server's copies of its files.
+    /// Those of a task sent inline, whose output was sent already, are removed too.
     ///
-    /// For it to be possible, the following is required:
+    /// The output is sent by a thread of its own, so as not to hold up the server.
+    pub fn finish_stream(&mut self, task: &ClientTask, succeeded: bool) -> Result<(), ServerError> {
+        if task.inline.is_some() {
+            inline::remove_spooled(&self.udsock_dir, task.request_id);
+        }
+        let stream = match self.streams.remove(&task.client_pid) {
+            None => return Ok(()),
+            Some(stream) => stream,
+        };
+
+        self.stream_senders.retain(|sender| !sender.is_finished());
+        let task = task.clone();
+        let sender = thread::Builder::new()
+            .name(String::from("sdstored_stream_sender"))
+            .spawn(move || {
+                if let Err(err) = streaming::send_output(stream, &task, succeeded) {
+                    log::warn!("could not send output to client {}: {:?}", task.client_pid, err);
+                }
+            })
+            .map_err(ServerError::StreamThreadSpawnError)?;
+        self.stream_senders.push(sender);
+
+        Ok(())
+    }
+
+    /// Listen for the termination signals `SIGINT` and `SIGTERM`, which the server's event
+    /// loop then receives as [`MessageToServer::Shutdown`], see [`ServerState::next_message`].
+    ///
+    /// Must be called from within the server's async runtime.
+    pub fn listen_for_signals(&mut self) -> Result<(), ServerError> {
+        let interrupt = signal(SignalKind::interrupt()).map_err(ServerError::SignalHandlerError)?;
+        let terminate = signal(SignalKind::terminate()).map_err(ServerError::SignalHandlerError)?;
+        self.signals = Some((interrupt, terminate));
+        Ok(())
+    }
+
+    /// Start the pool of workers the server was configured with, if any, see [`WorkerPool`].
+    pub fn start_worker_pool(&mut self, server_config: &ServerConfig) -> Result<(), ServerError> {
+        if server_config.pool_size > 0 {
+            let pool = WorkerPool::new(server_config.pool_size, server_config)
+                .map_err(ServerError::WorkerPoolSpawnError)?;
+            self.pool = Some(Arc::new(pool));
+        }
+        Ok(())
+    }
+
+    /// Start the pool of threads monitors run on, of the size the server was configured
+    /// with, see [`MonitorPool`]. Until it is started, no task runs.
+    pub fn start_monitor_pool(&mut self, server_config: &ServerConfig) -> Result<(), ServerError> {
+        let monitors = MonitorPool::new(server_config.monitor_threads).map_err(ServerError::MonitorPoolSpawnError)?;
+        self.monitors = Some(monitors);
+        Ok(())
+    }
+
+    /// Start tasks with monitors that never run them, at most `size` at once, their results
+    /// being handed to the server by tests instead, see [`MonitorPool::held`].
+    #[cfg(test)]
+    pub(crate) fn hold_monitors(&mut self, size: usize) {
+        self.monitors = Some(MonitorPool::held(size));
+    }
+
+    /// Open the audit file the server was configured with, if any, see [`AuditLog`].
+    pub fn open_audit_log(&mut self, server_config: &ServerConfig) -> Result<(), ServerError> {
+        if let Some(config) = &server_config.audit {
+            let audit = AuditLog::open(config.clone()).map_err(ServerError::AuditFileError)?;
+            self.audit = Some(audit);
+        }
+        Ok(())
+    }
+
+    /// Open the directory of the result cache the server was configured with, if any, see
+    /// [`ResultCache`].
+    pub fn open_result_cache(&mut self, server_config: &ServerConfig) -> Result<(), ServerError> {
+        if let Some(config) = &server_config.cache {
+            let cache = ResultCache::open(config.clone()).map_err(ServerError::CacheDirError)?;
+            self.cache = Some(Arc::new(cache));
+        }
+        Ok(())
+    }
+
+    /// Open the file the server's task counter is saved to, for its tasks to be numbered
+    /// after those of its last run, see [`TaskCounter`].
+    pub fn open_task_counter(&mut self, server_config: &ServerConfig) -> Result<(), ServerError> {
+        self.task_counter = TaskCounter::open(&server_config.counter_file).map_err(ServerError::CounterFileError)?;
+        Ok(())
+    }
+
+    /// Open the directory of the output store the server was configured with, if any, see
+    /// [`OutputStore`].
+    pub fn open_output_store(&mut self, server_config: &ServerConfig) -> Result<(), ServerError> {
+        if let Some(dir) = &server_config.store_dir {
+            let store = OutputStore::open(dir.clone()).map_err(ServerError::StoreDirError)?;
+            self.store = Some(store);
+        }
+        Ok(())
+    }
+
+    /// Compile the WASI modules of the server's WASM filters, if it has any, see
+    /// [`WasmRuntime`].
+    pub fn load_wasm_filters(&mut self, server_config: &ServerConfig) -> Result<(), ServerError> {
+        let modules = server_config.wasm_modules();
+        if !modules.is_empty() {
+            let runtime = WasmRuntime::load(&modules).map_err(ServerError::WasmError)?;
+            self.wasm = Some(Arc::new(runtime));
+        }
+        Ok(())
+    }
+
+    /// Take a request from the bucket of `task`'s client, if clients are rate limited, see
+    /// [`RateLimiter`]. Returns whether the task may be queued, as it is rejected if its
+    /// client's bucket is empty, with how long until it may submit another.
+    pub fn rate_limit(&mut self, task: &ClientTask) -> bool {
+        let Some(limiter) = &mut self.rate_limiter else { return true };
+        let Err(retry_after) = limiter.acquire(task.client_uid, task.client_pid, Instant::now()) else { return true };
+        log::warn!("client {} is rate limited for another {:?}", task.client_pid, retry_after);
+        self.reject_task(Arc::new(task.clone()), RequestFailure::RateLimited { retry_after });
+        false
+    }
+
+    /// Spool the input of `task`, if it is sent inline, next to the server's socket, and have
+    /// the task read it there, see [`inline::spool_input`]. Returns whether the task may be
+    /// queued, as it is rejected if its input is longer than the server takes inline, or
+    /// can't be spooled.
+    pub fn spool_inline(&mut self, task: &mut ClientTask) -> bool {
+        let Some(len) = task.inline.as_ref().map(|input| input.len()) else {
+            return true
+        };
+        let failure = match len > self.inline_max_size {
+            true => RequestFailure::InlineTooLarge { len, max: self.inline_max_size },
+            false => match inline::spool_input(&self.udsock_dir, task) {
+                Ok(()) => return true,
+                Err(err) => RequestFailure::Internal(format!("could not spool the inline input: {err}")),
+            },
+        };
+        task.inline = Some(Box::default());
+        self.reject_task(Arc::new(task.clone()), failure);
+        false
+    }
+
+    /// Send the output of `task`, if it was sent inline, and concluded, as `outcome` tells,
+    /// to its client ahead of `outcome`, which is returned, unless the output can't be sent
+    /// inline, which the task then fails with instead.
+    fn send_inline_output(&mut self, task: &ClientTask, outcome: MessageToClient) -> MessageToClient {
+        if task.inline.is_none() || !matches!(outcome, MessageToClient::Concluded(_)) {
+            return outcome
+        }
+        let output = match inline::read_output(task, self.inline_max_size) {
+            Ok(output) => MessageToClient::InlineOutput(output),
+            Err(failure) => return MessageToClient::Failed(failure),
+        };
+        // Should the client be gone, it is found out as the outcome is sent.
+        if let Err(err) = self.send_msg_to_client(task.client_pid, task.request_id, &output) {
+            log::warn!("could not send inline output to client {}: {:?}", task.client_pid, err);
+        }
+        outcome
+    }
+
+    /// Have `task` write its output to where it is staged in the store, see
+    /// [`OutputStore::staging_path`], if it is written to the store, and the server has one.
+    pub fn stage_in_store(&self, task: &mut ClientTask) {
+        if let Some(store) = self.store.as_ref().filter(|_| task.store) {
+            let input = task.input_filepath().to_path_buf();
+            task.relocate(input, store.staging_path(task.request_id));
+        }
+    }
+
+    /// Record `event`, about `task`, in the audit log, if the server keeps one.
+    fn audit(&mut self, task: &ClientTask, event: AuditEvent) {
+        if let Some(audit) = &mut self.audit {
+            audit.record(task, event);
+        }
+    }
+
+    /// Record that a task finished or failed, as `event` tells, its client having been sent
+    /// `outcome` as the last message about it: the event is published, and kept in the
+    /// history, as is the task's log, the task is accounted for, and the clients waiting for
+    /// the task are sent `outcome` too.
+    ///
+    /// A task that failed, whether it was rejected, orphaned or ran, has its idempotency key
+    /// forgotten, so that retrying it runs it again, see [`ServerState::deduplicate`].
+    fn finish(&mut self, event: TaskEvent, outcome: MessageToClient) {
+        self.tell_waiters(event.task().request_id, &outcome);
+        let concluded = match &event {
+            TaskEvent::Failed { failure, .. } => TaskLogEvent::Failed(failure.clone()),
+            _ => TaskLogEvent::Finished,
+        };
+        match &concluded {
+            TaskLogEvent::Failed(_) => {
+                self.stats.failed += 1;
+                self.forget_idempotency_key(event.task());
+            },
+            _ => self.stats.completed += 1,
+        }
+        self.task_logs.conclude(event.task(), concluded);
+        self.accounting.record(event.task(), &outcome);
+        self.task_spans.remove(&event.task().request_id);
+        if self.history.len() == HISTORY_LEN {
+            self.history.pop_front();
+        }
+        self.history.push_back((event.clone(), outcome));
+        self.publish(event);
+    }
+
+    /// Forget the idempotency key of `task`, unless a later request took it over already.
+    fn forget_idempotency_key(&mut self, task: &ClientTask) {
+        let Some(key) = task.idempotency_key.clone() else {
+            return
+        };
+        if let Entry::Occupied(entry) = self.idempotency_keys.entry((task.client_uid, key)) {
+            if entry.get().0 == task.request_id {
+                entry.remove();
+            }
+        }
+    }
+
+    /// The span of `task`, see [`ClientTask::span`], created as the server receives it, and
+    /// closed once it concludes, see [`ServerState::finish`].
+    fn task_span(&mut self, task: &ClientTask) -> tracing::Span {
+        self.task_spans.entry(task.request_id).or_insert_with(|| task.span()).clone()
+    }
+
+    /// Send `msg` to the clients waiting for the request `request_id`, which then no longer
+    /// wait for it, see [`ServerState::wait_for`].
+    fn tell_waiters(&mut self, request_id: Uuid, msg: &MessageToClient) {
+        for (client_pid, own_request_id) in self.waiters.remove(&request_id).unwrap_or_default() {
+            if let Err(err) = self.send_msg_to_client(client_pid, own_request_id, msg) {
+                log::warn!("could not tell client {client_pid} what became of request {request_id}: {:?}", err);
+            }
+        }
+    }
+
+    /// Hand new inbound task to the scheduler of the queue it was submitted to, and
+    /// inform the sending client that it is now pending, where in its queue, and how long
+    /// it should wait, and run, see [`TaskDurations::estimate_wait`].
     ///
-    /// * That the server has pending tasks in the queue
-    /// * That the task that was sucessfully popped can be run, given the server's
-    ///   currently running filter count, and the filters required to execute the task.
+    /// If the server has no such queue, or its queues are full, see
+    /// [`ServerConfig::queue_capacity`], or the task uses a filter it doesn't know of, or
+    /// that it or its queue never runs, with a limit of `0`, the client is told its request
+    /// could not start.
+    pub fn new_task(&mut self, mut task: ClientTask) -> Result<(), ServerError> {
+        let span = self.task_span(&task);
+        let _entered = span.enter();
+        let (client_pid, request_id) = (task.client_pid, task.request_id);
+        let received_at = Some(Instant::now());
+        task.received_at = received_at;
+        let task = Arc::new(task);
+        let received = AuditEvent::Received {
+            input: task.input_filepath(),
+            output: task.output_filepath(),
+            filters: &task.transformations,
+            queue: task.queue_name(),
+            priority: task.priority,
+        };
+        if let Some(audit) = &mut self.audit {
+            audit.record(&task, received);
+        }
+
+        let queue_idx = match self.queues.iter().position(|q| q.name() == task.queue_name()) {
+            Some(idx) => idx,
+            None => {
+                let queue = task.queue_name().to_string();
+                self.reject_task(task, RequestFailure::UnknownQueue(queue.clone()));
+                return Err(ServerError::UnknownQueue(queue))
+            }
+        };
+
+        let len = task.transformations.len();
+        if len > self.max_transformations {
+            self.reject_task(task, RequestFailure::PipelineTooLong { len, max: self.max_transformations });
+            return Err(ServerError::PipelineTooLong(len))
+        }
+        if let Some(filter) = task.transformations.iter().find(|filter| !self.known_filters.contains(filter)).cloned() {
+            self.reject_task(task, RequestFailure::UnknownFilter(filter.clone()));
+            return Err(ServerError::UnknownFilter(filter))
+        }
+        let queue_limits = &self.queues[queue_idx].config.filters_config;
+        let disabled = task
+            .transformations
+            .iter()
+            .find(|filter| self.disabled_filters.contains(filter) || queue_limits.limit(filter) == 0);
+        if let Some(filter) = disabled.cloned() {
+            self.reject_task(task, RequestFailure::FilterDisabled(filter.clone()));
+            return Err(ServerError::FilterDisabled(filter))
+        }
+
+        if let Some(path) = self.path_policy.denied(&task).map(Path::to_path_buf) {
+            log::warn!("task by client {} accesses {}, outside the allowed directories", task.client_pid, path.display());
+            self.reject_task(task, RequestFailure::PathNotAllowed(path.clone()));
+            return Err(ServerError::PathNotAllowed(path))
+        }
+        if let Some(name) = self.environment.denied(&task).map(str::to_string) {
+            log::warn!("task by client {} sets {name} for its filters, which isn't allowed", task.client_pid);
+            self.reject_task(task, RequestFailure::EnvVarNotAllowed(name.clone()));
+            return Err(ServerError::EnvVarNotAllowed(name))
+        }
+        if let Some(cwd) = task.cwd.clone() {
+            let failure = match self.jailed_filters {
+                true => Some(RequestFailure::WorkingDirSandboxed),
+                false => (!cwd.is_dir()).then(|| RequestFailure::WorkingDirMissing(cwd.clone())),
+            };
+            if let Some(failure) = failure {
+                self.reject_task(task, failure);
+                return Err(ServerError::InvalidWorkingDir(cwd))
+            }
+        }
+        if task.store && (self.store.is_none() || batch::is_batch(task.input_filepath())) {
+            let output = task.output_filepath().to_path_buf();
+            let failure = if self.store.is_none() { RequestFailure::NoStore } else { RequestFailure::BatchToStore };
+            self.reject_task(task, failure);
+            return Err(ServerError::InvalidOutput(output))
+        }
+        let remote_output = remote::is_remote(task.output_filepath());
+        let remote_path = [task.input_filepath(), task.output_filepath()].into_iter().find(|path| remote::is_remote(path));
+        if let Some(url) = remote_path.filter(|_| !remote::SUPPORTED).map(Path::to_path_buf) {
+            self.reject_task(task, RequestFailure::RemoteUnsupported);
+            return Err(ServerError::RemoteUnsupported(url))
+        }
+        if remote_output && batch::is_batch(task.input_filepath()) {
+            let output = task.output_filepath().to_path_buf();
+            self.reject_task(task, RequestFailure::BatchToRemote);
+            return Err(ServerError::InvalidOutput(output))
+        }
+        // A batch's output directory is created as it starts, see `batch::expand`, and a
+        // remote output is uploaded once written, see `remote::Staging`.
+        if !batch::is_batch(task.input_filepath()) && !remote_output {
+            let output = task.output_filepath().to_path_buf();
+            let output_dir = output.parent().filter(|dir| !dir.as_os_str().is_empty());
+            if task.output_is_input() {
+                self.reject_task(task, RequestFailure::OutputIsInput);
+                return Err(ServerError::InvalidOutput(output))
+            }
+            if let Some(dir) = output_dir.filter(|dir| !dir.is_dir()) {
+                self.reject_task(task, RequestFailure::OutputDirMissing(dir.to_path_buf()));
+                return Err(ServerError::InvalidOutput(output))
+            }
+        }
+
+        // Tasks resuming from a checkpoint were accepted before the server restarted.
+        let pending = self.queues.iter().map(|queue| queue.pending().len()).sum::<usize>();
+        if let Some(capacity) = self.queue_capacity.filter(|capacity| pending >= *capacity && task.checkpoint.is_none()) {
+            self.reject_task(task, RequestFailure::QueueFull(capacity));
+            return Err(ServerError::QueueFull(capacity))
+        }
+
+        let min_service = self
+            .queues
+            .iter()
+            .filter_map(TaskQueue::backlogged_service)
+            .min();
+        let resumed = task.checkpoint.is_some();
+        if let Some(key) = task.idempotency_key.clone().filter(|_| !task.is_spooled()) {
+            self.idempotency_keys.insert((task.client_uid, key), (request_id, Instant::now()));
+        }
+        self.publish(TaskEvent::Queued(Arc::clone(&task)));
+        self.queues[queue_idx].push(task, min_service);
+
+        let queue = &self.queues[queue_idx];
+        let pending = queue.pending();
+        let position = pending
+            .iter()
+            .position(|task| task.client_pid == client_pid && task.received_at == received_at)
+            .unwrap_or_default();
+        if let Some(task) = pending.get(position) {
+            let queued = TaskLogEvent::Queued { queue: queue.name().to_string(), priority: task.priority, position };
+            self.task_logs.record(task, queued);
+        }
+        let ahead = pending
+            .iter()
+            .take(position)
+            .map(|task| task.transformations.as_slice())
+            .collect::<Vec<_>>();
+        let running = self
+            .running_tasks
+            .values()
+            .map(|monitor| (monitor.task.transformations.as_slice(), monitor.started_at.elapsed()))
+            .collect::<Vec<_>>();
+        let est_wait = self.task_durations.estimate_wait(&ahead, &running);
+        let est_done = pending
+            .get(position)
+            .and_then(|task| Some(est_wait? + self.task_durations.estimate_run(&task.transformations)?));
+        let msg_to_client = MessageToClient::Queued { position, est_wait, est_done };
+        match self.send_msg_to_client(client_pid, request_id, &msg_to_client) {
+            // Resumed tasks run whether their clients are still there or not, see `process_task`.
+            Err(ServerError::UdSocketWriteError(err)) if !resumed => {
+                if let Some(task) = self.queues[queue_idx].remove(&|task| task.request_id == request_id) {
+                    self.orphan_task(task, &err);
+                }
+                Err(ServerError::ClientGone(client_pid))
+            },
+            res => res,
+        }
+    }
+
+    /// Replace the pipeline of `task` with its optimized form, see [`optimizer::optimize`],
+    /// and tell its client about it, if it changed.
+    pub fn optimize_task(&mut self, server_config: &ServerConfig, task: &mut ClientTask) -> Result<(), ServerError> {
+        let optimized = optimizer::optimize(&task.transformations, &server_config.builtin_filters);
+        if optimized == task.transformations {
+            return Ok(())
+        }
+
+        let original = std::mem::replace(&mut task.transformations, optimized.clone());
+        self.send_msg_to_client(task.client_pid, task.request_id, &MessageToClient::Optimized(original, optimized))
+    }
+
+    /// Reduce the number of chunks `task` asked for, see [`ClientTask::chunks`], to what
+    /// can be run: a single one if its pipeline can't be split, see
+    /// [`chunking::is_splittable`], and otherwise as many as the server-wide and queue
+    /// limits allow at once.
+    pub fn fit_chunks(&self, server_config: &ServerConfig, task: &mut ClientTask) {
+        if !chunking::is_splittable(&task.transformations) {
+            task.chunks = 1;
+            return
+        }
+
+        let queue_limits = self
+            .queues
+            .iter()
+            .find(|q| q.name() == task.queue_name())
+            .map(|q| &q.config.filters_config);
+        while task.chunks > 1 {
+            let needed = &RunningFilters::default() + &task.filter_demand();
+            if needed.fits_within(&server_config.filters_config) &&
+                queue_limits.is_none_or(|limits| needed.fits_within(limits)) {
+                break;
+            }
+            task.chunks -= 1;
+        }
+    }
+
+    /// Directory where the checkpoints of restartable tasks are kept, see
+    /// [`checkpoint::Checkpoint`].
+    fn checkpoint_dir(&self) -> PathBuf {
+        self.udsock_dir.join(checkpoint::CHECKPOINT_DIR)
+    }
+
+    /// Queue the tasks that were interrupted by the server shutting down, or crashing, to
+    /// resume them from their checkpoints, creating the checkpoint directory if needed.
     ///
-    /// If this is not possible, return `None`.
-    pub fn try_pop_task(&mut self, server_config: &ServerConfig) -> Option<ClientTask> {
-        if let Some((task, _)) = self.task_pqueue.peek() {
-            if self.filters_count.can_run_pipeline(
-                &server_config.filters_config,
-                &task.transformations
-            ) {
-                // Since the loop is only entered if the queue's highest priority element can be
-                // peeked into, this unwrap is safe.
-                let (task, _) = self.task_pqueue.pop().unwrap();
-                return Some(task);
+    /// Checkpoints that can't be read, or whose tasks are no longer restartable, e.g. if the
+    /// server's config changed, are discarded, along with the tasks' partial outputs.
+    pub fn resume_checkpointed(&mut self, server_config: &ServerConfig) -> io::Result<()> {
+        let checkpoint_dir = self.checkpoint_dir();
+        fs::create_dir_all(&checkpoint_dir)?;
+
+        for (path, loaded) in checkpoint::load_all(&checkpoint_dir)? {
+            let checkpoint = match loaded {
+                Err(err) => {
+                    log::warn!("discarding unreadable checkpoint {:?}: {:?}", path, err);
+                    checkpoint::remove(&path);
+                    continue;
+                },
+                Ok(checkpoint) => checkpoint,
+            };
+
+            let mut task = checkpoint.task;
+            let client_pid = task.client_pid;
+            if !server_config.is_restartable(&task) {
+                log::warn!("discarding checkpoint {:?} of task by client {client_pid}, no longer restartable", path);
+                remove_stale(&checkpoint.tmp_output, &path);
+                continue;
+            }
+
+            log::info!("resuming task by client {client_pid} from checkpoint {:?}", path);
+            task.checkpoint = Some(path.clone());
+            match self.new_task(task) {
+                Ok(_) => {},
+                Err(ServerError::UnknownQueue(queue)) => {
+                    log::warn!("discarding checkpoint {:?}: queue {queue} no longer exists", path);
+                    remove_stale(&checkpoint.tmp_output, &path);
+                },
+                // The task was queued regardless: its client may just be gone.
+                Err(err) => log::warn!("could not tell client {client_pid} its task is resuming: {:?}", err),
             }
         }
 
-        None
+        Ok(())
+    }
+
+    /// Validate a task submitted with `--dry-run`, see [`dry_run::check_task`], and report
+    /// to its client what would happen if it were submitted for real.
+    pub fn dry_run_task(&mut self, server_config: &ServerConfig, task: &ClientTask) -> Result<(), ServerError> {
+        let problems = dry_run::check_task(task, server_config);
+
+        let queue = self.queues.iter().find(|q| q.name() == task.queue_name());
+        let would_start_now = problems.is_empty() && match queue {
+            None => false,
+            Some(queue) =>
+                queue.pending().is_empty() &&
+                self.filters_count.can_run_pipeline(&server_config.filters_config, &task.filter_demand()) &&
+                queue.filters_count.can_run_pipeline(&queue.config.filters_config, &task.filter_demand()),
+        };
+
+        let stages = task
+            .transformations
+            .iter()
+            .map(|filter| (filter.clone(), server_config.filter_executor(filter).to_string()))
+            .collect();
+
+        let report = DryRunReport {
+            queue: task.queue_name().to_string(),
+            stages,
+            problems,
+            would_start_now
+        };
+        self.send_msg_to_client(task.client_pid, task.request_id, &MessageToClient::DryRun(report))
+    }
+
+    /// Attempt to remove the next task to be executed from one of the queues.
+    ///
+    /// For it to be possible, the following is required:
+    ///
+    /// * That the server has pending tasks in some queue
+    /// * That the task chosen by that queue's scheduler can be run, given the server's
+    ///   currently running filter count, the queue's own count and budget, and the filters
+    ///   required to execute the task, or else one of the few after it, see
+    ///   [`ServerConfig::scan_depth`].
+    ///
+    /// Among the queues with a task that can be run, the one that received the least service
+    /// relative to its weight is chosen. If no task can be run, return `None`.
+    pub fn try_pop_task(&mut self, server_config: &ServerConfig) -> Option<Arc<ClientTask>> {
+        // Pending tasks are left for the server taking over, see `ServerState::start_handover`.
+        if self.handover.is_some() {
+            return None
+        }
+        // Every thread of the monitor pool is taken, by the tasks not handed to workers, but
+        // for those of suspended tasks, which the pool has spare threads for.
+        let running = self
+            .running_tasks
+            .values()
+            .filter(|monitor| monitor.worker().is_none() && !monitor.is_suspended())
+            .count();
+        if running >= self.monitors.as_ref().map_or(0, MonitorPool::size) {
+            return None
+        }
+        let filters_count = &self.filters_count;
+        let (queue, (position, runnable)) = self.queues
+            .iter_mut()
+            .filter_map(|queue| {
+                let runnable = queue.next_runnable(filters_count, &server_config.filters_config)?;
+                Some((queue, runnable))
+            })
+            .min_by_key(|(queue, _)| queue.backlogged_service())?;
+        let task = queue.pop_runnable(&runnable)?;
+        if position > 0 {
+            self.task_logs.record(&task, TaskLogEvent::RanAhead { position });
+        }
+        Some(task)
+    }
+
+    /// Pop a pending task that a worker can run, see [`coordinator::is_eligible`], given the
+    /// filters it runs and its own limits, rather than the server's and its queue's budget,
+    /// as [`ServerState::try_pop_task`] does, returning it with the ID of the worker.
+    ///
+    /// Workers running the fewest tasks are handed tasks first. If no worker can run one of
+    /// the tasks next in their queues, return `None`.
+    pub fn try_pop_remote_task(&mut self) -> Option<(usize, Arc<ClientTask>)> {
+        if self.handover.is_some() {
+            return None
+        }
+        let mut workers = self.workers.values().filter(|worker| worker.tasks < worker.max_tasks).collect::<Vec<_>>();
+        workers.sort_by_key(|worker| worker.tasks);
+        let (worker_id, queue, (position, runnable)) = workers
+            .into_iter()
+            .find_map(|worker| self.queues
+                .iter()
+                .enumerate()
+                .filter_map(|(queue, pending)| {
+                    let runnable = pending.next_runnable_on(&worker.filters_count, &worker.limits)?;
+                    coordinator::is_eligible(&runnable.1).then_some((worker.link.id, queue, runnable))
+                })
+                .min_by_key(|(_, queue, _)| self.queues[*queue].backlogged_service())
+            )?;
+        let task = self.queues[queue].pop_runnable(&runnable)?;
+        if position > 0 {
+            self.task_logs.record(&task, TaskLogEvent::RanAhead { position });
+        }
+        Some((worker_id, task))
+    }
+
+    /// The queue a task was submitted to. Tasks are only ever queued if their queue exists.
+    fn queue_of(&mut self, task: &ClientTask) -> Option<&mut TaskQueue> {
+        self.queues.iter_mut().find(|q| q.name() == task.queue_name())
     }
 
     /// Begin processing of a task popped from the priority queue.
@@ -283,135 +1392,1329 @@ impl ServerState {
     pub fn process_task(
         &mut self,
         server_config: &ServerConfig,
-        task: ClientTask
-    ) -> Result<(ThreadId, usize), ServerError> {
-            let msg_to_client = MessageToClient::Processing;
+        task: Arc<ClientTask>
+    ) -> Result<usize, ServerError> {
+            let span = self.task_span(&task);
+            let _entered = span.enter();
+            self.announce_start(&task)?;
 
-            self.send_msg_to_client(task.client_pid, &msg_to_client)?;
-
-            // update server's limits with new task's counts.
-            self.filters_count.add_assign(&task.transformations);
+            // update server's and queue's limits with new task's counts.
+            self.claim_filters(&task);
             // get and update server's task counter
             let task_number = self.get_incr_task_counter();
+            span.record("task_number", task_number);
 
+            let executors = task
+                .transformations
+                .iter()
+                .map(|filter| server_config.filter_executor(filter))
+                .collect();
+            // Resumed tasks keep their checkpoint, see `resume_checkpointed`.
+            let checkpoint = match server_config.is_restartable(&task) {
+                false => None,
+                true => task
+                    .checkpoint
+                    .clone()
+                    .or_else(|| Some(checkpoint::new_path(&self.checkpoint_dir(), task_number))),
+            };
             let sender_clone = self.sender.clone();
-            let monitor = Monitor::build(
-                task, task_number, server_config.transformations_path(), sender_clone
-            )?;
-            let monitor_id = monitor.thread_id();
+            // Pooled workers are started in the server's environment, and cached outputs were
+            // written in it, so tasks setting their own bypass both.
+            let own_environment = !task.env.is_empty() || task.cwd.is_some();
+            let started = TaskEvent::Started { task_number, task: Arc::clone(&task) };
+            let built = match self.monitors.as_ref() {
+                None => Err(MonitorBuildError::PoolStopped),
+                Some(monitors) => Monitor::build(
+                    Arc::clone(&task),
+                    task_number,
+                    executors,
+                    server_config.resource_limits.for_priority(task.priority),
+                    server_config.sandbox,
+                    server_config.environment.for_task(&task),
+                    sender_clone,
+                    checkpoint,
+                    self.pool.clone().filter(|_| !own_environment),
+                    self.cache.clone().filter(|_| !own_environment),
+                    self.wasm.clone(),
+                    server_config.progress_interval,
+                    server_config.pipe_buffer,
+                    self.path_policy.for_task(&task),
+                    monitors
+                ),
+            };
+            let monitor = match built {
+                Ok(monitor) => monitor,
+                Err(err) => {
+                    // The task never runs, so the filters counted for it are free again.
+                    self.release_filters(&task);
+                    self.reject_task(task, RequestFailure::Internal(String::from("the task could not be started")));
+                    return Err(err.into())
+                },
+            };
+            let commands = monitor
+                .task
+                .transformations
+                .iter()
+                .map(|filter| (filter.clone(), server_config.filter_executor(filter).to_string()))
+                .collect();
+            self.task_logs.record(&monitor.task, TaskLogEvent::Started { task_number, commands });
+            // Resumed tasks weren't received by this server, so how long they waited is unknown.
+            if let Some(received_at) = monitor.task.received_at {
+                self.stats.record_start(monitor.started_at.duration_since(received_at));
+            }
+
+            self.running_tasks.insert(task_number, monitor);
+            self.publish(started);
+
+            Ok(task_number)
+    }
+
+    /// Tell the client of `task` that it is starting. Should the client be gone, the task is
+    /// dropped, see [`ServerState::orphan_task`], unless it is resuming from its checkpoint.
+    fn announce_start(&mut self, task: &Arc<ClientTask>) -> Result<(), ServerError> {
+        // The client of a resumed task may be gone, since the server was down, but the
+        // task's output is still wanted.
+        match self.send_msg_to_client(task.client_pid, task.request_id, &MessageToClient::Processing) {
+            Err(err) if task.checkpoint.is_some() =>
+                log::warn!("could not tell client {} its task resumed: {:?}", task.client_pid, err),
+            Err(ServerError::UdSocketWriteError(err)) => {
+                self.orphan_task(Arc::clone(task), &err);
+                return Err(ServerError::ClientGone(task.client_pid))
+            },
+            res => res?,
+        }
+        Ok(())
+    }
+
+    /// Hand `task`, popped for the worker with `worker_id`, see
+    /// [`ServerState::try_pop_remote_task`], to that worker, counting its filters as running
+    /// on it, and in its queue, and indexing it among the running tasks, as
+    /// [`ServerState::process_task`] does for those the server runs itself.
+    ///
+    /// Should the worker not be sent the task, it is disconnected, and its tasks put back in
+    /// their queues, see [`ServerState::handle_worker_event`].
+    pub fn dispatch_task(&mut self, worker_id: usize, task: Arc<ClientTask>) -> Result<usize, ServerError> {
+        let span = self.task_span(&task);
+        let _entered = span.enter();
+        let Some(worker) = self.workers.get_mut(&worker_id) else {
+            // The worker was popped for just now, so this can't happen.
+            self.requeue(task);
+            return Err(ServerError::UnknownWorker(worker_id))
+        };
+        worker.filters_count.add_assign(&task.filter_demand());
+        worker.tasks += 1;
+        let link = Arc::clone(&worker.link);
+        if let Err(err) = self.announce_start(&task) {
+            self.release_remote_filters(worker_id, &task);
+            return Err(err)
+        }
+        let task_number = self.get_incr_task_counter();
+        span.record("task_number", task_number);
 
-            self.running_tasks.insert(monitor.thread_id(), monitor);
+        let waited = task.received_at.map(|at| at.elapsed()).unwrap_or_default();
+        let run = CoordinatorMessage::Run { task_number, task: Box::new(ClientTask::clone(&task)), waited };
+        if let Err(err) = link.send(&run) {
+            log::warn!("could not hand task #{task_number} to worker {}, disconnecting it: {:?}", link.name, err);
+            link.close();
+        }
+        let monitor = Monitor::remote(Arc::clone(&task), task_number, Arc::clone(&link));
+        let dispatched = TaskLogEvent::Dispatched { task_number, worker: link.name.clone() };
+        self.task_logs.record(&task, dispatched);
+        if let Some(received_at) = task.received_at {
+            self.stats.record_start(monitor.started_at.duration_since(received_at));
+        }
+        self.running_tasks.insert(task_number, monitor);
+        self.publish(TaskEvent::Started { task_number, task });
 
-            Ok((monitor_id, task_number))
+        Ok(task_number)
     }
 
     /// Given the result of a monitor that was responsible for a given task,
     /// process its data and update the server's state accordingly:
     ///
-    /// * inform the client if the task ended in success or failure, and
+    /// * inform the client if the task ended in success or failure,
+    /// * log what became of a failed task's partial output, and
     /// * update the server's count of currently running filters
     pub fn handle_task_result(&mut self, mon_res: MonitorResult) -> Result<(), ServerError> {
-        let MonitorResult { thread, result } = mon_res;
+        let MonitorResult { task_number, result, partial_output } = mon_res;
 
-        let monitor = match self.running_tasks.remove(&thread) {
+        let monitor = match self.running_tasks.remove(&task_number) {
             Some(m) => m,
-            // This would be very odd: there is a thread in the server supposedly running a
-            // monitor, but that monitor does not exist.
-            None => panic!()
+            // This would be very odd: a monitor reported the result of a task the server
+            // doesn't know to be running, which there is nothing to do with.
+            None => {
+                log::error!("received the result of task #{task_number}, which isn't running");
+                return Ok(())
+            },
         };
+        let _entered = monitor.span.clone().entered();
 
-        // update server's running filter counts to account for finished task.
-        self.filters_count.sub_assign(&monitor.task.get_transformations());
+        // update server's, or worker's, and queue's running filter counts to account for finished task.
+        // Those of suspended tasks were released as they were suspended.
+        match monitor.worker() {
+            None if monitor.is_suspended() => {},
+            None => self.release_filters(&monitor.task),
+            Some(worker) => self.release_remote_filters(worker.id, &monitor.task),
+        }
 
-        let msg_to_client = mon_res_to_cl_msg(result);
+        let suspended = matches!(partial_output, Some(PartialOutput::Checkpointed(_)));
+        log_partial_output(partial_output, monitor.task_number);
+        let result = match result {
+            Ok(TaskSummary::File(summary)) if monitor.task.store => self.add_to_store(&monitor.task, summary),
+            result => result,
+        };
+        self.audit_stages(&monitor, &result);
+        self.log_stages(&monitor, &result);
+        if !suspended {
+            self.stats.record_run(monitor.started_at.elapsed());
+        }
+        if result.is_ok() && !suspended {
+            self.task_durations.record(monitor.started_at.elapsed());
+        }
+        if let Ok(TaskSummary::File(MonitorSuccess { stage_timings, .. })) = &result {
+            self.task_durations.record_stages(stage_timings);
+        }
+        match &result {
+            Ok(TaskSummary::File(MonitorSuccess { bytes_in, bytes_out, .. })) |
+            Ok(TaskSummary::Batch(BatchSummary { bytes_in, bytes_out, .. })) if !suspended => {
+                self.stats.bytes_in += bytes_in;
+                self.stats.bytes_out += bytes_out;
+            },
+            _ => {},
+        }
 
-        let client_pid = monitor.task.client_pid;
-        self.send_msg_to_client(client_pid, &msg_to_client)
-    }
+        // A record of what each queue's tasks used, to account for it.
+        match &result {
+            Err(err) => log::warn!("task #{} failed: {:?}", monitor.task_number, err),
+            Ok(TaskSummary::File(MonitorSuccess { resource_usage, .. })) |
+            Ok(TaskSummary::Batch(BatchSummary { resource_usage, .. })) => log::info!(
+                "task #{} by client {} in queue {} used {resource_usage}",
+                monitor.task_number, monitor.task.client_pid, monitor.task.queue_name()
+            ),
+        }
+        // The client only reads its output once told the task concluded, so this is sent first.
+        let succeeded = result.is_ok();
+        let msg_to_client = match suspended {
+            true => MessageToClient::Suspended,
+            false => mon_res_to_cl_msg(result),
+        };
+        let msg_to_client = self.send_inline_output(&monitor.task, msg_to_client);
+        let (task_number, task) = (monitor.task_number, Arc::clone(&monitor.task));
+        let event = match &msg_to_client {
+            MessageToClient::Suspended => None,
+            MessageToClient::Failed(failure) =>
+                Some(TaskEvent::Failed { task_number: Some(task_number), task, failure: failure.clone() }),
+            _ => Some(TaskEvent::Finished { task_number, task }),
+        };
 
-    /// Create a `String` message representing the server's state, including
+        let client_pid = monitor.task.client_pid;
+        let sent = self.send_msg_to_client(client_pid, monitor.task.request_id, &msg_to_client);
+        // Subscribers and waiters are told of the task regardless of whether its client still listens.
+        match event {
+            Some(event) => self.finish(event, msg_to_client),
+            None => {
+                self.task_logs.record(&monitor.task, TaskLogEvent::Suspended);
+                self.task_spans.remove(&monitor.task.request_id);
+                self.tell_waiters(monitor.task.request_id, &msg_to_client)
+            },
+        }
+        // Streamed tasks are done with whether their clients were told or not.
+        let streamed = self.finish_stream(&monitor.task, succeeded);
+        sent.and(streamed)
+    }
+
+    /// Add the output of `task`, which is written to the store, to the store, see
+    /// [`OutputStore::add`], telling its client where it is stored in `summary`.
+    fn add_to_store(&self, task: &ClientTask, mut summary: MonitorSuccess) -> Result<TaskSummary, MonitorError> {
+        // Tasks written to the store are refused if there is none, see `new_task`.
+        let Some(store) = &self.store else {
+            return Ok(TaskSummary::File(summary))
+        };
+        let path = store
+            .add(task.output_filepath(), &summary.sha256_out)
+            .map_err(MonitorError::OutputRenameError)?;
+        log::info!("output of request {} stored as {}", task.request_id, path.display());
+        summary.stored = Some(path);
+        Ok(TaskSummary::File(summary))
+    }
+
+    /// Count the filters of `task` as running, in the server's and its queue's counts.
+    fn claim_filters(&mut self, task: &ClientTask) {
+        self.filters_count.add_assign(&task.filter_demand());
+        if let Some(queue) = self.queue_of(task) {
+            queue.filters_count.add_assign(&task.filter_demand());
+        }
+    }
+
+    /// Take the filters `task` was counted as running off the server's and its queue's counts.
+    fn release_filters(&mut self, task: &ClientTask) {
+        self.filters_count.sub_assign(&task.filter_demand());
+        if let Some(queue) = self.queue_of(task) {
+            queue.filters_count.sub_assign(&task.filter_demand());
+        }
+    }
+
+    /// Take the filters `task` was counted as running off the counts of the worker with
+    /// `worker_id`, if it is still registered.
+    fn release_remote_filters(&mut self, worker_id: usize, task: &ClientTask) {
+        if let Some(worker) = self.workers.get_mut(&worker_id) {
+            worker.filters_count.sub_assign(&task.filter_demand());
+            worker.tasks = worker.tasks.saturating_sub(1);
+        }
+    }
+
+    /// Record how each stage of the pipeline of `monitor`'s task went in the audit log, as
+    /// far as `result` tells: every stage of a file's pipeline that succeeded, or the one
+    /// that failed.
+    fn audit_stages(&mut self, monitor: &Monitor, result: &Result<TaskSummary, MonitorError>) {
+        let task_number = monitor.task_number;
+        let stages = match result {
+            Ok(TaskSummary::File(success)) => success
+                .stage_timings
+                .iter()
+                .enumerate()
+                .map(|(stage, timing)| AuditEvent::Stage {
+                    task_number,
+                    stage,
+                    filter: &timing.filter,
+                    duration_ms: Some(timing.end.saturating_sub(timing.start).as_millis() as u64),
+                    failure: None,
+                })
+                .collect(),
+            Err(
+                MonitorError::StageError { stage, .. } |
+                MonitorError::WorkerFailed(RequestFailure::StageFailed { stage, .. })
+            ) => vec![AuditEvent::Stage {
+                task_number,
+                stage: stage.index,
+                filter: &stage.filter,
+                duration_ms: None,
+                failure: Some(stage.to_string()),
+            }],
+            _ => Vec::new(),
+        };
+        for event in stages {
+            self.audit(&monitor.task, event);
+        }
+    }
+
+    /// Record how long each stage of the pipeline of `monitor`'s task ran, and what they
+    /// wrote to `stderr`, in its log, if it succeeded on a file. Failures are recorded as
+    /// the task concludes, see [`ServerState::finish`].
+    fn log_stages(&mut self, monitor: &Monitor, result: &Result<TaskSummary, MonitorError>) {
+        let Ok(TaskSummary::File(success)) = result else { return };
+        for (stage, timing) in success.stage_timings.iter().enumerate() {
+            self.task_logs.record(&monitor.task, TaskLogEvent::Stage { stage, timing: timing.clone() });
+        }
+        if !success.stderr.is_empty() {
+            self.task_logs.record(&monitor.task, TaskLogEvent::Stderr(success.stderr.clone()));
+        }
+    }
+
+    /// Relay the result of a batch task's pipeline on one of its files to the client that
+    /// submitted it, logging what became of its partial output if it failed.
+    pub fn handle_batch_file(&mut self, file_result: BatchFileResult) -> Result<(), ServerError> {
+        let BatchFileResult { task_number, input, output, result, partial_output } = file_result;
+        let monitor = match self.running_tasks.get(&task_number) {
+            None => return Ok(()),
+            Some(monitor) => monitor,
+        };
+        let _entered = monitor.span.clone().entered();
+
+        log_partial_output(partial_output, monitor.task_number);
+        if let Err(err) = &result {
+            log::warn!("task #{} failed on {:?}: {:?}", monitor.task_number, input, err);
+        }
+
+        let result = Box::new(result.map_or_else(mon_err_to_cl_msg, MessageToClient::Concluded));
+        let msg_to_client = MessageToClient::BatchFile { input, output, result };
+        self.send_msg_to_client(monitor.task.client_pid, monitor.task.request_id, &msg_to_client)
+    }
+
+    /// Relay the progress of a running task's pipeline to the client that submitted it.
+    ///
+    /// Progress from a monitor that is no longer running is ignored.
+    pub fn handle_task_progress(&mut self, progress: MonitorProgress) -> Result<(), ServerError> {
+        let MonitorProgress { task_number, bytes_out } = progress;
+        let monitor = self.running_tasks.get_mut(&task_number).map(|monitor| {
+            monitor.bytes_out = bytes_out;
+            (monitor.task.client_pid, monitor.task.request_id)
+        });
+        match monitor {
+            None => Ok(()),
+            Some((client_pid, request_id)) =>
+                self.send_msg_to_client(client_pid, request_id, &MessageToClient::Progress { bytes_out }),
+        }
+    }
+
+    /// Cancel the request `request_id`, for the client with `client_pid`, see
+    /// [`ClientRequest::Cancel`]. A pending task is dropped from its queue, and its client told
+    /// so, while a running one is killed, its monitor then reporting it cancelled as usual.
+    ///
+    /// Only the user who submitted the request may cancel it, or an admin, see
+    /// [`auth::authorize_owner`]: others are only warned of. Requests that concluded already
+    /// aren't cancelled.
+    pub fn cancel(&mut self, client_pid: u32, request_id: Uuid, credentials: Option<Credentials>) -> io::Result<()> {
+        let is_task = |task: &ClientTask| task.request_id == request_id;
+        let owner = self.queues
+            .iter()
+            .flat_map(TaskQueue::pending)
+            .map(|task| &**task)
+            .chain(self.running_tasks.values().map(|monitor| &*monitor.task))
+            .find(|task| is_task(task))
+            .map(|task| task.client_uid);
+        if let Some(owner) = owner {
+            // SAFETY: the call has no memory safety requirement.
+            if let Err(err) = auth::authorize_owner(credentials, owner, unsafe { libc::geteuid() }) {
+                log::warn!("refused to cancel request {request_id} for client PID {client_pid}: {err}");
+                return Ok(())
+            }
+        }
+        if let Some(task) = self.queues.iter_mut().find_map(|queue| queue.remove(&is_task)) {
+            let _entered = self.task_span(&task).entered();
+            self.audit(&task, AuditEvent::CancelRequested { by_pid: client_pid });
+            self.task_logs.record(&task, TaskLogEvent::CancelRequested { by_pid: client_pid });
+            self.reject_task(task, RequestFailure::Cancelled);
+            return Ok(())
+        }
+        match self.running_tasks.values().find(|monitor| is_task(&monitor.task)) {
+            Some(monitor) => {
+                let _entered = monitor.span.enter();
+                log::info!(
+                    "client {client_pid} cancelling task #{} by client {}", monitor.task_number, monitor.task.client_pid
+                );
+                if let Some(audit) = &mut self.audit {
+                    audit.record(&monitor.task, AuditEvent::CancelRequested { by_pid: client_pid });
+                }
+                self.task_logs.record(&monitor.task, TaskLogEvent::CancelRequested { by_pid: client_pid });
+                monitor.cancel()
+            },
+            None => {
+                log::warn!("client {client_pid} cancelled request {request_id}, which isn't pending nor running");
+                Ok(())
+            },
+        }
+    }
+
+    /// Suspend the running task `target`, as the client with `client_pid` asked for with its
+    /// request `request_id`, see [`ClientRequest::Suspend`]: its pipeline is stopped, see
+    /// [`Monitor::suspend`], and its filters no longer counted as running, so that other
+    /// tasks may run in their stead, the monitor pool being given a thread for it.
+    ///
+    /// Suspended tasks go on counting towards their timeout, if any, and are resumed as the
+    /// server shuts down, or hands over, see [`ServerState::resume_suspended`].
+    pub fn suspend(&mut self, client_pid: u32, request_id: Uuid, target: TaskId, credentials: Option<Credentials>) -> Result<(), ServerError> {
+        self.set_suspended(client_pid, request_id, target, credentials, true)
+    }
+
+    /// Resume the suspended task `target`, as the client with `client_pid` asked for with its
+    /// request `request_id`, see [`ClientRequest::Resume`]. Its filters are counted as running
+    /// again, even if that takes the counts past their limits, until enough tasks conclude.
+    pub fn resume(&mut self, client_pid: u32, request_id: Uuid, target: TaskId, credentials: Option<Credentials>) -> Result<(), ServerError> {
+        self.set_suspended(client_pid, request_id, target, credentials, false)
+    }
+
+    /// Suspend, or resume, the running task `target`, see [`ServerState::suspend`], telling
+    /// the client with `client_pid` how it went, in reply to its request `request_id`. Only
+    /// the task's own user, and admins, may, see [`auth::authorize_owner`]: the client is
+    /// refused otherwise, as told by its `credentials`.
+    fn set_suspended(
+        &mut self,
+        client_pid: u32,
+        request_id: Uuid,
+        target: TaskId,
+        credentials: Option<Credentials>,
+        suspend: bool
+    ) -> Result<(), ServerError> {
+        let monitor = self.running_tasks.values().find(|monitor| match target {
+            TaskId::Request(id) => monitor.task.request_id == id,
+            TaskId::Number(task_number) => monitor.task_number == task_number,
+        });
+        let Some(monitor) = monitor.filter(|monitor| monitor.worker().is_none()) else {
+            let failed = MessageToClient::Failed(RequestFailure::NotRunning(target));
+            return self.send_msg_to_client(client_pid, request_id, &failed)
+        };
+        // SAFETY: the call has no memory safety requirement.
+        if let Err(err) = auth::authorize_owner(credentials, monitor.task.client_uid, unsafe { libc::geteuid() }) {
+            log::warn!("refused request {request_id} by client PID {client_pid}: {err}");
+            return self.send_msg_to_client(client_pid, request_id, &MessageToClient::Refused(err.to_string()))
+        }
+        let span = monitor.span.clone();
+        let _entered = span.enter();
+        let (task_number, task) = (monitor.task_number, Arc::clone(&monitor.task));
+        let changed = if suspend { monitor.suspend() } else { monitor.resume() };
+
+        // The pipeline is marked as suspended, or not, even if signalling some of its stages failed.
+        if !matches!(changed, Ok(false)) {
+            if suspend {
+                log::info!("client {client_pid} suspended task #{task_number}");
+                self.release_filters(&task);
+                self.task_logs.record(&task, TaskLogEvent::SuspendedByClient { by_pid: client_pid });
+                self.reserve_monitors();
+            } else {
+                log::info!("client {client_pid} resumed task #{task_number}");
+                self.claim_filters(&task);
+                self.task_logs.record(&task, TaskLogEvent::ResumedByClient { by_pid: client_pid });
+            }
+        }
+        let reply = match changed {
+            Err(err) => {
+                log::error!("could not signal every stage of task #{task_number}: {:?}", err);
+                MessageToClient::Failed(RequestFailure::Internal(format!("could not signal every stage of task #{task_number}")))
+            },
+            Ok(_) if suspend => MessageToClient::TaskSuspended { task_number },
+            Ok(_) => MessageToClient::TaskResumed { task_number },
+        };
+        self.send_msg_to_client(client_pid, request_id, &reply)
+    }
+
+    /// Have the monitor pool spawn a thread for every suspended task, see
+    /// [`MonitorPool::reserve`].
+    fn reserve_monitors(&mut self) {
+        let suspended = self.running_tasks.values().filter(|monitor| monitor.is_suspended()).count();
+        if let Some(Err(err)) = self.monitors.as_mut().map(|monitors| monitors.reserve(suspended)) {
+            log::error!("could not spawn a monitor thread for every suspended task: {:?}", err);
+        }
+    }
+
+    /// Resume every suspended task, for them to conclude as the server shuts down, or hands
+    /// over, rather than be killed, see [`ServerState::suspend`].
+    fn resume_suspended(&mut self) {
+        let suspended = self
+            .running_tasks
+            .values()
+            .filter(|monitor| monitor.is_suspended())
+            .map(|monitor| (monitor.task_number, Arc::clone(&monitor.task), monitor.resume()))
+            .collect::<Vec<_>>();
+        for (task_number, task, resumed) in suspended {
+            log::info!("resuming suspended task #{task_number}");
+            if let Err(err) = resumed {
+                log::error!("could not resume every stage of task #{task_number}: {:?}", err);
+            }
+            self.claim_filters(&task);
+        }
+    }
+
+    /// Check, every [`RECONCILE_INTERVAL`], that the server's and its queues' counts of the
+    /// filters running are those of the tasks running, correcting those that drifted, as a
+    /// task counted in or out twice would make them, so that limits aren't held up, or
+    /// exceeded, for good.
+    pub fn reconcile_filters(&mut self) {
+        if self.reconciled_at.elapsed() < RECONCILE_INTERVAL {
+            return
+        }
+        self.reconciled_at = Instant::now();
+
+        let running = |counted: &dyn Fn(&Monitor) -> bool| {
+            let mut count = RunningFilters::default();
+            self.running_tasks
+                .values()
+                .filter(|monitor| counted(monitor))
+                .for_each(|monitor| count += &monitor.task.filter_demand());
+            count
+        };
+        // Tasks handed to workers count towards their limits, rather than the server's or their
+        // queues', and suspended tasks towards none.
+        let counted = running(&|monitor| monitor.worker().is_none() && !monitor.is_suspended());
+        if self.filters_count != counted {
+            log::error!("running filters were counted as {:?}, rather than {:?}", self.filters_count, counted);
+            self.filters_count = counted;
+        }
+        let queues_counted = self
+            .queues
+            .iter()
+            .map(|queue| running(&|monitor| {
+                monitor.worker().is_none() && !monitor.is_suspended() && monitor.task.queue_name() == queue.name()
+            }))
+            .collect::<Vec<_>>();
+        let workers_counted = self
+            .workers
+            .keys()
+            .map(|id| running(&|monitor| monitor.worker().is_some_and(|link| link.id == *id)))
+            .collect::<Vec<_>>();
+        for (queue, counted) in self.queues.iter_mut().zip(queues_counted) {
+            if queue.filters_count != counted {
+                log::error!(
+                    "running filters of queue {} were counted as {:?}, rather than {:?}",
+                    queue.name(), queue.filters_count, counted
+                );
+                queue.filters_count = counted;
+            }
+        }
+        for (worker, counted) in self.workers.values_mut().zip(workers_counted) {
+            if worker.filters_count != counted {
+                log::error!(
+                    "running filters of worker {} were counted as {:?}, rather than {:?}",
+                    worker.link.name, worker.filters_count, counted
+                );
+                worker.filters_count = counted;
+            }
+        }
+    }
+
+    /// Cancel every running task that started over `timeout` ago, see
+    /// [`ServerConfig::task_timeout`], as if its client had, see [`ServerState::cancel`].
+    pub fn cancel_overdue(&mut self, timeout: Duration) {
+        let overdue = self
+            .running_tasks
+            .values()
+            .filter(|monitor| !monitor.is_cancelled() && monitor.started_at.elapsed() > timeout);
+        for monitor in overdue {
+            let _entered = monitor.span.enter();
+            log::warn!(
+                "cancelling task #{} by client {}, which ran for longer than {:?}",
+                monitor.task_number, monitor.task.client_pid, timeout
+            );
+            self.task_logs.record(&monitor.task, TaskLogEvent::TimedOut(timeout));
+            if let Err(err) = monitor.cancel() {
+                log::warn!("failed to cancel task #{}: {:?}", monitor.task_number, err);
+            }
+        }
+    }
+
+    /// Handle what happened with a worker, see [`coordinator`]:
+    ///
+    /// * a worker that registered is handed tasks from then on, unless the server is
+    ///   shutting down, in which case it is disconnected;
+    /// * the progress and results of the tasks a worker runs are handled as those of the
+    ///   server's own monitors, see [`ServerState::handle_task_result`], while those it
+    ///   declines are put back in their queues;
+    /// * and the tasks of a worker that left are put back in their queues too, to run
+    ///   elsewhere, unless they were cancelled, or the server is shutting down, in which
+    ///   case they fail.
+    ///
+    /// Messages about tasks the worker doesn't run are ignored.
+    pub fn handle_worker_event(&mut self, event: WorkerEvent) -> Result<(), ServerError> {
+        let runs_on = |state: &Self, task_number: usize, worker_id: usize| state
+            .running_tasks
+            .get(&task_number)
+            .and_then(Monitor::worker)
+            .is_some_and(|worker| worker.id == worker_id);
+        match event {
+            WorkerEvent::Joined(link, _, _) if self.shutting_down => link.close(),
+            WorkerEvent::Joined(link, limits, max_tasks) => {
+                log::info!("worker {} registered as #{}, running up to {max_tasks} task(s) within {:?}", link.name, link.id, limits);
+                self.workers.insert(link.id, RemoteWorker::new(link, limits, max_tasks));
+            },
+            WorkerEvent::Message(worker_id, WorkerMessage::Progress { task_number, bytes_out }) =>
+                if runs_on(self, task_number, worker_id) {
+                    return self.handle_task_progress(MonitorProgress { task_number, bytes_out })
+                },
+            WorkerEvent::Message(worker_id, WorkerMessage::Finished { task_number, result }) =>
+                if runs_on(self, task_number, worker_id) {
+                    let result = result.map(TaskSummary::File).map_err(MonitorError::WorkerFailed);
+                    return self.handle_task_result(MonitorResult { task_number, result, partial_output: None })
+                },
+            WorkerEvent::Message(worker_id, WorkerMessage::Declined { task_number, reason }) =>
+                if runs_on(self, task_number, worker_id) {
+                    if let Some(monitor) = self.running_tasks.remove(&task_number) {
+                        log::warn!("worker #{worker_id} declined task #{task_number}: {reason}");
+                        self.release_remote_filters(worker_id, &monitor.task);
+                        self.requeue_remote(monitor, format!("declined it: {reason}"))?;
+                    }
+                },
+            WorkerEvent::Message(_, WorkerMessage::Heartbeat) => {},
+            WorkerEvent::Message(worker_id, msg @ WorkerMessage::Register { .. }) =>
+                log::warn!("worker #{worker_id} sent {:?} once registered already", msg),
+            WorkerEvent::Left(worker_id) => {
+                let Some(worker) = self.workers.remove(&worker_id) else {
+                    // It never registered.
+                    return Ok(())
+                };
+                let task_numbers = self
+                    .running_tasks
+                    .values()
+                    .filter(|monitor| monitor.worker().is_some_and(|link| link.id == worker_id))
+                    .map(|monitor| monitor.task_number)
+                    .collect::<Vec<_>>();
+                log::warn!("worker {} (#{worker_id}) left, running {} task(s)", worker.link.name, task_numbers.len());
+                for task_number in task_numbers {
+                    let Some(monitor) = self.running_tasks.remove(&task_number) else { continue };
+                    self.release_remote_filters(worker_id, &monitor.task);
+                    self.requeue_remote(monitor, String::from("disconnected"))?;
+                }
+            },
+        }
+        Ok(())
+    }
+
+    /// Put the task of `monitor`, which was handed to a worker that couldn't run it, for
+    /// `reason`, and was already counted out of the running filters, back in its queue, to be
+    /// run again. Should it have been cancelled, or the server be shutting down, it fails
+    /// instead.
+    fn requeue_remote(&mut self, monitor: Monitor, reason: String) -> Result<(), ServerError> {
+        let worker = monitor.worker().map(|link| link.name.clone()).unwrap_or_default();
+        let _entered = monitor.span.clone().entered();
+        if monitor.is_cancelled() || self.shutting_down {
+            let failure = match monitor.is_cancelled() {
+                true => RequestFailure::Cancelled,
+                false => RequestFailure::Internal(format!("the worker {worker} running the request {reason}")),
+            };
+            let msg = MessageToClient::Failed(failure.clone());
+            let task_number = Some(monitor.task_number);
+            let sent = self.send_msg_to_client(monitor.task.client_pid, monitor.task.request_id, &msg);
+            self.finish(TaskEvent::Failed { task_number, task: Arc::clone(&monitor.task), failure }, msg);
+            return sent
+        }
+
+        log::info!("requeuing task #{}, as worker {worker} {reason}", monitor.task_number);
+        self.task_logs.record(&monitor.task, TaskLogEvent::Requeued { worker, reason });
+        self.requeue(monitor.task);
+        Ok(())
+    }
+
+    /// Put `task`, which was popped from its queue, back in it.
+    fn requeue(&mut self, task: Arc<ClientTask>) {
+        let min_service = self.queues.iter().filter_map(TaskQueue::backlogged_service).min();
+        match self.queue_of(&task) {
+            Some(queue) => queue.push(task, min_service),
+            None => {
+                let queue = task.queue_name().to_string();
+                self.reject_task(task, RequestFailure::UnknownQueue(queue))
+            },
+        }
+    }
+
+    /// Send every worker a heartbeat, every [`HEARTBEAT_INTERVAL`](coordinator::HEARTBEAT_INTERVAL),
+    /// so that they can tell the server is still there, disconnecting those that can't be
+    /// sent to.
+    pub fn heartbeat_workers(&mut self) {
+        if self.heartbeat_at.elapsed() < coordinator::HEARTBEAT_INTERVAL {
+            return
+        }
+        self.heartbeat_at = Instant::now();
+        for worker in self.workers.values() {
+            if let Err(err) = worker.link.send(&CoordinatorMessage::Heartbeat) {
+                log::warn!("could not send heartbeat to worker {}, disconnecting it: {:?}", worker.link.name, err);
+                worker.link.close();
+            }
+        }
+    }
+
+    /// Wait up to `timeout` for every running monitor to finish, handling the messages that
+    /// come in meanwhile as the server shuts down, see [`ServerState::serve_shutdown_for`].
+    ///
+    /// Returns how many are still running. Their tasks remain in the running tasks until
+    /// their results are handled, see [`ServerState::handle_task_result`].
+    pub async fn wait_for_monitors(&mut self, config: &ServerConfig, timeout: Duration) -> usize {
+        const POLL_INTERVAL: Duration = Duration::from_millis(50);
+        let deadline = Instant::now() + timeout;
+
+        loop {
+            let still_running = self.running_tasks.values().filter(|monitor| !monitor.is_finished()).count();
+
+            let now = Instant::now();
+            if still_running == 0 || now >= deadline {
+                return still_running
+            }
+            self.serve_shutdown_for(config, POLL_INTERVAL.min(deadline - now)).await;
+        }
+    }
+
+    /// Handle the messages that come in for up to `duration` as the server shuts down, or
+    /// only those already in, if `duration` is zero, see [`ServerState::shutdown`].
+    async fn serve_shutdown_for(&mut self, config: &ServerConfig, duration: Duration) {
+        let deadline = tokio::time::Instant::now() + duration;
+        while let Ok(Some(msg)) = tokio::time::timeout_at(deadline, self.next_message()).await {
+            self.handle_on_shutdown(config, msg);
+        }
+    }
+
+    /// Handle `msg` as the server shuts down: results of tasks are relayed to their clients,
+    /// as are health checks, but new tasks are rejected, and other requests ignored.
+    fn handle_on_shutdown(&mut self, config: &ServerConfig, msg: MessageToServer) {
+        match msg {
+            MessageToServer::Monitor(res) => {
+                if let Err(err) = self.handle_task_result(res) {
+                    log::warn!("failed to relay task result during shutdown: {:?}", err);
+                }
+            },
+            MessageToServer::Client(ClientRequest::ProcFile(task), peer, _) => {
+                self.register_peer(task.client_pid, peer);
+                self.reject_task(Arc::new(*task), RequestFailure::ShuttingDown);
+            },
+            MessageToServer::Client(ClientRequest::Connect(client_pid), peer, _) => self.register_peer(client_pid, peer),
+            MessageToServer::Streamed(task, stream) => {
+                self.add_stream(task.client_pid, stream);
+                self.reject_task(Arc::new(task), RequestFailure::ShuttingDown);
+            },
+            MessageToServer::BatchFile(file_result) => {
+                if let Err(err) = self.handle_batch_file(file_result) {
+                    log::warn!("failed to relay batch file result during shutdown: {:?}", err);
+                }
+            },
+            MessageToServer::Unreadable(peer, credentials, failure) =>
+                self.reject_unreadable(peer, credentials, failure),
+            MessageToServer::Worker(event) => {
+                if let Err(err) = self.handle_worker_event(event) {
+                    log::warn!("failed to relay result of task run by worker during shutdown: {:?}", err);
+                }
+            },
+            MessageToServer::Client(ClientRequest::Health(client_pid, request_id), peer, _) => {
+                self.register_peer(client_pid, peer);
+                if let Err(err) = self.send_health(config, client_pid, request_id) {
+                    log::warn!("could not tell client {client_pid} the server is shutting down: {:?}", err);
+                }
+            },
+            MessageToServer::Client(ClientRequest::ServerInfo(client_pid, request_id), peer, _) => {
+                self.register_peer(client_pid, peer);
+                if let Err(err) = self.send_server_info(config, client_pid, request_id) {
+                    log::warn!("failed to send server info to client {client_pid} during shutdown: {:?}", err);
+                }
+            },
+            // Clients waiting for their tasks to conclude, or be suspended, check it's alive.
+            MessageToServer::Client(ClientRequest::Ping(client_pid, request_id), peer, _) => {
+                self.register_peer(client_pid, peer);
+                if let Err(err) = self.send_pong(client_pid, request_id) {
+                    log::warn!("failed to answer ping by client {client_pid} during shutdown: {:?}", err);
+                }
+            },
+            MessageToServer::Client(
+                ClientRequest::Status(..) | ClientRequest::Ack(..) | ClientRequest::Subscribe(..) |
+                ClientRequest::Unsubscribe(_) | ClientRequest::Cancel(..) |
+                ClientRequest::History(..) | ClientRequest::Query(..) | ClientRequest::Wait(..) |
+                ClientRequest::Logs(..) | ClientRequest::Report(..) | ClientRequest::Reexec(..) |
+                ClientRequest::Suspend(..) | ClientRequest::Resume(..), ..
+            ) |
+            MessageToServer::Progress(_) | MessageToServer::Shutdown(_) => {},
+        }
+    }
+
+    /// Let clients have the server exec `executable` afresh, e.g. once upgraded, handing
+    /// itself over to it, see [`ServerState::start_handover`].
+    pub fn enable_reexec(&mut self, executable: PathBuf) {
+        self.executable = Some(executable);
+    }
+
+    /// The executable the server execs afresh as it hands over, if it may, see
+    /// [`ServerState::enable_reexec`].
+    pub fn executable(&self) -> Option<&Path> {
+        self.executable.as_deref()
+    }
+
+    /// Start handing the server over to a freshly exec'd one, as the client with
+    /// `client_pid` asked for with its request `request_id`, see [`ClientRequest::Reexec`]:
+    /// no pending task is started anymore, so that they are all handed over, once the tasks
+    /// running conclude, see [`ServerState::ready_to_hand_over`]. Requests are taken as
+    /// usual meanwhile, new tasks waiting in their queues too.
+    pub fn start_handover(&mut self, client_pid: u32, request_id: Uuid) -> Result<(), ServerError> {
+        let refusal = match (&self.executable, &self.handover) {
+            (None, _) => Some("the server can't be re-exec'd, as it runs embedded in another program"),
+            (_, Some(_)) => Some("the server is handing over already"),
+            (Some(_), None) => None,
+        };
+        if let Some(refusal) = refusal {
+            return self.send_msg_to_client(client_pid, request_id, &MessageToClient::Refused(refusal.to_string()))
+        }
+
+        self.resume_suspended();
+        let running = self.running_tasks.len();
+        log::info!("handing the server over once its {running} running task(s) conclude");
+        self.handover = Some(PendingHandover { client_pid, request_id, started_at: Instant::now(), killed: false });
+        self.send_msg_to_client(client_pid, request_id, &MessageToClient::HandingOver { running })
+    }
+
+    /// Whether the server is to hand over now, no task running anymore, see
+    /// [`ServerState::start_handover`]. The tasks still running once the server waited
+    /// `timeout` for them are killed, as they would be on shutdown.
+    pub fn ready_to_hand_over(&mut self, timeout: Duration) -> bool {
+        let Some(handover) = &mut self.handover else {
+            return false
+        };
+        if self.running_tasks.is_empty() {
+            return true
+        }
+        if !handover.killed && handover.started_at.elapsed() >= timeout {
+            handover.killed = true;
+            log::warn!("killing {} task(s) still running after {:?}, to hand over", self.running_tasks.len(), timeout);
+            for monitor in self.running_tasks.values() {
+                if let Err(err) = monitor.kill() {
+                    log::error!("could not kill task #{}: {:?}", monitor.task_number, err);
+                }
+            }
+        }
+        false
+    }
+
+    /// Hand the server over, once ready to, see [`ServerState::ready_to_hand_over`]: its
+    /// pending tasks are taken from their queues, with what it takes to go on notifying
+    /// their clients, and the server winds down as it would on shutdown, see
+    /// [`ServerState::shutdown`].
+    ///
+    /// Streamed tasks, and those of clients connected to the server, can't outlive its
+    /// process, and are rejected instead, as on shutdown.
+    pub async fn hand_over(&mut self, config: &ServerConfig) -> Handover {
+        self.shutting_down = true;
+        let requested_by = self.handover.take().map(|handover| (handover.client_pid, handover.request_id));
+        let pending = self
+            .queues
+            .iter_mut()
+            .flat_map(|queue| std::iter::from_fn(|| queue.pop()))
+            .collect::<Vec<_>>();
+
+        let mut handover = Handover { requested_by, ..Default::default() };
+        let mut clients = Vec::from_iter(requested_by);
+        for task in pending {
+            if task.stream || matches!(self.peers.get(&task.client_pid), Some(Peer::Connection(_))) {
+                self.reject_task(task, RequestFailure::ShuttingDown);
+                continue;
+            }
+            clients.push((task.client_pid, task.request_id));
+            if let Some(waiters) = self.waiters.remove(&task.request_id) {
+                clients.extend(&waiters);
+                handover.waiters.insert(task.request_id, waiters);
+            }
+            let credentials = task.client_uid.zip(task.client_gid);
+            handover.tasks.push((Arc::unwrap_or_clone(task), credentials));
+        }
+        for (client_pid, request_id) in clients {
+            if let Some(peer) = self.peers.get(&client_pid) {
+                handover.peers.insert(client_pid, peer.clone());
+            }
+            if let Some(seq) = self.next_seq.get(&(client_pid, request_id)) {
+                handover.next_seq.insert((client_pid, request_id), *seq);
+            }
+            let unacked = self.unacked.get(&client_pid).into_iter().flatten();
+            handover.unacked.extend(unacked
+                .filter(|((unacked_id, _), _)| *unacked_id == request_id)
+                .map(|((_, seq), unacked)| ((client_pid, request_id, *seq), unacked.bytes.clone())));
+        }
+
+        self.wind_down(config, config.shutdown_timeout).await;
+        handover
+    }
+
+    /// Give up on `handover`, as the server couldn't exec its executable, as `err` says: its
+    /// tasks are rejected, as on shutdown, and the client that asked for it told why.
+    pub fn abandon_handover(&mut self, handover: Handover, err: &io::Error) {
+        for (task, _) in handover.tasks {
+            self.reject_task(Arc::new(task), RequestFailure::ShuttingDown);
+        }
+        if let Some((client_pid, request_id)) = handover.requested_by {
+            let refused = MessageToClient::Refused(format!("the server could not be re-exec'd: {err}"));
+            if let Err(err) = self.send_msg_to_client(client_pid, request_id, &refused) {
+                log::warn!("could not tell client {client_pid} the server could not be re-exec'd: {:?}", err);
+            }
+        }
+    }
+
+    /// Take over from the server that exec'd this one, see [`ServerState::hand_over`]: its
+    /// pending tasks are queued again, in order, and their clients, and those waiting for
+    /// them, go on being notified where the previous server left off.
+    pub fn take_over(&mut self, handover: Handover) {
+        let Handover { tasks, waiters, peers, next_seq, unacked, requested_by } = handover;
+        self.peers.extend(peers);
+        self.next_seq.extend(next_seq);
+        let sent_at = Instant::now();
+        for ((client_pid, request_id, seq), bytes) in unacked {
+            let unacked = Unacked { bytes, sent_at, transmissions: 1 };
+            self.unacked.entry(client_pid).or_default().insert((request_id, seq), unacked);
+        }
+        // Told of tasks that may not be queued anymore, e.g. as the config changed.
+        self.waiters.extend(waiters);
+
+        let pending = tasks.len();
+        for (mut task, credentials) in tasks {
+            let client_pid = task.client_pid;
+            (task.client_uid, task.client_gid) = credentials.unzip();
+            // Inline tasks were spooled by the previous server, and streamed ones never handed over.
+            task.spooled = task.inline.is_some();
+            if let Err(err) = self.new_task(task) {
+                log::warn!("could not take over pending task by client {client_pid}: {:?}", err);
+            }
+        }
+        log::info!("took over {pending} pending task(s)");
+        if let Some((client_pid, request_id)) = requested_by {
+            let took_over = MessageToClient::TookOver { server_version: String::from(env!("CARGO_PKG_VERSION")), pending };
+            if let Err(err) = self.send_msg_to_client(client_pid, request_id, &took_over) {
+                log::warn!("could not tell client {client_pid} the server took over: {:?}", err);
+            }
+        }
+    }
+
+    /// Shut the server down gracefully:
+    ///
+    /// * the clients of pending tasks, which will never run, are told so;
+    /// * running tasks are given up to `timeout` to finish, after which they are killed;
+    /// * the results of every task that ran are relayed to their clients, who are
+    ///   given up to `timeout` more to receive the outputs of streamed tasks;
+    /// * and subscribers to task events are unsubscribed.
+    ///
+    /// Requests received meanwhile are rejected, but for health checks, which are told the
+    /// server isn't ready.
+    pub async fn shutdown(&mut self, config: &ServerConfig, timeout: Duration) {
+        self.shutting_down = true;
+        self.resume_suspended();
+        if let Some(PendingHandover { client_pid, request_id, .. }) = self.handover.take() {
+            let failed = MessageToClient::Failed(RequestFailure::ShuttingDown);
+            if let Err(err) = self.send_msg_to_client(client_pid, request_id, &failed) {
+                log::warn!("could not tell client {client_pid} the server shut down rather than hand over: {:?}", err);
+            }
+        }
+        let pending = self
+            .queues
+            .iter_mut()
+            .flat_map(|queue| std::iter::from_fn(|| queue.pop()))
+            .collect::<Vec<_>>();
+        for task in pending {
+            self.reject_task(task, RequestFailure::ShuttingDown);
+        }
+
+        let still_running = self.wait_for_monitors(config, timeout).await;
+        if still_running > 0 {
+            log::warn!("killing {still_running} task(s) still running after {:?}", timeout);
+            for monitor in self.running_tasks.values() {
+                if let Err(err) = monitor.kill() {
+                    log::error!("could not kill task #{}: {:?}", monitor.task_number, err);
+                }
+            }
+            let still_running = self.wait_for_monitors(config, timeout).await;
+            if still_running > 0 {
+                log::error!("{still_running} task(s) could not be stopped");
+            }
+        }
+
+        // Requests that came in as the last monitors finished.
+        self.serve_shutdown_for(config, Duration::ZERO).await;
+        self.wind_down(config, timeout).await;
+    }
+
+    /// Let go of what the server runs once no task runs anymore, as it shuts down, or hands
+    /// over: subscribers are unsubscribed, the outputs of streamed tasks given up to
+    /// `timeout` to be sent back, workers disconnected, and the pools of workers and
+    /// monitors stopped.
+    async fn wind_down(&mut self, config: &ServerConfig, timeout: Duration) {
+        // Subscribers would otherwise wait for events forever.
+        for client_pid in self.subscribers.keys().copied().collect::<Vec<_>>() {
+            if let Err(err) = self.unsubscribe(client_pid) {
+                log::warn!("could not unsubscribe client {client_pid} on shutdown: {:?}", err);
+            }
+        }
+
+        let deadline = Instant::now() + timeout;
+        while self.stream_senders.iter().any(|sender| !sender.is_finished()) && Instant::now() < deadline {
+            self.serve_shutdown_for(config, Duration::from_millis(50)).await;
+        }
+        if self.stream_senders.iter().any(|sender| !sender.is_finished()) {
+            log::warn!("giving up on sending outputs to streaming clients after {:?}", timeout);
+        }
+
+        // Workers register again once the server restarts.
+        for worker in self.workers.values() {
+            worker.link.close();
+        }
+
+        // Every monitor is done with the pool by now, so this kills its idle workers.
+        drop(self.pool.take());
+        // Which waits for monitors that could not be stopped, if any, which can't wait for
+        // room in the channel anymore.
+        self.receiver.close();
+        drop(self.monitors.take());
+    }
+
+    /// Drop `task`, which hasn't started, as its client couldn't be sent to, as `err` says, and
+    /// is taken to be gone: the task concludes as failed, for those waiting for it or
+    /// subscribed, and its stream, if any, is closed, so nothing of it is left behind.
+    fn orphan_task(&mut self, task: Arc<ClientTask>, err: &io::Error) {
+        log::warn!("client {} is gone, dropping its request {}: {:?}", task.client_pid, task.request_id, err);
+        let failure = RequestFailure::ClientGone;
+        let msg = MessageToClient::Failed(failure.clone());
+        self.finish(TaskEvent::Failed { task_number: None, task: Arc::clone(&task), failure }, msg);
+        if let Err(err) = self.finish_stream(&task, false) {
+            log::warn!("could not disconnect client {}: {:?}", task.client_pid, err);
+        }
+    }
+
+    /// Reject `task`, received as a request of its own, though it claims to be streamed: only
+    /// tasks received over the stream socket are, and have their files relocated to the
+    /// server's copies, see [`streaming`], so that the client's paths are never trusted as
+    /// the server's own.
+    pub fn refuse_streamed(&mut self, task: ClientTask) {
+        log::warn!("refused task by client {} claiming to be streamed", task.client_pid);
+        let failure = RequestFailure::MalformedRequest(String::from("streamed tasks are only taken over the stream socket"));
+        self.reject_task(Arc::new(task), failure);
+    }
+
+    /// Tell the client of a task that will never run that it could not be started, as `failure`.
+    fn reject_task(&mut self, task: Arc<ClientTask>, failure: RequestFailure) {
+        log::info!("rejecting task by client {}: {failure}", task.client_pid);
+        let msg = MessageToClient::Failed(failure.clone());
+        if let Err(err) = self.send_msg_to_client(task.client_pid, task.request_id, &msg) {
+            log::warn!("could not inform client {} its task was rejected: {:?}", task.client_pid, err);
+        }
+        self.finish(TaskEvent::Failed { task_number: None, task: Arc::clone(&task), failure }, msg);
+        if let Err(err) = self.finish_stream(&task, false) {
+            log::warn!("could not disconnect client {}: {:?}", task.client_pid, err);
+        }
+    }
+
+    /// The server's state, see [`ServerStatus`], including
     /// * currently running client requests
+    /// * pending client requests, in the order they'd be popped from each queue
     /// * the server's currently running tranformations, and their limits specified
-    ///   in the its configuration
-    /// and send it to the requester.
-    pub fn fmt_client_status(&self, config: &ServerConfig, client_pid: u32) -> Result<(), ServerError> {
-        let mut status_msg = String::new();
-        let mut sorted_mons = self
+    ///   in the its configuration, both server-wide and for each non-default queue
+    pub fn status(&self, config: &ServerConfig) -> ServerStatus {
+        let mut running = self
             .running_tasks
             .values()
+            .map(|monitor| RunningTask { task_number: monitor.task_number, task: Arc::clone(&monitor.task) })
             .collect::<Vec<_>>();
-        sorted_mons
-            .sort_by(|mon1, mon2| { mon1.task_number.cmp(&mon2.task_number) });
+        running.sort_by_key(|running| running.task_number);
 
-        for monitor in sorted_mons {
-            fmt_running_task(monitor, &mut status_msg)?;
+        let queued = self
+            .queues
+            .iter()
+            .flat_map(|queue| queue.pending().into_iter().enumerate())
+            .map(|(position, task)| QueuedTask { position, task: Arc::clone(task) })
+            .collect();
+
+        // The default queue is only bound by the server-wide limits.
+        let queues = self
+            .queues
+            .iter()
+            .skip(1)
+            .map(|queue| QueueStatus {
+                name: queue.name().to_string(),
+                weight: queue.config.weight,
+                filters: status::filter_usage(&queue.filters_count, &queue.config.filters_config),
+            })
+            .collect();
+
+        ServerStatus {
+            running,
+            queued,
+            filters: status::filter_usage(&self.filters_count, &config.filters_config),
+            queues,
+            uptime: self.started_at.elapsed(),
+            stats: self.stats.clone(),
         }
-        fmt_filters(&self.filters_count, &config.filters_config, &mut status_msg)?;
+    }
+
+    /// Tell the client with `client_pid` that the server is alive, in reply to its request
+    /// `request_id`, see [`ClientRequest::Ping`].
+    pub fn send_pong(&mut self, client_pid: u32, request_id: Uuid) -> Result<(), ServerError> {
+        let pong = MessageToClient::Pong {
+            server_version: env!("CARGO_PKG_VERSION").to_string(),
+            uptime: self.started_at.elapsed(),
+        };
+        self.send_msg_to_client(client_pid, request_id, &pong)
+    }
+
+    /// Whether the server is ready to take requests, see [`Health`]: whether it still reads
+    /// from its transport, and its thread accepting streamed tasks is running, it isn't
+    /// shutting down, the filters it may run have executables, and its queues aren't full.
+    pub fn health(&self, config: &ServerConfig) -> Health {
+        let running = |thread: &Option<JoinHandle<()>>| thread.as_ref().is_some_and(|thread| !thread.is_finished());
+        let missing_executables = config
+            .missing_executables()
+            .into_iter()
+            .map(|(filter, path)| (filter.to_string(), path.display().to_string()))
+            .collect();
+        Health {
+            server_version: env!("CARGO_PKG_VERSION").to_string(),
+            uptime: self.started_at.elapsed(),
+            listeners: self.incoming.is_some() && running(&self.stream_listener),
+            accepting: !self.shutting_down,
+            missing_executables,
+            pending: self.queues.iter().map(|queue| queue.pending().len()).sum(),
+            capacity: self.queue_capacity,
+        }
+    }
+
+    /// Tell the client with `client_pid` whether the server is ready to take requests, in
+    /// reply to its request `request_id`, see [`ClientRequest::Health`].
+    pub fn send_health(&mut self, config: &ServerConfig, client_pid: u32, request_id: Uuid) -> Result<(), ServerError> {
+        let health = MessageToClient::Health(self.health(config));
+        self.send_msg_to_client(client_pid, request_id, &health)
+    }
+
+    /// Tell the client with `client_pid` what the server runs with, in reply to its request
+    /// `request_id`, see [`ClientRequest::ServerInfo`].
+    pub fn send_server_info(&mut self, config: &ServerConfig, client_pid: u32, request_id: Uuid) -> Result<(), ServerError> {
+        self.send_msg_to_client(client_pid, request_id, &MessageToClient::ServerInfo(config.info()))
+    }
+
+    /// Send the totals of the tasks that concluded in the last `window`, or of every task the
+    /// server accounts for, to the client with `client_pid`, in reply to its request
+    /// `request_id`, see [`ClientRequest::Report`].
+    pub fn send_report(&mut self, client_pid: u32, request_id: Uuid, window: Option<Duration>) -> Result<(), ServerError> {
+        let report = MessageToClient::Report(self.accounting.report(window));
+        self.send_msg_to_client(client_pid, request_id, &report)
+    }
 
-        self.send_msg_to_client(client_pid, &status_msg)
+    /// Send the tasks that most recently finished or failed to the client with `client_pid`,
+    /// in reply to its request `request_id`, see [`ClientRequest::History`].
+    pub fn send_history(&mut self, client_pid: u32, request_id: Uuid) -> Result<(), ServerError> {
+        let history = MessageToClient::History(self.history.iter().map(|(event, _)| event.clone()).collect());
+        self.send_msg_to_client(client_pid, request_id, &history)
+    }
+
+    /// Where the request `queried` is: pending, running, or recently finished, if the server
+    /// knows of it at all.
+    pub fn request_state(&self, queried: Uuid) -> Option<RequestState> {
+        let pending = self.queues.iter().find_map(|queue| {
+            let pending = queue.pending();
+            let position = pending.iter().position(|task| task.request_id == queried)?;
+            Some(QueuedTask { position, task: Arc::clone(pending[position]) })
+        });
+        let running = || self.running_tasks.values().find(|monitor| monitor.task.request_id == queried).map(running_state);
+        let done = || self
+            .history
+            .iter()
+            .rev()
+            .find(|(event, _)| event.task().request_id == queried)
+            .map(|(event, outcome)| RequestState::Done(event.clone(), Box::new(outcome.clone())));
+
+        pending.map(RequestState::Pending).or_else(running).or_else(done)
+    }
+
+    /// Where the task that started running as task #`task_number` is: running, or recently
+    /// finished, if the server knows of it at all. Pending tasks have no number yet.
+    pub fn task_state(&self, task_number: usize) -> Option<RequestState> {
+        let done = || self
+            .history
+            .iter()
+            .rev()
+            .find(|(event, _)| event.task_number() == Some(task_number))
+            .map(|(event, outcome)| RequestState::Done(event.clone(), Box::new(outcome.clone())));
+
+        self.running_tasks.get(&task_number).map(running_state).or_else(done)
+    }
+
+    /// Send where the task `queried` is to the client with `client_pid`, in reply to its
+    /// request `request_id`, see [`ClientRequest::Query`].
+    pub fn send_request_state(&mut self, client_pid: u32, request_id: Uuid, queried: TaskId) -> Result<(), ServerError> {
+        let state = match queried {
+            TaskId::Request(queried) => self.request_state(queried).ok_or(RequestFailure::UnknownRequest(queried)),
+            TaskId::Number(queried) => self.task_state(queried).ok_or(RequestFailure::UnknownTask(queried)),
+        };
+        let msg = state.map_or_else(MessageToClient::Failed, MessageToClient::State);
+        self.send_msg_to_client(client_pid, request_id, &msg)
+    }
+
+    /// Send what the server did with the request `logged` to the client with `client_pid`,
+    /// in reply to its request `request_id`, see [`ClientRequest::Logs`].
+    pub fn send_task_log(&mut self, client_pid: u32, request_id: Uuid, logged: Uuid) -> Result<(), ServerError> {
+        let msg = match self.task_logs.get(logged) {
+            Some(log) => MessageToClient::Log(log.clone()),
+            None => MessageToClient::Failed(RequestFailure::UnknownRequest(logged)),
+        };
+        self.send_msg_to_client(client_pid, request_id, &msg)
+    }
+
+    /// Send the client with `client_pid` the last message about the request `awaited`, in
+    /// reply to its request `request_id`, see [`ClientRequest::Wait`]: once it concludes, if
+    /// it is pending or running, and at once if it finished recently.
+    ///
+    /// A request suspended by the server shutting down is waited on no longer, its waiters
+    /// being sent [`MessageToClient::Suspended`], as its client is.
+    pub fn wait_for(&mut self, client_pid: u32, request_id: Uuid, awaited: Uuid) -> Result<(), ServerError> {
+        match self.request_state(awaited) {
+            Some(RequestState::Done(_, outcome)) => self.send_msg_to_client(client_pid, request_id, &outcome),
+            Some(RequestState::Pending(_) | RequestState::Running { .. }) => {
+                self.waiters.entry(awaited).or_default().push((client_pid, request_id));
+                Ok(())
+            },
+            None => {
+                let failure = RequestFailure::UnknownRequest(awaited);
+                self.send_msg_to_client(client_pid, request_id, &MessageToClient::Failed(failure))
+            },
+        }
+    }
+
+    /// Have `task` follow the request submitted with the same idempotency key, by the same
+    /// user, within the [`ServerConfig::idempotency_window`], rather than run twice, if the
+    /// server still knows of that request, and it is pending, running or succeeded: a retry
+    /// of a request that failed runs, rather than be told the same failure.
+    ///
+    /// Its client is sent the state of the earlier request, see [`MessageToClient::Duplicate`],
+    /// and its outcome once it concludes, as if it [waited](ServerState::wait_for) for it, or
+    /// at once if it concluded already. Returns whether `task` was a duplicate. Streamed, and
+    /// inline, tasks and dry runs never are.
+    pub fn deduplicate(&mut self, task: &ClientTask) -> Result<bool, ServerError> {
+        let window = self.idempotency_window;
+        self.idempotency_keys.retain(|_, (_, submitted_at)| submitted_at.elapsed() < window);
+
+        let Some(key) = task.idempotency_key.clone().filter(|_| !task.is_spooled() && !task.dry_run) else {
+            return Ok(false)
+        };
+        let Some(&(original, _)) = self.idempotency_keys.get(&(task.client_uid, key)) else {
+            return Ok(false)
+        };
+        let Some(state) = self.request_state(original) else {
+            return Ok(false)
+        };
+
+        if matches!(&state, RequestState::Done(_, outcome) if matches!(**outcome, MessageToClient::Failed(_))) {
+            return Ok(false)
+        }
+
+        log::info!("request {} by client {} duplicates request {original}", task.request_id, task.client_pid);
+        match state {
+            RequestState::Done(_, outcome) => self.send_msg_to_client(task.client_pid, task.request_id, &outcome)?,
+            state => {
+                let duplicate = MessageToClient::Duplicate { original, state };
+                self.send_msg_to_client(task.client_pid, task.request_id, &duplicate)?;
+                self.waiters.entry(original).or_default().push((task.client_pid, task.request_id));
+            },
+        }
+        Ok(true)
+    }
+
+    /// Send the server's status to the client with `client_pid`, in reply to its request
+    /// `request_id`, see [`ServerState::status`].
+    pub fn send_status(&mut self, config: &ServerConfig, client_pid: u32, request_id: Uuid) -> Result<(), ServerError> {
+        let status = self.status(config);
+        self.send_msg_to_client(client_pid, request_id, &MessageToClient::Status(status))
+    }
+}
+
+/// The state of the task `monitor` runs, as told to clients that query it.
+fn running_state(monitor: &Monitor) -> RequestState {
+    RequestState::Running {
+        running: RunningTask { task_number: monitor.task_number, task: Arc::clone(&monitor.task) },
+        elapsed: monitor.started_at.elapsed(),
+        bytes_out: monitor.bytes_out,
     }
 }
 
 /// Convert the result of a pipeline sent by its responsible monitor to a message
 /// to be sent to the requester client.
-fn mon_res_to_cl_msg(result: Result<MonitorSuccess, MonitorError>) -> MessageToClient {
+fn mon_res_to_cl_msg(result: Result<TaskSummary, MonitorError>) -> MessageToClient {
     match result {
-        Ok(bytes_in_out) => MessageToClient::Concluded(bytes_in_out),
-        Err(err) => match err {
-            MonitorError::NoTransformationsGiven |
-            MonitorError::InputFileError(_) |
-            MonitorError::OutputFileError(_) => {
-                MessageToClient::RequestInitError
-            },
-            MonitorError::PipelineFailure(_) | MonitorError::PipelineExitStatusError(_) |
-            MonitorError::InputFileMetadataError(_) | MonitorError::OutputFileMetadataError(_) |
-            MonitorError::MpscSenderError => {
-                MessageToClient::RequestError
-            } 
-        }
+        Ok(TaskSummary::File(summary)) => MessageToClient::Concluded(summary),
+        Ok(TaskSummary::Batch(summary)) => MessageToClient::BatchConcluded(summary),
+        Err(err) => mon_err_to_cl_msg(err),
     }
 }
 
-/// Format a single task into the status message that'll be sent to the client.
-///
-/// The end result will be:
-///
-/// `task #<num>: proc-file <priority> <input-file> <output-file> <filter_1> <filter_2> ... <filter_n>`
-fn fmt_running_task(
-    monitor: &Monitor,
-    output: &mut String
-) -> Result<(), std::fmt::Error> {
-    write!(
-        output,
-        "task #{}: proc-file {} {} {}",
-        monitor.task_number,
-        monitor.task.priority,
-        monitor.task.input_filepath().display(),
-        monitor.task.output_filepath().display(),
-    )?;
-
-    for transformation in &monitor.task.transformations {
-        write!(output, " {}", transformation)?;
-    }
-
-    write!(output, "\n")
+/// Message informing a client of why its task, or a file of its batch task, failed.
+fn mon_err_to_cl_msg(err: MonitorError) -> MessageToClient {
+    MessageToClient::Failed(RequestFailure::from(err))
 }
 
-/// Format filters into the string that will be shown to the client upon
-/// their request of the server's status.
-///
-/// It'll show currently running filters vs. the server's limits specified in the
-/// config parsed from CLI on start-up.
-fn fmt_filters(
-    running: &RunningFilters,
-    config: &FiltersConfig,
-    output: &mut String
-) -> Result<(), std::fmt::Error> {
-    writeln!(output, "transformation nop: {}/{} (running/max)", running.nop, config.nop)?;
-    writeln!(output, "transformation bcompress: {}/{} (running/max)", running.bcompress, config.bcompress)?;
-    writeln!(output, "transformation bdecompress: {}/{} (running/max)", running.bdecompress, config.bdecompress)?;
-    writeln!(output, "transformation gcompress: {}/{} (running/max)", running.gcompress, config.gcompress)?;
-    writeln!(output, "transformation gdecompress: {}/{} (running/max)", running.gdecompress, config.gdecompress)?;
-    writeln!(output, "transformation encrypt: {}/{} (running/max)", running.encrypt, config.encrypt)?;
-    writeln!(output, "transformation decrypt: {}/{} (running/max)", running.decrypt, config.decrypt)
-}
\ No newline at end of file
+/// Log what became of the partial output of a failed pipeline of task #`task_number`.
+fn log_partial_output(partial_output: Option<PartialOutput>, task_number: usize) {
+    match partial_output {
+        None => {},
+        Some(PartialOutput::Removed(path)) =>
+            log::info!("removed partial output {:?} of failed task #{}", path, task_number),
+        Some(PartialOutput::Left(path, err)) =>
+            log::warn!(
+                "could not remove partial output {:?} of failed task #{}: {:?}",
+                path, task_number, err
+            ),
+        Some(PartialOutput::Checkpointed(path)) =>
+            log::info!("kept partial output {:?} of interrupted task #{}, to resume it", path, task_number),
+    }
+}
+
+/// Remove a checkpoint that won't be resumed, and its task's partial output.
+fn remove_stale(tmp_output: &Path, checkpoint_path: &Path) {
+    if let Err(err) = fs::remove_file(tmp_output) {
+        if err.kind() != io::ErrorKind::NotFound {
+            log::warn!("could not remove partial output {:?}: {:?}", tmp_output, err);
+        }
+    }
+    checkpoint::remove(checkpoint_path);
+}
@@ -0,0 +1,79 @@
+//! The server's content-addressed store of outputs, which tasks submitted with `--store`
+//! are written to instead of a path of their own, see [`OutputStore`].
+
+use std::{
+    fs, io,
+    os::unix::fs::PermissionsExt,
+    path::{self, Path, PathBuf},
+};
+
+use uuid::Uuid;
+
+/// Prefix of the files tasks write their outputs to, before they are added to the store.
+const STAGING_PREFIX: &str = "tmp.";
+
+/// Directory holding a file per output written to it, named by the hex-encoded SHA-256
+/// hash of its contents, so that identical outputs are stored once, whoever asked for them,
+/// and can be verified by hashing them again.
+///
+/// Outputs are read-only once stored, as other tasks may have been given the same path.
+pub struct OutputStore {
+    dir: PathBuf,
+}
+
+impl OutputStore {
+    /// Open the store's directory, creating it if it doesn't exist.
+    ///
+    /// Its path is made absolute, as clients are told of outputs by their path.
+    pub fn open(dir: PathBuf) -> io::Result<Self> {
+        fs::create_dir_all(&dir)?;
+        Ok(OutputStore { dir: path::absolute(dir)? })
+    }
+
+    /// Where the task submitted in request `request_id` writes its output, before it is
+    /// added to the store, see [`OutputStore::add`].
+    pub fn staging_path(&self, request_id: Uuid) -> PathBuf {
+        self.dir.join(format!("{STAGING_PREFIX}{request_id}"))
+    }
+
+    /// Add the output at `staged`, whose contents have the hex-encoded SHA-256 hash
+    /// `sha256`, to the store, returning the path it is stored at.
+    ///
+    /// If the store already holds the same contents, they are kept. Either way, `staged` is
+    /// removed, as is a failed task's partial output.
+    pub fn add(&self, staged: &Path, sha256: &str) -> io::Result<PathBuf> {
+        let path = self.dir.join(sha256);
+        // Hard linked rather than renamed, so that an entry, which may be being read, is never
+        // replaced.
+        let added = fs::set_permissions(staged, fs::Permissions::from_mode(0o444))
+            .and_then(|_| match fs::hard_link(staged, &path) {
+                Err(err) if err.kind() == io::ErrorKind::AlreadyExists => Ok(()),
+                linked => linked,
+            });
+        fs::remove_file(staged)?;
+        added.map(|_| path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::core::testing::TempDir;
+
+    use super::*;
+
+    #[test]
+    fn outputs_are_stored_once() {
+        let dir = TempDir::new("store");
+        let store = OutputStore::open(dir.to_path_buf()).unwrap();
+
+        let (first, second) = (store.staging_path(Uuid::new_v4()), store.staging_path(Uuid::new_v4()));
+        fs::write(&first, "stored").unwrap();
+        fs::write(&second, "stored").unwrap();
+        let path = store.add(&first, "abc").unwrap();
+        assert_eq!(store.add(&second, "abc").unwrap(), path);
+
+        assert_eq!((path.clone(), fs::read_to_string(&path).unwrap()), (dir.join("abc"), String::from("stored")));
+        assert!(!first.exists() && !second.exists());
+        assert!(fs::metadata(&path).unwrap().permissions().readonly());
+    }
+}
@@ -0,0 +1,145 @@
+use std::{
+    fs, io, os::unix::net::{UnixListener, UnixStream}, path::{Path, PathBuf}, thread, time::Duration,
+};
+
+use tokio::sync::mpsc::Sender;
+
+use crate::core::{
+    client_task::ClientTask, framing,
+    messaging::{ClientRequest, Codec, CodecError, MessageToServer, WireFormat},
+};
+
+/// Name of the stream socket, next to the server's datagram socket, over which clients
+/// submit streamed tasks, see [`ClientTask::stream`].
+pub const STREAM_SOCKET: &str = "sdstored_stream.sock";
+
+/// How long the thread accepting streamed tasks waits after failing to accept one.
+const ACCEPT_RETRY_DELAY: Duration = Duration::from_millis(100);
+
+/// Errors that may happen while receiving a streamed task.
+#[derive(Debug)]
+pub enum StreamError {
+    /// Reading the task, or its input, from the socket, or spooling the input, failed.
+    Io(io::Error),
+    /// The task's request could not be deserialized.
+    RequestDeserializeError(CodecError),
+    /// The request was not a `proc-file` with `--stream`.
+    NotStreamed,
+    /// The server's main thread no longer receives messages.
+    ServerGone,
+}
+
+impl From<io::Error> for StreamError {
+    fn from(err: io::Error) -> Self {
+        Self::Io(err)
+    }
+}
+
+impl From<CodecError> for StreamError {
+    fn from(err: CodecError) -> Self {
+        Self::RequestDeserializeError(err)
+    }
+}
+
+/// Where the server keeps its copies of the input and output of the streamed task sent
+/// by the client with `client_pid`.
+pub fn spool_paths(spool_dir: &Path, client_pid: u32) -> (PathBuf, PathBuf) {
+    (
+        spool_dir.join(format!("sdstored_spool_{client_pid}.in")),
+        spool_dir.join(format!("sdstored_spool_{client_pid}.out")),
+    )
+}
+
+/// Accept streamed tasks on `listener`, receiving each in a thread of its own, so that a
+/// slow client does not hold up the others, see [`receive_task`]. Their requests are
+/// decoded with `codec`.
+pub fn stream_listen(
+    listener: UnixListener,
+    spool_dir: PathBuf,
+    sender: Sender<MessageToServer>,
+    codec: WireFormat
+) {
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Err(err) => {
+                log::warn!("could not accept streamed task: {:?}", err);
+                // As when out of file descriptors, which accepting right away again won't help.
+                thread::sleep(ACCEPT_RETRY_DELAY);
+                continue;
+            },
+            Ok(stream) => stream,
+        };
+
+        let (spool_dir, sender) = (spool_dir.clone(), sender.clone());
+        let receiver = thread::Builder::new()
+            .name(String::from("sdstored_stream_receiver"))
+            .spawn(move || {
+                if let Err(err) = receive_task(stream, &spool_dir, &sender, codec) {
+                    log::warn!("failed to receive streamed task: {:?}", err);
+                }
+            });
+        if let Err(err) = receiver {
+            log::warn!("could not spawn thread to receive streamed task: {:?}", err);
+        }
+    }
+}
+
+/// Receive a streamed task from `stream`: a frame holding its [`ClientRequest`], followed
+/// by its input, see [`framing::send_file`].
+///
+/// The input is spooled to the file given by [`spool_paths`], and the task made to read
+/// it, and to write its output next to it, before being handed to the server's main thread
+/// alongside `stream`, over which its output is to be sent back.
+///
+/// Should the server be busy, with its channel full, the task waits for room in it.
+fn receive_task(
+    mut stream: UnixStream,
+    spool_dir: &Path,
+    sender: &Sender<MessageToServer>,
+    codec: WireFormat
+) -> Result<(), StreamError> {
+    let mut task = match codec.decode(&framing::read_frame(&mut stream)?)? {
+        ClientRequest::ProcFile(task) if task.stream => *task,
+        _ => return Err(StreamError::NotStreamed),
+    };
+
+    let (input, output) = spool_paths(spool_dir, task.client_pid);
+    let received = fs::File::create(&input)
+        .and_then(|file| framing::receive_file(&mut stream, io::BufWriter::new(file)));
+    if let Err(err) = received {
+        remove_spooled(&input);
+        return Err(err.into())
+    }
+    task.relocate(input, output);
+    task.spooled = true;
+
+    sender
+        .blocking_send(MessageToServer::Streamed(task, stream))
+        .map_err(|_| StreamError::ServerGone)
+}
+
+/// Send the output of a finished streamed task back to its client over `stream`, if it
+/// `succeeded`, then remove the server's copies of the task's files.
+///
+/// If the task failed, the client is only disconnected.
+pub fn send_output(mut stream: UnixStream, task: &ClientTask, succeeded: bool) -> io::Result<()> {
+    let sent = match succeeded {
+        true => fs::File::open(task.output_filepath())
+            .and_then(|output| framing::send_file(output, &mut stream))
+            .map(|_| ()),
+        false => Ok(()),
+    };
+
+    remove_spooled(task.input_filepath());
+    remove_spooled(task.output_filepath());
+    sent
+}
+
+/// Remove the spooled file at `path`, if it is still there, only warning if it can't be.
+pub fn remove_spooled(path: &Path) {
+    match fs::remove_file(path) {
+        Err(err) if err.kind() != io::ErrorKind::NotFound =>
+            log::warn!("could not remove spooled file {:?}: {:?}", path, err),
+        _ => {},
+    }
+}
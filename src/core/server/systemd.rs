@@ -0,0 +1,160 @@
+//! Telling systemd how the server is doing, as a `Type=notify` service with a `WatchdogSec=`,
+//! see [`Notifier`].
+
+use std::{
+    env, ffi::{OsStr, OsString}, io,
+    os::{linux::net::SocketAddrExt, unix::{ffi::OsStrExt, net::{SocketAddr, UnixDatagram}}},
+    process,
+    time::{Duration, Instant},
+};
+
+/// Environment variable giving the socket systemd listens for notifications on: a path, or a
+/// name in the abstract namespace, if it begins with `@`.
+pub const NOTIFY_SOCKET_VAR: &str = "NOTIFY_SOCKET";
+
+/// Environment variable giving the microseconds systemd waits for a watchdog ping, before it
+/// takes the service to be wedged, and restarts it.
+pub const WATCHDOG_USEC_VAR: &str = "WATCHDOG_USEC";
+
+/// Environment variable giving the PID of the process the watchdog is for, if not any.
+pub const WATCHDOG_PID_VAR: &str = "WATCHDOG_PID";
+
+/// Sends systemd notifications about the server: that it is ready, see [`Notifier::ready`],
+/// stopping, see [`Notifier::stopping`], and still alive, see [`Notifier::keep_alive`].
+///
+/// The server must run in the foreground, see
+/// [`ServerCli::foreground`](super::cli::ServerCli::foreground), as systemd only takes
+/// notifications from the process it started.
+pub struct Notifier {
+    socket: UnixDatagram,
+    address: SocketAddr,
+    /// The socket, as [`NOTIFY_SOCKET_VAR`] gave it.
+    socket_var: OsString,
+    /// How long systemd waits for a ping, if it watches the server.
+    watchdog: Option<Duration>,
+    /// When the last ping was sent.
+    pinged_at: Instant,
+}
+
+impl Notifier {
+    /// A notifier for the socket systemd gave the server in its environment, if it did, with
+    /// the watchdog it set up, if any was for this process.
+    ///
+    /// The variables are removed from the environment, so that the filters the server runs
+    /// don't notify systemd in its place. Must be called before any thread is spawned, which
+    /// could read the environment meanwhile.
+    pub fn from_env() -> io::Result<Option<Self>> {
+        let socket = env::var_os(NOTIFY_SOCKET_VAR).filter(|socket| !socket.is_empty());
+        let watchdog = env::var(WATCHDOG_USEC_VAR).ok().and_then(|usec| usec.parse().ok());
+        let watchdog_pid = env::var(WATCHDOG_PID_VAR).ok().and_then(|pid| pid.parse::<u32>().ok());
+        for var in [NOTIFY_SOCKET_VAR, WATCHDOG_USEC_VAR, WATCHDOG_PID_VAR] {
+            env::remove_var(var);
+        }
+
+        let Some(socket_var) = socket else {
+            return Ok(None)
+        };
+        let address = match socket_var.as_bytes() {
+            [b'@', name @ ..] => SocketAddr::from_abstract_name(name)?,
+            path => SocketAddr::from_pathname(OsStr::from_bytes(path))?,
+        };
+        let watchdog = watchdog
+            .filter(|usec| *usec > 0 && watchdog_pid.is_none_or(|pid| pid == process::id()))
+            .map(Duration::from_micros);
+        Ok(Some(Notifier { socket: UnixDatagram::unbound()?, address, socket_var, watchdog, pinged_at: Instant::now() }))
+    }
+
+    /// Tell systemd the server is ready to take requests, its sockets listened on.
+    pub fn ready(&self) -> io::Result<()> {
+        self.notify("READY=1")
+    }
+
+    /// Tell systemd the server is handing over to a freshly exec'd one, which tells it once
+    /// it is ready in turn, see [`handover`](super::handover).
+    pub fn reloading(&self) -> io::Result<()> {
+        self.notify("RELOADING=1")
+    }
+
+    /// The environment systemd gave the server, for a server exec'd in its place to notify
+    /// systemd in turn, as the same process.
+    pub fn env(&self) -> Vec<(&'static str, OsString)> {
+        let mut env = vec![(NOTIFY_SOCKET_VAR, self.socket_var.clone())];
+        if let Some(watchdog) = self.watchdog {
+            env.push((WATCHDOG_USEC_VAR, watchdog.as_micros().to_string().into()));
+            env.push((WATCHDOG_PID_VAR, process::id().to_string().into()));
+        }
+        env
+    }
+
+    /// Tell systemd the server is shutting down, and draining its running tasks.
+    pub fn stopping(&self) -> io::Result<()> {
+        self.notify("STOPPING=1")
+    }
+
+    /// Ping systemd's watchdog, if it watches the server, and half of its timeout went by
+    /// since the last ping, so that calling this every time the server's main loop comes
+    /// around has systemd restart the server once the loop is wedged.
+    pub fn keep_alive(&mut self) -> io::Result<()> {
+        match self.watchdog {
+            Some(timeout) if self.pinged_at.elapsed() >= timeout / 2 => {
+                self.pinged_at = Instant::now();
+                self.notify("WATCHDOG=1")
+            },
+            _ => Ok(()),
+        }
+    }
+
+    /// How long systemd waits for a ping, if it watches the server.
+    pub fn watchdog(&self) -> Option<Duration> {
+        self.watchdog
+    }
+
+    /// Send the notification `state`, a line of `KEY=VALUE` as in `sd_notify(3)`.
+    fn notify(&self, state: &str) -> io::Result<()> {
+        self.socket.send_to_addr(state.as_bytes(), &self.address).map(|_| ())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn notifications_reach_systemd() {
+        let path = env::temp_dir().join(format!("sdstore_notify_test_{}.sock", process::id()));
+        let systemd = UnixDatagram::bind(&path).unwrap();
+        systemd.set_read_timeout(Some(Duration::from_secs(1))).unwrap();
+        let recv = || {
+            let mut buf = [0; 64];
+            let len = systemd.recv(&mut buf).unwrap();
+            String::from_utf8_lossy(&buf[..len]).into_owned()
+        };
+
+        let mut notifier = Notifier {
+            socket: UnixDatagram::unbound().unwrap(),
+            address: SocketAddr::from_pathname(&path).unwrap(),
+            socket_var: path.clone().into_os_string(),
+            watchdog: Some(Duration::from_millis(100)),
+            pinged_at: Instant::now(),
+        };
+        notifier.ready().unwrap();
+        assert_eq!(recv(), "READY=1");
+
+        // Pings are only sent once half the watchdog's timeout went by.
+        notifier.keep_alive().unwrap();
+        std::thread::sleep(Duration::from_millis(60));
+        notifier.keep_alive().unwrap();
+        notifier.keep_alive().unwrap();
+        notifier.stopping().unwrap();
+        assert_eq!((recv(), recv()), (String::from("WATCHDOG=1"), String::from("STOPPING=1")));
+
+        // A server exec'd in its place notifies the same socket, with the same watchdog.
+        assert_eq!(notifier.env(), vec![
+            (NOTIFY_SOCKET_VAR, path.clone().into_os_string()),
+            (WATCHDOG_USEC_VAR, OsString::from("100000")),
+            (WATCHDOG_PID_VAR, OsString::from(process::id().to_string())),
+        ]);
+
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,519 @@
+//! Harness running the server's state in-process, over no socket, and with no pipeline
+//! ever run, so that its scheduling, limits and messaging can be tested deterministically,
+//! see [`TestServer`].
+//!
+//! Clients' requests are handed to the server as its event loop would have read them, see
+//! [`embed::handle_message`], and what it sends back is kept by a [`MemoryTransport`], for
+//! tests to read. Tasks are started by monitors that never run them, see
+//! [`MonitorPool::held`](super::monitor_pool::MonitorPool::held), and conclude with whatever
+//! result the test hands the server.
+
+use std::{
+    collections::VecDeque, fs, io,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use uuid::Uuid;
+
+use crate::core::{
+    client_task::ClientTask,
+    filter::Filter,
+    messaging::{ClientRequest, Codec, MessageReceiver, MessageToClient, MessageToServer, Sequenced},
+    monitor::{MonitorError, MonitorResult, MonitorSuccess, TaskSummary},
+    status::{self, ServerStatus},
+    testing::TempDir,
+    transport::{Credentials, Peer, Transport},
+};
+
+use super::{
+    cli::{ServerCli, ServerEnv},
+    config::ServerConfig,
+    embed,
+    state::ServerState,
+};
+
+/// Transport that keeps every datagram sent over it, by who it was sent to, rather than
+/// send it anywhere, unless they are gone. Nothing is ever received over it.
+#[derive(Debug, Default)]
+pub struct MemoryTransport {
+    sent: Mutex<Vec<(Peer, Vec<u8>)>>,
+    gone: Mutex<Vec<Peer>>,
+}
+
+impl MemoryTransport {
+    /// Fail to send anything to `peer` from now on, as if its socket was closed.
+    pub fn disconnect(&self, peer: Peer) {
+        self.gone.lock().unwrap().push(peer);
+    }
+
+    /// Take the datagrams sent to `peer` since last taken, in the order they were sent.
+    pub fn take(&self, peer: &Peer) -> VecDeque<Vec<u8>> {
+        let mut sent = self.sent.lock().unwrap();
+        let (taken, kept) = sent.drain(..).partition(|(to, _)| to == peer);
+        *sent = kept;
+        taken.into_iter().map(|(_, datagram)| datagram).collect::<Vec<_>>().into()
+    }
+}
+
+impl Transport for MemoryTransport {
+    fn send_to(&self, datagram: &[u8], peer: &Peer) -> io::Result<()> {
+        if self.gone.lock().unwrap().contains(peer) {
+            return Err(io::Error::from(io::ErrorKind::ConnectionRefused))
+        }
+        self.sent.lock().unwrap().push((peer.clone(), datagram.to_vec()));
+        Ok(())
+    }
+
+    fn recv_from(&self, _: &mut [u8]) -> io::Result<(usize, Peer, Option<Credentials>)> {
+        Err(io::Error::new(io::ErrorKind::Unsupported, "requests are handed to the server"))
+    }
+}
+
+/// Datagrams taken from a [`MemoryTransport`], read back as a client would from its socket.
+struct Inbox(Mutex<VecDeque<Vec<u8>>>);
+
+impl Transport for Inbox {
+    fn send_to(&self, _: &[u8], _: &Peer) -> io::Result<()> {
+        Err(io::Error::new(io::ErrorKind::Unsupported, "clients' replies are handed to the server"))
+    }
+
+    /// Fails with [`io::ErrorKind::WouldBlock`] once every datagram was read.
+    fn recv_from(&self, buf: &mut [u8]) -> io::Result<(usize, Peer, Option<Credentials>)> {
+        let datagram = self.0.lock().unwrap().pop_front().ok_or(io::ErrorKind::WouldBlock)?;
+        let n = datagram.len().min(buf.len());
+        buf[..n].copy_from_slice(&datagram[..n]);
+        Ok((datagram.len(), Peer::Unnamed, None))
+    }
+}
+
+/// A server, configured by a limits file, whose requests are handed to it by the test, and
+/// whose tasks only conclude as the test says. Its tasks' files are in a directory of its
+/// own, removed once it's dropped.
+pub struct TestServer {
+    pub state: ServerState,
+    pub config: ServerConfig,
+    transport: Arc<MemoryTransport>,
+    dir: TempDir,
+}
+
+impl TestServer {
+    /// A server with the limits file `limits`, running at most `monitors` tasks at once.
+    pub fn new(limits: &str, monitors: usize) -> Self {
+        let dir = TempDir::new("server");
+        fs::write(dir.join("limits.txt"), limits).unwrap();
+        let env = ServerEnv {
+            limits_file: Some(dir.join("limits.txt")),
+            transformations_dir: Some(dir.to_path_buf()),
+            socket_dir: Some(dir.to_path_buf()),
+            ..Default::default()
+        };
+        let config = ServerConfig::build(&ServerCli::default(), &env).unwrap();
+
+        let transport = Arc::new(MemoryTransport::default());
+        let mut state = ServerState::with_transport(Arc::clone(&transport) as Arc<dyn Transport>, None, dir.to_path_buf(), &config);
+        state.hold_monitors(monitors);
+        TestServer { state, config, transport, dir }
+    }
+
+    /// A task by client `client_pid`, with `priority`, running `filters` from an input file
+    /// of the server's directory, which the task is the first to use, to an output next to it.
+    pub fn task(&self, client_pid: u32, priority: usize, filters: &[Filter]) -> ClientTask {
+        let request_id = Uuid::new_v4();
+        let input = self.dir.join(format!("{request_id}.in"));
+        fs::write(&input, "input").unwrap();
+        let mut task = ClientTask::new(client_pid, priority, input, self.dir.join(format!("{request_id}.out")), filters.to_vec());
+        task.request_id = request_id;
+        task
+    }
+
+    /// Submit `task`, returning the ID of its request, see [`TestServer::request`].
+    pub fn submit(&mut self, task: ClientTask) -> Uuid {
+        let request_id = task.request_id;
+        self.request(ClientRequest::ProcFile(Box::new(task)));
+        request_id
+    }
+
+    /// Hand the server `request`, as if its client had sent it, see [`TestServer::handle`].
+    pub fn request(&mut self, mut request: ClientRequest) -> bool {
+        let client_pid = *request.client_pid_mut();
+        // SAFETY: neither call has any memory safety requirement.
+        let (uid, gid) = unsafe { (libc::getuid(), libc::getgid()) };
+        let credentials = Credentials { pid: client_pid, uid, gid };
+        self.handle(MessageToServer::Client(request, peer(client_pid), Some(credentials)))
+    }
+
+    /// Conclude task #`task_number` with `result`, as its monitor would have.
+    pub fn conclude(&mut self, task_number: usize, result: Result<TaskSummary, MonitorError>) -> bool {
+        self.handle(MessageToServer::Monitor(MonitorResult { task_number, result, partial_output: None }))
+    }
+
+    /// Conclude task #`task_number` successfully, see [`TestServer::conclude`].
+    pub fn succeed(&mut self, task_number: usize) -> bool {
+        self.conclude(task_number, Ok(TaskSummary::File(success())))
+    }
+
+    /// Have the server handle `msg` as its event loop would, then start every pending task
+    /// it can, returning whether it's to go on, rather than shut down.
+    pub fn handle(&mut self, msg: MessageToServer) -> bool {
+        let go_on = embed::handle_message(&mut self.state, &self.config, msg);
+        embed::schedule(&mut self.state, &self.config);
+        go_on
+    }
+
+    /// The server's status, as a client would be told.
+    pub fn status(&self) -> ServerStatus {
+        self.state.status(&self.config)
+    }
+
+    /// The numbers of the running tasks, with the IDs of their requests, in ascending order.
+    pub fn running(&self) -> Vec<(usize, Uuid)> {
+        self.status().running.iter().map(|running| (running.task_number, running.task.request_id)).collect()
+    }
+
+    /// Have client `client_pid` be gone, see [`MemoryTransport::disconnect`].
+    pub fn disconnect(&self, client_pid: u32) {
+        self.transport.disconnect(peer(client_pid));
+    }
+
+    /// The messages client `client_pid` was sent since last asked, with the ID of the
+    /// request each is about, acknowledging them all, as the client would.
+    pub fn messages(&mut self, client_pid: u32) -> Vec<(Uuid, MessageToClient)> {
+        let inbox = Inbox(Mutex::new(self.transport.take(&peer(client_pid))));
+        let mut receiver = MessageReceiver::default();
+        let mut messages = Vec::new();
+        loop {
+            let bytes = match receiver.recv(&inbox) {
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => return messages,
+                received => received.unwrap(),
+            };
+            let Sequenced { seq, request_id, message } = self.config.wire_format.decode(&bytes).unwrap();
+            self.state.acknowledge(client_pid, request_id, seq);
+            messages.push((request_id, message));
+        }
+    }
+}
+
+/// Where client `client_pid` sends its requests from.
+fn peer(client_pid: u32) -> Peer {
+    Peer::Path(PathBuf::from(format!("sdstore_{client_pid}.sock")))
+}
+
+/// Summary of a task that succeeded, but which read and wrote nothing.
+pub fn success() -> MonitorSuccess {
+    MonitorSuccess {
+        bytes_in: 0,
+        bytes_out: 0,
+        ratio: None,
+        elapsed: Duration::ZERO,
+        throughput: None,
+        sha256_in: String::new(),
+        sha256_out: String::new(),
+        queue_wait: Duration::ZERO,
+        stage_timings: Vec::new(),
+        resource_usage: Default::default(),
+        stderr: String::new(),
+        cached: false,
+        stored: None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::core::{
+        messaging::{RequestFailure, RequestState, TaskId},
+        monitor::MonitorProgress,
+        server::config::DEFAULT_INLINE_MAX_SIZE,
+    };
+
+    use super::*;
+
+    #[test]
+    fn tasks_wait_for_filters_to_be_free_in_order() {
+        let mut server = TestServer::new("nop 1\ngcompress 2\nbuiltin nop gcompress", 4);
+        let first = server.submit(server.task(1, 0, &[Filter::Nop]));
+        let second = server.submit(server.task(2, 0, &[Filter::Nop, Filter::Gcompress]));
+        let third = server.submit(server.task(3, 0, &[Filter::Gcompress]));
+        // The third task could run, but not ahead of the second, as no queue is scanned past its head.
+        let [(task_number, request_id)] = server.running()[..] else { panic!("expected a single running task") };
+        assert_eq!(request_id, first);
+        assert!(matches!(server.messages(1).last(), Some((id, MessageToClient::Processing)) if *id == first));
+        assert!(matches!(server.messages(2).last(), Some((id, MessageToClient::Queued { position: 0, .. })) if *id == second));
+        assert!(matches!(server.messages(3).last(), Some((id, MessageToClient::Queued { position: 1, .. })) if *id == third));
+
+        assert!(server.succeed(task_number));
+        assert!(matches!(server.messages(1).last(), Some((_, MessageToClient::Concluded(_)))));
+        assert!(matches!(server.messages(2).last(), Some((_, MessageToClient::Processing))));
+        assert!(matches!(server.messages(3).last(), Some((_, MessageToClient::Processing))));
+        assert_eq!(server.running().iter().map(|(_, request_id)| *request_id).collect::<Vec<_>>(), vec![second, third]);
+    }
+
+    #[test]
+    fn higher_priority_tasks_run_first() {
+        let mut server = TestServer::new("nop 3\nbuiltin nop", 1);
+        let running = server.submit(server.task(1, 0, &[Filter::Nop]));
+        let low = server.submit(server.task(2, 1, &[Filter::Nop]));
+        let high = server.submit(server.task(3, 5, &[Filter::Nop]));
+        // Only one task runs at once, as the server has a single monitor.
+        let [(task_number, request_id)] = server.running()[..] else { panic!("expected a single running task") };
+        assert_eq!(request_id, running);
+
+        server.request(ClientRequest::Cancel(2, low));
+        assert!(matches!(
+            server.messages(2).last(), Some((id, MessageToClient::Failed(RequestFailure::Cancelled))) if *id == low
+        ));
+        server.conclude(task_number, Err(MonitorError::Killed));
+        assert!(matches!(server.messages(1).last(), Some((_, MessageToClient::Failed(_)))));
+        assert_eq!(server.running().iter().map(|(_, request_id)| *request_id).collect::<Vec<_>>(), vec![high]);
+    }
+
+    #[test]
+    fn only_requests_that_did_not_fail_are_deduplicated() {
+        let mut server = TestServer::new("nop 1\nbuiltin nop", 1);
+        let keyed = |server: &TestServer, client_pid| {
+            let mut task = server.task(client_pid, 0, &[Filter::Nop]);
+            task.idempotency_key = Some(String::from("nightly"));
+            task
+        };
+        server.submit(server.task(9, 0, &[Filter::Nop]));
+        let [(busy, _)] = server.running()[..] else { panic!("expected a single running task") };
+
+        // Orphaned as its client is gone before being told it's queued, the request is
+        // retried by another client, which isn't told it's gone too.
+        server.disconnect(1);
+        server.submit(keyed(&server, 1));
+        let retry = server.submit(keyed(&server, 2));
+        assert!(matches!(server.messages(2).last(), Some((id, MessageToClient::Queued { .. })) if *id == retry));
+        // Nor once the retry is cancelled.
+        server.request(ClientRequest::Cancel(2, retry));
+        let pending = server.submit(keyed(&server, 3));
+        assert!(matches!(server.messages(3).last(), Some((id, MessageToClient::Queued { .. })) if *id == pending));
+        let duplicate = server.submit(keyed(&server, 4));
+        assert!(matches!(
+            server.messages(4).last(),
+            Some((id, MessageToClient::Duplicate { original, .. })) if *id == duplicate && *original == pending
+        ));
+
+        // Once the request failed, after running, a retry runs, and once that succeeds, is
+        // told so again.
+        assert!(server.succeed(busy));
+        let [(failing, _)] = server.running()[..] else { panic!("expected a single running task") };
+        server.conclude(failing, Err(MonitorError::Killed));
+        server.submit(keyed(&server, 5));
+        let [(succeeding, _)] = server.running()[..] else { panic!("expected a single running task") };
+        assert!(server.succeed(succeeding));
+        let replayed = server.submit(keyed(&server, 6));
+        assert!(matches!(server.messages(6).last(), Some((id, MessageToClient::Concluded(_))) if *id == replayed));
+        assert!(server.running().is_empty());
+    }
+
+    #[test]
+    fn tasks_are_cancelled_by_their_owners_only() {
+        let mut server = TestServer::new("nop 1\nbuiltin nop", 1);
+        let running = server.submit(server.task(1, 0, &[Filter::Nop]));
+        let pending = server.submit(server.task(2, 0, &[Filter::Nop]));
+
+        // SAFETY: the call has no memory safety requirement.
+        let uid = unsafe { libc::getuid() };
+        let stranger = Credentials { pid: 8, uid: if uid == 0 { 4000000 } else { uid + 1 }, gid: 0 };
+        for request_id in [running, pending] {
+            server.handle(MessageToServer::Client(ClientRequest::Cancel(8, request_id), peer(8), Some(stranger)));
+        }
+        assert!(matches!(server.messages(2).last(), Some((_, MessageToClient::Queued { .. }))));
+        assert_eq!(server.running().iter().map(|(_, request_id)| *request_id).collect::<Vec<_>>(), vec![running]);
+
+        // Its owner's credentials are those of `TestServer::request`.
+        server.request(ClientRequest::Cancel(2, pending));
+        assert!(matches!(
+            server.messages(2).last(), Some((id, MessageToClient::Failed(RequestFailure::Cancelled))) if *id == pending
+        ));
+    }
+
+    #[test]
+    fn tasks_claiming_to_be_streamed_are_refused() {
+        let mut server = TestServer::new("nop 1\nbuiltin nop", 4);
+        let mut task = server.task(1, 0, &[Filter::Nop]);
+        task.stream = true;
+        let forged = server.submit(task);
+
+        // Its paths would otherwise be taken for the server's own copies, exempt from the path policy.
+        assert!(server.running().is_empty());
+        assert!(matches!(
+            server.messages(1).last(),
+            Some((id, MessageToClient::Failed(RequestFailure::MalformedRequest(_)))) if *id == forged
+        ));
+    }
+
+    #[test]
+    fn tasks_are_queried_by_request_id_or_number() {
+        let mut server = TestServer::new("nop 1\nbuiltin nop", 4);
+        let running = server.submit(server.task(1, 0, &[Filter::Nop]));
+        let pending = server.submit(server.task(2, 0, &[Filter::Nop]));
+        let [(task_number, _)] = server.running()[..] else { panic!("expected a single running task") };
+        server.handle(MessageToServer::Progress(MonitorProgress { task_number, bytes_out: 42 }));
+
+        let query = |server: &mut TestServer, task| {
+            server.request(ClientRequest::Query(9, Uuid::new_v4(), task));
+            server.messages(9).pop().unwrap().1
+        };
+        assert!(matches!(
+            query(&mut server, TaskId::Number(task_number)),
+            MessageToClient::State(RequestState::Running { running: status::RunningTask { task, .. }, bytes_out: 42, .. })
+                if task.request_id == running
+        ));
+        assert!(matches!(
+            query(&mut server, TaskId::Request(pending)),
+            MessageToClient::State(RequestState::Pending(status::QueuedTask { position: 0, .. }))
+        ));
+
+        server.succeed(task_number);
+        for task in [TaskId::Number(task_number), TaskId::Request(running)] {
+            assert!(matches!(
+                query(&mut server, task),
+                MessageToClient::State(RequestState::Done(_, outcome)) if matches!(*outcome, MessageToClient::Concluded(_))
+            ));
+        }
+        assert!(matches!(
+            query(&mut server, TaskId::Number(task_number + 5)),
+            MessageToClient::Failed(RequestFailure::UnknownTask(number)) if number == task_number + 5
+        ));
+    }
+
+    #[test]
+    fn suspended_tasks_free_their_filters_until_resumed() {
+        let mut server = TestServer::new("nop 1\nbuiltin nop", 1);
+        let first = server.submit(server.task(1, 0, &[Filter::Nop]));
+        let second = server.submit(server.task(2, 0, &[Filter::Nop]));
+        let third = server.submit(server.task(3, 0, &[Filter::Nop]));
+        let [(suspended, _)] = server.running()[..] else { panic!("expected a single running task") };
+
+        let control = |server: &mut TestServer, request: fn(u32, Uuid, TaskId) -> ClientRequest, task| {
+            server.request(request(9, Uuid::new_v4(), task));
+            server.messages(9).pop().unwrap().1
+        };
+        assert!(matches!(
+            control(&mut server, ClientRequest::Suspend, TaskId::Request(second)),
+            MessageToClient::Failed(RequestFailure::NotRunning(TaskId::Request(id))) if id == second
+        ));
+        assert!(matches!(
+            control(&mut server, ClientRequest::Suspend, TaskId::Request(first)),
+            MessageToClient::TaskSuspended { task_number } if task_number == suspended
+        ));
+        // Only the task's own user, or an admin, may suspend or resume it.
+        // SAFETY: the call has no memory safety requirement.
+        let uid = unsafe { libc::getuid() };
+        let stranger = Credentials { pid: 8, uid: if uid == 0 { 4000000 } else { uid + 1 }, gid: 0 };
+        let request = ClientRequest::Resume(8, Uuid::new_v4(), TaskId::Request(first));
+        server.handle(MessageToServer::Client(request, peer(8), Some(stranger)));
+        assert!(matches!(server.messages(8).pop(), Some((_, MessageToClient::Refused(_)))));
+        assert_eq!(server.running().len(), 2);
+
+        // The second task takes the first's filters, and its monitor, as the server has a single one.
+        assert_eq!(server.running().iter().map(|(_, request_id)| *request_id).collect::<Vec<_>>(), vec![first, second]);
+        assert!(matches!(server.messages(2).last(), Some((id, MessageToClient::Processing)) if *id == second));
+
+        assert!(matches!(
+            control(&mut server, ClientRequest::Resume, TaskId::Number(suspended)),
+            MessageToClient::TaskResumed { task_number } if task_number == suspended
+        ));
+        // Resuming it again changes nothing, but isn't an error.
+        assert!(matches!(
+            control(&mut server, ClientRequest::Resume, TaskId::Number(suspended)),
+            MessageToClient::TaskResumed { .. }
+        ));
+        // Resumed, the first task's filters are in use past their limit, until both tasks conclude.
+        let [_, (second_number, _)] = server.running()[..] else { panic!("expected two running tasks") };
+        server.succeed(second_number);
+        assert_eq!(server.running().len(), 1);
+        server.succeed(suspended);
+        assert_eq!(server.running().iter().map(|(_, request_id)| *request_id).collect::<Vec<_>>(), vec![third]);
+    }
+
+    #[test]
+    fn inline_tasks_are_spooled_and_their_output_sent_back() {
+        let mut server = TestServer::new("nop 1\nbuiltin nop", 4);
+        let mut task = ClientTask::new(1, 0, PathBuf::from("in"), PathBuf::from("out"), vec![Filter::Nop]);
+        (task.request_id, task.inline) = (Uuid::new_v4(), Some(b"input".as_slice().into()));
+        server.submit(task);
+
+        let running = server.status().running.pop().unwrap();
+        assert_eq!(fs::read(running.task.input_filepath()).unwrap(), b"input");
+        assert_eq!(running.task.inline.as_deref(), Some([].as_slice()));
+        // As the monitor would have written it.
+        fs::write(running.task.output_filepath(), "output").unwrap();
+        server.succeed(running.task_number);
+        let messages = server.messages(1).into_iter().map(|(_, message)| message).collect::<Vec<_>>();
+        assert!(matches!(
+            &messages[messages.len() - 2..],
+            [MessageToClient::InlineOutput(output), MessageToClient::Concluded(_)] if output == b"output"
+        ));
+        assert!(!running.task.input_filepath().exists() && !running.task.output_filepath().exists());
+
+        let mut task = server.task(2, 0, &[Filter::Nop]);
+        task.inline = Some(vec![0; DEFAULT_INLINE_MAX_SIZE + 1].into());
+        server.submit(task);
+        assert!(matches!(
+            server.messages(2).last(),
+            Some((_, MessageToClient::Failed(RequestFailure::InlineTooLarge { len, max: DEFAULT_INLINE_MAX_SIZE })))
+                if *len == DEFAULT_INLINE_MAX_SIZE + 1
+        ));
+    }
+
+    #[test]
+    fn reports_total_tasks_by_client_and_filter_for_admins_only() {
+        let mut server = TestServer::new("nop 1\ngcompress 1\nbuiltin nop gcompress", 4);
+        server.submit(server.task(1, 0, &[Filter::Nop, Filter::Gcompress]));
+        let cancelled = server.submit(server.task(2, 0, &[Filter::Nop]));
+        let [(task_number, _)] = server.running()[..] else { panic!("expected a single running task") };
+        server.request(ClientRequest::Cancel(2, cancelled));
+        let summary = MonitorSuccess { bytes_in: 100, bytes_out: 40, ..success() };
+        server.conclude(task_number, Ok(TaskSummary::File(summary)));
+
+        server.request(ClientRequest::Report(9, Uuid::new_v4(), None));
+        let Some((_, MessageToClient::Report(report))) = server.messages(9).pop() else { panic!("expected a report") };
+        assert_eq!((report.total.tasks, report.total.failed, report.total.bytes_in), (2, 1, 100));
+        assert_eq!(report.by_client.iter().map(|(_, pid, totals)| (*pid, totals.failed)).collect::<Vec<_>>(), vec![(1, 0), (2, 1)]);
+        let by_filter = report.by_filter.iter().map(|(filter, totals)| (filter.clone(), totals.tasks)).collect::<Vec<_>>();
+        assert_eq!(by_filter, vec![(Filter::Gcompress, 1), (Filter::Nop, 2)]);
+
+        // SAFETY: the call has no memory safety requirement.
+        let uid = unsafe { libc::getuid() };
+        let credentials = Credentials { pid: 9, uid: if uid == 0 { 4000000 } else { uid + 1 }, gid: 0 };
+        server.handle(MessageToServer::Client(ClientRequest::Report(9, Uuid::new_v4(), None), peer(9), Some(credentials)));
+        assert!(matches!(server.messages(9).pop(), Some((_, MessageToClient::Refused(_)))));
+    }
+
+    #[test]
+    fn pending_tasks_are_handed_over_once_running_ones_conclude() {
+        let mut server = TestServer::new("nop 1\nbuiltin nop", 4);
+        server.request(ClientRequest::Reexec(9, Uuid::new_v4()));
+        // An embedded server can't be re-exec'd, as its executable is another program's.
+        assert!(matches!(server.messages(9).pop(), Some((_, MessageToClient::Refused(_)))));
+
+        server.state.enable_reexec(PathBuf::from("sdstored"));
+        server.submit(server.task(1, 0, &[Filter::Nop]));
+        let pending = server.submit(server.task(2, 0, &[Filter::Nop]));
+        let [(task_number, _)] = server.running()[..] else { panic!("expected a single running task") };
+        server.request(ClientRequest::Reexec(9, Uuid::new_v4()));
+        assert!(matches!(server.messages(9).pop(), Some((_, MessageToClient::HandingOver { running: 1 }))));
+        assert!(!server.state.ready_to_hand_over(Duration::MAX));
+
+        // The pending task isn't started as the running one concludes, but handed over.
+        server.succeed(task_number);
+        assert!(server.running().is_empty() && server.state.ready_to_hand_over(Duration::MAX));
+        let runtime = tokio::runtime::Builder::new_current_thread().enable_all().build().unwrap();
+        let handover = runtime.block_on(server.state.hand_over(&server.config));
+        assert_eq!(handover.tasks.iter().map(|(task, _)| task.request_id).collect::<Vec<_>>(), vec![pending]);
+        // SAFETY: neither call has any memory safety requirement.
+        let credentials = unsafe { (libc::getuid(), libc::getgid()) };
+        assert_eq!(handover.tasks[0].1, Some(credentials));
+
+        let mut next = TestServer::new("nop 1\nbuiltin nop", 4);
+        next.state.take_over(handover);
+        assert!(matches!(next.messages(9).pop(), Some((_, MessageToClient::TookOver { pending: 1, .. }))));
+        next.request(ClientRequest::Ping(9, Uuid::new_v4()));
+        assert_eq!(next.running().iter().map(|(_, request_id)| *request_id).collect::<Vec<_>>(), vec![pending]);
+        assert!(matches!(next.messages(2).last(), Some((id, MessageToClient::Processing)) if *id == pending));
+    }
+}
@@ -0,0 +1,268 @@
+//! Filters implemented as WASI modules, which the server runs in-process, sandboxed by
+//! wasmtime, rather than as executables, see [`WasmRuntime`].
+//!
+//! Running them needs the server to be built with the `wasm` feature, without which it
+//! refuses to start with any configured, see [`SUPPORTED`].
+
+use std::{fmt::Display, io, path::{Path, PathBuf}};
+
+#[cfg(not(feature = "wasm"))]
+use std::fs;
+
+#[cfg(not(feature = "wasm"))]
+use crate::core::monitor::StageFailure;
+
+/// Whether the server can run WASM filters, having been built with the `wasm` feature.
+pub const SUPPORTED: bool = cfg!(feature = "wasm");
+
+/// Extension of the files configured as a filter's executable, see
+/// [`ServerConfig::filter_executor`](super::config::ServerConfig::filter_executor), which
+/// are WASI modules rather than executables.
+pub const MODULE_EXTENSION: &str = "wasm";
+
+/// Whether the filter executable at `path` is a WASI module, see [`MODULE_EXTENSION`].
+pub fn is_module(path: &Path) -> bool {
+    path.extension().is_some_and(|ext| ext == MODULE_EXTENSION)
+}
+
+/// Errors that may happen while loading WASM filters.
+#[derive(Debug)]
+pub enum WasmError {
+    /// The server was built without the `wasm` feature, see [`SUPPORTED`].
+    Unsupported,
+    /// The engine couldn't be set up, for this reason.
+    EngineError(String),
+    /// The module at this path couldn't be read, or compiled, for this reason.
+    InvalidModule(PathBuf, String),
+    /// The thread interrupting modules of killed pipelines couldn't be started.
+    TickerSpawnError(io::Error),
+}
+
+impl Display for WasmError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Unsupported => write!(f, "the server was built without support for WASM filters"),
+            Self::EngineError(reason) => write!(f, "could not set up the WASM engine: {reason}"),
+            Self::InvalidModule(path, reason) => write!(f, "the WASM module {} is invalid: {reason}", path.display()),
+            Self::TickerSpawnError(err) => write!(f, "could not start the WASM epoch thread: {err}"),
+        }
+    }
+}
+
+#[cfg(feature = "wasm")]
+pub use runtime::WasmRuntime;
+
+/// Stands in for the runtime of a server built without the `wasm` feature, which is never
+/// loaded, see [`WasmRuntime::load`].
+#[cfg(not(feature = "wasm"))]
+pub struct WasmRuntime {
+    never: std::convert::Infallible,
+}
+
+#[cfg(not(feature = "wasm"))]
+impl WasmRuntime {
+    pub fn load(_: &[PathBuf]) -> Result<Self, WasmError> {
+        Err(WasmError::Unsupported)
+    }
+
+    pub fn run<R>(&self, _: &str, _: &Path, _: R, _: fs::File, _: fs::File, _: impl Fn() -> bool) -> Result<(), StageFailure> {
+        match self.never {}
+    }
+}
+
+#[cfg(feature = "wasm")]
+mod runtime {
+    use std::{
+        collections::HashMap, fs, io::{self, Read, Write},
+        path::{Path, PathBuf},
+        sync::{atomic::{AtomicBool, Ordering}, mpsc::{self, RecvTimeoutError, Sender}, Arc},
+        thread::{self, JoinHandle},
+        time::Duration,
+    };
+
+    use wasi_common::{pipe::{ReadPipe, WritePipe}, sync::WasiCtxBuilder, I32Exit, WasiCtx};
+    use wasmtime::{Config, Engine, Linker, Module, Store, UpdateDeadline};
+
+    use crate::core::monitor::StageFailure;
+
+    use super::WasmError;
+
+    /// How often running modules check whether their pipeline was killed, see
+    /// [`WasmRuntime::run`].
+    const EPOCH_TICK: Duration = Duration::from_millis(100);
+
+    /// Output of a WASM stage, which tells whether the next stage stopped reading it, as the
+    /// module is only told of a failed write, and may well exit with any code then.
+    struct StageOutput {
+        file: fs::File,
+        broken_pipe: Arc<AtomicBool>,
+    }
+
+    impl Write for StageOutput {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.file.write(buf).inspect_err(|err| if err.kind() == io::ErrorKind::BrokenPipe {
+                self.broken_pipe.store(true, Ordering::SeqCst);
+            })
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            self.file.flush()
+        }
+    }
+
+    /// The engine WASM filters are run by, with every configured module compiled once, as
+    /// the server starts.
+    ///
+    /// Modules only get the WASI functions of `wasi_snapshot_preview1`, and of the system,
+    /// only their `stdin`, `stdout` and `stderr`, and the filter's name as their sole
+    /// argument: no file, directory, environment variable or socket.
+    pub struct WasmRuntime {
+        engine: Engine,
+        linker: Linker<WasiCtx>,
+        modules: HashMap<PathBuf, Module>,
+        /// Stops the thread advancing the engine's epoch when dropped, see [`EPOCH_TICK`].
+        ticker: Option<(Sender<()>, JoinHandle<()>)>,
+    }
+
+    impl WasmRuntime {
+        /// Compile the modules at `paths`, and start the thread advancing the engine's epoch.
+        pub fn load(paths: &[PathBuf]) -> Result<Self, WasmError> {
+            let mut config = Config::new();
+            config.epoch_interruption(true);
+            let engine = Engine::new(&config).map_err(|err| WasmError::EngineError(format!("{err:#}")))?;
+            let mut linker = Linker::new(&engine);
+            wasi_common::sync::add_to_linker(&mut linker, |ctx| ctx).map_err(|err| WasmError::EngineError(format!("{err:#}")))?;
+
+            let mut modules = HashMap::new();
+            for path in paths {
+                let module = Module::from_file(&engine, path)
+                    .map_err(|err| WasmError::InvalidModule(path.clone(), format!("{err:#}")))?;
+                modules.insert(path.clone(), module);
+            }
+
+            let (stop, stopped) = mpsc::channel();
+            let ticking = engine.clone();
+            let ticker = thread::Builder::new()
+                .name(String::from("Wasm-epoch"))
+                .spawn(move || while let Err(RecvTimeoutError::Timeout) = stopped.recv_timeout(EPOCH_TICK) {
+                    ticking.increment_epoch();
+                })
+                .map_err(WasmError::TickerSpawnError)?;
+            Ok(WasmRuntime { engine, linker, modules, ticker: Some((stop, ticker)) })
+        }
+
+        /// Run the module at `path`, with `name` as its argument, reading `input` and writing
+        /// `output` and `stderr`, until it returns from `_start`, exits, or traps, which it
+        /// does soon after `is_killed` returns `true`.
+        pub fn run<R>(
+            &self,
+            name: &str,
+            path: &Path,
+            input: R,
+            output: fs::File,
+            stderr: fs::File,
+            is_killed: impl Fn() -> bool + Send + Sync + 'static
+        ) -> Result<(), StageFailure>
+        where
+            R: Read + Send + Sync + 'static
+        {
+            let failed = |reason: String| StageFailure::Trapped(reason);
+            let module = self.modules.get(path).ok_or_else(|| failed(format!("{} was not loaded", path.display())))?;
+
+            let broken_pipe = Arc::new(AtomicBool::new(false));
+            let output = StageOutput { file: output, broken_pipe: Arc::clone(&broken_pipe) };
+            let ctx = WasiCtxBuilder::new()
+                .stdin(Box::new(ReadPipe::new(input)))
+                .stdout(Box::new(WritePipe::new(output)))
+                .stderr(Box::new(WritePipe::new(stderr)))
+                .arg(name)
+                .map_err(|err| failed(format!("{err:#}")))?
+                .build();
+            let mut store = Store::new(&self.engine, ctx);
+            store.set_epoch_deadline(1);
+            store.epoch_deadline_callback(move |_| match is_killed() {
+                true => Err(wasmtime::Error::msg("the pipeline was killed")),
+                false => Ok(UpdateDeadline::Continue(1)),
+            });
+
+            let result = self
+                .linker
+                .instantiate(&mut store, module)
+                .and_then(|instance| instance.get_typed_func::<(), ()>(&mut store, "_start"))
+                .and_then(|start| start.call(&mut store, ()));
+            match result {
+                Ok(()) => Ok(()),
+                Err(err) => match err.downcast_ref::<I32Exit>() {
+                    Some(I32Exit(0)) => Ok(()),
+                    _ if broken_pipe.load(Ordering::SeqCst) => Err(StageFailure::BrokenPipe),
+                    Some(I32Exit(code)) => Err(StageFailure::Exited(*code)),
+                    None => Err(failed(format!("{err:#}"))),
+                },
+            }
+        }
+    }
+
+    impl Drop for WasmRuntime {
+        fn drop(&mut self) {
+            if let Some((stop, ticker)) = self.ticker.take() {
+                drop(stop);
+                let _ = ticker.join();
+            }
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use std::io::Cursor;
+
+        use crate::core::testing::TempDir;
+
+        use super::*;
+
+        /// A module copying its `stdin` to its `stdout`, which exits with code 3 if its
+        /// input begins with `!`, and loops forever if it begins with `~`.
+        const CAT: &str = r#"(module
+            (import "wasi_snapshot_preview1" "fd_read" (func $fd_read (param i32 i32 i32 i32) (result i32)))
+            (import "wasi_snapshot_preview1" "fd_write" (func $fd_write (param i32 i32 i32 i32) (result i32)))
+            (import "wasi_snapshot_preview1" "proc_exit" (func $proc_exit (param i32)))
+            (memory (export "memory") 1)
+            (func (export "_start")
+                (loop $copy
+                    (i32.store (i32.const 0) (i32.const 64))
+                    (i32.store (i32.const 4) (i32.const 1024))
+                    (if (call $fd_read (i32.const 0) (i32.const 0) (i32.const 1) (i32.const 16))
+                        (then (call $proc_exit (i32.const 2))))
+                    (if (i32.eqz (i32.load (i32.const 16))) (then (return)))
+                    (if (i32.eq (i32.load8_u (i32.const 64)) (i32.const 33)) (then (call $proc_exit (i32.const 3))))
+                    (if (i32.eq (i32.load8_u (i32.const 64)) (i32.const 126)) (then (loop $spin (br $spin))))
+                    (i32.store (i32.const 4) (i32.load (i32.const 16)))
+                    (drop (call $fd_write (i32.const 1) (i32.const 0) (i32.const 1) (i32.const 16)))
+                    (br $copy))))"#;
+
+        #[test]
+        fn modules_run_as_filters() {
+            let dir = TempDir::new("wasm");
+            let module = dir.join("cat.wasm");
+            fs::write(&module, wat::parse_str(CAT).unwrap()).unwrap();
+            let runtime = WasmRuntime::load(std::slice::from_ref(&module)).unwrap();
+
+            let run = |input: &str, killed: Arc<AtomicBool>| {
+                let output = dir.join("out");
+                let result = runtime.run(
+                    "cat",
+                    &module,
+                    Cursor::new(input.as_bytes().to_vec()),
+                    fs::File::create(&output).unwrap(),
+                    fs::File::create(dir.join("err")).unwrap(),
+                    move || killed.load(Ordering::SeqCst)
+                );
+                (result, fs::read_to_string(&output).unwrap())
+            };
+            assert_eq!(run("filtered", Arc::default()), (Ok(()), String::from("filtered")));
+            assert_eq!(run("!", Arc::default()).0, Err(StageFailure::Exited(3)));
+            assert!(matches!(run("~", Arc::new(AtomicBool::new(true))).0, Err(StageFailure::Trapped(_))));
+
+            assert!(matches!(WasmRuntime::load(&[dir.join("err")]), Err(WasmError::InvalidModule(..))));
+        }
+    }
+}
@@ -0,0 +1,112 @@
+//! What the server runs with, sent to clients that ask for it with `./sdstore server-info`,
+//! so that its users can tell its limits without reading its config, see [`ServerInfo`].
+
+use std::fmt::Display;
+
+use serde::{Serialize, Deserialize};
+
+/// The server's effective configuration, as far as its clients are concerned: which filters
+/// it runs, how many of each at once, and how it schedules and bounds requests.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct ServerInfo {
+    pub server_version: String,
+    /// Directory of the filters' executables, on the server's host.
+    pub transformations_path: String,
+    /// Filters the server may run, in the order it lists them, see [`FilterInfo`].
+    pub filters: Vec<FilterInfo>,
+    /// Name of the policy pending requests are scheduled by.
+    pub scheduling_policy: String,
+    /// Queues requests may be submitted to, by name, with their weights.
+    pub queues: Vec<(String, usize)>,
+    /// Most requests that may be pending at once, if bounded.
+    pub queue_capacity: Option<usize>,
+    /// Most filters a request's pipeline may have.
+    pub max_transformations: usize,
+    /// Most requests that may run at once.
+    pub monitor_threads: usize,
+    /// Optional features the server was built with, such as `remote` and `wasm`, followed
+    /// by those its config enables, such as `cache`, `store` and `sandbox`.
+    pub features: Vec<String>,
+}
+
+/// A filter the server may run, see [`ServerInfo::filters`].
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct FilterInfo {
+    pub name: String,
+    /// How many of it may run at once, across requests.
+    pub limit: usize,
+    /// How it is run: `builtin`, in the server, or else the path of its executable, or module.
+    pub runs: String,
+}
+
+/// Formats the info as a line per setting, and per filter, e.g.
+///
+/// ```text
+/// server version 0.1.0
+/// transformations: bin/sdstore-transformations
+/// scheduling policy: priority
+/// queues: default (weight 1), batch (weight 2)
+/// queue capacity: 100
+/// max transformations: 64
+/// monitor threads: 4
+/// filter nop: limit 3, builtin
+/// filter gcompress: limit 1, bin/sdstore-transformations/gcompress
+/// features: remote, cache
+/// ```
+impl Display for ServerInfo {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "server version {}", self.server_version)?;
+        writeln!(f, "transformations: {}", self.transformations_path)?;
+        writeln!(f, "scheduling policy: {}", self.scheduling_policy)?;
+        let queues = self.queues.iter().map(|(name, weight)| format!("{name} (weight {weight})")).collect::<Vec<_>>();
+        writeln!(f, "queues: {}", queues.join(", "))?;
+        match self.queue_capacity {
+            None => writeln!(f, "queue capacity: unbounded")?,
+            Some(capacity) => writeln!(f, "queue capacity: {capacity}")?,
+        }
+        writeln!(f, "max transformations: {}", self.max_transformations)?;
+        writeln!(f, "monitor threads: {}", self.monitor_threads)?;
+        for filter in &self.filters {
+            writeln!(f, "filter {}: limit {}, {}", filter.name, filter.limit, filter.runs)?;
+        }
+        match self.features.as_slice() {
+            [] => write!(f, "features: none"),
+            features => write!(f, "features: {}", features.join(", ")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn info_is_listed_by_line() {
+        let info = ServerInfo {
+            server_version: String::from("0.1.0"),
+            transformations_path: String::from("bin"),
+            filters: vec![
+                FilterInfo { name: String::from("nop"), limit: 3, runs: String::from("builtin") },
+                FilterInfo { name: String::from("gcompress"), limit: 1, runs: String::from("bin/gcompress") },
+            ],
+            scheduling_policy: String::from("priority"),
+            queues: vec![(String::from("default"), 1), (String::from("batch"), 2)],
+            queue_capacity: None,
+            max_transformations: 64,
+            monitor_threads: 4,
+            features: vec![String::from("cache")],
+        };
+        assert_eq!(info.to_string(), "\
+server version 0.1.0
+transformations: bin
+scheduling policy: priority
+queues: default (weight 1), batch (weight 2)
+queue capacity: unbounded
+max transformations: 64
+monitor threads: 4
+filter nop: limit 3, builtin
+filter gcompress: limit 1, bin/gcompress
+features: cache");
+        assert!(ServerInfo { features: Vec::new(), ..info }.to_string().ends_with("\nfeatures: none"));
+    }
+}
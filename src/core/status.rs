@@ -0,0 +1,311 @@
+use std::{fmt::Display, sync::Arc, time::Duration};
+
+use serde::{Serialize, Deserialize};
+
+use super::{client_task::ClientTask, filter::Filter, server::config::FiltersConfig};
+
+/// The server's status, sent to clients that ask for it with `./sdstore status`, who format
+/// it themselves, see its `Display` implementation.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct ServerStatus {
+    /// Running tasks, by ascending task number.
+    pub running: Vec<RunningTask>,
+    /// Pending tasks, queue by queue, in the order they'd be popped from each.
+    pub queued: Vec<QueuedTask>,
+    /// Filters running across every queue, against the server-wide limits.
+    pub filters: Vec<FilterUsage>,
+    /// Every queue but the default one, which is only bound by the server-wide limits.
+    pub queues: Vec<QueueStatus>,
+    /// How long the server has been running for.
+    pub uptime: Duration,
+    /// What the server did since it started.
+    pub stats: ServerStats,
+}
+
+/// Totals of the requests the server handled since it started, kept up to date as they
+/// start and conclude.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct ServerStats {
+    /// Requests that succeeded.
+    pub completed: u64,
+    /// Requests that failed, whether they ran or not, e.g. because they were cancelled.
+    pub failed: u64,
+    /// Total size of the inputs of the requests that succeeded, in bytes.
+    pub bytes_in: u64,
+    /// Total size of their outputs, in bytes.
+    pub bytes_out: u64,
+    /// Requests that started running.
+    pub started: u64,
+    /// How long those requests waited in their queues, in total.
+    pub queue_wait: Duration,
+    /// Requests that ran until they succeeded or failed.
+    pub ran: u64,
+    /// How long those requests ran for, in total.
+    pub run_time: Duration,
+}
+
+impl ServerStats {
+    /// Record that a request started running, after waiting for `queue_wait`.
+    pub fn record_start(&mut self, queue_wait: Duration) {
+        self.started += 1;
+        self.queue_wait += queue_wait;
+    }
+
+    /// Record that a request ran for `run_time`, before succeeding or failing.
+    pub fn record_run(&mut self, run_time: Duration) {
+        self.ran += 1;
+        self.run_time += run_time;
+    }
+
+    /// Mean time requests waited in their queues before starting, if any started yet.
+    pub fn mean_queue_wait(&self) -> Option<Duration> {
+        mean(self.queue_wait, self.started)
+    }
+
+    /// Mean time requests ran for, if any concluded yet.
+    pub fn mean_run_time(&self) -> Option<Duration> {
+        mean(self.run_time, self.ran)
+    }
+}
+
+/// `total` divided by `count`, unless it is `0`.
+fn mean(total: Duration, count: u64) -> Option<Duration> {
+    match count {
+        0 => None,
+        count => Some(Duration::from_secs_f64(total.as_secs_f64() / count as f64)),
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct RunningTask {
+    pub task_number: usize,
+    pub task: Arc<ClientTask>,
+}
+
+/// A pending task, which has not yet been assigned a task number, and its position in its
+/// queue, starting at `0` for the next task to be executed.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct QueuedTask {
+    pub position: usize,
+    pub task: Arc<ClientTask>,
+}
+
+/// How many instances of a filter are running, and how many may.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct FilterUsage {
+    pub filter: Filter,
+    pub running: usize,
+    pub limit: usize,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct QueueStatus {
+    pub name: String,
+    pub weight: usize,
+    /// Filters run by the queue's tasks, against its own limits.
+    pub filters: Vec<FilterUsage>,
+}
+
+/// Usage of every filter, given how many of each are `running`, and their `limits`.
+pub fn filter_usage(running: &FiltersConfig, limits: &FiltersConfig) -> Vec<FilterUsage> {
+    limits
+        .filters()
+        .iter()
+        .map(|filter| FilterUsage { filter: filter.clone(), running: running.limit(filter), limit: limits.limit(filter) })
+        .collect()
+}
+
+/// Formats the status as the course's statement shows it:
+///
+/// ```text
+/// task #3: proc-file 0 /home/user/samples/file-c file-c-output nop bcompress
+/// pending #0: proc-file 1 samples/file-a file-a-output bcompress nop
+/// transformation nop: 1/3 (running/max)
+/// ...
+/// ```
+///
+/// followed by the filters of each queue, indented, the totals of what the server did since
+/// it started, see [`ServerStats`], and its uptime.
+impl Display for ServerStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for RunningTask { task_number, task } in &self.running {
+            writeln!(f, "task #{task_number}: {}", ProcFile(task))?;
+        }
+        for QueuedTask { position, task } in &self.queued {
+            writeln!(f, "pending #{position}: {}", ProcFile(task))?;
+        }
+        fmt_filters(&self.filters, "", f)?;
+        for queue in &self.queues {
+            writeln!(f, "queue {} (weight {}):", queue.name, queue.weight)?;
+            fmt_filters(&queue.filters, "  ", f)?;
+        }
+        let stats = &self.stats;
+        writeln!(f, "requests: {} completed, {} failed", stats.completed, stats.failed)?;
+        writeln!(f, "bytes: {} in, {} out", stats.bytes_in, stats.bytes_out)?;
+        let secs = |mean: Option<Duration>| mean.map_or(String::from("-"), |mean| format!("{:.3}s", mean.as_secs_f64()));
+        writeln!(f, "mean queue wait: {}, mean run time: {}", secs(stats.mean_queue_wait()), secs(stats.mean_run_time()))?;
+        write!(f, "uptime: {:.1}s", self.uptime.as_secs_f64())
+    }
+}
+
+/// Formats a task as the client command that submitted it, e.g.
+///
+/// `proc-file [--queue <name>] <priority> <input-file> <output-file> <filter_1> ... <filter_n>`
+///
+/// The queue is only shown for tasks not in the default queue.
+pub struct ProcFile<'a>(pub &'a ClientTask);
+
+impl Display for ProcFile<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let task = self.0;
+        write!(f, "proc-file")?;
+        if let Some(queue) = &task.queue {
+            write!(f, " --queue {}", queue)?;
+        }
+        if task.no_clobber {
+            write!(f, " --no-clobber")?;
+        }
+        if task.stream {
+            write!(f, " --stream")?;
+        }
+        if task.inline.is_some() {
+            write!(f, " --inline")?;
+        }
+        if task.chunks > 1 {
+            write!(f, " --chunks {}", task.chunks)?;
+        }
+        write!(
+            f,
+            " {} {} {}",
+            task.priority,
+            task.input_filepath().display(),
+            task.output_filepath().display(),
+        )?;
+
+        for transformation in &task.transformations {
+            write!(f, " {}", transformation)?;
+        }
+        Ok(())
+    }
+}
+
+/// Width, in characters, of the bars showing how much of each filter's limit is used, see
+/// [`Dashboard`].
+const BAR_WIDTH: usize = 20;
+
+/// Formats the status as a top-like view, redrawn by `./sdstore watch` as it changes:
+///
+/// ```text
+/// up 1.5s, 1 running, 1 pending
+///
+/// running:
+///   task #4: proc-file 2 in out nop
+/// pending:
+///   pending #0: proc-file 1 in2 out2 gcompress
+///
+/// filters:
+///   nop          [######--------------] 1/3
+/// ```
+///
+/// followed by the filters of each queue. Filters that may not run at all are left out.
+pub struct Dashboard<'a>(pub &'a ServerStatus);
+
+impl Display for Dashboard<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let status = self.0;
+        writeln!(
+            f,
+            "up {:.1}s, {} running, {} pending\n",
+            status.uptime.as_secs_f64(), status.running.len(), status.queued.len()
+        )?;
+
+        writeln!(f, "running:")?;
+        for RunningTask { task_number, task } in &status.running {
+            writeln!(f, "  task #{task_number}: {}", ProcFile(task))?;
+        }
+        writeln!(f, "pending:")?;
+        for QueuedTask { position, task } in &status.queued {
+            writeln!(f, "  pending #{position}: {}", ProcFile(task))?;
+        }
+
+        write!(f, "\nfilters:")?;
+        fmt_bars(&status.filters, f)?;
+        for queue in &status.queues {
+            write!(f, "\nqueue {} (weight {}):", queue.name, queue.weight)?;
+            fmt_bars(&queue.filters, f)?;
+        }
+        Ok(())
+    }
+}
+
+/// Format a bar for each filter that may run, on a line of its own, see [`Dashboard`].
+fn fmt_bars(filters: &[FilterUsage], f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    for FilterUsage { filter, running, limit } in filters.iter().filter(|usage| usage.limit > 0) {
+        let filled = (running * BAR_WIDTH / limit).min(BAR_WIDTH);
+        let bar = format!("{}{}", "#".repeat(filled), "-".repeat(BAR_WIDTH - filled));
+        write!(f, "\n  {:<12} [{bar}] {running}/{limit}", filter.to_string())?;
+    }
+    Ok(())
+}
+
+/// Format the usage of filters, one per line, each started with `prefix`.
+fn fmt_filters(filters: &[FilterUsage], prefix: &str, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    for FilterUsage { filter, running, limit } in filters {
+        writeln!(f, "{prefix}transformation {filter}: {running}/{limit} (running/max)")?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+
+    #[test]
+    fn status_formatting_works() {
+        let mut task = ClientTask::new(1, 2, PathBuf::from("in"), PathBuf::from("out"), vec![Filter::Nop]);
+        task.queue = Some(String::from("batch"));
+        let limits = FiltersConfig { nop: 3, ..Default::default() };
+        let running = FiltersConfig { nop: 1, ..Default::default() };
+
+        let status = ServerStatus {
+            running: vec![RunningTask { task_number: 4, task: Arc::new(task.clone()) }],
+            queued: vec![QueuedTask { position: 0, task: Arc::new(task) }],
+            filters: filter_usage(&running, &limits)[..2].to_vec(),
+            queues: vec![QueueStatus { name: String::from("batch"), weight: 2, filters: filter_usage(&running, &limits)[..1].to_vec() }],
+            uptime: Duration::from_millis(1500),
+            stats: ServerStats { completed: 3, failed: 1, bytes_in: 2048, bytes_out: 512, ..Default::default() },
+        };
+        assert_eq!(status.to_string(), "\
+task #4: proc-file --queue batch 2 in out nop
+pending #0: proc-file --queue batch 2 in out nop
+transformation nop: 1/3 (running/max)
+transformation bcompress: 0/0 (running/max)
+queue batch (weight 2):
+  transformation nop: 1/3 (running/max)
+requests: 3 completed, 1 failed
+bytes: 2048 in, 512 out
+mean queue wait: -, mean run time: -
+uptime: 1.5s");
+
+        assert_eq!(Dashboard(&status).to_string(), "\
+up 1.5s, 1 running, 1 pending
+
+running:
+  task #4: proc-file --queue batch 2 in out nop
+pending:
+  pending #0: proc-file --queue batch 2 in out nop
+
+filters:
+  nop          [######--------------] 1/3
+queue batch (weight 2):
+  nop          [######--------------] 1/3");
+
+        let mut status = status;
+        status.stats.record_start(Duration::from_millis(10));
+        status.stats.record_start(Duration::from_millis(30));
+        status.stats.record_run(Duration::from_millis(250));
+        assert!(status.to_string().contains("\nmean queue wait: 0.020s, mean run time: 0.250s\n"));
+    }
+}
@@ -0,0 +1,237 @@
+//! The server's record of what became of each request, sent to clients that ask for it with
+//! `./sdstore logs`, see [`TaskLog`].
+
+use std::{collections::{HashMap, VecDeque}, fmt::Display, time::Duration};
+
+use serde::{Serialize, Deserialize};
+use uuid::Uuid;
+
+use super::{client_task::ClientTask, filter::Filter, messaging::RequestFailure, monitor::StageTiming, status::ProcFile};
+
+/// What the server did with a request, as it did it: how it was scheduled, the commands its
+/// pipeline's stages ran, what they wrote to `stderr`, and how long each took.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct TaskLog {
+    pub task: ClientTask,
+    /// In the order they happened.
+    pub entries: Vec<TaskLogEntry>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct TaskLogEntry {
+    /// When it happened, since the server received the request.
+    pub at: Duration,
+    pub event: TaskLogEvent,
+}
+
+/// Something the server did with a request, see [`TaskLog`].
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub enum TaskLogEvent {
+    /// The request is pending in its queue, with this priority, behind `position` others.
+    Queued {
+        queue: String,
+        priority: usize,
+        position: usize
+    },
+    /// The request was run ahead of the `position` requests pending before it in its
+    /// queue, none of which could run yet, see
+    /// [`ServerConfig::scan_depth`](super::server::config::ServerConfig::scan_depth).
+    RanAhead { position: usize },
+    /// The request started running as task #`task_number`, each stage of its pipeline
+    /// running its filter with a command: the path of its executable, or `builtin`.
+    Started {
+        task_number: usize,
+        commands: Vec<(Filter, String)>
+    },
+    /// The request started running as task #`task_number`, handed to the worker with this
+    /// name, see [`coordinator`](super::server::coordinator).
+    Dispatched {
+        task_number: usize,
+        worker: String
+    },
+    /// The request was put back in its queue, as the worker with this name it was handed
+    /// to could not run it, for this reason.
+    Requeued {
+        worker: String,
+        reason: String
+    },
+    /// A stage of the request's pipeline ran for as long as `timing` tells.
+    Stage {
+        stage: usize,
+        timing: StageTiming
+    },
+    /// The request's filters wrote this to `stderr`, as excerpted by
+    /// [`stderr_excerpt`](super::monitor::stderr_excerpt), though it succeeded.
+    Stderr(String),
+    /// The client with this PID asked for the request to be cancelled.
+    CancelRequested { by_pid: u32 },
+    /// The request ran for longer than the server allows, and was cancelled, see
+    /// [`ServerConfig::task_timeout`](super::server::config::ServerConfig::task_timeout).
+    TimedOut(Duration),
+    /// The request succeeded.
+    Finished,
+    /// The request failed for this reason, including what its filters wrote to `stderr`
+    /// if one of them failed.
+    Failed(RequestFailure),
+    /// The server shut down while the request was running, to resume it once it restarts.
+    Suspended,
+    /// The client with this PID suspended the running request, see
+    /// [`ClientRequest::Suspend`](super::messaging::ClientRequest::Suspend).
+    SuspendedByClient { by_pid: u32 },
+    /// The client with this PID resumed the suspended request.
+    ResumedByClient { by_pid: u32 },
+}
+
+impl TaskLog {
+    pub fn new(task: ClientTask) -> Self {
+        TaskLog { task, entries: Vec::new() }
+    }
+
+    /// Record that `event` happened now, timed from when the server received the request.
+    pub fn push(&mut self, event: TaskLogEvent) {
+        let at = self.task.received_at.map(|received_at| received_at.elapsed()).unwrap_or_default();
+        self.entries.push(TaskLogEntry { at, event });
+    }
+}
+
+/// The logs the server keeps: of every pending or running request, and of the last few to
+/// conclude, see [`TaskLogs::conclude`].
+#[derive(Debug, Default)]
+pub struct TaskLogs {
+    /// Logs of pending and running requests, by ID.
+    active: HashMap<Uuid, TaskLog>,
+    /// Logs of the requests that most recently concluded, oldest first.
+    concluded: VecDeque<TaskLog>,
+    /// Number of concluded requests whose logs are kept.
+    keep: usize,
+}
+
+impl TaskLogs {
+    /// Logs keeping those of the last `keep` requests to conclude.
+    pub fn new(keep: usize) -> Self {
+        TaskLogs { keep, ..Default::default() }
+    }
+
+    /// Record that `event` happened to `task`, starting its log if it has none yet.
+    pub fn record(&mut self, task: &ClientTask, event: TaskLogEvent) {
+        self.active
+            .entry(task.request_id)
+            .or_insert_with(|| TaskLog::new(task.clone()))
+            .push(event);
+    }
+
+    /// Record that `task` concluded, as `event` tells, after which its log is kept among
+    /// the last few, dropping the oldest.
+    pub fn conclude(&mut self, task: &ClientTask, event: TaskLogEvent) {
+        self.record(task, event);
+        if let Some(log) = self.active.remove(&task.request_id) {
+            if self.concluded.len() == self.keep {
+                self.concluded.pop_front();
+            }
+            self.concluded.push_back(log);
+        }
+    }
+
+    /// The log of the request `request_id`, if it is pending, running, or concluded recently.
+    pub fn get(&self, request_id: Uuid) -> Option<&TaskLog> {
+        self.active
+            .get(&request_id)
+            .or_else(|| self.concluded.iter().rev().find(|log| log.task.request_id == request_id))
+    }
+}
+
+/// Formats the log as the request, followed by a line for each entry, e.g.
+///
+/// ```text
+/// request 0c5a...: proc-file 0 in out nop
+/// +0.000s queued in default with priority 0, behind 0 request(s)
+/// +0.001s started as task #3: nop (bin/nop)
+/// +0.012s stage 0 (nop): 0.000s-0.010s (0.010s)
+/// +0.012s finished
+/// ```
+impl Display for TaskLog {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "request {}: {}", self.task.request_id, ProcFile(&self.task))?;
+        for TaskLogEntry { at, event } in &self.entries {
+            write!(f, "\n+{:.3}s {event}", at.as_secs_f64())?;
+        }
+        Ok(())
+    }
+}
+
+impl Display for TaskLogEvent {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Queued { queue, priority, position } =>
+                write!(f, "queued in {queue} with priority {priority}, behind {position} request(s)"),
+            Self::RanAhead { position } =>
+                write!(f, "scheduled ahead of {position} pending request(s) that could not run yet"),
+            Self::Started { task_number, commands } => {
+                let commands = commands
+                    .iter()
+                    .map(|(filter, command)| format!("{filter} ({command})"))
+                    .collect::<Vec<_>>();
+                write!(f, "started as task #{task_number}: {}", commands.join(" | "))
+            },
+            Self::Dispatched { task_number, worker } => write!(f, "started as task #{task_number} on worker {worker}"),
+            Self::Requeued { worker, reason } => write!(f, "requeued, as worker {worker} {reason}"),
+            Self::Stage { stage, timing } => write!(f, "stage {stage} ({}): {timing}", timing.filter),
+            Self::Stderr(stderr) => write!(f, "filter stderr:\n{stderr}"),
+            Self::CancelRequested { by_pid } => write!(f, "cancellation requested by client {by_pid}"),
+            Self::TimedOut(timeout) => write!(f, "cancelled after running for longer than {:.1}s", timeout.as_secs_f64()),
+            Self::Finished => write!(f, "finished"),
+            Self::Failed(failure) => write!(f, "failed: {failure}"),
+            Self::Suspended => write!(f, "suspended by the server shutting down"),
+            Self::SuspendedByClient { by_pid } => write!(f, "suspended by client {by_pid}"),
+            Self::ResumedByClient { by_pid } => write!(f, "resumed by client {by_pid}"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+
+    #[test]
+    fn task_logs_are_formatted() {
+        let task = ClientTask::new(7, 1, PathBuf::from("in"), PathBuf::from("out"), vec![Filter::Nop, Filter::Gcompress]);
+        let mut log = TaskLog::new(task);
+        log.push(TaskLogEvent::Queued { queue: String::from("default"), priority: 1, position: 0 });
+        log.push(TaskLogEvent::Started {
+            task_number: 3,
+            commands: vec![(Filter::Nop, String::from("bin/nop")), (Filter::Gcompress, String::from("builtin"))],
+        });
+        let timing = StageTiming { filter: Filter::Nop, start: Duration::ZERO, end: Duration::from_millis(10) };
+        log.push(TaskLogEvent::Stage { stage: 0, timing });
+        log.push(TaskLogEvent::Finished);
+
+        let lines = log.to_string();
+        let lines = lines.lines().collect::<Vec<_>>();
+        assert_eq!(lines[0], format!("request {}: proc-file 1 in out nop gcompress", Uuid::nil()));
+        assert_eq!(lines[1], "+0.000s queued in default with priority 1, behind 0 request(s)");
+        assert_eq!(lines[2], "+0.000s started as task #3: nop (bin/nop) | gcompress (builtin)");
+        assert_eq!(lines[3], "+0.000s stage 0 (nop): 0.000s-0.010s (0.010s)");
+        assert_eq!(lines[4], "+0.000s finished");
+    }
+
+    #[test]
+    fn concluded_logs_are_kept_as_configured() {
+        let task = |n: u128| {
+            let mut task = ClientTask::new(7, 0, PathBuf::from("in"), PathBuf::from("out"), vec![Filter::Nop]);
+            task.request_id = Uuid::from_u128(n);
+            task
+        };
+        let mut logs = TaskLogs::new(2);
+        for n in 0..3 {
+            logs.record(&task(n), TaskLogEvent::CancelRequested { by_pid: 1 });
+            logs.conclude(&task(n), TaskLogEvent::Failed(RequestFailure::Cancelled));
+        }
+        logs.record(&task(3), TaskLogEvent::Suspended);
+
+        assert!(logs.get(Uuid::from_u128(0)).is_none());
+        assert_eq!(logs.get(Uuid::from_u128(1)).map(|log| log.entries.len()), Some(2));
+        assert_eq!(logs.get(Uuid::from_u128(3)).map(|log| log.entries.len()), Some(1));
+    }
+}
@@ -0,0 +1,48 @@
+//! Fixtures shared by the tests of the whole crate, see [`TempDir`].
+
+use std::{
+    env, fs, ops::Deref,
+    path::{Path, PathBuf},
+    process,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+/// Numbers the directories of a test run, as its tests run at once.
+static NEXT_DIR: AtomicUsize = AtomicUsize::new(0);
+
+/// A directory of its own for a test's files, removed with everything in it once dropped,
+/// whether the test passed or panicked.
+///
+/// It stands for its path, so that the files in it are at `dir.join(name)`.
+#[derive(Debug)]
+pub struct TempDir(PathBuf);
+
+impl TempDir {
+    /// Create a directory named after the test `name`, and this test run.
+    pub fn new(name: &str) -> Self {
+        let dir = NEXT_DIR.fetch_add(1, Ordering::Relaxed);
+        let path = env::temp_dir().join(format!("sdstore_{name}_test_{}_{dir}", process::id()));
+        fs::create_dir_all(&path).unwrap();
+        TempDir(path)
+    }
+}
+
+impl Deref for TempDir {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl AsRef<Path> for TempDir {
+    fn as_ref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
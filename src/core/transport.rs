@@ -0,0 +1,813 @@
+use std::{
+    collections::HashMap, env, ffi::OsStr, io::{self, Write}, mem,
+    os::{
+        linux::net::SocketAddrExt,
+        unix::{ffi::{OsStrExt, OsStringExt}, io::{AsRawFd, RawFd}, net::{SocketAddr, UnixDatagram, UnixListener, UnixStream}},
+    },
+    future, path::{Path, PathBuf}, ptr, str::FromStr, sync::{Arc, Mutex, MutexGuard, PoisonError},
+    task::{ready, Context, Poll}, thread, time::Duration,
+};
+
+use serde::{Serialize, Deserialize};
+use tokio::io::unix::AsyncFd;
+
+use super::framing;
+
+/// Environment variable choosing the [`TransportMode`] the server and its clients use, which
+/// must agree on it.
+pub const TRANSPORT_MODE_VAR: &str = "SDSTORE_TRANSPORT";
+
+/// Environment variable choosing the [`SocketNamespace`] the server's and its clients'
+/// sockets are bound in, which must agree on it.
+pub const SOCKET_NAMESPACE_VAR: &str = "SDSTORE_SOCK_NAMESPACE";
+
+/// Where the server's and its clients' sockets are bound, chosen at run time with
+/// [`SOCKET_NAMESPACE_VAR`]: `filesystem`, the default, or `abstract`.
+///
+/// Sockets are named after their path in the socket directory, see [`socket_dir`](super::paths::socket_dir), in
+/// either namespace, so that servers with different socket directories don't clash.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SocketNamespace {
+    /// Each socket is a file, which is removed once the socket is done with, or left
+    /// behind, should its process be killed.
+    #[default]
+    Filesystem,
+    /// Each socket is named in Linux's abstract namespace, with no file to it: the name is
+    /// released as soon as the socket is closed, however its process exits.
+    Abstract,
+}
+
+/// Error for an unrecognized socket namespace name.
+#[derive(Debug, PartialEq, Eq)]
+pub struct SocketNamespaceParseError(pub String);
+
+impl FromStr for SocketNamespace {
+    type Err = SocketNamespaceParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "filesystem" => Ok(SocketNamespace::Filesystem),
+            "abstract"   => Ok(SocketNamespace::Abstract),
+            s            => Err(SocketNamespaceParseError(s.to_string())),
+        }
+    }
+}
+
+impl SocketNamespace {
+    /// The socket namespace set by [`SOCKET_NAMESPACE_VAR`], or the default one if it isn't set.
+    pub fn from_env() -> Result<Self, SocketNamespaceParseError> {
+        match env::var(SOCKET_NAMESPACE_VAR) {
+            Err(_) => Ok(SocketNamespace::default()),
+            Ok(name) => name.parse(),
+        }
+    }
+
+    /// Whether sockets are files, which must be removed once done with.
+    pub fn has_files(self) -> bool {
+        self == SocketNamespace::Filesystem
+    }
+
+    /// The peer to send datagrams to the socket named after `path` with.
+    pub fn peer(self, path: PathBuf) -> Peer {
+        match self {
+            SocketNamespace::Filesystem => Peer::Path(path),
+            SocketNamespace::Abstract => Peer::Abstract(path.into_os_string().into_vec()),
+        }
+    }
+
+    /// Address of the socket named after `path`, which fails if the name is too long.
+    pub fn address(self, path: &Path) -> io::Result<SocketAddr> {
+        match self {
+            SocketNamespace::Filesystem => SocketAddr::from_pathname(path),
+            SocketNamespace::Abstract => SocketAddr::from_abstract_name(path.as_os_str().as_bytes()),
+        }
+    }
+
+    /// Bind a datagram socket named after `path`.
+    pub fn bind_datagram(self, path: &Path) -> io::Result<UnixDatagram> {
+        UnixDatagram::bind_addr(&self.address(path)?)
+    }
+
+    /// Bind a socket named after `path`, listening for connections.
+    pub fn bind_listener(self, path: &Path) -> io::Result<UnixListener> {
+        UnixListener::bind_addr(&self.address(path)?)
+    }
+
+    /// Connect to the listening socket named after `path`.
+    pub fn connect(self, path: &Path) -> io::Result<UnixStream> {
+        UnixStream::connect_addr(&self.address(path)?)
+    }
+}
+
+/// Whether `err`, from sending to or connecting to the server's socket, means that socket
+/// isn't available, e.g. as the server is restarting, so that trying again later may succeed.
+pub fn server_unavailable(err: &io::Error) -> bool {
+    matches!(err.kind(), io::ErrorKind::NotFound | io::ErrorKind::ConnectionRefused)
+}
+
+/// Let the server whose socket file is `server` reply to a client's datagram socket file
+/// `socket`, if the server runs as another user than the client, as one does after dropping
+/// root privileges, see `sdstored --user`: the socket is then made writable by every user,
+/// which only lets them send to it.
+///
+/// Sockets bound in the abstract namespace, and those of a server that isn't running, are
+/// left alone.
+pub fn allow_server_replies(socket: &Path, server: &Path) -> io::Result<()> {
+    use std::os::unix::fs::{MetadataExt, PermissionsExt};
+
+    let owner = match std::fs::metadata(server) {
+        Ok(metadata) => metadata.uid(),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(err) => return Err(err),
+    };
+    // SAFETY: `geteuid` always succeeds.
+    if owner == 0 || owner == unsafe { libc::geteuid() } {
+        return Ok(())
+    }
+    std::fs::set_permissions(socket, std::fs::Permissions::from_mode(0o622))
+}
+
+/// How a client retries reaching a server whose socket is unavailable, see
+/// [`server_unavailable`]: up to `retries` more times, waiting `delay` before the first
+/// retry, and twice as long as the last before each later one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Backoff {
+    pub retries: u32,
+    pub delay: Duration,
+}
+
+impl Backoff {
+    /// Make `attempt`, making it again while it fails as the server is unavailable, as many
+    /// times as allowed, and returning its last result.
+    pub fn retry<T>(&self, mut attempt: impl FnMut() -> io::Result<T>) -> io::Result<T> {
+        let mut delay = self.delay;
+        for retry in 1..=self.retries {
+            match attempt() {
+                Err(err) if server_unavailable(&err) => {
+                    log::warn!("server unavailable ({err}), retry {retry}/{} in {:?}", self.retries, delay);
+                    thread::sleep(delay);
+                    delay = delay.saturating_mul(2);
+                },
+                result => return result,
+            }
+        }
+        attempt()
+    }
+}
+
+/// Name of the socket, in the server's socket directory, on which it accepts connections
+/// from clients, in [`TransportMode::Stream`].
+pub const CONNECTION_SOCKET: &str = "sdstored_conn.sock";
+
+/// How the server and its clients are connected, chosen at run time with
+/// [`TRANSPORT_MODE_VAR`]: `datagram`, the default, or `stream`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum TransportMode {
+    /// The server and each client bind a datagram socket of their own, the client's being
+    /// named after its PID, so that the server can reply to it.
+    #[default]
+    Datagram,
+    /// Each client connects to the server's [`CONNECTION_SOCKET`], and keeps the connection
+    /// open for as long as its request lasts, the server replying over it, see
+    /// [`ConnectionListener`].
+    Stream,
+}
+
+/// Error for an unrecognized transport mode name.
+#[derive(Debug, PartialEq, Eq)]
+pub struct TransportModeParseError(pub String);
+
+impl FromStr for TransportMode {
+    type Err = TransportModeParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "datagram" => Ok(TransportMode::Datagram),
+            "stream"   => Ok(TransportMode::Stream),
+            s          => Err(TransportModeParseError(s.to_string())),
+        }
+    }
+}
+
+impl TransportMode {
+    /// The transport mode set by [`TRANSPORT_MODE_VAR`], or the default one if it isn't set.
+    pub fn from_env() -> Result<Self, TransportModeParseError> {
+        match env::var(TRANSPORT_MODE_VAR) {
+            Err(_) => Ok(TransportMode::default()),
+            Ok(name) => name.parse(),
+        }
+    }
+}
+
+/// Identity of the other end of a [`Transport`]: where a datagram came from, or where to
+/// send one.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Peer {
+    /// A socket bound to this path.
+    Path(PathBuf),
+    /// A socket bound to this name in the abstract namespace, see [`SocketNamespace::Abstract`].
+    Abstract(Vec<u8>),
+    /// A socket bound to no path, which can't be sent to.
+    Unnamed,
+    /// A connection accepted by a [`ConnectionListener`], numbered in the order they were
+    /// accepted.
+    Connection(u64),
+}
+
+/// Credentials of the process on the other end of a socket, as vouched for by the kernel,
+/// unlike the PID a client claims in its requests.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Credentials {
+    pub pid: u32,
+    pub uid: u32,
+    pub gid: u32,
+}
+
+impl From<libc::ucred> for Credentials {
+    fn from(ucred: libc::ucred) -> Self {
+        Credentials { pid: ucred.pid as u32, uid: ucred.uid, gid: ucred.gid }
+    }
+}
+
+/// Credentials of the process that connected `stream`, or that `stream` connected to, as
+/// they were when it connected, with `SO_PEERCRED`.
+pub fn peer_credentials(stream: &UnixStream) -> io::Result<Credentials> {
+    let mut ucred = libc::ucred { pid: 0, uid: 0, gid: 0 };
+    let mut len = mem::size_of::<libc::ucred>() as libc::socklen_t;
+    // SAFETY: `ucred` and `len` are valid for writes, and `len` is the size of `ucred`.
+    let res = unsafe {
+        libc::getsockopt(
+            stream.as_raw_fd(), libc::SOL_SOCKET, libc::SO_PEERCRED, ptr::addr_of_mut!(ucred).cast(), &mut len
+        )
+    };
+    match res {
+        0 => Ok(ucred.into()),
+        _ => Err(io::Error::last_os_error()),
+    }
+}
+
+/// Have the kernel attach the credentials of their sender to the datagrams received by
+/// `socket`, with `SO_PASSCRED`, so that its [`Transport::recv_from`] returns them.
+pub fn pass_credentials(socket: &UnixDatagram) -> io::Result<()> {
+    let enable: libc::c_int = 1;
+    // SAFETY: `enable` is valid for reads, and its size is given.
+    let res = unsafe {
+        libc::setsockopt(
+            socket.as_raw_fd(), libc::SOL_SOCKET, libc::SO_PASSCRED, ptr::addr_of!(enable).cast(),
+            mem::size_of::<libc::c_int>() as libc::socklen_t
+        )
+    };
+    match res {
+        0 => Ok(()),
+        _ => Err(io::Error::last_os_error()),
+    }
+}
+
+/// Ask the kernel for a receive buffer of `size` bytes for `socket`, with `SO_RCVBUF`, so that
+/// bursts of datagrams wait in it rather than being refused, or their senders blocked, while
+/// they aren't read. Returns the size the buffer was given, which the kernel caps at
+/// `net.core.rmem_max`, and doubles for its own bookkeeping.
+///
+/// The connections accepted by a listening stream socket are given its buffer size.
+pub fn set_recv_buffer(socket: &impl AsRawFd, size: usize) -> io::Result<usize> {
+    let size = libc::c_int::try_from(size).unwrap_or(libc::c_int::MAX);
+    let int_len = mem::size_of::<libc::c_int>() as libc::socklen_t;
+    // SAFETY: `size` is valid for reads, and its size is given.
+    let res = unsafe {
+        libc::setsockopt(socket.as_raw_fd(), libc::SOL_SOCKET, libc::SO_RCVBUF, ptr::addr_of!(size).cast(), int_len)
+    };
+    if res != 0 {
+        return Err(io::Error::last_os_error())
+    }
+
+    let (mut given, mut len): (libc::c_int, _) = (0, int_len);
+    // SAFETY: `given` and `len` are valid for writes, and `len` is the size of `given`.
+    let res = unsafe {
+        libc::getsockopt(socket.as_raw_fd(), libc::SOL_SOCKET, libc::SO_RCVBUF, ptr::addr_of_mut!(given).cast(), &mut len)
+    };
+    match res {
+        0 => Ok(given as usize),
+        _ => Err(io::Error::last_os_error()),
+    }
+}
+
+/// Whether `err`, from reading or writing a socket, only means the call should be made again:
+/// it was interrupted by a signal, or, the socket being non-blocking, it had nothing to read
+/// or no room to write. Clients' reads also fail with [`io::ErrorKind::WouldBlock`] once they
+/// time out, see [`Transport::set_read_timeout`], which isn't transient to them.
+pub fn is_transient(err: &io::Error) -> bool {
+    matches!(err.kind(), io::ErrorKind::Interrupted | io::ErrorKind::WouldBlock)
+}
+
+/// How the server and its clients exchange datagrams, each carrying part of a message, see
+/// [`send_message`](super::messaging::send_message).
+///
+/// The datagrams sent by a peer must arrive in the order they were sent, if they arrive at
+/// all: messages are numbered and sent again should they be lost, see
+/// [`Sequenced`](super::messaging::Sequenced).
+pub trait Transport: Send + Sync {
+    /// Send `datagram` to `peer`.
+    fn send_to(&self, datagram: &[u8], peer: &Peer) -> io::Result<()>;
+
+    /// Wait for a datagram, and read it into `buf`, returning its length, who sent it, and
+    /// their credentials, if the transport knows of them. A datagram longer than `buf` is
+    /// truncated to fit it, and its whole length returned, so that truncation can be told.
+    fn recv_from(&self, buf: &mut [u8]) -> io::Result<(usize, Peer, Option<Credentials>)>;
+
+    /// Make [`Transport::recv_from`] fail with [`io::ErrorKind::WouldBlock`] or
+    /// [`io::ErrorKind::TimedOut`] once it waited for `timeout`, or never if `None`.
+    ///
+    /// Only clients' transports support timeouts.
+    fn set_read_timeout(&self, _timeout: Option<Duration>) -> io::Result<()> {
+        Err(io::Error::new(io::ErrorKind::Unsupported, "the transport can't time out reads"))
+    }
+}
+
+impl Transport for UnixDatagram {
+    fn send_to(&self, datagram: &[u8], peer: &Peer) -> io::Result<()> {
+        match peer {
+            Peer::Path(path) => UnixDatagram::send_to(self, datagram, path).map(|_| ()),
+            Peer::Abstract(name) => {
+                UnixDatagram::send_to_addr(self, datagram, &SocketAddr::from_abstract_name(name)?).map(|_| ())
+            },
+            Peer::Unnamed => Err(io::Error::new(io::ErrorKind::InvalidInput, "the peer has no address")),
+            Peer::Connection(_) => Err(io::Error::new(io::ErrorKind::NotConnected, "the peer has no socket")),
+        }
+    }
+
+    /// The sender's credentials are only known if the socket was set to receive them, see
+    /// [`pass_credentials`].
+    fn recv_from(&self, buf: &mut [u8]) -> io::Result<(usize, Peer, Option<Credentials>)> {
+        recv_with_credentials(self.as_raw_fd(), buf, 0)
+    }
+
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        UnixDatagram::set_read_timeout(self, timeout)
+    }
+}
+
+/// Receive a datagram from the socket `fd` into `buf`, alongside its sender, and the
+/// credentials attached to it, if any, with `recvmsg`, as the standard library can't yet,
+/// passing it `flags`.
+///
+/// With `MSG_TRUNC`, the whole length of a datagram too long for `buf` is returned. A
+/// signal arriving while waiting doesn't make it fail: it waits again, see [`is_transient`].
+fn recv_with_credentials(fd: RawFd, buf: &mut [u8], flags: libc::c_int) -> io::Result<(usize, Peer, Option<Credentials>)> {
+    // SAFETY: all zeroes is a valid `sockaddr_un`, and `msghdr`.
+    let mut address: libc::sockaddr_un = unsafe { mem::zeroed() };
+    let mut iov = libc::iovec { iov_base: buf.as_mut_ptr().cast(), iov_len: buf.len() };
+    // `u64`s, for the alignment of the `cmsghdr`s written to it.
+    let mut control = [0u64; 8];
+    let mut msg: libc::msghdr = unsafe { mem::zeroed() };
+    msg.msg_name = ptr::addr_of_mut!(address).cast();
+    msg.msg_namelen = mem::size_of::<libc::sockaddr_un>() as libc::socklen_t;
+    msg.msg_iov = &mut iov;
+    msg.msg_iovlen = 1;
+    msg.msg_control = control.as_mut_ptr().cast();
+    msg.msg_controllen = mem::size_of_val(&control) as _;
+
+    let n = loop {
+        // SAFETY: every buffer `msg` points to is valid for writes of the length given for it.
+        match unsafe { libc::recvmsg(fd, &mut msg, libc::MSG_TRUNC | flags) } {
+            n if n >= 0 => break n as usize,
+            _ => match io::Error::last_os_error() {
+                err if err.kind() == io::ErrorKind::Interrupted => continue,
+                err => return Err(err),
+            },
+        }
+    };
+
+    let mut credentials = None;
+    // SAFETY: the kernel wrote `msg_controllen` bytes of `cmsghdr`s to `control`, and a
+    // `SCM_CREDENTIALS` one carries a `ucred`.
+    unsafe {
+        let mut cmsg = libc::CMSG_FIRSTHDR(&msg);
+        while !cmsg.is_null() {
+            if (*cmsg).cmsg_level == libc::SOL_SOCKET && (*cmsg).cmsg_type == libc::SCM_CREDENTIALS {
+                let ucred = ptr::read_unaligned(libc::CMSG_DATA(cmsg).cast::<libc::ucred>());
+                credentials = Some(ucred.into());
+            }
+            cmsg = libc::CMSG_NXTHDR(&msg, cmsg);
+        }
+    }
+
+    // The path is what follows the address family, up to the length of the address, or
+    // its first NUL. Unbound sockets have none, and abstract names follow a NUL instead,
+    // up to the length of the address, as they may hold NULs themselves.
+    let path_len = (msg.msg_namelen as usize).saturating_sub(mem::size_of::<libc::sa_family_t>());
+    let path = address.sun_path[..path_len.min(address.sun_path.len())]
+        .iter()
+        .map(|&c| c as u8)
+        .collect::<Vec<_>>();
+    let peer = match path.split_first() {
+        None => Peer::Unnamed,
+        Some((0, name)) => Peer::Abstract(name.to_vec()),
+        Some(_) => {
+            let path = path.split(|&c| c == 0).next().unwrap_or_default();
+            Peer::Path(PathBuf::from(OsStr::from_bytes(path)))
+        },
+    };
+    Ok((n, peer, credentials))
+}
+
+/// The peer bound to `address`, as [`Transport::recv_from`] tells it.
+fn peer_at(address: &SocketAddr) -> Peer {
+    match (address.as_pathname(), address.as_abstract_name()) {
+        (Some(path), _) => Peer::Path(path.to_path_buf()),
+        (None, Some(name)) => Peer::Abstract(name.to_vec()),
+        (None, None) => Peer::Unnamed,
+    }
+}
+
+/// A client's end of its connection to the server, in [`TransportMode::Stream`]. Its only
+/// peer is the server, whatever peer datagrams are sent to.
+///
+/// Each datagram is sent as a frame, see [`framing::write_frame`].
+impl Transport for UnixStream {
+    fn send_to(&self, datagram: &[u8], _: &Peer) -> io::Result<()> {
+        let mut stream = self;
+        framing::write_frame(&mut stream, datagram)?;
+        stream.flush()
+    }
+
+    fn recv_from(&self, buf: &mut [u8]) -> io::Result<(usize, Peer, Option<Credentials>)> {
+        let datagram = framing::read_frame(self)?;
+        let n = datagram.len().min(buf.len());
+        buf[..n].copy_from_slice(&datagram[..n]);
+        let peer = peer_at(&self.peer_addr()?);
+        Ok((datagram.len(), peer, peer_credentials(self).ok()))
+    }
+
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        UnixStream::set_read_timeout(self, timeout)
+    }
+}
+
+/// A datagram read from a connection, alongside the connection, and its client's credentials.
+type Received = (Vec<u8>, Peer, Option<Credentials>);
+
+/// The server's end of the connections of its clients, in [`TransportMode::Stream`].
+///
+/// Connections are accepted on the listener, and their datagrams, sent as frames, read until
+/// the client closes them, by the server's event loop, see [`Incoming::recv_from`], rather
+/// than by threads of their own. Datagrams are sent back over the connection they came from,
+/// see [`Peer::Connection`], and received with the credentials of the client that connected,
+/// see [`peer_credentials`].
+pub struct ConnectionListener {
+    /// Accepted from as the runtime's reactor finds it readable, in non-blocking mode.
+    listener: AsyncFd<UnixListener>,
+    connections: Mutex<Connections>,
+}
+
+/// Connections accepted by a [`ConnectionListener`].
+#[derive(Default)]
+struct Connections {
+    /// How many were accepted, numbering the next one.
+    accepted: u64,
+    /// Those still open, by number.
+    open: HashMap<u64, Connection>,
+}
+
+/// A connection accepted by a [`ConnectionListener`].
+struct Connection {
+    /// Read from as the runtime's reactor finds it readable, but written to in blocking mode.
+    stream: AsyncFd<Arc<UnixStream>>,
+    credentials: Option<Credentials>,
+    /// Bytes read that don't make a whole frame yet.
+    unread: Vec<u8>,
+}
+
+impl ConnectionListener {
+    /// Start accepting connections on `listener`. Must be called from within an async
+    /// runtime, which the listener, and its connections, are registered with.
+    pub fn new(listener: UnixListener) -> io::Result<Self> {
+        listener.set_nonblocking(true)?;
+        let listener = AsyncFd::new(listener)?;
+        Ok(ConnectionListener { listener, connections: Mutex::new(Connections::default()) })
+    }
+
+    /// Poll for the next datagram read from any connection, having accepted the pending ones.
+    /// Connections closed, or which can't be read from, are dropped.
+    fn poll_recv(&self, cx: &mut Context<'_>) -> Poll<io::Result<Received>> {
+        let mut connections = lock(&self.connections);
+        while let Poll::Ready(ready) = self.listener.poll_read_ready(cx) {
+            // Readiness is cleared once there are no more connections to accept.
+            match ready?.try_io(|listener| listener.get_ref().accept()) {
+                Err(_would_block) => {},
+                Ok(Ok((stream, _))) => connections.add(stream),
+                Ok(Err(err)) => {
+                    log::warn!("could not accept connection: {:?}", err);
+                    break
+                },
+            }
+        }
+
+        let (mut received, mut closed) = (None, Vec::new());
+        for (&id, connection) in connections.open.iter_mut() {
+            match connection.poll_frame(cx) {
+                Poll::Pending => {},
+                Poll::Ready(Ok(datagram)) => {
+                    received = Some((datagram, Peer::Connection(id), connection.credentials));
+                    break
+                },
+                Poll::Ready(Err(err)) => {
+                    if err.kind() != io::ErrorKind::UnexpectedEof {
+                        log::warn!("could not read from connection #{id}: {:?}", err);
+                    }
+                    closed.push(id);
+                },
+            }
+        }
+        for id in closed {
+            connections.open.remove(&id);
+        }
+        received.map_or(Poll::Pending, |received| Poll::Ready(Ok(received)))
+    }
+}
+
+impl Connections {
+    /// Number `stream`, and register it with the runtime, alongside its client's credentials.
+    fn add(&mut self, stream: UnixStream) {
+        let id = self.accepted;
+        self.accepted += 1;
+        let credentials = match peer_credentials(&stream) {
+            Err(err) => {
+                log::warn!("could not get the credentials of connection #{id}: {:?}", err);
+                None
+            },
+            Ok(credentials) => Some(credentials),
+        };
+        match AsyncFd::new(Arc::new(stream)) {
+            Err(err) => log::warn!("could not register connection #{id}: {:?}", err),
+            Ok(stream) => {
+                self.open.insert(id, Connection { stream, credentials, unread: Vec::new() });
+            },
+        }
+    }
+}
+
+impl Connection {
+    /// Poll for the next frame read from the connection. Its client closing it is an
+    /// [`io::ErrorKind::UnexpectedEof`] error.
+    fn poll_frame(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<Vec<u8>>> {
+        let mut buf = [0; 8 * 1024];
+        loop {
+            if let Some(frame) = framing::take_frame(&mut self.unread)? {
+                return Poll::Ready(Ok(frame))
+            }
+            let mut ready = ready!(self.stream.poll_read_ready(cx))?;
+            match ready.try_io(|stream| recv_available(stream.get_ref(), &mut buf)) {
+                Err(_would_block) => {},
+                Ok(Ok(0)) => return Poll::Ready(Err(io::ErrorKind::UnexpectedEof.into())),
+                Ok(Ok(n)) => self.unread.extend_from_slice(&buf[..n]),
+                Ok(Err(err)) if err.kind() == io::ErrorKind::Interrupted => {},
+                Ok(Err(err)) => return Poll::Ready(Err(err)),
+            }
+        }
+    }
+}
+
+/// Read what `stream` has available into `buf`, without waiting for more, whether or not
+/// it's in non-blocking mode.
+fn recv_available(stream: &UnixStream, buf: &mut [u8]) -> io::Result<usize> {
+    // SAFETY: `buf` is valid for writes of its length.
+    let n = unsafe { libc::recv(stream.as_raw_fd(), buf.as_mut_ptr().cast(), buf.len(), libc::MSG_DONTWAIT) };
+    if n < 0 {
+        return Err(io::Error::last_os_error())
+    }
+    Ok(n as usize)
+}
+
+impl Transport for ConnectionListener {
+    /// Send `datagram` over the connection `peer`. Other peers aren't connected, and a
+    /// connection closed by its client is a [`io::ErrorKind::BrokenPipe`] error.
+    fn send_to(&self, datagram: &[u8], peer: &Peer) -> io::Result<()> {
+        let Peer::Connection(id) = peer else {
+            return Err(io::Error::new(io::ErrorKind::NotConnected, "the peer has no connection"))
+        };
+        let stream = lock(&self.connections)
+            .open
+            .get(id)
+            .map(|connection| Arc::clone(connection.stream.get_ref()))
+            .ok_or_else(|| io::Error::new(io::ErrorKind::BrokenPipe, "the connection was closed"))?;
+        // The lock isn't held while writing, which waits for the client to read.
+        framing::write_frame(stream.as_ref(), datagram)
+    }
+
+    /// Fails: connections are only read from by the server's event loop, with
+    /// [`Incoming::recv_from`].
+    fn recv_from(&self, _: &mut [u8]) -> io::Result<(usize, Peer, Option<Credentials>)> {
+        Err(io::Error::new(io::ErrorKind::Unsupported, "connections are read from asynchronously"))
+    }
+}
+
+/// The server's [`Transport`], as read from by its event loop, which waits for datagrams
+/// alongside its other events rather than having a thread block on the transport.
+pub enum Incoming {
+    /// A datagram socket, read from whenever the runtime's reactor finds it readable.
+    Datagram(AsyncFd<Arc<UnixDatagram>>),
+    /// Clients' connections, accepted and read from whenever the reactor finds them readable.
+    Connections(Arc<ConnectionListener>),
+}
+
+impl Incoming {
+    /// Have the event loop read from the datagram `socket`. Must be called from within an
+    /// async runtime, which the socket is registered with.
+    pub fn datagram(socket: UnixDatagram) -> io::Result<Self> {
+        Ok(Incoming::Datagram(AsyncFd::new(Arc::new(socket))?))
+    }
+
+    /// The transport replies are sent over.
+    pub fn transport(&self) -> Arc<dyn Transport> {
+        match self {
+            Incoming::Datagram(socket) => Arc::clone(socket.get_ref()) as Arc<dyn Transport>,
+            Incoming::Connections(listener) => Arc::clone(listener) as Arc<dyn Transport>,
+        }
+    }
+
+    /// Wait for a datagram, as [`Transport::recv_from`] does, without blocking the thread.
+    ///
+    /// Nothing is lost if the returned future is dropped before it completes, so that it
+    /// may be raced against the server's other events.
+    pub async fn recv_from(&self, buf: &mut [u8]) -> io::Result<(usize, Peer, Option<Credentials>)> {
+        match self {
+            Incoming::Datagram(socket) => loop {
+                let mut ready = socket.readable().await?;
+                // Readiness is cleared should the datagram have been read already.
+                if let Ok(received) = ready.try_io(|socket| recv_with_credentials(socket.as_raw_fd(), buf, libc::MSG_DONTWAIT)) {
+                    return received
+                }
+            },
+            Incoming::Connections(listener) => {
+                let (datagram, peer, credentials) = future::poll_fn(|cx| listener.poll_recv(cx)).await?;
+                let n = datagram.len().min(buf.len());
+                buf[..n].copy_from_slice(&datagram[..n]);
+                Ok((datagram.len(), peer, credentials))
+            },
+        }
+    }
+}
+
+fn lock(connections: &Mutex<Connections>) -> MutexGuard<'_, Connections> {
+    // Connections are only ever inserted and removed, which can't leave them inconsistent.
+    connections.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc::{self, Receiver, Sender};
+
+    use crate::core::{messaging::{send_message, MessageReceiver, MAX_DATAGRAM_PAYLOAD}, testing::TempDir};
+
+    use super::*;
+
+    /// In-process transport, delivering datagrams over a channel.
+    struct ChannelTransport {
+        name: Peer,
+        peer: Sender<(Vec<u8>, Peer)>,
+        inbox: Mutex<Receiver<(Vec<u8>, Peer)>>,
+    }
+
+    impl Transport for ChannelTransport {
+        fn send_to(&self, datagram: &[u8], _: &Peer) -> io::Result<()> {
+            self.peer
+                .send((datagram.to_vec(), self.name.clone()))
+                .map_err(|_| io::Error::from(io::ErrorKind::BrokenPipe))
+        }
+
+        fn recv_from(&self, buf: &mut [u8]) -> io::Result<(usize, Peer, Option<Credentials>)> {
+            let (datagram, sender) = self.inbox.lock().unwrap().recv().map_err(|_| io::Error::from(io::ErrorKind::BrokenPipe))?;
+            let n = datagram.len().min(buf.len());
+            buf[..n].copy_from_slice(&datagram[..n]);
+            Ok((n, sender, None))
+        }
+    }
+
+    #[test]
+    fn messages_round_trip_over_any_transport() {
+        let ((to_a, a_inbox), (to_b, b_inbox)) = (mpsc::channel(), mpsc::channel());
+        let a = ChannelTransport { name: Peer::Path(PathBuf::from("a")), peer: to_b, inbox: Mutex::new(a_inbox) };
+        let b = ChannelTransport { name: Peer::Path(PathBuf::from("b")), peer: to_a, inbox: Mutex::new(b_inbox) };
+
+        let message = (0..2 * MAX_DATAGRAM_PAYLOAD + 1).map(|i| i as u8).collect::<Vec<_>>();
+        send_message(&a, &message, &b.name).unwrap();
+        assert_eq!(MessageReceiver::default().recv(&b).unwrap(), message);
+
+        send_message(&b, b"reply", &a.name).unwrap();
+        assert_eq!(MessageReceiver::default().recv(&a).unwrap(), b"reply");
+    }
+
+    #[tokio::test]
+    async fn connections_are_replied_to() {
+        let dir = TempDir::new("transport");
+        let path = dir.join(CONNECTION_SOCKET);
+        let server = Arc::new(ConnectionListener::new(UnixListener::bind(&path).unwrap()).unwrap());
+        let incoming = Incoming::Connections(Arc::clone(&server));
+        let clients = [UnixStream::connect(&path).unwrap(), UnixStream::connect(&path).unwrap()];
+
+        let server_peer = Peer::Path(path.clone());
+        let long = vec![7; MAX_DATAGRAM_PAYLOAD + 1];
+        send_message(&clients[1], b"second", &server_peer).unwrap();
+        send_message(&clients[0], &long, &server_peer).unwrap();
+
+        let mut messages = MessageReceiver::default();
+        let mut received = [
+            messages.recv_incoming(&incoming).await.unwrap(),
+            messages.recv_incoming(&incoming).await.unwrap(),
+        ];
+        received.sort_by_key(|(message, ..)| message.len());
+        let [(second, second_peer, second_credentials), (first, first_peer, _)] = received;
+        assert_eq!((second.as_slice(), first), (b"second".as_slice(), long));
+        assert_eq!(second_credentials, Some(own_credentials()));
+
+        send_message(server.as_ref(), b"to first", &first_peer).unwrap();
+        send_message(server.as_ref(), b"to second", &second_peer).unwrap();
+        assert_eq!(MessageReceiver::default().recv(&clients[0]).unwrap(), b"to first");
+        assert_eq!(MessageReceiver::default().recv(&clients[1]).unwrap(), b"to second");
+
+        assert_eq!(server.send_to(b"", &server_peer).unwrap_err().kind(), io::ErrorKind::NotConnected);
+    }
+
+    fn own_credentials() -> Credentials {
+        // SAFETY: `getuid` and `getgid` always succeed.
+        let (uid, gid) = unsafe { (libc::getuid(), libc::getgid()) };
+        Credentials { pid: std::process::id(), uid, gid }
+    }
+
+    #[test]
+    fn datagrams_carry_credentials() {
+        let dir = TempDir::new("credentials");
+        let (receiver, sender) = (dir.join("receiver.sock"), dir.join("sender.sock"));
+        let receiver = UnixDatagram::bind(&receiver).unwrap();
+        let sender_socket = UnixDatagram::bind(&sender).unwrap();
+        let unnamed = UnixDatagram::unbound().unwrap();
+
+        let mut buf = [0; 8];
+        Transport::send_to(&sender_socket, b"plain", &Peer::Path(dir.join("receiver.sock"))).unwrap();
+        assert_eq!(Transport::recv_from(&receiver, &mut buf).unwrap(), (5, Peer::Path(sender.clone()), None));
+
+        pass_credentials(&receiver).unwrap();
+        // The kernel doubles the size asked for, for its own bookkeeping.
+        assert!(set_recv_buffer(&receiver, 1 << 16).unwrap() >= 1 << 16);
+        Transport::send_to(&sender_socket, b"vouched", &Peer::Path(dir.join("receiver.sock"))).unwrap();
+        assert_eq!(Transport::recv_from(&receiver, &mut buf).unwrap(), (7, Peer::Path(sender), Some(own_credentials())));
+        unnamed.send_to(b"", dir.join("receiver.sock")).unwrap();
+        assert_eq!(Transport::recv_from(&receiver, &mut buf).unwrap(), (0, Peer::Unnamed, Some(own_credentials())));
+    }
+
+    #[test]
+    fn abstract_sockets_have_no_files() {
+        // The directory is never created, as no files are.
+        let dir = std::env::temp_dir().join(format!("sdstore_abstract_test_{}", std::process::id()));
+        let namespace = SocketNamespace::Abstract;
+        let (receiver_path, sender_path) = (dir.join("receiver.sock"), dir.join("sender.sock"));
+        let receiver = namespace.bind_datagram(&receiver_path).unwrap();
+        let sender = namespace.bind_datagram(&sender_path).unwrap();
+        pass_credentials(&receiver).unwrap();
+
+        let mut buf = [0; 8];
+        Transport::send_to(&sender, b"named", &namespace.peer(receiver_path.clone())).unwrap();
+        assert_eq!(
+            Transport::recv_from(&receiver, &mut buf).unwrap(),
+            (5, namespace.peer(sender_path), Some(own_credentials()))
+        );
+
+        // Names are taken until their socket is closed.
+        assert_eq!(namespace.bind_datagram(&receiver_path).unwrap_err().kind(), io::ErrorKind::AddrInUse);
+        drop(receiver);
+        namespace.bind_datagram(&receiver_path).unwrap();
+
+        let listener_path = dir.join(CONNECTION_SOCKET);
+        assert_eq!(namespace.connect(&listener_path).unwrap_err().kind(), io::ErrorKind::ConnectionRefused);
+        let _listener = namespace.bind_listener(&listener_path).unwrap();
+        namespace.connect(&listener_path).unwrap();
+        assert!(!dir.exists());
+    }
+
+    #[test]
+    fn unavailable_servers_are_retried() {
+        let dir = TempDir::new("backoff");
+        let server_path = dir.join("server.sock");
+        let backoff = Backoff { retries: 3, delay: Duration::from_millis(1) };
+
+        // The server only binds its socket once the client has tried twice.
+        let (mut attempts, mut listener) = (0, None);
+        let server = backoff.retry(|| {
+            attempts += 1;
+            if attempts == 3 {
+                listener = Some(UnixListener::bind(&server_path)?);
+            }
+            UnixStream::connect(&server_path)
+        });
+        assert!(server.is_ok() && listener.is_some());
+        assert_eq!(attempts, 3);
+
+        let mut attempts = 0;
+        let err = backoff.retry(|| {
+            attempts += 1;
+            UnixStream::connect(dir.join("missing.sock"))
+        }).unwrap_err();
+        assert!(server_unavailable(&err));
+        assert_eq!(attempts, 4);
+    }
+}
@@ -1,3 +1,5 @@
+pub mod client_api;
+
 pub mod core;
 
 pub mod util;
\ No newline at end of file
@@ -1,10 +1,219 @@
-use std::fs;
+use std::{
+    env, fmt::Display, fs::{self, File, OpenOptions}, io::{self, Write}, os::unix::net::UnixDatagram,
+    path::{Path, PathBuf}, process, str::FromStr, sync::{Mutex, PoisonError}, time::{Duration, Instant, SystemTime},
+};
 
-use log::SetLoggerError;
+use log::{
+    kv::{self, Key, Value, VisitSource, VisitValue}, Log, Metadata, Record, SetLoggerError,
+};
 use simplelog::{
-    ColorChoice, CombinedLogger, ConfigBuilder, LevelFilter, SharedLogger, TermLogger, TerminalMode,
+    ColorChoice, CombinedLogger, Config, ConfigBuilder, LevelFilter, SharedLogger, TermLogger, TerminalMode,
     WriteLogger,
 };
+use tracing_subscriber::{
+    filter::{self, FilterFn}, fmt::writer::{BoxMakeWriter, MakeWriterExt}, layer::SubscriberExt,
+    util::{SubscriberInitExt, TryInitError}, Layer,
+};
+
+/// How log messages are written, to the terminal and to the log file alike.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum LogFormat {
+    /// Lines meant to be read by people, as written by `simplelog`.
+    #[default]
+    Text,
+    /// A JSON object per line, see [`json_record`], meant to be indexed by log pipelines.
+    Json,
+}
+
+/// The name given for a [`LogFormat`] isn't `text` nor `json`.
+#[derive(Debug, PartialEq, Eq)]
+pub struct LogFormatParseError(pub String);
+
+impl FromStr for LogFormat {
+    type Err = LogFormatParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "text" => Ok(LogFormat::Text),
+            "json" => Ok(LogFormat::Json),
+            _ => Err(LogFormatParseError(s.to_string())),
+        }
+    }
+}
+
+impl Display for LogFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LogFormat::Text => write!(f, "text"),
+            LogFormat::Json => write!(f, "json"),
+        }
+    }
+}
+
+/// Where log messages are written, other than to the log file, if any.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum LogSink {
+    /// The terminal, in the [`LogFormat`] logs are written in.
+    #[default]
+    Terminal,
+    /// systemd-journald, through its native protocol, each message with its key-values as
+    /// fields of its own, see [`journald_entry`].
+    Journald,
+    /// The system's syslog daemon, as a line of text, see [`syslog_line`].
+    Syslog,
+}
+
+/// The name given for a [`LogSink`] isn't `terminal`, `journald` nor `syslog`.
+#[derive(Debug, PartialEq, Eq)]
+pub struct LogSinkParseError(pub String);
+
+impl FromStr for LogSink {
+    type Err = LogSinkParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "terminal" => Ok(LogSink::Terminal),
+            "journald" => Ok(LogSink::Journald),
+            "syslog" => Ok(LogSink::Syslog),
+            _ => Err(LogSinkParseError(s.to_string())),
+        }
+    }
+}
+
+impl Display for LogSink {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LogSink::Terminal => write!(f, "terminal"),
+            LogSink::Journald => write!(f, "journald"),
+            LogSink::Syslog => write!(f, "syslog"),
+        }
+    }
+}
+
+impl LogSink {
+    /// Socket the sink's messages are sent to, unless they are written to the terminal.
+    pub fn socket(&self) -> Option<&'static Path> {
+        match self {
+            LogSink::Terminal => None,
+            LogSink::Journald => Some(Path::new("/run/systemd/journal/socket")),
+            LogSink::Syslog => Some(Path::new("/dev/log")),
+        }
+    }
+}
+
+/// When a file written line by line is rotated, see [`RotatingFile`]. Files are never rotated
+/// by default.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Rotation {
+    /// Size, in bytes, past which the file is rotated: renamed to `<file>.1`, the file
+    /// previously there to `<file>.2`, and so on.
+    pub max_size: Option<u64>,
+    /// How long the file is written to before it is rotated.
+    pub max_age: Option<Duration>,
+    /// Number of rotated files kept, the oldest being removed. `0` if the file is removed
+    /// instead.
+    pub keep: usize,
+}
+
+impl Rotation {
+    /// Whether files are rotated at all.
+    pub fn is_enabled(&self) -> bool {
+        self.max_size.is_some() || self.max_age.is_some()
+    }
+}
+
+/// A file appended to, rotated once it is due to be, as its [`Rotation`] says. It is only
+/// rotated between lines, so that none is split across files, however it is written.
+#[derive(Debug)]
+pub struct RotatingFile {
+    path: PathBuf,
+    file: File,
+    rotation: Rotation,
+    /// Size of the file, as written so far.
+    size: u64,
+    /// When the file was opened, or last rotated.
+    opened_at: Instant,
+    /// Whether what was written so far ends with a newline.
+    at_line_start: bool,
+}
+
+impl RotatingFile {
+    /// Open the file at `path`, creating it if it doesn't exist, to append to it.
+    pub fn open(path: PathBuf, rotation: Rotation) -> io::Result<Self> {
+        let file = OpenOptions::new().append(true).create(true).open(&path)?;
+        let size = file.metadata()?.len();
+        Ok(RotatingFile { path, file, rotation, size, opened_at: Instant::now(), at_line_start: true })
+    }
+
+    /// Rotate the file if `len` more bytes, starting a line, would grow it past its maximum
+    /// size, or if it has been written to for long enough.
+    ///
+    /// If it can't be, it isn't tried again until it is due once more, the file being
+    /// written to meanwhile.
+    pub fn rotate_if_due(&mut self, len: usize) -> io::Result<()> {
+        let full = self.rotation.max_size.is_some_and(|max_size| self.size + len as u64 > max_size);
+        let old = self.rotation.max_age.is_some_and(|max_age| self.opened_at.elapsed() >= max_age);
+        if !(self.at_line_start && self.size > 0 && (full || old)) {
+            return Ok(())
+        }
+        let rotated = self.rotate();
+        self.size = 0;
+        self.opened_at = Instant::now();
+        rotated
+    }
+
+    /// Shift the rotated files along, dropping the oldest, and start a new file.
+    fn rotate(&mut self) -> io::Result<()> {
+        let rotated = |n: usize| {
+            let mut path = self.path.clone().into_os_string();
+            path.push(format!(".{n}"));
+            PathBuf::from(path)
+        };
+        match self.rotation.keep {
+            0 => fs::remove_file(&self.path)?,
+            keep => {
+                for n in (1..keep).rev() {
+                    match fs::rename(rotated(n), rotated(n + 1)) {
+                        Err(err) if err.kind() != io::ErrorKind::NotFound => return Err(err),
+                        _ => {},
+                    }
+                }
+                fs::rename(&self.path, rotated(1))?;
+            },
+        }
+
+        self.file = OpenOptions::new().append(true).create(true).open(&self.path)?;
+        Ok(())
+    }
+}
+
+impl Write for RotatingFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if let Err(err) = self.rotate_if_due(buf.len()) {
+            // This may be a logger's file, through which it can't be reported.
+            eprintln!("Could not rotate file {}! Error: {:?}", self.path.display(), err);
+        }
+        let written = self.file.write(buf)?;
+        if written > 0 {
+            self.size += written as u64;
+            self.at_line_start = buf[written - 1] == b'\n';
+        }
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+/// Open the log file at `path`, as [`init_logging_infrastructure`] and [`init_tracing`] do:
+/// appended to if it is rotated, and otherwise replaced.
+fn open_log_file(path: &str, rotation: Rotation) -> io::Result<RotatingFile> {
+    if !rotation.is_enabled() {
+        File::create(path)?;
+    }
+    RotatingFile::open(PathBuf::from(path), rotation)
+}
 
 /// Function to initialize logging infrastructure.
 ///
@@ -15,49 +224,536 @@ use simplelog::{
 ///
 /// The default logging configuration is used, which is then modified to allow
 /// source-code information on every log message, not just errors.
+///
+/// Messages are logged up to `log_level`, unless their target is in `targets`, along with
+/// the level to log it at instead, see [`target_level`], and written in `format`. The log
+/// file is rotated as `rotation` says, see [`RotatingFile`]. Messages are written to `sink`
+/// rather than to the terminal, falling back to it if the sink can't be reached.
 pub fn init_logging_infrastructure(
     opt_log_file_name : Option<&str>,
-    log_level: LevelFilter
+    log_level: LevelFilter,
+    targets: &[(String, LevelFilter)],
+    format: LogFormat,
+    rotation: Rotation,
+    sink: LogSink
     ) -> Result<(), SetLoggerError> {
+    // The loggers let through every message some target may log, see `TargetFilter`.
+    let max_level = targets.iter().map(|(_, level)| *level).fold(log_level, Ord::max);
     let config = ConfigBuilder::new()
         // This enables source-code location in logging message of any level
         .set_location_level(LevelFilter::Error)
         .build();
-    let term_logger = TermLogger::new(
-        // This is the field used to control the granularity of logs shown in the terminal.
-        log_level,
-        config.clone(),
-        TerminalMode::Mixed,
-        ColorChoice::Auto,
-    );
-
-    // Terminal logging is always used, but file_based logging will
+    let system_logger = sink.socket().and_then(|socket| match SystemLogger::connect(max_level, sink, socket) {
+        Ok(logger) => Some(logger),
+        Err(err) => {
+            eprintln!("Could not connect to {sink} at {}! Error: {:?}", socket.display(), err);
+            eprintln!("Terminal logging will be done instead.");
+            None
+        },
+    });
+    let only = match system_logger {
+        Some(_) => sink.to_string(),
+        None => String::from("Terminal"),
+    };
+    let term_logger: Box<dyn SharedLogger> = match (system_logger, format) {
+        (Some(system_logger), _) => system_logger,
+        (None, LogFormat::Text) => TermLogger::new(
+            // This is the field used to control the granularity of logs shown in the terminal.
+            max_level,
+            config.clone(),
+            TerminalMode::Mixed,
+            ColorChoice::Auto,
+        ),
+        (None, LogFormat::Json) => JsonLogger::new(max_level, io::stdout()),
+    };
+
+    // Terminal logging, or the sink's, is always used, but file_based logging will
     // depend on the log file name the program user may or may not provide.
     let mut logger_vec: Vec<Box<dyn SharedLogger>> = vec![term_logger];
 
     match opt_log_file_name {
         None => {
-            println!("No log file name provided.");
-            println!("Terminal-only logging will be done instead.");
+            eprintln!("No log file name provided.");
+            eprintln!("{only}-only logging will be done instead.");
         }
         Some(log_file_name) => {
-            let log_file = fs::File::create(log_file_name);
+            let log_file = open_log_file(log_file_name, rotation);
             match log_file {
                 Err(err) => {
                     eprintln!("Could not create logging file! Error: {:?}", err);
                     eprintln!("Terminal-only logging will be attempted.");
                 }
                 Ok(file) => {
-                    let file_logger = WriteLogger::new(
-                        LevelFilter::Info,
-                        config,
-                        file
-                    );
+                    let file_logger: Box<dyn SharedLogger> = match format {
+                        LogFormat::Text => WriteLogger::new(max_level, config, file),
+                        LogFormat::Json => JsonLogger::new(max_level, file),
+                    };
                     logger_vec.push(file_logger);
                 }
             }
         }
     };
 
-    CombinedLogger::init(logger_vec)
-}
\ No newline at end of file
+    log::set_max_level(max_level);
+    log::set_boxed_logger(Box::new(TargetFilter {
+        logger: CombinedLogger::new(logger_vec),
+        level: log_level,
+        targets: targets.to_vec(),
+    }))
+}
+
+/// Initialize logging through a `tracing` subscriber, rather than `simplelog`, as
+/// [`init_logging_infrastructure`] would otherwise, with the same arguments.
+///
+/// Messages logged with `log` are turned into `tracing` events, which are written in the
+/// spans they are logged in, such as each task's, see
+/// [`ClientTask::span`](crate::core::client_task::ClientTask::span), whichever thread logs
+/// them. Other subscribers, e.g. exporting spans over OTLP, may be layered on this one's.
+pub fn init_tracing(
+    opt_log_file_name: Option<&str>,
+    log_level: LevelFilter,
+    targets: &[(String, LevelFilter)],
+    format: LogFormat,
+    rotation: Rotation
+) -> Result<(), TryInitError> {
+    let max_level = targets.iter().map(|(_, level)| *level).fold(log_level, Ord::max);
+    let targets = targets.to_vec();
+    let filter = FilterFn::new(move |metadata| {
+        let level = match *metadata.level() {
+            tracing::Level::ERROR => log::Level::Error,
+            tracing::Level::WARN => log::Level::Warn,
+            tracing::Level::INFO => log::Level::Info,
+            tracing::Level::DEBUG => log::Level::Debug,
+            tracing::Level::TRACE => log::Level::Trace,
+        };
+        level <= target_level(metadata.target(), log_level, &targets)
+    });
+    let max_level_hint = match max_level {
+        LevelFilter::Off => filter::LevelFilter::OFF,
+        LevelFilter::Error => filter::LevelFilter::ERROR,
+        LevelFilter::Warn => filter::LevelFilter::WARN,
+        LevelFilter::Info => filter::LevelFilter::INFO,
+        LevelFilter::Debug => filter::LevelFilter::DEBUG,
+        LevelFilter::Trace => filter::LevelFilter::TRACE,
+    };
+
+    let log_file = opt_log_file_name.and_then(|log_file_name| match open_log_file(log_file_name, rotation) {
+        Ok(file) => Some(file),
+        Err(err) => {
+            eprintln!("Could not create logging file! Error: {:?}", err);
+            eprintln!("Terminal-only logging will be attempted.");
+            None
+        },
+    });
+    // Colors are only for the terminal, which would otherwise write them to the file too.
+    let ansi = log_file.is_none();
+    let writer = match log_file {
+        None => BoxMakeWriter::new(io::stdout),
+        Some(file) => BoxMakeWriter::new(io::stdout.and(Mutex::new(file))),
+    };
+
+    let layer = tracing_subscriber::fmt::layer().with_writer(writer).with_ansi(ansi);
+    let layer = match format {
+        LogFormat::Text => layer.boxed(),
+        LogFormat::Json => layer.json().with_current_span(true).with_span_list(true).boxed(),
+    };
+    tracing_subscriber::registry()
+        .with(layer.with_filter(filter.with_max_level_hint(max_level_hint)))
+        .try_init()
+}
+
+/// The level messages of `target` are logged up to: that of the most specific of `targets`
+/// it is, or is a submodule of, as in `rust_sdstore::core::server` for
+/// `rust_sdstore::core::server::state`, or else `level`. Of equally specific targets, the
+/// last one wins.
+pub fn target_level(target: &str, level: LevelFilter, targets: &[(String, LevelFilter)]) -> LevelFilter {
+    targets
+        .iter()
+        .filter(|(prefix, _)| {
+            target.strip_prefix(prefix.as_str()).is_some_and(|rest| rest.is_empty() || rest.starts_with("::"))
+        })
+        .max_by_key(|(prefix, _)| prefix.len())
+        .map_or(level, |(_, level)| *level)
+}
+
+/// Logger dropping the messages above the level of their target, see [`target_level`],
+/// before they reach the terminal and file loggers.
+struct TargetFilter {
+    logger: Box<CombinedLogger>,
+    level: LevelFilter,
+    targets: Vec<(String, LevelFilter)>,
+}
+
+impl Log for TargetFilter {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= target_level(metadata.target(), self.level, &self.targets) &&
+        self.logger.enabled(metadata)
+    }
+
+    fn log(&self, record: &Record) {
+        if self.enabled(record.metadata()) {
+            self.logger.log(record)
+        }
+    }
+
+    fn flush(&self) {
+        self.logger.flush()
+    }
+}
+
+/// Logger writing every message up to its level as a line of JSON, see [`json_record`].
+struct JsonLogger<W> {
+    level: LevelFilter,
+    writer: Mutex<W>,
+}
+
+impl<W: Write + Send + 'static> JsonLogger<W> {
+    fn new(level: LevelFilter, writer: W) -> Box<Self> {
+        Box::new(JsonLogger { level, writer: Mutex::new(writer) })
+    }
+}
+
+impl<W: Write + Send> Log for JsonLogger<W> {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= self.level
+    }
+
+    fn log(&self, record: &Record) {
+        if self.enabled(record.metadata()) {
+            // A writer can't be left in an inconsistent state by a panicking thread.
+            let mut writer = self.writer.lock().unwrap_or_else(PoisonError::into_inner);
+            // There's nowhere to report that logging failed.
+            let _ = writeln!(writer, "{}", json_record(record, SystemTime::now()));
+        }
+    }
+
+    fn flush(&self) {
+        let _ = self.writer.lock().unwrap_or_else(PoisonError::into_inner).flush();
+    }
+}
+
+impl<W: Write + Send + 'static> SharedLogger for JsonLogger<W> {
+    fn level(&self) -> LevelFilter {
+        self.level
+    }
+
+    fn config(&self) -> Option<&Config> {
+        None
+    }
+
+    fn as_log(self: Box<Self>) -> Box<dyn Log> {
+        self
+    }
+}
+
+/// Logger sending every message up to its level to the system's logger, as its [`LogSink`]
+/// says, over a datagram socket.
+struct SystemLogger {
+    level: LevelFilter,
+    sink: LogSink,
+    socket: UnixDatagram,
+    /// Name of the program, which the system's logger files messages under.
+    identifier: String,
+}
+
+impl SystemLogger {
+    /// Logger sending messages to `sink`, over the `socket` it reads them from.
+    fn connect(level: LevelFilter, sink: LogSink, socket: &Path) -> io::Result<Box<Self>> {
+        let identifier = env::args_os()
+            .next()
+            .as_deref()
+            .and_then(|program| Path::new(program).file_name())
+            .map_or(String::from("sdstored"), |program| program.to_string_lossy().into_owned());
+        let datagram = UnixDatagram::unbound()?;
+        datagram.connect(socket)?;
+        Ok(Box::new(SystemLogger { level, sink, socket: datagram, identifier }))
+    }
+}
+
+impl Log for SystemLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= self.level
+    }
+
+    fn log(&self, record: &Record) {
+        if self.enabled(record.metadata()) {
+            let message = match self.sink {
+                LogSink::Syslog => syslog_line(record, &self.identifier, process::id()).into_bytes(),
+                _ => journald_entry(record, &self.identifier),
+            };
+            // There's nowhere to report that logging failed.
+            let _ = self.socket.send(&message);
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+impl SharedLogger for SystemLogger {
+    fn level(&self) -> LevelFilter {
+        self.level
+    }
+
+    fn config(&self) -> Option<&Config> {
+        None
+    }
+
+    fn as_log(self: Box<Self>) -> Box<dyn Log> {
+        self
+    }
+}
+
+/// Severity of messages logged at `level`, as syslog and journald number them.
+fn syslog_severity(level: log::Level) -> u8 {
+    match level {
+        log::Level::Error => 3,
+        log::Level::Warn => 4,
+        log::Level::Info => 6,
+        log::Level::Debug | log::Level::Trace => 7,
+    }
+}
+
+/// `record`, logged by the program `identifier`, as an entry of journald's native protocol:
+/// its `MESSAGE`, `PRIORITY`, `SYSLOG_IDENTIFIER`, `TARGET`, `CODE_FILE` and `CODE_LINE`,
+/// along with its key-values, their keys in upper case, as in `CLIENT_PID=42`.
+pub fn journald_entry(record: &Record, identifier: &str) -> Vec<u8> {
+    let mut entry = Vec::new();
+    let mut field = |name: &str, value: &str| {
+        // Values spanning several lines are preceded by their length instead.
+        match value.contains('\n') {
+            false => entry.extend_from_slice(format!("{name}={value}\n").as_bytes()),
+            true => {
+                entry.extend_from_slice(format!("{name}\n").as_bytes());
+                entry.extend_from_slice(&(value.len() as u64).to_le_bytes());
+                entry.extend_from_slice(value.as_bytes());
+                entry.push(b'\n');
+            },
+        }
+    };
+    field("MESSAGE", &record.args().to_string());
+    field("PRIORITY", &syslog_severity(record.level()).to_string());
+    field("SYSLOG_IDENTIFIER", identifier);
+    field("TARGET", record.target());
+    if let (Some(file), Some(line)) = (record.file(), record.line()) {
+        field("CODE_FILE", file);
+        field("CODE_LINE", &line.to_string());
+    }
+    for (key, value) in key_values(record) {
+        // Field names are upper case letters, digits and underscores, starting with a letter.
+        let name = key
+            .chars()
+            .map(|c| if c.is_ascii_alphanumeric() { c.to_ascii_uppercase() } else { '_' })
+            .collect::<String>();
+        let name = name.trim_start_matches(|c: char| !c.is_ascii_alphabetic());
+        if !name.is_empty() {
+            field(name, &value);
+        }
+    }
+    entry
+}
+
+/// `record`, logged by the program `identifier` with `pid`, as a line for a syslog daemon,
+/// with the `daemon` facility, as in `<30>sdstored[42]: task #3 finished client_pid=42`.
+/// Key-values follow the message, quoted if they have spaces.
+pub fn syslog_line(record: &Record, identifier: &str, pid: u32) -> String {
+    // The `daemon` facility is 3.
+    let priority = 3 * 8 + syslog_severity(record.level());
+    let mut line = format!("<{priority}>{identifier}[{pid}]: {}", record.args());
+    for (key, value) in key_values(record) {
+        match value.contains(|c: char| c.is_whitespace() || c == '"') || value.is_empty() {
+            false => line.push_str(&format!(" {key}={value}")),
+            true => line.push_str(&format!(" {key}={value:?}")),
+        }
+    }
+    line
+}
+
+/// The key-values of `record`, as text, in the order they were given.
+fn key_values(record: &Record) -> Vec<(String, String)> {
+    struct Pairs(Vec<(String, String)>);
+
+    impl<'kvs> VisitSource<'kvs> for Pairs {
+        fn visit_pair(&mut self, key: Key<'kvs>, value: Value<'kvs>) -> Result<(), kv::Error> {
+            self.0.push((key.to_string(), value.to_string()));
+            Ok(())
+        }
+    }
+
+    let mut pairs = Pairs(Vec::new());
+    // The visitor above always succeeds.
+    let _ = record.key_values().visit(&mut pairs);
+    pairs.0
+}
+
+/// `record`, logged at `time`, as a JSON object: its `time`, in seconds since the Unix epoch,
+/// `level`, `target`, source `location`, and `message`, along with its key-values, as in
+/// `log::info!(client_pid = 42; "...")`, which are numbers, booleans, `null`, or strings.
+pub fn json_record(record: &Record, time: SystemTime) -> serde_json::Value {
+    let mut object = serde_json::Map::new();
+    let time = time.duration_since(SystemTime::UNIX_EPOCH).unwrap_or_default().as_secs_f64();
+    object.insert(String::from("time"), time.into());
+    object.insert(String::from("level"), record.level().as_str().into());
+    object.insert(String::from("target"), record.target().into());
+    if let (Some(file), Some(line)) = (record.file(), record.line()) {
+        object.insert(String::from("location"), format!("{file}:{line}").into());
+    }
+    object.insert(String::from("message"), record.args().to_string().into());
+    // Every visitor method below succeeds.
+    let _ = record.key_values().visit(&mut JsonFields(&mut object));
+    serde_json::Value::Object(object)
+}
+
+/// Adds the key-values of a record to its JSON object, see [`json_record`].
+struct JsonFields<'a>(&'a mut serde_json::Map<String, serde_json::Value>);
+
+impl<'kvs> VisitSource<'kvs> for JsonFields<'_> {
+    fn visit_pair(&mut self, key: Key<'kvs>, value: Value<'kvs>) -> Result<(), kv::Error> {
+        let mut json = JsonValue(serde_json::Value::Null);
+        value.visit(&mut json)?;
+        self.0.insert(key.to_string(), json.0);
+        Ok(())
+    }
+}
+
+/// A key-value of a record, as JSON, see [`json_record`].
+struct JsonValue(serde_json::Value);
+
+impl<'v> VisitValue<'v> for JsonValue {
+    fn visit_any(&mut self, value: Value) -> Result<(), kv::Error> {
+        self.0 = value.to_string().into();
+        Ok(())
+    }
+
+    fn visit_null(&mut self) -> Result<(), kv::Error> {
+        self.0 = serde_json::Value::Null;
+        Ok(())
+    }
+
+    fn visit_u64(&mut self, value: u64) -> Result<(), kv::Error> {
+        self.0 = value.into();
+        Ok(())
+    }
+
+    fn visit_i64(&mut self, value: i64) -> Result<(), kv::Error> {
+        self.0 = value.into();
+        Ok(())
+    }
+
+    fn visit_f64(&mut self, value: f64) -> Result<(), kv::Error> {
+        self.0 = value.into();
+        Ok(())
+    }
+
+    fn visit_bool(&mut self, value: bool) -> Result<(), kv::Error> {
+        self.0 = value.into();
+        Ok(())
+    }
+
+    fn visit_str(&mut self, value: &str) -> Result<(), kv::Error> {
+        self.0 = value.into();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use log::kv::ToValue;
+
+    use crate::core::testing::TempDir;
+
+    use super::*;
+
+    #[test]
+    fn target_levels_work() {
+        let targets = [
+            (String::from("rust_sdstore::core"), LevelFilter::Warn),
+            (String::from("rust_sdstore::core::server::state"), LevelFilter::Error),
+            (String::from("rust_sdstore::core::server::state"), LevelFilter::Debug),
+        ];
+        let level = |target| target_level(target, LevelFilter::Info, &targets);
+
+        assert_eq!(level("rust_sdstore::core::server::state"), LevelFilter::Debug);
+        assert_eq!(level("rust_sdstore::core::server::scheduler"), LevelFilter::Warn);
+        assert_eq!(level("rust_sdstore::core"), LevelFilter::Warn);
+        // Only whole path segments match.
+        assert_eq!(level("rust_sdstore::core_extra"), LevelFilter::Info);
+        assert_eq!(level("sdstored"), LevelFilter::Info);
+    }
+
+    #[test]
+    fn files_are_rotated_between_lines() {
+        let dir = TempDir::new("rotation");
+        let path = dir.join("sdstored.log");
+        let rotated = |n: usize| fs::read_to_string(dir.join(format!("sdstored.log.{n}"))).ok();
+
+        let rotation = Rotation { max_size: Some(10), max_age: None, keep: 1 };
+        let mut file = RotatingFile::open(path.clone(), rotation).unwrap();
+        // As a logger may write a line in several pieces, past the maximum size.
+        for piece in ["first ", "line", "\n", "second line\n", "third", " line\n"] {
+            file.write_all(piece.as_bytes()).unwrap();
+        }
+        assert_eq!(fs::read_to_string(&path).unwrap(), "third line\n");
+        assert_eq!(rotated(1).as_deref(), Some("second line\n"));
+        assert_eq!(rotated(2), None);
+
+        // Files that are old enough are rotated whatever their size.
+        let rotation = Rotation { max_size: None, max_age: Some(Duration::ZERO), keep: 1 };
+        let mut file = RotatingFile::open(path.clone(), rotation).unwrap();
+        file.write_all(b"fourth line\n").unwrap();
+        assert_eq!(rotated(1).as_deref(), Some("third line\n"));
+        assert_eq!(fs::read_to_string(&path).unwrap(), "fourth line\n");
+    }
+
+    #[test]
+    fn json_records_work() {
+        let client_pid = 42_u32;
+        let (filters, outcome) = ("nop gcompress", None::<&str>);
+        let kvs = [("client_pid", Value::from(client_pid)), ("filters", Value::from(filters)), ("failure", outcome.to_value())];
+        let args = format_args!("task #{} finished", 3);
+        let record = Record::builder()
+            .args(args)
+            .level(log::Level::Info)
+            .target("rust_sdstore::core::server::state")
+            .key_values(&kvs)
+            .build();
+
+        let json = json_record(&record, SystemTime::UNIX_EPOCH + Duration::from_millis(1500));
+        assert_eq!(json, serde_json::json!({
+            "time": 1.5,
+            "level": "INFO",
+            "target": "rust_sdstore::core::server::state",
+            "message": "task #3 finished",
+            "client_pid": 42,
+            "filters": "nop gcompress",
+            "failure": null,
+        }));
+    }
+
+    #[test]
+    fn system_log_messages_work() {
+        let kvs = [("client_pid", Value::from(42_u32)), ("filters", Value::from("nop gcompress"))];
+        let args = format_args!("task #3 failed:\nstage 0 (nop) exited with code 1");
+        let record = Record::builder()
+            .args(args)
+            .level(log::Level::Warn)
+            .target("rust_sdstore::core::server::state")
+            .file(Some("src/core/server/state.rs"))
+            .line(Some(245))
+            .key_values(&kvs)
+            .build();
+
+        assert_eq!(
+            syslog_line(&record, "sdstored", 7),
+            "<28>sdstored[7]: task #3 failed:\nstage 0 (nop) exited with code 1 client_pid=42 filters=\"nop gcompress\""
+        );
+
+        let message = "task #3 failed:\nstage 0 (nop) exited with code 1";
+        let mut expected = b"MESSAGE\n".to_vec();
+        expected.extend_from_slice(&(message.len() as u64).to_le_bytes());
+        expected.extend_from_slice(message.as_bytes());
+        expected.extend_from_slice(b"\nPRIORITY=4\nSYSLOG_IDENTIFIER=sdstored\nTARGET=rust_sdstore::core::server::state\n");
+        expected.extend_from_slice(b"CODE_FILE=src/core/server/state.rs\nCODE_LINE=245\n");
+        expected.extend_from_slice(b"CLIENT_PID=42\nFILTERS=nop gcompress\n");
+        assert_eq!(journald_entry(&record, "sdstored"), expected);
+    }
+}